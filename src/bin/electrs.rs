@@ -6,23 +6,33 @@ extern crate electrs;
 
 use error_chain::ChainedError;
 use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, RwLock};
-use std::time::Duration;
+use std::thread;
+use std::time::{Duration, Instant};
 
 use electrs::{
+    chain::BlockHash,
     config::Config,
     daemon::Daemon,
     electrum::RPC as ElectrumRPC,
     errors::*,
-    metrics::Metrics,
-    new_index::{precache, ChainQuery, FetchFrom, Indexer, Mempool, Query, Store},
+    metrics::{Gauge, Metrics},
+    new_index::{
+        broadcast_queue, daily_stats, forks, integrity, precache, price, rebroadcast, ChainQuery,
+        FetchFrom, Indexer, Mempool, Query, Store,
+    },
     rest,
     signal::Waiter,
+    util::{sd_notify, spawn_thread},
+    zmq,
 };
 
 #[cfg(feature = "liquid")]
-use electrs::elements::AssetRegistry;
+use electrs::elements::{AssetRegistry, RegistrySource};
 use electrs::metrics::MetricOpts;
+#[cfg(not(feature = "liquid"))]
+use electrs::new_index::utxo_deltas;
 
 fn fetch_from(config: &Config, store: &Store) -> FetchFrom {
     let mut jsonrpc_import = config.jsonrpc_import;
@@ -40,109 +50,309 @@ fn fetch_from(config: &Config, store: &Store) -> FetchFrom {
     }
 }
 
-fn run_server(config: Arc<Config>) -> Result<()> {
-    let signal = Waiter::start();
-    let metrics = Metrics::new(config.monitoring_addr);
-    metrics.start();
-
-    let daemon = Arc::new(Daemon::new(
-        &config.daemon_dir,
-        &config.blocks_dir,
-        config.daemon_rpc_addr,
-        config.cookie_getter(),
-        config.network_type,
-        signal.clone(),
-        &metrics,
-    )?);
-    let store = Arc::new(Store::open(&config.db_path.join("newindex"), &config));
-    let mut indexer = Indexer::open(
-        Arc::clone(&store),
-        fetch_from(&config, &store),
-        &config,
-        &metrics,
-    );
-    let mut tip = indexer.update(&daemon)?;
-
-    let chain = Arc::new(ChainQuery::new(
-        Arc::clone(&store),
-        Arc::clone(&daemon),
-        &config,
-        &metrics,
-    ));
-
-    if let Some(ref precache_file) = config.precache_scripts {
-        let precache_scripthashes = precache::scripthashes_from_file(precache_file.to_string())
-            .expect("cannot load scripts to precache");
-        precache::precache(&chain, precache_scripthashes);
-    }
+// The indexing, mempool and Electrum server of one of the networks served by the process
+struct NetworkServer {
+    config: Arc<Config>,
+    signal: Waiter,
+    daemon: Arc<Daemon>,
+    store: Arc<Store>,
+    indexer: Option<Indexer>,
+    tip: BlockHash,
+    mempool: Arc<RwLock<Mempool>>,
+    zmq_resync: Option<Arc<AtomicBool>>,
+    last_resync: Instant,
+    query: Arc<Query>,
+    electrum_server: ElectrumRPC,
+    main_loop_count: Gauge,
+}
 
-    let mempool = Arc::new(RwLock::new(Mempool::new(
-        Arc::clone(&chain),
-        &metrics,
-        Arc::clone(&config),
-    )));
-    loop {
-        match Mempool::update(&mempool, &daemon) {
-            Ok(_) => break,
-            Err(e) => {
-                warn!("Error performing initial mempool update, trying again in 5 seconds: {}", e.display_chain());
-                signal.wait(Duration::from_secs(5), false)?;
-            },
+impl NetworkServer {
+    fn start(config: Arc<Config>) -> Result<NetworkServer> {
+        let signal = Waiter::start();
+        let metrics = Metrics::new(config.monitoring_addr);
+        metrics.start();
+
+        let daemon = Arc::new(Daemon::new(
+            &config.daemon_dir,
+            &config.blocks_dir,
+            config.daemon_rpc_addr,
+            config.cookie_getter(),
+            config.network_type,
+            signal.clone(),
+            &metrics,
+        )?);
+        let store = Arc::new(Store::open(&config.db_path.join("newindex"), &config));
+        // read-only replicas follow the index of the primary instance instead of indexing
+        let mut indexer = match config.replica_path {
+            Some(_) => None,
+            None => Some(Indexer::open(
+                Arc::clone(&store),
+                fetch_from(&config, &store),
+                &config,
+                &metrics,
+            )),
+        };
+        let tip = match indexer {
+            Some(ref mut indexer) => indexer.update(&daemon)?,
+            None => store.catch_up(),
+        };
+
+        let chain = Arc::new(ChainQuery::new(
+            Arc::clone(&store),
+            Arc::clone(&daemon),
+            &config,
+            &metrics,
+        ));
+
+        if let Some(ref precache_file) = config.precache_scripts {
+            let precache_scripthashes = precache::scripthashes_from_file(precache_file.to_string())
+                .expect("cannot load scripts to precache");
+            precache::precache(&chain, precache_scripthashes);
         }
-    }
 
-    #[cfg(feature = "liquid")]
-    let asset_db = config.asset_db_path.as_ref().map(|db_dir| {
-        let asset_db = Arc::new(RwLock::new(AssetRegistry::new(db_dir.clone())));
-        AssetRegistry::spawn_sync(asset_db.clone());
-        asset_db
-    });
-
-    let query = Arc::new(Query::new(
-        Arc::clone(&chain),
-        Arc::clone(&mempool),
-        Arc::clone(&daemon),
-        Arc::clone(&config),
-        #[cfg(feature = "liquid")]
-        asset_db,
-    ));
+        if config.daily_stats {
+            daily_stats::spawn(Arc::clone(&chain));
+        }
 
-    // TODO: configuration for which servers to start
-    let rest_server = rest::start(Arc::clone(&config), Arc::clone(&query));
-    let electrum_server = ElectrumRPC::start(Arc::clone(&config), Arc::clone(&query), &metrics);
+        #[cfg(not(feature = "liquid"))]
+        utxo_deltas::spawn(Arc::clone(&chain));
 
-    let main_loop_count = metrics.gauge(MetricOpts::new(
-        "electrs_main_loop_count",
-        "count of iterations of electrs main loop each 5 seconds or after interrupts",
-    ));
+        if let Some(ref source) = config.price_feed {
+            price::spawn(Arc::clone(&chain), source.clone(), config.price_feed_interval);
+        }
 
-    loop {
+        if !config.secondary_daemons.is_empty() {
+            forks::spawn(
+                Arc::clone(&chain),
+                Arc::clone(&daemon),
+                Arc::clone(&config),
+                &metrics,
+            );
+        }
 
-        main_loop_count.inc();
+        if let Some(interval) = config.integrity_check_interval {
+            integrity::spawn(Arc::clone(&chain), interval, config.utxos_limit, &metrics);
+        }
 
-        if let Err(err) = signal.wait(Duration::from_secs(5), true) {
-            info!("stopping server: {}", err);
-            rest_server.stop();
-            // the electrum server is stopped when dropped
-            break;
+        let mempool = Arc::new(RwLock::new(Mempool::new(
+            Arc::clone(&chain),
+            &metrics,
+            Arc::clone(&config),
+        )));
+        sd_notify::status("Syncing the mempool");
+        loop {
+            match Mempool::update(&mempool, &daemon) {
+                Ok(_) => break,
+                Err(e) => {
+                    warn!("Error performing initial mempool update, trying again in 5 seconds: {}", e.display_chain());
+                    signal.wait(Duration::from_secs(5), false)?;
+                },
+            }
         }
 
-        // Index new blocks
-        let current_tip = daemon.getbestblockhash()?;
-        if current_tip != tip {
-            indexer.update(&daemon)?;
-            tip = current_tip;
+        // with ZMQ, the mempool gets updated incrementally and only resynced once in a while
+        let zmq_resync = config
+            .zmq_addr
+            .map(|addr| zmq::spawn(addr, Arc::clone(&mempool), signal.clone()));
+
+        #[cfg(feature = "liquid")]
+        let asset_db = {
+            // the --asset-db-path directory takes precedence over the other sources
+            let sources = config
+                .asset_db_path
+                .iter()
+                .map(|db_dir| Ok(RegistrySource::Directory(db_dir.clone())))
+                .chain(config.asset_registry_sources.iter().map(|s| RegistrySource::parse(s)))
+                .collect::<Result<Vec<_>>>()?;
+            Some(sources).filter(|sources| !sources.is_empty()).map(|sources| {
+                let registry = AssetRegistry::new(sources, config.asset_registry_merge);
+                let asset_db = Arc::new(RwLock::new(registry));
+                AssetRegistry::spawn_sync(asset_db.clone(), config.asset_registry_refresh_interval);
+                asset_db
+            })
         };
 
-        // Update mempool
-        if let Err(e) = Mempool::update(&mempool, &daemon) {
-            // Log the error if the result is an Err
-            warn!("Error updating mempool, skipping mempool update: {}", e.display_chain());
+        let query = Arc::new(Query::new(
+            Arc::clone(&chain),
+            Arc::clone(&mempool),
+            Arc::clone(&daemon),
+            Arc::clone(&config),
+            #[cfg(feature = "liquid")]
+            asset_db,
+        ));
+
+        if config.broadcast_queue {
+            broadcast_queue::spawn(Arc::clone(&query));
+        }
+
+        if let Some(after) = config.rebroadcast_after {
+            rebroadcast::spawn(Arc::clone(&query), after);
         }
 
-        // Update subscribed clients
-        electrum_server.notify();
+        let electrum_server = ElectrumRPC::start(Arc::clone(&config), Arc::clone(&query), &metrics);
+
+        let main_loop_count = metrics.gauge(MetricOpts::new(
+            "electrs_main_loop_count",
+            "count of iterations of electrs main loop each 5 seconds or after interrupts",
+        ));
+
+        Ok(NetworkServer {
+            config,
+            signal,
+            daemon,
+            store,
+            indexer,
+            tip,
+            mempool,
+            zmq_resync,
+            last_resync: Instant::now(),
+            query,
+            electrum_server,
+            main_loop_count,
+        })
+    }
+
+    // Keep indexing the new blocks and updating the mempool until the shutdown signal, calling
+    // `on_iteration` on every iteration of the loop
+    fn run<F>(&mut self, loop_interval: Duration, mut on_iteration: F) -> Result<()>
+    where
+        F: FnMut() -> Result<()>,
+    {
+        loop {
+
+            self.main_loop_count.inc();
+            on_iteration()?;
+
+            if let Err(err) = self.signal.wait(loop_interval, true) {
+                info!("stopping server: {}", err);
+                return Ok(());
+            }
+
+            // Index new blocks
+            let prev_tip = self.tip;
+            match self.indexer {
+                Some(ref mut indexer) => {
+                    let current_tip = self.daemon.getbestblockhash()?;
+                    if current_tip != self.tip {
+                        indexer.update(&self.daemon)?;
+                        self.tip = current_tip;
+                    };
+                }
+                None => self.tip = self.store.catch_up(),
+            }
+
+            // Update mempool
+            let resync = match self.zmq_resync {
+                Some(ref resync_needed) => {
+                    self.tip != prev_tip
+                        || resync_needed.swap(false, Ordering::Relaxed)
+                        || self.last_resync.elapsed()
+                            >= Duration::from_secs(self.config.zmq_resync_interval)
+                }
+                None => true,
+            };
+            if resync {
+                if let Err(e) = Mempool::update(&self.mempool, &self.daemon) {
+                    // Log the error if the result is an Err
+                    warn!("Error updating mempool, skipping mempool update: {}", e.display_chain());
+                }
+                self.last_resync = Instant::now();
+            }
+
+            // Update subscribed clients
+            self.electrum_server.notify();
+        }
+    }
+
+    fn stop(self) {
+        // the electrum server is stopped when dropped
+        drop(self.electrum_server);
+        // flush the index, so that the next startup doesn't have to recover it
+        self.store.close();
+    }
+}
+
+fn run_server(config: Arc<Config>) -> Result<()> {
+    // the initial sync may take a long while, don't let systemd time out the startup meanwhile
+    sd_notify::spawn_startup_extender();
+    sd_notify::status("Indexing blocks");
+
+    // the additional networks sync in parallel with the primary one
+    let (mut server, networks) = thread::scope(|scope| {
+        let starting: Vec<_> = config
+            .additional_networks
+            .iter()
+            .map(|(name, network_config)| {
+                let network_config = Arc::new(network_config.clone());
+                (name, scope.spawn(move || NetworkServer::start(network_config)))
+            })
+            .collect();
+        let server = NetworkServer::start(Arc::clone(&config))?;
+        let mut networks = vec![];
+        for (name, started) in starting {
+            let network = started.join().expect("network startup panicked")?;
+            networks.push((name.clone(), network));
+        }
+        Ok::<_, Error>((server, networks))
+    })?;
+
+    // TODO: configuration for which servers to start
+    let rest_server = rest::start_with_networks(
+        Arc::clone(&config),
+        Arc::clone(&server.query),
+        Some(server.electrum_server.sessions()),
+        networks
+            .iter()
+            .map(|(name, network)| rest::NetworkMount {
+                prefix: name.clone(),
+                config: Arc::clone(&network.config),
+                query: Arc::clone(&network.query),
+                electrum_sessions: Some(network.electrum_server.sessions()),
+            })
+            .collect(),
+    );
+
+    // the additional networks are kept up to date in the background
+    let mut running: Vec<_> = networks
+        .into_iter()
+        .map(|(name, mut network)| {
+            spawn_thread(&format!("network-{}", name), move || {
+                network
+                    .run(Duration::from_secs(5), || Ok(()))
+                    .map(|_| network)
+            })
+        })
+        .collect();
+    let mut stopped = vec![];
+
+    // the index caught up with the daemon and the servers are up
+    sd_notify::ready();
+    sd_notify::status("Serving");
+    // pet the watchdog at least twice within its interval
+    let loop_interval = match sd_notify::watchdog_interval() {
+        Some(interval) => Duration::from_secs(5).min(interval / 2),
+        None => Duration::from_secs(5),
+    };
+
+    server.run(loop_interval, || {
+        sd_notify::watchdog();
+        // the additional networks stop on the shutdown signal too, or fail the whole process
+        while let Some(i) = running.iter().position(|network| network.is_finished()) {
+            stopped.push(running.remove(i).join().expect("network panicked")?);
+        }
+        Ok(())
+    })?;
+
+    sd_notify::stopping();
+    // stop accepting new connections, and let the in-flight requests complete
+    rest_server.stop();
+    for network in running {
+        stopped.push(network.join().expect("network panicked")?);
+    }
+    for network in stopped {
+        network.stop();
     }
+    server.stop();
     info!("server stopped");
     Ok(())
 }
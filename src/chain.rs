@@ -92,6 +92,15 @@ impl Network {
         }
     }
 
+    // The relay policy limits of the network's daemon
+    #[cfg(not(feature = "liquid"))]
+    pub fn params(self) -> &'static ChainParams {
+        match self {
+            Network::Bitcoin | Network::Testnet | Network::Signet => &JUNKCOIN_PARAMS,
+            Network::Regtest => &REGTEST_PARAMS,
+        }
+    }
+
     pub fn names() -> Vec<String> {
         #[cfg(not(feature = "liquid"))]
         return vec![
@@ -110,6 +119,43 @@ impl Network {
     }
 }
 
+// The consensus and relay policy parameters of a network
+#[cfg(not(feature = "liquid"))]
+#[derive(Debug)]
+pub struct ChainParams {
+    pub retarget_interval: usize,
+    pub target_block_spacing: u32, // in seconds
+    // the standardness limits the daemon relays transactions within
+    pub max_standard_tx_weight: u64,
+    pub max_standard_scriptsig_size: usize,
+    pub max_standard_tx_sigops_cost: usize,
+    pub max_p2sh_sigops: usize,
+    pub max_op_return_relay: usize,
+    pub dust_relay_feerate: u64, // sat/vB
+}
+
+// Junkcoin's, with the policy defaults inherited from Litecoin Core 0.16 along with its 10x
+// higher relay fees
+#[cfg(not(feature = "liquid"))]
+static JUNKCOIN_PARAMS: ChainParams = ChainParams {
+    retarget_interval: 2016,
+    target_block_spacing: 150,
+    max_standard_tx_weight: 400_000,
+    max_standard_scriptsig_size: 1650,
+    max_standard_tx_sigops_cost: 16_000,
+    max_p2sh_sigops: 15,
+    max_op_return_relay: 83,
+    dust_relay_feerate: 30,
+};
+
+// bitcoind's, the regtest daemon the tests run against
+#[cfg(not(feature = "liquid"))]
+static REGTEST_PARAMS: ChainParams = ChainParams {
+    target_block_spacing: 600,
+    dust_relay_feerate: 3,
+    ..JUNKCOIN_PARAMS
+};
+
 pub fn genesis_hash(network: Network) -> BlockHash {
     #[cfg(not(feature = "liquid"))]
     return bitcoin_genesis_hash(network.into());
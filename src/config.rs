@@ -1,18 +1,28 @@
 use clap::{App, Arg};
 use dirs::home_dir;
+use std::collections::BTreeMap;
+use std::env;
+use std::ffi::OsString;
 use std::fs;
 use std::net::SocketAddr;
 use std::net::ToSocketAddrs;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
 use stderrlog;
 
-use crate::chain::Network;
+use crate::chain::{BlockHash, Network};
+use crate::config_file;
 use crate::daemon::CookieGetter;
 use crate::errors::*;
 
 #[cfg(feature = "liquid")]
 use bitcoin::Network as BNetwork;
+#[cfg(feature = "liquid")]
+use crate::elements::RegistryMerge;
+use crate::util::Locale;
+#[cfg(not(feature = "liquid"))]
+use crate::util::ScriptTemplate;
 
 const ELECTRS_VERSION: &str = env!("CARGO_PKG_VERSION");
 
@@ -26,29 +36,102 @@ pub struct Config {
     pub blocks_dir: PathBuf,
     pub daemon_rpc_addr: SocketAddr,
     pub cookie: Option<String>,
+    pub zmq_addr: Option<SocketAddr>,
+    pub zmq_resync_interval: u64,
+    pub mempool_fetch_batch_size: usize,
+    pub mempool_fetch_concurrency: usize,
+    pub mempool_limit_txs: Option<usize>,
+    pub mempool_limit_memory: Option<usize>,
+    pub mempool_eviction: MempoolEviction,
+    pub secondary_daemons: Vec<SecondaryDaemon>,
+    pub fork_alert_depth: usize,
+    pub checkpoints: BTreeMap<usize, BlockHash>,
+    pub max_reorg_depth: Option<usize>,
+    pub deployments: Vec<Deployment>,
+    pub deployment_period: usize,
+    #[cfg(not(feature = "liquid"))]
+    pub retarget_interval: usize,
+    #[cfg(not(feature = "liquid"))]
+    pub target_block_spacing: u32,
     pub electrum_rpc_addr: SocketAddr,
     pub http_addr: SocketAddr,
     pub http_socket_file: Option<PathBuf>,
     pub monitoring_addr: SocketAddr,
     pub jsonrpc_import: bool,
     pub light_mode: bool,
+    pub replica_path: Option<PathBuf>,
     pub address_search: bool,
+    pub flow_analysis: bool,
     pub index_unspendables: bool,
-    pub cors: Option<String>,
+    pub large_tx_threshold: Option<u64>,
+    pub index_output_values: bool,
+    pub replaced_tx_retention: Duration,
+    pub orphaned_spend_window: usize,
+    pub index_revealed_scripts: bool,
+    pub index_spend_paths: bool,
+    #[cfg(not(feature = "liquid"))]
+    pub script_templates: Vec<ScriptTemplate>,
+    pub daily_stats: bool,
+    pub broadcast_queue: bool,
+    pub rebroadcast_after: Option<usize>,
+    pub integrity_check_interval: Option<u64>,
+    pub cors: Option<CorsPolicy>,
+    pub locales: Vec<Locale>,
+    pub cache_policy: CachePolicy,
+    pub stale_while_revalidate: u64,
     pub precache_scripts: Option<String>,
+    pub price_feed: Option<String>,
+    pub price_feed_interval: Duration,
     pub utxos_limit: usize,
+    pub max_txs_per_page: usize,
+    pub max_mempool_txs: usize,
+    pub max_batch_size: usize,
+    pub max_outspends_txids: usize,
+    pub outspends_concurrency: usize,
     pub electrum_txs_limit: usize,
     pub electrum_banner: String,
+    pub electrum_donation_address: Option<String>,
+    pub electrum_peers: Vec<crate::electrum::StaticPeer>,
     pub electrum_rpc_logging: Option<RpcLogging>,
+    pub electrum_batch_limit: usize,
+    pub electrum_max_response_size: usize,
+    pub electrum_max_pending_requests: usize,
+    pub electrum_max_connections: Option<usize>,
+    pub electrum_max_connections_per_ip: Option<usize>,
+    pub electrum_idle_timeout: Option<Duration>,
+    pub admin_api: bool,
+    pub admin_token: Option<String>,
+    pub shutdown_timeout: Duration,
+    pub additional_networks: Vec<(String, Config)>,
+    pub tx_cache_size: usize,
+    pub block_cache_size: usize,
+    pub prevout_cache_size: usize,
+    pub raw_block_cache_size: usize,
+    pub slow_query_threshold: Option<Duration>,
+    pub slow_query_log_file: Option<PathBuf>,
+    pub audit_log_file: Option<PathBuf>,
+    pub audit_log_max_size: u64,
+    pub audit_log_max_files: usize,
+    pub request_budget: Option<u64>,
+    pub request_budget_keys: BTreeMap<String, u64>,
+    pub request_timeout: Option<Duration>,
 
     #[cfg(feature = "liquid")]
     pub parent_network: BNetwork,
     #[cfg(feature = "liquid")]
     pub asset_db_path: Option<PathBuf>,
+    #[cfg(feature = "liquid")]
+    pub asset_registry_sources: Vec<String>,
+    #[cfg(feature = "liquid")]
+    pub asset_registry_refresh_interval: Duration,
+    #[cfg(feature = "liquid")]
+    pub asset_registry_merge: RegistryMerge,
 
     #[cfg(feature = "electrum-discovery")]
     pub electrum_public_hosts: Option<crate::electrum::ServerHosts>,
     #[cfg(feature = "electrum-discovery")]
+    pub electrum_onion_host: Option<crate::electrum::Hostname>,
+    #[cfg(feature = "electrum-discovery")]
     pub electrum_announce: bool,
     #[cfg(feature = "electrum-discovery")]
     pub tor_proxy: Option<std::net::SocketAddr>,
@@ -63,13 +146,66 @@ fn str_to_socketaddr(address: &str, what: &str) -> SocketAddr {
         .unwrap()
 }
 
+// Log records from the `log` crate are forwarded to the tracing subscriber, so that they are
+// emitted along with the fields of the span they were logged in (e.g. the request id)
+fn init_json_logging(verbosity: usize) {
+    use tracing_subscriber::fmt::format::FmtSpan;
+
+    let level = match verbosity {
+        0 => tracing::Level::ERROR,
+        1 => tracing::Level::WARN,
+        2 => tracing::Level::INFO,
+        3 => tracing::Level::DEBUG,
+        _ => tracing::Level::TRACE,
+    };
+    tracing_subscriber::fmt()
+        .json()
+        .with_max_level(level)
+        .with_current_span(true)
+        .with_span_events(FmtSpan::CLOSE)
+        .with_writer(std::io::stderr)
+        .init();
+}
+
+fn config_error(e: Error) -> ! {
+    let causes: Vec<String> = e.iter().map(|e| e.to_string()).collect();
+    clap::Error::with_description(&causes.join(": "), clap::ErrorKind::InvalidValue).exit()
+}
+
 impl Config {
     pub fn from_args() -> Config {
+        let argv = config_file::expand_args(env::args_os().collect())
+            .unwrap_or_else(|e| config_error(e));
+        Config::parse(argv, true)
+    }
+
+    // The configuration of an additional network, along with the name of the network
+    fn from_network_conf(path: &str) -> (String, Config) {
+        let argv = config_file::file_args(Path::new(path)).unwrap_or_else(|e| config_error(e));
+        let config = Config::parse(argv, false);
+        let name = Network::names()
+            .into_iter()
+            .find(|name| Network::from(name.as_str()) == config.network_type)
+            .unwrap();
+        (name, config)
+    }
+
+    // Parse the command-line arguments, which only set up the logging for the primary network
+    fn parse(argv: Vec<OsString>, primary: bool) -> Config {
         let network_help = format!("Select network type ({})", Network::names().join(", "));
         let rpc_logging_help = format!(
             "Select RPC logging option ({})",
             RpcLogging::options().join(", ")
         );
+        let mempool_eviction_help = format!(
+            "Which transactions to evict first when the mempool exceeds its limits ({})",
+            MempoolEviction::options().join(", ")
+        );
+        #[cfg(feature = "liquid")]
+        let asset_registry_merge_help = format!(
+            "How the metadata of assets found in several registry sources is merged ({})",
+            RegistryMerge::options().join(", ")
+        );
 
         let args = App::new("Electrum Rust Server")
             .version(crate_version!())
@@ -79,11 +215,22 @@ impl Config {
                     .multiple(true)
                     .help("Increase logging verbosity"),
             )
+            .arg(
+                Arg::with_name("conf")
+                    .long("conf")
+                    .help("Configuration file to read the options from (default: $ELECTRS_CONF). Options set on the command line or with ELECTRS_<OPTION> environment variables take precedence.")
+                    .takes_value(true),
+            )
             .arg(
                 Arg::with_name("timestamp")
                     .long("timestamp")
                     .help("Prepend log lines with a timestamp"),
             )
+            .arg(
+                Arg::with_name("log_json")
+                    .long("log-json")
+                    .help("Emit structured JSON log lines, including request ids and DB timing spans"),
+            )
             .arg(
                 Arg::with_name("db_dir")
                     .long("db-dir")
@@ -132,6 +279,96 @@ impl Config {
                     .help("Bitcoin daemon JSONRPC 'addr:port' to connect (default: 127.0.0.1:8332 for mainnet, 127.0.0.1:18332 for testnet and 127.0.0.1:18443 for regtest)")
                     .takes_value(true),
             )
+            .arg(
+                Arg::with_name("zmq_addr")
+                    .long("zmq-addr")
+                    .help("Bitcoin daemon ZMQ 'addr:port' publishing the rawtx and hashblock notifications (-zmqpubrawtx and -zmqpubhashblock), for updating the mempool incrementally instead of polling it")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("zmq_resync_interval")
+                    .long("zmq-resync-interval")
+                    .help("With --zmq-addr, the interval in seconds for fully resyncing the mempool with the daemon's, to catch up with the evicted and expired transactions and with any missed notification")
+                    .default_value("60")
+            )
+            .arg(
+                Arg::with_name("mempool_fetch_batch_size")
+                    .long("mempool-fetch-batch-size")
+                    .help("Number of new mempool transactions fetched from the daemon per batched RPC request")
+                    .default_value("1000")
+            )
+            .arg(
+                Arg::with_name("mempool_fetch_concurrency")
+                    .long("mempool-fetch-concurrency")
+                    .help("Maximum number of batches of new mempool transactions fetched from the daemon in parallel, over separate connections")
+                    .default_value("4")
+            )
+            .arg(
+                Arg::with_name("mempool_limit_txs")
+                    .long("mempool-limit-txs")
+                    .help("Maximum number of transactions kept in the indexed mempool, evicting the others from the view of electrs (default: no limit)")
+                    .takes_value(true)
+            )
+            .arg(
+                Arg::with_name("mempool_limit_memory")
+                    .long("mempool-limit-memory")
+                    .help("Maximum estimated memory used by the indexed mempool in MB, evicting transactions from the view of electrs past it (default: no limit)")
+                    .takes_value(true)
+            )
+            .arg(
+                Arg::with_name("mempool_eviction")
+                    .long("mempool-eviction")
+                    .help(&mempool_eviction_help)
+                    .default_value("lowest-feerate")
+            )
+            .arg(
+                Arg::with_name("secondary_daemons")
+                    .long("secondary-daemons")
+                    .help("Comma-separated list of additional daemon JSONRPC '[cookie-file@]addr:port' to compare chain tips against, for detecting chain splits (served by /network/forks). The cookie file holds the 'USER:PASSWORD' credentials of the daemon, which default to those of the primary daemon")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("fork_alert_depth")
+                    .long("fork-alert-depth")
+                    .help("Raise a chain split alert when a secondary daemon diverges from the indexed chain by more than this many blocks")
+                    .default_value("6")
+            )
+            .arg(
+                Arg::with_name("checkpoints")
+                    .long("checkpoints")
+                    .help("Comma-separated list of trusted 'height:blockhash' checkpoints. The indexer refuses to follow the daemon onto a chain that conflicts with them (reported by /network/checkpoints)")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("max_reorg_depth")
+                    .long("max-reorg-depth")
+                    .help("Refuse to follow reorgs that unwind more than this many indexed blocks (default: unlimited)")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("deployments")
+                    .long("deployments")
+                    .help("Comma-separated list of BIP9 deployments to track, as 'name:bit:start_time:timeout[:threshold]' (served by /stats/version-bits, the threshold defaults to 95% of the period)")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("deployment_period")
+                    .long("deployment-period")
+                    .help("Number of blocks in a BIP9 signaling period")
+                    .default_value("2016")
+            )
+            .arg(
+                Arg::with_name("retarget_interval")
+                    .long("retarget-interval")
+                    .help("Blocks between difficulty adjustments (defaults to the network's)")
+                    .takes_value(true)
+            )
+            .arg(
+                Arg::with_name("target_block_spacing")
+                    .long("target-block-spacing")
+                    .help("Targeted number of seconds between blocks (defaults to the network's)")
+                    .takes_value(true)
+            )
             .arg(
                 Arg::with_name("monitoring_addr")
                     .long("monitoring-addr")
@@ -148,22 +385,160 @@ impl Config {
                     .long("lightmode")
                     .help("Enable light mode for reduced storage")
             )
+            .arg(
+                Arg::with_name("replica_db_dir")
+                    .long("replica-db-dir")
+                    .help("Serve the index of the primary instance sharing --db-dir as a read-only replica, following it as it indexes new blocks. The replica keeps its own files, including its cache, in this directory")
+                    .takes_value(true)
+            )
             .arg(
                 Arg::with_name("address_search")
                     .long("address-search")
                     .help("Enable prefix address search")
             )
+            .arg(
+                Arg::with_name("flow_analysis")
+                    .long("flow-analysis")
+                    .help("Enable the search for the paths value took between two addresses (served by /flow). Each search may look up thousands of transactions")
+            )
             .arg(
                 Arg::with_name("index_unspendables")
                     .long("index-unspendables")
                     .help("Enable indexing of provably unspendable outputs")
             )
+            .arg(
+                Arg::with_name("large_tx_threshold")
+                    .long("large-tx-threshold")
+                    .help("Index transactions with a total output value of at least this many satoshis (served by /txs/large, default: disabled)")
+                    .takes_value(true)
+            )
+            .arg(
+                Arg::with_name("index_output_values")
+                    .long("index-output-values")
+                    .help("Enable indexing of the outputs by their exact value (served by /search/outputs)")
+            )
+            .arg(
+                Arg::with_name("replaced_tx_retention")
+                    .long("replaced-tx-retention")
+                    .help("Number of seconds to keep replaced mempool transactions in address histories, marked with the transaction that replaced them (0 to disable)")
+                    .default_value("3600")
+            )
+            .arg(
+                Arg::with_name("orphaned_spend_window")
+                    .long("orphaned-spend-window")
+                    .help("Mark the UTXOs that were spent in blocks orphaned by a reorg forking off the best chain up to this many blocks below the tip with the orphaned spending transaction (0 to disable)")
+                    .default_value("6")
+            )
+            .arg(
+                Arg::with_name("index_revealed_scripts")
+                    .long("index-revealed-scripts")
+                    .help("Enable indexing of the redeem and witness scripts revealed by spends (served by /script/:hash/reveal, and used to annotate the timelocks of UTXOs)")
+            )
+            .arg(
+                Arg::with_name("index_spend_paths")
+                    .long("index-spend-paths")
+                    .help("Enable indexing of the paths taken by Taproot and P2WSH spends (served by /address/:address/stats?verbose=true)")
+            )
+            .arg(
+                Arg::with_name("script_templates")
+                    .long("script-templates")
+                    .help("Comma-separated list of 'name:pattern' script templates, whose matching outputs get indexed (served by /templates/:name/txs). Patterns are in the asm form, with '<n>' for pushes of n bytes, '<*>' for any push, '*' for any instruction and '...' for any number of them. More templates can be registered with POST /admin/templates")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("daily_stats")
+                    .long("daily-stats")
+                    .help("Aggregate network-wide daily statistics in the background (served by /stats/daily)")
+            )
+            .arg(
+                Arg::with_name("broadcast_queue")
+                    .long("broadcast-queue")
+                    .help("Queue the transactions broadcasted while the daemon is unreachable and keep retrying them in the background (reported by /tx/:txid/broadcast-status)")
+            )
+            .arg(
+                Arg::with_name("rebroadcast_after")
+                    .long("rebroadcast-after")
+                    .help("Rebroadcast the transactions broadcasted through this instance that remain unconfirmed for this many blocks, unless submitted with ?rebroadcast=false (reported by /admin/rebroadcast/pending, default: disabled)")
+                    .takes_value(true)
+            )
+            .arg(
+                Arg::with_name("integrity_check_interval")
+                    .long("integrity-check-interval")
+                    .help("Check a random sample of scripthashes for index inconsistencies every this many seconds (reported by /admin/integrity, default: disabled)")
+                    .takes_value(true)
+            )
             .arg(
                 Arg::with_name("cors")
                     .long("cors")
-                    .help("Origins allowed to make cross-site requests")
+                    .help("Comma-separated origins allowed to make cross-site requests, where '*' matches anything (e.g. 'https://*.example.com'). Append '=METHOD|METHOD' to an origin to override --cors-methods for it")
+                    .takes_value(true)
+            )
+            .arg(
+                Arg::with_name("cors_methods")
+                    .long("cors-methods")
+                    .help("Comma-separated HTTP methods allowed for cross-site requests")
+                    .default_value("GET,POST")
+            )
+            .arg(
+                Arg::with_name("cors_headers")
+                    .long("cors-headers")
+                    .help("Comma-separated request headers allowed for cross-site requests")
+                    .default_value("Content-Type")
+            )
+            .arg(
+                Arg::with_name("cors_max_age")
+                    .long("cors-max-age")
+                    .help("Number of seconds browsers may cache the response to CORS preflight requests")
+                    .default_value("3600")
+            )
+            .arg(
+                Arg::with_name("locales")
+                    .long("locales")
+                    .help("Comma-separated languages the REST error messages and the formatted balance amounts can be served in, picked according to the Accept-Language header of the requests (en, de, es, fr or pt). The first one is the default")
+                    .default_value("en")
+            )
+            .arg(
+                Arg::with_name("cache_ttl_long")
+                    .long("cache-ttl-long")
+                    .help("Number of seconds the REST responses that won't change (e.g. deeply confirmed transactions) may be cached for")
+                    .default_value("157784630")
+            )
+            .arg(
+                Arg::with_name("cache_ttl_short")
+                    .long("cache-ttl-short")
+                    .help("Number of seconds the volatile REST responses (e.g. the chain tip or address balances) may be cached for")
+                    .default_value("10")
+            )
+            .arg(
+                Arg::with_name("cache_ttl_mempool_recent")
+                    .long("cache-ttl-mempool-recent")
+                    .help("Number of seconds the responses to /mempool/recent may be cached for")
+                    .default_value("5")
+            )
+            .arg(
+                Arg::with_name("cache_routes")
+                    .long("cache-routes")
+                    .help("Comma-separated REST paths with their own caching, where '*' matches anything, as 'PATH=SETTING|SETTING' with settings 'long:SECS', 'short:SECS', 'mempool-recent:SECS' or 'no-store' (e.g. '/blocks/tip/*=short:2,/tx/*=long:86400'). The first matching path applies, admin routes are never stored")
+                    .default_value("")
+            )
+            .arg(
+                Arg::with_name("stale_while_revalidate")
+                    .long("stale-while-revalidate")
+                    .help("Keep the responses of the heavy stats endpoints in memory for their short TTL, and keep serving them for up to this many seconds past it while refreshing them in the background (0 to disable)")
+                    .default_value("0")
+            )
+            .arg(
+                Arg::with_name("price_feed")
+                    .long("price-feed")
+                    .help("An http(s):// URL or a file to fetch coin exchange rates from, enabling ?fiat=<currency> (see doc/API.md for the format)")
                     .takes_value(true)
             )
+            .arg(
+                Arg::with_name("price_feed_interval")
+                    .long("price-feed-interval")
+                    .help("Number of seconds between fetches of the price feed")
+                    .default_value("300")
+            )
             .arg(
                 Arg::with_name("precache_scripts")
                     .long("precache-scripts")
@@ -176,6 +551,36 @@ impl Config {
                     .help("Maximum number of utxos to process per address. Lookups for addresses with more utxos will fail. Applies to the Electrum and HTTP APIs.")
                     .default_value("500")
             )
+            .arg(
+                Arg::with_name("max_txs_per_page")
+                    .long("max-txs-per-page")
+                    .help("Maximum number of transactions returned per page by the HTTP API. Requests for more will fail.")
+                    .default_value("100")
+            )
+            .arg(
+                Arg::with_name("max_mempool_txs")
+                    .long("max-mempool-txs")
+                    .help("Maximum number of unconfirmed transactions returned per address or asset by the HTTP API.")
+                    .default_value("50")
+            )
+            .arg(
+                Arg::with_name("max_batch_size")
+                    .long("max-batch-size")
+                    .help("Maximum number of items in a single batch request, for both the HTTP and Electrum APIs. Larger batches will fail.")
+                    .default_value("100")
+            )
+            .arg(
+                Arg::with_name("max_outspends_txids")
+                    .long("max-outspends-txids")
+                    .help("Maximum number of txids in a single /txs/outspends request")
+                    .default_value("1000")
+            )
+            .arg(
+                Arg::with_name("outspends_concurrency")
+                    .long("outspends-concurrency")
+                    .help("Maximum number of transactions of a /txs/outspends request to look up the spends of in parallel")
+                    .default_value("16")
+            )
             .arg(
                 Arg::with_name("electrum_txs_limit")
                     .long("electrum-txs-limit")
@@ -184,13 +589,139 @@ impl Config {
             ).arg(
                 Arg::with_name("electrum_banner")
                     .long("electrum-banner")
-                    .help("Welcome banner for the Electrum server, shown in the console to clients.")
+                    .help("Welcome banner for the Electrum server, shown in the console to clients. {height} and {version} are replaced with the current chain height and the server version.")
+                    .takes_value(true)
+            ).arg(
+                Arg::with_name("electrum_banner_file")
+                    .long("electrum-banner-file")
+                    .help("Read the Electrum welcome banner from a file (supports the same template variables as --electrum-banner)")
+                    .takes_value(true)
+                    .conflicts_with("electrum_banner")
+            ).arg(
+                Arg::with_name("electrum_donation_address")
+                    .long("electrum-donation-address")
+                    .help("Donation address returned by server.donation_address")
+                    .takes_value(true)
+            ).arg(
+                Arg::with_name("electrum_peers")
+                    .long("electrum-peers")
+                    .help("Comma-separated list of Electrum peers to always advertise via server.peers.subscribe, formatted as '<hostname> [t<port>] [s<port>]'")
                     .takes_value(true)
             ).arg(
                 Arg::with_name("electrum_rpc_logging")
                     .long("electrum-rpc-logging")
                     .help(&rpc_logging_help)
                     .takes_value(true),
+            ).arg(
+                Arg::with_name("electrum_batch_limit")
+                    .long("electrum-batch-limit")
+                    .help("Maximum number of requests in a single Electrum JSON-RPC batch (default: --max-batch-size)")
+                    .takes_value(true)
+            ).arg(
+                Arg::with_name("electrum_max_response_size")
+                    .long("electrum-max-response-size")
+                    .help("Maximum size (in bytes) of an Electrum response. Larger responses are replaced with an error.")
+                    .default_value("10000000")
+            ).arg(
+                Arg::with_name("electrum_max_pending_requests")
+                    .long("electrum-max-pending-requests")
+                    .help("Maximum number of requests queued per Electrum connection before reading from it is paused (at least 1)")
+                    .default_value("10")
+            ).arg(
+                Arg::with_name("electrum_max_connections")
+                    .long("electrum-max-connections")
+                    .help("Maximum number of concurrent Electrum connections (default: unlimited)")
+                    .takes_value(true)
+            ).arg(
+                Arg::with_name("electrum_max_connections_per_ip")
+                    .long("electrum-max-connections-per-ip")
+                    .help("Maximum number of concurrent Electrum connections from a single IP address (default: unlimited)")
+                    .takes_value(true)
+            ).arg(
+                Arg::with_name("electrum_idle_timeout")
+                    .long("electrum-idle-timeout")
+                    .help("Disconnect Electrum clients that send no requests for this many seconds (default: never)")
+                    .takes_value(true)
+            ).arg(
+                Arg::with_name("tx_cache_size")
+                    .long("tx-cache-size")
+                    .help("Size (in MB) of the in-memory LRU cache for raw transactions (0 to disable)")
+                    .default_value("64")
+            ).arg(
+                Arg::with_name("block_cache_size")
+                    .long("block-cache-size")
+                    .help("Size (in MB) of the in-memory LRU cache for block metadata (0 to disable)")
+                    .default_value("4")
+            ).arg(
+                Arg::with_name("prevout_cache_size")
+                    .long("prevout-cache-size")
+                    .help("Size (in MB) of the in-memory LRU cache for resolved prevouts (0 to disable)")
+                    .default_value("32")
+            ).arg(
+                Arg::with_name("raw_block_cache_size")
+                    .long("raw-block-cache-size")
+                    .help("Size (in MB) of the on-disk LRU cache for raw blocks fetched from the daemon in light mode (0 to disable)")
+                    .default_value("0")
+            ).arg(
+                Arg::with_name("slow_query_threshold")
+                    .long("slow-query-threshold")
+                    .help("Log REST and Electrum requests taking longer than this many milliseconds to the slow query log (default: disabled)")
+                    .takes_value(true)
+            ).arg(
+                Arg::with_name("slow_query_log_file")
+                    .long("slow-query-log-file")
+                    .help("File to append slow query log entries to (default: the regular log, with the slow_query target)")
+                    .takes_value(true)
+            ).arg(
+                Arg::with_name("audit_log_file")
+                    .long("audit-log-file")
+                    .help("File to append an audit log of the broadcasts and the admin endpoint calls to (served by /admin/audit, default: disabled)")
+                    .takes_value(true)
+            ).arg(
+                Arg::with_name("audit_log_max_size")
+                    .long("audit-log-max-size")
+                    .help("Size (in MB) past which the audit log file gets rotated")
+                    .default_value("64")
+            ).arg(
+                Arg::with_name("audit_log_max_files")
+                    .long("audit-log-max-files")
+                    .help("Number of rotated audit log files to keep")
+                    .default_value("4")
+            ).arg(
+                Arg::with_name("request_budget")
+                    .long("request-budget")
+                    .help("Cost units (DB lookups and scanned rows, plus KBs read) each client IP may spend per minute on REST and Electrum requests, past which requests are rejected with 429 (default: unlimited)")
+                    .takes_value(true)
+            ).arg(
+                Arg::with_name("request_budget_keys")
+                    .long("request-budget-keys")
+                    .help("Comma-separated list of API keys and their per-minute cost budgets, as KEY=BUDGET. Requests with a listed key in their X-Api-Key header are charged to the key rather than to their IP.")
+                    .takes_value(true)
+            ).arg(
+                Arg::with_name("request_timeout")
+                    .long("request-timeout")
+                    .help("Cancel the DB scans of REST and Electrum requests running for longer than this many seconds, failing them with 503 (default: disabled)")
+                    .takes_value(true)
+            ).arg(
+                Arg::with_name("admin_api")
+                    .long("admin-api")
+                    .help("Enable the /admin HTTP endpoints, for the requests bearing --admin-token. These should not be exposed publicly.")
+                    .requires("admin_token")
+            ).arg(
+                Arg::with_name("admin_token")
+                    .long("admin-token")
+                    .help("Token the /admin HTTP endpoints require in the 'Authorization: Bearer <TOKEN>' header. Prefer setting it through ELECTRS_ADMIN_TOKEN or the configuration file, as command line arguments are visible to other users")
+                    .takes_value(true)
+            ).arg(
+                Arg::with_name("shutdown_timeout")
+                    .long("shutdown-timeout")
+                    .help("Seconds to wait for the in-flight REST and Electrum requests to complete when shutting down, before flushing the index and exiting")
+                    .default_value("30")
+            ).arg(
+                Arg::with_name("network_conf")
+                    .long("network-conf")
+                    .help("Configuration files of additional networks to serve from the same process, comma-separated. Each network has its own daemon, database and Electrum server, and its REST API is served under the /<network>/ prefix.")
+                    .takes_value(true)
             );
 
         #[cfg(unix)]
@@ -214,6 +745,24 @@ impl Config {
                     .long("asset-db-path")
                     .help("Directory for liquid/elements asset db")
                     .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("asset_registry_sources")
+                    .long("asset-registry-sources")
+                    .help("Comma-separated list of additional asset registry directories or http(s):// URLs, taking precedence in the listed order after --asset-db-path")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("asset_registry_refresh_interval")
+                    .long("asset-registry-refresh-interval")
+                    .help("Number of seconds between reloads of the asset registry sources")
+                    .default_value("15"),
+            )
+            .arg(
+                Arg::with_name("asset_registry_merge")
+                    .long("asset-registry-merge")
+                    .help(&asset_registry_merge_help)
+                    .default_value("first"),
             );
 
         #[cfg(feature = "electrum-discovery")]
@@ -222,6 +771,11 @@ impl Config {
                     .long("electrum-public-hosts")
                     .help("A dictionary of hosts where the Electrum server can be reached at. Required to enable server discovery. See https://electrumx.readthedocs.io/en/latest/protocol-methods.html#server-features")
                     .takes_value(true)
+            ).arg(
+                Arg::with_name("electrum_onion_host")
+                    .long("electrum-onion-host")
+                    .help("Tor onion hostname the Electrum server is reachable at, advertised in server.features and to peers")
+                    .takes_value(true)
             ).arg(
                 Arg::with_name("electrum_announce")
                     .long("electrum-announce")
@@ -233,7 +787,7 @@ impl Config {
                 .takes_value(true),
         );
 
-        let m = args.get_matches();
+        let m = args.get_matches_from(argv);
 
         let network_name = m.value_of("network").unwrap_or("mainnet");
         let network_type = Network::from(network_name);
@@ -360,17 +914,41 @@ impl Config {
             .map(PathBuf::from)
             .unwrap_or_else(|| daemon_dir.join("blocks"));
         let cookie = m.value_of("cookie").map(|s| s.to_owned());
+        let secondary_daemons = m
+            .value_of("secondary_daemons")
+            .map_or_else(Vec::new, SecondaryDaemon::parse_list);
 
-        let electrum_banner = m.value_of("electrum_banner").map_or_else(
-            || format!("Welcome to electrs-esplora {}", ELECTRS_VERSION),
-            |s| s.into(),
-        );
+        let electrum_banner = match m.value_of("electrum_banner_file") {
+            Some(path) => fs::read_to_string(path)
+                .unwrap_or_else(|e| panic!("failed reading --electrum-banner-file: {}", e)),
+            None => m.value_of("electrum_banner").map_or_else(
+                || format!("Welcome to electrs-esplora {}", ELECTRS_VERSION),
+                |s| s.into(),
+            ),
+        };
+
+        let max_batch_size = value_t_or_exit!(m, "max_batch_size", usize);
+
+        // a zero-sized channel would make the reader wait for every request to be handled
+        let electrum_max_pending_requests =
+            value_t_or_exit!(m, "electrum_max_pending_requests", usize);
+        if electrum_max_pending_requests == 0 {
+            panic!("invalid --electrum-max-pending-requests, expected at least 1");
+        }
 
         #[cfg(feature = "electrum-discovery")]
         let electrum_public_hosts = m
             .value_of("electrum_public_hosts")
             .map(|s| serde_json::from_str(s).expect("invalid --electrum-public-hosts"));
 
+        #[cfg(feature = "electrum-discovery")]
+        let electrum_onion_host = m.value_of("electrum_onion_host").map(|host| {
+            if !host.ends_with(".onion") {
+                panic!("invalid --electrum-onion-host, expected an .onion hostname");
+            }
+            host.to_string()
+        });
+
         let mut log = stderrlog::new();
         log.verbosity(m.occurrences_of("verbosity") as usize);
         log.timestamp(if m.is_present("timestamp") {
@@ -378,7 +956,28 @@ impl Config {
         } else {
             stderrlog::Timestamp::Off
         });
-        log.init().expect("logging initialization failed");
+        // the logging is set up once, by the primary network
+        if primary {
+            if m.is_present("log_json") {
+                init_json_logging(m.occurrences_of("verbosity") as usize);
+            } else {
+                log.init().expect("logging initialization failed");
+            }
+        }
+
+        let additional_networks: Vec<(String, Config)> = match m.value_of("network_conf") {
+            Some(_) if !primary => panic!("--network-conf can't be set for additional networks"),
+            Some(list) => list.split(',').map(Config::from_network_conf).collect(),
+            None => vec![],
+        };
+        let mut served = vec![network_name];
+        for (name, _) in &additional_networks {
+            if served.contains(&name.as_str()) {
+                panic!("network {} is served more than once", name);
+            }
+            served.push(name);
+        }
+
         let config = Config {
             log,
             network_type,
@@ -387,31 +986,195 @@ impl Config {
             blocks_dir,
             daemon_rpc_addr,
             cookie,
+            zmq_addr: m
+                .value_of("zmq_addr")
+                .map(|addr| str_to_socketaddr(addr, "Bitcoin ZMQ")),
+            zmq_resync_interval: value_t_or_exit!(m, "zmq_resync_interval", u64).max(1),
+            mempool_fetch_batch_size: value_t_or_exit!(m, "mempool_fetch_batch_size", usize).max(1),
+            mempool_fetch_concurrency: value_t_or_exit!(m, "mempool_fetch_concurrency", usize)
+                .max(1),
+            mempool_limit_txs: m
+                .value_of("mempool_limit_txs")
+                .map(|s| s.parse().expect("invalid --mempool-limit-txs")),
+            mempool_limit_memory: m.value_of("mempool_limit_memory").map(|s| {
+                s.parse::<usize>().expect("invalid --mempool-limit-memory") * 1024 * 1024
+            }),
+            mempool_eviction: MempoolEviction::from(m.value_of("mempool_eviction").unwrap()),
+            secondary_daemons,
+            fork_alert_depth: value_t_or_exit!(m, "fork_alert_depth", usize),
+            checkpoints: m
+                .value_of("checkpoints")
+                .map_or_else(BTreeMap::new, parse_checkpoints),
+            max_reorg_depth: m
+                .value_of("max_reorg_depth")
+                .map(|s| s.parse().expect("invalid --max-reorg-depth")),
+            deployments: m
+                .value_of("deployments")
+                .map_or_else(Vec::new, Deployment::parse_list),
+            deployment_period: value_t_or_exit!(m, "deployment_period", usize).max(1),
+            #[cfg(not(feature = "liquid"))]
+            retarget_interval: m
+                .value_of("retarget_interval")
+                .map_or(network_type.params().retarget_interval, |s| {
+                    s.parse().expect("invalid --retarget-interval")
+                })
+                .max(1),
+            #[cfg(not(feature = "liquid"))]
+            target_block_spacing: m
+                .value_of("target_block_spacing")
+                .map_or(network_type.params().target_block_spacing, |s| {
+                    s.parse().expect("invalid --target-block-spacing")
+                })
+                .max(1),
             utxos_limit: value_t_or_exit!(m, "utxos_limit", usize),
+            max_txs_per_page: value_t_or_exit!(m, "max_txs_per_page", usize),
+            max_mempool_txs: value_t_or_exit!(m, "max_mempool_txs", usize),
+            max_batch_size,
+            max_outspends_txids: value_t_or_exit!(m, "max_outspends_txids", usize),
+            outspends_concurrency: value_t_or_exit!(m, "outspends_concurrency", usize).max(1),
             electrum_rpc_addr,
             electrum_txs_limit: value_t_or_exit!(m, "electrum_txs_limit", usize),
             electrum_banner,
+            electrum_donation_address: m
+                .value_of("electrum_donation_address")
+                .map(|s| s.to_string()),
+            electrum_peers: m.value_of("electrum_peers").map_or_else(Vec::new, |peers| {
+                peers
+                    .split(',')
+                    .map(|peer| peer.parse().expect("invalid --electrum-peers"))
+                    .collect()
+            }),
             electrum_rpc_logging: m
                 .value_of("electrum_rpc_logging")
                 .map(|option| RpcLogging::from(option)),
+            electrum_batch_limit: m
+                .value_of("electrum_batch_limit")
+                .map_or(max_batch_size, |s| s.parse().expect("invalid --electrum-batch-limit")),
+            electrum_max_response_size: value_t_or_exit!(m, "electrum_max_response_size", usize),
+            electrum_max_pending_requests,
+            electrum_max_connections: m
+                .value_of("electrum_max_connections")
+                .map(|s| s.parse().expect("invalid --electrum-max-connections")),
+            electrum_max_connections_per_ip: m
+                .value_of("electrum_max_connections_per_ip")
+                .map(|s| s.parse().expect("invalid --electrum-max-connections-per-ip")),
+            electrum_idle_timeout: m
+                .value_of("electrum_idle_timeout")
+                .map(|s| Duration::from_secs(s.parse().expect("invalid --electrum-idle-timeout"))),
+            admin_api: m.is_present("admin_api"),
+            admin_token: m.value_of("admin_token").map(|s| s.to_owned()),
+            shutdown_timeout: Duration::from_secs(value_t_or_exit!(m, "shutdown_timeout", u64)),
+            additional_networks,
+            tx_cache_size: value_t_or_exit!(m, "tx_cache_size", usize) * 1024 * 1024,
+            block_cache_size: value_t_or_exit!(m, "block_cache_size", usize) * 1024 * 1024,
+            prevout_cache_size: value_t_or_exit!(m, "prevout_cache_size", usize) * 1024 * 1024,
+            raw_block_cache_size: value_t_or_exit!(m, "raw_block_cache_size", usize) * 1024 * 1024,
+            slow_query_threshold: m
+                .value_of("slow_query_threshold")
+                .map(|s| Duration::from_millis(s.parse().expect("invalid --slow-query-threshold"))),
+            slow_query_log_file: m.value_of("slow_query_log_file").map(PathBuf::from),
+            audit_log_file: m.value_of("audit_log_file").map(PathBuf::from),
+            audit_log_max_size: value_t_or_exit!(m, "audit_log_max_size", u64) * 1024 * 1024,
+            audit_log_max_files: value_t_or_exit!(m, "audit_log_max_files", usize),
+            request_budget: m
+                .value_of("request_budget")
+                .map(|budget| budget.parse().expect("invalid --request-budget")),
+            request_budget_keys: m
+                .value_of("request_budget_keys")
+                .map_or_else(BTreeMap::new, parse_request_budget_keys),
+            request_timeout: m
+                .value_of("request_timeout")
+                .map(|s| Duration::from_secs(s.parse().expect("invalid --request-timeout"))),
             http_addr,
             http_socket_file,
             monitoring_addr,
             jsonrpc_import: m.is_present("jsonrpc_import"),
             light_mode: m.is_present("light_mode"),
+            replica_path: m
+                .value_of("replica_db_dir")
+                .map(|dir| Path::new(dir).join(network_name)),
             address_search: m.is_present("address_search"),
+            flow_analysis: m.is_present("flow_analysis"),
             index_unspendables: m.is_present("index_unspendables"),
-            cors: m.value_of("cors").map(|s| s.to_string()),
+            large_tx_threshold: m
+                .value_of("large_tx_threshold")
+                .map(|s| s.parse().expect("invalid --large-tx-threshold")),
+            index_output_values: m.is_present("index_output_values"),
+            replaced_tx_retention: Duration::from_secs(value_t_or_exit!(
+                m,
+                "replaced_tx_retention",
+                u64
+            )),
+            orphaned_spend_window: value_t_or_exit!(m, "orphaned_spend_window", usize),
+            index_revealed_scripts: m.is_present("index_revealed_scripts"),
+            index_spend_paths: m.is_present("index_spend_paths"),
+            #[cfg(not(feature = "liquid"))]
+            script_templates: m
+                .value_of("script_templates")
+                .map_or_else(Vec::new, parse_script_templates),
+            daily_stats: m.is_present("daily_stats"),
+            broadcast_queue: m.is_present("broadcast_queue"),
+            rebroadcast_after: m
+                .value_of("rebroadcast_after")
+                .map(|s| s.parse().expect("invalid --rebroadcast-after")),
+            integrity_check_interval: m
+                .value_of("integrity_check_interval")
+                .map(|s| s.parse().expect("invalid --integrity-check-interval")),
+            cache_policy: CachePolicy::new(
+                CacheTtl {
+                    long: value_t_or_exit!(m, "cache_ttl_long", u32),
+                    short: value_t_or_exit!(m, "cache_ttl_short", u32),
+                    mempool_recent: value_t_or_exit!(m, "cache_ttl_mempool_recent", u32),
+                },
+                m.value_of("cache_routes").unwrap(),
+            ),
+            stale_while_revalidate: value_t_or_exit!(m, "stale_while_revalidate", u64),
+            cors: m.value_of("cors").map(|origins| {
+                CorsPolicy::new(
+                    origins,
+                    m.value_of("cors_methods").unwrap(),
+                    m.value_of("cors_headers").unwrap(),
+                    value_t_or_exit!(m, "cors_max_age", u64),
+                )
+            }),
+            locales: parse_locales(m.value_of("locales").unwrap()),
             precache_scripts: m.value_of("precache_scripts").map(|s| s.to_string()),
+            price_feed: m.value_of("price_feed").map(|s| s.to_string()),
+            price_feed_interval: Duration::from_secs(value_t_or_exit!(
+                m,
+                "price_feed_interval",
+                u64
+            )),
 
             #[cfg(feature = "liquid")]
             parent_network,
             #[cfg(feature = "liquid")]
             asset_db_path,
+            #[cfg(feature = "liquid")]
+            asset_registry_sources: m
+                .value_of("asset_registry_sources")
+                .map_or_else(Vec::new, |sources| {
+                    sources
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|source| !source.is_empty())
+                        .map(String::from)
+                        .collect()
+                }),
+            #[cfg(feature = "liquid")]
+            asset_registry_refresh_interval: Duration::from_secs(value_t_or_exit!(
+                m,
+                "asset_registry_refresh_interval",
+                u64
+            )),
+            #[cfg(feature = "liquid")]
+            asset_registry_merge: RegistryMerge::from(m.value_of("asset_registry_merge").unwrap()),
 
             #[cfg(feature = "electrum-discovery")]
             electrum_public_hosts,
             #[cfg(feature = "electrum-discovery")]
+            electrum_onion_host,
+            #[cfg(feature = "electrum-discovery")]
             electrum_announce: m.is_present("electrum_announce"),
             #[cfg(feature = "electrum-discovery")]
             tor_proxy: m.value_of("tor_proxy").map(|s| s.parse().unwrap()),
@@ -427,7 +1190,7 @@ impl Config {
             })
         } else {
             Arc::new(CookieFile {
-                daemon_dir: self.daemon_dir.clone(),
+                path: self.daemon_dir.join(".cookie"),
             })
         }
     }
@@ -456,6 +1219,312 @@ impl From<&str> for RpcLogging {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MempoolEviction {
+    LowestFeerate,
+    Oldest,
+}
+
+impl MempoolEviction {
+    pub fn options() -> Vec<String> {
+        return vec!["lowest-feerate".to_string(), "oldest".to_string()];
+    }
+}
+
+impl From<&str> for MempoolEviction {
+    fn from(option: &str) -> Self {
+        match option {
+            "lowest-feerate" => MempoolEviction::LowestFeerate,
+            "oldest" => MempoolEviction::Oldest,
+
+            _ => panic!("unsupported mempool eviction option: {:?}", option),
+        }
+    }
+}
+
+fn parse_checkpoints(list: &str) -> BTreeMap<usize, BlockHash> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let (height, hash) = entry
+                .split_once(':')
+                .unwrap_or_else(|| panic!("invalid --checkpoints entry: {:?}", entry));
+            (
+                height.parse().expect("invalid --checkpoints height"),
+                hash.parse().expect("invalid --checkpoints blockhash"),
+            )
+        })
+        .collect()
+}
+
+// A BIP9 soft fork deployment signaled through a block version bit
+#[derive(Debug, Clone)]
+pub struct Deployment {
+    pub name: String,
+    pub bit: u8,
+    pub start_time: u32,
+    pub timeout: u32,
+    pub threshold: Option<u32>, // blocks per period, defaults to 95% of it
+}
+
+impl Deployment {
+    fn parse_list(list: &str) -> Vec<Self> {
+        list.split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| {
+                let fields: Vec<&str> = entry.split(':').collect();
+                if fields.len() != 4 && fields.len() != 5 {
+                    panic!("invalid --deployments entry: {:?}", entry);
+                }
+                let bit: u8 = fields[1].parse().expect("invalid --deployments bit");
+                assert!(bit < 29, "invalid --deployments bit: {}", bit);
+                Deployment {
+                    name: fields[0].to_string(),
+                    bit,
+                    start_time: fields[2].parse().expect("invalid --deployments start time"),
+                    timeout: fields[3].parse().expect("invalid --deployments timeout"),
+                    threshold: fields
+                        .get(4)
+                        .map(|s| s.parse().expect("invalid --deployments threshold")),
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(not(feature = "liquid"))]
+fn parse_script_templates(list: &str) -> Vec<ScriptTemplate> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let (name, pattern) = entry
+                .split_once(':')
+                .unwrap_or_else(|| panic!("invalid --script-templates entry: {:?}", entry));
+            ScriptTemplate::parse(name.trim(), pattern).unwrap_or_else(|e| {
+                panic!("invalid --script-templates entry {:?}: {}", entry, e)
+            })
+        })
+        .collect()
+}
+
+fn parse_locales(list: &str) -> Vec<Locale> {
+    let locales: Vec<Locale> = list
+        .split(',')
+        .map(str::trim)
+        .filter(|tag| !tag.is_empty())
+        .map(|tag| tag.parse().unwrap_or_else(|e| panic!("invalid --locales entry: {}", e)))
+        .collect();
+    assert!(!locales.is_empty(), "--locales must not be empty");
+    locales
+}
+
+fn parse_request_budget_keys(list: &str) -> BTreeMap<String, u64> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let (key, budget) = entry
+                .split_once('=')
+                .unwrap_or_else(|| panic!("invalid --request-budget-keys entry: {:?}", entry));
+            (
+                key.to_string(),
+                budget.parse().expect("invalid --request-budget-keys budget"),
+            )
+        })
+        .collect()
+}
+
+// An additional daemon whose chain tip is compared against the primary one
+#[derive(Debug, Clone)]
+pub struct SecondaryDaemon {
+    pub rpc_addr: SocketAddr,
+    pub cookie_file: Option<PathBuf>, // holds 'USER:PASSWORD', defaults to the primary daemon's
+}
+
+impl SecondaryDaemon {
+    fn parse_list(list: &str) -> Vec<Self> {
+        list.split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| match entry.rsplit_once('@') {
+                Some((cookie_file, addr)) => SecondaryDaemon {
+                    rpc_addr: str_to_socketaddr(addr, "secondary daemon RPC"),
+                    cookie_file: Some(PathBuf::from(cookie_file)),
+                },
+                None => SecondaryDaemon {
+                    rpc_addr: str_to_socketaddr(entry, "secondary daemon RPC"),
+                    cookie_file: None,
+                },
+            })
+            .collect()
+    }
+
+    // The cookie file is read on every connection, to pick up the daemon's rotated credentials
+    pub fn cookie_getter(&self, config: &Config) -> Arc<dyn CookieGetter> {
+        match self.cookie_file {
+            Some(ref path) => Arc::new(CookieFile { path: path.clone() }),
+            None => config.cookie_getter(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CorsPolicy {
+    rules: Vec<CorsRule>,
+    pub allowed_headers: String,
+    pub max_age: u64,
+}
+
+#[derive(Debug, Clone)]
+struct CorsRule {
+    origin: String, // may contain '*' wildcards
+    methods: Vec<String>,
+}
+
+impl CorsPolicy {
+    pub fn new(origins: &str, methods: &str, headers: &str, max_age: u64) -> Self {
+        let split_list = |list: &str, sep: char| -> Vec<String> {
+            list.split(sep)
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(str::to_string)
+                .collect()
+        };
+        let default_methods: Vec<String> = split_list(&methods.to_uppercase(), ',');
+        let rules = split_list(origins, ',')
+            .into_iter()
+            .map(|rule| match rule.split_once('=') {
+                Some((origin, methods)) => CorsRule {
+                    origin: origin.trim().to_string(),
+                    methods: split_list(&methods.to_uppercase(), '|'),
+                },
+                None => CorsRule {
+                    origin: rule,
+                    methods: default_methods.clone(),
+                },
+            })
+            .collect();
+        CorsPolicy {
+            rules,
+            allowed_headers: split_list(headers, ',').join(", "),
+            max_age,
+        }
+    }
+
+    // The value of the Access-Control-Allow-Origin header and the methods allowed for requests
+    // from `origin` (empty for same-origin requests), according to the first matching rule
+    pub fn lookup<'a>(&'a self, origin: &'a str) -> Option<(&'a str, &'a [String])> {
+        self.rules
+            .iter()
+            .find(|rule| wildcard_match(&rule.origin, origin))
+            .map(|rule| {
+                let allow_origin = if rule.origin == "*" { "*" } else { origin };
+                (allow_origin, &rule.methods[..])
+            })
+    }
+}
+
+// The max-age of the REST responses, by how volatile the resources are
+#[derive(Debug, Clone, Copy)]
+pub struct CacheTtl {
+    pub long: u32,           // resources that won't change, e.g. deeply confirmed transactions
+    pub short: u32,          // volatile resources
+    pub mempool_recent: u32, // GET /mempool/recent
+}
+
+#[derive(Debug, Clone)]
+pub struct CachePolicy {
+    pub default: CacheTtl,
+    routes: Vec<CacheRoute>,
+}
+
+#[derive(Debug, Clone)]
+struct CacheRoute {
+    path: String, // may contain '*' wildcards
+    ttl: CacheTtl,
+    no_store: bool,
+}
+
+impl CachePolicy {
+    pub fn new(default: CacheTtl, routes: &str) -> Self {
+        let routes = routes
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| {
+                let (path, settings) = entry
+                    .split_once('=')
+                    .unwrap_or_else(|| panic!("invalid --cache-routes entry: {:?}", entry));
+                let mut route = CacheRoute {
+                    path: path.trim().to_string(),
+                    ttl: default,
+                    no_store: false,
+                };
+                for setting in settings.split('|').map(str::trim) {
+                    if setting == "no-store" {
+                        route.no_store = true;
+                        continue;
+                    }
+                    let (class, ttl) = setting.split_once(':').unwrap_or_else(|| {
+                        panic!("invalid --cache-routes setting: {:?}", setting)
+                    });
+                    let ttl = ttl.parse().expect("invalid --cache-routes ttl");
+                    match class {
+                        "long" => route.ttl.long = ttl,
+                        "short" => route.ttl.short = ttl,
+                        "mempool-recent" => route.ttl.mempool_recent = ttl,
+                        _ => panic!("invalid --cache-routes setting: {:?}", setting),
+                    }
+                }
+                route
+            })
+            .collect();
+        CachePolicy { default, routes }
+    }
+
+    fn route(&self, path: &str) -> Option<&CacheRoute> {
+        self.routes
+            .iter()
+            .find(|route| wildcard_match(&route.path, path))
+    }
+
+    // The TTLs of the responses to requests for `path`, according to the first matching route
+    pub fn ttl(&self, path: &str) -> CacheTtl {
+        self.route(path).map_or(self.default, |route| route.ttl)
+    }
+
+    // Whether the responses to requests for `path` must not be stored by caches, as is always the
+    // case for the admin routes
+    pub fn no_store(&self, path: &str) -> bool {
+        path.starts_with("/admin/") || self.route(path).map_or(false, |route| route.no_store)
+    }
+}
+
+fn wildcard_match(pattern: &str, value: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or("");
+    let mut rest = match value.strip_prefix(first) {
+        Some(rest) => rest,
+        None => return false,
+    };
+    let parts: Vec<&str> = parts.collect();
+    match parts.split_last() {
+        None => rest.is_empty(),
+        Some((last, middle)) => {
+            for part in middle {
+                match rest.find(part) {
+                    Some(pos) => rest = &rest[pos + part.len()..],
+                    None => return false,
+                }
+            }
+            rest.ends_with(last)
+        }
+    }
+}
+
 pub fn get_network_subdir(network: Network) -> Option<&'static str> {
     match network {
         #[cfg(not(feature = "liquid"))]
@@ -487,14 +1556,13 @@ impl CookieGetter for StaticCookie {
 }
 
 struct CookieFile {
-    daemon_dir: PathBuf,
+    path: PathBuf,
 }
 
 impl CookieGetter for CookieFile {
     fn get(&self) -> Result<Vec<u8>> {
-        let path = self.daemon_dir.join(".cookie");
-        let contents = fs::read(&path).chain_err(|| {
-            ErrorKind::Connection(format!("failed to read cookie from {:?}", path))
+        let contents = fs::read(&self.path).chain_err(|| {
+            ErrorKind::Connection(format!("failed to read cookie from {:?}", self.path))
         })?;
         Ok(contents)
     }
@@ -0,0 +1,967 @@
+// Configuration files, in a subset of the TOML format, and environment variable overrides. Both
+// are turned into command-line arguments, so that the options are parsed and validated the same
+// way regardless of where they come from. The precedence is: command line > environment > file.
+use std::collections::{BTreeMap, HashSet};
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::errors::*;
+
+pub const ENV_PREFIX: &str = "ELECTRS_";
+
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Flag,   // a boolean
+    Scalar, // a string or a number
+    List,   // an array, or a string of comma-separated values
+    Count,  // the number of times the flag is repeated
+}
+use self::Kind::*;
+
+// The options available in the configuration files, named after their command-line flags with
+// underscores instead of dashes, and grouped in sections. Options with no section are top-level.
+const OPTIONS: &[(Option<&str>, &[(&str, Kind)])] = &[
+    (
+        None,
+        &[
+            ("network", Scalar),
+            ("shutdown_timeout", Scalar),
+            ("network_conf", List),
+        ],
+    ),
+    (
+        Some("log"),
+        &[
+            ("verbosity", Count),
+            ("timestamp", Flag),
+            ("log_json", Flag),
+            ("slow_query_threshold", Scalar),
+            ("slow_query_log_file", Scalar),
+            ("audit_log_file", Scalar),
+            ("audit_log_max_size", Scalar),
+            ("audit_log_max_files", Scalar),
+            ("request_budget", Scalar),
+            ("request_budget_keys", List),
+            ("request_timeout", Scalar),
+        ],
+    ),
+    (
+        Some("daemon"),
+        &[
+            ("daemon_dir", Scalar),
+            ("blocks_dir", Scalar),
+            ("cookie", Scalar),
+            ("daemon_rpc_addr", Scalar),
+            ("jsonrpc_import", Flag),
+            ("zmq_addr", Scalar),
+            ("zmq_resync_interval", Scalar),
+            ("secondary_daemons", List),
+            ("fork_alert_depth", Scalar),
+        ],
+    ),
+    (
+        Some("db"),
+        &[
+            ("db_dir", Scalar),
+            ("replica_db_dir", Scalar),
+            ("lightmode", Flag),
+            ("tx_cache_size", Scalar),
+            ("block_cache_size", Scalar),
+            ("prevout_cache_size", Scalar),
+            ("raw_block_cache_size", Scalar),
+        ],
+    ),
+    (
+        Some("index"),
+        &[
+            ("address_search", Flag),
+            ("flow_analysis", Flag),
+            ("index_unspendables", Flag),
+            ("large_tx_threshold", Scalar),
+            ("index_output_values", Flag),
+            ("replaced_tx_retention", Scalar),
+            ("orphaned_spend_window", Scalar),
+            ("index_revealed_scripts", Flag),
+            ("index_spend_paths", Flag),
+            ("script_templates", List),
+            ("precache_scripts", Scalar),
+            ("checkpoints", List),
+            ("max_reorg_depth", Scalar),
+            ("deployments", List),
+            ("deployment_period", Scalar),
+            ("retarget_interval", Scalar),
+            ("target_block_spacing", Scalar),
+        ],
+    ),
+    (
+        Some("mempool"),
+        &[
+            ("mempool_fetch_batch_size", Scalar),
+            ("mempool_fetch_concurrency", Scalar),
+            ("mempool_limit_txs", Scalar),
+            ("mempool_limit_memory", Scalar),
+            ("mempool_eviction", Scalar),
+        ],
+    ),
+    (
+        Some("rest"),
+        &[
+            ("http_addr", Scalar),
+            ("http_socket_file", Scalar),
+            ("cors", Scalar),
+            ("cors_methods", List),
+            ("cors_headers", List),
+            ("cors_max_age", Scalar),
+            ("locales", List),
+            ("cache_ttl_long", Scalar),
+            ("cache_ttl_short", Scalar),
+            ("cache_ttl_mempool_recent", Scalar),
+            ("cache_routes", List),
+            ("stale_while_revalidate", Scalar),
+            ("utxos_limit", Scalar),
+            ("max_txs_per_page", Scalar),
+            ("max_mempool_txs", Scalar),
+            ("max_batch_size", Scalar),
+            ("max_outspends_txids", Scalar),
+            ("outspends_concurrency", Scalar),
+            ("admin_api", Flag),
+            ("admin_token", Scalar),
+        ],
+    ),
+    (
+        Some("electrum"),
+        &[
+            ("electrum_rpc_addr", Scalar),
+            ("electrum_txs_limit", Scalar),
+            ("electrum_banner", Scalar),
+            ("electrum_banner_file", Scalar),
+            ("electrum_donation_address", Scalar),
+            ("electrum_peers", List),
+            ("electrum_rpc_logging", Scalar),
+            ("electrum_batch_limit", Scalar),
+            ("electrum_max_response_size", Scalar),
+            ("electrum_max_pending_requests", Scalar),
+            ("electrum_max_connections", Scalar),
+            ("electrum_max_connections_per_ip", Scalar),
+            ("electrum_idle_timeout", Scalar),
+            ("electrum_public_hosts", Scalar),
+            ("electrum_onion_host", Scalar),
+            ("electrum_announce", Flag),
+            ("tor_proxy", Scalar),
+        ],
+    ),
+    (
+        Some("jobs"),
+        &[
+            ("daily_stats", Flag),
+            ("broadcast_queue", Flag),
+            ("rebroadcast_after", Scalar),
+            ("integrity_check_interval", Scalar),
+            ("price_feed", Scalar),
+            ("price_feed_interval", Scalar),
+        ],
+    ),
+    (Some("monitoring"), &[("monitoring_addr", Scalar)]),
+    (
+        Some("liquid"),
+        &[
+            ("parent_network", Scalar),
+            ("asset_db_path", Scalar),
+            ("asset_registry_sources", List),
+            ("asset_registry_refresh_interval", Scalar),
+            ("asset_registry_merge", Scalar),
+        ],
+    ),
+];
+
+fn lookup(section: Option<&str>, key: &str) -> Option<Kind> {
+    OPTIONS
+        .iter()
+        .filter(|(name, _)| *name == section)
+        .flat_map(|(_, options)| options.iter())
+        .find(|(name, _)| *name == key)
+        .map(|(_, kind)| *kind)
+}
+
+// The section the key belongs to, for pointing out misplaced keys
+fn section_of(key: &str) -> Option<Option<&'static str>> {
+    OPTIONS
+        .iter()
+        .find(|(_, options)| options.iter().any(|(name, _)| *name == key))
+        .map(|(section, _)| *section)
+}
+
+fn qualified(section: Option<&str>, key: &str) -> String {
+    match section {
+        Some(section) => format!("{}.{}", section, key),
+        None => key.to_string(),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Float(f64),
+    Boolean(bool),
+    Array(Vec<Value>),
+}
+
+impl Value {
+    fn type_name(&self) -> &'static str {
+        match self {
+            Value::String(_) => "a string",
+            Value::Integer(_) => "an integer",
+            Value::Float(_) => "a float",
+            Value::Boolean(_) => "a boolean",
+            Value::Array(_) => "an array",
+        }
+    }
+
+    fn scalar(&self) -> Option<String> {
+        match self {
+            Value::String(s) => Some(s.clone()),
+            Value::Integer(n) => Some(n.to_string()),
+            Value::Float(n) => Some(n.to_string()),
+            Value::Boolean(_) | Value::Array(_) => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub section: Option<String>,
+    pub key: String,
+    pub value: Value,
+    pub line: usize,
+}
+
+struct Parser<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+    line: usize,
+    item_line: usize, // the line the current section header or key starts at
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().cloned()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.next();
+        if c == Some('\n') {
+            self.line += 1;
+        }
+        c
+    }
+
+    fn expect(&mut self, expected: char) -> Result<()> {
+        match self.next() {
+            Some(c) if c == expected => Ok(()),
+            Some(c) => bail!("expected {:?}, found {:?}", expected, c),
+            None => bail!("expected {:?}, found the end of the file", expected),
+        }
+    }
+
+    // Skip the spaces, and the newlines and comments too if `multiline`
+    fn skip_whitespace(&mut self, multiline: bool) {
+        while let Some(c) = self.peek() {
+            match c {
+                ' ' | '\t' | '\r' => (),
+                '\n' if multiline => (),
+                '#' => {
+                    while self.peek().map_or(false, |c| c != '\n') {
+                        self.next();
+                    }
+                    continue;
+                }
+                _ => break,
+            }
+            self.next();
+        }
+    }
+
+    fn end_of_line(&mut self) -> Result<()> {
+        self.skip_whitespace(false);
+        match self.next() {
+            None | Some('\n') => Ok(()),
+            Some(c) => bail!("unexpected {:?} after the value", c),
+        }
+    }
+
+    fn key(&mut self) -> Result<String> {
+        let key = match self.peek() {
+            Some('"') => self.basic_string()?,
+            Some('\'') => self.literal_string()?,
+            _ => {
+                let mut key = String::new();
+                while let Some(c) = self.peek() {
+                    if !(c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+                        break;
+                    }
+                    key.push(c);
+                    self.next();
+                }
+                key
+            }
+        };
+        if key.is_empty() {
+            bail!("expected a key");
+        }
+        self.skip_whitespace(false);
+        if self.peek() == Some('.') {
+            bail!("dotted keys are not supported");
+        }
+        Ok(key)
+    }
+
+    fn basic_string(&mut self) -> Result<String> {
+        self.expect('"')?;
+        let mut s = String::new();
+        loop {
+            match self.next() {
+                Some('"') => return Ok(s),
+                Some('\\') => {
+                    let c = match self.next() {
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some('r') => '\r',
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('u') => {
+                            let hex: String = (0..4).filter_map(|_| self.next()).collect();
+                            u32::from_str_radix(&hex, 16)
+                                .ok()
+                                .and_then(std::char::from_u32)
+                                .chain_err(|| format!("invalid unicode escape \\u{}", hex))?
+                        }
+                        c => bail!("invalid escape sequence {:?}", c),
+                    };
+                    s.push(c);
+                }
+                Some('\n') | None => bail!("unterminated string"),
+                Some(c) => s.push(c),
+            }
+        }
+    }
+
+    fn literal_string(&mut self) -> Result<String> {
+        self.expect('\'')?;
+        let mut s = String::new();
+        loop {
+            match self.next() {
+                Some('\'') => return Ok(s),
+                Some('\n') | None => bail!("unterminated string"),
+                Some(c) => s.push(c),
+            }
+        }
+    }
+
+    fn value(&mut self) -> Result<Value> {
+        match self.peek() {
+            Some('"') => Ok(Value::String(self.basic_string()?)),
+            Some('\'') => Ok(Value::String(self.literal_string()?)),
+            Some('[') => {
+                self.next();
+                let mut values = vec![];
+                loop {
+                    self.skip_whitespace(true);
+                    if self.peek() == Some(']') {
+                        self.next();
+                        return Ok(Value::Array(values));
+                    }
+                    values.push(self.value()?);
+                    self.skip_whitespace(true);
+                    match self.next() {
+                        Some(',') => (),
+                        Some(']') => return Ok(Value::Array(values)),
+                        _ => bail!("expected ',' or ']' in the array"),
+                    }
+                }
+            }
+            Some('{') => bail!("inline tables are not supported"),
+            _ => {
+                let mut token = String::new();
+                while let Some(c) = self.peek() {
+                    if !(c.is_ascii_alphanumeric() || "+-._".contains(c)) {
+                        break;
+                    }
+                    token.push(c);
+                    self.next();
+                }
+                let number = token.replace('_', "");
+                match token.as_str() {
+                    "true" => Ok(Value::Boolean(true)),
+                    "false" => Ok(Value::Boolean(false)),
+                    _ => {
+                        if let Ok(n) = number.parse() {
+                            Ok(Value::Integer(n))
+                        } else if let Ok(n) = number.parse() {
+                            Ok(Value::Float(n))
+                        } else {
+                            bail!("invalid value {:?}", token)
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn entries(&mut self) -> Result<Vec<Entry>> {
+        let mut entries = vec![];
+        let mut section = None;
+        let mut seen = HashSet::new();
+        loop {
+            self.skip_whitespace(true);
+            self.item_line = self.line;
+            match self.peek() {
+                None => return Ok(entries),
+                Some('[') => {
+                    self.next();
+                    if self.peek() == Some('[') {
+                        bail!("arrays of tables are not supported");
+                    }
+                    self.skip_whitespace(false);
+                    let name = self.key()?;
+                    self.expect(']')?;
+                    self.end_of_line()?;
+                    if !seen.insert((Some(name.clone()), None)) {
+                        bail!("duplicate section [{}]", name);
+                    }
+                    section = Some(name);
+                }
+                Some(_) => {
+                    let key = self.key()?;
+                    self.expect('=')?;
+                    self.skip_whitespace(false);
+                    let value = self.value()?;
+                    self.end_of_line()?;
+                    if !seen.insert((section.clone(), Some(key.clone()))) {
+                        bail!("duplicate key `{}`", qualified(section.as_deref(), &key));
+                    }
+                    entries.push(Entry {
+                        section: section.clone(),
+                        key,
+                        value,
+                        line: self.item_line,
+                    });
+                }
+            }
+        }
+    }
+}
+
+pub fn parse(text: &str) -> Result<Vec<Entry>> {
+    let mut parser = Parser {
+        chars: text.chars().peekable(),
+        line: 1,
+        item_line: 1,
+    };
+    parser
+        .entries()
+        .chain_err(|| format!("line {}", parser.item_line))
+}
+
+// The option values set by a configuration file, keyed by their name, along with where they were
+// set. Included files are loaded first, so that the including file takes precedence.
+fn load(
+    path: &Path,
+    options: &mut BTreeMap<String, (Kind, Value)>,
+    loading: &mut Vec<PathBuf>,
+) -> Result<()> {
+    let canonical = fs::canonicalize(path)
+        .chain_err(|| format!("failed to open config file {}", path.display()))?;
+    if loading.contains(&canonical) {
+        bail!("config file {} includes itself", path.display());
+    }
+    let text = fs::read_to_string(path)
+        .chain_err(|| format!("failed to read config file {}", path.display()))?;
+    let entries =
+        parse(&text).chain_err(|| format!("failed to parse config file {}", path.display()))?;
+
+    loading.push(canonical);
+    let (includes, entries): (Vec<Entry>, Vec<Entry>) = entries
+        .into_iter()
+        .partition(|entry| entry.section.is_none() && entry.key == "include");
+    for entry in includes {
+        let included = match entry.value {
+            Value::String(path) => vec![path],
+            Value::Array(ref paths) => paths
+                .iter()
+                .map(|path| match path {
+                    Value::String(path) => Ok(path.clone()),
+                    _ => Err(()),
+                })
+                .collect::<std::result::Result<_, _>>()
+                .ok()
+                .chain_err(|| {
+                    format!(
+                        "{}:{}: `include` expects strings",
+                        path.display(),
+                        entry.line
+                    )
+                })?,
+            ref value => bail!(
+                "{}:{}: `include` expects a string or an array, not {}",
+                path.display(),
+                entry.line,
+                value.type_name()
+            ),
+        };
+        // relative to the including file
+        let dir = path.parent().unwrap_or_else(|| Path::new("."));
+        for included in included {
+            load(&dir.join(included), options, loading)?;
+        }
+    }
+    loading.pop();
+
+    for entry in entries {
+        let section = entry.section.as_deref();
+        let name = qualified(section, &entry.key);
+        let origin = format!("{}:{}: `{}`", path.display(), entry.line, name);
+        let kind = match lookup(section, &entry.key) {
+            Some(kind) => kind,
+            None => match section_of(&entry.key) {
+                Some(Some(expected)) => {
+                    bail!("{}: unknown key, expected in [{}]", origin, expected)
+                }
+                Some(None) => bail!("{}: unknown key, expected at the top level", origin),
+                None if OPTIONS.iter().all(|(name, _)| *name != section) => {
+                    bail!("{}: unknown section", origin)
+                }
+                None => bail!("{}: unknown key", origin),
+            },
+        };
+        let valid = match (kind, &entry.value) {
+            (Flag, Value::Boolean(_)) => true,
+            (Count, Value::Integer(n)) => *n >= 0,
+            (Scalar, value) => value.scalar().is_some(),
+            (List, Value::Array(values)) => values.iter().all(|v| v.scalar().is_some()),
+            (List, value) => value.scalar().is_some(),
+            _ => false,
+        };
+        if !valid {
+            let expected = match kind {
+                Flag => "a boolean",
+                Scalar => "a string or a number",
+                List => "an array or a comma-separated string",
+                Count => "a non-negative integer",
+            };
+            bail!(
+                "{}: expected {}, found {}",
+                origin,
+                expected,
+                entry.value.type_name()
+            );
+        }
+        options.insert(entry.key, (kind, entry.value));
+    }
+    Ok(())
+}
+
+// The value of the ELECTRS_<OPTION> environment variable overriding the option, if set
+fn env_override(key: &str, kind: Kind) -> Result<Option<Value>> {
+    let var = format!("{}{}", ENV_PREFIX, key.to_uppercase());
+    let value = match env::var(&var) {
+        Ok(value) => value,
+        Err(env::VarError::NotPresent) => return Ok(None),
+        Err(e) => bail!("{}: {}", var, e),
+    };
+    let value = match kind {
+        Flag => match value.as_str() {
+            "1" | "true" => Value::Boolean(true),
+            "0" | "false" | "" => Value::Boolean(false),
+            _ => bail!("{}: expected true or false, found {:?}", var, value),
+        },
+        Count => Value::Integer(
+            value
+                .parse()
+                .chain_err(|| format!("{}: expected a non-negative integer", var))?,
+        ),
+        Scalar | List => Value::String(value),
+    };
+    Ok(Some(value))
+}
+
+fn flag(key: &str) -> String {
+    format!("--{}", key.replace('_', "-"))
+}
+
+// Whether the option was set on the command line, which takes precedence
+fn on_command_line(args: &[OsString], key: &str, kind: Kind) -> bool {
+    let flag = flag(key);
+    args.iter()
+        .filter_map(|arg| arg.to_str())
+        .any(|arg| match kind {
+            // -v, -vv, ...
+            Count => arg.starts_with('-') && !arg.starts_with("--") && arg.len() > 1,
+            _ => arg == flag || arg.starts_with(&format!("{}=", flag)),
+        })
+}
+
+// The path of the configuration file, from --conf or ELECTRS_CONF
+fn conf_path(args: &[OsString]) -> Option<PathBuf> {
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "--conf" {
+            return args.next().map(PathBuf::from);
+        }
+        if let Some(path) = arg.to_str().and_then(|arg| arg.strip_prefix("--conf=")) {
+            return Some(PathBuf::from(path));
+        }
+    }
+    env::var_os(format!("{}CONF", ENV_PREFIX)).map(PathBuf::from)
+}
+
+// The command-line arguments, extended with the options set by the configuration file and the
+// environment that weren't set on the command line
+pub fn expand_args(args: Vec<OsString>) -> Result<Vec<OsString>> {
+    let mut args = args.into_iter();
+    let program = match args.next() {
+        Some(program) => program,
+        None => return Ok(vec![]),
+    };
+    let args: Vec<OsString> = args.collect();
+
+    let mut options = BTreeMap::new();
+    if let Some(path) = conf_path(&args) {
+        load(&path, &mut options, &mut vec![])?;
+    }
+    for (_, keys) in OPTIONS {
+        for (key, kind) in keys.iter() {
+            if let Some(value) = env_override(key, *kind)? {
+                options.insert(key.to_string(), (*kind, value));
+            }
+        }
+    }
+
+    let mut expanded = vec![program];
+    expanded.extend(to_args(options, &args));
+    expanded.extend(args);
+    Ok(expanded)
+}
+
+// The command-line arguments of an additional network served by the same process, which are only
+// read from its own configuration file
+pub fn file_args(path: &Path) -> Result<Vec<OsString>> {
+    let mut options = BTreeMap::new();
+    load(path, &mut options, &mut vec![])?;
+    let mut args = vec![OsString::from("electrs")];
+    args.extend(to_args(options, &[]));
+    Ok(args)
+}
+
+fn to_args(options: BTreeMap<String, (Kind, Value)>, command_line: &[OsString]) -> Vec<OsString> {
+    let mut expanded = vec![];
+    for (key, (kind, value)) in options {
+        if on_command_line(command_line, &key, kind) {
+            continue;
+        }
+        match (kind, value) {
+            (Flag, Value::Boolean(true)) => expanded.push(flag(&key).into()),
+            (Flag, _) => (),
+            (Count, Value::Integer(n)) if n > 0 => {
+                expanded.push(format!("-{}", "v".repeat(n as usize)).into())
+            }
+            (Count, _) => (),
+            (_, Value::Array(values)) => {
+                let values: Vec<String> = values.iter().filter_map(Value::scalar).collect();
+                expanded.push(format!("{}={}", flag(&key), values.join(",")).into());
+            }
+            (_, value) => {
+                let value = value.scalar().expect("validated above");
+                expanded.push(format!("{}={}", flag(&key), value).into());
+            }
+        }
+    }
+    expanded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{expand_args, file_args, parse, Value};
+    use std::ffi::OsString;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    // The error along with its causes, outermost first
+    fn parse_error(text: &str) -> String {
+        let e = parse(text).unwrap_err();
+        e.iter()
+            .map(|e| e.to_string())
+            .collect::<Vec<_>>()
+            .join(": ")
+    }
+
+    fn load_error(path: &Path) -> String {
+        let e = file_args(path).unwrap_err();
+        e.iter()
+            .map(|e| e.to_string())
+            .collect::<Vec<_>>()
+            .join(": ")
+    }
+
+    fn write_conf(dir: &Path, name: &str, text: &str) -> PathBuf {
+        let path = dir.join(name);
+        fs::write(&path, text).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_parse_values() {
+        let entries = parse(
+            r#"
+            # a comment
+            network = "regtest"  # trailing comment
+            shutdown_timeout = 1_000
+            ratio = -1.5
+            lightmode = true
+            path = 'C:\no\escapes'
+            escaped = "tab\tquote\"\u00e9"
+            "quoted-key" = false
+            "#,
+        )
+        .unwrap();
+        let values: Vec<(&str, &Value)> = entries
+            .iter()
+            .map(|entry| (entry.key.as_str(), &entry.value))
+            .collect();
+        assert_eq!(
+            values,
+            vec![
+                ("network", &Value::String("regtest".into())),
+                ("shutdown_timeout", &Value::Integer(1000)),
+                ("ratio", &Value::Float(-1.5)),
+                ("lightmode", &Value::Boolean(true)),
+                ("path", &Value::String(r"C:\no\escapes".into())),
+                ("escaped", &Value::String("tab\tquote\"\u{e9}".into())),
+                ("quoted-key", &Value::Boolean(false)),
+            ]
+        );
+        assert_eq!(entries[0].line, 3);
+        assert!(entries.iter().all(|entry| entry.section.is_none()));
+    }
+
+    #[test]
+    fn test_parse_arrays() {
+        let entries = parse(
+            "empty = []\n\
+             inline = [1, \"two\", 3.5]\n\
+             multiline = [\n  \"a\", # first\n\n  \"b\",\n]\n\
+             nested = [[1], []]\n\
+             after = 1\n",
+        )
+        .unwrap();
+        assert_eq!(entries[0].value, Value::Array(vec![]));
+        assert_eq!(
+            entries[1].value,
+            Value::Array(vec![
+                Value::Integer(1),
+                Value::String("two".into()),
+                Value::Float(3.5)
+            ])
+        );
+        assert_eq!(
+            entries[2].value,
+            Value::Array(vec![Value::String("a".into()), Value::String("b".into())])
+        );
+        assert_eq!(
+            entries[3].value,
+            Value::Array(vec![
+                Value::Array(vec![Value::Integer(1)]),
+                Value::Array(vec![])
+            ])
+        );
+        // lines are counted across the multiline array
+        assert_eq!(entries[4].line, 9);
+    }
+
+    #[test]
+    fn test_parse_sections() {
+        let entries = parse(
+            "network = \"regtest\"\n\
+             [daemon]\n\
+             cookie = \"user:pass\"\n\
+             [ \"rest\" ]  # quoted section name\n\
+             cors = \"*\"\n",
+        )
+        .unwrap();
+        let sections: Vec<(Option<&str>, &str)> = entries
+            .iter()
+            .map(|entry| (entry.section.as_deref(), entry.key.as_str()))
+            .collect();
+        assert_eq!(
+            sections,
+            vec![
+                (None, "network"),
+                (Some("daemon"), "cookie"),
+                (Some("rest"), "cors")
+            ]
+        );
+        // the same key in different sections is not a duplicate
+        assert!(parse("a = 1\n[x]\na = 2\n[y]\na = 3\n").is_ok());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        for (text, expected) in &[
+            ("a = 1\nb = ", "line 2: invalid value \"\""),
+            ("a = 1 2", "line 1: unexpected '2' after the value"),
+            ("a 1", "line 1: expected '=', found '1'"),
+            ("= 1", "line 1: expected a key"),
+            ("a.b = 1", "line 1: dotted keys are not supported"),
+            ("a = { b = 1 }", "line 1: inline tables are not supported"),
+            ("[[a]]", "line 1: arrays of tables are not supported"),
+            ("[a\nb = 1", "line 1: expected ']', found '\\n'"),
+            ("a = \"abc", "line 1: unterminated string"),
+            ("a = 'abc\n'", "line 1: unterminated string"),
+            ("a = \"\\q\"", "line 1: invalid escape sequence Some('q')"),
+            ("a = \"\\uZZZZ\"", "line 1: invalid unicode escape \\uZZZZ"),
+            ("a = [1 2]", "line 1: expected ',' or ']' in the array"),
+            ("a = [1", "line 1: expected ',' or ']' in the array"),
+            ("a = 1\n\na = 2", "line 3: duplicate key `a`"),
+            ("[x]\na = 1\na = 2", "line 3: duplicate key `x.a`"),
+            ("[x]\n[y]\n[x]", "line 3: duplicate section [x]"),
+        ] {
+            assert_eq!(parse_error(text), *expected, "parsing {:?}", text);
+        }
+    }
+
+    #[test]
+    fn test_unknown_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        for (text, expected) in &[
+            ("foo = 1", "`foo`: unknown key"),
+            ("[daemon]\nfoo = 1", "`daemon.foo`: unknown key"),
+            ("[foo]\nbar = 1", "`foo.bar`: unknown section"),
+            (
+                "cookie = \"a\"",
+                "`cookie`: unknown key, expected in [daemon]",
+            ),
+            (
+                "[rest]\ncookie = \"a\"",
+                "`rest.cookie`: unknown key, expected in [daemon]",
+            ),
+            (
+                "[daemon]\nnetwork = \"regtest\"",
+                "unknown key, expected at the top level",
+            ),
+        ] {
+            let path = write_conf(dir.path(), "electrs.toml", text);
+            let error = load_error(&path);
+            assert!(error.ends_with(expected), "{:?} for {:?}", error, text);
+        }
+    }
+
+    #[test]
+    fn test_value_types() {
+        let dir = tempfile::tempdir().unwrap();
+        for (text, expected) in &[
+            (
+                "[db]\nlightmode = 1",
+                "expected a boolean, found an integer",
+            ),
+            (
+                "network = true",
+                "expected a string or a number, found a boolean",
+            ),
+            (
+                "network = [\"a\"]",
+                "expected a string or a number, found an array",
+            ),
+            (
+                "[daemon]\nsecondary_daemons = [true]",
+                "expected an array or a comma-separated string, found an array",
+            ),
+            (
+                "[log]\nverbosity = -1",
+                "expected a non-negative integer, found an integer",
+            ),
+        ] {
+            let path = write_conf(dir.path(), "electrs.toml", text);
+            let error = load_error(&path);
+            assert!(error.ends_with(expected), "{:?} for {:?}", error, text);
+        }
+    }
+
+    #[test]
+    fn test_file_args() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_conf(
+            dir.path(),
+            "electrs.toml",
+            "network = \"regtest\"\n\
+             [log]\n\
+             verbosity = 2\n\
+             timestamp = true\n\
+             [db]\n\
+             lightmode = false\n\
+             [daemon]\n\
+             secondary_daemons = [\"127.0.0.1:1\", \"127.0.0.1:2\"]\n\
+             [rest]\n\
+             cache_ttl_long = 60\n",
+        );
+        let args: Vec<OsString> = vec![
+            "electrs",
+            "--cache-ttl-long=60",
+            "--network=regtest",
+            "--secondary-daemons=127.0.0.1:1,127.0.0.1:2",
+            "--timestamp",
+            "-vv",
+        ]
+        .into_iter()
+        .map(OsString::from)
+        .collect();
+        assert_eq!(file_args(&path).unwrap(), args);
+    }
+
+    #[test]
+    fn test_includes() {
+        let dir = tempfile::tempdir().unwrap();
+        write_conf(
+            dir.path(),
+            "base.toml",
+            "network = \"testnet\"\n[db]\nlightmode = true\n",
+        );
+        let path = write_conf(
+            dir.path(),
+            "electrs.toml",
+            "include = \"base.toml\"\nnetwork = \"regtest\"\n",
+        );
+        // the including file takes precedence
+        let args = file_args(&path).unwrap();
+        assert_eq!(args, vec!["electrs", "--lightmode", "--network=regtest"]);
+
+        let path = write_conf(dir.path(), "loop.toml", "include = \"loop.toml\"\n");
+        assert!(load_error(&path).ends_with("loop.toml includes itself"));
+        let path = write_conf(dir.path(), "bad.toml", "include = 1\n");
+        assert!(
+            load_error(&path).ends_with("`include` expects a string or an array, not an integer")
+        );
+    }
+
+    #[test]
+    fn test_command_line_precedence() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_conf(
+            dir.path(),
+            "electrs.toml",
+            "network = \"testnet\"\n[log]\nverbosity = 3\n[db]\nlightmode = true\n",
+        );
+        let args: Vec<OsString> = vec![
+            "electrs".into(),
+            OsString::from(format!("--conf={}", path.display())),
+            "--network=regtest".into(),
+            "-v".into(),
+        ];
+        let expanded = expand_args(args.clone()).unwrap();
+        assert_eq!(
+            expanded[..2],
+            [OsString::from("electrs"), "--lightmode".into()]
+        );
+        assert_eq!(expanded[2..], args[1..]);
+    }
+}
@@ -4,7 +4,10 @@ use std::io::{BufRead, BufReader, Lines, Write};
 use std::net::{SocketAddr, TcpStream};
 use std::path::PathBuf;
 use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::AtomicUsize;
 use std::sync::{Arc, Mutex};
+use std::thread;
 use std::time::Duration;
 
 use base64::prelude::{Engine, BASE64_STANDARD};
@@ -85,7 +88,7 @@ fn parse_jsonrpc_reply(mut reply: Value, method: &str, expected_id: u64) -> Resu
                     match code {
                         // RPC_IN_WARMUP -> retry by later reconnection
                         -28 => bail!(ErrorKind::Connection(err.to_string())),
-                        _ => bail!("{} RPC error: {}", method, err),
+                        _ => bail!(ErrorKind::RpcError(method.to_string(), code, err.clone())),
                     }
                 }
             }
@@ -140,7 +143,8 @@ pub struct TxOutSetInfo {
     pub hash_serialized_2: String,
     #[serde(default)]
     pub disk_size: u64,
-    pub total_amount: f64,
+    #[serde(with = "bitcoin::amount::serde::as_btc")]
+    pub total_amount: bitcoin::Amount,
 }
 
 pub trait CookieGetter: Send + Sync {
@@ -301,6 +305,8 @@ pub struct Daemon {
     conn: Mutex<Connection>,
     message_id: Counter, // for monotonic JSONRPC 'id'
     signal: Waiter,
+    reconnecting: AtomicBool,
+    fetch_pool: Mutex<Vec<Daemon>>, // the extra connections fetching the mempool in parallel
 
     // monitoring
     latency: HistogramVec,
@@ -327,6 +333,8 @@ impl Daemon {
                 signal.clone(),
             )?),
             message_id: Counter::new(),
+            reconnecting: AtomicBool::new(false),
+            fetch_pool: Mutex::new(vec![]),
             signal: signal.clone(),
             latency: metrics.histogram_vec(
                 HistogramOpts::new("daemon_rpc", "Bitcoind RPC latency (in seconds)"),
@@ -375,6 +383,29 @@ impl Daemon {
             network: self.network,
             conn: Mutex::new(self.conn.lock().unwrap().reconnect()?),
             message_id: Counter::new(),
+            reconnecting: AtomicBool::new(false),
+            fetch_pool: Mutex::new(vec![]),
+            signal: self.signal.clone(),
+            latency: self.latency.clone(),
+            size: self.size.clone(),
+        })
+    }
+
+    // Connect another daemon of the same network, sharing the monitoring of this one. Unlike
+    // new(), this doesn't wait for the daemon to finish syncing.
+    pub fn connect_secondary(
+        &self,
+        rpc_addr: SocketAddr,
+        cookie_getter: Arc<dyn CookieGetter>,
+    ) -> Result<Daemon> {
+        Ok(Daemon {
+            daemon_dir: self.daemon_dir.clone(),
+            blocks_dir: self.blocks_dir.clone(),
+            network: self.network,
+            conn: Mutex::new(Connection::new(rpc_addr, cookie_getter, self.signal.clone())?),
+            message_id: Counter::new(),
+            reconnecting: AtomicBool::new(false),
+            fetch_pool: Mutex::new(vec![]),
             signal: self.signal.clone(),
             latency: self.latency.clone(),
             size: self.size.clone(),
@@ -440,9 +471,17 @@ impl Daemon {
             match self.handle_request_batch(method, params_list) {
                 Err(Error(ErrorKind::Connection(msg), _)) => {
                     warn!("reconnecting to bitcoind: {}", msg);
-                    self.signal.wait(Duration::from_secs(3), false)?;
-                    let mut conn = self.conn.lock().unwrap();
-                    *conn = conn.reconnect()?;
+                    // only the thread that raised the flag lowers it, so that it stays up while
+                    // the others are still reconnecting
+                    let raised = self
+                        .reconnecting
+                        .compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire)
+                        .is_ok();
+                    let reconnected = self.reconnect_conn();
+                    if raised {
+                        self.reconnecting.store(false, Ordering::Release);
+                    }
+                    reconnected?;
                     continue;
                 }
                 result => return result,
@@ -450,6 +489,13 @@ impl Daemon {
         }
     }
 
+    fn reconnect_conn(&self) -> Result<()> {
+        self.signal.wait(Duration::from_secs(3), false)?;
+        let mut conn = self.conn.lock().unwrap();
+        *conn = conn.reconnect()?;
+        Ok(())
+    }
+
     fn request(&self, method: &str, params: Value) -> Result<Value> {
         let mut values = self.retry_request_batch(method, &[params])?;
         assert_eq!(values.len(), 1);
@@ -472,6 +518,10 @@ impl Daemon {
         Ok(from_value(info).chain_err(|| "invalid network info")?)
     }
 
+    pub fn subversion(&self) -> Result<String> {
+        Ok(self.getnetworkinfo()?.subversion)
+    }
+
     pub fn getbestblockhash(&self) -> Result<BlockHash> {
         parse_hash(&self.request("getbestblockhash", json!([]))?)
     }
@@ -547,6 +597,94 @@ impl Daemon {
         self.request("getrawtransaction", json!([txid, verbose, blockhash]))
     }
 
+    // Fetch the mempool transactions in batches of `batch_size`, sending up to `concurrency`
+    // batches at once over separate connections. The transactions that left the mempool since
+    // they were listed are skipped.
+    pub fn getmempooltxs(
+        &self,
+        txids: &[&Txid],
+        batch_size: usize,
+        concurrency: usize,
+    ) -> Result<Vec<Transaction>> {
+        let batches: Vec<&[&Txid]> = txids.chunks(batch_size.max(1)).collect();
+        if batches.len() <= 1 || concurrency <= 1 {
+            let mut txs = vec![];
+            for batch in batches {
+                txs.extend(self.getmempooltxs_batch(batch)?);
+            }
+            return Ok(txs);
+        }
+
+        // the extra connections are kept around for the next fetches
+        let wanted = concurrency.min(batches.len()) - 1;
+        let mut connections: Vec<Daemon> = {
+            let mut pool = self.fetch_pool.lock().unwrap();
+            let pooled = pool.len();
+            pool.drain(pooled.saturating_sub(wanted)..).collect()
+        };
+        while connections.len() < wanted {
+            connections.push(self.reconnect()?);
+        }
+        let next_batch = AtomicUsize::new(0);
+        let fetched: Vec<Result<Vec<(usize, Vec<Transaction>)>>> = thread::scope(|scope| {
+            let workers: Vec<_> = connections
+                .iter()
+                .chain(std::iter::once(self))
+                .map(|daemon| {
+                    let (batches, next_batch) = (&batches, &next_batch);
+                    scope.spawn(move || -> Result<Vec<(usize, Vec<Transaction>)>> {
+                        let mut fetched = vec![];
+                        loop {
+                            let i = next_batch.fetch_add(1, Ordering::Relaxed);
+                            let batch = match batches.get(i) {
+                                Some(batch) => batch,
+                                None => return Ok(fetched),
+                            };
+                            fetched.push((i, daemon.getmempooltxs_batch(batch)?));
+                        }
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .map(|worker| worker.join().expect("mempool fetch worker panicked"))
+                .collect()
+        });
+
+        // after a failure the connections may be broken, they get dropped rather than pooled
+        if fetched.iter().all(Result::is_ok) {
+            self.fetch_pool.lock().unwrap().extend(connections);
+        }
+        let mut fetched = fetched
+            .into_iter()
+            .collect::<Result<Vec<_>>>()?
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>();
+        fetched.sort_unstable_by_key(|(i, _)| *i);
+        Ok(fetched.into_iter().flat_map(|(_, txs)| txs).collect())
+    }
+
+    fn getmempooltxs_batch(&self, txids: &[&Txid]) -> Result<Vec<Transaction>> {
+        match self.gettransactions(txids) {
+            // some left the mempool, fetch the batch one by one to skip them
+            Err(Error(ErrorKind::RpcError(_, -5, _), _)) => {
+                let mut txs = vec![];
+                for txid in txids {
+                    match self.getmempooltx(txid) {
+                        Ok(tx) => txs.push(tx),
+                        Err(Error(ErrorKind::RpcError(_, -5, _), _)) => {
+                            debug!("mempool tx {} is gone", txid)
+                        }
+                        Err(e) => return Err(e),
+                    }
+                }
+                Ok(txs)
+            }
+            result => result,
+        }
+    }
+
     pub fn getmempooltx(&self, txhash: &Txid) -> Result<Transaction> {
         let value = self.request("getrawtransaction", json!([txhash, /*verbose=*/ false]))?;
         tx_from_value(value)
@@ -569,6 +707,20 @@ impl Daemon {
         )
     }
 
+    // Broadcast without waiting for the daemon to become reachable, failing with a connection
+    // error rather than reconnecting
+    pub fn try_broadcast_raw(&self, txhex: &str) -> Result<Txid> {
+        if self.reconnecting.load(Ordering::Acquire) {
+            bail!(ErrorKind::Connection("daemon is reconnecting".to_owned()));
+        }
+        let mut replies = self.handle_request_batch("sendrawtransaction", &[json!([txhex])])?;
+        let txid = replies.remove(0);
+        Ok(
+            Txid::from_str(txid.as_str().chain_err(|| "non-string txid")?)
+                .chain_err(|| "failed to parse txid")?,
+        )
+    }
+
     // Get estimated feerates for the provided confirmation targets using a batch RPC request
     // Missing estimates are logged but do not cause a failure, whatever is available is returned
     #[allow(clippy::float_cmp)]
@@ -662,6 +814,17 @@ impl Daemon {
         Ok(new_headers)
     }
 
+    // Daemons with segwit active require its rule to be set, while the ones that don't know it
+    // reject it as an invalid parameter (RPC_INVALID_PARAMETER), and get asked without rules
+    pub fn getblocktemplate(&self) -> Result<Value> {
+        match self.request("getblocktemplate", json!([{"rules": ["segwit"]}])) {
+            Err(Error(ErrorKind::RpcError(_, -8, _), _)) => {
+                self.request("getblocktemplate", json!([{}]))
+            }
+            result => result,
+        }
+    }
+
     pub fn get_relayfee(&self) -> Result<f64> {
         let relayfee = self.getnetworkinfo()?.relayfee;
 
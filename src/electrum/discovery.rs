@@ -11,7 +11,7 @@ use std::time::{Duration, Instant};
 use electrum_client::ElectrumApi;
 
 use crate::chain::Network;
-use crate::electrum::{Client, Hostname, Port, ProtocolVersion, ServerFeatures};
+use crate::electrum::{Client, Hostname, Port, ProtocolVersion, ServerFeatures, StaticPeer};
 use crate::errors::{Result, ResultExt};
 use crate::util::spawn_thread;
 
@@ -123,6 +123,7 @@ impl DiscoveryManager {
     /// Add a server requested via `server.add_peer`
     pub fn add_server_request(&self, added_by: IpAddr, features: ServerFeatures) -> Result<()> {
         self.verify_compatibility(&features)?;
+        ensure!(!features.hosts.is_empty(), "no hosts advertised");
 
         let mut queue = self.queue.write().unwrap();
         ensure!(queue.len() < MAX_QUEUE_SIZE, "queue size exceeded");
@@ -144,10 +145,14 @@ impl DiscoveryManager {
             .filter_map(|(hostname, ports)| {
                 let hostname = hostname.to_lowercase();
 
-                if hostname.len() > 100 {
+                if !is_valid_hostname(&hostname) {
                     warn!("skipping invalid hostname");
                     return None;
                 }
+                if ports.tcp_port.is_none() && ports.ssl_port.is_none() {
+                    warn!("skipping {} without advertised ports", hostname);
+                    return None;
+                }
                 let addr = match ServerAddr::resolve(&hostname) {
                     Ok(addr) => addr,
                     Err(e) => {
@@ -214,6 +219,14 @@ impl DiscoveryManager {
         Ok(())
     }
 
+    /// Add a server from the static `--electrum-peers` list. These are health checked like the
+    /// default servers, but always advertised regardless of their health.
+    pub fn add_static_peer(&self, peer: &StaticPeer) -> Result<()> {
+        let tcp_service = peer.ports.tcp_port.into_iter().map(Service::Tcp);
+        let ssl_service = peer.ports.ssl_port.into_iter().map(Service::Ssl);
+        self.add_default_server(peer.hostname.clone(), tcp_service.chain(ssl_service).collect())
+    }
+
     /// Get the list of healthy servers formatted for `servers.peers.subscribe`
     pub fn get_servers(&self) -> Vec<ServerEntry> {
         // XXX return a random sample instead of everything?
@@ -518,6 +531,15 @@ fn is_remote_addr(addr: &ServerAddr) -> bool {
     }
 }
 
+/// Accept plain DNS hostnames, IPv4/IPv6 addresses and onion hosts only
+fn is_valid_hostname(hostname: &str) -> bool {
+    !hostname.is_empty()
+        && hostname.len() <= 100
+        && hostname
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.' || c == ':')
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1,5 +1,5 @@
 mod server;
-pub use server::RPC;
+pub use server::{get_scripthash_status, SessionInfo, Sessions, RPC};
 
 #[cfg(feature = "electrum-discovery")]
 mod client;
@@ -10,7 +10,9 @@ pub use {client::Client, discovery::DiscoveryManager};
 
 use std::cmp::Ordering;
 use std::collections::HashMap;
+use std::net::ToSocketAddrs;
 use std::str::FromStr;
+use std::sync::OnceLock;
 
 use serde::{de, Deserialize, Deserializer, Serialize};
 
@@ -48,6 +50,72 @@ pub struct ServerPorts {
     ssl_port: Option<Port>,
 }
 
+/// A peer from the static `--electrum-peers` list, always advertised via `server.peers.subscribe`
+#[derive(Clone, Debug)]
+pub struct StaticPeer {
+    pub hostname: Hostname,
+    pub ports: ServerPorts,
+    // resolved the first time the peer gets advertised, not to block parsing the config
+    addr: OnceLock<String>,
+}
+
+impl StaticPeer {
+    /// The address the peer is advertised by: its ip address, unless it is an onion host or
+    /// fails resolving
+    pub fn addr(&self) -> &str {
+        self.addr.get_or_init(|| {
+            if self.hostname.ends_with(".onion") {
+                return self.hostname.clone();
+            }
+            (self.hostname.as_str(), 0)
+                .to_socket_addrs()
+                .ok()
+                .and_then(|mut addrs| addrs.next())
+                .map_or_else(|| self.hostname.clone(), |addr| addr.ip().to_string())
+        })
+    }
+
+    /// The entry format used by `server.peers.subscribe`
+    pub fn entry(&self, protocol_version: &ProtocolVersion) -> (String, Hostname, Vec<String>) {
+        let mut features = vec![format!("v{}", protocol_version)];
+        features.extend(self.ports.tcp_port.map(|port| format!("t{}", port)));
+        features.extend(self.ports.ssl_port.map(|port| format!("s{}", port)));
+        (self.addr().to_string(), self.hostname.clone(), features)
+    }
+}
+
+/// Parses peers in the "<hostname> [t<port>] [s<port>]" format
+impl FromStr for StaticPeer {
+    type Err = crate::errors::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut iter = s.split_whitespace();
+        let hostname = iter.next().chain_err(|| "missing hostname")?.to_lowercase();
+        let mut ports = ServerPorts {
+            tcp_port: None,
+            ssl_port: None,
+        };
+        for service in iter {
+            let port = service.get(1..).and_then(|port| port.parse().ok());
+            match (service.chars().next(), port) {
+                (Some('t'), Some(port)) => ports.tcp_port = Some(port),
+                (Some('s'), Some(port)) => ports.ssl_port = Some(port),
+                _ => bail!("invalid service {}", service),
+            }
+        }
+        ensure!(
+            ports.tcp_port.is_some() || ports.ssl_port.is_some(),
+            "missing ports for {}",
+            hostname
+        );
+
+        Ok(StaticPeer {
+            hostname,
+            ports,
+            addr: OnceLock::new(),
+        })
+    }
+}
+
 #[derive(Eq, PartialEq, Debug, Clone, Default)]
 pub struct ProtocolVersion {
     major: usize,
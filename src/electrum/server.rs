@@ -1,10 +1,10 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::io::{BufRead, BufReader, Write};
 use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
 use std::sync::mpsc::{self, Receiver, Sender, SyncSender, TrySendError};
 use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::Instant;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use bitcoin::hashes::sha256d::Hash as Sha256dHash;
 use crypto::digest::Digest;
@@ -18,21 +18,30 @@ use bitcoin::consensus::encode::serialize_hex;
 #[cfg(feature = "liquid")]
 use elements::encode::serialize_hex;
 
+#[cfg(not(feature = "liquid"))]
+use crate::{
+    chain::{deserialize, Network, Transaction},
+    util::{is_coinbase, parse_multisig, ScriptToAddr, ScriptToAsm},
+};
+
 use crate::chain::Txid;
 use crate::config::{Config, RpcLogging};
 use crate::electrum::{get_electrum_height, ProtocolVersion};
 use crate::errors::*;
 use crate::metrics::{Gauge, HistogramOpts, HistogramVec, MetricOpts, Metrics};
-use crate::new_index::{Query, Utxo};
+use crate::new_index::{Deadline, Query, ReadStats, ScriptChanges, Utxo};
 use crate::util::electrum_merkle::{get_header_merkle_proof, get_id_from_pos, get_tx_merkle_proof};
-use crate::util::{create_socket, spawn_thread, BlockId, BoolThen, Channel, FullHash, HeaderEntry};
+use crate::util::{
+    create_socket, full_hash, join_timeout, spawn_thread, AuditEvent, BlockId, BoolThen, Channel,
+    FullHash, HeaderEntry, SlowQuery,
+};
 
 const ELECTRS_VERSION: &str = env!("CARGO_PKG_VERSION");
 const PROTOCOL_VERSION: ProtocolVersion = ProtocolVersion::new(1, 4);
 const MAX_HEADERS: usize = 2016;
 
 #[cfg(feature = "electrum-discovery")]
-use crate::electrum::{DiscoveryManager, ServerFeatures};
+use crate::electrum::{DiscoveryManager, ServerFeatures, ServerHosts, ServerPorts};
 
 // TODO: Sha256dHash should be a generic hash-container (since script hash is single SHA256)
 fn hash_from_value(val: Option<&Value>) -> Result<Sha256dHash> {
@@ -42,6 +51,112 @@ fn hash_from_value(val: Option<&Value>) -> Result<Sha256dHash> {
     Ok(script_hash)
 }
 
+// The transaction in the format of bitcoind's `getrawtransaction` with `verbose=true`
+#[cfg(not(feature = "liquid"))]
+fn verbose_tx(
+    tx: &Transaction,
+    rawtx: &[u8],
+    blockid: Option<BlockId>,
+    tip_height: usize,
+    network: Network,
+) -> Value {
+    let vin: Vec<Value> = tx
+        .input
+        .iter()
+        .map(|txin| {
+            let mut vin = if is_coinbase(txin) {
+                json!({ "coinbase": txin.script_sig.as_bytes().to_lower_hex_string() })
+            } else {
+                json!({
+                    "txid": txin.previous_output.txid,
+                    "vout": txin.previous_output.vout,
+                    "scriptSig": {
+                        "asm": txin.script_sig.to_asm(),
+                        "hex": txin.script_sig.as_bytes().to_lower_hex_string(),
+                    },
+                })
+            };
+            if !txin.witness.is_empty() {
+                vin["txinwitness"] = json!(txin
+                    .witness
+                    .iter()
+                    .map(DisplayHex::to_lower_hex_string)
+                    .collect::<Vec<_>>());
+            }
+            vin["sequence"] = json!(txin.sequence.0);
+            vin
+        })
+        .collect();
+    let vout: Vec<Value> = tx
+        .output
+        .iter()
+        .enumerate()
+        .map(|(n, txout)| {
+            let script = &txout.script_pubkey;
+            let mut script_pubkey = json!({
+                "asm": script.to_asm(),
+                "hex": script.as_bytes().to_lower_hex_string(),
+                "type": bitcoind_script_type(script),
+            });
+            if let Some(address) = script.to_address_str(network) {
+                script_pubkey["address"] = json!(address);
+            }
+            json!({
+                "value": txout.value.to_btc(),
+                "n": n,
+                "scriptPubKey": script_pubkey,
+            })
+        })
+        .collect();
+
+    let mut value = json!({
+        "hex": rawtx.to_lower_hex_string(),
+        "txid": tx.txid(),
+        "hash": tx.wtxid(),
+        "size": tx.total_size(),
+        "vsize": tx.vsize(),
+        "weight": tx.weight().to_wu(),
+        "version": tx.version.0,
+        "locktime": tx.lock_time.to_consensus_u32(),
+        "vin": vin,
+        "vout": vout,
+    });
+    if let Some(blockid) = blockid {
+        value["blockhash"] = json!(blockid.hash);
+        // the tip may have been reorged below the block since it was looked up
+        value["confirmations"] = json!((tip_height + 1).saturating_sub(blockid.height));
+        value["time"] = json!(blockid.time);
+        value["blocktime"] = json!(blockid.time);
+    }
+    value
+}
+
+// The script types as named by bitcoind
+#[cfg(not(feature = "liquid"))]
+fn bitcoind_script_type(script: &bitcoin::Script) -> &'static str {
+    if script.is_p2pk() {
+        "pubkey"
+    } else if script.is_p2pkh() {
+        "pubkeyhash"
+    } else if script.is_p2sh() {
+        "scripthash"
+    } else if script.is_p2wpkh() {
+        "witness_v0_keyhash"
+    } else if script.is_p2wsh() {
+        "witness_v0_scripthash"
+    } else if script.is_p2tr() {
+        "witness_v1_taproot"
+    } else if script.is_witness_program() {
+        "witness_unknown"
+    } else if script.is_op_return() {
+        "nulldata"
+    } else if parse_multisig(script).is_some() {
+        "multisig"
+    } else {
+        "nonstandard"
+    }
+}
+
 fn usize_from_value(val: Option<&Value>, name: &str) -> Result<usize> {
     let val = val.chain_err(|| format!("missing {}", name))?;
     let val = val.as_u64().chain_err(|| format!("non-integer {}", name))?;
@@ -68,27 +183,100 @@ fn bool_from_value_or(val: Option<&Value>, name: &str, default: bool) -> Result<
     bool_from_value(val, name)
 }
 
-// TODO: implement caching and delta updates
-fn get_status_hash(txs: Vec<(Txid, Option<BlockId>)>, query: &Query) -> Option<FullHash> {
-    if txs.is_empty() {
-        None
-    } else {
-        let mut hash = FullHash::default();
-        let mut sha2 = Sha256::new();
-        for (txid, blockid) in txs {
-            let is_mempool = blockid.is_none();
-            let has_unconfirmed_parents = is_mempool
-                .and_then(|| Some(query.has_unconfirmed_parents(&txid)))
-                .unwrap_or(false);
-            let height = get_electrum_height(blockid, has_unconfirmed_parents);
-            let part = format!("{}:{}:", txid, height);
-            sha2.input(part.as_bytes());
-        }
-        sha2.result(&mut hash);
-        Some(hash)
+// The Electrum status of a subscribed scripthash. The status hash input of the confirmed
+// history is kept, so that only the history confirmed since the last update gets read
+// (unless the chain got reorged below it).
+struct ScriptStatus {
+    confirmed: Sha256,
+    confirmed_count: usize,
+    tip: Option<BlockId>, // the best block the confirmed history was read up to
+    status_hash: Option<FullHash>,
+}
+
+impl ScriptStatus {
+    fn new(query: &Query, scripthash: &[u8], txs_limit: usize) -> Result<Self> {
+        let mut status = ScriptStatus {
+            confirmed: Sha256::new(),
+            confirmed_count: 0,
+            tip: None,
+            status_hash: None,
+        };
+        status.update(query, scripthash, txs_limit)?;
+        Ok(status)
+    }
+
+    // Fold the newly confirmed history into the status and recompute the status hash with the
+    // mempool history, returning whether it changed
+    fn update(&mut self, query: &Query, scripthash: &[u8], txs_limit: usize) -> Result<bool> {
+        let chain = query.chain();
+        let reorged = self.tip.as_ref().map_or(false, |last| {
+            chain
+                .header_by_height(last.height)
+                .map_or(true, |header| *header.hash() != last.hash)
+        });
+        if reorged {
+            self.confirmed = Sha256::new();
+            self.confirmed_count = 0;
+            self.tip = None;
+        }
+
+        // to avoid silently truncating the history, ask for one extra more than the limit and
+        // fail if it exists
+        let tip = BlockId::from(&chain.best_header());
+        let start_height = self.tip.as_ref().map_or(0, |last| last.height + 1);
+        let remaining = txs_limit - self.confirmed_count;
+        let confirmed_txids =
+            chain.history_txids_range(scripthash, start_height, tip.height, remaining + 1);
+        ensure!(confirmed_txids.len() <= remaining, ErrorKind::TooPopular);
+        for (txid, blockid) in confirmed_txids {
+            let part = format!("{}:{}:", txid, get_electrum_height(Some(blockid), false));
+            self.confirmed.input(part.as_bytes());
+            self.confirmed_count += 1;
+        }
+        self.tip = Some(tip);
+
+        let remaining = txs_limit - self.confirmed_count;
+        let mempool_txids = query
+            .mempool()
+            .history_txids(scripthash, None, remaining + 1);
+        ensure!(mempool_txids.len() <= remaining, ErrorKind::TooPopular);
+        let status_hash = if self.confirmed_count + mempool_txids.len() == 0 {
+            None
+        } else {
+            let mut sha2 = self.confirmed;
+            for txid in mempool_txids {
+                let height = get_electrum_height(None, query.has_unconfirmed_parents(&txid));
+                let part = format!("{}:{}:", txid, height);
+                sha2.input(part.as_bytes());
+            }
+            let mut hash = FullHash::default();
+            sha2.result(&mut hash);
+            Some(hash)
+        };
+
+        let changed = status_hash != self.status_hash;
+        self.status_hash = status_hash;
+        Ok(changed)
+    }
+
+    fn to_value(&self) -> Value {
+        self.status_hash
+            .map_or(Value::Null, |h| json!(h.to_lower_hex_string()))
     }
 }
 
+// The Electrum status of a scripthash, as returned by blockchain.scripthash.subscribe
+pub fn get_scripthash_status(
+    query: &Query,
+    scripthash: &[u8],
+    txs_limit: usize,
+) -> Result<Option<FullHash>> {
+    Ok(ScriptStatus::new(query, scripthash, txs_limit)?.status_hash)
+}
+
+// ScriptHash -> ids of the connections subscribed to it
+type SubscriptionIndex = Arc<Mutex<HashMap<FullHash, HashSet<usize>>>>;
+
 macro_rules! conditionally_log_rpc_event {
     ($self:ident, $event:expr) => {
         if $self.rpc_logging.is_some() {
@@ -98,13 +286,16 @@ macro_rules! conditionally_log_rpc_event {
 }
 
 struct Connection {
+    id: usize,
     query: Arc<Query>,
     last_header_entry: Option<HeaderEntry>,
-    status_hashes: HashMap<Sha256dHash, Value>, // ScriptHash -> StatusHash
+    status_hashes: HashMap<Sha256dHash, ScriptStatus>, // ScriptHash -> Status
     stream: TcpStream,
     addr: SocketAddr,
     sender: SyncSender<Message>,
     stats: Arc<Stats>,
+    subscriptions: SubscriptionIndex,
+    sessions: Arc<Sessions>,
     txs_limit: usize,
     #[cfg(feature = "electrum-discovery")]
     discovery: Option<Arc<DiscoveryManager>>,
@@ -112,17 +303,22 @@ struct Connection {
 }
 
 impl Connection {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
+        id: usize,
         query: Arc<Query>,
         stream: TcpStream,
         addr: SocketAddr,
         sender: SyncSender<Message>,
         stats: Arc<Stats>,
+        subscriptions: SubscriptionIndex,
+        sessions: Arc<Sessions>,
         txs_limit: usize,
         #[cfg(feature = "electrum-discovery")] discovery: Option<Arc<DiscoveryManager>>,
         rpc_logging: Option<RpcLogging>,
     ) -> Connection {
         Connection {
+            id,
             query,
             last_header_entry: None, // disable header subscription for now
             status_hashes: HashMap::new(),
@@ -130,6 +326,8 @@ impl Connection {
             addr,
             sender,
             stats,
+            subscriptions,
+            sessions,
             txs_limit,
             #[cfg(feature = "electrum-discovery")]
             discovery,
@@ -153,7 +351,13 @@ impl Connection {
     }
 
     fn server_banner(&self) -> Result<Value> {
-        Ok(json!(self.query.config().electrum_banner.clone()))
+        let banner = self
+            .query
+            .config()
+            .electrum_banner
+            .replace("{version}", ELECTRS_VERSION)
+            .replace("{height}", &self.query.chain().best_height().to_string());
+        Ok(json!(banner))
     }
 
     #[cfg(feature = "electrum-discovery")]
@@ -166,20 +370,31 @@ impl Connection {
     }
 
     fn server_donation_address(&self) -> Result<Value> {
-        Ok(Value::Null)
+        Ok(json!(self.query.config().electrum_donation_address))
     }
 
     fn server_peers_subscribe(&self) -> Result<Value> {
         #[cfg(feature = "electrum-discovery")]
-        let servers = self
-            .discovery
-            .as_ref()
-            .map_or_else(|| json!([]), |d| json!(d.get_servers()));
+        let discovered: Vec<Value> = self.discovery.as_ref().map_or_else(Vec::new, |d| {
+            d.get_servers().into_iter().map(|entry| json!(entry)).collect()
+        });
 
         #[cfg(not(feature = "electrum-discovery"))]
-        let servers = json!([]);
+        let discovered: Vec<Value> = vec![];
 
-        Ok(servers)
+        let static_peers = &self.query.config().electrum_peers;
+        let mut servers: Vec<Value> = static_peers
+            .iter()
+            .map(|peer| json!(peer.entry(&PROTOCOL_VERSION)))
+            .collect();
+        // discovered servers that are also configured statically are only listed once
+        servers.extend(
+            discovered
+                .into_iter()
+                .filter(|entry| !static_peers.iter().any(|peer| entry[1] == peer.hostname)),
+        );
+
+        Ok(json!(servers))
     }
 
     #[cfg(feature = "electrum-discovery")]
@@ -280,12 +495,20 @@ impl Connection {
     fn blockchain_scripthash_subscribe(&mut self, params: &[Value]) -> Result<Value> {
         let script_hash = hash_from_value(params.get(0)).chain_err(|| "bad script_hash")?;
 
-        let history_txids = get_history(&self.query, &script_hash[..], self.txs_limit)?;
-        let status_hash = get_status_hash(history_txids, &self.query)
-            .map_or(Value::Null, |h| json!(h.to_lower_hex_string()));
+        let status = ScriptStatus::new(&self.query, &script_hash[..], self.txs_limit)?;
+        let status_hash = status.to_value();
 
-        if let None = self.status_hashes.insert(script_hash, status_hash.clone()) {
+        if let None = self.status_hashes.insert(script_hash, status) {
             self.stats.subscriptions.inc();
+            self.subscriptions
+                .lock()
+                .unwrap()
+                .entry(full_hash(&script_hash[..]))
+                .or_insert_with(HashSet::new)
+                .insert(self.id);
+            let subscriptions = self.status_hashes.len();
+            self.sessions
+                .update(self.id, |session| session.subscriptions = subscriptions);
         }
         Ok(status_hash)
     }
@@ -293,7 +516,7 @@ impl Connection {
     #[cfg(not(feature = "liquid"))]
     fn blockchain_scripthash_get_balance(&self, params: &[Value]) -> Result<Value> {
         let script_hash = hash_from_value(params.get(0)).chain_err(|| "bad script_hash")?;
-        let (chain_stats, mempool_stats) = self.query.stats(&script_hash[..]);
+        let (chain_stats, mempool_stats) = self.query.stats(&script_hash[..])?;
 
         Ok(json!({
             "confirmed": chain_stats.funded_txo_sum - chain_stats.spent_txo_sum,
@@ -350,8 +573,20 @@ impl Connection {
     fn blockchain_transaction_broadcast(&self, params: &[Value]) -> Result<Value> {
         let tx = params.get(0).chain_err(|| "missing tx")?;
         let tx = tx.as_str().chain_err(|| "non-string tx")?.to_string();
-        let txid = self.query.broadcast_raw(&tx)?;
-        if let Err(e) = self.sender.try_send(Message::PeriodicUpdate) {
+        // queued broadcasts return their txid too, as the protocol has no way to tell them apart
+        let result = self
+            .query
+            .broadcast_raw(&tx, true)
+            .map(|broadcast| broadcast.txid);
+        if let Some(audit_log) = self.query.audit_log() {
+            let txid = result.as_ref().ok();
+            let mut event = AuditEvent::new("broadcast", "electrum", json!({ "txids": [txid] }));
+            event.client = Some(self.addr.to_string());
+            event.error = result.as_ref().err().map(|e| e.to_string());
+            audit_log.record(&event);
+        }
+        let txid = result?;
+        if let Err(e) = self.sender.try_send(Message::PeriodicUpdate(None)) {
             warn!("failed to issue PeriodicUpdate after broadcast: {}", e);
         }
         Ok(json!(txid))
@@ -364,7 +599,7 @@ impl Connection {
             None => false,
         };
 
-        // FIXME: implement verbose support
+        #[cfg(feature = "liquid")]
         if verbose {
             bail!("verbose transactions are currently unsupported");
         }
@@ -373,6 +608,13 @@ impl Connection {
             .query
             .lookup_raw_txn(&tx_hash)
             .chain_err(|| "missing transaction")?;
+        #[cfg(not(feature = "liquid"))]
+        if verbose {
+            let tx: Transaction = deserialize(&rawtx).chain_err(|| "cannot parse transaction")?;
+            let blockid = self.query.chain().tx_confirming_block(&tx_hash);
+            let tip_height = self.query.chain().best_height();
+            return Ok(verbose_tx(&tx, &rawtx, blockid, tip_height, self.query.network()));
+        }
         Ok(json!(rawtx.to_lower_hex_string()))
     }
 
@@ -447,6 +689,9 @@ impl Connection {
             "server.features" => self.server_features(),
             #[cfg(feature = "electrum-discovery")]
             "server.add_peer" => self.server_add_peer(&params),
+            // peers can't be added without discovery, let them know they were not accepted
+            #[cfg(not(feature = "electrum-discovery"))]
+            "server.add_peer" => Ok(json!(false)),
 
             &_ => bail!("unknown method {} {:?}", method, params),
         };
@@ -462,12 +707,45 @@ impl Connection {
                     params,
                     e.display_chain()
                 );
-                json!({"jsonrpc": "2.0", "id": id, "error": format!("{}", e)})
+                match e.kind() {
+                    ErrorKind::RpcError(_, code, error)
+                        if method == "blockchain.transaction.broadcast" =>
+                    {
+                        json!({"jsonrpc": "2.0", "id": id, "error": broadcast_error(*code, error)})
+                    }
+                    _ => error_reply(id, format!("{}", e)),
+                }
             }
         })
     }
 
-    fn update_subscriptions(&mut self) -> Result<Vec<Value>> {
+    fn record_if_slow(&self, method: &str, params: &[Value], duration: Duration, db: ReadStats) {
+        let slow_query_log = match self.query.slow_query_log() {
+            Some(slow_query_log) if slow_query_log.is_slow(duration) => slow_query_log,
+            _ => return,
+        };
+        let scripthash = if method.starts_with("blockchain.scripthash.") {
+            params.get(0).and_then(|param| param.as_str())
+        } else {
+            None
+        };
+        let script_tx_count = scripthash
+            .and_then(|_| hash_from_value(params.get(0)).ok())
+            .and_then(|script_hash| self.query.chain().cached_tx_count(&script_hash[..]));
+
+        slow_query_log.record(&SlowQuery {
+            source: "electrum",
+            route: method,
+            params: json!(params),
+            duration_ms: duration.as_millis(),
+            scripthash: scripthash.map(|s| s.to_string()),
+            script_tx_count,
+            db,
+        });
+    }
+
+    // Only the scripthashes in `changed` are re-checked, or all of them when it is None
+    fn update_subscriptions(&mut self, changed: Option<HashSet<FullHash>>) -> Result<Vec<Value>> {
         let timer = self
             .stats
             .latency
@@ -486,18 +764,19 @@ impl Connection {
                     "params": [header]}));
             }
         }
-        for (script_hash, status_hash) in self.status_hashes.iter_mut() {
-            let history_txids = get_history(&self.query, &script_hash[..], self.txs_limit)?;
-            let new_status_hash = get_status_hash(history_txids, &self.query)
-                .map_or(Value::Null, |h| json!(h.to_lower_hex_string()));
-            if new_status_hash == *status_hash {
+        for (script_hash, status) in self.status_hashes.iter_mut() {
+            if let Some(ref changed) = changed {
+                if !changed.contains(&full_hash(&script_hash[..])) {
+                    continue;
+                }
+            }
+            if !status.update(&self.query, &script_hash[..], self.txs_limit)? {
                 continue;
             }
             result.push(json!({
                 "jsonrpc": "2.0",
                 "method": "blockchain.scripthash.subscribe",
-                "params": [script_hash, new_status_hash]}));
-            *status_hash = new_status_hash;
+                "params": [script_hash, status.to_value()]}));
         }
         timer.observe_duration();
         Ok(result)
@@ -524,62 +803,135 @@ impl Connection {
         Ok(())
     }
 
-    fn handle_replies(&mut self, receiver: Receiver<Message>) -> Result<()> {
+    fn handle_request(&mut self, cmd: &Value) -> Result<Value> {
+        let _span = tracing::info_span!("rpc", conn_id = self.id, addr = %self.addr).entered();
+        let start_time = Instant::now();
+        ReadStats::take();
+        self.sessions.update(self.id, |session| {
+            session.requests += 1;
+            session.last_request = unix_time();
+        });
         let empty_params = json!([]);
+        match (
+            cmd.get("method"),
+            cmd.get("params").unwrap_or_else(|| &empty_params),
+            cmd.get("id"),
+        ) {
+            (Some(&Value::String(ref method)), &Value::Array(ref params), Some(ref id)) => {
+                conditionally_log_rpc_event!(
+                    self,
+                    json!({
+                        "event": "rpc request",
+                        "id": id,
+                        "method": method,
+                        "params": if let Some(RpcLogging::Full) = self.rpc_logging {
+                            json!(params)
+                        } else {
+                            Value::Null
+                        }
+                    })
+                );
+
+                let budget = self.query.request_budget();
+                let budget_client =
+                    budget.and_then(|budget| budget.client(None, Some(self.addr.ip())));
+                if let Some(ref client) = budget_client {
+                    if let Err(retry_after) = budget.unwrap().check(client) {
+                        let message = format!(
+                            "request budget exceeded, retry in {} seconds",
+                            retry_after.as_secs() + 1
+                        );
+                        return Ok(error_reply(id, message));
+                    }
+                }
+
+                let deadline = self.query.config().request_timeout.map(Deadline::set);
+                let mut reply = self.handle_command(method, params, id)?;
+                if deadline.map_or(false, |deadline| deadline.cancelled()) {
+                    reply = error_reply(id, "request timed out".to_string());
+                }
+                let db = ReadStats::take();
+                if let Some(client) = budget_client {
+                    budget.unwrap().charge(client, db.cost());
+                }
+                self.record_if_slow(method, params, start_time.elapsed(), db);
+
+                conditionally_log_rpc_event!(
+                    self,
+                    json!({
+                        "event": "rpc response",
+                        "method": method,
+                        "payload_size": reply.to_string().as_bytes().len(),
+                        "duration_micros": start_time.elapsed().as_micros(),
+                        "id": id,
+                    })
+                );
+
+                Ok(reply)
+            }
+            _ => bail!("invalid command: {}", cmd),
+        }
+    }
+
+    fn handle_batch(&mut self, cmds: &[Value]) -> Result<Value> {
+        let config = self.query.config();
+        let (batch_limit, max_response_size) =
+            (config.electrum_batch_limit, config.electrum_max_response_size);
+
+        if cmds.is_empty() || cmds.len() > batch_limit {
+            return Ok(error_reply(
+                &Value::Null,
+                format!("batch size must be between 1 and {}", batch_limit),
+            ));
+        }
+
+        let mut replies = Vec::with_capacity(cmds.len());
+        let mut response_size = 0;
+        for cmd in cmds {
+            // a malformed entry gets an error reply of its own, the rest of the batch still runs
+            let reply = self.handle_request(cmd).unwrap_or_else(|e| {
+                error_reply(cmd.get("id").unwrap_or(&Value::Null), e.to_string())
+            });
+            // stop early rather than buffering an oversized response
+            response_size += reply.to_string().len();
+            if response_size > max_response_size {
+                return Ok(error_reply(
+                    &Value::Null,
+                    format!("response exceeds {} bytes", max_response_size),
+                ));
+            }
+            replies.push(reply);
+        }
+        Ok(Value::Array(replies))
+    }
+
+    fn handle_replies(&mut self, receiver: Receiver<Message>) -> Result<()> {
         loop {
             let msg = receiver.recv().chain_err(|| "channel closed")?;
-            let start_time = Instant::now();
             trace!("RPC {:?}", msg);
             match msg {
                 Message::Request(line) => {
                     let cmd: Value = from_str(&line).chain_err(|| "invalid JSON format")?;
-                    match (
-                        cmd.get("method"),
-                        cmd.get("params").unwrap_or_else(|| &empty_params),
-                        cmd.get("id"),
-                    ) {
-                        (
-                            Some(&Value::String(ref method)),
-                            &Value::Array(ref params),
-                            Some(ref id),
-                        ) => {
-                            conditionally_log_rpc_event!(
-                                self,
-                                json!({
-                                    "event": "rpc request",
-                                    "id": id,
-                                    "method": method,
-                                    "params": if let Some(RpcLogging::Full) = self.rpc_logging {
-                                        json!(params)
-                                    } else {
-                                        Value::Null
-                                    }
-                                })
-                            );
-
-                            let reply = self.handle_command(method, params, id)?;
-
-                            conditionally_log_rpc_event!(
-                                self,
-                                json!({
-                                    "event": "rpc response",
-                                    "method": method,
-                                    "payload_size": reply.to_string().as_bytes().len(),
-                                    "duration_micros": start_time.elapsed().as_micros(),
-                                    "id": id,
-                                })
-                            );
-
-                            self.send_values(&[reply])?
-                        }
-                        _ => {
-                            bail!("invalid command: {}", cmd)
+                    let reply = match cmd {
+                        Value::Array(ref cmds) => self.handle_batch(cmds)?,
+                        ref cmd => {
+                            let reply = self.handle_request(cmd)?;
+                            let max_response_size = self.query.config().electrum_max_response_size;
+                            if reply.to_string().len() > max_response_size {
+                                error_reply(
+                                    cmd.get("id").unwrap_or(&Value::Null),
+                                    format!("response exceeds {} bytes", max_response_size),
+                                )
+                            } else {
+                                reply
+                            }
                         }
-                    }
+                    };
+                    self.send_values(&[reply])?
                 }
-                Message::PeriodicUpdate => {
+                Message::PeriodicUpdate(changed) => {
                     let values = self
-                        .update_subscriptions()
+                        .update_subscriptions(changed)
                         .chain_err(|| "failed to update subscriptions")?;
                     self.send_values(&values)?
                 }
@@ -621,11 +973,29 @@ impl Connection {
         result
     }
 
+    fn unsubscribe_all(&self) {
+        let mut subscriptions = self.subscriptions.lock().unwrap();
+        for script_hash in self.status_hashes.keys() {
+            let script_hash = full_hash(&script_hash[..]);
+            if let Some(conn_ids) = subscriptions.get_mut(&script_hash) {
+                conn_ids.remove(&self.id);
+                if conn_ids.is_empty() {
+                    subscriptions.remove(&script_hash);
+                }
+            }
+        }
+    }
+
     pub fn run(mut self, receiver: Receiver<Message>) {
         self.stats.clients.inc();
         conditionally_log_rpc_event!(self, json!({ "event": "connection established" }));
 
-        let reader = BufReader::new(self.stream.try_clone().expect("failed to clone TcpStream"));
+        let reader_stream = self.stream.try_clone().expect("failed to clone TcpStream");
+        // idle clients are disconnected once reading from them times out
+        if let Err(e) = reader_stream.set_read_timeout(self.query.config().electrum_idle_timeout) {
+            warn!("[{}] failed to set idle timeout: {}", self.addr, e);
+        }
+        let reader = BufReader::new(reader_stream);
         let sender = self.sender.clone();
         let child = spawn_thread("reader", || Connection::reader_thread(reader, sender));
         if let Err(e) = self.handle_replies(receiver) {
@@ -639,6 +1009,7 @@ impl Connection {
         self.stats
             .subscriptions
             .sub(self.status_hashes.len() as i64);
+        self.unsubscribe_all();
 
         debug!("[{}] shutting down connection", self.addr);
         conditionally_log_rpc_event!(self, json!({ "event": "connection closed" }));
@@ -661,6 +1032,76 @@ fn get_history(
     Ok(history_txids)
 }
 
+fn error_reply(id: &Value, message: String) -> Value {
+    json!({"jsonrpc": "2.0", "id": id, "error": message})
+}
+
+// Electrum protocol error codes
+const BAD_REQUEST: i64 = 1;
+const DAEMON_ERROR: i64 = 2;
+
+// Map a sendrawtransaction failure to the error ElectrumX returns, which Electrum clients look
+// for the daemon's reject reason in, along with a reason wallets can classify the failure by
+fn broadcast_error(code: i64, error: &Value) -> Value {
+    let message = error["message"].as_str().unwrap_or_default();
+    let lowercase = message.to_lowercase();
+    let matches = |patterns: &[&str]| patterns.iter().any(|pattern| lowercase.contains(pattern));
+    let already_in_mempool = ["txn-already-in-mempool", "txn-already-known", "already in mempool"];
+    let reason = if matches(&already_in_mempool) {
+        "already-in-mempool"
+    } else if code == -27 || matches(&["already in block chain", "already in utxo set"]) {
+        "already-in-chain"
+    } else if matches(&["missing inputs", "missingorspent", "missing-inputs"]) {
+        "missing-inputs"
+    } else if matches(&[
+        "min relay fee not met",
+        "mempool min fee not met",
+        "insufficient fee",
+        "insufficient priority",
+    ]) {
+        "insufficient-fee"
+    } else if matches(&["absurdly-high-fee", "max-fee-exceeded"]) {
+        "excessive-fee"
+    } else if matches(&["txn-mempool-conflict"]) {
+        "mempool-conflict"
+    } else if matches(&["too-long-mempool-chain"]) {
+        "too-long-mempool-chain"
+    } else if code == -22 {
+        "decode-failed"
+    } else if code == -26 || code == -25 {
+        "rejected"
+    } else {
+        return json!({
+            "code": DAEMON_ERROR,
+            "message": format!("daemon error: {}", message),
+            "data": {"reason": "daemon-error", "rpc_code": code},
+        });
+    };
+    json!({
+        "code": BAD_REQUEST,
+        "message": format!("the transaction was rejected by network rules.\n\n{}", message),
+        "data": {"reason": reason, "rpc_code": code},
+    })
+}
+
+// Map each connection to the changed scripthashes it is subscribed to
+fn group_by_subscriber(
+    changes: &ScriptChanges,
+    subscriptions: &SubscriptionIndex,
+) -> HashMap<usize, HashSet<FullHash>> {
+    let subscriptions = subscriptions.lock().unwrap();
+    let mut changed_by_conn = HashMap::new();
+    for script_hash in changes.iter() {
+        for conn_id in subscriptions.get(script_hash).into_iter().flatten() {
+            changed_by_conn
+                .entry(*conn_id)
+                .or_insert_with(HashSet::new)
+                .insert(*script_hash);
+        }
+    }
+    changed_by_conn
+}
+
 #[derive(Serialize, Debug)]
 struct GetHistoryResult {
     #[serde(rename = "tx_hash")]
@@ -673,7 +1114,7 @@ struct GetHistoryResult {
 #[derive(Debug)]
 pub enum Message {
     Request(String),
-    PeriodicUpdate,
+    PeriodicUpdate(Option<HashSet<FullHash>>), // changed scripthashes, None to re-check all
     Done,
 }
 
@@ -685,6 +1126,8 @@ pub enum Notification {
 pub struct RPC {
     notification: Sender<Notification>,
     server: Option<thread::JoinHandle<()>>, // so we can join the server while dropping this ojbect
+    sessions: Arc<Sessions>,
+    shutdown_timeout: Duration,
 }
 
 struct Stats {
@@ -693,24 +1136,142 @@ struct Stats {
     subscriptions: Gauge,
 }
 
+#[derive(Serialize, Clone)]
+pub struct SessionInfo {
+    pub id: usize,
+    pub addr: SocketAddr,
+    pub connected_at: u64,
+    pub last_request: Option<u64>,
+    pub requests: u64,
+    pub subscriptions: usize,
+}
+
+// The active Electrum sessions, shared with the REST admin API
+#[derive(Default)]
+pub struct Sessions {
+    sessions: Mutex<HashMap<usize, SessionInfo>>,
+}
+
+impl Sessions {
+    pub fn list(&self) -> Vec<SessionInfo> {
+        let mut sessions: Vec<SessionInfo> =
+            self.sessions.lock().unwrap().values().cloned().collect();
+        sessions.sort_unstable_by_key(|session| session.id);
+        sessions
+    }
+
+    // Register a new session, unless it exceeds the configured connection limits. The session
+    // is removed once the returned slot gets dropped.
+    fn try_add(
+        self: &Arc<Self>,
+        id: usize,
+        addr: SocketAddr,
+        config: &Config,
+    ) -> Result<SessionSlot> {
+        let mut sessions = self.sessions.lock().unwrap();
+        if let Some(max_connections) = config.electrum_max_connections {
+            ensure!(
+                sessions.len() < max_connections,
+                "too many connections ({})",
+                sessions.len()
+            );
+        }
+        if let Some(max_per_ip) = config.electrum_max_connections_per_ip {
+            let ip_connections = sessions
+                .values()
+                .filter(|session| session.addr.ip() == addr.ip())
+                .count();
+            ensure!(
+                ip_connections < max_per_ip,
+                "too many connections from {} ({})",
+                addr.ip(),
+                ip_connections
+            );
+        }
+        sessions.insert(
+            id,
+            SessionInfo {
+                id,
+                addr,
+                connected_at: unix_time().unwrap_or(0),
+                last_request: None,
+                requests: 0,
+                subscriptions: 0,
+            },
+        );
+        Ok(SessionSlot {
+            sessions: Arc::clone(self),
+            id,
+        })
+    }
+
+    fn update(&self, id: usize, f: impl FnOnce(&mut SessionInfo)) {
+        if let Some(session) = self.sessions.lock().unwrap().get_mut(&id) {
+            f(session)
+        }
+    }
+
+    fn remove(&self, id: usize) {
+        self.sessions.lock().unwrap().remove(&id);
+    }
+}
+
+// Keeps a session registered until the connection is done with, even if its thread panics
+struct SessionSlot {
+    sessions: Arc<Sessions>,
+    id: usize,
+}
+
+impl Drop for SessionSlot {
+    fn drop(&mut self) {
+        self.sessions.remove(self.id);
+    }
+}
+
+fn unix_time() -> Option<u64> {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .ok()
+        .map(|duration| duration.as_secs())
+}
+
 impl RPC {
     fn start_notifier(
         notification: Channel<Notification>,
-        senders: Arc<Mutex<Vec<SyncSender<Message>>>>,
+        senders: Arc<Mutex<HashMap<usize, SyncSender<Message>>>>,
+        subscriptions: SubscriptionIndex,
+        query: Arc<Query>,
         acceptor: Sender<Option<(TcpStream, SocketAddr)>>,
     ) {
         spawn_thread("notification", move || {
+            // connections that missed an update and need to re-check all their subscriptions
+            let mut missed_update = HashSet::new();
             for msg in notification.receiver().iter() {
-                let mut senders = senders.lock().unwrap();
                 match msg {
                     Notification::Periodic => {
-                        senders.retain(|sender| {
-                            if let Err(TrySendError::Disconnected(_)) =
-                                sender.try_send(Message::PeriodicUpdate)
-                            {
-                                false // drop disconnected clients
+                        let changes = query.take_script_changes();
+                        let mut changed_by_conn = group_by_subscriber(&changes, &subscriptions);
+
+                        let mut senders = senders.lock().unwrap();
+                        senders.retain(|conn_id, sender| {
+                            let changed = if changes.is_all() || missed_update.contains(conn_id) {
+                                None
                             } else {
-                                true
+                                Some(changed_by_conn.remove(conn_id).unwrap_or_default())
+                            };
+                            match sender.try_send(Message::PeriodicUpdate(changed)) {
+                                Ok(()) => {
+                                    missed_update.remove(conn_id);
+                                    true
+                                }
+                                Err(TrySendError::Full(_)) => {
+                                    missed_update.insert(*conn_id);
+                                    true
+                                }
+                                Err(TrySendError::Disconnected(_)) => {
+                                    missed_update.remove(conn_id);
+                                    false // drop disconnected clients
+                                }
                             }
                         })
                     }
@@ -743,6 +1304,22 @@ impl RPC {
         chan
     }
 
+    // The hosts advertised via server.features, including the onion host if configured
+    #[cfg(feature = "electrum-discovery")]
+    fn public_hosts(config: &Config) -> Option<ServerHosts> {
+        let mut hosts = config.electrum_public_hosts.clone();
+        if let Some(ref onion_host) = config.electrum_onion_host {
+            hosts
+                .get_or_insert_with(HashMap::new)
+                .entry(onion_host.clone())
+                .or_insert_with(|| ServerPorts {
+                    tcp_port: Some(config.electrum_rpc_addr.port()),
+                    ssl_port: None,
+                });
+        }
+        hosts
+    }
+
     pub fn start(config: Arc<Config>, query: Arc<Query>, metrics: &Metrics) -> RPC {
         let stats = Arc::new(Stats {
             latency: metrics.histogram_vec(
@@ -760,9 +1337,9 @@ impl RPC {
 
         let notification = Channel::unbounded();
 
-        // Discovery is enabled when electrum-public-hosts is set
+        // Discovery is enabled when electrum-public-hosts or electrum-onion-host is set
         #[cfg(feature = "electrum-discovery")]
-        let discovery = config.electrum_public_hosts.clone().map(|hosts| {
+        let discovery = RPC::public_hosts(&config).map(|hosts| {
             use crate::chain::genesis_hash;
             let features = ServerFeatures {
                 hosts,
@@ -780,44 +1357,80 @@ impl RPC {
                 config.electrum_announce,
                 config.tor_proxy,
             ));
+            for peer in &config.electrum_peers {
+                if let Err(e) = discovery.add_static_peer(peer) {
+                    warn!("failed adding static peer {}: {}", peer.hostname, e);
+                }
+            }
             DiscoveryManager::spawn_jobs_thread(Arc::clone(&discovery));
             discovery
         });
 
         let rpc_addr = config.electrum_rpc_addr;
         let txs_limit = config.electrum_txs_limit;
+        let max_pending_requests = config.electrum_max_pending_requests;
+        let sessions = Arc::new(Sessions::default());
+        let server_sessions = Arc::clone(&sessions);
 
         RPC {
             notification: notification.sender(),
+            sessions,
+            shutdown_timeout: config.shutdown_timeout,
             server: Some(spawn_thread("rpc", move || {
-                let senders = Arc::new(Mutex::new(Vec::<SyncSender<Message>>::new()));
+                let sessions = server_sessions;
+                let senders = Arc::new(Mutex::new(HashMap::<usize, SyncSender<Message>>::new()));
+                let subscriptions: SubscriptionIndex = Arc::new(Mutex::new(HashMap::new()));
 
                 let acceptor = RPC::start_acceptor(rpc_addr);
-                RPC::start_notifier(notification, senders.clone(), acceptor.sender());
+                RPC::start_notifier(
+                    notification,
+                    senders.clone(),
+                    subscriptions.clone(),
+                    Arc::clone(&query),
+                    acceptor.sender(),
+                );
 
                 let mut threads = HashMap::new();
+                let mut next_conn_id = 0;
                 let (garbage_sender, garbage_receiver) = crossbeam_channel::unbounded();
 
                 while let Some((stream, addr)) = acceptor.receiver().recv().unwrap() {
+                    let conn_id = next_conn_id;
+                    next_conn_id += 1;
+                    let slot = match sessions.try_add(conn_id, addr, &config) {
+                        Ok(slot) => slot,
+                        Err(e) => {
+                            warn!("[{}] rejecting connection: {}", addr, e);
+                            let _ = stream.shutdown(Shutdown::Both);
+                            continue;
+                        }
+                    };
+
                     // explicitly scope the shadowed variables for the new thread
+                    let sessions = Arc::clone(&sessions);
                     let query = Arc::clone(&query);
                     let stats = Arc::clone(&stats);
+                    let subscriptions = Arc::clone(&subscriptions);
                     let garbage_sender = garbage_sender.clone();
                     let rpc_logging = config.electrum_rpc_logging.clone();
                     #[cfg(feature = "electrum-discovery")]
                     let discovery = discovery.clone();
 
-                    let (sender, receiver) = mpsc::sync_channel(10);
-                    senders.lock().unwrap().push(sender.clone());
+                    let (sender, receiver) = mpsc::sync_channel(max_pending_requests);
+                    senders.lock().unwrap().insert(conn_id, sender.clone());
 
                     let spawned = spawn_thread("peer", move || {
+                        let _slot = slot;
                         info!("[{}] connected peer", addr);
                         let conn = Connection::new(
+                            conn_id,
                             query,
                             stream,
                             addr,
                             sender,
                             stats,
+                            subscriptions,
+                            sessions,
                             txs_limit,
                             #[cfg(feature = "electrum-discovery")]
                             discovery,
@@ -841,7 +1454,7 @@ impl RPC {
                 }
 
                 trace!("closing {} RPC connections", senders.lock().unwrap().len());
-                for sender in senders.lock().unwrap().iter() {
+                for sender in senders.lock().unwrap().values() {
                     let _ = sender.send(Message::Done);
                 }
 
@@ -860,14 +1473,25 @@ impl RPC {
     pub fn notify(&self) {
         self.notification.send(Notification::Periodic).unwrap();
     }
+
+    pub fn sessions(&self) -> Arc<Sessions> {
+        Arc::clone(&self.sessions)
+    }
 }
 
 impl Drop for RPC {
     fn drop(&mut self) {
         trace!("stop accepting new RPCs");
         self.notification.send(Notification::Exit).unwrap();
+        // let the connections complete their in-flight requests
         if let Some(handle) = self.server.take() {
-            handle.join().unwrap();
+            match join_timeout(handle, self.shutdown_timeout) {
+                Some(result) => result.unwrap(),
+                None => warn!(
+                    "Electrum requests still in-flight after {:?}, stopping anyway",
+                    self.shutdown_timeout
+                ),
+            }
         }
         trace!("RPC server is stopped");
     }
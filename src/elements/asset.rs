@@ -5,7 +5,9 @@ use bitcoin::hashes::{sha256, Hash};
 use elements::confidential::{Asset, Value};
 use elements::encode::{deserialize, serialize};
 use elements::secp256k1_zkp::ZERO_TWEAK;
-use elements::{issuance::ContractHash, AssetId, AssetIssuance, OutPoint, Transaction, TxIn};
+use elements::{
+    issuance::ContractHash, AssetId, AssetIssuance, OutPoint, Script, Transaction, TxIn, TxOut,
+};
 
 use crate::chain::{BNetwork, BlockHash, Network, Txid};
 use crate::elements::peg::{get_pegin_data, get_pegout_data, PeginInfo, PegoutInfo};
@@ -13,7 +15,10 @@ use crate::elements::registry::{AssetMeta, AssetRegistry};
 use crate::errors::*;
 use crate::new_index::schema::{TxHistoryInfo, TxHistoryKey, TxHistoryRow};
 use crate::new_index::{db::DBFlush, ChainQuery, DBRow, Mempool, Query};
-use crate::util::{bincode, full_hash, Bytes, FullHash, TransactionStatus, TxInput};
+use crate::util::{
+    bincode, full_hash, has_prevout, is_spendable, Bytes, FullHash, ScriptToAddr,
+    TransactionStatus, TxInput,
+};
 
 lazy_static! {
     pub static ref NATIVE_ASSET_ID: AssetId =
@@ -177,9 +182,11 @@ pub fn index_confirmed_tx_assets(
     confirmed_height: u32,
     network: Network,
     parent_network: BNetwork,
+    previous_txos_map: &HashMap<OutPoint, TxOut>,
     rows: &mut Vec<DBRow>,
 ) {
     let (history, issuances) = index_tx_assets(tx, network, parent_network);
+    index_holder_changes(tx, confirmed_height, previous_txos_map, rows);
 
     rows.extend(
         history.into_iter().map(|(asset_id, info)| {
@@ -453,6 +460,19 @@ where
     }
 }
 
+// Get the number of confirmed and unconfirmed txs of the asset, using its cached stats
+pub fn asset_tx_count(query: &Query, asset_id: &AssetId) -> (usize, usize) {
+    if query.network().pegged_asset() == Some(asset_id) {
+        let (chain_stats, mempool_stats) = pegged_asset_stats(query, asset_id);
+        return (chain_stats.tx_count, mempool_stats.tx_count);
+    }
+    let afn = apply_issued_asset_stats;
+    (
+        chain_asset_stats(query.chain(), asset_id, afn).tx_count,
+        mempool_asset_stats(&query.mempool(), asset_id, afn).tx_count,
+    )
+}
+
 // Get stats for the network's pegged asset
 fn pegged_asset_stats(query: &Query, asset_id: &AssetId) -> (PeggedAssetStats, PeggedAssetStats) {
     (
@@ -461,6 +481,12 @@ fn pegged_asset_stats(query: &Query, asset_id: &AssetId) -> (PeggedAssetStats, P
     )
 }
 
+// Get the confirmed stats of the network's pegged asset, which tally its peg-ins and peg-outs
+pub fn chain_pegged_asset_stats(chain: &ChainQuery) -> Option<PeggedAssetStats> {
+    let asset_id = chain.network().pegged_asset()?;
+    Some(chain_asset_stats(chain, asset_id, apply_pegged_asset_stats))
+}
+
 // Get stats for issued assets
 fn issued_asset_stats(
     chain: &ChainQuery,
@@ -641,3 +667,209 @@ fn apply_pegged_asset_stats(
         }
     }
 }
+
+//
+// Asset holders
+//
+
+// Holders are the scripts holding explicit (unblinded) amounts of the asset. Confidential
+// amounts are unknown and not accounted for.
+#[derive(Serialize)]
+pub struct AssetHolders {
+    pub asset_id: AssetId,
+    pub holder_count: usize,
+    pub total_amount: u64,
+    pub concentration: HolderConcentration,
+    pub holders: Vec<AssetHolder>, // the top holders, largest first
+}
+
+#[derive(Serialize)]
+pub struct AssetHolder {
+    pub scriptpubkey: Script,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scriptpubkey_address: Option<String>,
+    pub amount: u64,
+    pub share: f64,
+}
+
+#[derive(Serialize)]
+pub struct HolderConcentration {
+    pub top_10_share: f64,
+    pub top_100_share: f64,
+    pub herfindahl_index: f64, // the sum of the squared shares, 1 for a single holder
+}
+
+// persist the changes to the explicit balances of the holders, by height:
+//      h{asset-id}{height}{txid}{vout|vin}{is-spend} → {scriptpubkey}{value}
+type HolderChangeKey = (u8, FullHash, u32, FullHash, u16, bool);
+
+fn holder_change_row(
+    asset_id: &AssetId,
+    height: u32,
+    txid: FullHash,
+    (index, is_spend): (u16, bool),
+    script: &Script,
+    value: u64,
+) -> DBRow {
+    let asset_hash = full_hash(&asset_id.into_inner()[..]);
+    let key: HolderChangeKey = (b'h', asset_hash, height, txid, index, is_spend);
+    DBRow {
+        key: bincode::serialize_big(&key).unwrap(),
+        value: bincode::serialize_little(&(script.as_bytes(), value)).unwrap(),
+    }
+}
+
+fn index_holder_changes(
+    tx: &Transaction,
+    confirmed_height: u32,
+    previous_txos_map: &HashMap<OutPoint, TxOut>,
+    rows: &mut Vec<DBRow>,
+) {
+    let txid = full_hash(&tx.txid()[..]);
+    let explicit = |txo: &TxOut| match (txo.asset, txo.value) {
+        (Asset::Explicit(asset_id), Value::Explicit(value)) if value > 0 && is_spendable(txo) => {
+            Some((asset_id, value))
+        }
+        _ => None,
+    };
+    for (vout, txo) in tx.output.iter().enumerate() {
+        if let Some((asset_id, value)) = explicit(txo) {
+            let index = (vout as u16, false);
+            let row = holder_change_row(
+                &asset_id,
+                confirmed_height,
+                txid,
+                index,
+                &txo.script_pubkey,
+                value,
+            );
+            rows.push(row);
+        }
+    }
+    for (vin, txin) in tx.input.iter().enumerate() {
+        if !has_prevout(txin) {
+            continue;
+        }
+        let prevout = previous_txos_map
+            .get(&txin.previous_output)
+            .unwrap_or_else(|| panic!("missing previous txo {}", txin.previous_output));
+        if let Some((asset_id, value)) = explicit(prevout) {
+            let index = (vin as u16, true);
+            let row = holder_change_row(
+                &asset_id,
+                confirmed_height,
+                txid,
+                index,
+                &prevout.script_pubkey,
+                value,
+            );
+            rows.push(row);
+        }
+    }
+}
+
+fn holders_cache_key(asset_id: &AssetId) -> Bytes {
+    [b"d", &asset_id.into_inner()[..]].concat()
+}
+
+// Get the confirmed explicit balances of the asset holders, keyed by their scriptpubkey
+fn chain_asset_holders(chain: &ChainQuery, asset_id: &AssetId) -> HashMap<Bytes, u64> {
+    // get the last known balances and the blockhash they are updated for, like the stats are.
+    // persisted in the cache db as:
+    //      d{asset-id} → {balances}{blockhash}
+    let cache: Option<(HashMap<Bytes, u64>, usize)> = chain
+        .store()
+        .cache_db()
+        .get(&holders_cache_key(asset_id))
+        .map(|c| bincode::deserialize_little(&c).unwrap())
+        .and_then(|(balances, blockhash)| {
+            chain
+                .height_by_hash(&blockhash)
+                .map(|height| (balances, height))
+        });
+    let (mut balances, start_height) = cache.map_or_else(
+        || (HashMap::new(), 0),
+        |(balances, height)| (balances, height + 1),
+    );
+
+    let mut lastblock = None;
+    for row in chain.history_iter_scan(b'h', &asset_id.into_inner()[..], start_height) {
+        let (_, _, _, txid, _, is_spend): HolderChangeKey =
+            bincode::deserialize_big(&row.key).expect("failed to parse HolderChangeKey");
+        let blockid = match chain.tx_confirming_block(&deserialize(&txid).unwrap()) {
+            Some(blockid) => blockid,
+            None => continue, // orphaned
+        };
+        let (script, value): (Bytes, u64) =
+            bincode::deserialize_little(&row.value).expect("failed to parse holder change");
+        if is_spend {
+            let balance = balances.entry(script.clone()).or_insert(0);
+            *balance = balance.saturating_sub(value);
+            if *balance == 0 {
+                balances.remove(&script);
+            }
+        } else {
+            *balances.entry(script).or_insert(0) += value;
+        }
+        lastblock = Some(blockid.hash);
+    }
+
+    if let Some(lastblock) = lastblock {
+        chain.store().cache_db().write(
+            vec![DBRow {
+                key: holders_cache_key(asset_id),
+                value: bincode::serialize_little(&(&balances, lastblock)).unwrap(),
+            }],
+            DBFlush::Enable,
+        );
+    }
+    balances
+}
+
+pub fn asset_holders(
+    chain: &ChainQuery,
+    asset_id: &AssetId,
+    limit: usize,
+    network: Network,
+) -> AssetHolders {
+    let mut balances: Vec<(Bytes, u64)> =
+        chain_asset_holders(chain, asset_id).into_iter().collect();
+    balances.sort_unstable_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+    let total_amount: u64 = balances.iter().map(|(_, amount)| amount).sum();
+    let share = |amount: u64| match total_amount {
+        0 => 0.0,
+        total => amount as f64 / total as f64,
+    };
+    let top_share = |n: usize| share(balances.iter().take(n).map(|(_, amount)| amount).sum());
+    let concentration = HolderConcentration {
+        top_10_share: top_share(10),
+        top_100_share: top_share(100),
+        herfindahl_index: balances
+            .iter()
+            .map(|(_, amount)| share(*amount).powi(2))
+            .sum(),
+    };
+
+    let holders = balances
+        .iter()
+        .take(limit)
+        .map(|(script, amount)| {
+            let script = Script::from(script.clone());
+            AssetHolder {
+                scriptpubkey_address: script.to_address_str(network),
+                scriptpubkey: script,
+                amount: *amount,
+                share: share(*amount),
+            }
+        })
+        .collect();
+
+    AssetHolders {
+        asset_id: *asset_id,
+        holder_count: balances.len(),
+        total_amount,
+        concentration,
+        holders,
+    }
+}
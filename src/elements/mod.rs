@@ -5,10 +5,11 @@ use elements::{issuance::ContractHash, AssetId, TxIn};
 pub mod asset;
 pub mod peg;
 mod registry;
+pub mod unblind;
 
 use asset::get_issuance_entropy;
 pub use asset::{lookup_asset, LiquidAsset};
-pub use registry::{AssetRegistry, AssetSorting};
+pub use registry::{AssetMeta, AssetRegistry, AssetSorting, RegistryMerge, RegistrySource};
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct IssuanceValue {
@@ -1,7 +1,10 @@
-use elements::{confidential::Asset, PeginData, PegoutData, TxIn, TxOut};
+use bitcoin::hashes::Hash;
+use elements::encode::deserialize;
+use elements::{confidential::Asset, PeginData, PegoutData, Transaction, TxIn, TxOut};
 
-use crate::chain::{bitcoin_genesis_hash, BNetwork, Network};
-use crate::util::{FullHash, ScriptToAsm};
+use crate::chain::{bitcoin_genesis_hash, BNetwork, Network, Txid};
+use crate::new_index::{ChainQuery, DBRow, DB};
+use crate::util::{bincode, full_hash, Bytes, FullHash, ScriptToAsm, TransactionStatus};
 
 pub fn get_pegin_data(txout: &TxIn, network: Network) -> Option<PeginData> {
     let pegged_asset_id = network.pegged_asset()?;
@@ -63,3 +66,217 @@ pub struct PegoutInfo {
     pub vout: u16,
     pub value: u64,
 }
+
+//
+// Peg index
+//
+
+pub const PEG_IN: u8 = b'i';
+pub const PEG_OUT: u8 = b'o';
+
+// persist the peg-ins and peg-outs of the pegged asset, by height:
+//      p{i|o}{height}{txid}{vin|vout} → {value}{parent-data}
+// where the parent data is the claimed outpoint of peg-ins and the scriptpubkey paid by peg-outs,
+// both on the parent chain
+struct PegRow {
+    direction: u8,
+    height: u32,
+    txid: FullHash,
+    index: u16,
+    value: u64,
+    parent: Bytes,
+}
+
+impl PegRow {
+    fn filter(direction: u8) -> Bytes {
+        vec![b'p', direction]
+    }
+
+    fn prefix_height(direction: u8, height: u32) -> Bytes {
+        bincode::serialize_big(&(b'p', direction, height)).unwrap()
+    }
+
+    fn into_row(self) -> DBRow {
+        let key = (b'p', self.direction, self.height, self.txid, self.index);
+        DBRow {
+            key: bincode::serialize_big(&key).unwrap(),
+            value: bincode::serialize_little(&(self.value, self.parent)).unwrap(),
+        }
+    }
+
+    fn from_row(row: DBRow) -> Self {
+        let (_, direction, height, txid, index): (u8, u8, u32, FullHash, u16) =
+            bincode::deserialize_big(&row.key).expect("failed to parse PegRow key");
+        let (value, parent) =
+            bincode::deserialize_little(&row.value).expect("failed to parse PegRow");
+        PegRow {
+            direction,
+            height,
+            txid,
+            index,
+            value,
+            parent,
+        }
+    }
+}
+
+// persist the height of the first block indexed along with its pegs, as blocks indexed by
+// versions without the peg index are missing from it:
+//      ps → {height}
+const PEG_INDEX_START_KEY: &[u8] = b"ps";
+
+// The row recording the start of the peg index, if these are the first blocks indexed with it
+pub fn peg_index_start_row(history_db: &DB, first_height: u32) -> Option<DBRow> {
+    if history_db.get(PEG_INDEX_START_KEY).is_some() {
+        return None;
+    }
+    Some(DBRow {
+        key: PEG_INDEX_START_KEY.to_vec(),
+        value: bincode::serialize_little(&first_height).unwrap(),
+    })
+}
+
+// The height of the first block indexed along with its pegs, None before any got indexed
+pub fn peg_index_start(chain: &ChainQuery) -> Option<u32> {
+    let value = chain.store().history_db().get(PEG_INDEX_START_KEY)?;
+    Some(bincode::deserialize_little(&value).expect("failed to parse the peg index start"))
+}
+
+pub fn index_confirmed_tx_pegs(
+    tx: &Transaction,
+    confirmed_height: u32,
+    network: Network,
+    parent_network: BNetwork,
+    rows: &mut Vec<DBRow>,
+) {
+    let txid = full_hash(&tx.txid()[..]);
+    for (vin, txin) in tx.input.iter().enumerate() {
+        if let Some(pegin) = get_pegin_data(txin, network) {
+            let outpoint = (full_hash(&pegin.outpoint.txid[..]), pegin.outpoint.vout);
+            let row = PegRow {
+                direction: PEG_IN,
+                height: confirmed_height,
+                txid,
+                index: vin as u16,
+                value: pegin.value,
+                parent: bincode::serialize_little(&outpoint).unwrap(),
+            };
+            rows.push(row.into_row());
+        }
+    }
+    for (vout, txout) in tx.output.iter().enumerate() {
+        if let Some(pegout) = get_pegout_data(txout, network, parent_network) {
+            let row = PegRow {
+                direction: PEG_OUT,
+                height: confirmed_height,
+                txid,
+                index: vout as u16,
+                value: pegout.value,
+                parent: pegout.script_pubkey.to_bytes(),
+            };
+            rows.push(row.into_row());
+        }
+    }
+}
+
+// API representation of an indexed peg-in or peg-out
+#[derive(Serialize)]
+pub struct PegValue {
+    pub txid: Txid,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vin: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vout: Option<u16>,
+    pub value: u64,
+    // the parent chain outpoint claimed by peg-ins
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parent_txid: Option<bitcoin::Txid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parent_vout: Option<u32>,
+    // the parent chain destination of peg-outs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scriptpubkey: Option<bitcoin::ScriptBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scriptpubkey_address: Option<bitcoin::Address>,
+    pub status: TransactionStatus,
+}
+
+impl PegValue {
+    fn new(row: PegRow, status: TransactionStatus, parent_network: BNetwork) -> Self {
+        let txid = deserialize(&row.txid).expect("failed to parse Txid");
+        let mut peg = PegValue {
+            txid,
+            vin: None,
+            vout: None,
+            value: row.value,
+            parent_txid: None,
+            parent_vout: None,
+            scriptpubkey: None,
+            scriptpubkey_address: None,
+            status,
+        };
+        if row.direction == PEG_IN {
+            let (parent_txid, parent_vout): (FullHash, u32) =
+                bincode::deserialize_little(&row.parent).expect("failed to parse peg-in outpoint");
+            peg.vin = Some(row.index);
+            peg.parent_txid = Some(bitcoin::Txid::from_slice(&parent_txid).unwrap());
+            peg.parent_vout = Some(parent_vout);
+        } else {
+            let scriptpubkey = bitcoin::ScriptBuf::from(row.parent);
+            peg.vout = Some(row.index);
+            peg.scriptpubkey_address =
+                bitcoin::Address::from_script(&scriptpubkey, parent_network).ok();
+            peg.scriptpubkey = Some(scriptpubkey);
+        }
+        peg
+    }
+}
+
+// The confirmed peg-ins or peg-outs, most recent first, resuming right after the `last_seen`
+// txid and input/output index
+pub fn confirmed_pegs(
+    chain: &ChainQuery,
+    direction: u8,
+    last_seen: Option<(Txid, u16)>,
+    limit: usize,
+    parent_network: BNetwork,
+) -> Vec<PegValue> {
+    let max_height = match last_seen {
+        None => chain.best_height() as u32,
+        Some((txid, _)) => match chain.tx_confirming_block(&txid) {
+            Some(blockid) => blockid.height as u32,
+            // unknown or unconfirmed cursor, there is nothing to resume from
+            None => return vec![],
+        },
+    };
+    let last_seen = last_seen.map(|(txid, index)| (full_hash(&txid[..]), index));
+
+    chain
+        .store()
+        .history_db()
+        .iter_scan_reverse(
+            &PegRow::filter(direction),
+            &PegRow::prefix_height(direction, max_height.saturating_add(1)),
+        )
+        .map(PegRow::from_row)
+        // skip the pegs that precede the last seen one within its block, and the last seen one
+        .skip_while(|row| match last_seen {
+            Some(last_seen) => row.height == max_height && (row.txid, row.index) > last_seen,
+            None => false,
+        })
+        .filter(|row| Some((row.txid, row.index)) != last_seen)
+        .filter_map(|row| {
+            let txid = deserialize(&row.txid).expect("failed to parse Txid");
+            // skip rows left behind by orphaned blocks
+            let blockid = chain
+                .tx_confirming_block(&txid)
+                .filter(|blockid| blockid.height == row.height as usize)?;
+            Some(PegValue::new(
+                row,
+                TransactionStatus::from(Some(blockid)),
+                parent_network,
+            ))
+        })
+        .take(limit)
+        .collect()
+}
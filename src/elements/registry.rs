@@ -1,30 +1,173 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::str::FromStr;
 use std::sync::{Arc, RwLock};
 use std::time::{Duration, SystemTime};
-use std::{cmp, fs, path, thread};
+use std::{cmp, fmt, fs, path, thread};
 
+use error_chain::ChainedError;
 use serde_json::Value as JsonValue;
+use url::Url;
 
 use elements::AssetId;
 
 use crate::errors::*;
+use crate::util::{http_get, spawn_thread};
 
 // length of asset id prefix to use for sub-directory partitioning
 // (in number of hex characters, not bytes)
 
 const DIR_PARTITION_LEN: usize = 2;
+
+// Where asset metadata is loaded from: a directory partitioned like the registry
+// (`{2-char-prefix}/{asset-id}.json`), or an http(s):// URL serving the metadata of all the assets
+// keyed by their id at `index.json`, and of a single asset at `{asset-id}.json`
+pub enum RegistrySource {
+    Directory(path::PathBuf),
+    Url(Url),
+}
+
+impl RegistrySource {
+    pub fn parse(source: &str) -> Result<Self> {
+        if source.starts_with("http://") || source.starts_with("https://") {
+            Ok(RegistrySource::Url(Url::parse(source).chain_err(|| {
+                format!("invalid registry url {}", source)
+            })?))
+        } else {
+            Ok(RegistrySource::Directory(path::PathBuf::from(source)))
+        }
+    }
+
+    fn url_path(url: &Url, file: &str) -> Result<Url> {
+        // keep the last path segment of the base url, which has no trailing slash
+        let mut base = url.clone();
+        if !base.path().ends_with('/') {
+            base.set_path(&format!("{}/", base.path()));
+        }
+        base.join(file).chain_err(|| "invalid registry url")
+    }
+
+    // Load the metadata of all the assets, reusing the previously loaded files that didn't change
+    fn load_all(&self, previous: &AssetCache) -> Result<AssetCache> {
+        match self {
+            RegistrySource::Directory(directory) => load_directory(directory, previous),
+            RegistrySource::Url(url) => {
+                let body = http_get(&Self::url_path(url, "index.json")?)?;
+                let index: HashMap<String, AssetMeta> =
+                    serde_json::from_str(&body).chain_err(|| "failed parsing registry index")?;
+                let now = SystemTime::now();
+                index
+                    .into_iter()
+                    .map(|(asset_id, metadata)| {
+                        let asset_id = AssetId::from_str(&asset_id)
+                            .chain_err(|| format!("invalid asset id {}", asset_id))?;
+                        Ok((asset_id, (now, metadata)))
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    // Load the metadata of a single asset, if the source has it
+    fn load_one(&self, asset_id: &AssetId) -> Result<Option<(SystemTime, AssetMeta)>> {
+        let filename = format!("{}.json", asset_id);
+        let (modified, contents) = match self {
+            RegistrySource::Directory(directory) => {
+                let path = directory
+                    .join(&filename[..DIR_PARTITION_LEN])
+                    .join(&filename);
+                if !path.exists() {
+                    return Ok(None);
+                }
+                let modified = fs::metadata(&path)
+                    .and_then(|metadata| metadata.modified())
+                    .chain_err(|| "failed reading metadata")?;
+                let contents = fs::read_to_string(path).chain_err(|| "failed reading file")?;
+                (modified, contents)
+            }
+            RegistrySource::Url(url) => match http_get(&Self::url_path(url, &filename)?) {
+                Ok(contents) => (SystemTime::now(), contents),
+                Err(e) => match e.kind() {
+                    // the asset is unknown to this source
+                    ErrorKind::HttpStatus(404) => return Ok(None),
+                    _ => return Err(e),
+                },
+            },
+        };
+        let metadata = serde_json::from_str(&contents).chain_err(|| "failed parsing file")?;
+        Ok(Some((modified, metadata)))
+    }
+}
+
+impl fmt::Display for RegistrySource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RegistrySource::Directory(directory) => write!(f, "{}", directory.display()),
+            RegistrySource::Url(url) => write!(f, "{}", url),
+        }
+    }
+}
+
+// How the metadata of an asset found in several sources gets merged
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegistryMerge {
+    // the metadata of the first source listing the asset is used as is
+    First,
+    // the fields missing from the first source listing the asset are filled in from the next ones
+    Fill,
+}
+
+impl RegistryMerge {
+    pub fn options() -> Vec<String> {
+        return vec!["first".to_string(), "fill".to_string()];
+    }
+
+    fn merge(self, metadata: &mut AssetMeta, other: &AssetMeta) {
+        if self == RegistryMerge::First {
+            return;
+        }
+        if metadata.contract.is_null() {
+            metadata.contract = other.contract.clone();
+        }
+        if metadata.entity.is_null() {
+            metadata.entity = other.entity.clone();
+        }
+        if metadata.ticker.is_none() {
+            metadata.ticker = other.ticker.clone();
+        }
+    }
+}
+
+impl From<&str> for RegistryMerge {
+    fn from(option: &str) -> Self {
+        match option {
+            "first" => RegistryMerge::First,
+            "fill" => RegistryMerge::Fill,
+
+            _ => panic!("unsupported registry merge option: {:?}", option),
+        }
+    }
+}
+
+type AssetCache = HashMap<AssetId, (SystemTime, AssetMeta)>;
+
 pub struct AssetRegistry {
-    directory: path::PathBuf,
-    assets_cache: HashMap<AssetId, (SystemTime, AssetMeta)>,
+    // loaded without holding the lock on the registry
+    sources: Arc<Vec<RegistrySource>>,
+    merge: RegistryMerge,
+    // the assets loaded from each of the sources, in the same order, shared with the syncs
+    // comparing against them
+    source_assets: Vec<Arc<AssetCache>>,
+    assets_cache: AssetCache,
 }
 
 pub type AssetEntry<'a> = (&'a AssetId, &'a AssetMeta);
 
 impl AssetRegistry {
-    pub fn new(directory: path::PathBuf) -> Self {
+    pub fn new(sources: Vec<RegistrySource>, merge: RegistryMerge) -> Self {
         Self {
-            directory,
+            source_assets: sources.iter().map(|_| Arc::default()).collect(),
+            sources: Arc::new(sources),
+            merge,
             assets_cache: Default::default(),
         }
     }
@@ -53,66 +196,154 @@ impl AssetRegistry {
         )
     }
 
-    pub fn fs_sync(&mut self) -> Result<()> {
-        for entry in fs::read_dir(&self.directory).chain_err(|| "failed reading asset dir")? {
-            let entry = entry.chain_err(|| "invalid fh")?;
-            let filetype = entry.file_type().chain_err(|| "failed getting file type")?;
-            if !filetype.is_dir() || entry.file_name().len() != DIR_PARTITION_LEN {
-                continue;
-            }
+    // The metadata of the asset merged across the sources, or None if none of them has it
+    fn merged(&self, asset_id: &AssetId) -> Option<(SystemTime, AssetMeta)> {
+        let mut found = self
+            .source_assets
+            .iter()
+            .filter_map(|assets| assets.get(asset_id));
+        let (modified, mut metadata) = found.next().cloned()?;
+        for (_, other) in found {
+            self.merge.merge(&mut metadata, other);
+        }
+        Some((modified, metadata))
+    }
 
-            for file_entry in
-                fs::read_dir(entry.path()).chain_err(|| "failed reading asset subdir")?
-            {
-                let file_entry = file_entry.chain_err(|| "invalid fh")?;
-                let path = file_entry.path();
-                if path.extension().and_then(|e| e.to_str()) != Some("json") {
-                    continue;
-                }
+    fn remerge(&mut self) {
+        let asset_ids: HashSet<AssetId> = self
+            .source_assets
+            .iter()
+            .flat_map(|assets| assets.keys().cloned())
+            .collect();
+        self.assets_cache = asset_ids
+            .into_iter()
+            .filter_map(|asset_id| Some((asset_id, self.merged(&asset_id)?)))
+            .collect();
+    }
 
-                let asset_id = AssetId::from_str(
-                    path.file_stem()
-                        .unwrap() // cannot fail if extension() succeeded
-                        .to_str()
-                        .chain_err(|| "invalid filename")?,
-                )
-                .chain_err(|| "invalid filename")?;
-
-                let modified = file_entry
-                    .metadata()
-                    .chain_err(|| "failed reading metadata")?
-                    .modified()
-                    .chain_err(|| "metadata modified failed")?;
-
-                if let Some((last_update, _)) = self.assets_cache.get(&asset_id) {
-                    if *last_update == modified {
-                        continue;
-                    }
+    // Reload all the sources. The registry only gets locked once they are loaded, and the sources
+    // failing to load keep their previously loaded assets.
+    pub fn sync(asset_db: &RwLock<AssetRegistry>) {
+        let (sources, previous) = {
+            let registry = asset_db.read().unwrap();
+            (
+                Arc::clone(&registry.sources),
+                registry.source_assets.clone(),
+            )
+        };
+        let loaded: Vec<Option<Arc<AssetCache>>> = sources
+            .iter()
+            .zip(&previous)
+            .map(|(source, previous)| match source.load_all(previous) {
+                Ok(assets) => Some(Arc::new(assets)),
+                Err(e) => {
+                    error!("registry sync of {} failed: {}", source, e.display_chain());
+                    None
                 }
+            })
+            .collect();
 
-                let metadata: AssetMeta = serde_json::from_str(
-                    &fs::read_to_string(path).chain_err(|| "failed reading file")?,
-                )
-                .chain_err(|| "failed parsing file")?;
-
-                self.assets_cache.insert(asset_id, (modified, metadata));
+        let mut registry = asset_db.write().unwrap();
+        for (assets, loaded) in registry.source_assets.iter_mut().zip(loaded) {
+            if let Some(loaded) = loaded {
+                *assets = loaded;
             }
         }
-        Ok(())
+        registry.remerge();
     }
 
-    pub fn spawn_sync(asset_db: Arc<RwLock<AssetRegistry>>) -> thread::JoinHandle<()> {
-        thread::spawn(move || loop {
-            if let Err(e) = asset_db.write().unwrap().fs_sync() {
-                error!("registry fs_sync failed: {:?}", e);
-            }
+    // Reload the metadata of a single asset from all the sources, returning its merged metadata
+    pub fn refresh(
+        asset_db: &RwLock<AssetRegistry>,
+        asset_id: &AssetId,
+    ) -> Result<Option<AssetMeta>> {
+        let sources = Arc::clone(&asset_db.read().unwrap().sources);
+        let loaded = sources
+            .iter()
+            .map(|source| {
+                source
+                    .load_one(asset_id)
+                    .chain_err(|| format!("failed refreshing {} from {}", asset_id, source))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut registry = asset_db.write().unwrap();
+        for (assets, loaded) in registry.source_assets.iter_mut().zip(loaded) {
+            // copies the assets only if a sync is still comparing against them
+            let assets = Arc::make_mut(assets);
+            match loaded {
+                Some(entry) => assets.insert(*asset_id, entry),
+                None => assets.remove(asset_id),
+            };
+        }
+        let merged = registry.merged(asset_id);
+        match merged {
+            Some(ref entry) => registry.assets_cache.insert(*asset_id, entry.clone()),
+            None => registry.assets_cache.remove(asset_id),
+        };
+        Ok(merged.map(|(_, metadata)| metadata))
+    }
 
-            thread::sleep(Duration::from_secs(15));
+    pub fn spawn_sync(
+        asset_db: Arc<RwLock<AssetRegistry>>,
+        interval: Duration,
+    ) -> thread::JoinHandle<()> {
+        spawn_thread("asset-registry", move || loop {
+            AssetRegistry::sync(&asset_db);
+            thread::sleep(interval);
             // TODO handle shutdowm
         })
     }
 }
 
+fn load_directory(directory: &path::Path, previous: &AssetCache) -> Result<AssetCache> {
+    let mut assets = HashMap::new();
+    for entry in fs::read_dir(directory).chain_err(|| "failed reading asset dir")? {
+        let entry = entry.chain_err(|| "invalid fh")?;
+        let filetype = entry.file_type().chain_err(|| "failed getting file type")?;
+        if !filetype.is_dir() || entry.file_name().len() != DIR_PARTITION_LEN {
+            continue;
+        }
+
+        for file_entry in fs::read_dir(entry.path()).chain_err(|| "failed reading asset subdir")? {
+            let file_entry = file_entry.chain_err(|| "invalid fh")?;
+            let path = file_entry.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+
+            let asset_id = AssetId::from_str(
+                path.file_stem()
+                    .unwrap() // cannot fail if extension() succeeded
+                    .to_str()
+                    .chain_err(|| "invalid filename")?,
+            )
+            .chain_err(|| "invalid filename")?;
+
+            let modified = file_entry
+                .metadata()
+                .chain_err(|| "failed reading metadata")?
+                .modified()
+                .chain_err(|| "metadata modified failed")?;
+
+            if let Some((last_update, metadata)) = previous.get(&asset_id) {
+                if *last_update == modified {
+                    assets.insert(asset_id, (modified, metadata.clone()));
+                    continue;
+                }
+            }
+
+            let metadata: AssetMeta = serde_json::from_str(
+                &fs::read_to_string(path).chain_err(|| "failed reading file")?,
+            )
+            .chain_err(|| "failed parsing file")?;
+
+            assets.insert(asset_id, (modified, metadata));
+        }
+    }
+    Ok(assets)
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct AssetMeta {
     #[serde(skip_serializing_if = "JsonValue::is_null")]
@@ -0,0 +1,88 @@
+// Unblinding of confidential outputs on behalf of their owners, who provide either the blinding
+// key of their outputs or their blinding factors. Nothing provided is kept by the server.
+use elements::confidential::{Asset, AssetBlindingFactor, Value, ValueBlindingFactor};
+use elements::secp256k1_zkp::{All, Secp256k1, SecretKey};
+use elements::{AssetId, Transaction, TxOut};
+
+use crate::errors::*;
+
+lazy_static! {
+    static ref SECP: Secp256k1<All> = Secp256k1::new();
+}
+
+#[derive(Deserialize)]
+pub struct UnblindRequest {
+    // the outputs this key unblinds are found by trying it on every confidential output
+    pub blinding_key: Option<SecretKey>,
+    #[serde(default)]
+    pub outputs: Vec<UnblindedOutput>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct UnblindedOutput {
+    pub vout: u32,
+    pub value: u64,
+    pub asset: AssetId,
+    pub value_blinder: ValueBlindingFactor,
+    pub asset_blinder: AssetBlindingFactor,
+}
+
+// Check that the secrets open the commitments of the output
+fn matches_commitments(txout: &TxOut, secrets: &UnblindedOutput) -> bool {
+    let asset = Asset::new_confidential(&SECP, secrets.asset, secrets.asset_blinder);
+    let generator = match asset.commitment() {
+        Some(generator) => generator,
+        None => return false,
+    };
+    let value = Value::new_confidential(&SECP, secrets.value, generator, secrets.value_blinder);
+    txout.asset == asset && txout.value == value
+}
+
+// The unblinded outputs of the caller, ordered by index. Fails if some of the provided blinding
+// factors don't match the commitments of their output.
+pub fn unblind_outputs(tx: &Transaction, request: UnblindRequest) -> Result<Vec<UnblindedOutput>> {
+    if request.blinding_key.is_none() && request.outputs.is_empty() {
+        bail!("either a blinding_key or the blinders of the outputs are required");
+    }
+
+    let mut unblinded = vec![];
+    for secrets in request.outputs {
+        let txout = tx
+            .output
+            .get(secrets.vout as usize)
+            .ok_or_else(|| Error::from(format!("output {} not found", secrets.vout)))?;
+        if !matches_commitments(txout, &secrets) {
+            bail!(
+                "the blinders of output {} don't match its commitments",
+                secrets.vout
+            );
+        }
+        unblinded.push(secrets);
+    }
+
+    if let Some(blinding_key) = request.blinding_key {
+        for (vout, txout) in tx.output.iter().enumerate() {
+            let vout = vout as u32;
+            if unblinded.iter().any(|output| output.vout == vout) {
+                continue;
+            }
+            // outputs blinded to other keys can't be unblinded and don't belong to the caller
+            let secrets = match txout.unblind(&SECP, blinding_key) {
+                Ok(secrets) => UnblindedOutput {
+                    vout,
+                    value: secrets.value,
+                    asset: secrets.asset,
+                    value_blinder: secrets.value_bf,
+                    asset_blinder: secrets.asset_bf,
+                },
+                Err(_) => continue,
+            };
+            if matches_commitments(txout, &secrets) {
+                unblinded.push(secrets);
+            }
+        }
+    }
+
+    unblinded.sort_by_key(|output| output.vout);
+    Ok(unblinded)
+}
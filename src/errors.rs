@@ -14,11 +14,26 @@ error_chain! {
             display("Iterrupted by signal {}", sig)
         }
 
+        RpcError(method: String, code: i64, error: serde_json::Value) {
+            description("Daemon RPC error")
+            display("{} RPC error: {}", method, error)
+        }
+
+        HttpStatus(status: u16) {
+            description("HTTP request failed")
+            display("HTTP request failed with status {}", status)
+        }
+
         TooPopular {
             description("Too many history entries")
             display("Too many history entries")
         }
 
+        Timeout {
+            description("Request timed out")
+            display("Request timed out")
+        }
+
         #[cfg(feature = "electrum-discovery")]
         ElectrumClient(e: electrum_client::Error) {
             description("Electrum client error")
@@ -24,6 +24,7 @@ extern crate lazy_static;
 
 pub mod chain;
 pub mod config;
+pub mod config_file;
 pub mod daemon;
 pub mod electrum;
 pub mod errors;
@@ -32,6 +33,7 @@ pub mod new_index;
 pub mod rest;
 pub mod signal;
 pub mod util;
+pub mod zmq;
 
 #[cfg(feature = "liquid")]
 pub mod elements;
@@ -0,0 +1,159 @@
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::chain::{deserialize, Txid};
+use crate::errors::*;
+use crate::new_index::db::{DBFlush, DBRow};
+use crate::new_index::{ChainQuery, Query};
+use crate::util::{bincode, spawn_thread};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+// the retry backoff starts at the poll interval and doubles with every attempt, up to this
+const MAX_BACKOFF: u32 = 300; // seconds
+// queued transactions are given up on once they couldn't be submitted for this long
+const QUEUE_EXPIRY: u32 = 24 * 3600;
+// submitted and failed transactions are reported for this long before being pruned
+const RETENTION: u32 = 7 * 24 * 3600;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BroadcastStatus {
+    Queued,
+    Submitted,
+    Failed,
+}
+
+// A transaction broadcasted while the daemon was unreachable
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct QueuedBroadcast {
+    #[serde(skip_serializing)]
+    pub txhex: String,
+    pub status: BroadcastStatus,
+    pub attempts: u32,
+    pub error: Option<String>, // the last submission error
+    pub queued_at: u32,
+    pub updated_at: u32,
+    #[serde(skip_serializing)]
+    pub next_attempt: u32,
+}
+
+// persist the queued transactions, keyed by their txid:
+//      B{txid} → {queued broadcast}
+fn queue_key(txid: &Txid) -> Vec<u8> {
+    [&b"B"[..], &txid[..]].concat()
+}
+
+fn now() -> u32 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs() as u32)
+}
+
+fn save(chain: &ChainQuery, txid: &Txid, entry: &QueuedBroadcast) {
+    chain.store().cache_db().write(
+        vec![DBRow {
+            key: queue_key(txid),
+            value: bincode::serialize_little(entry).unwrap(),
+        }],
+        DBFlush::Enable,
+    );
+}
+
+pub fn status(chain: &ChainQuery, txid: &Txid) -> Option<QueuedBroadcast> {
+    chain
+        .store()
+        .cache_db()
+        .get(&queue_key(txid))
+        .map(|value| bincode::deserialize_little(&value).expect("failed to parse QueuedBroadcast"))
+}
+
+// Queue the transaction for submission once the daemon is reachable again. Transactions already
+// queued are left as is.
+pub fn enqueue(chain: &ChainQuery, txid: &Txid, txhex: &str, error: String) {
+    if let Some(BroadcastStatus::Queued) = status(chain, txid).map(|entry| entry.status) {
+        return;
+    }
+    let now = now();
+    let entry = QueuedBroadcast {
+        txhex: txhex.to_string(),
+        status: BroadcastStatus::Queued,
+        attempts: 1,
+        error: Some(error),
+        queued_at: now,
+        updated_at: now,
+        next_attempt: now,
+    };
+    save(chain, txid, &entry);
+}
+
+fn backoff(attempts: u32) -> u32 {
+    let backoff = (POLL_INTERVAL.as_secs() as u32).saturating_mul(1 << attempts.min(16));
+    backoff.min(MAX_BACKOFF)
+}
+
+// Retry the queued transactions that are due, and prune the ones settled long ago
+pub fn update(query: &Query) {
+    let chain = query.chain();
+    let now = now();
+    let entries: Vec<(Txid, QueuedBroadcast)> = chain
+        .store()
+        .cache_db()
+        .iter_scan(b"B")
+        .map(|row| {
+            let txid = deserialize(&row.key[1..]).expect("failed to parse Txid");
+            let entry = bincode::deserialize_little(&row.value)
+                .expect("failed to parse QueuedBroadcast");
+            (txid, entry)
+        })
+        .collect();
+
+    let mut pruned = vec![];
+    for (txid, mut entry) in entries {
+        if entry.status != BroadcastStatus::Queued {
+            if entry.updated_at + RETENTION < now {
+                pruned.push(queue_key(&txid));
+            }
+            continue;
+        }
+        if entry.next_attempt > now {
+            continue;
+        }
+        entry.attempts += 1;
+        entry.updated_at = now;
+        match query.submit_raw(&entry.txhex, false) {
+            Ok(_) => {
+                info!("submitted queued transaction {}", txid);
+                entry.status = BroadcastStatus::Submitted;
+                entry.error = None;
+            }
+            Err(Error(ErrorKind::Connection(msg), _)) => {
+                if entry.queued_at + QUEUE_EXPIRY < now {
+                    warn!("giving up on queued transaction {}: {}", txid, msg);
+                    entry.status = BroadcastStatus::Failed;
+                } else {
+                    entry.next_attempt = now + backoff(entry.attempts);
+                }
+                entry.error = Some(msg);
+            }
+            Err(e) => {
+                warn!("queued transaction {} was rejected: {}", txid, e);
+                entry.status = BroadcastStatus::Failed;
+                entry.error = Some(e.to_string());
+            }
+        }
+        save(chain, &txid, &entry);
+    }
+
+    for key in pruned {
+        chain.store().cache_db().delete(&key);
+    }
+}
+
+// Keep retrying the queued transactions in the background
+pub fn spawn(query: Arc<Query>) -> thread::JoinHandle<()> {
+    spawn_thread("broadcast-queue", move || loop {
+        update(&query);
+        thread::sleep(POLL_INTERVAL);
+    })
+}
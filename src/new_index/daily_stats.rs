@@ -0,0 +1,192 @@
+use std::collections::{BTreeMap, HashSet};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use rayon::prelude::*;
+
+use crate::chain::TxOut;
+use crate::errors::*;
+use crate::new_index::db::{DBFlush, DBRow};
+use crate::new_index::{compute_script_hash, ChainQuery, TxHistoryRow};
+use crate::util::{bincode, is_spendable, spawn_thread, HeaderEntry};
+
+const DAY: u32 = 86_400;
+// only blocks this deep are aggregated, so that the persisted days never need to be
+// rolled back after a reorg
+const CONFIRMATIONS: usize = 10;
+// how far back the aggregation reaches on its first run
+pub const MAX_DAYS: u32 = 365;
+// persist progress every this many blocks, so that an interrupted run can resume
+const FLUSH_EVERY: usize = 1000;
+
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+pub struct DailyStats {
+    pub day: u32, // days since the unix epoch (UTC)
+    pub block_count: u32,
+    pub tx_count: u64,
+    #[cfg(not(feature = "liquid"))]
+    pub output_volume: u64,
+    pub total_fee: u64,
+    pub new_address_count: u64,
+    pub block_interval_sum: i64, // seconds between each block and its parent
+}
+
+impl DailyStats {
+    pub fn avg_block_interval(&self) -> Option<f64> {
+        if self.block_count == 0 {
+            None
+        } else {
+            Some(self.block_interval_sum as f64 / self.block_count as f64)
+        }
+    }
+}
+
+// persist the aggregates of each day and the height the aggregation got to:
+//      Y{day} → {stats}
+//      y → {height}
+fn day_key(day: u32) -> Vec<u8> {
+    bincode::serialize_big(&(b'Y', day)).unwrap()
+}
+
+const CURSOR_KEY: &[u8] = b"y";
+
+// The persisted stats of the `days` most recent days, oldest first
+pub fn daily_stats(chain: &ChainQuery, days: u32) -> Vec<DailyStats> {
+    let today = chain.best_header().header().time / DAY;
+    let first_day = (today + 1).saturating_sub(days);
+    chain
+        .store()
+        .cache_db()
+        .iter_scan_from(b"Y", &day_key(first_day))
+        .map(|row| bincode::deserialize_little(&row.value).expect("failed to parse DailyStats"))
+        .collect()
+}
+
+// Aggregate the blocks buried since the last run into their days
+pub fn update(chain: &ChainQuery) -> Result<()> {
+    let cache_db = chain.store().cache_db();
+    let end_height = match chain.best_height().checked_sub(CONFIRMATIONS) {
+        Some(height) => height,
+        None => return Ok(()),
+    };
+    let start_height = match cache_db.get(CURSOR_KEY) {
+        Some(cursor) => bincode::deserialize_little::<u32>(&cursor).unwrap() as usize + 1,
+        None => first_height(chain, end_height),
+    };
+    if start_height > end_height {
+        return Ok(());
+    }
+    debug!("aggregating daily stats of blocks {}-{}", start_height, end_height);
+
+    let mut days: BTreeMap<u32, DailyStats> = BTreeMap::new();
+    for height in start_height..=end_height {
+        let header = chain
+            .header_by_height(height)
+            .chain_err(|| "reorged while aggregating the daily stats")?;
+        let time = header.header().time;
+        let day = time / DAY;
+        let stats = days.entry(day).or_insert_with(|| {
+            cache_db.get(&day_key(day)).map_or_else(
+                || DailyStats {
+                    day,
+                    ..Default::default()
+                },
+                |value| bincode::deserialize_little(&value).expect("failed to parse DailyStats"),
+            )
+        });
+
+        add_block(chain, &header, stats)?;
+        if let Some(prev) = height.checked_sub(1).and_then(|h| chain.header_by_height(h)) {
+            stats.block_interval_sum += time as i64 - prev.header().time as i64;
+        }
+
+        if (height - start_height + 1) % FLUSH_EVERY == 0 || height == end_height {
+            let mut rows: Vec<DBRow> = days
+                .values()
+                .map(|stats| DBRow {
+                    key: day_key(stats.day),
+                    value: bincode::serialize_little(stats).unwrap(),
+                })
+                .collect();
+            rows.push(DBRow {
+                key: CURSOR_KEY.to_vec(),
+                value: bincode::serialize_little(&(height as u32)).unwrap(),
+            });
+            cache_db.write(rows, DBFlush::Enable);
+            days.clear();
+        }
+    }
+    Ok(())
+}
+
+fn add_block(chain: &ChainQuery, header: &HeaderEntry, stats: &mut DailyStats) -> Result<()> {
+    let height = header.height();
+    let txids = chain
+        .get_block_txids(header.hash())
+        .chain_err(|| "missing block txids")?;
+
+    stats.block_count += 1;
+    stats.tx_count += txids.len() as u64;
+    stats.total_fee += chain
+        .block_stats(height, height)
+        .first()
+        .map_or(0, |block| block.total_fee);
+
+    let outputs = txids
+        .par_iter()
+        .map(|txid| chain.lookup_txn_outputs(txid).chain_err(|| "missing tx"))
+        .collect::<Result<Vec<Vec<TxOut>>>>()?;
+
+    let mut scripthashes = HashSet::new();
+    for txout in outputs.iter().flatten().filter(|txout| is_spendable(txout)) {
+        #[cfg(not(feature = "liquid"))]
+        {
+            stats.output_volume += txout.value.to_sat();
+        }
+        scripthashes.insert(compute_script_hash(&txout.script_pubkey));
+    }
+
+    // a script is new if its history starts at this block. each script is only looked up once
+    // per block, and the lookups run in parallel.
+    stats.new_address_count += scripthashes
+        .par_iter()
+        .filter(|scripthash| {
+            let first_height = chain
+                .history_iter_scan(b'H', &scripthash[..], 0)
+                .next()
+                .map(|row| TxHistoryRow::from_row(row).key.confirmed_height as usize);
+            first_height == Some(height)
+        })
+        .count() as u64;
+    Ok(())
+}
+
+// The first block of the oldest day covered by the aggregation
+fn first_height(chain: &ChainQuery, end_height: usize) -> usize {
+    let end_time = chain
+        .header_by_height(end_height)
+        .map_or(0, |header| header.header().time);
+    let first_day = (end_time / DAY).saturating_sub(MAX_DAYS - 1);
+    let mut height = end_height;
+    while height > 0 {
+        match chain.header_by_height(height - 1) {
+            Some(header) if header.header().time / DAY >= first_day => height -= 1,
+            _ => break,
+        }
+    }
+    height
+}
+
+// Keep aggregating newly buried blocks in the background
+pub fn spawn(chain: Arc<ChainQuery>) -> thread::JoinHandle<()> {
+    spawn_thread("daily-stats", move || {
+        let mut tip = chain.best_hash();
+        loop {
+            if let Err(e) = update(&chain) {
+                warn!("failed aggregating the daily stats: {}", e);
+            }
+            tip = chain.wait_tip_change(&tip, Duration::from_secs(60));
+        }
+    })
+}
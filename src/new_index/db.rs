@@ -1,8 +1,13 @@
 use rocksdb;
 
+use std::cell::Cell;
 use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
 use crate::config::Config;
+use crate::errors::*;
 use crate::util::{bincode, Bytes};
 
 static DB_VERSION: u32 = 1;
@@ -13,6 +18,140 @@ pub struct DBRow {
     pub value: Vec<u8>,
 }
 
+/// DB reads made by the current thread, used to attribute DB work to the request being served
+#[derive(Default, Clone, Copy, Debug, Serialize)]
+pub struct ReadStats {
+    pub gets: u64,
+    pub rows: u64,
+    pub bytes: u64, // the size of the rows and values read
+}
+
+thread_local! {
+    static READ_STATS: Cell<ReadStats> = Cell::new(ReadStats::default());
+    static DEADLINE: Cell<Option<Instant>> = Cell::new(None);
+    static CANCELLED: Cell<bool> = Cell::new(false);
+}
+
+impl ReadStats {
+    /// Get the reads made by the current thread since the last call, and reset them
+    pub fn take() -> ReadStats {
+        READ_STATS.with(|stats| stats.take())
+    }
+
+    /// The cost score charged against the request budgets: a unit per lookup or scanned row, and
+    /// one per KB read
+    pub fn cost(&self) -> u64 {
+        self.gets + self.rows + self.bytes / 1024
+    }
+
+    fn add(&mut self, other: &ReadStats) {
+        self.gets += other.gets;
+        self.rows += other.rows;
+        self.bytes += other.bytes;
+    }
+
+    fn count(f: impl FnOnce(&mut ReadStats)) {
+        READ_STATS.with(|stats| {
+            let mut current = stats.get();
+            f(&mut current);
+            stats.set(current);
+        })
+    }
+}
+
+/// A deadline for the DB reads made by the current thread, past which they get cancelled: scans
+/// stop yielding rows, leaving the results computed from them incomplete, and `Deadline::check`
+/// fails from then on. Cleared once dropped.
+pub struct Deadline(());
+
+impl Deadline {
+    pub fn set(timeout: Duration) -> Deadline {
+        DEADLINE.with(|deadline| deadline.set(Some(Instant::now() + timeout)));
+        CANCELLED.with(|cancelled| cancelled.set(false));
+        Deadline(())
+    }
+
+    /// Whether a scan got cancelled since the deadline was set
+    pub fn cancelled(&self) -> bool {
+        CANCELLED.with(|cancelled| cancelled.get())
+    }
+
+    /// Push back the deadline of the current thread, if any, for time spent waiting rather than
+    /// reading the DB
+    pub fn postpone(by: Duration) {
+        DEADLINE.with(|deadline| deadline.set(deadline.get().map(|at| at + by)));
+    }
+
+    /// Fail with `ErrorKind::Timeout` once the reads of the current thread got cancelled, for the
+    /// results computed from them to be discarded rather than returned or cached
+    pub fn check() -> Result<()> {
+        if Deadline::reached() || CANCELLED.with(|cancelled| cancelled.get()) {
+            bail!(ErrorKind::Timeout)
+        }
+        Ok(())
+    }
+
+    fn reached() -> bool {
+        let reached =
+            DEADLINE.with(|deadline| deadline.get().map_or(false, |at| Instant::now() >= at));
+        if reached {
+            CANCELLED.with(|cancelled| cancelled.set(true));
+        }
+        reached
+    }
+}
+
+impl Drop for Deadline {
+    fn drop(&mut self) {
+        DEADLINE.with(|deadline| deadline.set(None));
+        CANCELLED.with(|cancelled| cancelled.set(false));
+    }
+}
+
+/// The deadline of the current thread, carried over to the rayon threads reading the DB on its
+/// behalf. The cancellations of their reads, and the reads themselves for the request budgets,
+/// are reported back to it once dropped.
+pub struct ReadScope {
+    deadline: Option<Instant>,
+    cancelled: AtomicBool,
+    reads: Mutex<ReadStats>,
+}
+
+impl ReadScope {
+    pub fn capture() -> Self {
+        ReadScope {
+            deadline: DEADLINE.with(|deadline| deadline.get()),
+            cancelled: AtomicBool::new(false),
+            reads: Mutex::new(ReadStats::default()),
+        }
+    }
+
+    /// Run `f`, on any thread, with the deadline of the thread the scope was captured on
+    pub fn run<T>(&self, f: impl FnOnce() -> T) -> T {
+        let deadline = DEADLINE.with(|deadline| deadline.replace(self.deadline));
+        let cancelled = CANCELLED.with(|cancelled| cancelled.replace(false));
+        let reads = ReadStats::take();
+        let result = f();
+        if CANCELLED.with(|current| current.replace(cancelled)) {
+            self.cancelled.store(true, Ordering::Relaxed);
+        }
+        DEADLINE.with(|current| current.set(deadline));
+        let scope_reads = READ_STATS.with(|current| current.replace(reads));
+        self.reads.lock().unwrap().add(&scope_reads);
+        result
+    }
+}
+
+impl Drop for ReadScope {
+    fn drop(&mut self) {
+        if *self.cancelled.get_mut() {
+            CANCELLED.with(|cancelled| cancelled.set(true));
+        }
+        let reads = *self.reads.get_mut().unwrap();
+        ReadStats::count(|stats| stats.add(&reads));
+    }
+}
+
 pub struct ScanIterator<'a> {
     prefix: Vec<u8>,
     iter: rocksdb::DBIterator<'a>,
@@ -23,7 +162,7 @@ impl<'a> Iterator for ScanIterator<'a> {
     type Item = DBRow;
 
     fn next(&mut self) -> Option<DBRow> {
-        if self.done {
+        if self.done || Deadline::reached() {
             return None;
         }
         let (key, value) = self.iter.next()?.expect("valid iterator");
@@ -31,6 +170,10 @@ impl<'a> Iterator for ScanIterator<'a> {
             self.done = true;
             return None;
         }
+        ReadStats::count(|stats| {
+            stats.rows += 1;
+            stats.bytes += (key.len() + value.len()) as u64;
+        });
         Some(DBRow {
             key: key.to_vec(),
             value: value.to_vec(),
@@ -48,7 +191,7 @@ impl<'a> Iterator for ReverseScanIterator<'a> {
     type Item = DBRow;
 
     fn next(&mut self) -> Option<DBRow> {
-        if self.done || !self.iter.valid() {
+        if self.done || !self.iter.valid() || Deadline::reached() {
             return None;
         }
 
@@ -65,6 +208,10 @@ impl<'a> Iterator for ReverseScanIterator<'a> {
 
         self.iter.prev();
 
+        ReadStats::count(|stats| {
+            stats.rows += 1;
+            stats.bytes += (row.key.len() + row.value.len()) as u64;
+        });
         Some(row)
     }
 }
@@ -106,6 +253,32 @@ impl DB {
         db
     }
 
+    // Open the DB of another (primary) instance read-only, as a RocksDB secondary instance that
+    // keeps its own files under `secondary_path` and follows the primary with catch_up()
+    pub fn open_secondary(path: &Path, secondary_path: &Path, config: &Config) -> DB {
+        debug!("opening DB at {:?} as a secondary of {:?}", secondary_path, path);
+        let mut db_opts = rocksdb::Options::default();
+        // secondary instances have to keep all the files of the primary open
+        db_opts.set_max_open_files(-1);
+
+        let db = DB {
+            db: rocksdb::DB::open_as_secondary(&db_opts, path, secondary_path)
+                .expect("failed to open RocksDB as a secondary instance"),
+        };
+        if db.get(b"V").is_none() {
+            panic!("the primary instance did not initialize the DB at {:?} yet", path);
+        }
+        db.verify_compatibility(config);
+        db
+    }
+
+    // Pick up the changes written by the primary instance since, for secondary instances
+    pub fn catch_up(&self) {
+        if let Err(e) = self.db.try_catch_up_with_primary() {
+            warn!("failed catching up with the primary DB: {}", e);
+        }
+    }
+
     pub fn full_compaction(&self) {
         // TODO: make sure this doesn't fail silently
         debug!("starting full compaction on {:?}", self.db);
@@ -182,6 +355,16 @@ impl DB {
         self.db.flush().unwrap();
     }
 
+    // Flush the memtables and wait for the background jobs, ahead of exiting. The rows written
+    // with DBFlush::Disable skip the WAL, and would otherwise be lost.
+    pub fn close(&self) {
+        debug!("closing DB {:?}", self.db);
+        if let Err(e) = self.db.flush() {
+            warn!("failed flushing DB: {}", e);
+        }
+        self.db.cancel_all_background_work(true);
+    }
+
     pub fn put(&self, key: &[u8], value: &[u8]) {
         self.db.put(key, value).unwrap();
     }
@@ -192,8 +375,20 @@ impl DB {
         self.db.put_opt(key, value, &opts).unwrap();
     }
 
+    pub fn delete(&self, key: &[u8]) {
+        self.db.delete(key).unwrap();
+    }
+
     pub fn get(&self, key: &[u8]) -> Option<Bytes> {
-        self.db.get(key).unwrap().map(|v| v.to_vec())
+        // lookups aren't cut short, but flag the reads past the deadline as cancelled for the
+        // loops of lookups to bail out on `Deadline::check`
+        Deadline::reached();
+        let value = self.db.get(key).unwrap();
+        ReadStats::count(|stats| {
+            stats.gets += 1;
+            stats.bytes += value.as_ref().map_or(0, |v| v.len()) as u64;
+        });
+        value.map(|v| v.to_vec())
     }
 
     fn verify_compatibility(&self, config: &Config) {
@@ -0,0 +1,125 @@
+use std::sync::Mutex;
+
+use crate::chain::BlockHash;
+use crate::errors::*;
+use crate::new_index::ChainQuery;
+use crate::util::HeaderEntry;
+
+#[derive(Serialize, Debug)]
+pub struct Retarget {
+    pub next_retarget_height: usize,
+    pub blocks_until_retarget: usize,
+    pub estimated_next_difficulty: f64,
+}
+
+// The next difficulty adjustment, estimated from the pace of the blocks found since the current
+// period started. The adjustment is capped to a factor of 4 either way, as bitcoind does.
+pub fn next_retarget(chain: &ChainQuery, interval: usize, target_spacing: u32) -> Result<Retarget> {
+    let tip = chain.best_header();
+    let period_start = tip.height() - tip.height() % interval;
+    let next_retarget_height = period_start + interval;
+    let difficulty = tip.header().difficulty_float();
+
+    let elapsed_blocks = tip.height() - period_start;
+    let estimated_next_difficulty = if elapsed_blocks == 0 {
+        difficulty
+    } else {
+        let start_time = best_header(chain, period_start)?.header().time;
+        let actual_timespan = tip.header().time.saturating_sub(start_time).max(1) as f64;
+        let target_timespan = elapsed_blocks as f64 * target_spacing as f64;
+        difficulty * (target_timespan / actual_timespan).clamp(0.25, 4.0)
+    };
+
+    Ok(Retarget {
+        next_retarget_height,
+        blocks_until_retarget: next_retarget_height - tip.height(),
+        estimated_next_difficulty,
+    })
+}
+
+// The best chain header at the height, which a concurrent reorg may have just removed
+fn best_header(chain: &ChainQuery, height: usize) -> Result<HeaderEntry> {
+    chain
+        .header_by_height(height)
+        .chain_err(|| format!("no best chain header at height {} (reorged?)", height))
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Epoch {
+    pub start_height: usize,
+    pub end_height: usize, // the last block found so far for the current epoch
+    pub start_time: u32,
+    pub difficulty: f64, // as of the first block of the epoch
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub average_block_interval: Option<f64>, // in seconds, None for single-block epochs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub adjustment: Option<f64>, // relative to the difficulty of the previous epoch
+    pub complete: bool,
+}
+
+// The completed epochs of the best chain, oldest first, along with the hash of their last block
+// to detect the ones orphaned by reorgs
+#[derive(Default)]
+pub struct EpochCache(Mutex<Vec<(Epoch, BlockHash)>>);
+
+fn epoch_at(
+    chain: &ChainQuery,
+    start_height: usize,
+    end_height: usize,
+    previous: Option<&Epoch>,
+    complete: bool,
+) -> Result<Epoch> {
+    let start = best_header(chain, start_height)?;
+    let end = best_header(chain, end_height)?;
+    let difficulty = start.header().difficulty_float();
+    let timespan = end.header().time.saturating_sub(start.header().time);
+    Ok(Epoch {
+        start_height,
+        end_height,
+        start_time: start.header().time,
+        difficulty,
+        average_block_interval: (end_height > start_height)
+            .then(|| timespan as f64 / (end_height - start_height) as f64),
+        adjustment: previous.map(|previous| difficulty / previous.difficulty),
+        complete,
+    })
+}
+
+// The retarget epochs of the best chain, newest first, up to `limit` of them. The completed
+// epochs are only computed once.
+pub fn epochs(chain: &ChainQuery, interval: usize, limit: usize) -> Result<Vec<Epoch>> {
+    let tip_height = chain.best_height();
+    let completed_count = (tip_height + 1) / interval;
+
+    let mut completed = chain.epoch_cache().0.lock().unwrap();
+    while let Some((epoch, hash)) = completed.last() {
+        let is_best = chain
+            .header_by_height(epoch.end_height)
+            .map_or(false, |header| header.hash() == hash);
+        if is_best && completed.len() <= completed_count {
+            break;
+        }
+        completed.pop();
+    }
+    while completed.len() < completed_count {
+        let start_height = completed.len() * interval;
+        let end_height = start_height + interval - 1;
+        let previous = completed.last().map(|(epoch, _)| epoch);
+        let epoch = epoch_at(chain, start_height, end_height, previous, true)?;
+        let hash = *best_header(chain, end_height)?.hash();
+        completed.push((epoch, hash));
+    }
+
+    let current_start = completed_count * interval;
+    let current = if current_start <= tip_height {
+        let previous = completed.last().map(|(epoch, _)| epoch);
+        Some(epoch_at(chain, current_start, tip_height, previous, false)?)
+    } else {
+        None
+    };
+    Ok(current
+        .into_iter()
+        .chain(completed.iter().rev().map(|(epoch, _)| epoch.clone()))
+        .take(limit)
+        .collect())
+}
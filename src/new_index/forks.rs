@@ -0,0 +1,274 @@
+use std::collections::BTreeSet;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::chain::BlockHash;
+use crate::config::Config;
+use crate::daemon::Daemon;
+use crate::errors::*;
+use crate::metrics::{Gauge, GaugeVec, MetricOpts, Metrics};
+use crate::new_index::db::{DBFlush, DBRow};
+use crate::new_index::ChainQuery;
+use crate::util::{bincode, spawn_thread};
+
+// how far back the fork point of a competing tip is looked for
+const MAX_FORK_DEPTH: u32 = 1000;
+// how many competing tips are kept around once they're no longer observed
+const MAX_COMPETING_TIPS: usize = 100;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TipStatus {
+    Synced,
+    Behind, // the daemon's tip is an ancestor of the indexed one
+    Ahead,  // the indexed tip is an ancestor of the daemon's one
+    Forked,
+    Error,
+}
+
+// The chain tip of a secondary daemon as of its last check
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DaemonTip {
+    pub daemon: String,
+    pub subversion: Option<String>,
+    pub status: TipStatus,
+    pub height: Option<u32>,
+    pub hash: Option<BlockHash>,
+    pub fork_height: Option<u32>, // the last block shared with the indexed chain, if found
+    pub divergence: u32,          // the length of the longest branch past the fork point
+    pub error: Option<String>,
+    pub time: u32,
+}
+
+// A tip off the indexed chain that was observed on some of the daemons
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CompetingTip {
+    pub hash: BlockHash,
+    pub height: u32,
+    pub fork_height: Option<u32>,
+    pub divergence: u32,
+    pub daemons: BTreeSet<String>,
+    pub first_seen: u32,
+    pub last_seen: u32,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+pub struct ForkReport {
+    pub daemons: Vec<DaemonTip>,
+    pub competing_tips: Vec<CompetingTip>, // most recently seen first
+}
+
+impl ForkReport {
+    pub fn max_divergence(&self) -> u32 {
+        self.daemons
+            .iter()
+            .map(|tip| tip.divergence)
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+// persist the last report, so that the competing tips survive restarts:
+//      f → {report}
+const REPORT_KEY: &[u8] = b"f";
+
+pub fn report(chain: &ChainQuery) -> Option<ForkReport> {
+    chain
+        .store()
+        .cache_db()
+        .get(REPORT_KEY)
+        .map(|value| bincode::deserialize_little(&value).expect("failed to parse ForkReport"))
+}
+
+// Connect the secondary daemons, labeled by their address
+pub fn connect(daemon: &Daemon, config: &Config) -> Result<Vec<(String, Daemon)>> {
+    config
+        .secondary_daemons
+        .iter()
+        .map(|secondary| {
+            let daemon =
+                daemon.connect_secondary(secondary.rpc_addr, secondary.cookie_getter(config))?;
+            Ok((secondary.rpc_addr.to_string(), daemon))
+        })
+        .collect()
+}
+
+// Compare the tips of the secondary daemons against the indexed chain and persist the report
+pub fn update(chain: &ChainQuery, daemons: &[(String, Daemon)]) -> ForkReport {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs() as u32);
+
+    let tips: Vec<DaemonTip> = daemons
+        .iter()
+        .map(|(label, daemon)| {
+            check_tip(chain, daemon, label.clone(), now).unwrap_or_else(|e| {
+                warn!("failed checking the chain tip of {}: {}", label, e);
+                DaemonTip {
+                    daemon: label.clone(),
+                    subversion: None,
+                    status: TipStatus::Error,
+                    height: None,
+                    hash: None,
+                    fork_height: None,
+                    divergence: 0,
+                    error: Some(e.to_string()),
+                    time: now,
+                }
+            })
+        })
+        .collect();
+
+    // merge the currently observed competing tips with the previous ones, dropping those the
+    // indexed chain has since reorged onto
+    let mut competing_tips: Vec<CompetingTip> = report(chain)
+        .map_or_else(Vec::new, |report| report.competing_tips)
+        .into_iter()
+        .filter(|competing| chain.height_by_hash(&competing.hash).is_none())
+        .collect();
+    for tip in tips.iter().filter(|tip| tip.status == TipStatus::Forked) {
+        let hash = tip.hash.expect("forked tip without hash");
+        match competing_tips.iter_mut().find(|competing| competing.hash == hash) {
+            Some(competing) => {
+                competing.divergence = tip.divergence;
+                competing.daemons.insert(tip.daemon.clone());
+                competing.last_seen = now;
+            }
+            None => competing_tips.push(CompetingTip {
+                hash,
+                height: tip.height.unwrap_or(0),
+                fork_height: tip.fork_height,
+                divergence: tip.divergence,
+                daemons: vec![tip.daemon.clone()].into_iter().collect(),
+                first_seen: now,
+                last_seen: now,
+            }),
+        }
+    }
+    competing_tips.sort_by(|a, b| b.last_seen.cmp(&a.last_seen));
+    competing_tips.truncate(MAX_COMPETING_TIPS);
+
+    let report = ForkReport {
+        daemons: tips,
+        competing_tips,
+    };
+    chain.store().cache_db().write(
+        vec![DBRow {
+            key: REPORT_KEY.to_vec(),
+            value: bincode::serialize_little(&report).unwrap(),
+        }],
+        DBFlush::Enable,
+    );
+    report
+}
+
+fn check_tip(chain: &ChainQuery, daemon: &Daemon, label: String, now: u32) -> Result<DaemonTip> {
+    let info = daemon.getblockchaininfo()?;
+    let hash: BlockHash = info
+        .bestblockhash
+        .parse()
+        .chain_err(|| "invalid best block hash")?;
+    let height = info.blocks;
+    let tip_height = chain.best_height() as u32;
+
+    let (status, fork_height) = if chain.height_by_hash(&hash).is_some() {
+        let status = if height == tip_height {
+            TipStatus::Synced
+        } else {
+            TipStatus::Behind
+        };
+        (status, Some(height))
+    } else {
+        // walk back the daemon's chain until it joins the indexed one
+        let mut fork_height = None;
+        let mut blockhash = hash;
+        for ancestor_height in (height.saturating_sub(MAX_FORK_DEPTH)..height).rev() {
+            blockhash = daemon.getblockheader(&blockhash)?.prev_blockhash;
+            if chain.height_by_hash(&blockhash).is_some() {
+                fork_height = Some(ancestor_height);
+                break;
+            }
+        }
+        match fork_height {
+            Some(fork_height) if fork_height == tip_height => (TipStatus::Ahead, Some(fork_height)),
+            fork_height => (TipStatus::Forked, fork_height),
+        }
+    };
+
+    let divergence = match (status, fork_height) {
+        (TipStatus::Forked, Some(fork_height)) => {
+            (height - fork_height).max(tip_height.saturating_sub(fork_height))
+        }
+        (TipStatus::Forked, None) => MAX_FORK_DEPTH,
+        _ => 0,
+    };
+
+    Ok(DaemonTip {
+        daemon: label,
+        subversion: daemon.subversion().ok(),
+        status,
+        height: Some(height),
+        hash: Some(hash),
+        fork_height,
+        divergence,
+        error: None,
+        time: now,
+    })
+}
+
+// Keep comparing the tips of the secondary daemons in the background
+pub fn spawn(
+    chain: Arc<ChainQuery>,
+    daemon: Arc<Daemon>,
+    config: Arc<Config>,
+    metrics: &Metrics,
+) -> thread::JoinHandle<()> {
+    let divergence_gauge: GaugeVec = metrics.gauge_vec(
+        MetricOpts::new(
+            "electrs_fork_divergence",
+            "Blocks past the fork point between a secondary daemon's chain and the indexed one",
+        ),
+        &["daemon"],
+    );
+    let alert_gauge: Gauge = metrics.gauge(MetricOpts::new(
+        "electrs_fork_alert",
+        "Whether a secondary daemon diverges by more than --fork-alert-depth blocks",
+    ));
+
+    spawn_thread("fork-monitor", move || {
+        let daemons = match connect(&daemon, &config) {
+            Ok(daemons) => daemons,
+            Err(e) => {
+                warn!("failed connecting secondary daemons: {}", e);
+                return;
+            }
+        };
+        loop {
+            let report = update(&chain, &daemons);
+            for tip in &report.daemons {
+                divergence_gauge
+                    .with_label_values(&[&tip.daemon])
+                    .set(tip.divergence as f64);
+            }
+            let alert = report.max_divergence() as usize > config.fork_alert_depth;
+            if alert {
+                warn!(
+                    "chain split detected: {}",
+                    report
+                        .daemons
+                        .iter()
+                        .filter(|tip| tip.status == TipStatus::Forked)
+                        .map(|tip| format!(
+                            "{} at {:?} (fork height {:?}, divergence {})",
+                            tip.daemon, tip.hash, tip.fork_height, tip.divergence
+                        ))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                );
+            }
+            alert_gauge.set(alert as i64);
+            thread::sleep(Duration::from_secs(30));
+        }
+    })
+}
@@ -0,0 +1,130 @@
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bitcoin::hashes::{sha256, Hash};
+
+use crate::chain::Txid;
+use crate::new_index::db::{DBFlush, DBRow};
+use crate::new_index::ChainQuery;
+use crate::util::bincode;
+
+// how long the broadcasts are remembered for, in seconds
+const RETENTION: u32 = 24 * 3600;
+// the expired entries are pruned once every this many recorded broadcasts
+const PRUNE_INTERVAL: usize = 1000;
+
+pub const MAX_KEY_LEN: usize = 128;
+
+static RECORDED: AtomicUsize = AtomicUsize::new(0);
+
+// persist the transactions broadcasted with an idempotency key, scoped to the client that set
+// it. The client and key get hashed not to store API keys in the clear:
+//      k{sha256(client, key)} → {txid}{time}
+fn entry_key(client: &str, key: &str) -> Vec<u8> {
+    let hash = sha256::Hash::hash(format!("{}\0{}", client, key).as_bytes());
+    [&b"k"[..], &hash[..]].concat()
+}
+
+fn now() -> u32 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs() as u32)
+}
+
+fn parse_entry(value: &[u8]) -> Option<(Txid, u32)> {
+    bincode::deserialize_little(value)
+        .map_err(|e| warn!("failed to parse idempotent broadcast: {}", e))
+        .ok()
+}
+
+// The broadcasts in flight, for concurrent retries to wait for their outcome rather than
+// submit the transaction again
+#[derive(Default)]
+pub struct IdempotentBroadcasts {
+    in_flight: Mutex<HashSet<Vec<u8>>>,
+    finished: Condvar,
+}
+
+pub enum Reserved<'a> {
+    // the transaction previously broadcasted with the key, if it's still remembered
+    Broadcasted(Txid),
+    // the key is free, and held until the reservation gets dropped
+    Reservation(Reservation<'a>),
+}
+
+pub struct Reservation<'a> {
+    broadcasts: &'a IdempotentBroadcasts,
+    entry_key: Vec<u8>,
+}
+
+impl IdempotentBroadcasts {
+    // Reserve the key of the client for a broadcast, waiting for the broadcast in flight with
+    // it to finish first
+    pub fn reserve(&self, chain: &ChainQuery, client: &str, key: &str) -> Reserved<'_> {
+        let entry_key = entry_key(client, key);
+        let mut in_flight = self
+            .finished
+            .wait_while(self.in_flight.lock().unwrap(), |in_flight| {
+                in_flight.contains(&entry_key)
+            })
+            .unwrap();
+        if let Some(txid) = lookup(chain, &entry_key) {
+            return Reserved::Broadcasted(txid);
+        }
+        in_flight.insert(entry_key.clone());
+        Reserved::Reservation(Reservation {
+            broadcasts: self,
+            entry_key,
+        })
+    }
+}
+
+impl Reservation<'_> {
+    pub fn record(&self, chain: &ChainQuery, txid: &Txid) {
+        record(chain, &self.entry_key, txid)
+    }
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        let mut in_flight = self.broadcasts.in_flight.lock().unwrap();
+        in_flight.remove(&self.entry_key);
+        self.broadcasts.finished.notify_all();
+    }
+}
+
+fn lookup(chain: &ChainQuery, entry_key: &[u8]) -> Option<Txid> {
+    let value = chain.store().cache_db().get(entry_key)?;
+    let (txid, time) = parse_entry(&value)?;
+    if time + RETENTION < now() {
+        return None;
+    }
+    Some(txid)
+}
+
+fn record(chain: &ChainQuery, entry_key: &[u8], txid: &Txid) {
+    let cache_db = chain.store().cache_db();
+    let now = now();
+    cache_db.write(
+        vec![DBRow {
+            key: entry_key.to_vec(),
+            value: bincode::serialize_little(&(txid, now)).unwrap(),
+        }],
+        DBFlush::Enable,
+    );
+
+    if RECORDED.fetch_add(1, Ordering::Relaxed) % PRUNE_INTERVAL == PRUNE_INTERVAL - 1 {
+        // the unparseable entries get pruned along with the expired ones
+        let expired: Vec<Vec<u8>> = cache_db
+            .iter_scan(b"k")
+            .filter(|row| parse_entry(&row.value).map_or(true, |(_, time)| time + RETENTION < now))
+            .map(|row| row.key)
+            .collect();
+        debug!("pruning {} expired idempotency keys", expired.len());
+        for key in expired {
+            cache_db.delete(&key);
+        }
+    }
+}
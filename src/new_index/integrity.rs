@@ -0,0 +1,276 @@
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, BTreeSet};
+use std::hash::{BuildHasher, Hasher};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use hex::DisplayHex;
+
+use crate::chain::{OutPoint, Txid};
+use crate::metrics::{Gauge, GaugeVec, MetricOpts, Metrics};
+use crate::new_index::db::{DBFlush, DBRow};
+use crate::new_index::schema::FullHash;
+use crate::new_index::{
+    compute_script_hash, ChainQuery, GetAmountVal, TxHistoryInfo, TxHistoryRow,
+};
+use crate::util::{bincode, full_hash, spawn_thread};
+
+// how many scripthashes are checked on every run
+pub const SAMPLE_SIZE: usize = 10;
+// history entries past this many are not checked, to keep the runs of popular scripthashes short
+const MAX_HISTORY_ENTRIES: usize = 1000;
+// how many findings are kept around for the report
+const MAX_FINDINGS: usize = 100;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum FindingKind {
+    History, // a history entry that disagrees with the transaction it refers to
+    Stats,   // the cached stats disagree with the history
+    Utxo,    // the cached UTXOs disagree with the history
+}
+
+impl FindingKind {
+    fn label(&self) -> &'static str {
+        match self {
+            FindingKind::History => "history",
+            FindingKind::Stats => "stats",
+            FindingKind::Utxo => "utxo",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Finding {
+    pub scripthash: String,
+    pub kind: FindingKind,
+    pub txid: Option<Txid>,
+    pub detail: String,
+    pub time: u32,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+pub struct IntegrityReport {
+    pub runs: u64,
+    pub checked_scripthashes: u64,
+    pub checked_entries: u64,
+    pub findings_count: BTreeMap<FindingKind, u64>,
+    pub findings: Vec<Finding>, // most recent first
+    pub last_run: Option<u32>,
+}
+
+// persist the report, so that the findings survive restarts:
+//      c → {report}
+const REPORT_KEY: &[u8] = b"c";
+
+pub fn report(chain: &ChainQuery) -> Option<IntegrityReport> {
+    chain
+        .store()
+        .cache_db()
+        .get(REPORT_KEY)
+        .map(|value| bincode::deserialize_little(&value).expect("failed to parse IntegrityReport"))
+}
+
+fn now() -> u32 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs() as u32)
+}
+
+fn random_hash() -> FullHash {
+    let mut hash = [0u8; 32];
+    for chunk in hash.chunks_mut(8) {
+        // every RandomState is seeded with different keys
+        let random = RandomState::new().build_hasher().finish();
+        chunk.copy_from_slice(&random.to_le_bytes());
+    }
+    hash
+}
+
+// Pick up to `count` indexed scripthashes at random, by seeking to random history positions
+fn sample(chain: &ChainQuery, count: usize) -> BTreeSet<FullHash> {
+    let history_db = chain.store().history_db();
+    let mut sampled = BTreeSet::new();
+    for _ in 0..count {
+        let start_at = [&b"H"[..], &random_hash()[..]].concat();
+        // wrap around to the first scripthash when seeking past the last one
+        let row = history_db
+            .iter_scan_from(b"H", &start_at)
+            .next()
+            .or_else(|| history_db.iter_scan(b"H").next());
+        match row {
+            Some(row) => sampled.insert(full_hash(&row.key[1..33])),
+            None => break,
+        };
+    }
+    sampled
+}
+
+// The reason the history entry disagrees with the transaction it refers to, if it does
+fn verify_entry(chain: &ChainQuery, scripthash: &[u8], entry: &TxHistoryRow) -> Option<String> {
+    let txid = entry.get_txid();
+    let height = entry.key.confirmed_height as usize;
+    // entries left behind by orphaned blocks are expected
+    let blockid = chain
+        .tx_confirming_block(&txid)
+        .filter(|blockid| blockid.height == height)?;
+    let in_block = chain
+        .get_block_txids(&blockid.hash)
+        .map_or(false, |txids| txids.contains(&txid));
+    if !in_block {
+        return Some(format!("not included in block {}", blockid.hash));
+    }
+    let tx = match chain.lookup_txn(&txid, Some(&blockid.hash)) {
+        Some(tx) => tx,
+        None => return Some("missing transaction".to_string()),
+    };
+
+    let (outpoint, value) = match entry.key.txinfo {
+        TxHistoryInfo::Funding(ref info) => (OutPoint::new(txid, info.vout as u32), info.value),
+        TxHistoryInfo::Spending(ref info) => {
+            let outpoint = entry.key.txinfo.get_funded_outpoint();
+            match tx.input.get(info.vin as usize) {
+                Some(txin) if txin.previous_output == outpoint => (),
+                Some(txin) => {
+                    return Some(format!(
+                        "input {} spends {} instead of {}",
+                        info.vin, txin.previous_output, outpoint
+                    ))
+                }
+                None => return Some(format!("missing input {}", info.vin)),
+            }
+            (outpoint, info.value)
+        }
+        #[cfg(feature = "liquid")]
+        _ => return None,
+    };
+
+    let txo = if outpoint.txid == txid {
+        tx.output.get(outpoint.vout as usize).cloned()
+    } else {
+        chain
+            .lookup_txn_outputs(&outpoint.txid)
+            .and_then(|txos| txos.get(outpoint.vout as usize).cloned())
+    };
+    match txo {
+        None => Some(format!("missing output {}", outpoint)),
+        Some(txo) if compute_script_hash(&txo.script_pubkey)[..] != *scripthash => {
+            Some(format!("output {} pays to another script", outpoint))
+        }
+        Some(txo) if txo.value.amount_value() != value => Some(format!(
+            "output {} has value {:?} instead of {:?}",
+            outpoint,
+            txo.value.amount_value(),
+            value
+        )),
+        Some(_) => None,
+    }
+}
+
+// Verify the history entries of the scripthash against the transactions they refer to, and its
+// cached stats and UTXOs against its history. Returns the findings and the number of history
+// entries checked.
+pub fn check_scripthash(
+    chain: &ChainQuery,
+    scripthash: &[u8],
+    utxos_limit: usize,
+) -> (Vec<Finding>, usize) {
+    let now = now();
+    let finding = |kind, txid, detail| Finding {
+        scripthash: scripthash.to_lower_hex_string(),
+        kind,
+        txid,
+        detail,
+        time: now,
+    };
+
+    let mut findings = vec![];
+    let mut checked = 0;
+    let entries = chain
+        .history_iter_scan(b'H', scripthash, 0)
+        .map(TxHistoryRow::from_row)
+        .take(MAX_HISTORY_ENTRIES);
+    for entry in entries {
+        checked += 1;
+        if let Some(detail) = verify_entry(chain, scripthash, &entry) {
+            findings.push(finding(FindingKind::History, Some(entry.get_txid()), detail));
+        }
+    }
+
+    if let Some(detail) = chain.verify_cached_stats(scripthash) {
+        findings.push(finding(FindingKind::Stats, None, detail));
+    }
+    // scripthashes with too many UTXOs can't be checked
+    if let Ok(Some(detail)) = chain.verify_cached_utxos(scripthash, utxos_limit) {
+        findings.push(finding(FindingKind::Utxo, None, detail));
+    }
+    (findings, checked)
+}
+
+// Check a random sample of scripthashes and add the findings to the persisted report
+pub fn update(chain: &ChainQuery, utxos_limit: usize) -> IntegrityReport {
+    let mut report = report(chain).unwrap_or_default();
+    let mut findings = vec![];
+    for scripthash in sample(chain, SAMPLE_SIZE) {
+        let (mut scripthash_findings, checked) =
+            check_scripthash(chain, &scripthash[..], utxos_limit);
+        report.checked_scripthashes += 1;
+        report.checked_entries += checked as u64;
+        findings.append(&mut scripthash_findings);
+    }
+
+    for finding in &findings {
+        warn!(
+            "integrity check failed for scripthash {} ({:?}): {}",
+            finding.scripthash, finding.kind, finding.detail
+        );
+        *report.findings_count.entry(finding.kind).or_insert(0) += 1;
+    }
+    findings.append(&mut report.findings);
+    findings.truncate(MAX_FINDINGS);
+    report.findings = findings;
+    report.runs += 1;
+    report.last_run = Some(now());
+
+    chain.store().cache_db().write(
+        vec![DBRow {
+            key: REPORT_KEY.to_vec(),
+            value: bincode::serialize_little(&report).unwrap(),
+        }],
+        DBFlush::Enable,
+    );
+    report
+}
+
+// Keep checking random scripthashes in the background, every `interval` seconds
+pub fn spawn(
+    chain: Arc<ChainQuery>,
+    interval: u64,
+    utxos_limit: usize,
+    metrics: &Metrics,
+) -> thread::JoinHandle<()> {
+    let checked_gauge: Gauge = metrics.gauge(MetricOpts::new(
+        "electrs_integrity_checked_scripthashes",
+        "Scripthashes checked by the integrity checker",
+    ));
+    let findings_gauge: GaugeVec = metrics.gauge_vec(
+        MetricOpts::new(
+            "electrs_integrity_findings",
+            "Inconsistencies found by the integrity checker",
+        ),
+        &["kind"],
+    );
+
+    spawn_thread("integrity-checker", move || loop {
+        let report = update(&chain, utxos_limit);
+        checked_gauge.set(report.checked_scripthashes as i64);
+        for kind in &[FindingKind::History, FindingKind::Stats, FindingKind::Utxo] {
+            let count = report.findings_count.get(kind).cloned().unwrap_or(0);
+            findings_gauge
+                .with_label_values(&[kind.label()])
+                .set(count as f64);
+        }
+        thread::sleep(Duration::from_secs(interval));
+    })
+}
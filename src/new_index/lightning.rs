@@ -0,0 +1,335 @@
+// The on-chain footprint of Lightning channels, as specified by BOLT 3. Channels only get revealed
+// by spends: their 2-of-2 funding output once it gets spent by a closing transaction, and the
+// outputs of the commitment transactions once they get claimed.
+use std::collections::HashMap;
+
+use bitcoin::consensus::encode::deserialize;
+
+use crate::chain::{OutPoint, Transaction, TxIn, TxOut, Txid};
+use crate::new_index::db::{DBFlush, DBRow};
+use crate::new_index::{BlockEntry, ChainQuery};
+use crate::util::{
+    bincode, full_hash, get_innerscripts, has_prevout, Bytes, FullHash, ScriptTemplate,
+};
+
+// only the closes this deep get the opens of their channels folded into the cache db, so that
+// reorgs never need to be rolled back
+const CONFIRMATIONS: usize = 10;
+
+lazy_static! {
+    static ref FUNDING: ScriptTemplate = template(
+        "funding",
+        "OP_PUSHNUM_2 <33> <33> OP_PUSHNUM_2 OP_CHECKMULTISIG",
+    );
+    static ref TO_LOCAL: ScriptTemplate = template(
+        "to_local",
+        "OP_IF <33> OP_ELSE <*> OP_CSV OP_DROP <33> OP_ENDIF OP_CHECKSIG",
+    );
+    // with anchor outputs, the HTLC outputs also have a `1 OP_CSV OP_DROP` before the last
+    // OP_ENDIF, which `...` accounts for
+    static ref OFFERED_HTLC: ScriptTemplate = template(
+        "offered_htlc",
+        "OP_DUP OP_HASH160 <20> OP_EQUAL OP_IF OP_CHECKSIG OP_ELSE <33> OP_SWAP OP_SIZE <1> \
+         OP_EQUAL OP_NOTIF OP_DROP OP_PUSHNUM_2 OP_SWAP <33> OP_PUSHNUM_2 OP_CHECKMULTISIG \
+         OP_ELSE OP_HASH160 <20> OP_EQUALVERIFY OP_CHECKSIG OP_ENDIF ... OP_ENDIF",
+    );
+    static ref RECEIVED_HTLC: ScriptTemplate = template(
+        "received_htlc",
+        "OP_DUP OP_HASH160 <20> OP_EQUAL OP_IF OP_CHECKSIG OP_ELSE <33> OP_SWAP OP_SIZE <1> \
+         OP_EQUAL OP_IF OP_HASH160 <20> OP_EQUALVERIFY OP_PUSHNUM_2 OP_SWAP <33> OP_PUSHNUM_2 \
+         OP_CHECKMULTISIG OP_ELSE OP_DROP <*> OP_CLTV OP_DROP OP_CHECKSIG OP_ENDIF ... OP_ENDIF",
+    );
+}
+
+fn template(name: &str, pattern: &str) -> ScriptTemplate {
+    ScriptTemplate::parse(name, pattern).expect("invalid lightning script template")
+}
+
+// The role of the output spent by an input, as far as Lightning is concerned
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum InputKind {
+    ChannelFunding,
+    ToLocal,
+    OfferedHtlc,
+    ReceivedHtlc,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CloseKind {
+    Cooperative,
+    Force,
+}
+
+// Lightning outputs are all P2WSH, identified by the witness script revealed by their spend
+pub fn input_kind(txin: &TxIn, prevout: &TxOut) -> Option<InputKind> {
+    if !prevout.script_pubkey.is_p2wsh() {
+        return None;
+    }
+    let script = get_innerscripts(txin, prevout).witness_script?;
+    if is_funding_script(&script) {
+        Some(InputKind::ChannelFunding)
+    } else if TO_LOCAL.matches(&script) {
+        Some(InputKind::ToLocal)
+    } else if OFFERED_HTLC.matches(&script) {
+        Some(InputKind::OfferedHtlc)
+    } else if RECEIVED_HTLC.matches(&script) {
+        Some(InputKind::ReceivedHtlc)
+    } else {
+        None
+    }
+}
+
+// Funding outputs pay to a 2-of-2 multisig of compressed keys, sorted lexicographically
+fn is_funding_script(script: &bitcoin::Script) -> bool {
+    let bytes = script.as_bytes();
+    FUNDING.matches(script) && bytes[2..35] < bytes[36..69]
+}
+
+// How the channel got closed by the transaction spending its funding output, None if it spends
+// other outputs too as neither closing nor commitment transactions do. Commitment transactions
+// (broadcasted by force closes) encode the obscured commitment number in their locktime and
+// sequence, under the 0x20 and 0x80 upper bytes.
+pub fn close_kind(tx: &Transaction, funding_vin: usize) -> Option<CloseKind> {
+    if tx.input.len() != 1 {
+        return None;
+    }
+    let locktime = tx.lock_time.to_consensus_u32();
+    let sequence = tx.input[funding_vin].sequence.to_consensus_u32();
+    Some(if locktime >> 24 == 0x20 && sequence >> 24 == 0x80 {
+        CloseKind::Force
+    } else {
+        CloseKind::Cooperative
+    })
+}
+
+// The channel closes and the claims of commitment outputs seen by a block
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+pub struct LightningStats {
+    pub cooperative_closes: u32,
+    pub force_closes: u32,
+    pub closed_capacity: u64,
+    pub htlc_claims: u32,
+    pub to_local_claims: u32,
+}
+
+impl LightningStats {
+    fn merge(&mut self, other: &LightningStats) {
+        self.cooperative_closes += other.cooperative_closes;
+        self.force_closes += other.force_closes;
+        self.closed_capacity += other.closed_capacity;
+        self.htlc_claims += other.htlc_claims;
+        self.to_local_claims += other.to_local_claims;
+    }
+}
+
+// The stats of a block, along with the funding txids of the channels it closed. None for blocks
+// without anything Lightning-related.
+pub fn block_stats(
+    b: &BlockEntry,
+    txos: &HashMap<OutPoint, TxOut>,
+) -> Option<(LightningStats, Vec<FullHash>)> {
+    let mut stats = LightningStats::default();
+    let mut funding_txids = vec![];
+    for tx in &b.block.txdata {
+        for (vin, txin) in tx.input.iter().enumerate() {
+            if !has_prevout(txin) {
+                continue;
+            }
+            let prevout = match txos.get(&txin.previous_output) {
+                Some(prevout) => prevout,
+                None => continue,
+            };
+            match input_kind(txin, prevout) {
+                Some(InputKind::ChannelFunding) => {
+                    match close_kind(tx, vin) {
+                        Some(CloseKind::Cooperative) => stats.cooperative_closes += 1,
+                        Some(CloseKind::Force) => stats.force_closes += 1,
+                        None => continue,
+                    }
+                    stats.closed_capacity += prevout.value.to_sat();
+                    funding_txids.push(full_hash(&txin.previous_output.txid[..]));
+                }
+                Some(InputKind::ToLocal) => stats.to_local_claims += 1,
+                Some(InputKind::OfferedHtlc) | Some(InputKind::ReceivedHtlc) => {
+                    stats.htlc_claims += 1
+                }
+                None => (),
+            }
+        }
+    }
+    if stats.cooperative_closes + stats.force_closes + stats.htlc_claims + stats.to_local_claims
+        == 0
+    {
+        return None;
+    }
+    Some((stats, funding_txids))
+}
+
+// persist the stats of each block with Lightning activity, keyed by height:
+//      N{height} → {blockhash}{stats}{funding-txids}
+fn height_key(height: u32) -> Bytes {
+    bincode::serialize_big(&(b'N', height)).unwrap()
+}
+
+// persist the channels closed by the buried blocks, keyed by the height they got funded at,
+// along with the last block folded in:
+//      N{funding-height}{funding-txid} → ""     (in the cache db)
+//      n → {height}{blockhash}                 (in the cache db)
+fn open_key(funding_height: u32, funding_txid: &FullHash) -> Bytes {
+    bincode::serialize_big(&(b'N', funding_height, funding_txid)).unwrap()
+}
+
+pub fn stats_row(
+    height: u32,
+    hash: FullHash,
+    stats: &LightningStats,
+    funding_txids: &[FullHash],
+) -> DBRow {
+    DBRow {
+        key: height_key(height),
+        value: bincode::serialize_little(&(hash, stats, funding_txids)).unwrap(),
+    }
+}
+
+fn is_best(chain: &ChainQuery, height: u32, hash: &FullHash) -> bool {
+    chain
+        .header_by_height(height as usize)
+        .map_or(false, |header| header.hash()[..] == hash[..])
+}
+
+// The stats of the best chain blocks in the given height range (inclusive) with Lightning
+// activity, along with the funding txids of the channels they closed
+fn best_stats(
+    chain: &ChainQuery,
+    start_height: usize,
+    end_height: usize,
+) -> impl Iterator<Item = (usize, LightningStats, Vec<FullHash>)> + '_ {
+    chain
+        .store()
+        .history_db()
+        .iter_scan_from(b"N", &height_key(start_height as u32))
+        .map(|row| {
+            let (_, height): (u8, u32) =
+                bincode::deserialize_big(&row.key).expect("failed to parse LightningStats key");
+            (height, row)
+        })
+        .take_while(move |(height, _)| *height as usize <= end_height)
+        .filter_map(move |(height, row)| {
+            let (hash, stats, funding_txids): (FullHash, LightningStats, Vec<FullHash>) =
+                bincode::deserialize_little(&row.value).expect("failed to parse LightningStats");
+            // skip rows left behind by orphaned blocks
+            if is_best(chain, height, &hash) {
+                Some((height as usize, stats, funding_txids))
+            } else {
+                None
+            }
+        })
+}
+
+fn funding_height(chain: &ChainQuery, funding_txid: &FullHash) -> Option<usize> {
+    let txid: Txid = deserialize(funding_txid).expect("failed to parse Txid");
+    chain
+        .tx_confirming_block(&txid)
+        .map(|blockid| blockid.height)
+}
+
+// Fold the channels closed by the blocks buried since the last run into the cache db, returning
+// the height of the first block left out
+fn catch_up(chain: &ChainQuery) -> usize {
+    let cache_db = chain.store().cache_db();
+    let state: Option<(u32, FullHash)> = cache_db
+        .get(b"n")
+        .map(|value| bincode::deserialize_little(&value).expect("failed to parse LightningStats"));
+    let next_height = match state {
+        Some((height, hash)) if is_best(chain, height, &hash) => height as usize + 1,
+        _ => 0,
+    };
+
+    let stable_height = match chain.best_height().checked_sub(CONFIRMATIONS) {
+        Some(height) if next_height <= height => height,
+        _ => return next_height,
+    };
+    let stable_hash = match chain.header_by_height(stable_height) {
+        Some(header) => full_hash(&header.hash()[..]),
+        None => return next_height, // reorged meanwhile
+    };
+    let mut rows: Vec<DBRow> = best_stats(chain, next_height, stable_height)
+        .flat_map(|(_, _, funding_txids)| funding_txids)
+        .filter_map(|funding_txid| {
+            let height = funding_height(chain, &funding_txid)?;
+            Some(DBRow {
+                key: open_key(height as u32, &funding_txid),
+                value: vec![],
+            })
+        })
+        .collect();
+    rows.push(DBRow {
+        key: b"n".to_vec(),
+        value: bincode::serialize_little(&(stable_height as u32, stable_hash)).unwrap(),
+    });
+    cache_db.write(rows, DBFlush::Enable);
+    stable_height + 1
+}
+
+#[derive(Serialize, Debug)]
+pub struct LightningWindow {
+    pub start_height: usize,
+    pub end_height: usize,
+    pub channel_opens: u32,
+    #[serde(flatten)]
+    pub stats: LightningStats,
+}
+
+// The activity of the best chain blocks in the given height range (inclusive), by windows of
+// `interval` blocks. Channel opens are attributed to the window their funding transaction
+// confirmed in, and only known for the channels that got closed since. The closes of the
+// buried blocks are looked up by the funding height they got folded in at, leaving only the
+// most recent blocks to scan up to the tip.
+pub fn windows(
+    chain: &ChainQuery,
+    start_height: usize,
+    end_height: usize,
+    interval: usize,
+) -> Vec<LightningWindow> {
+    let mut windows: Vec<LightningWindow> = (start_height..=end_height)
+        .step_by(interval)
+        .map(|window_start| LightningWindow {
+            start_height: window_start,
+            end_height: (window_start + interval - 1).min(end_height),
+            channel_opens: 0,
+            stats: LightningStats::default(),
+        })
+        .collect();
+    let window = |height: usize| (height - start_height) / interval;
+
+    let next_height = catch_up(chain);
+    for (height, stats, _) in best_stats(chain, start_height, end_height) {
+        windows[window(height)].stats.merge(&stats);
+    }
+
+    let opens = chain
+        .store()
+        .cache_db()
+        .iter_scan_from(b"N", &open_key(start_height as u32, &[0; 32]));
+    for row in opens {
+        let (_, funding_height, _): (u8, u32, FullHash) =
+            bincode::deserialize_big(&row.key).expect("failed to parse LightningStats key");
+        if funding_height as usize > end_height {
+            break;
+        }
+        windows[window(funding_height as usize)].channel_opens += 1;
+    }
+    let recent_closes = best_stats(chain, next_height, chain.best_height())
+        .flat_map(|(_, _, funding_txids)| funding_txids);
+    for funding_txid in recent_closes {
+        match funding_height(chain, &funding_txid) {
+            Some(height) if (start_height..=end_height).contains(&height) => {
+                windows[window(height)].channel_opens += 1
+            }
+            _ => (),
+        }
+    }
+    windows
+}
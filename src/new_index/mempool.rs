@@ -1,48 +1,73 @@
 use arraydeque::{ArrayDeque, Wrapping};
 use itertools::Itertools;
+use rayon::prelude::*;
 
 #[cfg(not(feature = "liquid"))]
 use bitcoin::consensus::encode::serialize;
 #[cfg(feature = "liquid")]
 use elements::{encode::serialize, AssetId};
 
-use std::collections::{BTreeSet, HashMap, HashSet};
-use std::iter::FromIterator;
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::iter::{once, FromIterator};
+use std::mem::size_of;
+use std::ops::Bound;
 use std::sync::{Arc, RwLock};
 use std::time::{Duration, Instant};
 
 use crate::chain::{deserialize, Network, OutPoint, Transaction, TxOut, Txid};
-use crate::config::Config;
+use crate::config::{Config, MempoolEviction};
 use crate::daemon::Daemon;
 use crate::errors::*;
-use crate::metrics::{GaugeVec, HistogramOpts, HistogramVec, MetricOpts, Metrics};
+use crate::metrics::{
+    Counter, Gauge, GaugeVec, HistogramOpts, HistogramVec, MetricOpts, Metrics,
+};
 use crate::new_index::{
-    compute_script_hash, schema::FullHash, ChainQuery, FundingInfo, GetAmountVal, ScriptStats,
-    SpendingInfo, SpendingInput, TxHistoryInfo, Utxo,
+    compute_script_hash, schema::FullHash, summarize_history, ChainQuery, FundingInfo,
+    GetAmountVal, ScriptChanges, ScriptStats, SpendingInfo, SpendingInput, TxHistoryInfo,
+    TxSummary, Utxo,
 };
 use crate::util::fees::{make_fee_histogram, TxFeeInfo};
 use crate::util::{extract_tx_prevouts, full_hash, has_prevout, is_spendable, Bytes};
 
 #[cfg(feature = "liquid")]
-use crate::elements::asset;
+use crate::elements::{asset, ebcompact::SizeMethod};
 
 const RECENT_TXS_SIZE: usize = 10;
+// the new transactions are indexed in parallel chunks of at least this many
+const INDEX_CHUNK_SIZE: usize = 100;
+// the memory used by the entries of a transaction in the mempool maps, besides its own size and
+// history entries
+const TX_OVERHEAD: usize = 256;
 const BACKLOG_STATS_TTL: u64 = 10;
 
 pub struct Mempool {
     chain: Arc<ChainQuery>,
     config: Arc<Config>,
     txstore: HashMap<Txid, Transaction>,
+    sorted_txids: BTreeSet<Txid>, // the txstore keys, ordered for paging through them
     feeinfo: HashMap<Txid, TxFeeInfo>,
     history: HashMap<FullHash, Vec<TxHistoryInfo>>, // ScriptHash -> {history_entries}
     edges: HashMap<OutPoint, (Txid, u32)>,          // OutPoint -> (spending_txid, spending_vin)
+    parents: HashMap<Txid, HashSet<Txid>>,          // Txid -> its mempool parents
+    children: HashMap<Txid, HashSet<Txid>>,         // Txid (confirmed or not) -> its mempool children
     recent: ArrayDeque<TxOverview, RECENT_TXS_SIZE, Wrapping>, // The N most recent txs to enter the mempool
+    large_txs: HashMap<Txid, u64>, // Txs above the --large-tx-threshold -> total output value
+    replaced: HashMap<Txid, ReplacedTx>, // Recently replaced txs, kept for --replaced-tx-retention
+    replaced_history: HashMap<FullHash, Vec<Txid>>, // ScriptHash -> recently replaced txs
     backlog_stats: (BacklogStats, Instant),
+    script_changes: ScriptChanges, // scripthashes touched since last collected
+    usage: HashMap<Txid, TxUsage>, // Txid -> its estimated memory usage
+    usage_bytes: usize,            // the estimated memory usage of the whole mempool
+    next_seq: u64,
+    eviction_order: BTreeSet<(u64, Txid)>, // (eviction key, txid), the first ones evicted first
+    evicted: HashSet<Txid>, // Txs evicted to stay within the limits, while the daemon keeps them
 
     // monitoring
     latency: HistogramVec, // mempool requests latency
     delta: HistogramVec,   // # of added/removed txs
     count: GaugeVec,       // current state of the mempool
+    memory_usage: Gauge,   // estimated memory usage of the mempool, in bytes
+    evictions: Counter,    // # of txs evicted to stay within the limits
 
     // elements only
     #[cfg(feature = "liquid")]
@@ -61,20 +86,147 @@ pub struct TxOverview {
     value: u64,
 }
 
+// The estimated memory used by a mempool transaction, and its position in the eviction order
+struct TxUsage {
+    bytes: usize,
+    eviction_key: u64,
+}
+
+// The usage of the mempool and its limits, for the admin view
+#[derive(Serialize)]
+pub struct MempoolInfo {
+    pub count: usize,
+    pub memory_usage: usize, // estimated, in bytes
+    pub limit_txs: Option<usize>,
+    pub limit_memory: Option<usize>,
+    pub eviction: &'static str,
+    pub evicted: usize,           // the evicted txs still in the daemon's mempool
+    pub evicted_total: u64,       // since startup
+    pub min_feerate: Option<f64>, // the lowest feerate of the kept txs, in sat/vb
+}
+
+// The index data of a new mempool transaction, computed ahead of adding it to the mempool
+struct IndexedTx {
+    txid: Txid,
+    feeinfo: TxFeeInfo,
+    usage: usize, // estimated memory usage, in bytes
+    #[cfg(not(feature = "liquid"))]
+    input_value: u64,
+    entries: Vec<(FullHash, TxHistoryInfo)>, // funding and spending history entries
+}
+
+impl IndexedTx {
+    fn new(
+        txid: Txid,
+        tx: &Transaction,
+        txos: &HashMap<OutPoint, TxOut>,
+        config: &Config,
+    ) -> Self {
+        let txid_bytes = full_hash(&txid[..]);
+        let prevouts = extract_tx_prevouts(tx, txos, false);
+
+        // Get feeinfo for caching and recent tx overview
+        let feeinfo = TxFeeInfo::new(tx, &prevouts, config.network_type);
+        #[cfg(not(feature = "liquid"))]
+        let input_value = prevouts
+            .values()
+            .map(|prevout| prevout.value.to_sat())
+            .sum();
+
+        // An iterator over (ScriptHash, TxHistoryInfo)
+        let spending = prevouts.into_iter().map(|(input_index, prevout)| {
+            let txi = tx.input.get(input_index as usize).unwrap();
+            (
+                compute_script_hash(&prevout.script_pubkey),
+                TxHistoryInfo::Spending(SpendingInfo {
+                    txid: txid_bytes,
+                    vin: input_index as u16,
+                    prev_txid: full_hash(&txi.previous_output.txid[..]),
+                    prev_vout: txi.previous_output.vout as u16,
+                    value: prevout.value.amount_value(),
+                }),
+            )
+        });
+
+        // An iterator over (ScriptHash, TxHistoryInfo)
+        let funding = tx
+            .output
+            .iter()
+            .enumerate()
+            .filter(|(_, txo)| is_spendable(txo) || config.index_unspendables)
+            .map(|(index, txo)| {
+                (
+                    compute_script_hash(&txo.script_pubkey),
+                    TxHistoryInfo::Funding(FundingInfo {
+                        txid: txid_bytes,
+                        vout: index as u16,
+                        value: txo.value.amount_value(),
+                    }),
+                )
+            });
+
+        let entries: Vec<(FullHash, TxHistoryInfo)> = funding.chain(spending).collect();
+        let usage = tx.total_size()
+            + entries.len() * size_of::<(FullHash, TxHistoryInfo)>()
+            + tx.input.len() * size_of::<(OutPoint, (Txid, u32))>()
+            + TX_OVERHEAD;
+
+        IndexedTx {
+            txid,
+            feeinfo,
+            usage,
+            #[cfg(not(feature = "liquid"))]
+            input_value,
+            entries,
+        }
+    }
+}
+
+// A transaction that left the mempool because it (or one of its ancestors) got replaced
+struct ReplacedTx {
+    tx: Transaction,
+    replaced_by: Txid,
+    scripthashes: HashSet<FullHash>,
+    time: Instant,
+}
+
+// A mempool transaction within the dependency graph of another one
+#[derive(Serialize)]
+pub struct TxRelative {
+    txid: Txid,
+    depth: usize, // distance from the transaction the graph was built for
+    fee: u64,
+    vsize: u64,
+    depends: Vec<Txid>, // mempool parents
+    spentby: Vec<Txid>, // mempool children
+}
+
 impl Mempool {
     pub fn new(chain: Arc<ChainQuery>, metrics: &Metrics, config: Arc<Config>) -> Self {
         Mempool {
             chain,
             config,
             txstore: HashMap::new(),
+            sorted_txids: BTreeSet::new(),
             feeinfo: HashMap::new(),
             history: HashMap::new(),
             edges: HashMap::new(),
+            parents: HashMap::new(),
+            children: HashMap::new(),
             recent: ArrayDeque::new(),
+            large_txs: HashMap::new(),
+            replaced: HashMap::new(),
+            replaced_history: HashMap::new(),
             backlog_stats: (
                 BacklogStats::default(),
                 Instant::now() - Duration::from_secs(BACKLOG_STATS_TTL),
             ),
+            script_changes: ScriptChanges::default(),
+            usage: HashMap::new(),
+            usage_bytes: 0,
+            next_seq: 0,
+            eviction_order: BTreeSet::new(),
+            evicted: HashSet::new(),
             latency: metrics.histogram_vec(
                 HistogramOpts::new("mempool_latency", "Mempool requests latency (in seconds)"),
                 &["part"],
@@ -87,6 +239,14 @@ impl Mempool {
                 MetricOpts::new("mempool_count", "# of elements currently at the mempool"),
                 &["type"],
             ),
+            memory_usage: metrics.gauge(MetricOpts::new(
+                "mempool_memory_usage",
+                "Estimated memory used by the mempool (in bytes)",
+            )),
+            evictions: metrics.counter(MetricOpts::new(
+                "mempool_evictions",
+                "# of transactions evicted to stay within the mempool limits",
+            )),
 
             #[cfg(feature = "liquid")]
             asset_history: HashMap::new(),
@@ -133,19 +293,116 @@ impl Mempool {
             .any(|txin| self.txstore.contains_key(&txin.previous_output.txid))
     }
 
+    // The mempool transactions the given one depends on, up to `max_depth` levels up
+    pub fn ancestors(&self, txid: &Txid, max_depth: usize) -> Vec<TxRelative> {
+        let _timer = self.latency.with_label_values(&["ancestors"]).start_timer();
+        self.relatives(txid, max_depth, &self.parents)
+    }
+
+    // The mempool transactions depending on the given one, up to `max_depth` levels down.
+    // The given transaction may be confirmed.
+    pub fn descendants(&self, txid: &Txid, max_depth: usize) -> Vec<TxRelative> {
+        let _timer = self
+            .latency
+            .with_label_values(&["descendants"])
+            .start_timer();
+        self.relatives(txid, max_depth, &self.children)
+    }
+
+    // Walk the graph breadth-first, so that the closest relatives come first
+    fn relatives(
+        &self,
+        txid: &Txid,
+        max_depth: usize,
+        adjacency: &HashMap<Txid, HashSet<Txid>>,
+    ) -> Vec<TxRelative> {
+        let neighbours = |adjacency: &HashMap<Txid, HashSet<Txid>>, txid: &Txid| -> Vec<Txid> {
+            adjacency
+                .get(txid)
+                .map_or_else(Vec::new, |txids| txids.iter().cloned().sorted().collect())
+        };
+
+        let mut visited: HashSet<Txid> = HashSet::new();
+        visited.insert(*txid);
+        let mut queue: VecDeque<(Txid, usize)> = VecDeque::new();
+        queue.push_back((*txid, 0));
+
+        let mut relatives = vec![];
+        while let Some((txid, depth)) = queue.pop_front() {
+            if depth > 0 {
+                let feeinfo = match self.feeinfo.get(&txid) {
+                    Some(feeinfo) => feeinfo,
+                    None => continue,
+                };
+                relatives.push(TxRelative {
+                    txid,
+                    depth,
+                    fee: feeinfo.fee,
+                    vsize: feeinfo.vsize,
+                    depends: neighbours(&self.parents, &txid),
+                    spentby: neighbours(&self.children, &txid),
+                });
+            }
+            if depth < max_depth {
+                for relative in neighbours(adjacency, &txid) {
+                    if visited.insert(relative) {
+                        queue.push_back((relative, depth + 1));
+                    }
+                }
+            }
+        }
+        relatives
+    }
+
+    // The mempool history of the scripthash, followed by its recently replaced transactions
     pub fn history(&self, scripthash: &[u8], after_txid: Option<&Txid>, limit: usize) -> Vec<Transaction> {
         let _timer = self.latency.with_label_values(&["history"]).start_timer();
-        self.history
+        let live = self
+            .history
+            .get(scripthash)
+            .into_iter()
+            .flatten()
+            .map(|e| e.get_txid())
+            .unique();
+        let replaced = self
+            .replaced_history
             .get(scripthash)
-            .map_or_else(|| vec![], |entries| self._history(entries, after_txid, limit))
+            .into_iter()
+            .flat_map(|txids| txids.iter().rev())
+            .filter(|txid| !self.txstore.contains_key(txid)) // may have been re-added since
+            .filter(|txid| self.has_prevouts(&self.replaced[*txid].tx))
+            .cloned();
+        self._history(live.chain(replaced), after_txid, limit)
+    }
+
+    fn lookup_tx_or_replaced(&self, txid: &Txid) -> Option<&Transaction> {
+        self.txstore
+            .get(txid)
+            .or_else(|| self.replaced.get(txid).map(|replaced| &replaced.tx))
     }
 
-    fn _history(&self, entries: &[TxHistoryInfo], after_txid: Option<&Txid>, limit: usize) -> Vec<Transaction> {
-        let mut txids = entries
+    // Replaced txs can outlive their unconfirmed parents, which leaves their prevouts unknown
+    fn has_prevouts(&self, tx: &Transaction) -> bool {
+        let outpoints = tx
+            .input
             .iter()
-            .map(|e| e.get_txid())
-            .unique();
+            .filter(|txin| has_prevout(txin))
+            .map(|txin| txin.previous_output)
+            .collect();
+        self.lookup_txos(&outpoints).is_ok()
+    }
 
+    // The transaction that replaced the given one, if it was replaced recently
+    pub fn replaced_by(&self, txid: &Txid) -> Option<Txid> {
+        self.replaced.get(txid).map(|replaced| replaced.replaced_by)
+    }
+
+    fn _history(
+        &self,
+        mut txids: impl Iterator<Item = Txid>,
+        after_txid: Option<&Txid>,
+        limit: usize,
+    ) -> Vec<Transaction> {
         // If after_txid is provided, skip transactions until we find it
         if let Some(after_txid) = after_txid {
             // Skip transactions until we find the one after which we want to start
@@ -155,11 +412,41 @@ impl Mempool {
         // Take the next 'limit' transactions
         txids
             .take(limit)
-            .map(|txid| self.txstore.get(&txid).expect("missing mempool tx"))
+            .map(|txid| self.lookup_tx_or_replaced(&txid).expect("missing mempool tx"))
             .cloned()
             .collect()
     }
 
+    pub fn summary(
+        &self,
+        scripthash: &[u8],
+        after_txid: Option<&Txid>,
+        limit: usize,
+    ) -> Vec<TxSummary> {
+        let _timer = self.latency.with_label_values(&["summary"]).start_timer();
+        let entries = match self.history.get(scripthash) {
+            None => return vec![],
+            Some(entries) => entries,
+        };
+        let mut summary = summarize_history(entries.iter()).into_iter();
+
+        // If after_txid is provided, skip transactions until we find it
+        if let Some(after_txid) = after_txid {
+            let _ = summary.find(|(txid, _)| txid == after_txid);
+        }
+
+        summary
+            .take(limit)
+            .map(|(txid, _value)| TxSummary {
+                txid,
+                height: 0,
+                #[cfg(not(feature = "liquid"))]
+                value: _value,
+                time: 0,
+            })
+            .collect()
+    }
+
     pub fn history_txids(&self, scripthash: &[u8], after_txid: Option<&Txid>, limit: usize) -> Vec<Txid> {
         let _timer = self
             .latency
@@ -276,12 +563,27 @@ impl Mempool {
         stats
     }
 
+    pub fn count(&self) -> usize {
+        self.txstore.len()
+    }
+
     // Get all txids in the mempool
     pub fn txids(&self) -> Vec<Txid> {
         let _timer = self.latency.with_label_values(&["txids"]).start_timer();
         self.txstore.keys().cloned().collect()
     }
 
+    // Get a page of mempool txids in txid order, starting after `after_txid`
+    pub fn txids_page(&self, after_txid: Option<&Txid>, limit: usize) -> Vec<Txid> {
+        let _timer = self.latency.with_label_values(&["txids_page"]).start_timer();
+        let start = after_txid.map_or(Bound::Unbounded, Bound::Excluded);
+        self.sorted_txids
+            .range((start, Bound::Unbounded))
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+
     // Get an overview of the most recent transactions
     pub fn recent_txs_overview(&self) -> Vec<&TxOverview> {
         // We don't bother ever deleting elements from the recent list.
@@ -290,10 +592,74 @@ impl Mempool {
         self.recent.iter().collect()
     }
 
+    // Collect the scripthashes whose mempool history changed since the last call
+    pub fn take_script_changes(&mut self) -> ScriptChanges {
+        std::mem::take(&mut self.script_changes)
+    }
+
+    // The transactions with a total output value of at least `min_value`, largest first
+    pub fn large_txs(&self, min_value: u64) -> Vec<(Txid, u64)> {
+        let mut txs: Vec<(Txid, u64)> = self
+            .large_txs
+            .iter()
+            .filter(|(_, value)| **value >= min_value)
+            .map(|(txid, value)| (*txid, *value))
+            .collect();
+        txs.sort_unstable_by(|(_, a), (_, b)| b.cmp(a));
+        txs
+    }
+
+    // The funding and spending entries of the scripthash
+    pub fn history_entries(&self, scripthash: &[u8]) -> &[TxHistoryInfo] {
+        self.history.get(scripthash).map_or(&[], |entries| &entries[..])
+    }
+
+    // The outputs of exactly `value`, by scanning the whole mempool
+    #[cfg(not(feature = "liquid"))]
+    pub fn outputs_by_value(&self, value: u64) -> Vec<OutPoint> {
+        self.txstore
+            .iter()
+            .flat_map(|(txid, tx)| {
+                tx.output
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, txout)| txout.value.to_sat() == value && is_spendable(txout))
+                    .map(move |(vout, _)| OutPoint::new(*txid, vout as u32))
+            })
+            .collect()
+    }
+
     pub fn backlog_stats(&self) -> &BacklogStats {
         &self.backlog_stats.0
     }
 
+    // The feerate (in sat/vB) of the given transactions, with their rank in the mempool: the
+    // number of transactions paying a higher feerate, regardless of their ancestors. Counted in
+    // a single pass over the mempool rather than by sorting it.
+    pub fn feerate_ranks(&self, txids: &[Txid]) -> HashMap<Txid, (usize, f64)> {
+        let mut feerates: Vec<(f64, Txid)> = txids
+            .iter()
+            .filter_map(|txid| Some((self.feeinfo.get(txid)?.fee_per_vbyte, *txid)))
+            .collect();
+        feerates.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+
+        // each mempool transaction outranks the given ones from the first with a lower feerate
+        let mut outranked = vec![0; feerates.len() + 1];
+        for feeinfo in self.feeinfo.values() {
+            let first = feerates.partition_point(|(feerate, _)| *feerate >= feeinfo.fee_per_vbyte);
+            outranked[first] += 1;
+        }
+        let mut rank = 0;
+        feerates
+            .into_iter()
+            .zip(outranked)
+            .map(|((feerate, txid), count)| {
+                rank += count;
+                (txid, (rank, feerate))
+            })
+            .collect()
+    }
+
     pub fn old_txids(&self) -> HashSet<Txid> {
         return HashSet::from_iter(self.txstore.keys().cloned());
     }
@@ -314,6 +680,137 @@ impl Mempool {
         }
     }
 
+    // Add a transaction announced by the daemon's ZMQ notifications, evicting the mempool
+    // transactions it conflicts with (and their descendants) as replaced. Transactions that are
+    // already known or confirmed are ignored, as the daemon announces the transactions of the new
+    // blocks too.
+    pub fn add_notified(&mut self, tx: Transaction) {
+        let txid = tx.txid();
+        if self.txstore.contains_key(&txid)
+            || self.evicted.contains(&txid)
+            || self.chain.tx_confirming_block(&txid).is_some()
+        {
+            return;
+        }
+        let tx = match self.without_evicted_descendants(vec![tx]).pop() {
+            Some(tx) => tx,
+            None => return,
+        };
+
+        let conflicts: HashSet<Txid> = tx
+            .input
+            .iter()
+            .filter_map(|txin| self.edges.get(&txin.previous_output))
+            .map(|(spending_txid, _vin)| *spending_txid)
+            .collect();
+        let mut to_remove = conflicts.clone();
+        for conflict in &conflicts {
+            to_remove.extend(
+                self.descendants(conflict, usize::MAX)
+                    .into_iter()
+                    .map(|relative| relative.txid),
+            );
+        }
+        let removed = if to_remove.is_empty() {
+            vec![]
+        } else {
+            self.remove(to_remove.iter().collect())
+        };
+
+        self.add(vec![tx]);
+        self.track_replaced(removed);
+        self.enforce_limits();
+        self.count
+            .with_label_values(&["txs"])
+            .set(self.txstore.len() as f64);
+        self.memory_usage.set(self.usage_bytes as i64);
+
+        if self.backlog_stats.1.elapsed() > Duration::from_secs(BACKLOG_STATS_TTL) {
+            self.update_backlog_stats();
+        }
+    }
+
+    // Evict transactions (along with their descendants) until the mempool is back within the
+    // configured limits. They're left out of the mempool for as long as the daemon keeps them.
+    fn enforce_limits(&mut self) {
+        let limit_txs = self.config.mempool_limit_txs;
+        let limit_memory = self.config.mempool_limit_memory;
+        let exceeds_limits = |txs: usize, bytes: usize| {
+            limit_txs.map_or(false, |limit| txs > limit)
+                || limit_memory.map_or(false, |limit| bytes > limit)
+        };
+        let (mut txs, mut bytes) = (self.txstore.len(), self.usage_bytes);
+        if !exceeds_limits(txs, bytes) {
+            return;
+        }
+
+        let mut to_evict: HashSet<Txid> = HashSet::new();
+        for (_, txid) in &self.eviction_order {
+            if !exceeds_limits(txs, bytes) {
+                break;
+            }
+            if to_evict.contains(txid) {
+                continue;
+            }
+            let descendants = self.descendants(txid, usize::MAX);
+            for txid in once(*txid).chain(descendants.into_iter().map(|relative| relative.txid)) {
+                if to_evict.insert(txid) {
+                    txs -= 1;
+                    bytes -= self.usage.get(&txid).map_or(0, |usage| usage.bytes);
+                }
+            }
+        }
+
+        debug!("evicting {} txs to stay within the mempool limits", to_evict.len());
+        self.evictions.inc_by(to_evict.len() as u64);
+        self.remove(to_evict.iter().collect());
+        self.evicted.extend(to_evict);
+    }
+
+    // Leave out the transactions spending the outputs of evicted ones, evicting them too
+    fn without_evicted_descendants(&mut self, mut txs: Vec<Transaction>) -> Vec<Transaction> {
+        if self.evicted.is_empty() {
+            return txs;
+        }
+        loop {
+            let count = txs.len();
+            let evicted = &mut self.evicted;
+            txs.retain(|tx| {
+                let spends_evicted = tx
+                    .input
+                    .iter()
+                    .any(|txin| evicted.contains(&txin.previous_output.txid));
+                if spends_evicted {
+                    evicted.insert(tx.txid());
+                }
+                !spends_evicted
+            });
+            if txs.len() == count {
+                return txs;
+            }
+        }
+    }
+
+    pub fn info(&self) -> MempoolInfo {
+        MempoolInfo {
+            count: self.txstore.len(),
+            memory_usage: self.usage_bytes,
+            limit_txs: self.config.mempool_limit_txs,
+            limit_memory: self.config.mempool_limit_memory,
+            eviction: match self.config.mempool_eviction {
+                MempoolEviction::LowestFeerate => "lowest-feerate",
+                MempoolEviction::Oldest => "oldest",
+            },
+            evicted: self.evicted.len(),
+            evicted_total: self.evictions.get(),
+            min_feerate: self
+                .feeinfo
+                .values()
+                .map(|feeinfo| feeinfo.fee_per_vbyte)
+                .min_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal)),
+        }
+    }
+
     fn add(&mut self, txs: Vec<Transaction>) {
         self.delta
             .with_label_values(&["add"])
@@ -326,6 +823,7 @@ impl Mempool {
             let txid = tx.txid();
             txids.push(txid);
             self.txstore.insert(txid, tx);
+            self.sorted_txids.insert(txid);
         }
         // Phase 2: index history and spend edges (can fail if some txos cannot be found)
         let txos = match self.lookup_txos(&self.get_prevouts(&txids)) {
@@ -336,64 +834,65 @@ impl Mempool {
                 return;
             }
         };
-        for txid in txids {
-            let tx = self.txstore.get(&txid).expect("missing mempool tx");
-            let txid_bytes = full_hash(&txid[..]);
-            let prevouts = extract_tx_prevouts(&tx, &txos, false);
+        // Phase 3: compute the fee info and history entries in parallel chunks, as it's CPU-heavy
+        // for the large batches of the initial sync
+        let indexed: Vec<IndexedTx> = {
+            let (txstore, config) = (&self.txstore, &self.config);
+            txids
+                .par_iter()
+                .with_min_len(INDEX_CHUNK_SIZE)
+                .map(|txid| {
+                    let tx = txstore.get(txid).expect("missing mempool tx");
+                    IndexedTx::new(*txid, tx, &txos, config)
+                })
+                .collect()
+        };
 
-            // Get feeinfo for caching and recent tx overview
-            let feeinfo = TxFeeInfo::new(&tx, &prevouts, self.config.network_type);
+        // Phase 4: apply them to the mempool index
+        for indexed_tx in indexed {
+            let txid = indexed_tx.txid;
+            let tx = self.txstore.get(&txid).expect("missing mempool tx");
 
             // recent is an ArrayDeque that automatically evicts the oldest elements
             self.recent.push_front(TxOverview {
                 txid,
-                fee: feeinfo.fee,
-                vsize: feeinfo.vsize,
+                fee: indexed_tx.feeinfo.fee,
+                vsize: indexed_tx.feeinfo.vsize,
                 #[cfg(not(feature = "liquid"))]
-                value: prevouts
-                    .values()
-                    .map(|prevout| prevout.value.to_sat())
-                    .sum(),
-            });
-
-            self.feeinfo.insert(txid, feeinfo);
-
-            // An iterator over (ScriptHash, TxHistoryInfo)
-            let spending = prevouts.into_iter().map(|(input_index, prevout)| {
-                let txi = tx.input.get(input_index as usize).unwrap();
-                (
-                    compute_script_hash(&prevout.script_pubkey),
-                    TxHistoryInfo::Spending(SpendingInfo {
-                        txid: txid_bytes,
-                        vin: input_index as u16,
-                        prev_txid: full_hash(&txi.previous_output.txid[..]),
-                        prev_vout: txi.previous_output.vout as u16,
-                        value: prevout.value.amount_value(),
-                    }),
-                )
+                value: indexed_tx.input_value,
             });
 
-            let config = &self.config;
-
-            // An iterator over (ScriptHash, TxHistoryInfo)
-            let funding = tx
-                .output
-                .iter()
-                .enumerate()
-                .filter(|(_, txo)| is_spendable(txo) || config.index_unspendables)
-                .map(|(index, txo)| {
-                    (
-                        compute_script_hash(&txo.script_pubkey),
-                        TxHistoryInfo::Funding(FundingInfo {
-                            txid: txid_bytes,
-                            vout: index as u16,
-                            value: txo.value.amount_value(),
-                        }),
-                    )
-                });
+            // evicted by increasing feerate, or in the order they entered the mempool in. the
+            // bits of non-negative floats order like the floats.
+            let eviction_key = match self.config.mempool_eviction {
+                MempoolEviction::LowestFeerate => {
+                    indexed_tx.feeinfo.fee_per_vbyte.max(0.0).to_bits()
+                }
+                MempoolEviction::Oldest => self.next_seq,
+            };
+            self.feeinfo.insert(txid, indexed_tx.feeinfo);
+            self.usage.insert(
+                txid,
+                TxUsage {
+                    bytes: indexed_tx.usage,
+                    eviction_key,
+                },
+            );
+            self.eviction_order.insert((eviction_key, txid));
+            self.usage_bytes += indexed_tx.usage;
+            self.next_seq += 1;
+
+            #[cfg(not(feature = "liquid"))]
+            if let Some(threshold) = self.config.large_tx_threshold {
+                let value = tx.output.iter().map(|txo| txo.value.to_sat()).sum::<u64>();
+                if value >= threshold {
+                    self.large_txs.insert(txid, value);
+                }
+            }
 
             // Index funding/spending history entries and spend edges
-            for (scripthash, entry) in funding.chain(spending) {
+            for (scripthash, entry) in indexed_tx.entries {
+                self.script_changes.insert(scripthash);
                 self.history
                     .entry(scripthash)
                     .or_insert_with(Vec::new)
@@ -402,6 +901,13 @@ impl Mempool {
             for (i, txi) in tx.input.iter().enumerate() {
                 self.edges.insert(txi.previous_output, (txid, i as u32));
             }
+            for txi in tx.input.iter().filter(|txin| has_prevout(txin)) {
+                let parent = txi.previous_output.txid;
+                self.children.entry(parent).or_default().insert(txid);
+                if self.txstore.contains_key(&parent) {
+                    self.parents.entry(txid).or_default().insert(parent);
+                }
+            }
 
             // Index issued assets & native asset pegins/pegouts/burns
             #[cfg(feature = "liquid")]
@@ -433,8 +939,8 @@ impl Mempool {
             .iter()
             .filter(|outpoint| !confirmed_txos.contains_key(outpoint))
             .map(|outpoint| {
-                self.txstore
-                    .get(&outpoint.txid)
+                // replaced txs may spend the outputs of other replaced txs
+                self.lookup_tx_or_replaced(&outpoint.txid)
                     .and_then(|tx| tx.output.get(outpoint.vout as usize).cloned())
                     .map(|txout| (*outpoint, txout))
                     .chain_err(|| format!("missing outpoint {:?}", outpoint))
@@ -464,26 +970,88 @@ impl Mempool {
             .collect()
     }
 
-    fn remove(&mut self, to_remove: HashSet<&Txid>) {
+    // Remove the transactions, returning them along with the scripthashes they touched
+    fn remove(&mut self, to_remove: HashSet<&Txid>) -> Vec<(Transaction, HashSet<FullHash>)> {
         self.delta
             .with_label_values(&["remove"])
             .observe(to_remove.len() as f64);
         let _timer = self.latency.with_label_values(&["remove"]).start_timer();
 
+        let mut removed = Vec::with_capacity(to_remove.len());
         for txid in &to_remove {
-            self.txstore
+            let tx = self
+                .txstore
                 .remove(*txid)
                 .unwrap_or_else(|| panic!("missing mempool tx {}", txid));
+            self.sorted_txids.remove(*txid);
+
+            for txi in tx.input.iter().filter(|txin| has_prevout(txin)) {
+                let parent = txi.previous_output.txid;
+                if let Some(siblings) = self.children.get_mut(&parent) {
+                    siblings.remove(*txid);
+                    if siblings.is_empty() {
+                        self.children.remove(&parent);
+                    }
+                }
+            }
+            self.parents.remove(*txid);
 
             self.feeinfo.remove(*txid).or_else(|| {
                 warn!("missing mempool tx feeinfo {}", txid);
                 None
             });
+            self.large_txs.remove(*txid);
+            if let Some(usage) = self.usage.remove(*txid) {
+                self.usage_bytes -= usage.bytes;
+                self.eviction_order.remove(&(usage.eviction_key, **txid));
+            }
+            removed.push(tx);
+        }
+
+        // Mempool children of removed txs may no longer have unconfirmed parents,
+        // which changes the status of their scripts too
+        let children: HashSet<Txid> = self
+            .edges
+            .iter()
+            .filter(|(outpoint, (txid, _vin))| {
+                to_remove.contains(&outpoint.txid) && !to_remove.contains(txid)
+            })
+            .map(|(_outpoint, (txid, _vin))| *txid)
+            .collect();
+
+        // The removed txs that got confirmed remain the parents of their children, but
+        // no longer as mempool parents
+        for child in &children {
+            if let Some(parents) = self.parents.get_mut(child) {
+                parents.retain(|parent| !to_remove.contains(parent));
+                if parents.is_empty() {
+                    self.parents.remove(child);
+                }
+            }
         }
 
         // TODO: make it more efficient (currently it takes O(|mempool|) time)
-        self.history.retain(|_scripthash, entries| {
-            entries.retain(|entry| !to_remove.contains(&entry.get_txid()));
+        let script_changes = &mut self.script_changes;
+        let mut removed_scripts: HashMap<Txid, HashSet<FullHash>> = HashMap::new();
+        self.history.retain(|scripthash, entries| {
+            let entries_count = entries.len();
+            if entries
+                .iter()
+                .any(|entry| children.contains(&entry.get_txid()))
+            {
+                script_changes.insert(*scripthash);
+            }
+            entries.retain(|entry| {
+                let txid = entry.get_txid();
+                if !to_remove.contains(&txid) {
+                    return true;
+                }
+                removed_scripts.entry(txid).or_default().insert(*scripthash);
+                false
+            });
+            if entries.len() != entries_count {
+                script_changes.insert(*scripthash);
+            }
             !entries.is_empty()
         });
 
@@ -496,17 +1064,128 @@ impl Mempool {
 
         self.edges
             .retain(|_outpoint, (txid, _vin)| !to_remove.contains(txid));
+
+        removed
+            .into_iter()
+            .map(|tx| {
+                let scripthashes = removed_scripts.remove(&tx.txid()).unwrap_or_default();
+                (tx, scripthashes)
+            })
+            .collect()
+    }
+
+    // Keep the removed transactions that got replaced for a while, so that histories can tell
+    // what happened to them. A transaction is replaced when another transaction, in the mempool
+    // or confirmed, now spends one of its inputs, or when it descends from a replaced transaction
+    // (which gets evicted along with it).
+    fn track_replaced(&mut self, removed: Vec<(Transaction, HashSet<FullHash>)>) {
+        let retention = self.config.replaced_tx_retention;
+        let replaced_history = &mut self.replaced_history;
+        self.replaced.retain(|txid, replaced| {
+            if replaced.time.elapsed() < retention {
+                return true;
+            }
+            for scripthash in &replaced.scripthashes {
+                if let Some(txids) = replaced_history.get_mut(scripthash) {
+                    txids.retain(|replaced_txid| replaced_txid != txid);
+                    if txids.is_empty() {
+                        replaced_history.remove(scripthash);
+                    }
+                }
+            }
+            false
+        });
+        if retention.is_zero() {
+            return;
+        }
+
+        let mut replaced_by: HashMap<Txid, Txid> = removed
+            .iter()
+            .filter_map(|(tx, _)| {
+                tx.input
+                    .iter()
+                    .filter(|txin| has_prevout(txin))
+                    .find_map(|txin| self.edges.get(&txin.previous_output))
+                    .map(|(replacement, _vin)| (tx.txid(), *replacement))
+            })
+            .collect();
+        // the ones that didn't get confirmed themselves may conflict with a confirmed transaction
+        for (tx, _) in &removed {
+            let txid = tx.txid();
+            if replaced_by.contains_key(&txid) || self.chain.tx_confirming_block(&txid).is_some() {
+                continue;
+            }
+            let conflict = tx
+                .input
+                .iter()
+                .filter(|txin| has_prevout(txin))
+                .filter_map(|txin| self.chain.lookup_spend(&txin.previous_output))
+                .find(|spend| spend.txid != txid);
+            if let Some(spend) = conflict {
+                replaced_by.insert(txid, spend.txid);
+            }
+        }
+        loop {
+            let replaced_count = replaced_by.len();
+            for (tx, _) in &removed {
+                let txid = tx.txid();
+                if replaced_by.contains_key(&txid) {
+                    continue;
+                }
+                let replacement = tx
+                    .input
+                    .iter()
+                    .find_map(|txin| replaced_by.get(&txin.previous_output.txid).cloned());
+                if let Some(replacement) = replacement {
+                    replaced_by.insert(txid, replacement);
+                }
+            }
+            if replaced_by.len() == replaced_count {
+                break;
+            }
+        }
+
+        let now = Instant::now();
+        for (tx, scripthashes) in removed {
+            let txid = tx.txid();
+            if let Some(replacement) = replaced_by.get(&txid) {
+                for scripthash in &scripthashes {
+                    self.replaced_history
+                        .entry(*scripthash)
+                        .or_default()
+                        .push(txid);
+                }
+                self.replaced.insert(
+                    txid,
+                    ReplacedTx {
+                        tx,
+                        replaced_by: *replacement,
+                        scripthashes,
+                        time: now,
+                    },
+                );
+            }
+        }
     }
 
     #[cfg(feature = "liquid")]
-    pub fn asset_history(&self, asset_id: &AssetId, limit: usize) -> Vec<Transaction> {
+    pub fn asset_history(
+        &self,
+        asset_id: &AssetId,
+        after_txid: Option<&Txid>,
+        limit: usize,
+    ) -> Vec<Transaction> {
         let _timer = self
             .latency
             .with_label_values(&["asset_history"])
             .start_timer();
-        self.asset_history
-            .get(asset_id)
-            .map_or_else(|| vec![], |entries| self._history(entries, limit))
+        self.asset_history.get(asset_id).map_or_else(
+            || vec![],
+            |entries| {
+                let txids = entries.iter().map(|e| e.get_txid()).unique();
+                self._history(txids, after_txid, limit)
+            },
+        )
     }
 
     pub fn update(mempool: &Arc<RwLock<Mempool>>, daemon: &Daemon) -> Result<()> {
@@ -521,24 +1200,54 @@ impl Mempool {
 
         // 2. Remove missing transactions. Even if we are unable to download new transactions from
         // the daemon, we still want to remove the transactions that are no longer in the mempool.
-        mempool.write().unwrap().remove(txids_to_remove);
+        let removed = {
+            let mut mempool = mempool.write().unwrap();
+            // ZMQ notifications or the limits may have evicted some of them in the meantime
+            let txids_to_remove = txids_to_remove
+                .into_iter()
+                .filter(|txid| mempool.txstore.contains_key(txid))
+                .collect();
+            mempool.remove(txids_to_remove)
+        };
 
         // 3. Download the new transactions from the daemon's mempool
-        let new_txids: Vec<&Txid> = all_txids.difference(&old_txids).collect();
+        let (config, evicted) = {
+            let mempool = mempool.read().unwrap();
+            (Arc::clone(&mempool.config), mempool.evicted.clone())
+        };
+        let new_txids: Vec<&Txid> = all_txids
+            .difference(&old_txids)
+            .filter(|txid| !evicted.contains(txid))
+            .collect();
         let txs_to_add = daemon
-            .gettransactions(&new_txids)
+            .getmempooltxs(
+                &new_txids,
+                config.mempool_fetch_batch_size,
+                config.mempool_fetch_concurrency,
+            )
             .chain_err(|| format!("failed to get {} transactions", new_txids.len()))?;
 
         // 4. Update local mempool to match daemon's state
         {
             let mut mempool = mempool.write().unwrap();
-            // Add new transactions
+            // Add new transactions, unless ZMQ notifications added them in the meantime
+            let txs_to_add = txs_to_add
+                .into_iter()
+                .filter(|tx| !mempool.txstore.contains_key(&tx.txid()))
+                .collect();
+            let txs_to_add = mempool.without_evicted_descendants(txs_to_add);
             mempool.add(txs_to_add);
+            // Tell the removed transactions that got replaced by the new ones
+            mempool.track_replaced(removed);
+            // Forget about the evicted transactions the daemon dropped too
+            mempool.evicted.retain(|txid| all_txids.contains(txid));
+            mempool.enforce_limits();
 
             mempool
                 .count
                 .with_label_values(&["txs"])
                 .set(mempool.txstore.len() as f64);
+            mempool.memory_usage.set(mempool.usage_bytes as i64);
 
             // Update cached backlog stats (if expired)
             if mempool.backlog_stats.1.elapsed() > Duration::from_secs(BACKLOG_STATS_TTL) {
@@ -1,15 +1,39 @@
+pub mod broadcast_queue;
+pub mod daily_stats;
 pub mod db;
+#[cfg(not(feature = "liquid"))]
+pub mod difficulty;
 mod fetch;
+pub mod forks;
+pub mod idempotency;
+pub mod integrity;
+#[cfg(not(feature = "liquid"))]
+pub mod lightning;
 mod mempool;
 pub mod precache;
+pub mod price;
 mod query;
+pub mod rebroadcast;
+pub mod reindex;
 pub mod schema;
+#[cfg(not(feature = "liquid"))]
+pub mod utxo_deltas;
+#[cfg(not(feature = "liquid"))]
+pub mod utxo_histogram;
+#[cfg(not(feature = "liquid"))]
+pub mod utxo_types;
+#[cfg(not(feature = "liquid"))]
+pub mod version_bits;
+pub mod watch_lists;
 
-pub use self::db::{DBRow, DB};
+pub use self::db::{DBRow, Deadline, ReadScope, ReadStats, DB};
 pub use self::fetch::{BlockEntry, FetchFrom};
-pub use self::mempool::Mempool;
+pub use self::mempool::{Mempool, TxRelative};
 pub use self::query::Query;
+#[cfg(not(feature = "liquid"))]
+pub use self::query::GraphDirection;
 pub use self::schema::{
-    compute_script_hash, parse_hash, ChainQuery, FundingInfo, GetAmountVal, Indexer, ScriptStats,
-    SpendingInfo, SpendingInput, Store, TxHistoryInfo, TxHistoryKey, TxHistoryRow, Utxo,
+    compute_script_hash, parse_hash, summarize_history, ChainQuery, FundingInfo, GetAmountVal,
+    Indexer, RevealedScript, ScriptChanges, ScriptStats, SpendingInfo, SpendingInput, Store,
+    TxHistoryInfo, TxHistoryKey, TxHistoryRow, TxSummary, Utxo,
 };
@@ -30,7 +30,9 @@ pub fn precache(chain: &ChainQuery, scripthashes: Vec<FullHash>) {
                 if i % 5 == 0 {
                     info!("running pre-cache for scripthash {}/{}", i + 1, total);
                 }
-                chain.stats(&scripthash[..]);
+                if let Err(e) = chain.stats(&scripthash[..]) {
+                    warn!("failed pre-caching scripthash {}: {}", i + 1, e);
+                }
                 //chain.utxo(&scripthash[..]);
             })
     });
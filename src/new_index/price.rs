@@ -0,0 +1,114 @@
+use std::collections::HashMap;
+use std::fs;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use url::Url;
+
+use crate::errors::*;
+use crate::new_index::db::{DBFlush, DBRow};
+use crate::new_index::ChainQuery;
+use crate::util::{bincode, http_get, spawn_thread};
+
+// The price of one coin in each currency (by upper-case code), as of `time`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PricePoint {
+    #[serde(default)]
+    pub time: u32, // defaults to the time the point was fetched at
+    pub rates: HashMap<String, f64>,
+}
+
+impl PricePoint {
+    pub fn rate(&self, currency: &str) -> Option<f64> {
+        self.rates.get(&currency.to_uppercase()).copied()
+    }
+}
+
+// Feeds serve either the current rates or a history of them
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Feed {
+    Current(PricePoint),
+    History(Vec<PricePoint>),
+}
+
+// persist the fetched rates, keyed by their time:
+//      P{time} → {rates}
+fn price_key(time: u32) -> Vec<u8> {
+    bincode::serialize_big(&(b'P', time)).unwrap()
+}
+
+// The rates as of `time`, i.e. the most recent ones fetched for a time up to it
+pub fn price_at(chain: &ChainQuery, time: u32) -> Option<PricePoint> {
+    chain
+        .store()
+        .cache_db()
+        .iter_scan_reverse(b"P", &price_key(time))
+        .next()
+        .map(|row| {
+            let (_, time): (u8, u32) =
+                bincode::deserialize_big(&row.key).expect("failed to parse PricePoint key");
+            let rates = bincode::deserialize_little(&row.value).expect("failed to parse PricePoint");
+            PricePoint { time, rates }
+        })
+}
+
+pub fn latest_price(chain: &ChainQuery) -> Option<PricePoint> {
+    price_at(chain, u32::MAX)
+}
+
+// Fetch the rates from the feed and add them to the persisted ones
+pub fn update(chain: &ChainQuery, source: &str) {
+    let points = match fetch(source) {
+        Ok(points) => points,
+        Err(e) => {
+            warn!("failed fetching prices from {}: {}", source, e);
+            return;
+        }
+    };
+    debug!("fetched {} price points from {}", points.len(), source);
+    let rows = points
+        .iter()
+        .map(|point| DBRow {
+            key: price_key(point.time),
+            value: bincode::serialize_little(&point.rates).unwrap(),
+        })
+        .collect();
+    chain.store().cache_db().write(rows, DBFlush::Enable);
+}
+
+fn fetch(source: &str) -> Result<Vec<PricePoint>> {
+    let body = if source.starts_with("http://") || source.starts_with("https://") {
+        http_get(&Url::parse(source).chain_err(|| "invalid price feed url")?)?
+    } else {
+        fs::read_to_string(source).chain_err(|| format!("failed to read {}", source))?
+    };
+    let points = match serde_json::from_str(&body).chain_err(|| "invalid price feed")? {
+        Feed::Current(point) => vec![point],
+        Feed::History(points) => points,
+    };
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs() as u32);
+    Ok(points
+        .into_iter()
+        .map(|point| PricePoint {
+            time: if point.time == 0 { now } else { point.time },
+            rates: point
+                .rates
+                .into_iter()
+                .map(|(currency, rate)| (currency.to_uppercase(), rate))
+                .collect(),
+        })
+        .collect())
+}
+
+// Keep fetching the rates in the background
+pub fn spawn(chain: Arc<ChainQuery>, source: String, interval: Duration) -> thread::JoinHandle<()> {
+    spawn_thread("price-feed", move || loop {
+        update(&chain, &source);
+        thread::sleep(interval);
+    })
+}
@@ -4,28 +4,192 @@ use std::collections::{BTreeSet, HashMap};
 use std::sync::{Arc, RwLock, RwLockReadGuard};
 use std::time::{Duration, Instant};
 
-use crate::chain::{Network, OutPoint, Transaction, TxOut, Txid};
+use crate::chain::{deserialize, Network, OutPoint, Transaction, TxOut, Txid, Value};
 use crate::config::Config;
 use crate::daemon::Daemon;
 use crate::errors::*;
-use crate::new_index::{ChainQuery, Mempool, ScriptStats, SpendingInput, Utxo};
-use crate::util::{is_spendable, BlockId, Bytes, TransactionStatus};
+use crate::new_index::broadcast_queue::{self, QueuedBroadcast};
+use crate::new_index::idempotency::{IdempotentBroadcasts, Reserved};
+use crate::new_index::rebroadcast;
+use crate::new_index::reindex::{ReindexJobs, ReindexStatus};
+use crate::new_index::watch_lists::WatchLists;
+use crate::new_index::{
+    ChainQuery, Deadline, Mempool, ReadScope, ScriptChanges, ScriptStats, SpendingInput,
+    TxRelative, Utxo,
+};
+use crate::util::{
+    is_spendable, AuditLog, BlockId, Bytes, RequestBudget, SlowQueryLog, TransactionStatus,
+};
+
+use hex::FromHex;
+
+#[cfg(not(feature = "liquid"))]
+use crate::{
+    new_index::{compute_script_hash, TxHistoryInfo},
+    util::{has_prevout, FullHash},
+};
+#[cfg(not(feature = "liquid"))]
+use bitcoin::hashes::{sha256, Hash};
+#[cfg(not(feature = "liquid"))]
+use std::collections::{HashSet, VecDeque};
 
 
 
 #[cfg(feature = "liquid")]
 use crate::{
     chain::AssetId,
-    elements::{lookup_asset, AssetRegistry, AssetSorting, LiquidAsset},
+    elements::{
+        asset::{self, AssetHolders},
+        lookup_asset,
+        peg::{self, PegValue},
+        AssetMeta, AssetRegistry, AssetSorting, LiquidAsset,
+    },
 };
 
 const FEE_ESTIMATES_TTL: u64 = 60; // seconds
+#[cfg(not(feature = "liquid"))]
+const MINING_TEMPLATE_TTL: u64 = 2; // seconds
 
 const CONF_TARGETS: [u16; 28] = [
     1u16, 2u16, 3u16, 4u16, 5u16, 6u16, 7u16, 8u16, 9u16, 10u16, 11u16, 12u16, 13u16, 14u16, 15u16,
     16u16, 17u16, 18u16, 19u16, 20u16, 21u16, 22u16, 23u16, 24u16, 25u16, 144u16, 504u16, 1008u16,
 ];
 
+// Recent activity of a scripthash, as returned by Query::activity()
+#[cfg(not(feature = "liquid"))]
+#[derive(Default, Debug)]
+pub struct AddressActivity {
+    pub tx_count: usize,
+    pub received_count: usize,
+    pub sent_count: usize,
+    pub received_value: u64,
+    pub sent_value: u64,
+    pub counterparty_count: usize,
+    pub truncated: bool,
+}
+
+// A funding or spending event of a scripthash, as returned by Query::history_delta()
+#[cfg(not(feature = "liquid"))]
+#[derive(Serialize, Debug)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum HistoryEvent {
+    Funding {
+        txid: Txid,
+        vout: u32,
+        value: u64,
+        height: Option<usize>, // None for unconfirmed events
+    },
+    Spending {
+        txid: Txid,
+        vin: u32,
+        prev_txid: Txid,
+        prev_vout: u32,
+        value: u64,
+        height: Option<usize>,
+    },
+}
+
+#[cfg(not(feature = "liquid"))]
+impl HistoryEvent {
+    fn new(info: &TxHistoryInfo, height: Option<usize>) -> Self {
+        let parse_txid = |txid: &FullHash| deserialize(txid).expect("cannot parse Txid");
+        match info {
+            TxHistoryInfo::Funding(info) => HistoryEvent::Funding {
+                txid: parse_txid(&info.txid),
+                vout: info.vout as u32,
+                value: info.value,
+                height,
+            },
+            TxHistoryInfo::Spending(info) => HistoryEvent::Spending {
+                txid: parse_txid(&info.txid),
+                vin: info.vin as u32,
+                prev_txid: parse_txid(&info.prev_txid),
+                prev_vout: info.prev_vout as u32,
+                value: info.value,
+                height,
+            },
+        }
+    }
+}
+
+// Identifies the contents of a block template: the block it builds on and its transactions, but
+// not its time
+#[cfg(not(feature = "liquid"))]
+fn template_hash(template: &serde_json::Value) -> String {
+    let mut data = template["previousblockhash"].as_str().unwrap_or("").to_string();
+    for tx in template["transactions"].as_array().into_iter().flatten() {
+        data.push_str(tx["txid"].as_str().unwrap_or(""));
+    }
+    sha256::Hash::hash(data.as_bytes()).to_string()
+}
+
+#[cfg(not(feature = "liquid"))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GraphDirection {
+    Upstream,   // towards the transactions funding the inputs
+    Downstream, // towards the transactions spending the outputs
+}
+
+// The spending relationships around a transaction, as returned by Query::tx_graph()
+#[cfg(not(feature = "liquid"))]
+#[derive(Serialize, Debug)]
+pub struct TxGraph {
+    pub nodes: Vec<TxGraphNode>,
+    pub edges: Vec<TxGraphEdge>,
+    pub truncated: bool, // whether the node or edge limits cut the traversal short
+}
+
+#[cfg(not(feature = "liquid"))]
+#[derive(Serialize, Debug)]
+pub struct TxGraphNode {
+    pub txid: Txid,
+    pub depth: usize, // distance from the transaction the graph was built for
+    pub status: TransactionStatus,
+}
+
+// The `txid:vout` output, spent by the `spent_by:vin` input
+#[cfg(not(feature = "liquid"))]
+#[derive(Serialize, Debug)]
+pub struct TxGraphEdge {
+    pub txid: Txid,
+    pub vout: u32,
+    pub value: u64,
+    pub spent_by: Txid,
+    pub vin: u32,
+}
+
+// The paths value took from one scripthash to another, as returned by Query::flow()
+#[cfg(not(feature = "liquid"))]
+#[derive(Serialize, Debug)]
+pub struct Flow {
+    pub paths: Vec<FlowPath>,
+    pub truncated: bool, // whether the node or path limits cut the search short
+}
+
+// The outputs followed from the transaction spending the source scripthash, the last one of
+// which pays to the destination
+#[cfg(not(feature = "liquid"))]
+#[derive(Serialize, Debug)]
+pub struct FlowPath {
+    pub hops: usize,
+    pub outputs: Vec<FlowOutput>,
+}
+
+#[cfg(not(feature = "liquid"))]
+#[derive(Serialize, Debug, Clone)]
+pub struct FlowOutput {
+    pub txid: Txid,
+    pub vout: u32,
+    pub value: u64,
+}
+
+// A broadcasted transaction, either submitted to the daemon or queued for later while it's
+// unreachable (with --broadcast-queue)
+pub struct Broadcast {
+    pub txid: Txid,
+    pub queued: bool,
+}
+
 pub struct Query {
     chain: Arc<ChainQuery>, // TODO: should be used as read-only
     mempool: Arc<RwLock<Mempool>>,
@@ -33,6 +197,14 @@ pub struct Query {
     config: Arc<Config>,
     cached_estimates: RwLock<(HashMap<u16, f64>, Option<Instant>)>,
     cached_relayfee: RwLock<Option<f64>>,
+    #[cfg(not(feature = "liquid"))]
+    cached_template: RwLock<Option<(serde_json::Value, Instant)>>,
+    slow_query_log: Option<SlowQueryLog>,
+    audit_log: Option<AuditLog>,
+    request_budget: Option<RequestBudget>,
+    reindex_jobs: ReindexJobs,
+    idempotent_broadcasts: IdempotentBroadcasts,
+    watch_lists: WatchLists,
     #[cfg(feature = "liquid")]
     asset_db: Option<Arc<RwLock<AssetRegistry>>>,
 }
@@ -45,13 +217,24 @@ impl Query {
         daemon: Arc<Daemon>,
         config: Arc<Config>,
     ) -> Self {
+        let slow_query_log = SlowQueryLog::new(&config);
+        let audit_log = AuditLog::new(&config);
+        let request_budget = RequestBudget::new(&config);
+        let watch_lists = WatchLists::load(&chain);
         Query {
             chain,
             mempool,
             daemon,
             config,
+            slow_query_log,
+            audit_log,
+            request_budget,
+            reindex_jobs: ReindexJobs::default(),
+            idempotent_broadcasts: IdempotentBroadcasts::default(),
+            watch_lists,
             cached_estimates: RwLock::new((HashMap::new(), None)),
             cached_relayfee: RwLock::new(None),
+            cached_template: RwLock::new(None),
         }
     }
 
@@ -63,16 +246,102 @@ impl Query {
         &self.config
     }
 
+    pub fn slow_query_log(&self) -> Option<&SlowQueryLog> {
+        self.slow_query_log.as_ref()
+    }
+
+    pub fn audit_log(&self) -> Option<&AuditLog> {
+        self.audit_log.as_ref()
+    }
+
+    // Start reindexing the scripthash in the background, None if another reindexing is running
+    pub fn start_reindex(
+        &self,
+        scripthash: &[u8],
+        scripthash_hex: &str,
+        dry_run: bool,
+    ) -> Option<ReindexStatus> {
+        self.reindex_jobs.start(
+            Arc::clone(&self.chain),
+            scripthash.to_vec(),
+            scripthash_hex.to_string(),
+            self.config.index_unspendables,
+            dry_run,
+        )
+    }
+
+    pub fn reindex_status(&self, id: u64) -> Option<ReindexStatus> {
+        self.reindex_jobs.status(id)
+    }
+
+    pub fn reindex_jobs(&self) -> Vec<ReindexStatus> {
+        self.reindex_jobs.list()
+    }
+
+    // Reserve the idempotency key of the client for a broadcast, unless it was used already
+    pub fn reserve_broadcast(&self, client: &str, key: &str) -> Reserved<'_> {
+        self.idempotent_broadcasts.reserve(&self.chain, client, key)
+    }
+
+    pub fn request_budget(&self) -> Option<&RequestBudget> {
+        self.request_budget.as_ref()
+    }
+
     pub fn network(&self) -> Network {
         self.config.network_type
     }
 
+    pub fn watch_lists(&self) -> &WatchLists {
+        &self.watch_lists
+    }
+
     pub fn mempool(&self) -> RwLockReadGuard<Mempool> {
         self.mempool.read().unwrap()
     }
 
-    pub fn broadcast_raw(&self, txhex: &str) -> Result<Txid> {
-        let txid = self.daemon.broadcast_raw(txhex)?;
+    // Collect the scripthashes affected by new blocks and mempool changes since the last call,
+    // notifying the watch lists of them
+    pub fn take_script_changes(&self) -> ScriptChanges {
+        let mut changes = self.chain.take_script_changes();
+        changes.extend(self.mempool.write().unwrap().take_script_changes());
+        self.watch_lists.notify(&changes);
+        changes
+    }
+
+    // Broadcast the transaction, tracking it for rebroadcasts until it confirms if `track` is set
+    pub fn broadcast_raw(&self, txhex: &str, track: bool) -> Result<Broadcast> {
+        let txid = if self.config.broadcast_queue {
+            match self.submit_raw(txhex, false) {
+                Err(Error(ErrorKind::Connection(msg), _)) => {
+                    let tx: Transaction =
+                        deserialize(&Vec::<u8>::from_hex(txhex).chain_err(|| "non-hex tx")?)
+                            .chain_err(|| "failed to parse tx")?;
+                    let txid = tx.txid();
+                    warn!("daemon unreachable, queueing transaction {}: {}", txid, msg);
+                    broadcast_queue::enqueue(&self.chain, &txid, txhex, msg);
+                    return Ok(Broadcast { txid, queued: true });
+                }
+                result => result?,
+            }
+        } else {
+            self.submit_raw(txhex, true)?
+        };
+        if track && self.config.rebroadcast_after.is_some() {
+            rebroadcast::track(&self.chain, &txid, txhex);
+        }
+        Ok(Broadcast {
+            txid,
+            queued: false,
+        })
+    }
+
+    // Submit the transaction to the daemon, waiting for it to become reachable if `wait` is set
+    pub fn submit_raw(&self, txhex: &str, wait: bool) -> Result<Txid> {
+        let txid = if wait {
+            self.daemon.broadcast_raw(txhex)?
+        } else {
+            self.daemon.try_broadcast_raw(txhex)?
+        };
         self.mempool
             .write()
             .unwrap()
@@ -80,6 +349,10 @@ impl Query {
         Ok(txid)
     }
 
+    pub fn broadcast_status(&self, txid: &Txid) -> Option<QueuedBroadcast> {
+        broadcast_queue::status(&self.chain, txid)
+    }
+
     pub fn utxo(&self, scripthash: &[u8]) -> Result<Vec<Utxo>> {
         let mut utxos = self.chain.utxo(scripthash, self.config.utxos_limit)?;
         let mempool = self.mempool();
@@ -88,6 +361,156 @@ impl Query {
         Ok(utxos)
     }
     
+    // Like `utxo`, truncated to the first `--utxos-limit` confirmed UTXOs rather than failing
+    // past it, along with the cursor to resume from with `utxo_with_cursor`. The unconfirmed
+    // UTXOs are left for the last page.
+    pub fn utxo_truncated(&self, scripthash: &[u8]) -> Result<(Vec<Utxo>, Option<(Txid, u32)>)> {
+        let (mut utxos, next_cursor) = self
+            .chain
+            .utxo_truncated(scripthash, self.config.utxos_limit)?;
+        let mempool = self.mempool();
+        utxos.retain(|utxo| !mempool.has_spend(&OutPoint::from(utxo)));
+        if next_cursor.is_none() {
+            utxos.extend(mempool.utxo(scripthash));
+        }
+        Ok((utxos, next_cursor))
+    }
+
+    pub fn utxo_values(&self, scripthash: &[u8]) -> Result<Vec<(OutPoint, Value)>> {
+        let mut utxos = self.chain.utxo_values(scripthash)?;
+        let mempool = self.mempool();
+        utxos.retain(|(outpoint, _)| !mempool.has_spend(outpoint));
+        utxos.extend(
+            mempool
+                .utxo(scripthash)
+                .into_iter()
+                .map(|utxo| (OutPoint::from(&utxo), utxo.value)),
+        );
+        Ok(utxos)
+    }
+
+    // Like `utxo_values`, along with the confirmation height of the outputs (None for the
+    // unconfirmed ones)
+    pub fn utxo_values_with_height(
+        &self,
+        scripthash: &[u8],
+    ) -> Result<Vec<(OutPoint, Option<usize>, Value)>> {
+        let mut utxos: Vec<_> = self
+            .chain
+            .utxo_values_with_height(scripthash)?
+            .into_iter()
+            .map(|(outpoint, height, value)| (outpoint, Some(height), value))
+            .collect();
+        let mempool = self.mempool();
+        utxos.retain(|(outpoint, _, _)| !mempool.has_spend(outpoint));
+        utxos.extend(
+            mempool
+                .utxo(scripthash)
+                .into_iter()
+                .map(|utxo| (OutPoint::from(&utxo), None, utxo.value)),
+        );
+        Ok(utxos)
+    }
+
+    // Summarize the transactions of a scripthash since `since_time`, looking at up to `max_txs`
+    // of its most recent ones (unconfirmed first)
+    #[cfg(not(feature = "liquid"))]
+    pub fn activity(&self, scripthash: &[u8], since_time: u32, max_txs: usize) -> AddressActivity {
+        let mut txs = self.mempool().summary(scripthash, None, max_txs + 1);
+        if txs.len() <= max_txs {
+            let limit = max_txs + 1 - txs.len();
+            txs.extend(self.chain.summary_since(scripthash, since_time, limit));
+        }
+        let truncated = txs.len() > max_txs;
+        txs.truncate(max_txs);
+
+        let mut activity = AddressActivity {
+            tx_count: txs.len(),
+            truncated,
+            ..Default::default()
+        };
+        // the counterparties are the recipients of sent txs and the senders of received txs
+        let mut counterparties: HashSet<FullHash> = HashSet::new();
+        let mut funding_outpoints: BTreeSet<OutPoint> = BTreeSet::new();
+        for summary in &txs {
+            let tx = match self.lookup_txn(&summary.txid) {
+                Some(tx) => tx,
+                None => continue,
+            };
+            if summary.value > 0 {
+                activity.received_count += 1;
+                activity.received_value += summary.value as u64;
+                funding_outpoints.extend(
+                    tx.input
+                        .iter()
+                        .filter(|txin| has_prevout(txin))
+                        .map(|txin| txin.previous_output),
+                );
+            } else if summary.value < 0 {
+                activity.sent_count += 1;
+                activity.sent_value += summary.value.unsigned_abs();
+                counterparties.extend(
+                    tx.output
+                        .iter()
+                        .filter(|txout| is_spendable(txout))
+                        .map(|txout| compute_script_hash(&txout.script_pubkey)),
+                );
+            }
+        }
+        counterparties.extend(
+            self.lookup_txos(&funding_outpoints)
+                .values()
+                .map(|txout| compute_script_hash(&txout.script_pubkey)),
+        );
+        counterparties.remove(scripthash);
+        activity.counterparty_count = counterparties.len();
+        activity
+    }
+
+    // The funding and spending events of a scripthash confirmed at or after `since_height`,
+    // oldest first, followed by the unconfirmed ones. When there are more than `max_events` of
+    // them, only the events of the first blocks are returned, along with the height to resume
+    // from. Fails when a single block, or the mempool, has more than `max_events` events.
+    #[cfg(not(feature = "liquid"))]
+    pub fn history_delta(
+        &self,
+        scripthash: &[u8],
+        since_height: usize,
+        max_events: usize,
+    ) -> Result<(Vec<HistoryEvent>, Option<usize>)> {
+        let confirmed = self.chain.history_since(scripthash, since_height, max_events + 1);
+        let to_events = |entries: &[(TxHistoryInfo, BlockId)]| -> Vec<HistoryEvent> {
+            entries
+                .iter()
+                .map(|(info, blockid)| HistoryEvent::new(info, Some(blockid.height)))
+                .collect()
+        };
+        if confirmed.len() > max_events {
+            // cut at a block boundary, so that resuming from the next height misses nothing
+            let next_height = confirmed[max_events].1.height;
+            let complete = confirmed
+                .iter()
+                .take_while(|(_, blockid)| blockid.height < next_height)
+                .count();
+            if complete == 0 {
+                bail!(ErrorKind::TooPopular)
+            }
+            return Ok((to_events(&confirmed[..complete]), Some(next_height)));
+        }
+        let mut events = to_events(&confirmed);
+        let mempool = self.mempool();
+        let unconfirmed = mempool.history_entries(scripthash);
+        if events.len() + unconfirmed.len() > max_events {
+            // the unconfirmed events are left for the next call
+            return match confirmed.last() {
+                Some((_, blockid)) => Ok((events, Some(blockid.height + 1))),
+                None => bail!(ErrorKind::TooPopular),
+            };
+        }
+        events.extend(unconfirmed.iter().map(|info| HistoryEvent::new(info, None)));
+        Ok((events, None))
+    }
+
     pub fn utxo_paginated(&self, scripthash: &[u8], start_index: usize, limit: usize) -> Result<(Vec<Utxo>, usize)> {
         // Get paginated UTXOs from the chain with the total count
         let (mut chain_utxos, total_chain_count) = self.chain.utxo_paginated(scripthash, start_index, limit)?;
@@ -198,11 +621,11 @@ impl Query {
         confirmed_txids.chain(mempool_txids).collect()
     }
 
-    pub fn stats(&self, scripthash: &[u8]) -> (ScriptStats, ScriptStats) {
-        (
-            self.chain.stats(scripthash),
+    pub fn stats(&self, scripthash: &[u8]) -> Result<(ScriptStats, ScriptStats)> {
+        Ok((
+            self.chain.stats(scripthash)?,
             self.mempool().stats(scripthash),
-        )
+        ))
     }
 
     pub fn lookup_txn(&self, txid: &Txid) -> Option<Transaction> {
@@ -229,23 +652,47 @@ impl Query {
             .or_else(|| self.mempool().lookup_spend(outpoint))
     }
 
-    pub fn lookup_tx_spends(&self, tx: Transaction) -> Vec<Option<SpendingInput>> {
-        let txid = tx.txid();
+    // The transaction that spent the outpoint in a recently orphaned block, if any
+    #[cfg(not(feature = "liquid"))]
+    pub fn lookup_orphaned_spend(&self, outpoint: &OutPoint) -> Option<Txid> {
+        match self.config.orphaned_spend_window {
+            0 => None,
+            window => self.chain.lookup_orphaned_spend(outpoint, window),
+        }
+    }
 
-        tx.output
+    pub fn lookup_tx_outputs(&self, txid: &Txid) -> Option<Vec<TxOut>> {
+        self.chain
+            .lookup_txn_outputs(txid)
+            .or_else(|| self.mempool().lookup_txn(txid).map(|tx| tx.output))
+    }
+
+    pub fn lookup_tx_spends(
+        &self,
+        txid: &Txid,
+        outputs: &[TxOut],
+    ) -> Result<Vec<Option<SpendingInput>>> {
+        let scope = ReadScope::capture();
+        let spends: Vec<Option<SpendingInput>> = outputs
             .par_iter()
             .enumerate()
             .map(|(vout, txout)| {
-                if is_spendable(txout) {
-                    self.lookup_spend(&OutPoint {
-                        txid,
-                        vout: vout as u32,
-                    })
-                } else {
-                    None
-                }
+                scope.run(|| {
+                    if is_spendable(txout) {
+                        self.lookup_spend(&OutPoint {
+                            txid: *txid,
+                            vout: vout as u32,
+                        })
+                    } else {
+                        None
+                    }
+                })
             })
-            .collect()
+            .collect();
+        drop(scope);
+        // spends cut short by the deadline would otherwise show up as unspent outputs
+        Deadline::check()?;
+        Ok(spends)
     }
 
     pub fn get_tx_status(&self, txid: &Txid) -> TransactionStatus {
@@ -260,6 +707,236 @@ impl Query {
         self.mempool().has_unconfirmed_parents(txid)
     }
 
+    pub fn tx_ancestors(&self, txid: &Txid, max_depth: usize) -> Vec<TxRelative> {
+        self.mempool().ancestors(txid, max_depth)
+    }
+
+    pub fn tx_descendants(&self, txid: &Txid, max_depth: usize) -> Vec<TxRelative> {
+        self.mempool().descendants(txid, max_depth)
+    }
+
+    // Walk the spending relationships of a transaction breadth-first, up to `max_depth` levels
+    // away, following only the outputs worth at least `min_value`. Confirmed and unconfirmed
+    // transactions alike.
+    #[cfg(not(feature = "liquid"))]
+    pub fn tx_graph(
+        &self,
+        txid: &Txid,
+        direction: GraphDirection,
+        max_depth: usize,
+        min_value: u64,
+        max_nodes: usize,
+        max_edges: usize,
+    ) -> Result<TxGraph> {
+        let mut graph = TxGraph {
+            nodes: vec![],
+            edges: vec![],
+            truncated: false,
+        };
+        let mut visited: HashSet<Txid> = HashSet::new();
+        visited.insert(*txid);
+        let mut queue: VecDeque<(Txid, usize)> = VecDeque::new();
+        queue.push_back((*txid, 0));
+
+        while let Some((txid, depth)) = queue.pop_front() {
+            graph.nodes.push(TxGraphNode {
+                txid,
+                depth,
+                status: self.get_tx_status(&txid),
+            });
+            if depth == max_depth {
+                continue;
+            }
+            for edge in self.tx_graph_edges(&txid, direction)? {
+                if edge.value < min_value {
+                    continue;
+                }
+                if graph.edges.len() >= max_edges {
+                    graph.truncated = true;
+                    break;
+                }
+                let next = match direction {
+                    GraphDirection::Upstream => edge.txid,
+                    GraphDirection::Downstream => edge.spent_by,
+                };
+                if !visited.contains(&next) {
+                    if visited.len() >= max_nodes {
+                        graph.truncated = true;
+                        continue;
+                    }
+                    visited.insert(next);
+                    queue.push_back((next, depth + 1));
+                }
+                graph.edges.push(edge);
+            }
+        }
+        Ok(graph)
+    }
+
+    // The outputs spent by the transaction's inputs (upstream), or its outputs that got spent
+    // (downstream)
+    #[cfg(not(feature = "liquid"))]
+    fn tx_graph_edges(
+        &self,
+        txid: &Txid,
+        direction: GraphDirection,
+    ) -> Result<Vec<TxGraphEdge>> {
+        match direction {
+            GraphDirection::Upstream => {
+                let tx = match self.lookup_txn(txid) {
+                    Some(tx) => tx,
+                    None => return Ok(vec![]),
+                };
+                let outpoints: BTreeSet<OutPoint> = tx
+                    .input
+                    .iter()
+                    .filter(|txin| has_prevout(txin))
+                    .map(|txin| txin.previous_output)
+                    .collect();
+                let prevouts = self.lookup_txos(&outpoints);
+                Ok(tx
+                    .input
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(vin, txin)| {
+                        let prevout = prevouts.get(&txin.previous_output)?;
+                        Some(TxGraphEdge {
+                            txid: txin.previous_output.txid,
+                            vout: txin.previous_output.vout,
+                            value: prevout.value.to_sat(),
+                            spent_by: *txid,
+                            vin: vin as u32,
+                        })
+                    })
+                    .collect())
+            }
+            GraphDirection::Downstream => {
+                let outputs = self.lookup_tx_outputs(txid).unwrap_or_default();
+                Ok(self
+                    .lookup_tx_spends(txid, &outputs)?
+                    .into_iter()
+                    .zip(outputs)
+                    .enumerate()
+                    .filter_map(|(vout, (spend, txout))| {
+                        let spend = spend?;
+                        Some(TxGraphEdge {
+                            txid: *txid,
+                            vout: vout as u32,
+                            value: txout.value.to_sat(),
+                            spent_by: spend.txid,
+                            vin: spend.vin,
+                        })
+                    })
+                    .collect())
+            }
+        }
+    }
+
+    // Search the spends of the outputs of `from` breadth-first for outputs paying to `to`,
+    // through up to `max_hops` transactions. Only the shortest path to each transaction is
+    // kept, and the outputs paying to `to` are not followed any further.
+    #[cfg(not(feature = "liquid"))]
+    pub fn flow(
+        &self,
+        from: &[u8],
+        to: &[u8],
+        max_hops: usize,
+        max_nodes: usize,
+        max_paths: usize,
+    ) -> Result<Flow> {
+        let mut flow = Flow {
+            paths: vec![],
+            truncated: false,
+        };
+
+        // the transactions spending from the source are the first hops
+        let spender = |info: &TxHistoryInfo| -> Option<Txid> {
+            match info {
+                TxHistoryInfo::Spending(info) => {
+                    Some(deserialize(&info.txid).expect("cannot parse Txid"))
+                }
+                TxHistoryInfo::Funding(_) => None,
+            }
+        };
+        let mut visited: HashSet<Txid> = HashSet::new();
+        let mut queue: VecDeque<(Txid, usize)> = VecDeque::new();
+        {
+            let confirmed = self.chain.history_since(from, 0, max_nodes + 1);
+            // the funding rows count toward the limit too, and the spends past it are left out
+            if confirmed.len() > max_nodes {
+                flow.truncated = true;
+            }
+            let mempool = self.mempool();
+            let entries = confirmed.iter().map(|(info, _)| info);
+            for txid in entries.chain(mempool.history_entries(from)).filter_map(spender) {
+                if visited.len() >= max_nodes {
+                    flow.truncated = true;
+                    break;
+                }
+                if visited.insert(txid) {
+                    queue.push_back((txid, 1));
+                }
+            }
+        }
+
+        // the output each transaction was reached through, by the transaction spending it
+        let mut parents: HashMap<Txid, FlowOutput> = HashMap::new();
+
+        while let Some((txid, hops)) = queue.pop_front() {
+            let outputs = self.lookup_tx_outputs(&txid).unwrap_or_default();
+            for (vout, txout) in outputs.iter().enumerate() {
+                if compute_script_hash(&txout.script_pubkey)[..] != *to {
+                    continue;
+                }
+                if flow.paths.len() >= max_paths {
+                    flow.truncated = true;
+                    return Ok(flow);
+                }
+                let mut path = vec![FlowOutput {
+                    txid,
+                    vout: vout as u32,
+                    value: txout.value.to_sat(),
+                }];
+                let mut hop = txid;
+                while let Some(parent) = parents.get(&hop) {
+                    path.push(parent.clone());
+                    hop = parent.txid;
+                }
+                path.reverse();
+                flow.paths.push(FlowPath {
+                    hops,
+                    outputs: path,
+                });
+            }
+            if hops == max_hops {
+                continue;
+            }
+            for edge in self.tx_graph_edges(&txid, GraphDirection::Downstream)? {
+                let txout = &outputs[edge.vout as usize];
+                if compute_script_hash(&txout.script_pubkey)[..] == *to
+                    || visited.contains(&edge.spent_by)
+                {
+                    continue;
+                }
+                if visited.len() >= max_nodes {
+                    flow.truncated = true;
+                    break;
+                }
+                visited.insert(edge.spent_by);
+                parents.insert(
+                    edge.spent_by,
+                    FlowOutput {
+                        txid: edge.txid,
+                        vout: edge.vout,
+                        value: edge.value,
+                    },
+                );
+                queue.push_back((edge.spent_by, hops + 1));
+            }
+        }
+        Ok(flow)
+    }
+
     pub fn estimate_fee(&self, conf_target: u16) -> Option<f64> {
         if self.config.network_type.is_regtest() {
             return self.get_relayfee().ok();
@@ -301,6 +978,66 @@ impl Query {
         }
     }
 
+    // The block template of the daemon, with the feerate of each transaction and its rank in the
+    // mempool by feerate, as indexed. Cached briefly, for as long as the tip doesn't change.
+    #[cfg(not(feature = "liquid"))]
+    pub fn block_template(&self) -> Result<serde_json::Value> {
+        let best_hash = self.chain.best_hash().to_string();
+        if let Some((ref template, cache_time)) = *self.cached_template.read().unwrap() {
+            if cache_time.elapsed() < Duration::from_secs(MINING_TEMPLATE_TTL)
+                && template["previousblockhash"].as_str() == Some(best_hash.as_str())
+            {
+                return Ok(template.clone());
+            }
+        }
+
+        let mut template = self.daemon.getblocktemplate()?;
+        let txids: Vec<Txid> = template["transactions"]
+            .as_array()
+            .map_or(&[][..], |txs| &txs[..])
+            .iter()
+            .filter_map(|tx| tx["txid"].as_str()?.parse().ok())
+            .collect();
+        let (ranks, mempool_count) = {
+            let mempool = self.mempool();
+            (mempool.feerate_ranks(&txids), mempool.count())
+        };
+        if let Some(txs) = template["transactions"].as_array_mut() {
+            for tx in txs {
+                let txid: Option<Txid> = tx["txid"].as_str().and_then(|txid| txid.parse().ok());
+                // None for the transactions that aren't indexed yet
+                let rank = txid.and_then(|txid| ranks.get(&txid));
+                tx["feerate"] = rank.map(|(_, feerate)| *feerate).into();
+                tx["mempool_rank"] = rank.map(|(rank, _)| *rank).into();
+            }
+        }
+        template["mempool_count"] = mempool_count.into();
+        template["template_hash"] = template_hash(&template).into();
+        *self.cached_template.write().unwrap() = Some((template.clone(), Instant::now()));
+        Ok(template)
+    }
+
+    // Wait up to `timeout` for the block template to differ from the `known` one, re-fetching it
+    // every MINING_TEMPLATE_TTL seconds, or as soon as the tip changes
+    #[cfg(not(feature = "liquid"))]
+    pub fn wait_template_change(
+        &self,
+        known: Option<&str>,
+        timeout: Duration,
+    ) -> Result<serde_json::Value> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let tip = self.chain.best_hash();
+            let template = self.block_template()?;
+            let now = Instant::now();
+            if known != template["template_hash"].as_str() || now >= deadline {
+                return Ok(template);
+            }
+            let wait = (deadline - now).min(Duration::from_secs(MINING_TEMPLATE_TTL));
+            self.chain.wait_tip_change(&tip, wait);
+        }
+    }
+
     pub fn get_relayfee(&self) -> Result<f64> {
         if let Some(cached) = *self.cached_relayfee.read().unwrap() {
             return Ok(cached);
@@ -311,14 +1048,15 @@ impl Query {
         Ok(relayfee)
     }
 
-    pub fn get_total_coin_supply(&self) -> Result<f64> {
+    // The total amount of coins in the UTXO set, in satoshis
+    pub fn get_total_coin_supply(&self) -> Result<u64> {
         // Get the total coin supply directly from the daemon
         // This uses the gettxoutsetinfo RPC call which returns accurate information
         // about the current UTXO set, including the total amount of coins
         let txout_set_info = self.daemon.gettxoutsetinfo()?;
 
         // Return the total amount from the txoutsetinfo
-        Ok(txout_set_info.total_amount)
+        Ok(txout_set_info.total_amount.to_sat())
     }
 
 
@@ -332,12 +1070,22 @@ impl Query {
         config: Arc<Config>,
         asset_db: Option<Arc<RwLock<AssetRegistry>>>,
     ) -> Self {
+        let slow_query_log = SlowQueryLog::new(&config);
+        let audit_log = AuditLog::new(&config);
+        let request_budget = RequestBudget::new(&config);
+        let watch_lists = WatchLists::load(&chain);
         Query {
             chain,
             mempool,
             daemon,
             config,
             asset_db,
+            slow_query_log,
+            audit_log,
+            request_budget,
+            reindex_jobs: ReindexJobs::default(),
+            idempotent_broadcasts: IdempotentBroadcasts::default(),
+            watch_lists,
             cached_estimates: RwLock::new((HashMap::new(), None)),
             cached_relayfee: RwLock::new(None),
         }
@@ -348,6 +1096,56 @@ impl Query {
         lookup_asset(&self, self.asset_db.as_ref(), asset_id, None)
     }
 
+    #[cfg(feature = "liquid")]
+    pub fn asset_tx_count(&self, asset_id: &AssetId) -> (usize, usize) {
+        asset::asset_tx_count(self, asset_id)
+    }
+
+    #[cfg(feature = "liquid")]
+    pub fn pegs(
+        &self,
+        direction: u8,
+        last_seen: Option<(Txid, u16)>,
+        limit: usize,
+    ) -> Vec<PegValue> {
+        let parent_network = self.config.parent_network;
+        peg::confirmed_pegs(&self.chain, direction, last_seen, limit, parent_network)
+    }
+
+    // The height of the first block indexed along with its pegs
+    #[cfg(feature = "liquid")]
+    pub fn peg_index_start(&self) -> Option<u32> {
+        peg::peg_index_start(&self.chain)
+    }
+
+    // The number of confirmed peg-ins or peg-outs, tallied by the pegged asset stats. These cover
+    // the whole chain, so they only match the listed pegs when the peg index does too.
+    #[cfg(feature = "liquid")]
+    pub fn peg_count(&self, direction: u8) -> Option<usize> {
+        if self.peg_index_start() != Some(0) {
+            return None;
+        }
+        let stats = asset::chain_pegged_asset_stats(&self.chain)?;
+        Some(match direction {
+            peg::PEG_IN => stats.peg_in_count,
+            _ => stats.peg_out_count,
+        })
+    }
+
+    #[cfg(feature = "liquid")]
+    pub fn asset_holders(&self, asset_id: &AssetId, limit: usize) -> AssetHolders {
+        asset::asset_holders(&self.chain, asset_id, limit, self.config.network_type)
+    }
+
+    // Reload the registry metadata of the asset from its sources, None if it's no longer listed
+    #[cfg(feature = "liquid")]
+    pub fn refresh_asset_meta(&self, asset_id: &AssetId) -> Result<Option<AssetMeta>> {
+        match self.asset_db {
+            Some(ref asset_db) => AssetRegistry::refresh(asset_db, asset_id),
+            None => bail!("the asset registry is disabled"),
+        }
+    }
+
     #[cfg(feature = "liquid")]
     pub fn list_registry_assets(
         &self,
@@ -0,0 +1,132 @@
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::chain::{deserialize, Txid};
+use crate::errors::*;
+use crate::new_index::db::{DBFlush, DBRow};
+use crate::new_index::{ChainQuery, Query};
+use crate::util::{bincode, spawn_thread};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+// transactions still unconfirmed after this many rebroadcasts are given up on
+const MAX_REBROADCASTS: u32 = 10;
+
+// A transaction broadcasted through this instance that didn't confirm yet
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PendingBroadcast {
+    #[serde(skip_serializing)]
+    pub txhex: String,
+    pub broadcast_height: usize, // the tip height as of the broadcast
+    pub broadcast_time: u32,
+    pub rebroadcast_count: u32,
+    pub last_rebroadcast_height: Option<usize>,
+}
+
+// persist the tracked transactions, keyed by their txid:
+//      R{txid} → {pending broadcast}
+fn pending_key(txid: &Txid) -> Vec<u8> {
+    [&b"R"[..], &txid[..]].concat()
+}
+
+// Track the transaction until it confirms
+pub fn track(chain: &ChainQuery, txid: &Txid, txhex: &str) {
+    let entry = PendingBroadcast {
+        txhex: txhex.to_string(),
+        broadcast_height: chain.best_height(),
+        broadcast_time: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs() as u32),
+        rebroadcast_count: 0,
+        last_rebroadcast_height: None,
+    };
+    save(chain, txid, &entry);
+}
+
+fn save(chain: &ChainQuery, txid: &Txid, entry: &PendingBroadcast) {
+    chain.store().cache_db().write(
+        vec![DBRow {
+            key: pending_key(txid),
+            value: bincode::serialize_little(entry).unwrap(),
+        }],
+        DBFlush::Enable,
+    );
+}
+
+// The tracked transactions, as of the last update
+pub fn pending(chain: &ChainQuery) -> Vec<(Txid, PendingBroadcast)> {
+    chain
+        .store()
+        .cache_db()
+        .iter_scan(b"R")
+        .map(|row| {
+            let txid = deserialize(&row.key[1..]).expect("failed to parse Txid");
+            let entry = bincode::deserialize_little(&row.value)
+                .expect("failed to parse PendingBroadcast");
+            (txid, entry)
+        })
+        .collect()
+}
+
+// Rebroadcast the tracked transactions that remained unconfirmed for `after` blocks since their
+// last (re)broadcast, and stop tracking the confirmed ones
+pub fn update(query: &Query, after: usize) {
+    let chain = query.chain();
+    let tip_height = chain.best_height();
+    for (txid, mut entry) in pending(chain) {
+        if chain.tx_confirming_block(&txid).is_some() {
+            debug!("broadcasted transaction {} confirmed", txid);
+            chain.store().cache_db().delete(&pending_key(&txid));
+            continue;
+        }
+        let since_height = entry
+            .last_rebroadcast_height
+            .unwrap_or(entry.broadcast_height);
+        if tip_height < since_height + after {
+            continue;
+        }
+        if entry.rebroadcast_count >= MAX_REBROADCASTS {
+            warn!("giving up on rebroadcasting transaction {}", txid);
+            chain.store().cache_db().delete(&pending_key(&txid));
+            continue;
+        }
+        // don't wait for the daemon to become reachable, the transaction is retried on the next
+        // update anyway
+        match query.submit_raw(&entry.txhex, false) {
+            Ok(_) => info!("rebroadcasted unconfirmed transaction {}", txid),
+            Err(Error(ErrorKind::Connection(msg), _)) => {
+                debug!("daemon unreachable, rebroadcasting {} later: {}", txid, msg);
+                continue;
+            }
+            Err(Error(ErrorKind::RpcError(_, code, error), _)) if is_definitive(code) => {
+                // double-spent by a confirmed transaction (or invalid), it won't ever confirm
+                warn!(
+                    "transaction {} got rejected, untracking it: {}",
+                    txid, error
+                );
+                chain.store().cache_db().delete(&pending_key(&txid));
+                continue;
+            }
+            // the rejections for the state of the mempool (e.g. its minimum feerate) may not
+            // last, the transaction is retried until it runs out of rebroadcasts
+            Err(e) => warn!("failed rebroadcasting transaction {}: {}", txid, e),
+        }
+        entry.rebroadcast_count += 1;
+        entry.last_rebroadcast_height = Some(tip_height);
+        save(chain, &txid, &entry);
+    }
+}
+
+// Whether the daemon rejected the transaction for good: its inputs are missing or spent
+// (RPC_VERIFY_ERROR), or it doesn't even decode (RPC_DESERIALIZATION_ERROR)
+fn is_definitive(code: i64) -> bool {
+    code == -25 || code == -22
+}
+
+// Keep rebroadcasting the stuck transactions in the background
+pub fn spawn(query: Arc<Query>, after: usize) -> thread::JoinHandle<()> {
+    spawn_thread("rebroadcast", move || loop {
+        update(&query, after);
+        thread::sleep(POLL_INTERVAL);
+    })
+}
@@ -0,0 +1,135 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::new_index::schema::ReindexReport;
+use crate::new_index::ChainQuery;
+use crate::util::spawn_thread;
+
+// how many finished jobs are kept around for their reports
+const MAX_FINISHED_JOBS: usize = 20;
+
+// A scripthash reindexing, run in the background as it scans the whole chain
+struct ReindexJob {
+    id: u64,
+    scripthash: String,
+    dry_run: bool,
+    started_at: u64,
+    tip_height: usize,
+    scanned_blocks: AtomicUsize,
+    outcome: Mutex<Option<Result<ReindexReport, String>>>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ReindexStatus {
+    pub id: u64,
+    pub scripthash: String,
+    pub dry_run: bool,
+    pub started_at: u64,
+    pub state: &'static str, // running, done or failed
+    pub scanned_blocks: usize,
+    pub total_blocks: usize,
+    pub report: Option<ReindexReport>,
+    pub error: Option<String>,
+}
+
+impl ReindexJob {
+    fn is_running(&self) -> bool {
+        self.outcome.lock().unwrap().is_none()
+    }
+
+    fn status(&self) -> ReindexStatus {
+        let outcome = self.outcome.lock().unwrap();
+        let (state, report, error) = match &*outcome {
+            None => ("running", None, None),
+            Some(Ok(report)) => ("done", Some(report.clone()), None),
+            Some(Err(error)) => ("failed", None, Some(error.clone())),
+        };
+        ReindexStatus {
+            id: self.id,
+            scripthash: self.scripthash.clone(),
+            dry_run: self.dry_run,
+            started_at: self.started_at,
+            state,
+            scanned_blocks: self.scanned_blocks.load(Ordering::Relaxed),
+            total_blocks: self.tip_height + 1,
+            report,
+            error,
+        }
+    }
+}
+
+// The reindexing jobs, one running at a time not to load the DB with several full chain scans
+#[derive(Default)]
+pub struct ReindexJobs {
+    jobs: Mutex<VecDeque<Arc<ReindexJob>>>, // oldest first
+    next_id: AtomicU64,
+}
+
+impl ReindexJobs {
+    // Start reindexing the scripthash in the background, returning the status of the new job.
+    // None if another job is still running.
+    pub fn start(
+        &self,
+        chain: Arc<ChainQuery>,
+        scripthash: Vec<u8>,
+        scripthash_hex: String,
+        index_unspendables: bool,
+        dry_run: bool,
+    ) -> Option<ReindexStatus> {
+        let mut jobs = self.jobs.lock().unwrap();
+        if jobs.iter().any(|job| job.is_running()) {
+            return None;
+        }
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let job = Arc::new(ReindexJob {
+            id,
+            scripthash: scripthash_hex,
+            dry_run,
+            started_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |duration| duration.as_secs()),
+            tip_height: chain.best_height(),
+            scanned_blocks: AtomicUsize::new(0),
+            outcome: Mutex::new(None),
+        });
+        while jobs.len() >= MAX_FINISHED_JOBS {
+            jobs.pop_front();
+        }
+        jobs.push_back(Arc::clone(&job));
+        let status = job.status();
+
+        spawn_thread("reindex", move || {
+            let outcome = chain.reindex_scripthash(
+                &scripthash,
+                index_unspendables,
+                dry_run,
+                &job.scanned_blocks,
+            );
+            match outcome {
+                Ok(ref report) => info!(
+                    "reindexed {} (dry run: {}): {} missing and {} extra history entries",
+                    job.scripthash,
+                    dry_run,
+                    report.missing.len(),
+                    report.extra.len()
+                ),
+                Err(ref e) => warn!("failed reindexing scripthash {}: {}", job.scripthash, e),
+            }
+            *job.outcome.lock().unwrap() = Some(outcome.map_err(|e| e.to_string()));
+        });
+        Some(status)
+    }
+
+    pub fn status(&self, id: u64) -> Option<ReindexStatus> {
+        let jobs = self.jobs.lock().unwrap();
+        jobs.iter().find(|job| job.id == id).map(|job| job.status())
+    }
+
+    // The known jobs, most recent first
+    pub fn list(&self) -> Vec<ReindexStatus> {
+        let jobs = self.jobs.lock().unwrap();
+        jobs.iter().rev().map(|job| job.status()).collect()
+    }
+}
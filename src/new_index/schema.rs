@@ -5,8 +5,8 @@ use bitcoin::VarInt;
 use crypto::digest::Digest;
 use crypto::sha2::Sha256;
 use hex::FromHex;
-use itertools::Itertools;
 use rayon::prelude::*;
+use tracing::span::EnteredSpan;
 
 #[cfg(not(feature = "liquid"))]
 use bitcoin::consensus::encode::{deserialize, serialize};
@@ -17,9 +17,12 @@ use elements::{
     AssetId,
 };
 
-use std::collections::{BTreeSet, HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::mem;
 use std::path::Path;
-use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
 
 use crate::chain::{
     BlockHash, BlockHeader, Network, OutPoint, Script, Transaction, TxOut, Txid, Value,
@@ -29,17 +32,30 @@ use crate::daemon::Daemon;
 use crate::errors::*;
 use crate::metrics::{Gauge, HistogramOpts, HistogramTimer, HistogramVec, MetricOpts, Metrics};
 use crate::util::{
-    bincode, full_hash, has_prevout, is_spendable, BlockHeaderMeta, BlockId, BlockMeta,
-    BlockStatus, Bytes, HeaderEntry, HeaderList, ScriptToAddr,
+    bincode, decode_tx_outputs, extract_tx_prevouts, full_hash, get_innerscripts, get_tx_fee,
+    has_prevout, is_spendable, BlockHeaderMeta, BlockId, BlockMeta, BlockStats, BlockStatus,
+    Bytes, CacheMetrics, DiskLruCache, HeaderEntry, HeaderList, InnerScripts, ScriptToAddr,
+    SizedLruCache, TipNotifier, DEFAULT_BLOCKHASH,
 };
 
-use crate::new_index::db::{DBFlush, DBRow, ReverseScanIterator, ScanIterator, DB};
+use crate::new_index::db::{
+    DBFlush, DBRow, Deadline, ReadScope, ReverseScanIterator, ScanIterator, DB,
+};
 use crate::new_index::fetch::{start_fetcher, BlockEntry, FetchFrom};
 
 #[cfg(feature = "liquid")]
 use crate::elements::{asset, peg};
+#[cfg(not(feature = "liquid"))]
+use crate::new_index::{
+    difficulty::EpochCache, lightning, utxo_histogram, utxo_types, version_bits::DeploymentCache,
+};
+#[cfg(not(feature = "liquid"))]
+use crate::util::{block_multisig_counts, spend_path, MultisigCount, ScriptTemplate, SpendPath};
 
 const MIN_HISTORY_ITEMS_TO_CACHE: usize = 100;
+const CHAIN_SUMMARY_PAGE: usize = 100;
+const REINDEX_CHUNK_BLOCKS: usize = 100; // blocks fetched in parallel by reindex_scripthash()
+const MAX_TRACKED_SCRIPT_CHANGES: usize = 100_000;
 
 pub struct Store {
     // TODO: should be column families
@@ -49,19 +65,33 @@ pub struct Store {
     added_blockhashes: RwLock<HashSet<BlockHash>>,
     indexed_blockhashes: RwLock<HashSet<BlockHash>>,
     indexed_headers: RwLock<HeaderList>,
+    tip_notifier: TipNotifier,
+    script_changes: Mutex<ScriptChanges>,
+    refused_reorg: RwLock<Option<RefusedReorg>>,
+    replica: bool,
+    #[cfg(not(feature = "liquid"))]
+    script_templates: RwLock<Vec<ScriptTemplate>>,
 }
 
 impl Store {
     pub fn open(path: &Path, config: &Config) -> Self {
-        let txstore_db = DB::open(&path.join("txstore"), config);
+        // read-only replicas serve the index of the primary instance, and keep their own cache
+        let open = |name: &str| match config.replica_path {
+            Some(ref replica_path) => {
+                DB::open_secondary(&path.join(name), &replica_path.join(name), config)
+            }
+            None => DB::open(&path.join(name), config),
+        };
+        let txstore_db = open("txstore");
         let added_blockhashes = load_blockhashes(&txstore_db, &BlockRow::done_filter());
         debug!("{} blocks were added", added_blockhashes.len());
 
-        let history_db = DB::open(&path.join("history"), config);
+        let history_db = open("history");
         let indexed_blockhashes = load_blockhashes(&history_db, &BlockRow::done_filter());
         debug!("{} blocks were indexed", indexed_blockhashes.len());
 
-        let cache_db = DB::open(&path.join("cache"), config);
+        let cache_path = config.replica_path.as_ref().map_or(path, |p| p.as_path());
+        let cache_db = DB::open(&cache_path.join("cache"), config);
 
         let headers = if let Some(tip_hash) = txstore_db.get(b"t") {
             let tip_hash = deserialize(&tip_hash).expect("invalid chain tip in `t`");
@@ -76,6 +106,11 @@ impl Store {
             HeaderList::empty()
         };
 
+        let tip_notifier = TipNotifier::new(*headers.tip());
+
+        #[cfg(not(feature = "liquid"))]
+        let script_templates = load_script_templates(&history_db, &config.script_templates);
+
         Store {
             txstore_db,
             history_db,
@@ -83,9 +118,25 @@ impl Store {
             added_blockhashes: RwLock::new(added_blockhashes),
             indexed_blockhashes: RwLock::new(indexed_blockhashes),
             indexed_headers: RwLock::new(headers),
+            tip_notifier,
+            script_changes: Mutex::new(ScriptChanges::default()),
+            refused_reorg: RwLock::new(None),
+            replica: config.replica_path.is_some(),
+            #[cfg(not(feature = "liquid"))]
+            script_templates: RwLock::new(script_templates),
         }
     }
 
+    // Persist everything written so far, for shutting down cleanly. Read-only replicas only write
+    // to their cache.
+    pub fn close(&self) {
+        if !self.replica {
+            self.txstore_db.close();
+            self.history_db.close();
+        }
+        self.cache_db.close();
+    }
+
     pub fn txstore_db(&self) -> &DB {
         &self.txstore_db
     }
@@ -98,13 +149,91 @@ impl Store {
         &self.cache_db
     }
 
+    // The script templates whose matching outputs get indexed
+    #[cfg(not(feature = "liquid"))]
+    pub fn script_templates(&self) -> Vec<ScriptTemplate> {
+        self.script_templates.read().unwrap().clone()
+    }
+
+    // Register a template, whose matching outputs get indexed from the next indexed block on
+    #[cfg(not(feature = "liquid"))]
+    pub fn register_script_template(&self, template: ScriptTemplate) -> Result<()> {
+        if self.replica {
+            bail!("read-only replicas can't register script templates");
+        }
+        let mut templates = self.script_templates.write().unwrap();
+        if templates.iter().any(|t| t.name == template.name) {
+            bail!("script template {} is already registered", template.name);
+        }
+        let row = DBRow {
+            key: [b"j", template.name.as_bytes()].concat(),
+            value: template.pattern.as_bytes().to_vec(),
+        };
+        self.history_db.write(vec![row], DBFlush::Enable);
+        templates.push(template);
+        Ok(())
+    }
+
     pub fn done_initial_sync(&self) -> bool {
         self.txstore_db.get(b"t").is_some()
     }
+
+    // Follow the index of the primary instance, when running as a read-only replica. Returns the
+    // synced tip.
+    pub fn catch_up(&self) -> BlockHash {
+        // the history is caught up last, so that it's at least as recent as the synced tip
+        self.txstore_db.catch_up();
+        self.history_db.catch_up();
+
+        let mut headers = self.indexed_headers.write().unwrap();
+        let tip: BlockHash = match self.txstore_db.get(b"t") {
+            Some(tip) => deserialize(&tip).expect("invalid chain tip in `t`"),
+            None => return *headers.tip(), // the primary didn't complete its initial sync yet
+        };
+        if tip == *headers.tip() {
+            return tip;
+        }
+
+        // walk back from the new tip until joining the known chain
+        let mut new_headers = vec![];
+        let mut blockhash = tip;
+        while blockhash != *DEFAULT_BLOCKHASH && headers.header_by_blockhash(&blockhash).is_none()
+        {
+            let header = self
+                .txstore_db
+                .get(&BlockRow::header_key(full_hash(&blockhash[..])))
+                .expect("missing header of an indexed block");
+            let header: BlockHeader = deserialize(&header).expect("failed to parse BlockHeader");
+            blockhash = header.prev_blockhash;
+            new_headers.push(header);
+        }
+        if new_headers.is_empty() {
+            warn!("the primary instance rolled back to {}, which is not supported", tip);
+            return *headers.tip();
+        }
+        new_headers.reverse();
+        let new_headers = headers.order(new_headers);
+        debug!("following the primary instance to {:?}", new_headers.last());
+        // the affected scripts aren't known without the history rows of the new blocks
+        self.script_changes.lock().unwrap().set_all();
+        headers.apply(new_headers);
+        self.tip_notifier.notify(tip);
+        tip
+    }
 }
 
 type UtxoMap = HashMap<OutPoint, (BlockId, Value)>;
 
+// The inner scripts behind a P2SH/P2WSH scriptPubKey, and the spend that revealed them
+#[derive(Debug)]
+pub struct RevealedScript {
+    pub txid: Txid,
+    pub vin: u32,
+    pub confirmed: BlockId,
+    pub redeem_script: Option<Script>,
+    pub witness_script: Option<Script>,
+}
+
 #[derive(Debug)]
 pub struct Utxo {
     pub txid: Txid,
@@ -136,7 +265,7 @@ pub struct SpendingInput {
     pub confirmed: Option<BlockId>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct ScriptStats {
     pub tx_count: usize,
     pub funded_txo_count: usize,
@@ -161,6 +290,69 @@ impl ScriptStats {
     }
 }
 
+// A lightweight history entry with the net effect of a transaction on a scripthash
+#[derive(Serialize, Debug)]
+pub struct TxSummary {
+    pub txid: Txid,
+    pub height: usize, // 0 for unconfirmed transactions
+    #[cfg(not(feature = "liquid"))]
+    pub value: i64,
+    pub time: u64,
+}
+
+// Sum up the value changes of history entries per transaction, keeping the transactions
+// in the order they are first seen in.
+pub fn summarize_history<'a>(
+    entries: impl Iterator<Item = &'a TxHistoryInfo>,
+) -> Vec<(Txid, i64)> {
+    let mut positions = HashMap::new();
+    let mut summary: Vec<(Txid, i64)> = vec![];
+    for entry in entries {
+        let txid = entry.get_txid();
+        let pos = *positions.entry(txid).or_insert_with(|| {
+            summary.push((txid, 0));
+            summary.len() - 1
+        });
+        summary[pos].1 += entry.value_delta();
+    }
+    summary
+}
+
+// A history entry found missing from or extraneous to the index by reindex_scripthash()
+#[derive(Serialize, Debug, Clone)]
+pub struct HistoryDiscrepancy {
+    pub txid: Txid,
+    pub height: u32,
+    pub kind: &'static str, // funding or spending
+    pub index: u16,         // the funding vout or spending vin
+}
+
+impl HistoryDiscrepancy {
+    fn new(row: &TxHistoryRow) -> Self {
+        let (kind, index) = match row.key.txinfo {
+            TxHistoryInfo::Funding(ref info) => ("funding", info.vout),
+            TxHistoryInfo::Spending(ref info) => ("spending", info.vin),
+            #[cfg(feature = "liquid")]
+            _ => unreachable!(),
+        };
+        HistoryDiscrepancy {
+            txid: row.get_txid(),
+            height: row.key.confirmed_height,
+            kind,
+            index,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ReindexReport {
+    pub tip_height: usize,
+    pub history_entries: usize,
+    pub missing: Vec<HistoryDiscrepancy>,
+    pub extra: Vec<HistoryDiscrepancy>,
+    pub dry_run: bool,
+}
+
 pub struct Indexer {
     store: Arc<Store>,
     flush: DBFlush,
@@ -168,13 +360,34 @@ pub struct Indexer {
     iconfig: IndexerConfig,
     duration: HistogramVec,
     tip_metric: Gauge,
+    refused_reorg_metric: Gauge,
+}
+
+// A reorg the indexer refused to follow, because it conflicts with a checkpoint or is deeper
+// than --max-reorg-depth. The indexed chain stays at its tip until the daemon switches back.
+#[derive(Serialize, Debug, Clone)]
+pub struct RefusedReorg {
+    pub tip: BlockHash,
+    pub height: usize,
+    pub fork_height: usize, // the last block shared with the indexed chain
+    pub depth: usize,       // the number of indexed blocks the reorg would have unwound
+    pub reason: String,
 }
 
 struct IndexerConfig {
     light_mode: bool,
     address_search: bool,
     index_unspendables: bool,
+    #[cfg(not(feature = "liquid"))]
+    large_tx_threshold: Option<u64>,
+    #[cfg(not(feature = "liquid"))]
+    output_values: bool,
+    revealed_scripts: bool,
+    #[cfg(not(feature = "liquid"))]
+    spend_paths: bool,
     network: Network,
+    checkpoints: BTreeMap<usize, BlockHash>,
+    max_reorg_depth: Option<usize>,
     #[cfg(feature = "liquid")]
     parent_network: crate::chain::BNetwork,
 }
@@ -185,7 +398,16 @@ impl From<&Config> for IndexerConfig {
             light_mode: config.light_mode,
             address_search: config.address_search,
             index_unspendables: config.index_unspendables,
+            #[cfg(not(feature = "liquid"))]
+            large_tx_threshold: config.large_tx_threshold,
+            #[cfg(not(feature = "liquid"))]
+            output_values: config.index_output_values,
+            revealed_scripts: config.index_revealed_scripts,
+            #[cfg(not(feature = "liquid"))]
+            spend_paths: config.index_spend_paths,
             network: config.network_type,
+            checkpoints: config.checkpoints.clone(),
+            max_reorg_depth: config.max_reorg_depth,
             #[cfg(feature = "liquid")]
             parent_network: config.parent_network,
         }
@@ -196,8 +418,17 @@ pub struct ChainQuery {
     store: Arc<Store>, // TODO: should be used as read-only
     daemon: Arc<Daemon>,
     light_mode: bool,
+    revealed_scripts: bool,
     duration: HistogramVec,
     network: Network,
+    tx_cache: SizedLruCache<Txid, Bytes>,
+    block_meta_cache: SizedLruCache<BlockHash, BlockMeta>,
+    prevout_cache: SizedLruCache<OutPoint, TxOut>,
+    raw_block_cache: Option<DiskLruCache<BlockHash>>, // only used in light mode
+    #[cfg(not(feature = "liquid"))]
+    epoch_cache: EpochCache,
+    #[cfg(not(feature = "liquid"))]
+    deployment_cache: DeploymentCache,
 }
 
 // TODO: &[Block] should be an iterator / a queue.
@@ -213,6 +444,10 @@ impl Indexer {
                 &["step"],
             ),
             tip_metric: metrics.gauge(MetricOpts::new("tip_height", "Current chain tip height")),
+            refused_reorg_metric: metrics.gauge(MetricOpts::new(
+                "refused_reorg",
+                "Whether the daemon's chain was refused for conflicting with the reorg limits",
+            )),
         }
     }
 
@@ -260,11 +495,73 @@ impl Indexer {
         Ok(result)
     }
 
+    // Check the new headers against the checkpoints and the maximum reorg depth
+    fn check_reorg(&self, new_headers: &[HeaderEntry]) -> Option<RefusedReorg> {
+        let (first, tip) = (new_headers.first()?, new_headers.last()?);
+        let indexed_count = self.store.indexed_headers.read().unwrap().len();
+        let depth = indexed_count.saturating_sub(first.height());
+
+        let conflict = new_headers.iter().find_map(|header| {
+            self.iconfig
+                .checkpoints
+                .get(&header.height())
+                .filter(|checkpoint| *checkpoint != header.hash())
+                .map(|checkpoint| (header, checkpoint))
+        });
+        let reason = match (conflict, self.iconfig.max_reorg_depth) {
+            (Some((header, checkpoint)), _) => format!(
+                "block {} at height {} conflicts with checkpoint {}",
+                header.hash(),
+                header.height(),
+                checkpoint
+            ),
+            (None, Some(max_depth)) if depth > max_depth => format!(
+                "reorg of {} blocks exceeds the maximum depth of {}",
+                depth, max_depth
+            ),
+            _ => return None,
+        };
+        Some(RefusedReorg {
+            tip: *tip.hash(),
+            height: tip.height(),
+            fork_height: first.height().saturating_sub(1),
+            depth,
+            reason,
+        })
+    }
+
     pub fn update(&mut self, daemon: &Daemon) -> Result<BlockHash> {
         let daemon = daemon.reconnect()?;
         let tip = daemon.getbestblockhash()?;
         let new_headers = self.get_new_headers(&daemon, &tip)?;
 
+        // keep serving the indexed chain rather than unwinding past a checkpoint or too deep
+        let refused = self.check_reorg(&new_headers);
+        self.refused_reorg_metric.set(refused.is_some() as i64);
+        let previous = mem::replace(
+            &mut *self.store.refused_reorg.write().unwrap(),
+            refused.clone(),
+        );
+        match (refused, previous) {
+            (Some(refused), _) => {
+                error!(
+                    "refusing to follow the daemon to {} at height {}: {}",
+                    refused.tip, refused.height, refused.reason
+                );
+                return Ok(*self.store.indexed_headers.read().unwrap().tip());
+            }
+            (None, Some(previous)) => {
+                info!("the daemon left the refused chain of {}", previous.tip)
+            }
+            (None, None) => (),
+        }
+
+        // the scripts affected by orphaned blocks cannot be derived from the new history rows
+        let indexed_count = self.store.indexed_headers.read().unwrap().len();
+        if new_headers.first().map_or(false, |h| h.height() < indexed_count) {
+            self.store.script_changes.lock().unwrap().set_all();
+        }
+
         let to_add = self.headers_to_add(&new_headers);
         debug!(
             "adding transactions from {} blocks using {:?}",
@@ -279,7 +576,8 @@ impl Indexer {
             to_index.len(),
             self.from
         );
-        start_fetcher(self.from, &daemon, to_index)?.map(|blocks| self.index(&blocks));
+        start_fetcher(self.from, &daemon, to_index)?
+            .map(|blocks| self.index(&blocks, &new_headers));
         self.start_auto_compactions(&self.store.history_db);
 
         if let DBFlush::Disable = self.flush {
@@ -295,6 +593,7 @@ impl Indexer {
         let mut headers = self.store.indexed_headers.write().unwrap();
         headers.apply(new_headers);
         assert_eq!(tip, *headers.tip());
+        self.store.tip_notifier.notify(tip);
 
         if let FetchFrom::BlkFiles = self.from {
             self.from = FetchFrom::Bitcoind;
@@ -322,11 +621,22 @@ impl Indexer {
             .extend(blocks.iter().map(|b| b.entry.hash()));
     }
 
-    fn index(&self, blocks: &[BlockEntry]) {
+    // `new_headers` are the headers of the best chain past the fork point with the indexed one
+    fn index(&self, blocks: &[BlockEntry], new_headers: &[HeaderEntry]) {
         let previous_txos_map = {
             let _timer = self.start_timer("index_lookup");
             lookup_txos(&self.store.txstore_db, &get_previous_txos(blocks), false)
         };
+        let previous_txo_times = {
+            let _timer = self.start_timer("index_lookup_times");
+            let indexed_headers = self.store.indexed_headers.read().unwrap();
+            lookup_txo_times(
+                &self.store.txstore_db,
+                &previous_txos_map,
+                &indexed_headers,
+                new_headers,
+            )
+        };
         let rows = {
             let _timer = self.start_timer("index_process");
             let added_blockhashes = self.store.added_blockhashes.read().unwrap();
@@ -337,8 +647,32 @@ impl Indexer {
                     panic!("cannot index block {} (missing from store)", blockhash);
                 }
             }
-            index_blocks(blocks, &previous_txos_map, &self.iconfig)
+            index_blocks(
+                blocks,
+                &previous_txos_map,
+                &previous_txo_times,
+                &self.iconfig,
+                #[cfg(not(feature = "liquid"))]
+                &self.store.script_templates(),
+            )
+        };
+        // record where the peg index starts, the first time blocks get indexed with it
+        #[cfg(feature = "liquid")]
+        let rows = {
+            let mut rows = rows;
+            let first_height = blocks.first().map(|b| b.entry.height() as u32);
+            rows.extend(first_height.and_then(|height| {
+                peg::peg_index_start_row(&self.store.history_db, height)
+            }));
+            rows
         };
+        {
+            let mut script_changes = self.store.script_changes.lock().unwrap();
+            for row in rows.iter().filter(|row| row.key[0] == b'H') {
+                // history keys start with the code byte followed by the scripthash
+                script_changes.insert(full_hash(&row.key[1..33]));
+            }
+        }
         self.store.history_db.write(rows, self.flush);
     }
 
@@ -349,18 +683,54 @@ impl Indexer {
 
 impl ChainQuery {
     pub fn new(store: Arc<Store>, daemon: Arc<Daemon>, config: &Config, metrics: &Metrics) -> Self {
+        let cache_metrics = CacheMetrics::new(metrics);
         ChainQuery {
             store,
             daemon,
             light_mode: config.light_mode,
+            revealed_scripts: config.index_revealed_scripts,
             network: config.network_type,
             duration: metrics.histogram_vec(
                 HistogramOpts::new("query_duration", "Index query duration (in seconds)"),
                 &["name"],
             ),
+            tx_cache: SizedLruCache::new("tx", config.tx_cache_size, &cache_metrics),
+            block_meta_cache: SizedLruCache::new(
+                "block_meta",
+                config.block_cache_size,
+                &cache_metrics,
+            ),
+            prevout_cache: SizedLruCache::new("prevout", config.prevout_cache_size, &cache_metrics),
+            raw_block_cache: if config.light_mode && config.raw_block_cache_size > 0 {
+                Some(DiskLruCache::new(
+                    "raw_block",
+                    config
+                        .replica_path
+                        .as_ref()
+                        .unwrap_or(&config.db_path)
+                        .join("raw-blocks"),
+                    config.raw_block_cache_size,
+                    &cache_metrics,
+                ))
+            } else {
+                None
+            },
+            #[cfg(not(feature = "liquid"))]
+            epoch_cache: EpochCache::default(),
+            #[cfg(not(feature = "liquid"))]
+            deployment_cache: DeploymentCache::default(),
         }
     }
 
+    /// Drop all cached transactions, blocks, prevouts and raw blocks, returning the number of
+    /// flushed entries
+    pub fn flush_caches(&self) -> usize {
+        self.tx_cache.clear()
+            + self.block_meta_cache.clear()
+            + self.prevout_cache.clear()
+            + self.raw_block_cache.as_ref().map_or(0, DiskLruCache::clear)
+    }
+
     pub fn network(&self) -> Network {
         self.network
     }
@@ -369,8 +739,20 @@ impl ChainQuery {
         &self.store
     }
 
-    fn start_timer(&self, name: &str) -> HistogramTimer {
-        self.duration.with_label_values(&[name]).start_timer()
+    #[cfg(not(feature = "liquid"))]
+    pub fn epoch_cache(&self) -> &EpochCache {
+        &self.epoch_cache
+    }
+
+    #[cfg(not(feature = "liquid"))]
+    pub fn deployment_cache(&self) -> &DeploymentCache {
+        &self.deployment_cache
+    }
+
+    // The span lets structured logs attribute DB time to the request that caused it
+    fn start_timer(&self, name: &str) -> (HistogramTimer, EnteredSpan) {
+        let span = tracing::debug_span!("db", query = name).entered();
+        (self.duration.with_label_values(&[name]).start_timer(), span)
     }
 
     pub fn get_block_txids(&self, hash: &BlockHash) -> Option<Vec<Txid>> {
@@ -388,27 +770,50 @@ impl ChainQuery {
         }
     }
 
+    // The fee of each transaction of the block, in block order. Blocks indexed before the
+    // fees were introduced have none.
+    pub fn get_block_fees(&self, hash: &BlockHash) -> Option<Vec<u64>> {
+        let _timer = self.start_timer("get_block_fees");
+        self.store
+            .history_db
+            .get(&BlockRow::fees_key(full_hash(&hash[..])))
+            .map(|val| bincode::deserialize_little(&val).expect("failed to parse block fees"))
+    }
+
     pub fn get_block_meta(&self, hash: &BlockHash) -> Option<BlockMeta> {
         let _timer = self.start_timer("get_block_meta");
 
-        if self.light_mode {
+        if let Some(meta) = self.block_meta_cache.get(hash) {
+            return Some(meta);
+        }
+
+        let meta: BlockMeta = if self.light_mode {
             let blockinfo = self.daemon.getblock_raw(hash, 1).ok()?;
-            Some(serde_json::from_value(blockinfo).unwrap())
+            serde_json::from_value(blockinfo).unwrap()
         } else {
             self.store
                 .txstore_db
                 .get(&BlockRow::meta_key(full_hash(&hash[..])))
-                .map(|val| bincode::deserialize_little(&val).expect("failed to parse BlockMeta"))
-        }
+                .map(|val| bincode::deserialize_little(&val).expect("failed to parse BlockMeta"))?
+        };
+        self.block_meta_cache.insert(*hash, meta.clone(), mem::size_of::<(BlockHash, BlockMeta)>());
+        Some(meta)
     }
 
     pub fn get_block_raw(&self, hash: &BlockHash) -> Option<Vec<u8>> {
         let _timer = self.start_timer("get_block_raw");
 
         if self.light_mode {
+            if let Some(raw) = self.raw_block_cache.as_ref().and_then(|cache| cache.get(hash)) {
+                return Some(raw);
+            }
             let blockval = self.daemon.getblock_raw(hash, 0).ok()?;
             let blockhex = blockval.as_str().expect("valid block from bitcoind");
-            Some(Vec::from_hex(blockhex).expect("valid block from bitcoind"))
+            let raw = Vec::from_hex(blockhex).expect("valid block from bitcoind");
+            if let Some(cache) = &self.raw_block_cache {
+                cache.insert(*hash, &raw);
+            }
+            Some(raw)
         } else {
             let entry = self.header_by_hash(hash)?;
             let meta = self.get_block_meta(hash)?;
@@ -450,6 +855,209 @@ impl ChainQuery {
         })
     }
 
+    // The confirmed transactions with a total output value of at least `min_value`, most recent
+    // first, down to `min_height`. Only transactions above the `--large-tx-threshold` at the
+    // time they were indexed are available.
+    pub fn large_txs(
+        &self,
+        min_value: u64,
+        min_height: usize,
+        limit: usize,
+    ) -> Vec<(Txid, u64, BlockId)> {
+        let _timer = self.start_timer("large_txs");
+        let max_height = self.best_height() as u32;
+        self.store
+            .history_db
+            .iter_scan_reverse(
+                &LargeTxRow::filter(),
+                &LargeTxRow::prefix_height(max_height.saturating_add(1)),
+            )
+            .map(LargeTxRow::from_row)
+            .take_while(|row| row.height as usize >= min_height)
+            .filter(|row| row.value >= min_value)
+            .filter_map(|row| {
+                let txid: Txid = deserialize(&row.txid).expect("failed to parse Txid");
+                // skip rows left behind by orphaned blocks
+                let blockid = self.tx_confirming_block(&txid)?;
+                if blockid.height != row.height as usize {
+                    return None;
+                }
+                Some((txid, row.value, blockid))
+            })
+            .take(limit)
+            .collect()
+    }
+
+    // The confirmed outputs of exactly `value`, most recent first, within the given height range
+    // (inclusive). Only available with `--index-output-values`.
+    #[cfg(not(feature = "liquid"))]
+    pub fn outputs_by_value(
+        &self,
+        value: u64,
+        min_height: usize,
+        max_height: usize,
+        limit: usize,
+    ) -> Vec<(OutPoint, BlockId)> {
+        let _timer = self.start_timer("outputs_by_value");
+        let max_height = max_height.min(self.best_height()) as u32;
+        self.store
+            .history_db
+            .iter_scan_reverse(
+                &OutputValueRow::filter(value),
+                &OutputValueRow::prefix_height(value, max_height.saturating_add(1)),
+            )
+            .map(OutputValueRow::from_row)
+            .take_while(|row| row.height as usize >= min_height)
+            .filter_map(|row| {
+                let txid: Txid = deserialize(&row.txid).expect("failed to parse Txid");
+                // skip rows left behind by orphaned blocks
+                let blockid = self.tx_confirming_block(&txid)?;
+                if blockid.height != row.height as usize {
+                    return None;
+                }
+                Some((OutPoint::new(txid, row.vout as u32), blockid))
+            })
+            .take(limit)
+            .collect()
+    }
+
+    // The inner scripts of the scripthash, as first revealed by a confirmed spend. Always None
+    // unless the revealed scripts are indexed.
+    pub fn revealed_script(&self, scripthash: &[u8]) -> Option<RevealedScript> {
+        if !self.revealed_scripts {
+            return None;
+        }
+        let _timer = self.start_timer("revealed_script");
+        self.store
+            .history_db
+            .iter_scan(&RevealedScriptRow::filter(scripthash))
+            .map(RevealedScriptRow::from_row)
+            .find_map(|row| {
+                let txid: Txid = deserialize(&row.txid).expect("failed to parse Txid");
+                // skip rows left behind by orphaned blocks
+                let blockid = self.tx_confirming_block(&txid)?;
+                if blockid.height != row.height as usize {
+                    return None;
+                }
+                Some(RevealedScript {
+                    txid,
+                    vin: row.vin as u32,
+                    confirmed: blockid,
+                    redeem_script: row.redeem_script.map(Script::from),
+                    witness_script: row.witness_script.map(Script::from),
+                })
+            })
+    }
+
+    // The number of confirmed spends of the scripthash by each path, updating the cache of
+    // scripthashes with many of them like stats() does
+    #[cfg(not(feature = "liquid"))]
+    pub fn spend_path_counts(&self, scripthash: &[u8]) -> Result<SpendPathCounts> {
+        let _timer = self.start_timer("spend_path_counts");
+        let cache: Option<(SpendPathCounts, usize)> = self
+            .store
+            .cache_db
+            .get(&SpendPathCacheRow::key(scripthash))
+            .map(|c| bincode::deserialize_little(&c).unwrap())
+            .and_then(|(counts, blockhash)| {
+                self.height_by_hash(&blockhash)
+                    .map(|height| (counts, height))
+            });
+        let (mut counts, start_height) = match cache {
+            Some((counts, height)) => (counts, height + 1),
+            None => (SpendPathCounts::new(), 0),
+        };
+
+        let mut lastblock = None;
+        let rows = self
+            .store
+            .history_db
+            .iter_scan_from(
+                &SpendPathRow::filter(scripthash),
+                &SpendPathRow::prefix_height(scripthash, start_height as u32),
+            )
+            .map(SpendPathRow::from_row);
+        for row in rows {
+            let txid: Txid = deserialize(&row.txid).expect("failed to parse Txid");
+            // skip rows left behind by orphaned blocks
+            match self.tx_confirming_block(&txid) {
+                Some(blockid) if blockid.height == row.height as usize => {
+                    *counts.entry(row.path).or_default() += 1;
+                    lastblock = Some(blockid.hash);
+                }
+                _ => (),
+            }
+        }
+        // the scan may have stopped partway through a block, whose other rows would never be
+        // read again once cached
+        Deadline::check()?;
+
+        if let Some(lastblock) = lastblock {
+            if counts.values().sum::<usize>() > MIN_HISTORY_ITEMS_TO_CACHE {
+                self.store.cache_db.write(
+                    vec![SpendPathCacheRow::new(scripthash, &counts, &lastblock).into_row()],
+                    DBFlush::Enable,
+                );
+            }
+        }
+
+        Ok(counts)
+    }
+
+    // The stats of the blocks in the given height range (inclusive) of the best chain.
+    // Blocks indexed before the stats were introduced have none and are skipped.
+    pub fn block_stats(&self, start_height: usize, end_height: usize) -> Vec<BlockStats> {
+        let _timer = self.start_timer("block_stats");
+        let headers = self.store.indexed_headers.read().unwrap();
+        self.store
+            .history_db
+            .iter_scan_from(
+                &BlockStatsRow::filter(),
+                &BlockStatsRow::key(start_height as u32),
+            )
+            .filter_map(BlockStatsRow::from_row)
+            .take_while(|row| row.height as usize <= end_height)
+            .filter(|row| {
+                headers
+                    .header_by_height(row.height as usize)
+                    .map_or(false, |header| header.hash()[..] == row.hash[..])
+            })
+            .map(|row| row.stats)
+            .collect()
+    }
+
+    // The multisig scripts seen by the blocks in the given height range (inclusive) of the
+    // best chain, summed up by type, m and n
+    #[cfg(not(feature = "liquid"))]
+    pub fn multisig_stats(&self, start_height: usize, end_height: usize) -> Vec<MultisigCount> {
+        let _timer = self.start_timer("multisig_stats");
+        let headers = self.store.indexed_headers.read().unwrap();
+        let mut totals: Vec<MultisigCount> = vec![];
+        let rows = self
+            .store
+            .history_db
+            .iter_scan_from(
+                &MultisigStatsRow::filter(),
+                &MultisigStatsRow::key(start_height as u32),
+            )
+            .map(MultisigStatsRow::from_row)
+            .take_while(|row| row.height as usize <= end_height)
+            .filter(|row| {
+                headers
+                    .header_by_height(row.height as usize)
+                    .map_or(false, |header| header.hash()[..] == row.hash[..])
+            });
+        for count in rows.flat_map(|row| row.counts) {
+            match totals.iter_mut().find(|total| {
+                (total.script_type, total.m, total.n) == (count.script_type, count.m, count.n)
+            }) {
+                Some(total) => total.count += count.count,
+                None => totals.push(count),
+            }
+        }
+        totals
+    }
+
     pub fn history_iter_scan(&self, code: u8, hash: &[u8], start_height: usize) -> ScanIterator {
         self.store.history_db.iter_scan_from(
             &TxHistoryRow::filter(code, &hash[..]),
@@ -462,6 +1070,85 @@ impl ChainQuery {
             &TxHistoryRow::prefix_end(code, &hash[..]),
         )
     }
+    fn history_iter_scan_reverse_from(
+        &self,
+        code: u8,
+        hash: &[u8],
+        max_height: u32,
+    ) -> ReverseScanIterator {
+        self.store.history_db.iter_scan_reverse(
+            &TxHistoryRow::filter(code, &hash[..]),
+            &TxHistoryRow::prefix_height(code, &hash[..], max_height.saturating_add(1)),
+        )
+    }
+
+    // Turn history rows into the (txid, confirming block) of each transaction, keeping
+    // only rows from the height the tx is currently confirmed at (rows left behind by
+    // reorged blocks are skipped). Duplicates are tracked per height only, so memory use
+    // stays bounded regardless of the history size.
+    fn confirmed_history<'a>(
+        &'a self,
+        rows: impl Iterator<Item = DBRow> + 'a,
+    ) -> impl Iterator<Item = (Txid, BlockId)> + 'a {
+        let mut current_height = None;
+        let mut height_txids = HashSet::new();
+        rows.map(TxHistoryRow::from_row)
+            .filter_map(move |row| {
+                let height = row.key.confirmed_height;
+                if current_height != Some(height) {
+                    current_height = Some(height);
+                    height_txids.clear();
+                }
+                let txid = row.get_txid();
+                height_txids.insert(txid).then(|| (txid, height as usize))
+            })
+            .filter_map(move |(txid, height)| {
+                self.tx_confirming_block(&txid)
+                    .filter(|blockid| blockid.height == height)
+                    .map(|blockid| (txid, blockid))
+            })
+    }
+
+    // Stream the confirmed history of a scripthash, newest first, resuming right after
+    // `last_seen_txid`. Seeks directly to the height of the last seen tx rather than
+    // reading all the newer rows.
+    pub fn history_iter<'a>(
+        &'a self,
+        scripthash: &[u8],
+        last_seen_txid: Option<&Txid>,
+    ) -> Box<dyn Iterator<Item = (Txid, BlockId)> + 'a> {
+        self._history_iter(b'H', scripthash, last_seen_txid)
+    }
+
+    fn _history_iter<'a>(
+        &'a self,
+        code: u8,
+        hash: &[u8],
+        last_seen_txid: Option<&Txid>,
+    ) -> Box<dyn Iterator<Item = (Txid, BlockId)> + 'a> {
+        let last_seen_txid = match last_seen_txid {
+            None => {
+                let rows = self.history_iter_scan_reverse(code, hash);
+                return Box::new(self.confirmed_history(rows));
+            }
+            Some(txid) => *txid,
+        };
+        let last_seen_height = match self.tx_confirming_block(&last_seen_txid) {
+            Some(blockid) => blockid.height,
+            // unknown or unconfirmed cursor, there is nothing to resume from
+            None => return Box::new(std::iter::empty()),
+        };
+
+        let rows = self.history_iter_scan_reverse_from(code, hash, last_seen_height as u32);
+        Box::new(
+            self.confirmed_history(rows)
+                // skip the txs that precede the last seen one within its block
+                .skip_while(move |(txid, blockid)| {
+                    blockid.height == last_seen_height && *txid != last_seen_txid
+                })
+                .filter(move |(txid, _)| *txid != last_seen_txid),
+        )
+    }
 
     pub fn history(
         &self,
@@ -473,6 +1160,17 @@ impl ChainQuery {
         self._history(b'H', scripthash, last_seen_txid, limit)
     }
 
+    // The confirmed transactions with outputs matching the script template, most recent first
+    #[cfg(not(feature = "liquid"))]
+    pub fn template_history(
+        &self,
+        name: &str,
+        last_seen_txid: Option<&Txid>,
+        limit: usize,
+    ) -> Vec<(Transaction, BlockId)> {
+        self._history(b'J', &template_hash(name), last_seen_txid, limit)
+    }
+
     fn _history(
         &self,
         code: u8,
@@ -482,20 +1180,7 @@ impl ChainQuery {
     ) -> Vec<(Transaction, BlockId)> {
         let _timer_scan = self.start_timer("history");
         let txs_conf = self
-            .history_iter_scan_reverse(code, hash)
-            .map(|row| TxHistoryRow::from_row(row).get_txid())
-            // XXX: unique() requires keeping an in-memory list of all txids, can we avoid that?
-            .unique()
-            // TODO seek directly to last seen tx without reading earlier rows
-            .skip_while(|txid| {
-                // skip until we reach the last_seen_txid
-                last_seen_txid.map_or(false, |last_seen_txid| last_seen_txid != txid)
-            })
-            .skip(match last_seen_txid {
-                Some(_) => 1, // skip the last_seen_txid itself
-                None => 0,
-            })
-            .filter_map(|txid| self.tx_confirming_block(&txid).map(|b| (txid, b)))
+            ._history_iter(code, hash, last_seen_txid)
             .take(limit)
             .collect::<Vec<(Txid, BlockId)>>();
 
@@ -507,17 +1192,140 @@ impl ChainQuery {
             .collect()
     }
 
+    // Summarize the confirmed history of a scripthash, newest first, using the values
+    // stored in the history rows (no transaction or prevout lookups needed).
+    pub fn summary(
+        &self,
+        scripthash: &[u8],
+        last_seen_txid: Option<&Txid>,
+        limit: usize,
+    ) -> Vec<TxSummary> {
+        let _timer = self.start_timer("summary");
+        let (rows, last_seen) = match last_seen_txid {
+            None => (self.history_iter_scan_reverse(b'H', scripthash), None),
+            Some(txid) => match self.tx_confirming_block(txid) {
+                Some(blockid) => (
+                    self.history_iter_scan_reverse_from(b'H', scripthash, blockid.height as u32),
+                    Some((*txid, blockid.height)),
+                ),
+                // unknown or unconfirmed cursor, there is nothing to resume from
+                None => return vec![],
+            },
+        };
+        let mut rows = rows.map(TxHistoryRow::from_row).peekable();
+        let mut summary = vec![];
+        let mut skipping = last_seen;
+
+        while summary.len() < limit {
+            let height = match rows.peek() {
+                Some(row) => row.key.confirmed_height,
+                None => break,
+            };
+            let entries: Vec<TxHistoryInfo> =
+                std::iter::from_fn(|| rows.next_if(|row| row.key.confirmed_height == height))
+                    .map(|row| row.key.txinfo)
+                    .collect();
+
+            for (txid, _value) in summarize_history(entries.iter()) {
+                if let Some((last_seen_txid, last_seen_height)) = skipping {
+                    if height as usize == last_seen_height {
+                        // skip the txs up to and including the last seen one within its block
+                        if txid == last_seen_txid {
+                            skipping = None;
+                        }
+                        continue;
+                    }
+                    skipping = None;
+                }
+                // drop entries left behind by re-orged blocks
+                let blockid = match self.tx_confirming_block(&txid) {
+                    Some(blockid) if blockid.height == height as usize => blockid,
+                    _ => continue,
+                };
+                summary.push(TxSummary {
+                    txid,
+                    height: blockid.height,
+                    #[cfg(not(feature = "liquid"))]
+                    value: _value,
+                    time: blockid.time as u64,
+                });
+                if summary.len() >= limit {
+                    break;
+                }
+            }
+        }
+        summary
+    }
+
+    // Like summary(), but only for the transactions confirmed in blocks since `since_time`
+    pub fn summary_since(
+        &self,
+        scripthash: &[u8],
+        since_time: u32,
+        limit: usize,
+    ) -> Vec<TxSummary> {
+        let mut summary: Vec<TxSummary> = vec![];
+        while summary.len() < limit {
+            let last_seen_txid = summary.last().map(|entry| entry.txid);
+            let page = self.summary(scripthash, last_seen_txid.as_ref(), CHAIN_SUMMARY_PAGE);
+            let is_last_page = page.len() < CHAIN_SUMMARY_PAGE;
+            for entry in page {
+                if entry.time < since_time as u64 || summary.len() >= limit {
+                    return summary;
+                }
+                summary.push(entry);
+            }
+            if is_last_page {
+                break;
+            }
+        }
+        summary
+    }
+
+    // The history entries of the scripthash confirmed at or after `start_height`, oldest first
+    pub fn history_since(
+        &self,
+        scripthash: &[u8],
+        start_height: usize,
+        limit: usize,
+    ) -> Vec<(TxHistoryInfo, BlockId)> {
+        let _timer = self.start_timer("history_since");
+        self.history_iter_scan(b'H', scripthash, start_height)
+            .map(TxHistoryRow::from_row)
+            .filter_map(|history| {
+                self.tx_confirming_block(&history.get_txid())
+                    // skip rows left behind by orphaned blocks
+                    .filter(|blockid| blockid.height == history.key.confirmed_height as usize)
+                    .map(|blockid| (history.key.txinfo, blockid))
+            })
+            .take(limit)
+            .collect()
+    }
+
     pub fn history_txids(&self, scripthash: &[u8], limit: usize) -> Vec<(Txid, BlockId)> {
         // scripthash lookup
         self._history_txids(b'H', scripthash, limit)
     }
 
+    // The confirmed history of a scripthash in the given height range (inclusive), in the
+    // order of `history_txids`
+    pub fn history_txids_range(
+        &self,
+        scripthash: &[u8],
+        start_height: usize,
+        end_height: usize,
+        limit: usize,
+    ) -> Vec<(Txid, BlockId)> {
+        let _timer = self.start_timer("history_txids_range");
+        self.confirmed_history(self.history_iter_scan(b'H', scripthash, start_height))
+            .take_while(|(_, blockid)| blockid.height <= end_height)
+            .take(limit)
+            .collect()
+    }
+
     fn _history_txids(&self, code: u8, hash: &[u8], limit: usize) -> Vec<(Txid, BlockId)> {
         let _timer = self.start_timer("history_txids");
-        self.history_iter_scan(code, hash, 0)
-            .map(|row| TxHistoryRow::from_row(row).get_txid())
-            .unique()
-            .filter_map(|txid| self.tx_confirming_block(&txid).map(|b| (txid, b)))
+        self.confirmed_history(self.history_iter_scan(code, hash, 0))
             .take(limit)
             .collect()
     }
@@ -525,7 +1333,91 @@ impl ChainQuery {
     // TODO: avoid duplication with stats/stats_delta?
     pub fn utxo(&self, scripthash: &[u8], limit: usize) -> Result<Vec<Utxo>> {
         let _timer = self.start_timer("utxo");
+        let newutxos = self.cached_utxo_map(scripthash, limit)?;
+
+        // format as Utxo objects
+        Ok(newutxos
+            .into_iter()
+            .map(|(outpoint, (blockid, value))| self.to_utxo(outpoint, blockid, value))
+            .collect())
+    }
+
+    // Like `utxo`, but rather than failing past `limit` UTXOs, returns the first `limit` of them
+    // by outpoint, along with the last one returned to resume from with `utxo_with_cursor`
+    pub fn utxo_truncated(
+        &self,
+        scripthash: &[u8],
+        limit: usize,
+    ) -> Result<(Vec<Utxo>, Option<(Txid, u32)>)> {
+        let _timer = self.start_timer("utxo_truncated");
+        let newutxos = self.cached_utxo_map(scripthash, usize::MAX)?;
+        if newutxos.len() <= limit {
+            let utxos = newutxos
+                .into_iter()
+                .map(|(outpoint, (blockid, value))| self.to_utxo(outpoint, blockid, value))
+                .collect();
+            return Ok((utxos, None));
+        }
+        let mut newutxos: Vec<(OutPoint, (BlockId, Value))> = newutxos.into_iter().collect();
+        newutxos.sort_unstable_by_key(|(outpoint, _)| (outpoint.txid, outpoint.vout));
+        newutxos.truncate(limit);
+        let last = newutxos.last().map(|(outpoint, _)| (outpoint.txid, outpoint.vout));
+        let utxos = newutxos
+            .into_iter()
+            .map(|(outpoint, (blockid, value))| self.to_utxo(outpoint, blockid, value))
+            .collect();
+        Ok((utxos, last))
+    }
+
+    fn to_utxo(&self, outpoint: OutPoint, blockid: BlockId, value: Value) -> Utxo {
+        // in elements/liquid chains, we have to lookup the txo in order to get its
+        // associated asset. the asset information could be kept in the db history rows
+        // alongside the value to avoid this.
+        #[cfg(feature = "liquid")]
+        let txo = self.lookup_txo(&outpoint).expect("missing utxo");
+
+        Utxo {
+            txid: outpoint.txid,
+            vout: outpoint.vout,
+            value,
+            confirmed: Some(blockid),
+
+            #[cfg(feature = "liquid")]
+            asset: txo.asset,
+            #[cfg(feature = "liquid")]
+            nonce: txo.nonce,
+            #[cfg(feature = "liquid")]
+            witness: txo.witness,
+        }
+    }
 
+    // The value of each confirmed unspent output, for summaries where the outputs aren't
+    // returned one by one and the utxo limit does not apply. Giant scripthashes are bounded by
+    // the request budget and timeout instead.
+    pub fn utxo_values(&self, scripthash: &[u8]) -> Result<Vec<(OutPoint, Value)>> {
+        let _timer = self.start_timer("utxo_values");
+        Ok(self
+            .cached_utxo_map(scripthash, usize::MAX)?
+            .into_iter()
+            .map(|(outpoint, (_, value))| (outpoint, value))
+            .collect())
+    }
+
+    // Like `utxo_values`, along with the height each output got confirmed at. There's no limit
+    // on the number of UTXOs: like for `utxo_truncated`, the whole set gets computed and cached.
+    pub fn utxo_values_with_height(
+        &self,
+        scripthash: &[u8],
+    ) -> Result<Vec<(OutPoint, usize, Value)>> {
+        let _timer = self.start_timer("utxo_values");
+        Ok(self
+            .cached_utxo_map(scripthash, usize::MAX)?
+            .into_iter()
+            .map(|(outpoint, (blockid, value))| (outpoint, blockid.height, value))
+            .collect())
+    }
+
+    fn cached_utxo_map(&self, scripthash: &[u8], limit: usize) -> Result<UtxoMap> {
         // get the last known utxo set and the blockhash it was updated for.
         // invalidates the cache if the block was orphaned.
         let cache: Option<(UtxoMap, usize)> = self
@@ -556,31 +1448,7 @@ impl ChainQuery {
             }
         }
 
-        // format as Utxo objects
-        Ok(newutxos
-            .into_iter()
-            .map(|(outpoint, (blockid, value))| {
-                // in elements/liquid chains, we have to lookup the txo in order to get its
-                // associated asset. the asset information could be kept in the db history rows
-                // alongside the value to avoid this.
-                #[cfg(feature = "liquid")]
-                let txo = self.lookup_txo(&outpoint).expect("missing utxo");
-
-                Utxo {
-                    txid: outpoint.txid,
-                    vout: outpoint.vout,
-                    value,
-                    confirmed: Some(blockid),
-
-                    #[cfg(feature = "liquid")]
-                    asset: txo.asset,
-                    #[cfg(feature = "liquid")]
-                    nonce: txo.nonce,
-                    #[cfg(feature = "liquid")]
-                    witness: txo.witness,
-                }
-            })
-            .collect())
+        Ok(newutxos)
     }
 
     pub fn count_utxos(&self, scripthash: &[u8]) -> Result<usize> {
@@ -822,11 +1690,13 @@ impl ChainQuery {
                 bail!(ErrorKind::TooPopular)
             }
         }
+        // a cancelled scan may have stopped partway through a block, missing some of its spends
+        Deadline::check()?;
 
         Ok((utxos, lastblock, processed_items))
     }
 
-    pub fn stats(&self, scripthash: &[u8]) -> ScriptStats {
+    pub fn stats(&self, scripthash: &[u8]) -> Result<ScriptStats> {
         let _timer = self.start_timer("stats");
 
         // get the last known stats and the blockhash they are updated for.
@@ -845,7 +1715,7 @@ impl ChainQuery {
         let (newstats, lastblock) = cache.map_or_else(
             || self.stats_delta(scripthash, ScriptStats::default(), 0),
             |(oldstats, blockheight)| self.stats_delta(scripthash, oldstats, blockheight + 1),
-        );
+        )?;
 
         // save updated stats to cache
         if let Some(lastblock) = lastblock {
@@ -857,7 +1727,84 @@ impl ChainQuery {
             }
         }
 
-        newstats
+        Ok(newstats)
+    }
+
+    // The tx count from the stats cache, without updating it. Might be outdated or missing.
+    pub fn cached_tx_count(&self, scripthash: &[u8]) -> Option<usize> {
+        self.store
+            .cache_db
+            .get(&StatsCacheRow::key(scripthash))
+            .map(|c| {
+                let (stats, _): (ScriptStats, BlockHash) = bincode::deserialize_little(&c).unwrap();
+                stats.tx_count
+            })
+    }
+
+    // The disagreement between the cached stats of the scripthash, brought up to date with the
+    // blocks since, and the stats computed from its full history, if any. Scripthashes without
+    // a cache entry, or with an orphaned one, have nothing to disagree on.
+    pub fn verify_cached_stats(&self, scripthash: &[u8]) -> Option<String> {
+        let _timer = self.start_timer("verify_cached_stats");
+        let tip = self.best_hash();
+        let (cached, blockhash): (ScriptStats, BlockHash) = self
+            .store
+            .cache_db
+            .get(&StatsCacheRow::key(scripthash))
+            .map(|c| bincode::deserialize_little(&c).unwrap())?;
+        let height = self.height_by_hash(&blockhash)?;
+
+        let (cached, _) = self.stats_delta(scripthash, cached, height + 1).ok()?;
+        let (expected, _) = self
+            .stats_delta(scripthash, ScriptStats::default(), 0)
+            .ok()?;
+        // blocks connected in between would be accounted for by the second computation only
+        if cached == expected || self.best_hash() != tip {
+            return None;
+        }
+        Some(format!("cached {:?}, expected {:?}", cached, expected))
+    }
+
+    // The disagreement between the cached UTXOs of the scripthash, brought up to date with the
+    // blocks since, and the UTXOs computed from its full history, if any. Fails for scripthashes
+    // with more than `limit` UTXOs.
+    pub fn verify_cached_utxos(&self, scripthash: &[u8], limit: usize) -> Result<Option<String>> {
+        let _timer = self.start_timer("verify_cached_utxos");
+        let tip = self.best_hash();
+        let cache: Option<(CachedUtxoMap, BlockHash)> = self
+            .store
+            .cache_db
+            .get(&UtxoCacheRow::key(scripthash))
+            .map(|c| bincode::deserialize_little(&c).unwrap());
+        let (cached, height) = match cache
+            .and_then(|(cached, blockhash)| Some((cached, self.height_by_hash(&blockhash)?)))
+        {
+            Some(cache) => cache,
+            None => return Ok(None),
+        };
+
+        let cached = from_utxo_cache(cached, self);
+        let (cached, _, _) = self.utxo_delta(scripthash, cached, height + 1, limit)?;
+        let (expected, _, _) = self.utxo_delta(scripthash, HashMap::new(), 0, limit)?;
+        let cached = make_utxo_cache(&cached);
+        let expected = make_utxo_cache(&expected);
+        if cached == expected || self.best_hash() != tip {
+            return Ok(None);
+        }
+        let missing = expected.keys().filter(|k| !cached.contains_key(k)).count();
+        let extra = cached.keys().filter(|k| !expected.contains_key(k)).count();
+        let mismatched = cached
+            .iter()
+            .filter(|(k, v)| expected.get(k).map_or(false, |expected| expected != *v))
+            .count();
+        Ok(Some(format!(
+            "{} cached and {} expected UTXOs: {} missing, {} extra and {} mismatched",
+            cached.len(),
+            expected.len(),
+            missing,
+            extra,
+            mismatched
+        )))
     }
 
     fn stats_delta(
@@ -865,7 +1812,7 @@ impl ChainQuery {
         scripthash: &[u8],
         init_stats: ScriptStats,
         start_height: usize,
-    ) -> (ScriptStats, Option<BlockHash>) {
+    ) -> Result<(ScriptStats, Option<BlockHash>)> {
         let _timer = self.start_timer("stats_delta"); // TODO: measure also the number of txns processed.
         let history_iter = self
             .history_iter_scan(b'H', scripthash, start_height)
@@ -923,8 +1870,10 @@ impl ChainQuery {
 
             lastblock = Some(blockid.hash);
         }
+        // a cancelled scan may have stopped partway through a block
+        Deadline::check()?;
 
-        (stats, lastblock)
+        Ok((stats, lastblock))
     }
 
     pub fn address_search(&self, prefix: &str, limit: usize) -> Vec<String> {
@@ -937,7 +1886,7 @@ impl ChainQuery {
             .collect()
     }
 
-    fn header_by_hash(&self, hash: &BlockHash) -> Option<HeaderEntry> {
+    pub fn header_by_hash(&self, hash: &BlockHash) -> Option<HeaderEntry> {
         self.store
             .indexed_headers
             .read()
@@ -993,14 +1942,42 @@ impl ChainQuery {
             .map(BlockId::from)
     }
 
+    pub fn header_by_time(&self, time: u32) -> Option<HeaderEntry> {
+        self.store
+            .indexed_headers
+            .read()
+            .unwrap()
+            .header_by_time(time)
+            .cloned()
+    }
+
     pub fn best_height(&self) -> usize {
         self.store.indexed_headers.read().unwrap().len() - 1
     }
 
+    // The reorg the indexer last refused to follow, if the daemon is still on that chain
+    pub fn refused_reorg(&self) -> Option<RefusedReorg> {
+        self.store.refused_reorg.read().unwrap().clone()
+    }
+
     pub fn best_hash(&self) -> BlockHash {
         *self.store.indexed_headers.read().unwrap().tip()
     }
 
+    // Collect the scripthashes whose confirmed history changed since the last call
+    pub fn take_script_changes(&self) -> ScriptChanges {
+        mem::take(&mut *self.store.script_changes.lock().unwrap())
+    }
+
+    // Block until the best hash differs from `known` or the timeout elapses
+    pub fn wait_tip_change(&self, known: &BlockHash, timeout: Duration) -> BlockHash {
+        let start_time = Instant::now();
+        let tip = self.store.tip_notifier.wait_change(known, timeout);
+        // waiting doesn't count against the deadline of the request
+        Deadline::postpone(start_time.elapsed());
+        tip
+    }
+
     pub fn best_header(&self) -> HeaderEntry {
         let headers = self.store.indexed_headers.read().unwrap();
         headers
@@ -1013,11 +1990,15 @@ impl ChainQuery {
     // TODO: should also use a custom ThreadPoolBuilder?
     pub fn lookup_txns(&self, txids: &[(Txid, BlockId)]) -> Result<Vec<Transaction>> {
         let _timer = self.start_timer("lookup_txns");
+        let scope = ReadScope::capture();
         txids
             .par_iter()
             .map(|(txid, blockid)| {
-                self.lookup_txn(txid, Some(&blockid.hash))
-                    .chain_err(|| "missing tx")
+                scope.run(|| {
+                    Deadline::check()?;
+                    self.lookup_txn(txid, Some(&blockid.hash))
+                        .chain_err(|| "missing tx")
+                })
             })
             .collect::<Result<Vec<Transaction>>>()
     }
@@ -1031,10 +2012,22 @@ impl ChainQuery {
         })
     }
 
+    /// Get the transaction outputs without decoding its inputs and witnesses
+    pub fn lookup_txn_outputs(&self, txid: &Txid) -> Option<Vec<TxOut>> {
+        let _timer = self.start_timer("lookup_txn_outputs");
+        self.lookup_raw_txn(txid, None).map(|rawtx| {
+            decode_tx_outputs(&rawtx).expect("failed to parse Transaction outputs")
+        })
+    }
+
     pub fn lookup_raw_txn(&self, txid: &Txid, blockhash: Option<&BlockHash>) -> Option<Bytes> {
         let _timer = self.start_timer("lookup_raw_txn");
 
-        if self.light_mode {
+        if let Some(rawtx) = self.tx_cache.get(txid) {
+            return Some(rawtx);
+        }
+
+        let rawtx = if self.light_mode {
             let queried_blockhash =
                 blockhash.map_or_else(|| self.tx_confirming_block(txid).map(|b| b.hash), |_| None);
             let blockhash = blockhash.or_else(|| queried_blockhash.as_ref())?;
@@ -1044,10 +2037,13 @@ impl ChainQuery {
                 .gettransaction_raw(txid, blockhash, false)
                 .ok()?;
             let txhex = txval.as_str().expect("valid tx from bitcoind");
-            Some(Bytes::from_hex(txhex).expect("valid tx from bitcoind"))
+            Bytes::from_hex(txhex).expect("valid tx from bitcoind")
         } else {
-            self.store.txstore_db.get(&TxRow::key(&txid[..]))
-        }
+            self.store.txstore_db.get(&TxRow::key(&txid[..]))?
+        };
+        let size = rawtx.len() + mem::size_of::<(Txid, Bytes)>();
+        self.tx_cache.insert(*txid, rawtx.clone(), size);
+        Some(rawtx)
     }
 
     pub fn lookup_txo(&self, outpoint: &OutPoint) -> Option<TxOut> {
@@ -1057,12 +2053,42 @@ impl ChainQuery {
 
     pub fn lookup_txos(&self, outpoints: &BTreeSet<OutPoint>) -> HashMap<OutPoint, TxOut> {
         let _timer = self.start_timer("lookup_txos");
-        lookup_txos(&self.store.txstore_db, outpoints, false)
+        self.lookup_txos_cached(outpoints, false)
     }
 
     pub fn lookup_avail_txos(&self, outpoints: &BTreeSet<OutPoint>) -> HashMap<OutPoint, TxOut> {
         let _timer = self.start_timer("lookup_available_txos");
-        lookup_txos(&self.store.txstore_db, outpoints, true)
+        self.lookup_txos_cached(outpoints, true)
+    }
+
+    // Overlapping pages of the same history tend to spend the same funding outputs, so resolved
+    // prevouts are cached and only the missing ones are looked up in the db
+    fn lookup_txos_cached(
+        &self,
+        outpoints: &BTreeSet<OutPoint>,
+        allow_missing: bool,
+    ) -> HashMap<OutPoint, TxOut> {
+        let mut txos = HashMap::with_capacity(outpoints.len());
+        let mut missing = BTreeSet::new();
+        for outpoint in outpoints {
+            match self.prevout_cache.get(outpoint) {
+                Some(txout) => {
+                    txos.insert(*outpoint, txout);
+                }
+                None => {
+                    missing.insert(*outpoint);
+                }
+            }
+        }
+        if !missing.is_empty() {
+            let found = lookup_txos(&self.store.txstore_db, &missing, allow_missing);
+            for (outpoint, txout) in &found {
+                let size = mem::size_of::<(OutPoint, TxOut)>() + txout.script_pubkey.len();
+                self.prevout_cache.insert(*outpoint, txout.clone(), size);
+            }
+            txos.extend(found);
+        }
+        txos
     }
 
     pub fn lookup_spend(&self, outpoint: &OutPoint) -> Option<SpendingInput> {
@@ -1080,6 +2106,199 @@ impl ChainQuery {
                 })
             })
     }
+    // The transaction that spent the outpoint in a block orphaned by a reorg, when the reorg
+    // forked off the best chain at most `window` blocks below the tip
+    #[cfg(not(feature = "liquid"))]
+    pub fn lookup_orphaned_spend(&self, outpoint: &OutPoint, window: usize) -> Option<Txid> {
+        let _timer = self.start_timer("lookup_orphaned_spend");
+        let tip_height = self.best_height();
+        self.store
+            .history_db
+            .iter_scan(&TxEdgeRow::filter(&outpoint))
+            .map(TxEdgeRow::from_row)
+            .find_map(|edge| {
+                let txid: Txid = deserialize(&edge.key.spending_txid).unwrap();
+                let orphaned = self
+                    .store
+                    .txstore_db
+                    .iter_scan(&TxConfRow::filter(&txid[..]))
+                    .map(TxConfRow::from_row)
+                    .filter_map(|conf| self.fork_height(conf.key.blockhash, window))
+                    .any(|fork_height| tip_height.saturating_sub(fork_height) <= window);
+                if orphaned {
+                    Some(txid)
+                } else {
+                    None
+                }
+            })
+    }
+
+    // The height of the best chain block an orphaned block forked off from, if found within
+    // `max_depth` blocks. None for best chain blocks.
+    #[cfg(not(feature = "liquid"))]
+    fn fork_height(&self, blockhash: FullHash, max_depth: usize) -> Option<usize> {
+        let mut blockhash: BlockHash = deserialize(&blockhash).unwrap();
+        if self.height_by_hash(&blockhash).is_some() {
+            return None;
+        }
+        for _ in 0..=max_depth {
+            let header = self
+                .store
+                .txstore_db
+                .get(&BlockRow::header_key(full_hash(&blockhash[..])))?;
+            let header: BlockHeader = deserialize(&header).expect("failed to parse BlockHeader");
+            blockhash = header.prev_blockhash;
+            if let Some(height) = self.height_by_hash(&blockhash) {
+                return Some(height);
+            }
+        }
+        None
+    }
+
+    // Rebuild the history of the scripthash from the best chain blocks, and compare it with the
+    // indexed one. Unless `dry_run` is set, the missing entries get written, the extraneous ones
+    // deleted and the cached stats and UTXOs of the scripthash dropped. Entries left behind by
+    // orphaned blocks are expected, and not reported. `scanned_blocks` gets updated as the blocks
+    // get scanned.
+    pub fn reindex_scripthash(
+        &self,
+        scripthash: &[u8],
+        index_unspendables: bool,
+        dry_run: bool,
+        scanned_blocks: &AtomicUsize,
+    ) -> Result<ReindexReport> {
+        let _timer = self.start_timer("reindex_scripthash");
+        let tip_height = self.best_height();
+        let heights: Vec<usize> = (0..=tip_height).collect();
+
+        // spends of the scripthash can only spend the outputs funding it in earlier blocks, so
+        // the blocks are scanned in order without having to look up any prevout
+        let mut funded: HashMap<OutPoint, Value> = HashMap::new();
+        let mut expected: Vec<TxHistoryRow> = vec![];
+        let mut edges: Vec<DBRow> = vec![];
+        for chunk in heights.chunks(REINDEX_CHUNK_BLOCKS) {
+            let blocks: Vec<(u32, Vec<Transaction>)> = chunk
+                .par_iter()
+                .map(|height| {
+                    let hash = *self
+                        .header_by_height(*height)
+                        .chain_err(|| "reorged while reindexing")?
+                        .hash();
+                    let txs = self
+                        .get_block_txids(&hash)
+                        .chain_err(|| "missing block txids")?
+                        .iter()
+                        .map(|txid| self.lookup_txn(txid, Some(&hash)).chain_err(|| "missing tx"))
+                        .collect::<Result<Vec<Transaction>>>()?;
+                    Ok((*height as u32, txs))
+                })
+                .collect::<Result<_>>()?;
+            scanned_blocks.fetch_add(chunk.len(), Ordering::Relaxed);
+
+            for (height, txs) in blocks {
+                for tx in txs {
+                    let txid = tx.txid();
+                    for (vout, txo) in tx.output.iter().enumerate() {
+                        if compute_script_hash(&txo.script_pubkey)[..] != *scripthash {
+                            continue;
+                        }
+                        let outpoint = OutPoint::new(txid, vout as u32);
+                        funded.insert(outpoint, txo.value.amount_value());
+                        if is_spendable(txo) || index_unspendables {
+                            expected.push(TxHistoryRow::new(
+                                &txo.script_pubkey,
+                                height,
+                                TxHistoryInfo::Funding(FundingInfo {
+                                    txid: full_hash(&txid[..]),
+                                    vout: vout as u16,
+                                    value: txo.value.amount_value(),
+                                }),
+                            ));
+                        }
+                    }
+                    for (vin, txin) in tx.input.iter().enumerate() {
+                        let value = match funded.get(&txin.previous_output) {
+                            Some(value) if has_prevout(txin) => *value,
+                            _ => continue,
+                        };
+                        let prev_txid = full_hash(&txin.previous_output.txid[..]);
+                        let prev_vout = txin.previous_output.vout as u16;
+                        expected.push(TxHistoryRow {
+                            key: TxHistoryKey {
+                                code: b'H',
+                                hash: full_hash(scripthash),
+                                confirmed_height: height,
+                                txinfo: TxHistoryInfo::Spending(SpendingInfo {
+                                    txid: full_hash(&txid[..]),
+                                    vin: vin as u16,
+                                    prev_txid,
+                                    prev_vout,
+                                    value,
+                                }),
+                            },
+                        });
+                        let edge =
+                            TxEdgeRow::new(prev_txid, prev_vout, full_hash(&txid[..]), vin as u16);
+                        edges.push(edge.into_row());
+                    }
+                }
+            }
+        }
+
+        let expected: Vec<DBRow> = expected.into_iter().map(TxHistoryRow::into_row).collect();
+        let expected_keys: HashSet<Bytes> = expected.iter().map(|row| row.key.clone()).collect();
+        let indexed: Vec<DBRow> = self.history_iter_scan(b'H', scripthash, 0).collect();
+        let indexed_keys: HashSet<Bytes> = indexed.iter().map(|row| row.key.clone()).collect();
+        let parse_key = |key: &[u8]| TxHistoryRow {
+            key: bincode::deserialize_big(key).expect("failed to deserialize TxHistoryKey"),
+        };
+
+        let history_entries = expected.len();
+        let missing: Vec<DBRow> = expected
+            .into_iter()
+            .filter(|row| !indexed_keys.contains(&row.key))
+            .collect();
+        let extra: Vec<DBRow> = indexed
+            .into_iter()
+            .filter(|row| !expected_keys.contains(&row.key))
+            .filter(|row| {
+                // blocks found since the scan started, and orphaned blocks, aren't accounted for
+                let row = parse_key(&row.key);
+                let height = row.key.confirmed_height as usize;
+                height <= tip_height
+                    && self
+                        .tx_confirming_block(&row.get_txid())
+                        .map_or(false, |b| b.height == height)
+            })
+            .collect();
+
+        let report = ReindexReport {
+            tip_height,
+            history_entries,
+            missing: missing
+                .iter()
+                .map(|row| HistoryDiscrepancy::new(&parse_key(&row.key)))
+                .collect(),
+            extra: extra
+                .iter()
+                .map(|row| HistoryDiscrepancy::new(&parse_key(&row.key)))
+                .collect(),
+            dry_run,
+        };
+
+        if !dry_run {
+            for row in &extra {
+                self.store.history_db.delete(&row.key);
+            }
+            self.store
+                .history_db
+                .write(missing.into_iter().chain(edges).collect(), DBFlush::Enable);
+            self.store.cache_db.delete(&StatsCacheRow::key(scripthash));
+            self.store.cache_db.delete(&UtxoCacheRow::key(scripthash));
+        }
+        Ok(report)
+    }
+
     pub fn tx_confirming_block(&self, txid: &Txid) -> Option<BlockId> {
         let _timer = self.start_timer("tx_confirming_block");
         let headers = self.store.indexed_headers.read().unwrap();
@@ -1239,11 +2458,14 @@ fn lookup_txos(
         .thread_name(|i| format!("lookup-txo-{}", i))
         .build()
         .unwrap();
+    // charge the lookups to the request they're made for
+    let scope = ReadScope::capture();
     pool.install(|| {
         outpoints
             .par_iter()
             .filter_map(|outpoint| {
-                lookup_txo(&txstore_db, &outpoint)
+                scope
+                    .run(|| lookup_txo(&txstore_db, &outpoint))
                     .or_else(|| {
                         if !allow_missing {
                             panic!("missing txo {} in {:?}", outpoint, txstore_db);
@@ -1256,6 +2478,51 @@ fn lookup_txos(
     })
 }
 
+// The time of the block confirming each funding transaction, for the coin age of its outputs.
+// Transactions reorged into another block are confirmed by the one of the best chain: either
+// an indexed block below the fork point, or one of the new blocks.
+fn lookup_txo_times(
+    txstore_db: &DB,
+    txos_map: &HashMap<OutPoint, TxOut>,
+    indexed_headers: &HeaderList,
+    new_headers: &[HeaderEntry],
+) -> HashMap<Txid, u32> {
+    let fork_height = new_headers
+        .first()
+        .map_or(indexed_headers.len(), HeaderEntry::height);
+    let best_block_time = |blockhash: &BlockHash| {
+        indexed_headers
+            .header_by_blockhash(blockhash)
+            .filter(|entry| entry.height() < fork_height)
+            .or_else(|| new_headers.iter().find(|entry| entry.hash() == blockhash))
+            .map(|entry| entry.header().time)
+    };
+
+    let txids: HashSet<Txid> = txos_map.keys().map(|outpoint| outpoint.txid).collect();
+    txids
+        .into_par_iter()
+        .filter_map(|txid| {
+            let confs: Vec<TxConfRow> = txstore_db
+                .iter_scan(&TxConfRow::filter(&txid[..]))
+                .map(TxConfRow::from_row)
+                .collect();
+            let time = match &confs[..] {
+                // the only block confirming a spent transaction is part of the best chain
+                [conf] => {
+                    let header = txstore_db.get(&BlockRow::header_key(conf.key.blockhash))?;
+                    let header: BlockHeader =
+                        deserialize(&header).expect("failed to parse BlockHeader");
+                    header.time
+                }
+                confs => confs.iter().find_map(|conf| {
+                    best_block_time(&deserialize(&conf.key.blockhash).unwrap())
+                })?,
+            };
+            Some((txid, time))
+        })
+        .collect()
+}
+
 fn lookup_txo(txstore_db: &DB, outpoint: &OutPoint) -> Option<TxOut> {
     txstore_db
         .get(&TxOutRow::key(&outpoint))
@@ -1265,7 +2532,9 @@ fn lookup_txo(txstore_db: &DB, outpoint: &OutPoint) -> Option<TxOut> {
 fn index_blocks(
     block_entries: &[BlockEntry],
     previous_txos_map: &HashMap<OutPoint, TxOut>,
+    previous_txo_times: &HashMap<Txid, u32>,
     iconfig: &IndexerConfig,
+    #[cfg(not(feature = "liquid"))] script_templates: &[ScriptTemplate],
 ) -> Vec<DBRow> {
     block_entries
         .par_iter() // serialization is CPU-intensive
@@ -1273,8 +2542,47 @@ fn index_blocks(
             let mut rows = vec![];
             for tx in &b.block.txdata {
                 let height = b.entry.height() as u32;
-                index_transaction(tx, height, previous_txos_map, &mut rows, iconfig);
+                index_transaction(
+                    tx,
+                    height,
+                    previous_txos_map,
+                    &mut rows,
+                    iconfig,
+                    #[cfg(not(feature = "liquid"))]
+                    script_templates,
+                );
+            }
+            let stats =
+                BlockStats::new(b, previous_txos_map, previous_txo_times, iconfig.network);
+            rows.push(BlockStatsRow::new(full_hash(&b.entry.hash()[..]), stats).into_row());
+            #[cfg(not(feature = "liquid"))]
+            {
+                let counts = block_multisig_counts(b, previous_txos_map);
+                if !counts.is_empty() {
+                    let height = b.entry.height() as u32;
+                    let row = MultisigStatsRow::new(height, full_hash(&b.entry.hash()[..]), counts);
+                    rows.push(row.into_row());
+                }
+                let delta = utxo_histogram::block_delta(b, previous_txos_map);
+                let blockhash = full_hash(&b.entry.hash()[..]);
+                rows.push(utxo_histogram::delta_row(b.entry.height() as u32, blockhash, &delta));
+                let delta = utxo_types::block_delta(b, previous_txos_map);
+                rows.push(utxo_types::delta_row(b.entry.height() as u32, blockhash, &delta));
+                if let Some((stats, funding_txids)) = lightning::block_stats(b, previous_txos_map) {
+                    let height = b.entry.height() as u32;
+                    rows.push(lightning::stats_row(height, blockhash, &stats, &funding_txids));
+                }
             }
+            let fees: Vec<u64> = b
+                .block
+                .txdata
+                .iter()
+                .map(|tx| {
+                    let prevouts = extract_tx_prevouts(tx, previous_txos_map, false);
+                    get_tx_fee(tx, &prevouts, iconfig.network)
+                })
+                .collect();
+            rows.push(BlockRow::new_fees(full_hash(&b.entry.hash()[..]), &fees).into_row());
             rows.push(BlockRow::new_done(full_hash(&b.entry.hash()[..])).into_row()); // mark block as "indexed"
             rows
         })
@@ -1289,6 +2597,7 @@ fn index_transaction(
     previous_txos_map: &HashMap<OutPoint, TxOut>,
     rows: &mut Vec<DBRow>,
     iconfig: &IndexerConfig,
+    #[cfg(not(feature = "liquid"))] script_templates: &[ScriptTemplate],
 ) {
     // persist history index:
     //      H{funding-scripthash}{funding-height}F{funding-txid:vout} → ""
@@ -1344,11 +2653,94 @@ fn index_transaction(
             txi_index as u16,
         );
         rows.push(edge.into_row());
+
+        // persist the scripts revealed by spends, keyed by the scripthash they hash into:
+        //      R{funding-scripthash}{spending-height}{spending-txid:vin} → {inner-scripts}
+        if iconfig.revealed_scripts {
+            let innerscripts = get_innerscripts(txi, prev_txo);
+            if innerscripts.redeem_script.is_some() || innerscripts.witness_script.is_some() {
+                let row = RevealedScriptRow::new(
+                    compute_script_hash(&prev_txo.script_pubkey),
+                    confirmed_height,
+                    txid,
+                    txi_index as u16,
+                    innerscripts,
+                );
+                rows.push(row.into_row());
+            }
+        }
+
+        // persist the path taken by Taproot and P2WSH spends, keyed like the revealed scripts:
+        //      Q{funding-scripthash}{spending-height}{spending-txid:vin} → {spend-path}
+        #[cfg(not(feature = "liquid"))]
+        if iconfig.spend_paths {
+            if let Some(path) = spend_path(txi, prev_txo) {
+                let scripthash = compute_script_hash(&prev_txo.script_pubkey);
+                let row = SpendPathRow::new(scripthash, confirmed_height, txid, txi_index, path);
+                rows.push(row.into_row());
+            }
+        }
+    }
+
+    // persist large transactions, keyed by height for the most recent ones:
+    //      L{height}{txid} → {total-output-value}
+    #[cfg(not(feature = "liquid"))]
+    if let Some(threshold) = iconfig.large_tx_threshold {
+        let value = tx.output.iter().map(|txo| txo.value.to_sat()).sum::<u64>();
+        if value >= threshold {
+            rows.push(LargeTxRow::new(confirmed_height, txid, value).into_row());
+        }
+    }
+
+    // persist the outputs by their value, for exact value lookups:
+    //      V{value}{height}{txid:vout} → ""
+    #[cfg(not(feature = "liquid"))]
+    if iconfig.output_values {
+        for (vout, txo) in tx.output.iter().enumerate() {
+            if is_spendable(txo) {
+                let row = OutputValueRow::new(txo.value.to_sat(), confirmed_height, txid, vout);
+                rows.push(row.into_row());
+            }
+        }
+    }
+
+    // persist the outputs matching the script templates, as the history of the template:
+    //      J{template-name-hash}{funding-height}F{funding-txid:vout} → ""
+    #[cfg(not(feature = "liquid"))]
+    for template in script_templates {
+        for (vout, txo) in tx.output.iter().enumerate() {
+            if template.matches(&txo.script_pubkey) {
+                let history = TxHistoryRow {
+                    key: TxHistoryKey {
+                        code: b'J',
+                        hash: template_hash(&template.name),
+                        confirmed_height,
+                        txinfo: TxHistoryInfo::Funding(FundingInfo {
+                            txid,
+                            vout: vout as u16,
+                            value: txo.value.to_sat(),
+                        }),
+                    },
+                };
+                rows.push(history.into_row());
+            }
+        }
     }
 
     // Index issued assets & native asset pegins/pegouts/burns
     #[cfg(feature = "liquid")]
     asset::index_confirmed_tx_assets(
+        tx,
+        confirmed_height,
+        iconfig.network,
+        iconfig.parent_network,
+        previous_txos_map,
+        rows,
+    );
+
+    // Index the peg-ins and peg-outs for listing them by height
+    #[cfg(feature = "liquid")]
+    peg::index_confirmed_tx_pegs(
         tx,
         confirmed_height,
         iconfig.network,
@@ -1371,6 +2763,82 @@ fn addr_search_filter(prefix: &str) -> Bytes {
 // TODO: replace by a separate opaque type (similar to Sha256dHash, but without the "double")
 pub type FullHash = [u8; 32]; // serialized SHA256 result
 
+// Scripthashes whose history changed, used to refresh only the affected subscriptions.
+// Degrades to "everything changed" after reorgs or when too many scripts were touched
+// to be worth tracking individually.
+#[derive(Default)]
+pub struct ScriptChanges {
+    scripthashes: HashSet<FullHash>,
+    all: bool,
+}
+
+impl ScriptChanges {
+    pub fn insert(&mut self, scripthash: FullHash) {
+        if self.all {
+            return;
+        }
+        self.scripthashes.insert(scripthash);
+        if self.scripthashes.len() > MAX_TRACKED_SCRIPT_CHANGES {
+            self.set_all();
+        }
+    }
+
+    pub fn set_all(&mut self) {
+        self.all = true;
+        self.scripthashes = HashSet::new();
+    }
+
+    pub fn extend(&mut self, other: ScriptChanges) {
+        if other.all {
+            self.set_all();
+        } else {
+            other.scripthashes.into_iter().for_each(|hash| self.insert(hash));
+        }
+    }
+
+    pub fn is_all(&self) -> bool {
+        self.all
+    }
+
+    pub fn is_empty(&self) -> bool {
+        !self.all && self.scripthashes.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &FullHash> {
+        self.scripthashes.iter()
+    }
+}
+
+// The hash the history of a script template is keyed by
+#[cfg(not(feature = "liquid"))]
+fn template_hash(name: &str) -> FullHash {
+    let mut hash = FullHash::default();
+    let mut sha2 = Sha256::new();
+    sha2.input(name.as_bytes());
+    sha2.result(&mut hash);
+    hash
+}
+
+// The templates from the config, followed by the ones registered with the admin API. Registered
+// templates are shadowed by the configured ones of the same name.
+#[cfg(not(feature = "liquid"))]
+fn load_script_templates(db: &DB, configured: &[ScriptTemplate]) -> Vec<ScriptTemplate> {
+    let mut templates = configured.to_vec();
+    for row in db.iter_scan(b"j") {
+        let name = String::from_utf8(row.key[1..].to_vec()).expect("invalid template name");
+        let pattern = String::from_utf8(row.value).expect("invalid template pattern");
+        if templates.iter().any(|t| t.name == name) {
+            warn!("script template {} is shadowed by the configured one", name);
+            continue;
+        }
+        match ScriptTemplate::parse(&name, &pattern) {
+            Ok(template) => templates.push(template),
+            Err(e) => warn!("skipping invalid script template {}: {}", name, e),
+        }
+    }
+    templates
+}
+
 pub fn compute_script_hash(script: &Script) -> FullHash {
     let mut hash = FullHash::default();
     let mut sha2 = Sha256::new();
@@ -1497,6 +2965,338 @@ impl TxOutRow {
     }
 }
 
+// persist per-block statistics, keyed by height for range scans:
+//      G{height}{version} → {blockhash}{stats}
+// the blockhash tells apart stats left behind by orphaned blocks. the version is the layout of
+// the stats, to be bumped whenever figures get added.
+struct BlockStatsRow {
+    height: u32,
+    hash: FullHash,
+    stats: BlockStats,
+}
+
+const BLOCK_STATS_VERSION: u8 = 1;
+
+impl BlockStatsRow {
+    fn new(hash: FullHash, stats: BlockStats) -> Self {
+        BlockStatsRow {
+            height: stats.height,
+            hash,
+            stats,
+        }
+    }
+
+    fn filter() -> Bytes {
+        b"G".to_vec()
+    }
+
+    fn key(height: u32) -> Bytes {
+        bincode::serialize_big(&(b'G', height)).unwrap()
+    }
+
+    fn into_row(self) -> DBRow {
+        DBRow {
+            key: bincode::serialize_big(&(b'G', self.height, BLOCK_STATS_VERSION)).unwrap(),
+            value: bincode::serialize_little(&(self.hash, self.stats)).unwrap(),
+        }
+    }
+
+    // None for the rows of an unknown layout
+    fn from_row(row: DBRow) -> Option<Self> {
+        let (_, height, version): (u8, u32, u8) =
+            bincode::deserialize_big(&row.key).expect("failed to parse BlockStats key");
+        if version != BLOCK_STATS_VERSION {
+            warn!("skipping BlockStats of unknown version {}", version);
+            return None;
+        }
+        let (hash, stats) =
+            bincode::deserialize_little(&row.value).expect("failed to parse BlockStats");
+        Some(BlockStatsRow {
+            height,
+            hash,
+            stats,
+        })
+    }
+}
+
+// persist the multisig scripts seen by each block, keyed by height:
+//      K{height} → {blockhash}{counts}
+#[cfg(not(feature = "liquid"))]
+struct MultisigStatsRow {
+    height: u32,
+    hash: FullHash,
+    counts: Vec<MultisigCount>,
+}
+
+#[cfg(not(feature = "liquid"))]
+impl MultisigStatsRow {
+    fn new(height: u32, hash: FullHash, counts: Vec<MultisigCount>) -> Self {
+        MultisigStatsRow {
+            height,
+            hash,
+            counts,
+        }
+    }
+
+    fn filter() -> Bytes {
+        b"K".to_vec()
+    }
+
+    fn key(height: u32) -> Bytes {
+        bincode::serialize_big(&(b'K', height)).unwrap()
+    }
+
+    fn into_row(self) -> DBRow {
+        DBRow {
+            key: MultisigStatsRow::key(self.height),
+            value: bincode::serialize_little(&(self.hash, self.counts)).unwrap(),
+        }
+    }
+
+    fn from_row(row: DBRow) -> Self {
+        let (_, height): (u8, u32) =
+            bincode::deserialize_big(&row.key).expect("failed to parse MultisigStats key");
+        let (hash, counts) =
+            bincode::deserialize_little(&row.value).expect("failed to parse MultisigStats");
+        MultisigStatsRow {
+            height,
+            hash,
+            counts,
+        }
+    }
+}
+
+struct LargeTxRow {
+    height: u32,
+    txid: FullHash,
+    value: u64,
+}
+
+impl LargeTxRow {
+    fn new(height: u32, txid: FullHash, value: u64) -> Self {
+        LargeTxRow {
+            height,
+            txid,
+            value,
+        }
+    }
+
+    fn filter() -> Bytes {
+        b"L".to_vec()
+    }
+
+    fn prefix_height(height: u32) -> Bytes {
+        bincode::serialize_big(&(b'L', height)).unwrap()
+    }
+
+    fn into_row(self) -> DBRow {
+        DBRow {
+            key: bincode::serialize_big(&(b'L', self.height, self.txid)).unwrap(),
+            value: bincode::serialize_little(&self.value).unwrap(),
+        }
+    }
+
+    fn from_row(row: DBRow) -> Self {
+        let (_, height, txid): (u8, u32, FullHash) =
+            bincode::deserialize_big(&row.key).expect("failed to parse LargeTx key");
+        let value = bincode::deserialize_little(&row.value).expect("failed to parse LargeTx");
+        LargeTxRow {
+            height,
+            txid,
+            value,
+        }
+    }
+}
+
+#[cfg(not(feature = "liquid"))]
+struct OutputValueRow {
+    value: u64,
+    height: u32,
+    txid: FullHash,
+    vout: u16,
+}
+
+#[cfg(not(feature = "liquid"))]
+impl OutputValueRow {
+    fn new(value: u64, height: u32, txid: FullHash, vout: usize) -> Self {
+        OutputValueRow {
+            value,
+            height,
+            txid,
+            vout: vout as u16,
+        }
+    }
+
+    fn filter(value: u64) -> Bytes {
+        bincode::serialize_big(&(b'V', value)).unwrap()
+    }
+
+    fn prefix_height(value: u64, height: u32) -> Bytes {
+        bincode::serialize_big(&(b'V', value, height)).unwrap()
+    }
+
+    fn into_row(self) -> DBRow {
+        DBRow {
+            key: bincode::serialize_big(&(b'V', self.value, self.height, self.txid, self.vout))
+                .unwrap(),
+            value: vec![],
+        }
+    }
+
+    fn from_row(row: DBRow) -> Self {
+        let (_, value, height, txid, vout): (u8, u64, u32, FullHash, u16) =
+            bincode::deserialize_big(&row.key).expect("failed to parse OutputValue key");
+        OutputValueRow {
+            value,
+            height,
+            txid,
+            vout,
+        }
+    }
+}
+
+struct RevealedScriptRow {
+    scripthash: FullHash,
+    height: u32,
+    txid: FullHash,
+    vin: u16,
+    redeem_script: Option<Bytes>,
+    witness_script: Option<Bytes>,
+}
+
+impl RevealedScriptRow {
+    fn new(
+        scripthash: FullHash,
+        height: u32,
+        txid: FullHash,
+        vin: u16,
+        innerscripts: InnerScripts,
+    ) -> Self {
+        RevealedScriptRow {
+            scripthash,
+            height,
+            txid,
+            vin,
+            redeem_script: innerscripts.redeem_script.map(|script| script.to_bytes()),
+            witness_script: innerscripts.witness_script.map(|script| script.to_bytes()),
+        }
+    }
+
+    fn filter(scripthash: &[u8]) -> Bytes {
+        [b"R", scripthash].concat()
+    }
+
+    fn into_row(self) -> DBRow {
+        DBRow {
+            key: bincode::serialize_big(&(b'R', self.scripthash, self.height, self.txid, self.vin))
+                .unwrap(),
+            value: bincode::serialize_little(&(self.redeem_script, self.witness_script)).unwrap(),
+        }
+    }
+
+    fn from_row(row: DBRow) -> Self {
+        let (_, scripthash, height, txid, vin): (u8, FullHash, u32, FullHash, u16) =
+            bincode::deserialize_big(&row.key).expect("failed to parse RevealedScript key");
+        let (redeem_script, witness_script) =
+            bincode::deserialize_little(&row.value).expect("failed to parse RevealedScript");
+        RevealedScriptRow {
+            scripthash,
+            height,
+            txid,
+            vin,
+            redeem_script,
+            witness_script,
+        }
+    }
+}
+
+#[cfg(not(feature = "liquid"))]
+struct SpendPathRow {
+    scripthash: FullHash,
+    height: u32,
+    txid: FullHash,
+    vin: u16,
+    path: SpendPath,
+}
+
+#[cfg(not(feature = "liquid"))]
+impl SpendPathRow {
+    fn new(
+        scripthash: FullHash,
+        height: u32,
+        txid: FullHash,
+        vin: usize,
+        path: SpendPath,
+    ) -> Self {
+        SpendPathRow {
+            scripthash,
+            height,
+            txid,
+            vin: vin as u16,
+            path,
+        }
+    }
+
+    fn filter(scripthash: &[u8]) -> Bytes {
+        [b"Q", scripthash].concat()
+    }
+
+    fn prefix_height(scripthash: &[u8], height: u32) -> Bytes {
+        bincode::serialize_big(&(b'Q', full_hash(scripthash), height)).unwrap()
+    }
+
+    fn into_row(self) -> DBRow {
+        DBRow {
+            key: bincode::serialize_big(&(b'Q', self.scripthash, self.height, self.txid, self.vin))
+                .unwrap(),
+            value: bincode::serialize_little(&self.path).unwrap(),
+        }
+    }
+
+    fn from_row(row: DBRow) -> Self {
+        let (_, scripthash, height, txid, vin): (u8, FullHash, u32, FullHash, u16) =
+            bincode::deserialize_big(&row.key).expect("failed to parse SpendPath key");
+        let path = bincode::deserialize_little(&row.value).expect("failed to parse SpendPath");
+        SpendPathRow {
+            scripthash,
+            height,
+            txid,
+            vin,
+            path,
+        }
+    }
+}
+
+#[cfg(not(feature = "liquid"))]
+struct SpendPathCacheRow {
+    key: ScriptCacheKey,
+    value: Bytes,
+}
+
+#[cfg(not(feature = "liquid"))]
+impl SpendPathCacheRow {
+    fn new(scripthash: &[u8], counts: &SpendPathCounts, blockhash: &BlockHash) -> Self {
+        SpendPathCacheRow {
+            key: ScriptCacheKey {
+                code: b'q',
+                scripthash: full_hash(scripthash),
+            },
+            value: bincode::serialize_little(&(counts, blockhash)).unwrap(),
+        }
+    }
+
+    fn key(scripthash: &[u8]) -> Bytes {
+        [b"q", scripthash].concat()
+    }
+
+    fn into_row(self) -> DBRow {
+        DBRow {
+            key: bincode::serialize_little(&self.key).unwrap(),
+            value: self.value,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 struct BlockKey {
     code: u8,
@@ -1533,6 +3333,13 @@ impl BlockRow {
         }
     }
 
+    fn new_fees(hash: FullHash, fees: &[u64]) -> BlockRow {
+        BlockRow {
+            key: BlockKey { code: b'E', hash },
+            value: bincode::serialize_little(fees).unwrap(),
+        }
+    }
+
     fn new_done(hash: FullHash) -> BlockRow {
         BlockRow {
             key: BlockKey { code: b'D', hash },
@@ -1544,6 +3351,10 @@ impl BlockRow {
         b"B".to_vec()
     }
 
+    fn header_key(hash: FullHash) -> Bytes {
+        [b"B", &hash[..]].concat()
+    }
+
     fn txids_key(hash: FullHash) -> Bytes {
         [b"X", &hash[..]].concat()
     }
@@ -1552,6 +3363,10 @@ impl BlockRow {
         [b"M", &hash[..]].concat()
     }
 
+    fn fees_key(hash: FullHash) -> Bytes {
+        [b"E", &hash[..]].concat()
+    }
+
     fn done_filter() -> Bytes {
         b"D".to_vec()
     }
@@ -1620,7 +3435,7 @@ impl TxHistoryInfo {
 
 #[derive(Serialize, Deserialize)]
 pub struct TxHistoryKey {
-    pub code: u8,              // H for script history or I for asset history (elements only)
+    pub code: u8, // H for script history, J for script template history or I for asset history
     pub hash: FullHash, // either a scripthash (always on bitcoin) or an asset id (elements only)
     pub confirmed_height: u32, // MUST be serialized as big-endian (for correct scans).
     pub txinfo: TxHistoryInfo,
@@ -1674,6 +3489,19 @@ impl TxHistoryRow {
 }
 
 impl TxHistoryInfo {
+    // the change to the scripthash balance made by this entry.
+    // confidential values are unknown and count as zero.
+    pub fn value_delta(&self) -> i64 {
+        match self {
+            #[cfg(not(feature = "liquid"))]
+            TxHistoryInfo::Funding(ref info) => info.value as i64,
+            #[cfg(not(feature = "liquid"))]
+            TxHistoryInfo::Spending(ref info) => -(info.value as i64),
+            #[cfg(feature = "liquid")]
+            _ => 0,
+        }
+    }
+
     // for funding rows, returns the funded output.
     // for spending rows, returns the spent previous output.
     pub fn get_funded_outpoint(&self) -> OutPoint {
@@ -1779,6 +3607,9 @@ impl StatsCacheRow {
     }
 }
 
+#[cfg(not(feature = "liquid"))]
+pub type SpendPathCounts = BTreeMap<SpendPath, usize>;
+
 type CachedUtxoMap = HashMap<(Txid, u32), (u32, Value)>; // (txid,vout) => (block_height,output_value)
 
 struct UtxoCacheRow {
@@ -0,0 +1,274 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::chain::{OutPoint, TxOut};
+use crate::errors::*;
+use crate::new_index::db::{DBFlush, DBRow};
+use crate::new_index::{utxo_histogram, utxo_types, BlockEntry, ChainQuery};
+use crate::util::{bincode, full_hash, has_prevout, is_spendable, spawn_thread, Bytes, FullHash};
+
+// only blocks this deep are folded into the persisted breakdowns, the more recent ones are
+// applied on the fly so that reorgs never need to be rolled back
+const CONFIRMATIONS: usize = 10;
+
+// A breakdown of the UTXO set into buckets, summed up from the outputs each block adds and
+// spends:
+//      bucket → (count, total value)
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UtxoDeltas<K: Ord>(BTreeMap<K, (i64, i64)>);
+
+impl<K: Ord> Default for UtxoDeltas<K> {
+    fn default() -> Self {
+        UtxoDeltas(BTreeMap::new())
+    }
+}
+
+impl<K: Ord + Copy> UtxoDeltas<K> {
+    fn add(&mut self, bucket: K, value: u64, sign: i64) {
+        let (count, total) = self.0.entry(bucket).or_insert((0, 0));
+        *count += sign;
+        *total += sign * value as i64;
+    }
+
+    fn merge(&mut self, other: &UtxoDeltas<K>) {
+        for (bucket, (count, total)) in &other.0 {
+            let entry = self.0.entry(*bucket).or_insert((0, 0));
+            entry.0 += count;
+            entry.1 += total;
+        }
+    }
+
+    // The count and total value of each bucket. They can go negative when spending outputs
+    // created before the breakdown was indexed, and are floored at zero.
+    pub fn buckets(&self) -> impl Iterator<Item = (K, u64, u64)> + '_ {
+        self.0.iter().map(|(bucket, (count, total))| {
+            (*bucket, (*count).max(0) as u64, (*total).max(0) as u64)
+        })
+    }
+}
+
+// The outputs a block adds to the UTXO set, minus the ones it spends, by bucket
+pub fn block_delta<K: Ord + Copy>(
+    b: &BlockEntry,
+    txos: &HashMap<OutPoint, TxOut>,
+    bucket: impl Fn(&TxOut) -> K,
+) -> UtxoDeltas<K> {
+    let mut delta = UtxoDeltas::default();
+    for tx in &b.block.txdata {
+        for txout in tx.output.iter().filter(|txout| is_spendable(txout)) {
+            delta.add(bucket(txout), txout.value.to_sat(), 1);
+        }
+        for txin in tx.input.iter().filter(|txin| has_prevout(txin)) {
+            if let Some(prevout) = txos.get(&txin.previous_output) {
+                if is_spendable(prevout) {
+                    delta.add(bucket(prevout), prevout.value.to_sat(), -1);
+                }
+            }
+        }
+    }
+    delta
+}
+
+// Where a breakdown gets persisted: the delta of each block, the breakdown as of a buried block
+// and, with a snapshot interval, the breakdown as of every block at a multiple of it:
+//      {code}{height} → {blockhash}{delta}             (in the history db)
+//      {state-key} → {height}{blockhash}{breakdown}    (in the cache db)
+//      {code}{height} → {blockhash}{breakdown}         (in the cache db)
+pub struct DeltaIndex {
+    code: u8,
+    state_key: &'static [u8],
+    snapshot_interval: Option<usize>,
+    // held while folding the buried blocks into the persisted breakdown, for concurrent
+    // requests to wait for it rather than all fold them
+    catch_up: Mutex<()>,
+}
+
+impl DeltaIndex {
+    pub const fn new(code: u8, state_key: &'static [u8], snapshot_interval: Option<usize>) -> Self {
+        DeltaIndex {
+            code,
+            state_key,
+            snapshot_interval,
+            catch_up: Mutex::new(()),
+        }
+    }
+
+    fn height_key(&self, height: u32) -> Bytes {
+        bincode::serialize_big(&(self.code, height)).unwrap()
+    }
+
+    pub fn delta_row<K>(&self, height: u32, hash: FullHash, delta: &UtxoDeltas<K>) -> DBRow
+    where
+        K: Ord + serde::Serialize,
+    {
+        DBRow {
+            key: self.height_key(height),
+            value: bincode::serialize_little(&(hash, delta)).unwrap(),
+        }
+    }
+
+    // Apply the deltas of the best chain blocks in the given height range (inclusive),
+    // snapshotting the breakdown at the interval heights when `snapshots` is set
+    fn apply_deltas<K>(
+        &self,
+        chain: &ChainQuery,
+        breakdown: &mut UtxoDeltas<K>,
+        start: usize,
+        end: usize,
+        mut snapshots: Option<&mut Vec<DBRow>>,
+    ) where
+        K: Ord + Copy + serde::Serialize + serde::de::DeserializeOwned,
+    {
+        let rows = chain
+            .store()
+            .history_db()
+            .iter_scan_from(&[self.code], &self.height_key(start as u32));
+        for row in rows {
+            let (_, height): (u8, u32) =
+                bincode::deserialize_big(&row.key).expect("failed to parse UtxoDeltas key");
+            if height as usize > end {
+                break;
+            }
+            let (hash, delta): (FullHash, UtxoDeltas<K>) =
+                bincode::deserialize_little(&row.value).expect("failed to parse UtxoDeltas");
+            // skip rows left behind by orphaned blocks
+            if !is_best(chain, height, &hash) {
+                continue;
+            }
+            breakdown.merge(&delta);
+            if let (Some(ref mut snapshots), Some(interval)) =
+                (&mut snapshots, self.snapshot_interval)
+            {
+                if height as usize % interval == 0 {
+                    snapshots.push(DBRow {
+                        key: self.height_key(height),
+                        value: bincode::serialize_little(&(hash, &*breakdown)).unwrap(),
+                    });
+                }
+            }
+        }
+    }
+
+    // Fold the blocks buried since the last run into the persisted breakdown, returning it
+    // along with the height of the first block left out of it
+    pub fn catch_up<K>(&self, chain: &ChainQuery) -> Result<(UtxoDeltas<K>, usize)>
+    where
+        K: Ord + Copy + serde::Serialize + serde::de::DeserializeOwned,
+    {
+        let _lock = self.catch_up.lock().unwrap();
+        let cache_db = chain.store().cache_db();
+
+        // resume from the persisted breakdown, unless its block got reorged out
+        let state: Option<(u32, FullHash, UtxoDeltas<K>)> = cache_db
+            .get(self.state_key)
+            .map(|value| bincode::deserialize_little(&value).expect("failed to parse UtxoDeltas"));
+        let (mut breakdown, next_height) = match state {
+            Some((height, hash, breakdown)) if is_best(chain, height, &hash) => {
+                (breakdown, height as usize + 1)
+            }
+            _ => (UtxoDeltas::default(), 0),
+        };
+
+        let stable_height = match chain.best_height().checked_sub(CONFIRMATIONS) {
+            Some(height) if next_height <= height => height,
+            _ => return Ok((breakdown, next_height)),
+        };
+        let mut rows = vec![];
+        self.apply_deltas(
+            chain,
+            &mut breakdown,
+            next_height,
+            stable_height,
+            Some(&mut rows),
+        );
+        let stable_hash = chain
+            .header_by_height(stable_height)
+            .map(|header| full_hash(&header.hash()[..]))
+            .chain_err(|| "reorged while folding the UTXO deltas")?;
+        let state = (stable_height as u32, stable_hash, &breakdown);
+        rows.push(DBRow {
+            key: self.state_key.to_vec(),
+            value: bincode::serialize_little(&state).unwrap(),
+        });
+        cache_db.write(rows, DBFlush::Enable);
+        Ok((breakdown, stable_height + 1))
+    }
+
+    // The breakdown of the current UTXO set. Blocks indexed before the breakdown was introduced
+    // are not accounted for.
+    pub fn current<K>(&self, chain: &ChainQuery) -> Result<UtxoDeltas<K>>
+    where
+        K: Ord + Copy + serde::Serialize + serde::de::DeserializeOwned,
+    {
+        let (mut breakdown, next_height) = self.catch_up(chain)?;
+        self.apply_deltas(
+            chain,
+            &mut breakdown,
+            next_height,
+            chain.best_height(),
+            None,
+        );
+        Ok(breakdown)
+    }
+
+    // The snapshots taken at the heights in the given range (inclusive) that are multiples of
+    // `interval` blocks. Only buried blocks are snapshotted, as they get folded in.
+    pub fn snapshots<K>(
+        &self,
+        chain: &ChainQuery,
+        start_height: usize,
+        end_height: usize,
+        interval: usize,
+    ) -> Vec<(usize, UtxoDeltas<K>)>
+    where
+        K: Ord + serde::de::DeserializeOwned,
+    {
+        chain
+            .store()
+            .cache_db()
+            .iter_scan_from(&[self.code], &self.height_key(start_height as u32))
+            .map(|row| {
+                let (_, height): (u8, u32) =
+                    bincode::deserialize_big(&row.key).expect("failed to parse UtxoDeltas key");
+                (height as usize, row)
+            })
+            .take_while(|(height, _)| *height <= end_height)
+            .filter(|(height, _)| height % interval == 0)
+            .filter_map(|(height, row)| {
+                let (hash, breakdown): (FullHash, UtxoDeltas<K>) =
+                    bincode::deserialize_little(&row.value).expect("failed to parse UtxoDeltas");
+                // snapshots of orphaned blocks get overwritten once the best chain buries the
+                // height
+                if is_best(chain, height as u32, &hash) {
+                    Some((height, breakdown))
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+}
+
+fn is_best(chain: &ChainQuery, height: u32, hash: &FullHash) -> bool {
+    chain
+        .header_by_height(height as usize)
+        .map_or(false, |header| header.hash()[..] == hash[..])
+}
+
+// Keep folding the newly buried blocks into the persisted breakdowns in the background, leaving
+// only the most recent blocks to the requests
+pub fn spawn(chain: Arc<ChainQuery>) -> thread::JoinHandle<()> {
+    spawn_thread("utxo-deltas", move || {
+        let mut tip = chain.best_hash();
+        loop {
+            let caught_up =
+                utxo_histogram::catch_up(&chain).and_then(|_| utxo_types::catch_up(&chain));
+            if let Err(e) = caught_up {
+                warn!("failed folding the UTXO deltas: {}", e);
+            }
+            tip = chain.wait_tip_change(&tip, Duration::from_secs(60));
+        }
+    })
+}
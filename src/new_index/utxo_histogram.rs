@@ -0,0 +1,78 @@
+use std::collections::HashMap;
+
+use crate::chain::{OutPoint, TxOut};
+use crate::errors::*;
+use crate::new_index::db::DBRow;
+use crate::new_index::utxo_deltas::{self, DeltaIndex, UtxoDeltas};
+use crate::new_index::{BlockEntry, ChainQuery};
+use crate::util::{spending_vsize, FullHash};
+
+// The UTXO set by the vsize of the input needed to spend them and the bit length of their
+// value (i.e. buckets of powers of 2):
+//      (spending-vsize, bit-length) → (count, total value)
+pub type UtxoHistogram = UtxoDeltas<(u16, u8)>;
+
+// persisted as:
+//      Z{height} → {blockhash}{delta}       (in the history db)
+//      z → {height}{blockhash}{histogram}   (in the cache db)
+static INDEX: DeltaIndex = DeltaIndex::new(b'Z', b"z", None);
+
+impl UtxoHistogram {
+    // The number and total value of the UTXOs
+    pub fn totals(&self) -> (u64, u64) {
+        self.buckets()
+            .fold((0, 0), |(count, total), (_, c, t)| (count + c, total + t))
+    }
+
+    // The number and total value of the UTXOs worth less than the fee to spend them at
+    // `feerate` (in sat/vB). The bucket the threshold falls into is interpolated linearly.
+    pub fn dust(&self, feerate: u64) -> (u64, u64) {
+        self.buckets()
+            .fold((0, 0), |(count, total), ((vsize, bits), c, t)| {
+                let threshold = feerate.saturating_mul(vsize as u64) as f64;
+                let (min, max) = bucket_range(bits);
+                let share = ((threshold - min) / (max - min)).clamp(0.0, 1.0);
+                (
+                    count + (c as f64 * share).round() as u64,
+                    total + (t as f64 * share).round() as u64,
+                )
+            })
+    }
+}
+
+fn bucket(txout: &TxOut) -> (u16, u8) {
+    let vsize = spending_vsize(&txout.script_pubkey) as u16;
+    (vsize, bit_length(txout.value.to_sat()))
+}
+
+fn bit_length(value: u64) -> u8 {
+    (64 - value.leading_zeros()) as u8
+}
+
+// The values of the bucket of the given bit length: [min, max)
+fn bucket_range(bits: u8) -> (f64, f64) {
+    match bits {
+        0 => (0.0, 1.0),
+        bits => (2f64.powi(bits as i32 - 1), 2f64.powi(bits as i32)),
+    }
+}
+
+// The outputs a block adds to the UTXO set, minus the ones it spends
+pub fn block_delta(b: &BlockEntry, txos: &HashMap<OutPoint, TxOut>) -> UtxoHistogram {
+    utxo_deltas::block_delta(b, txos, bucket)
+}
+
+pub fn delta_row(height: u32, hash: FullHash, delta: &UtxoHistogram) -> DBRow {
+    INDEX.delta_row(height, hash, delta)
+}
+
+// Fold the buried blocks into the persisted histogram
+pub fn catch_up(chain: &ChainQuery) -> Result<()> {
+    INDEX.catch_up::<(u16, u8)>(chain).map(|_| ())
+}
+
+// The histogram of the current UTXO set. Blocks indexed before the histogram was introduced
+// are not accounted for.
+pub fn current(chain: &ChainQuery) -> Result<UtxoHistogram> {
+    INDEX.current(chain)
+}
@@ -0,0 +1,108 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::chain::{OutPoint, Script, TxOut};
+use crate::errors::*;
+use crate::new_index::db::DBRow;
+use crate::new_index::utxo_deltas::{self, DeltaIndex, UtxoDeltas};
+use crate::new_index::{BlockEntry, ChainQuery};
+use crate::util::FullHash;
+
+// the breakdown is also kept as of every block at a multiple of this height
+pub const SNAPSHOT_INTERVAL: usize = 100;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum ScriptType {
+    P2pkh,
+    P2sh,
+    V0P2wpkh,
+    V0P2wsh,
+    V1P2tr,
+    Other,
+}
+
+impl ScriptType {
+    fn of(script: &Script) -> Self {
+        if script.is_p2pkh() {
+            ScriptType::P2pkh
+        } else if script.is_p2sh() {
+            ScriptType::P2sh
+        } else if script.is_p2wpkh() {
+            ScriptType::V0P2wpkh
+        } else if script.is_p2wsh() {
+            ScriptType::V0P2wsh
+        } else if script.is_p2tr() {
+            ScriptType::V1P2tr
+        } else {
+            ScriptType::Other
+        }
+    }
+}
+
+// The UTXO set by the type of the output script:
+//      script-type → (count, total value)
+pub type UtxoTypes = UtxoDeltas<ScriptType>;
+
+// persisted as:
+//      W{height} → {blockhash}{delta}          (in the history db)
+//      w → {height}{blockhash}{breakdown}      (in the cache db)
+//      W{height} → {blockhash}{breakdown}      (in the cache db)
+static INDEX: DeltaIndex = DeltaIndex::new(b'W', b"w", Some(SNAPSHOT_INTERVAL));
+
+#[derive(Serialize, Debug)]
+pub struct UtxoTypeValue {
+    pub count: u64,
+    pub value: u64,
+}
+
+impl UtxoTypes {
+    // The count and value of each type, including the ones with no UTXOs
+    pub fn breakdown(&self) -> BTreeMap<ScriptType, UtxoTypeValue> {
+        let mut breakdown: BTreeMap<ScriptType, UtxoTypeValue> = [
+            ScriptType::P2pkh,
+            ScriptType::P2sh,
+            ScriptType::V0P2wpkh,
+            ScriptType::V0P2wsh,
+            ScriptType::V1P2tr,
+            ScriptType::Other,
+        ]
+        .iter()
+        .map(|script_type| (*script_type, UtxoTypeValue { count: 0, value: 0 }))
+        .collect();
+        for (script_type, count, value) in self.buckets() {
+            breakdown.insert(script_type, UtxoTypeValue { count, value });
+        }
+        breakdown
+    }
+}
+
+// The outputs a block adds to the UTXO set, minus the ones it spends
+pub fn block_delta(b: &BlockEntry, txos: &HashMap<OutPoint, TxOut>) -> UtxoTypes {
+    utxo_deltas::block_delta(b, txos, |txout| ScriptType::of(&txout.script_pubkey))
+}
+
+pub fn delta_row(height: u32, hash: FullHash, delta: &UtxoTypes) -> DBRow {
+    INDEX.delta_row(height, hash, delta)
+}
+
+// Fold the buried blocks into the persisted breakdown and its snapshots
+pub fn catch_up(chain: &ChainQuery) -> Result<()> {
+    INDEX.catch_up::<ScriptType>(chain).map(|_| ())
+}
+
+// The breakdown of the current UTXO set. Blocks indexed before the breakdown was introduced are
+// not accounted for.
+pub fn current(chain: &ChainQuery) -> Result<UtxoTypes> {
+    INDEX.current(chain)
+}
+
+// The snapshots taken at the heights in the given range (inclusive) that are multiples of
+// `interval` blocks. Only buried blocks are snapshotted, as they get folded in by catch_up().
+pub fn snapshots(
+    chain: &ChainQuery,
+    start_height: usize,
+    end_height: usize,
+    interval: usize,
+) -> Vec<(usize, UtxoTypes)> {
+    INDEX.snapshots(chain, start_height, end_height, interval)
+}
@@ -0,0 +1,218 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::chain::BlockHash;
+use crate::config::Deployment;
+use crate::errors::*;
+use crate::new_index::ChainQuery;
+use crate::util::HeaderEntry;
+
+// BIP9 signaling blocks set the top 3 bits of their version to 001, leaving 29 bits to signal
+const VERSION_TOP_MASK: u32 = 0xe000_0000;
+const VERSION_TOP_BITS: u32 = 0x2000_0000;
+const VERSION_BITS: u8 = 29;
+
+#[derive(Serialize, Debug)]
+pub struct BitSignaling {
+    pub bit: u8,
+    pub count: u32,
+    pub percent: f64,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DeploymentState {
+    Defined,
+    Started,
+    LockedIn,
+    Active,
+    Failed,
+}
+
+#[derive(Serialize, Debug)]
+pub struct DeploymentStatus {
+    pub name: String,
+    pub bit: u8,
+    pub start_time: u32,
+    pub timeout: u32,
+    pub state: DeploymentState,
+    pub since_height: usize, // the first block of the period the state applies from
+    pub period: usize,
+    pub threshold: usize,
+    pub period_start_height: usize, // the first block of the current period
+    pub period_elapsed: usize,      // the blocks of the current period found so far
+    pub period_signaling_count: usize,
+}
+
+// The state of a deployment as of a period boundary, along with the hash of the last block of
+// the previous period to detect the ones orphaned by reorgs
+#[derive(Debug, Clone, Copy)]
+struct PeriodState {
+    state: DeploymentState,
+    since_height: usize,
+    period_start: usize,
+    hash: BlockHash,
+}
+
+// The state of each deployment as of the last period boundary it was computed for, by bit, start
+// time, timeout, threshold and period
+#[derive(Default)]
+pub struct DeploymentCache(Mutex<HashMap<(u8, u32, u32, usize, usize), PeriodState>>);
+
+// Fails rather than panics when a concurrent reorg shortened the best chain
+fn header_at(chain: &ChainQuery, height: usize) -> Result<HeaderEntry> {
+    chain
+        .header_by_height(height)
+        .chain_err(|| format!("no best chain header at height {} (reorged?)", height))
+}
+
+fn block_version(chain: &ChainQuery, height: usize) -> Result<u32> {
+    Ok(header_at(chain, height)?.header().version.to_consensus() as u32)
+}
+
+fn signals(version: u32, bit: u8) -> bool {
+    version & VERSION_TOP_MASK == VERSION_TOP_BITS && (version >> bit) & 1 == 1
+}
+
+fn count_signaling(
+    chain: &ChainQuery,
+    start_height: usize,
+    end_height: usize,
+    bit: u8,
+) -> Result<usize> {
+    let mut count = 0;
+    for height in start_height..=end_height {
+        if signals(block_version(chain, height)?, bit) {
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+// The signaling of each bit over the blocks in the given height range (inclusive), for the bits
+// signaled at least once
+pub fn bit_signaling(
+    chain: &ChainQuery,
+    start_height: usize,
+    end_height: usize,
+) -> Result<Vec<BitSignaling>> {
+    let versions = (start_height..=end_height)
+        .map(|height| block_version(chain, height))
+        .collect::<Result<Vec<u32>>>()?;
+    let block_count = versions.len() as f64;
+    Ok((0..VERSION_BITS)
+        .map(|bit| {
+            let count = versions
+                .iter()
+                .filter(|version| signals(**version, bit))
+                .count() as u32;
+            BitSignaling {
+                bit,
+                count,
+                percent: count as f64 * 100.0 / block_count,
+            }
+        })
+        .filter(|signaling| signaling.count > 0)
+        .collect())
+}
+
+// The BIP9 state of the deployment as of the next block, as bitcoind 0.16 computes it: state
+// changes happen on period boundaries, depending on the median time past of the last block of
+// the previous period and the number of blocks that signaled during it. The periods already
+// walked through are resumed from.
+pub fn deployment_status(
+    chain: &ChainQuery,
+    deployment: &Deployment,
+    period: usize,
+) -> Result<DeploymentStatus> {
+    let threshold = deployment
+        .threshold
+        .map_or_else(|| (period * 95 + 99) / 100, |threshold| threshold as usize);
+    let next_height = chain.best_height() + 1;
+    let current_period_start = next_height - next_height % period;
+
+    let key = (
+        deployment.bit,
+        deployment.start_time,
+        deployment.timeout,
+        threshold,
+        period,
+    );
+    let cache = &chain.deployment_cache().0;
+    let cached = cache.lock().unwrap().get(&key).copied();
+    let resumed = cached.filter(|cached| {
+        cached.period_start <= current_period_start
+            && chain
+                .header_by_height(cached.period_start - 1)
+                .map_or(false, |header| *header.hash() == cached.hash)
+    });
+    let (mut state, mut since_height, mut period_start) = match resumed {
+        Some(cached) => (
+            cached.state,
+            cached.since_height,
+            cached.period_start + period,
+        ),
+        None => (DeploymentState::Defined, 0, period),
+    };
+    let mut computed = None;
+    while period_start <= current_period_start {
+        if let DeploymentState::Active | DeploymentState::Failed = state {
+            break;
+        }
+        let hash = *header_at(chain, period_start - 1)?.hash();
+        let mtp = chain.get_mtp(period_start - 1);
+        let next_state = match state {
+            DeploymentState::Defined if mtp >= deployment.timeout => DeploymentState::Failed,
+            DeploymentState::Defined if mtp >= deployment.start_time => DeploymentState::Started,
+            DeploymentState::Started if mtp >= deployment.timeout => DeploymentState::Failed,
+            DeploymentState::Started => {
+                let signaling = count_signaling(
+                    chain,
+                    period_start - period,
+                    period_start - 1,
+                    deployment.bit,
+                )?;
+                if signaling >= threshold {
+                    DeploymentState::LockedIn
+                } else {
+                    DeploymentState::Started
+                }
+            }
+            DeploymentState::LockedIn => DeploymentState::Active,
+            state => state,
+        };
+        if next_state != state {
+            state = next_state;
+            since_height = period_start;
+        }
+        computed = Some(PeriodState {
+            state,
+            since_height,
+            period_start,
+            hash,
+        });
+        period_start += period;
+    }
+    if let Some(computed) = computed {
+        cache.lock().unwrap().insert(key, computed);
+    }
+
+    let period_signaling_count = if current_period_start < next_height {
+        count_signaling(chain, current_period_start, next_height - 1, deployment.bit)?
+    } else {
+        0
+    };
+    Ok(DeploymentStatus {
+        name: deployment.name.clone(),
+        bit: deployment.bit,
+        start_time: deployment.start_time,
+        timeout: deployment.timeout,
+        state,
+        since_height,
+        period,
+        threshold,
+        period_start_height: current_period_start,
+        period_elapsed: next_height - current_period_start,
+        period_signaling_count,
+    })
+}
@@ -0,0 +1,275 @@
+use std::collections::{BTreeMap, HashSet};
+use std::mem;
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use error_chain::ChainedError;
+use hex::DisplayHex;
+use url::Url;
+
+use crate::errors::*;
+use crate::new_index::db::{DBFlush, DBRow};
+use crate::new_index::{ChainQuery, ScriptChanges};
+use crate::util::{bincode, spawn_thread, FullHash};
+
+pub const MAX_WATCH_LISTS: usize = 100;
+pub const MAX_WATCH_LIST_SIZE: usize = 100_000;
+pub const MAX_NAME_LEN: usize = 64;
+// webhook notifications waiting to be delivered, newer ones are dropped past this
+const WEBHOOK_QUEUE_SIZE: usize = 1000;
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+// persist the watch lists, each as a single row:
+//      l{name} → {webhook}{scripthashes}
+fn list_key(name: &str) -> Vec<u8> {
+    [&b"l"[..], name.as_bytes()].concat()
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredList {
+    webhook: Option<String>,
+    scripthashes: Vec<FullHash>,
+}
+
+struct WatchList {
+    webhook: Option<Url>,
+    scripthashes: HashSet<FullHash>,
+    // the changes not picked up yet, for the lists polled rather than notified via a webhook
+    pending: ScriptChanges,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ImportReport {
+    pub list: String,
+    pub added: usize,
+    pub total: usize,
+    pub webhook: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct WatchListChanges {
+    pub list: String,
+    // set when the changed scripts weren't tracked individually, e.g. after a reorg. All the
+    // scripthashes of the list should be re-checked then.
+    pub all: bool,
+    pub scripthashes: Vec<String>,
+}
+
+impl WatchListChanges {
+    fn new(list: &str, changes: &ScriptChanges) -> Self {
+        WatchListChanges {
+            list: list.to_string(),
+            all: changes.is_all(),
+            scripthashes: changes.iter().map(electrum_hex).collect(),
+        }
+    }
+}
+
+// Scripthashes are listed reversed, the way Electrum and the /scripthashes routes encode them
+pub fn electrum_hex(scripthash: &FullHash) -> String {
+    let mut reversed = *scripthash;
+    reversed.reverse();
+    reversed.to_lower_hex_string()
+}
+
+// The scripthashes watched for changes in bulk, by named list. Each list is either notified via
+// its webhook or polled for the changes accumulated since it was last polled.
+pub struct WatchLists {
+    lists: Mutex<BTreeMap<String, WatchList>>,
+    changed: Condvar,
+    webhooks: SyncSender<(Url, WatchListChanges)>,
+}
+
+impl WatchLists {
+    pub fn load(chain: &ChainQuery) -> Self {
+        let lists = chain
+            .store()
+            .cache_db()
+            .iter_scan(b"l")
+            .filter_map(|row| {
+                let name = String::from_utf8(row.key[1..].to_vec()).ok()?;
+                let stored: StoredList = bincode::deserialize_little(&row.value)
+                    .map_err(|e| warn!("failed to parse watch list {}: {}", name, e))
+                    .ok()?;
+                let list = WatchList {
+                    webhook: stored.webhook.and_then(|webhook| Url::parse(&webhook).ok()),
+                    scripthashes: stored.scripthashes.into_iter().collect(),
+                    pending: ScriptChanges::default(),
+                };
+                Some((name, list))
+            })
+            .collect::<BTreeMap<_, _>>();
+        if !lists.is_empty() {
+            info!("loaded {} watch lists", lists.len());
+        }
+
+        let (webhooks, receiver) = mpsc::sync_channel(WEBHOOK_QUEUE_SIZE);
+        spawn_thread("watch-webhooks", move || deliver_webhooks(receiver));
+        WatchLists {
+            lists: Mutex::new(lists),
+            changed: Condvar::new(),
+            webhooks,
+        }
+    }
+
+    // Add the scripthashes to the list, or replace its scripthashes with them when `replace` is
+    // set. The webhook is kept unless a new one is given or the list is replaced. Lists left
+    // empty get removed.
+    pub fn import(
+        &self,
+        chain: &ChainQuery,
+        name: &str,
+        webhook: Option<Url>,
+        scripthashes: Vec<FullHash>,
+        replace: bool,
+    ) -> Result<ImportReport> {
+        let mut lists = self.lists.lock().unwrap();
+        if !lists.contains_key(name) && lists.len() >= MAX_WATCH_LISTS {
+            bail!("Exceeded maximum of {} watch lists", MAX_WATCH_LISTS);
+        }
+        let list = lists.entry(name.to_string()).or_insert_with(|| WatchList {
+            webhook: None,
+            scripthashes: HashSet::new(),
+            pending: ScriptChanges::default(),
+        });
+
+        let mut updated = if replace {
+            HashSet::new()
+        } else {
+            list.scripthashes.clone()
+        };
+        let before = updated.len();
+        updated.extend(scripthashes);
+        if updated.len() > MAX_WATCH_LIST_SIZE {
+            if list.scripthashes.is_empty() {
+                lists.remove(name);
+            }
+            bail!(
+                "Exceeded maximum of {} scripthashes per watch list",
+                MAX_WATCH_LIST_SIZE
+            );
+        }
+        let added = updated.len() - before;
+        list.scripthashes = updated;
+        if webhook.is_some() || replace {
+            list.webhook = webhook;
+        }
+
+        let report = ImportReport {
+            list: name.to_string(),
+            added,
+            total: list.scripthashes.len(),
+            webhook: list.webhook.as_ref().map(Url::to_string),
+        };
+        let cache_db = chain.store().cache_db();
+        if list.scripthashes.is_empty() {
+            lists.remove(name);
+            cache_db.delete(&list_key(name));
+        } else {
+            let stored = StoredList {
+                webhook: report.webhook.clone(),
+                scripthashes: list.scripthashes.iter().cloned().collect(),
+            };
+            cache_db.write(
+                vec![DBRow {
+                    key: list_key(name),
+                    value: bincode::serialize_little(&stored).unwrap(),
+                }],
+                DBFlush::Enable,
+            );
+        }
+        Ok(report)
+    }
+
+    // The webhook and the scripthashes of the list, sorted
+    pub fn export(&self, name: &str) -> Option<(Option<String>, Vec<FullHash>)> {
+        let lists = self.lists.lock().unwrap();
+        let list = lists.get(name)?;
+        let mut scripthashes: Vec<FullHash> = list.scripthashes.iter().cloned().collect();
+        scripthashes.sort_unstable();
+        Some((list.webhook.as_ref().map(Url::to_string), scripthashes))
+    }
+
+    // Notify the lists watching the changed scripts, called with the changes collected after
+    // every index update
+    pub fn notify(&self, changes: &ScriptChanges) {
+        if changes.is_empty() {
+            return;
+        }
+        let mut lists = self.lists.lock().unwrap();
+        for (name, list) in lists.iter_mut() {
+            let mut changed = ScriptChanges::default();
+            if changes.is_all() {
+                changed.set_all();
+            } else {
+                changes
+                    .iter()
+                    .filter(|scripthash| list.scripthashes.contains(*scripthash))
+                    .for_each(|scripthash| changed.insert(*scripthash));
+            }
+            if changed.is_empty() {
+                continue;
+            }
+            match list.webhook {
+                Some(ref webhook) => {
+                    let notification = WatchListChanges::new(name, &changed);
+                    if let Err(TrySendError::Full(_)) =
+                        self.webhooks.try_send((webhook.clone(), notification))
+                    {
+                        warn!(
+                            "webhook queue is full, dropped the changes of watch list {}",
+                            name
+                        );
+                    }
+                }
+                None => {
+                    list.pending.extend(changed);
+                }
+            }
+        }
+        self.changed.notify_all();
+    }
+
+    // Take the changes accumulated by the list, waiting up to `timeout` for some if there are
+    // none yet. None if there is no such list.
+    pub fn wait_changes(&self, name: &str, timeout: Duration) -> Option<WatchListChanges> {
+        let deadline = Instant::now() + timeout;
+        let mut lists = self.lists.lock().unwrap();
+        loop {
+            let list = lists.get_mut(name)?;
+            let now = Instant::now();
+            if !list.pending.is_empty() || now >= deadline {
+                let changes = mem::take(&mut list.pending);
+                return Some(WatchListChanges::new(name, &changes));
+            }
+            lists = self.changed.wait_timeout(lists, deadline - now).unwrap().0;
+        }
+    }
+}
+
+fn deliver_webhooks(receiver: Receiver<(Url, WatchListChanges)>) {
+    let agent = ureq::AgentBuilder::new()
+        .timeout_connect(WEBHOOK_TIMEOUT)
+        .timeout_read(WEBHOOK_TIMEOUT)
+        .build();
+    for (webhook, changes) in receiver {
+        if let Err(e) = post_webhook(&agent, &webhook, &changes) {
+            warn!(
+                "failed notifying watch list {} via {}: {}",
+                changes.list,
+                webhook,
+                e.display_chain()
+            );
+        }
+    }
+}
+
+// The response body is ignored, anything but a 2xx status is a failure
+fn post_webhook(agent: &ureq::Agent, webhook: &Url, changes: &WatchListChanges) -> Result<()> {
+    match agent.request_url("POST", webhook).send_json(changes) {
+        Ok(_) => Ok(()),
+        Err(ureq::Error::Status(code, _)) => bail!("webhook replied with status {}", code),
+        Err(e) => bail!("failed posting to {}: {}", webhook, e),
+    }
+}
@@ -2,58 +2,158 @@ use crate::chain::{
     address, BlockHash, Network, OutPoint, Script, Sequence, Transaction, TxIn, TxMerkleNode,
     TxOut, Txid,
 };
-use crate::config::Config;
+use crate::config::{CacheTtl, Config, CorsPolicy};
+use crate::electrum::{get_scripthash_status, Sessions};
 use crate::errors;
-use crate::new_index::{compute_script_hash, Query, SpendingInput, Utxo};
+use crate::new_index::daily_stats::{self, DailyStats};
+use crate::new_index::idempotency::{self, Reserved};
+use crate::new_index::{forks, integrity, price, rebroadcast, watch_lists};
+#[cfg(not(feature = "liquid"))]
+use crate::new_index::{
+    difficulty, lightning, utxo_histogram, utxo_types, version_bits, GraphDirection,
+};
+use crate::new_index::{
+    compute_script_hash, Deadline, Query, ReadStats, RevealedScript, SpendingInput, Utxo,
+};
+use crate::util::{
+    absolute_timelock, create_socket, electrum_merkle, extract_tx_prevouts, get_innerscripts,
+    get_tx_fee, has_prevout, is_coinbase, join_timeout, median, relative_timelock, AuditEvent,
+    BlockHeaderMeta, BlockId, BlockStats, FullHash, Locale, ScriptToAddr, ScriptToAsm, SlowQuery,
+    SwrCache, Timelock, TransactionStatus, DEFAULT_BLOCKHASH,
+};
+#[cfg(not(feature = "liquid"))]
 use crate::util::{
-    create_socket, electrum_merkle, extract_tx_prevouts, get_innerscripts, get_tx_fee, has_prevout,
-    is_coinbase, BlockHeaderMeta, BlockId, FullHash, ScriptToAddr, ScriptToAsm, TransactionStatus,
-    DEFAULT_BLOCKHASH,
+    estimate_tx_vsize, inner_multisig, output_vsize, plan_consolidation, policy_violations,
+    script_timelocks, spending_vsize, spends_with_witness, tx_sigops, ConsolidationOrder,
+    InnerScripts, ScriptTemplate, ScriptTimelock, SigopCounts, SpendPath, SpendShape,
 };
 
 #[cfg(not(feature = "liquid"))]
-use bitcoin::consensus::encode;
+use crate::chain::BlockHeader;
+
+#[cfg(not(feature = "liquid"))]
+use bitcoin::{consensus::encode, Target, Work};
 
 use bitcoin::hashes::FromSliceError as HashError;
+use flate2::{write::GzEncoder, Compression};
 use hex::{DisplayHex, FromHex};
+use hyper::server::conn::AddrStream;
 use hyper::service::{make_service_fn, service_fn};
+use hyper::body::HttpBody;
 use hyper::{Body, Method, Response, Server, StatusCode};
 use hyperlocal::UnixServerExt;
+use rayon::prelude::*;
 use tokio::sync::oneshot;
 
 use std::fs;
+use std::io::Write;
 use std::str::FromStr;
 use std::convert::TryInto;
 
 #[cfg(feature = "liquid")]
 use {
-    crate::elements::{ebcompact::*, peg::PegoutValue, AssetSorting, IssuanceValue},
+    crate::elements::{
+        ebcompact::*,
+        peg::{self, PegoutValue},
+        unblind, AssetSorting, IssuanceValue,
+    },
     elements::{encode, secp256k1_zkp as zkp, AssetId},
 };
 
 use serde::Serialize;
 use serde_json;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
 use std::num::ParseIntError;
 use std::os::unix::fs::FileTypeExt;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::thread;
-use url::form_urlencoded;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use url::{form_urlencoded, Url};
 
 const CHAIN_TXS_PER_PAGE: usize = 25;
-const MAX_MEMPOOL_TXS: usize = 50;
 const BLOCK_LIMIT: usize = 10;
 const ADDRESS_SEARCH_LIMIT: usize = 10;
+const MEMPOOL_TXIDS_PER_PAGE: usize = 100;
+const MAX_MEMPOOL_TXIDS_PER_PAGE: usize = 1000;
+const AUDIT_EVENTS_PER_PAGE: usize = 100;
+const MAX_AUDIT_EVENTS_PER_PAGE: usize = 1000;
+const BLOCK_STATS_DEFAULT_RANGE: usize = 144;
+const MAX_BLOCK_STATS_RANGE: usize = 50_000;
+const BLOCK_INTERVALS_DEFAULT_WINDOW: usize = 2016;
+const MAX_BLOCK_STATS_POINTS: usize = 1000;
+const DAILY_STATS_DEFAULT_DAYS: u32 = 90;
+const TX_GRAPH_DEFAULT_DEPTH: usize = 25;
+const TX_GRAPH_MAX_DEPTH: usize = 100;
+#[cfg(not(feature = "liquid"))]
+const TX_SPEND_GRAPH_DEFAULT_DEPTH: usize = 3;
+#[cfg(not(feature = "liquid"))]
+const TX_SPEND_GRAPH_MAX_DEPTH: usize = 10;
+#[cfg(not(feature = "liquid"))]
+const TX_SPEND_GRAPH_MAX_NODES: usize = 500;
+#[cfg(not(feature = "liquid"))]
+const TX_SPEND_GRAPH_MAX_EDGES: usize = 2000;
+#[cfg(not(feature = "liquid"))]
+const FLOW_DEFAULT_HOPS: usize = 6;
+#[cfg(not(feature = "liquid"))]
+const FLOW_MAX_HOPS: usize = 10;
+#[cfg(not(feature = "liquid"))]
+const FLOW_MAX_NODES: usize = 5000; // txs looked at by a single search
+#[cfg(not(feature = "liquid"))]
+const FLOW_MAX_PATHS: usize = 25;
+#[cfg(not(feature = "liquid"))]
+const CONSOLIDATION_MAX_INPUTS: usize = 10_000; // planned by a single request
+#[cfg(not(feature = "liquid"))]
+const DIFFICULTY_EPOCHS_DEFAULT: usize = 50;
+#[cfg(not(feature = "liquid"))]
+const DIFFICULTY_EPOCHS_MAX: usize = 1000;
+#[cfg(not(feature = "liquid"))]
+const ACTIVITY_DEFAULT_WINDOW: &str = "24h";
+#[cfg(not(feature = "liquid"))]
+const ACTIVITY_MAX_WINDOW: Duration = Duration::from_secs(30 * 86_400);
+#[cfg(not(feature = "liquid"))]
+const ACTIVITY_MAX_TXS: usize = 1000; // txs looked at to compute the activity of an address
+#[cfg(not(feature = "liquid"))]
+const ADDRESS_DELTA_MAX_EVENTS: usize = 5000;
+#[cfg(not(feature = "liquid"))]
+const BATCHING_CONF_TARGET: u16 = 6; // default target of the fee estimate used by batching reports
+// batching reports assume the payments get funded by a single input of this type by default,
+// with a change output of the same type
+#[cfg(not(feature = "liquid"))]
+const BATCHING_INPUT_TYPE: &str = "p2pkh";
+#[cfg(not(feature = "liquid"))]
+const UTXO_SUMMARY_BUCKETS: [u64; 6] = [1_000, 10_000, 100_000, 1_000_000, 10_000_000, 100_000_000];
 
 #[cfg(feature = "liquid")]
 const ASSETS_PER_PAGE: usize = 25;
 #[cfg(feature = "liquid")]
 const ASSETS_MAX_PER_PAGE: usize = 100;
+#[cfg(feature = "liquid")]
+const ASSET_HOLDERS_PER_PAGE: usize = 100;
+#[cfg(feature = "liquid")]
+const ASSET_HOLDERS_MAX: usize = 1000;
 
-const TTL_LONG: u32 = 157_784_630; // ttl for static resources (5 years)
-const TTL_SHORT: u32 = 10; // ttl for volatie resources
-const TTL_MEMPOOL_RECENT: u32 = 5; // ttl for GET /mempool/recent
+const SATS_PER_COIN: u64 = 100_000_000;
 const CONF_FINAL: usize = 10; // reorgs deeper than this are considered unlikely
+const SWR_CACHE_ENTRIES: usize = 1000; // responses kept by the stale-while-revalidate cache
+const ALLOWED_METHODS: &str = "GET, HEAD, POST, OPTIONS"; // methods the routes respond to
+
+const LONGPOLL_TIMEOUT: u64 = 60; // default seconds to hold long-poll requests open
+const LONGPOLL_MAX_TIMEOUT: u64 = 300;
+
+#[derive(Serialize)]
+struct TipValue {
+    height: usize,
+    hash: BlockHash,
+    timestamp: u32,
+    mediantime: u32,
+    #[cfg(not(feature = "liquid"))]
+    difficulty: f64,
+    #[cfg(not(feature = "liquid"))]
+    #[serde(flatten)]
+    retarget: difficulty::Retarget,
+}
 
 #[derive(Serialize, Deserialize)]
 struct BlockValue {
@@ -74,6 +174,16 @@ struct BlockValue {
     bits: bitcoin::pow::CompactTarget,
     #[cfg(not(feature = "liquid"))]
     difficulty: f64,
+    #[cfg(not(feature = "liquid"))]
+    work: String,
+    #[cfg(not(feature = "liquid"))]
+    chainwork: String,
+    #[cfg(not(feature = "liquid"))]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    segwit_spend_percent: Option<f64>,
+    #[cfg(not(feature = "liquid"))]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    taproot_spend_percent: Option<f64>,
 
     #[cfg(feature = "liquid")]
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -109,11 +219,29 @@ impl BlockValue {
             nonce: header.nonce,
             #[cfg(not(feature = "liquid"))]
             difficulty: header.difficulty_float(),
+            #[cfg(not(feature = "liquid"))]
+            work: format_chainwork(header.work()),
+            #[cfg(not(feature = "liquid"))]
+            chainwork: format_chainwork(blockhm.header_entry.chainwork()),
+            #[cfg(not(feature = "liquid"))]
+            segwit_spend_percent: None,
+            #[cfg(not(feature = "liquid"))]
+            taproot_spend_percent: None,
 
             #[cfg(feature = "liquid")]
             ext: Some(header.ext.clone()),
         }
     }
+
+    // Include the segwit and taproot adoption figures, from the indexed stats of the block
+    #[cfg(not(feature = "liquid"))]
+    fn with_adoption(mut self, stats: Option<&BlockStats>) -> Self {
+        if let Some(stats) = stats {
+            self.segwit_spend_percent = stats.segwit_percent();
+            self.taproot_spend_percent = stats.taproot_percent();
+        }
+        self
+    }
 }
 
 #[derive(Serialize)]
@@ -127,7 +255,30 @@ struct TransactionValue {
     weight: u64,
     fee: u64,
     #[serde(skip_serializing_if = "Option::is_none")]
+    feerate: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     status: Option<TransactionStatus>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    balance_delta: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fiat: Option<FiatValue>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    replaced_by: Option<Txid>,
+    #[cfg(not(feature = "liquid"))]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    analysis: Option<TxAnalysisValue>,
+    // set when the transaction spends the funding output of a Lightning channel
+    #[cfg(not(feature = "liquid"))]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lightning_close: Option<lightning::CloseKind>,
+}
+
+#[cfg(not(feature = "liquid"))]
+#[derive(Serialize)]
+struct TxAnalysisValue {
+    sigops: SigopCounts,
+    standard: bool,
+    violations: Vec<&'static str>, // the reject reasons bitcoind would report
 }
 
 impl TransactionValue {
@@ -136,6 +287,18 @@ impl TransactionValue {
         blockid: Option<BlockId>,
         txos: &HashMap<OutPoint, TxOut>,
         config: &Config,
+    ) -> Self {
+        TransactionValue::with_fee(tx, blockid, txos, None, config)
+    }
+
+    // Use the fee when already known, rather than computing it from the prevouts (which
+    // may not have been looked up)
+    fn with_fee(
+        tx: Transaction,
+        blockid: Option<BlockId>,
+        txos: &HashMap<OutPoint, TxOut>,
+        fee: Option<u64>,
+        config: &Config,
     ) -> Self {
         let prevouts = extract_tx_prevouts(&tx, &txos, true);
         let vins: Vec<TxInValue> = tx
@@ -152,12 +315,18 @@ impl TransactionValue {
             .map(|txout| TxOutValue::new(txout, config))
             .collect();
 
-        let fee = get_tx_fee(&tx, &prevouts, config.network_type);
+        let fee = fee.unwrap_or_else(|| get_tx_fee(&tx, &prevouts, config.network_type));
 
         let weight = tx.weight();
         #[cfg(not(feature = "liquid"))] // rust-bitcoin has a wrapper Weight type
         let weight = weight.to_wu();
 
+        #[cfg(not(feature = "liquid"))]
+        let lightning_close = vins
+            .iter()
+            .position(|vin| vin.lightning == Some(lightning::InputKind::ChannelFunding))
+            .and_then(|funding_vin| lightning::close_kind(&tx, funding_vin));
+
         TransactionValue {
             txid: tx.txid(),
             #[cfg(not(feature = "liquid"))]
@@ -170,9 +339,64 @@ impl TransactionValue {
             size: tx.total_size() as u32,
             weight: weight as u64,
             fee,
+            feerate: None,
             status: Some(TransactionStatus::from(blockid)),
+            balance_delta: None,
+            fiat: None,
+            replaced_by: None,
+            #[cfg(not(feature = "liquid"))]
+            analysis: None,
+            #[cfg(not(feature = "liquid"))]
+            lightning_close,
         }
     }
+
+    // Include the sigop counts and the relay policy checks
+    #[cfg(not(feature = "liquid"))]
+    fn with_analysis(
+        mut self,
+        tx: &Transaction,
+        txos: &HashMap<OutPoint, TxOut>,
+        network: Network,
+    ) -> Self {
+        let prevouts = extract_tx_prevouts(tx, txos, true);
+        let sigops = tx_sigops(tx, &prevouts);
+        let violations = policy_violations(tx, &prevouts, &sigops, network.params());
+        self.analysis = Some(TxAnalysisValue {
+            sigops,
+            standard: violations.is_empty(),
+            violations,
+        });
+        self
+    }
+
+    // Include the feerate (in sat/vB), computed in millisatoshis per vbyte for an exact rounding
+    fn with_feerate(mut self) -> Self {
+        let msat_per_vbyte = self.fee.saturating_mul(4000) / self.weight.max(1);
+        self.feerate = Some(msat_per_vbyte as f64 / 1000.0);
+        self
+    }
+
+    // The net effect of the transaction on the balance of the given scripthash, or None
+    // if the value of one of its outputs or prevouts is not known (i.e. confidential)
+    fn balance_delta(&self, script_hash: &[u8]) -> Option<i64> {
+        let is_ours =
+            |txout: &&TxOutValue| compute_script_hash(&txout.scriptpubkey)[..] == *script_hash;
+        let received: u64 = self
+            .vout
+            .iter()
+            .filter(is_ours)
+            .map(TxOutValue::explicit_value)
+            .sum::<Option<u64>>()?;
+        let spent: u64 = self
+            .vin
+            .iter()
+            .filter_map(|txin| txin.prevout.as_ref())
+            .filter(is_ours)
+            .map(TxOutValue::explicit_value)
+            .sum::<Option<u64>>()?;
+        Some(received as i64 - spent as i64)
+    }
 }
 
 #[derive(Serialize, Clone)]
@@ -192,6 +416,11 @@ struct TxInValue {
     #[serde(skip_serializing_if = "Option::is_none")]
     inner_witnessscript_asm: Option<String>,
 
+    // the role of the spent output in a Lightning channel, if any
+    #[cfg(not(feature = "liquid"))]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lightning: Option<lightning::InputKind>,
+
     #[cfg(feature = "liquid")]
     is_pegin: bool,
     #[cfg(feature = "liquid")]
@@ -220,10 +449,18 @@ impl TxInValue {
 
         let innerscripts = prevout.map(|prevout| get_innerscripts(&txin, &prevout));
 
+        let spent_as = innerscripts
+            .as_ref()
+            .and_then(|i| i.redeem_script.as_ref())
+            .and_then(nested_script_type);
+
         TxInValue {
             txid: txin.previous_output.txid,
             vout: txin.previous_output.vout,
-            prevout: prevout.map(|prevout| TxOutValue::new(prevout, config)),
+            prevout: prevout.map(|prevout| TxOutValue {
+                spent_as,
+                ..TxOutValue::new(prevout, config)
+            }),
             scriptsig_asm: txin.script_sig.to_asm(),
             witness,
 
@@ -236,6 +473,9 @@ impl TxInValue {
                 .and_then(|i| i.witness_script.as_ref())
                 .map(ScriptToAsm::to_asm),
 
+            #[cfg(not(feature = "liquid"))]
+            lightning: prevout.and_then(|prevout| lightning::input_kind(txin, prevout)),
+
             is_coinbase,
             sequence: txin.sequence,
             #[cfg(feature = "liquid")]
@@ -261,6 +501,10 @@ struct TxOutValue {
     #[serde(skip_serializing_if = "Option::is_none")]
     scriptpubkey_address: Option<String>,
 
+    // the type of the script revealed when spending a P2SH output, for nested segwit
+    #[serde(skip_serializing_if = "Option::is_none")]
+    spent_as: Option<&'static str>,
+
     #[cfg(not(feature = "liquid"))]
     value: u64,
 
@@ -285,6 +529,42 @@ struct TxOutValue {
     pegout: Option<PegoutValue>,
 }
 
+// The outputs spent by a transaction, as found in block undo data
+#[derive(Serialize)]
+struct BlockSpendsValue {
+    txid: Txid,
+    prevouts: Vec<SpentOutputValue>,
+}
+
+#[derive(Serialize)]
+struct SpentOutputValue {
+    txid: Txid,
+    vout: u32,
+    #[serde(flatten)]
+    prevout: TxOutValue,
+}
+
+impl BlockSpendsValue {
+    fn new(tx: &Transaction, prevouts: &HashMap<OutPoint, TxOut>, config: &Config) -> Self {
+        BlockSpendsValue {
+            txid: tx.txid(),
+            prevouts: tx
+                .input
+                .iter()
+                .filter(|txin| has_prevout(txin))
+                .filter_map(|txin| {
+                    let prevout = prevouts.get(&txin.previous_output)?;
+                    Some(SpentOutputValue {
+                        txid: txin.previous_output.txid,
+                        vout: txin.previous_output.vout,
+                        prevout: TxOutValue::new(prevout, config),
+                    })
+                })
+                .collect(),
+        }
+    }
+}
+
 #[derive(Serialize)]
 struct AddressBalanceValue {
     confirm_amount: String,
@@ -293,11 +573,120 @@ struct AddressBalanceValue {
     confirm_coin_amount: String,
     pending_coin_amount: String,
     coin_amount: String,
+    confirm_sat_amount: u64,
+    pending_sat_amount: u64,
+    sat_amount: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fiat: Option<FiatValue>,
+}
+
+impl AddressBalanceValue {
+    fn new(confirmed: u64, pending: u64, unit: Unit, locale: Locale) -> Self {
+        let total = confirmed + pending;
+        let format = |amount: String| locale.format_amount(&amount);
+        AddressBalanceValue {
+            confirm_amount: format(unit.format(confirmed)),
+            pending_amount: format(unit.format(pending)),
+            amount: format(unit.format(total)),
+            confirm_coin_amount: format(format_coin_amount(confirmed)),
+            pending_coin_amount: format(format_coin_amount(pending)),
+            coin_amount: format(format_coin_amount(total)),
+            confirm_sat_amount: confirmed,
+            pending_sat_amount: pending,
+            sat_amount: total,
+            fiat: None,
+        }
+    }
+}
+
+// The unit amounts are formatted in, picked with ?unit=sat|coin
+#[derive(Clone, Copy)]
+enum Unit {
+    Sat,
+    Coin,
+}
+
+impl Unit {
+    fn from_params(query_params: &HashMap<String, String>) -> Result<Self, HttpError> {
+        match query_params.get("unit").map(String::as_str) {
+            None | Some("coin") => Ok(Unit::Coin),
+            Some("sat") => Ok(Unit::Sat),
+            Some(_) => Err(HttpError::from("Invalid unit, expected sat or coin".to_string())),
+        }
+    }
+
+    fn format(self, satoshis: u64) -> String {
+        match self {
+            Unit::Sat => satoshis.to_string(),
+            Unit::Coin => format_coin_amount(satoshis),
+        }
+    }
+}
+
+// The fiat equivalents of amounts, at the exchange rate as of some time
+#[derive(Serialize)]
+struct FiatValue {
+    currency: String,
+    rate: f64,
+    rate_time: u32,
+    #[serde(flatten)]
+    amounts: BTreeMap<&'static str, f64>,
+}
+
+// Convert the given amounts to the currency asked for with ?fiat=<currency>, at the rate as of
+// `time` (or the latest one)
+fn fiat_value(
+    query_params: &HashMap<String, String>,
+    query: &Query,
+    config: &Config,
+    time: Option<u32>,
+    amounts: &[(&'static str, u64)],
+) -> Result<Option<FiatValue>, HttpError> {
+    let currency = match query_params.get("fiat") {
+        Some(currency) => currency.to_uppercase(),
+        None => return Ok(None),
+    };
+    if config.price_feed.is_none() {
+        bail!(HttpError::from("price feed disabled".to_string()));
+    }
+    let point = match time {
+        Some(time) => price::price_at(query.chain(), time),
+        None => price::latest_price(query.chain()),
+    };
+    let rate = point
+        .as_ref()
+        .and_then(|point| point.rate(&currency))
+        .ok_or_else(|| HttpError::not_found(format!("No {} exchange rate", currency)))?;
+
+    Ok(Some(FiatValue {
+        amounts: amounts
+            .iter()
+            .map(|(name, satoshis)| (*name, *satoshis as f64 / SATS_PER_COIN as f64 * rate))
+            .collect(),
+        currency,
+        rate,
+        rate_time: point.map_or(0, |point| point.time),
+    }))
+}
+
+// The locale to serve the response in, among the --locales, according to its Accept-Language
+fn response_locale(headers: &hyper::HeaderMap, config: &Config) -> Locale {
+    headers
+        .get("Accept-Language")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|accept_language| Locale::negotiate(accept_language, &config.locales))
+        .unwrap_or(config.locales[0])
+}
+
+// Format satoshis as coins with 8 decimals, using integer math to avoid float rounding
+fn format_coin_amount(satoshis: u64) -> String {
+    format!("{}.{:08}", satoshis / SATS_PER_COIN, satoshis % SATS_PER_COIN)
 }
 
 #[derive(Serialize)]
 struct TotalCoinSupplyValue {
     total_amount: String,
+    total_sat_amount: u64,
     total_amount_float: f64,
     height: u32,
     block_hash: String,
@@ -315,9 +704,49 @@ struct AddressStatsValue {
     balance: u64,
     first_seen_tx_time: Option<u64>,
     last_seen_tx_time: Option<u64>,
+    #[cfg(not(feature = "liquid"))]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    spend_paths: Option<Vec<SpendPathValue>>,
+}
+
+#[cfg(not(feature = "liquid"))]
+#[derive(Serialize)]
+struct SpendPathValue {
+    path: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    leaf_hash: Option<String>,
+    count: usize,
+}
+
+#[cfg(not(feature = "liquid"))]
+impl From<(SpendPath, usize)> for SpendPathValue {
+    fn from((path, count): (SpendPath, usize)) -> Self {
+        let (path, leaf_hash) = match path {
+            SpendPath::KeyPath => ("key_path", None),
+            SpendPath::ScriptPath(leaf_hash) => ("script_path", Some(leaf_hash)),
+            SpendPath::IfBranch => ("if_branch", None),
+            SpendPath::ElseBranch => ("else_branch", None),
+            SpendPath::WitnessScript => ("witness_script", None),
+        };
+        SpendPathValue {
+            path,
+            leaf_hash: leaf_hash.map(|hash| hash.to_lower_hex_string()),
+            count,
+        }
+    }
 }
 
 impl TxOutValue {
+    #[cfg(not(feature = "liquid"))]
+    fn explicit_value(&self) -> Option<u64> {
+        Some(self.value)
+    }
+
+    #[cfg(feature = "liquid")]
+    fn explicit_value(&self) -> Option<u64> {
+        self.value
+    }
+
     fn new(txout: &TxOut, config: &Config) -> Self {
         #[cfg(not(feature = "liquid"))]
         let value = txout.value.to_sat();
@@ -333,30 +762,7 @@ impl TxOutValue {
         let script_asm = script.to_asm();
         let script_addr = script.to_address_str(config.network_type);
 
-        // TODO should the following something to put inside rust-elements lib?
-        let script_type = if is_fee {
-            "fee"
-        } else if script.is_empty() {
-            "empty"
-        } else if script.is_op_return() {
-            "op_return"
-        } else if script.is_p2pk() {
-            "p2pk"
-        } else if script.is_p2pkh() {
-            "p2pkh"
-        } else if script.is_p2sh() {
-            "p2sh"
-        } else if script.is_p2wpkh() {
-            "v0_p2wpkh"
-        } else if script.is_p2wsh() {
-            "v0_p2wsh"
-        } else if script.is_p2tr() {
-            "v1_p2tr"
-        } else if script.is_provably_unspendable() {
-            "provably_unspendable"
-        } else {
-            "unknown"
-        };
+        let script_type = if is_fee { "fee" } else { script_type(script) };
 
         #[cfg(feature = "liquid")]
         let pegout = PegoutValue::from_txout(txout, config.network_type, config.parent_network);
@@ -366,6 +772,7 @@ impl TxOutValue {
             scriptpubkey_asm: script_asm,
             scriptpubkey_address: script_addr,
             scriptpubkey_type: script_type.to_string(),
+            spent_as: None,
             value,
             #[cfg(feature = "liquid")]
             valuecommitment: txout.value.commitment(),
@@ -379,6 +786,161 @@ impl TxOutValue {
     }
 }
 
+// TODO should the following something to put inside rust-elements lib?
+fn script_type(script: &Script) -> &'static str {
+    if script.is_empty() {
+        "empty"
+    } else if script.is_op_return() {
+        "op_return"
+    } else if script.is_p2pk() {
+        "p2pk"
+    } else if script.is_p2pkh() {
+        "p2pkh"
+    } else if script.is_p2sh() {
+        "p2sh"
+    } else if script.is_p2wpkh() {
+        "v0_p2wpkh"
+    } else if script.is_p2wsh() {
+        "v0_p2wsh"
+    } else if script.is_p2tr() {
+        "v1_p2tr"
+    } else if script.is_provably_unspendable() {
+        "provably_unspendable"
+    } else {
+        "unknown"
+    }
+}
+
+// The nested segwit type of a P2SH output, going by the redeem script revealed by its spend
+fn nested_script_type(redeem_script: &Script) -> Option<&'static str> {
+    if redeem_script.is_p2wpkh() {
+        Some("p2sh-p2wpkh")
+    } else if redeem_script.is_p2wsh() {
+        Some("p2sh-p2wsh")
+    } else {
+        None
+    }
+}
+
+#[cfg(not(feature = "liquid"))]
+#[derive(Serialize)]
+struct AddressValidationValue {
+    isvalid: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    address: Option<String>,
+    #[serde(rename = "scriptPubKey", skip_serializing_if = "Option::is_none")]
+    scriptpubkey: Option<Script>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    scriptpubkey_type: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    isscript: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    iswitness: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    witness_version: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    witness_program: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[cfg(not(feature = "liquid"))]
+impl AddressValidationValue {
+    fn new(addr: &str, network: Network) -> Self {
+        let addr = match address::Address::from_str(addr) {
+            Ok(addr) if addr.is_valid_for_network(network.into()) => addr.assume_checked(),
+            Ok(_) => return Self::invalid("Address on invalid network".to_string()),
+            Err(err) => return Self::invalid(err.to_string()),
+        };
+        let script = addr.script_pubkey();
+        let witness_version = script.witness_version();
+
+        AddressValidationValue {
+            isvalid: true,
+            address: Some(addr.to_string()),
+            scriptpubkey_type: Some(script_type(&script)),
+            isscript: Some(script.is_p2sh() || script.is_p2wsh()),
+            iswitness: Some(witness_version.is_some()),
+            witness_version: witness_version.map(|version| version.to_num()),
+            // skip the version opcode and the push of the program
+            witness_program: witness_version
+                .map(|_| script.as_bytes()[2..].to_lower_hex_string()),
+            scriptpubkey: Some(script),
+            error: None,
+        }
+    }
+
+    fn invalid(error: String) -> Self {
+        AddressValidationValue {
+            isvalid: false,
+            address: None,
+            scriptpubkey: None,
+            scriptpubkey_type: None,
+            isscript: None,
+            iswitness: None,
+            witness_version: None,
+            witness_program: None,
+            error: Some(error),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct ReindexScripthashRequest {
+    scripthash: String, // as in GET /scripthash/{hash}
+    #[serde(default)]
+    dry_run: bool,
+}
+
+#[cfg(not(feature = "liquid"))]
+#[derive(Deserialize)]
+struct ScripthashDeltaRequest {
+    scripthash: String, // in the Electrum encoding
+    since_height: usize,
+}
+
+#[cfg(not(feature = "liquid"))]
+#[derive(Deserialize)]
+struct PaymentRequest {
+    address: String,
+    amount: u64, // in satoshis
+}
+
+#[cfg(not(feature = "liquid"))]
+#[derive(Serialize, Deserialize)]
+struct ScriptTemplateValue {
+    name: String,
+    pattern: String,
+}
+
+#[cfg(not(feature = "liquid"))]
+impl From<ScriptTemplate> for ScriptTemplateValue {
+    fn from(template: ScriptTemplate) -> Self {
+        ScriptTemplateValue {
+            name: template.name,
+            pattern: template.pattern,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct LargeTxValue {
+    txid: Txid,
+    value: u64,
+    status: TransactionStatus,
+}
+
+#[cfg(not(feature = "liquid"))]
+impl LargeTxValue {
+    fn new(txid: Txid, value: u64, blockid: Option<BlockId>) -> Self {
+        LargeTxValue {
+            txid,
+            value,
+            status: TransactionStatus::from(blockid),
+        }
+    }
+}
+
 #[derive(Serialize)]
 struct UtxoValue {
     txid: Txid,
@@ -416,6 +978,14 @@ struct UtxoValue {
     #[cfg(feature = "liquid")]
     #[serde(skip_serializing_if = "Option::is_none")]
     range_proof: Option<zkp::RangeProof>,
+
+    #[cfg(not(feature = "liquid"))]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    timelocks: Vec<OutputTimelockValue>,
+
+    // the spend of a recently orphaned block, which may get confirmed again
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_spent_in_orphaned: Option<Txid>,
 }
 impl From<Utxo> for UtxoValue {
     fn from(utxo: Utxo) -> Self {
@@ -441,47 +1011,627 @@ impl From<Utxo> for UtxoValue {
             surjection_proof: utxo.witness.surjection_proof.map(|p| *p),
             #[cfg(feature = "liquid")]
             range_proof: utxo.witness.rangeproof.map(|p| *p),
+
+            #[cfg(not(feature = "liquid"))]
+            timelocks: vec![],
+
+            last_spent_in_orphaned: None,
         }
     }
 }
 
 #[derive(Serialize)]
-struct SpendingValue {
-    spent: bool,
+struct RevealedScriptValue {
+    txid: Txid,
+    vin: u32,
+    status: TransactionStatus,
     #[serde(skip_serializing_if = "Option::is_none")]
-    txid: Option<Txid>,
+    redeemscript: Option<Script>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    vin: Option<u32>,
+    redeemscript_asm: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    status: Option<TransactionStatus>,
+    witnessscript: Option<Script>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    witnessscript_asm: Option<String>,
 }
-impl From<SpendingInput> for SpendingValue {
-    fn from(spend: SpendingInput) -> Self {
-        SpendingValue {
-            spent: true,
-            txid: Some(spend.txid),
-            vin: Some(spend.vin),
-            status: Some(TransactionStatus::from(spend.confirmed)),
+
+impl From<RevealedScript> for RevealedScriptValue {
+    fn from(revealed: RevealedScript) -> Self {
+        RevealedScriptValue {
+            txid: revealed.txid,
+            vin: revealed.vin,
+            status: TransactionStatus::from(Some(revealed.confirmed)),
+            redeemscript_asm: revealed.redeem_script.as_ref().map(ScriptToAsm::to_asm),
+            redeemscript: revealed.redeem_script,
+            witnessscript_asm: revealed.witness_script.as_ref().map(ScriptToAsm::to_asm),
+            witnessscript: revealed.witness_script,
         }
     }
 }
-impl Default for SpendingValue {
-    fn default() -> Self {
-        SpendingValue {
-            spent: false,
-            txid: None,
-            vin: None,
-            status: None,
-        }
-    }
+
+// A timelock constraining the spend of an output, found in its revealed inner script
+#[cfg(not(feature = "liquid"))]
+#[derive(Serialize)]
+struct OutputTimelockValue {
+    op: &'static str,
+    #[serde(flatten)]
+    timelock: Timelock,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    earliest_height: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    earliest_mtp: Option<u32>,
 }
 
-fn ttl_by_depth(height: Option<usize>, query: &Query) -> u32 {
-    height.map_or(TTL_SHORT, |height| {
-        if query.chain().best_height() - height >= CONF_FINAL {
-            TTL_LONG
+#[cfg(not(feature = "liquid"))]
+impl OutputTimelockValue {
+    // Relative timelocks can only be resolved once the output confirmed
+    fn new(script_timelock: ScriptTimelock, confirmed: Option<&BlockId>, query: &Query) -> Self {
+        let (op, timelock, earliest_height, earliest_mtp) = match script_timelock {
+            ScriptTimelock::Absolute(timelock @ Timelock::Height(height)) => {
+                ("cltv", timelock, Some(height as usize + 1), None)
+            }
+            ScriptTimelock::Absolute(timelock @ Timelock::Time(time)) => {
+                ("cltv", timelock, None, Some(time + 1))
+            }
+            ScriptTimelock::Relative(timelock @ Timelock::Height(blocks)) => (
+                "csv",
+                timelock,
+                confirmed.map(|b| b.height + blocks as usize),
+                None,
+            ),
+            ScriptTimelock::Relative(timelock @ Timelock::Time(seconds)) => (
+                "csv",
+                timelock,
+                None,
+                confirmed.map(|b| query.chain().get_mtp(b.height.saturating_sub(1)) + seconds),
+            ),
+        };
+        OutputTimelockValue {
+            op,
+            timelock,
+            earliest_height,
+            earliest_mtp,
+        }
+    }
+}
+
+#[cfg(not(feature = "liquid"))]
+#[derive(Serialize)]
+struct UtxoSummaryValue {
+    count: usize,
+    value: u64,
+    dust_feerate: u64,
+    dust_threshold: u64,
+    dust_count: usize,
+    dust_value: u64,
+    buckets: Vec<UtxoBucketValue>,
+}
+
+#[cfg(not(feature = "liquid"))]
+#[derive(Serialize)]
+struct UtxoBucketValue {
+    min_value: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_value: Option<u64>,
+    count: usize,
+    value: u64,
+}
+
+#[cfg(not(feature = "liquid"))]
+impl UtxoSummaryValue {
+    // outputs worth less than dust_threshold cost more in fees than they are worth
+    fn new(values: &[u64], dust_feerate: u64, dust_threshold: u64) -> Self {
+        let mut buckets: Vec<UtxoBucketValue> = std::iter::once(0)
+            .chain(UTXO_SUMMARY_BUCKETS.iter().copied())
+            .zip(UTXO_SUMMARY_BUCKETS.iter().copied().map(Some).chain(std::iter::once(None)))
+            .map(|(min_value, max_value)| UtxoBucketValue {
+                min_value,
+                max_value,
+                count: 0,
+                value: 0,
+            })
+            .collect();
+        let mut summary = UtxoSummaryValue {
+            count: values.len(),
+            value: values.iter().sum(),
+            dust_feerate,
+            dust_threshold,
+            dust_count: 0,
+            dust_value: 0,
+            buckets: vec![],
+        };
+        for &value in values {
+            if value < dust_threshold {
+                summary.dust_count += 1;
+                summary.dust_value += value;
+            }
+            let bucket = UTXO_SUMMARY_BUCKETS
+                .iter()
+                .position(|&max_value| value < max_value)
+                .unwrap_or(UTXO_SUMMARY_BUCKETS.len());
+            buckets[bucket].count += 1;
+            buckets[bucket].value += value;
+        }
+        summary.buckets = buckets;
+        summary
+    }
+}
+
+// The estimated cost of sending payments in a transaction each, versus in a single batched one
+#[cfg(not(feature = "liquid"))]
+#[derive(Serialize)]
+struct BatchingReportValue {
+    payment_count: usize,
+    payment_value: u64,
+    input_type: String,
+    conf_target: Option<u16>, // None when the feerate was given
+    feerate: f64,
+    payments: Vec<BatchingPaymentValue>,
+    individual: BatchingCostValue,
+    batched: BatchingCostValue,
+    vsize_savings: u64,
+    fee_savings: u64,
+    savings_percent: f64,
+}
+
+#[cfg(not(feature = "liquid"))]
+#[derive(Serialize)]
+struct BatchingPaymentValue {
+    address: String,
+    amount: u64,
+    vsize: u64, // of the transaction sending this payment alone
+    fee: u64,
+}
+
+#[cfg(not(feature = "liquid"))]
+#[derive(Serialize)]
+struct BatchingCostValue {
+    tx_count: usize,
+    vsize: u64,
+    fee: u64,
+}
+
+#[cfg(not(feature = "liquid"))]
+impl BatchingReportValue {
+    // payments are given with the virtual size of their output, along with their total value
+    fn new(
+        payments: Vec<(PaymentRequest, u64)>,
+        payment_value: u64,
+        input_type: &str,
+        conf_target: Option<u16>,
+        feerate: f64,
+    ) -> Result<Self, HttpError> {
+        let funding_script = template_script(input_type)
+            .ok_or_else(|| HttpError::from(format!("Invalid input_type {}", input_type)))?;
+        let input_vsize = spending_vsize(&funding_script);
+        let change_vsize = output_vsize(&funding_script);
+        let segwit = spends_with_witness(&funding_script);
+
+        let fee = |vsize: u64| (vsize as f64 * feerate).ceil() as u64;
+        let tx_vsize = |output_count: usize, outputs_vsize: u64| {
+            let outputs_vsize = outputs_vsize + change_vsize;
+            estimate_tx_vsize(1, input_vsize, output_count + 1, outputs_vsize, segwit)
+        };
+
+        let batched_vsize = tx_vsize(
+            payments.len(),
+            payments.iter().map(|(_, output_vsize)| output_vsize).sum(),
+        );
+        let batched = BatchingCostValue {
+            tx_count: 1,
+            vsize: batched_vsize,
+            fee: fee(batched_vsize),
+        };
+        let payments: Vec<BatchingPaymentValue> = payments
+            .into_iter()
+            .map(|(payment, output_vsize)| {
+                let vsize = tx_vsize(1, output_vsize);
+                BatchingPaymentValue {
+                    address: payment.address,
+                    amount: payment.amount,
+                    vsize,
+                    fee: fee(vsize),
+                }
+            })
+            .collect();
+        let individual = BatchingCostValue {
+            tx_count: payments.len(),
+            vsize: payments.iter().map(|payment| payment.vsize).sum(),
+            fee: payments.iter().map(|payment| payment.fee).sum(),
+        };
+
+        Ok(BatchingReportValue {
+            payment_count: payments.len(),
+            payment_value,
+            input_type: input_type.to_string(),
+            conf_target,
+            feerate,
+            vsize_savings: individual.vsize.saturating_sub(batched.vsize),
+            fee_savings: individual.fee.saturating_sub(batched.fee),
+            savings_percent: if individual.fee > 0 {
+                individual.fee.saturating_sub(batched.fee) as f64 * 100.0 / individual.fee as f64
+            } else {
+                0.0
+            },
+            payments,
+            individual,
+            batched,
+        })
+    }
+}
+
+// A script of the given type, with a zeroed out hash or key, for its size
+#[cfg(not(feature = "liquid"))]
+fn template_script(script_type: &str) -> Option<Script> {
+    let (prefix, len, suffix): (&[u8], usize, &[u8]) = match script_type {
+        "p2pkh" => (&[0x76, 0xa9, 0x14], 20, &[0x88, 0xac]),
+        "p2sh" => (&[0xa9, 0x14], 20, &[0x87]),
+        "p2wpkh" => (&[0x00, 0x14], 20, &[]),
+        "p2tr" => (&[0x51, 0x20], 32, &[]),
+        _ => return None,
+    };
+    Some(Script::from([prefix, &vec![0; len], suffix].concat()))
+}
+
+// Aggregated stats over a range of consecutive blocks
+#[derive(Serialize)]
+struct BlockStatsValue {
+    start_height: u32,
+    end_height: u32,
+    start_time: u32,
+    end_time: u32,
+    block_count: usize,
+    avg_size: f64,
+    avg_weight: f64,
+    tx_count: u64,
+    input_count: u64,
+    output_count: u64,
+    total_fee: u64,
+    median_feerate: f64, // median of the per-block median feerates
+    coin_days_destroyed: f64,
+    segwit_input_count: u64,
+    taproot_input_count: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    segwit_percent: Option<f64>, // of the inputs spent by the blocks
+    #[serde(skip_serializing_if = "Option::is_none")]
+    taproot_percent: Option<f64>,
+}
+
+impl BlockStatsValue {
+    fn new(blocks: &[BlockStats]) -> Self {
+        let (first, last) = (&blocks[0], &blocks[blocks.len() - 1]);
+        let sum = |f: fn(&BlockStats) -> u64| blocks.iter().map(f).sum::<u64>();
+        let mut feerates: Vec<f64> = blocks.iter().map(|b| b.median_feerate).collect();
+        feerates.sort_unstable_by(|a, b| a.partial_cmp(b).unwrap());
+        // excluding the coinbase input of each block
+        let spending_count = sum(|b| b.input_count as u64) - blocks.len() as u64;
+        let share = |count: u64| {
+            (spending_count > 0).then(|| count as f64 * 100.0 / spending_count as f64)
+        };
+        let segwit_input_count = sum(|b| b.segwit_input_count as u64);
+        let taproot_input_count = sum(|b| b.taproot_input_count as u64);
+
+        BlockStatsValue {
+            start_height: first.height,
+            end_height: last.height,
+            start_time: first.time,
+            end_time: last.time,
+            block_count: blocks.len(),
+            avg_size: sum(|b| b.size as u64) as f64 / blocks.len() as f64,
+            avg_weight: sum(|b| b.weight as u64) as f64 / blocks.len() as f64,
+            tx_count: sum(|b| b.tx_count as u64),
+            input_count: sum(|b| b.input_count as u64),
+            output_count: sum(|b| b.output_count as u64),
+            total_fee: sum(|b| b.total_fee),
+            median_feerate: median(&feerates),
+            coin_days_destroyed: blocks.iter().map(|b| b.coin_days_destroyed).sum(),
+            segwit_input_count,
+            taproot_input_count,
+            segwit_percent: share(segwit_input_count),
+            taproot_percent: share(taproot_input_count),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct DailyStatsValue {
+    date: String,
+    timestamp: u64,
+    block_count: u32,
+    tx_count: u64,
+    #[cfg(not(feature = "liquid"))]
+    output_volume: u64,
+    total_fee: u64,
+    new_address_count: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    avg_block_interval: Option<f64>,
+}
+
+impl From<&DailyStats> for DailyStatsValue {
+    fn from(stats: &DailyStats) -> Self {
+        let timestamp = stats.day as u64 * 86_400;
+        let date = time::OffsetDateTime::from_unix_timestamp(timestamp as i64)
+            .map(|date| date.date().to_string())
+            .unwrap_or_default();
+        DailyStatsValue {
+            date,
+            timestamp,
+            block_count: stats.block_count,
+            tx_count: stats.tx_count,
+            #[cfg(not(feature = "liquid"))]
+            output_volume: stats.output_volume,
+            total_fee: stats.total_fee,
+            new_address_count: stats.new_address_count,
+            avg_block_interval: stats.avg_block_interval(),
+        }
+    }
+}
+
+// Group the stats of blocks into buckets of `interval` heights, starting at `start_height`
+fn aggregate_block_stats(
+    stats: Vec<BlockStats>,
+    start_height: usize,
+    interval: usize,
+) -> Vec<BlockStatsValue> {
+    let mut buckets: Vec<Vec<BlockStats>> = vec![];
+    let mut current_bucket = None;
+    for block in stats {
+        let bucket = (block.height as usize - start_height) / interval;
+        if current_bucket != Some(bucket) {
+            current_bucket = Some(bucket);
+            buckets.push(vec![]);
+        }
+        buckets.last_mut().unwrap().push(block);
+    }
+    buckets
+        .iter()
+        .map(|blocks| BlockStatsValue::new(blocks))
+        .collect()
+}
+
+// Blocks may be timestamped up to 2 hours ahead of the network time
+const MAX_FUTURE_BLOCK_TIME: i64 = 2 * 60 * 60;
+const MAX_INTERVAL_ANOMALIES: usize = 100;
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+enum TimestampAnomaly {
+    NegativeInterval, // timestamped before its parent
+    FutureTime,       // timestamped more than 2 hours ahead of the current time
+}
+
+#[derive(Serialize)]
+struct TimestampAnomalyValue {
+    height: usize,
+    hash: BlockHash,
+    time: u32,
+    interval: Option<i64>,
+    anomaly: TimestampAnomaly,
+}
+
+#[derive(Serialize)]
+struct BlockIntervalsValue {
+    start_height: usize,
+    end_height: usize,
+    block_count: usize,
+    min_interval: Option<i64>,
+    median_interval: Option<f64>,
+    max_interval: Option<i64>,
+    avg_interval: Option<f64>,
+    anomaly_count: usize,
+    anomalies: Vec<TimestampAnomalyValue>, // the most recent ones first
+}
+
+// The intervals between the blocks in the height range (inclusive) and their parents
+fn block_intervals(
+    query: &Query,
+    start_height: usize,
+    end_height: usize,
+) -> Result<BlockIntervalsValue, errors::Error> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs() as i64);
+    let chain = query.chain();
+
+    let mut intervals = vec![];
+    let mut anomalies = vec![];
+    let mut prev_time = start_height
+        .checked_sub(1)
+        .and_then(|height| chain.header_by_height(height))
+        .map(|header| header.header().time as i64);
+    for height in start_height..=end_height {
+        // a concurrent reorg may have just shortened the best chain
+        let header = chain.header_by_height(height).ok_or_else(|| {
+            errors::Error::from(format!("no best chain header at height {} (reorged?)", height))
+        })?;
+        let time = header.header().time as i64;
+        let interval = prev_time.map(|prev_time| time - prev_time);
+        prev_time = Some(time);
+        intervals.extend(interval);
+
+        let anomaly = if interval.map_or(false, |interval| interval < 0) {
+            TimestampAnomaly::NegativeInterval
+        } else if time > now + MAX_FUTURE_BLOCK_TIME {
+            TimestampAnomaly::FutureTime
         } else {
-            TTL_SHORT
+            continue;
+        };
+        anomalies.push(TimestampAnomalyValue {
+            height,
+            hash: *header.hash(),
+            time: time as u32,
+            interval,
+            anomaly,
+        });
+    }
+
+    let anomaly_count = anomalies.len();
+    anomalies.reverse();
+    anomalies.truncate(MAX_INTERVAL_ANOMALIES);
+
+    let mut sorted: Vec<f64> = intervals.iter().map(|interval| *interval as f64).collect();
+    sorted.sort_unstable_by(|a, b| a.partial_cmp(b).unwrap());
+    Ok(BlockIntervalsValue {
+        start_height,
+        end_height,
+        block_count: end_height + 1 - start_height,
+        min_interval: intervals.iter().min().copied(),
+        median_interval: if sorted.is_empty() {
+            None
+        } else {
+            Some(median(&sorted))
+        },
+        max_interval: intervals.iter().max().copied(),
+        avg_interval: if intervals.is_empty() {
+            None
+        } else {
+            Some(intervals.iter().sum::<i64>() as f64 / intervals.len() as f64)
+        },
+        anomaly_count,
+        anomalies,
+    })
+}
+
+#[derive(Serialize)]
+struct SpendingValue {
+    spent: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    txid: Option<Txid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    vin: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<TransactionStatus>,
+}
+impl From<SpendingInput> for SpendingValue {
+    fn from(spend: SpendingInput) -> Self {
+        SpendingValue {
+            spent: true,
+            txid: Some(spend.txid),
+            vin: Some(spend.vin),
+            status: Some(TransactionStatus::from(spend.confirmed)),
+        }
+    }
+}
+impl Default for SpendingValue {
+    fn default() -> Self {
+        SpendingValue {
+            spent: false,
+            txid: None,
+            vin: None,
+            status: None,
+        }
+    }
+}
+
+// The timelocks of a transaction and when they allow it to be mined. Heights are the lowest
+// height of a block that may include the transaction, times the lowest median time past
+// of the block preceding it (per BIP113).
+#[derive(Serialize)]
+struct TimelocksValue {
+    locktime: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    absolute: Option<Timelock>,
+    inputs: Vec<InputTimelockValue>,
+    earliest_height: usize,
+    earliest_mtp: u32,
+    satisfied: bool, // whether the next block may include it
+}
+
+#[derive(Serialize)]
+struct InputTimelockValue {
+    vin: u32,
+    sequence: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    relative: Option<Timelock>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    earliest_height: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    earliest_mtp: Option<u32>,
+}
+
+impl TimelocksValue {
+    fn new(tx: &Transaction, query: &Query) -> Self {
+        let chain = query.chain();
+        let tip_height = chain.best_height();
+
+        let absolute = absolute_timelock(tx);
+        let (mut earliest_height, mut earliest_mtp) = match absolute {
+            Some(Timelock::Height(height)) => (height as usize + 1, 0),
+            Some(Timelock::Time(time)) => (0, time + 1),
+            None => (0, 0),
+        };
+
+        let inputs: Vec<InputTimelockValue> = tx
+            .input
+            .iter()
+            .enumerate()
+            .map(|(vin, txin)| {
+                let relative = relative_timelock(tx, txin);
+                // unconfirmed prevouts are assumed to confirm in the next block, like bitcoind does
+                let prevout_height = || {
+                    query
+                        .get_tx_status(&txin.previous_output.txid)
+                        .block_height
+                        .unwrap_or(tip_height + 1)
+                };
+                let (height, mtp) = match relative {
+                    Some(Timelock::Height(blocks)) => {
+                        (Some(prevout_height() + blocks as usize), None)
+                    }
+                    Some(Timelock::Time(seconds)) => {
+                        let prevout_mtp = chain.get_mtp(prevout_height().saturating_sub(1));
+                        (None, Some(prevout_mtp + seconds))
+                    }
+                    None => (None, None),
+                };
+                earliest_height = earliest_height.max(height.unwrap_or(0));
+                earliest_mtp = earliest_mtp.max(mtp.unwrap_or(0));
+                InputTimelockValue {
+                    vin: vin as u32,
+                    sequence: txin.sequence.0,
+                    relative,
+                    earliest_height: height,
+                    earliest_mtp: mtp,
+                }
+            })
+            .collect();
+
+        let satisfied =
+            earliest_height <= tip_height + 1 && earliest_mtp <= chain.get_mtp(tip_height);
+
+        TimelocksValue {
+            locktime: tx.lock_time.to_consensus_u32(),
+            absolute,
+            inputs,
+            earliest_height,
+            earliest_mtp,
+            satisfied,
+        }
+    }
+}
+
+// The spends of the outputs of the transaction, if known
+fn tx_outspends(query: &Query, txid: &str) -> Result<Vec<SpendingValue>, errors::Error> {
+    let txid = match Txid::from_str(txid) {
+        Ok(txid) => txid,
+        Err(_) => return Ok(vec![]),
+    };
+    Ok(match query.lookup_tx_outputs(&txid) {
+        Some(outputs) => query
+            .lookup_tx_spends(&txid, &outputs)?
+            .into_iter()
+            .map(|spend| spend.map_or_else(SpendingValue::default, SpendingValue::from))
+            .collect(),
+        None => vec![],
+    })
+}
+
+fn ttl_by_depth(height: Option<usize>, query: &Query, cache_ttl: CacheTtl) -> u32 {
+    height.map_or(cache_ttl.short, |height| {
+        if query.chain().best_height() - height >= CONF_FINAL {
+            cache_ttl.long
+        } else {
+            cache_ttl.short
         }
     })
 }
@@ -504,44 +1654,304 @@ fn prepare_txs(
     let prevouts = query.lookup_txos(&outpoints);
 
     txs.into_iter()
-        .map(|(tx, blockid)| TransactionValue::new(tx, blockid, &prevouts, config))
+        .map(|(tx, blockid)| {
+            let replaced_by = match blockid {
+                Some(_) => None,
+                None => query.mempool().replaced_by(&tx.txid()),
+            };
+            TransactionValue {
+                replaced_by,
+                ..TransactionValue::new(tx, blockid, &prevouts, config)
+            }
+        })
+        .collect()
+}
+
+// Annotate the UTXOs of the scripthash with the timelocks of its revealed inner script, if any,
+// and with their spends in orphaned blocks if `with_orphaned` is set
+#[cfg(not(feature = "liquid"))]
+fn prepare_utxos(
+    utxos: Vec<Utxo>,
+    script_hash: &[u8],
+    with_orphaned: bool,
+    query: &Query,
+) -> Vec<UtxoValue> {
+    let script_timelocks = match query.chain().revealed_script(script_hash) {
+        Some(revealed) if !utxos.is_empty() => revealed
+            .witness_script
+            .or(revealed.redeem_script)
+            .map_or_else(Vec::new, |script| script_timelocks(&script)),
+        _ => vec![],
+    };
+
+    utxos
+        .into_iter()
+        .map(|utxo| {
+            let timelocks = script_timelocks
+                .iter()
+                .map(|timelock| OutputTimelockValue::new(*timelock, utxo.confirmed.as_ref(), query))
+                .collect();
+            let last_spent_in_orphaned = if with_orphaned {
+                query.lookup_orphaned_spend(&OutPoint::from(&utxo))
+            } else {
+                None
+            };
+            UtxoValue {
+                timelocks,
+                last_spent_in_orphaned,
+                ..UtxoValue::from(utxo)
+            }
+        })
         .collect()
 }
 
+#[cfg(feature = "liquid")]
+fn prepare_utxos(
+    utxos: Vec<Utxo>,
+    _script_hash: &[u8],
+    _with_orphaned: bool,
+    _query: &Query,
+) -> Vec<UtxoValue> {
+    utxos.into_iter().map(UtxoValue::from).collect()
+}
+
+// Like prepare_txs(), for the transactions of a block with their indexed fees (if available).
+// The prevouts are only resolved if requested or needed to compute the fees.
+fn prepare_block_txs(
+    txs: Vec<(Transaction, Option<BlockId>)>,
+    fees: Option<Vec<u64>>,
+    with_prevouts: bool,
+    query: &Query,
+    config: &Config,
+) -> Vec<TransactionValue> {
+    let fees = match fees {
+        Some(fees) if fees.len() >= txs.len() => fees,
+        _ => {
+            return prepare_txs(txs, query, config)
+                .into_iter()
+                .map(TransactionValue::with_feerate)
+                .collect()
+        }
+    };
+    let prevouts = if with_prevouts {
+        let outpoints = txs
+            .iter()
+            .flat_map(|(tx, _)| {
+                tx.input
+                    .iter()
+                    .filter(|txin| has_prevout(txin))
+                    .map(|txin| txin.previous_output)
+            })
+            .collect();
+        query.lookup_txos(&outpoints)
+    } else {
+        HashMap::new()
+    };
+
+    txs.into_iter()
+        .zip(fees)
+        .map(|((tx, blockid), fee)| {
+            TransactionValue::with_fee(tx, blockid, &prevouts, Some(fee), config).with_feerate()
+        })
+        .collect()
+}
+
+// Like prepare_txs, but also includes the net effect of each transaction on the balance
+// of the scripthash whose history is being listed
+fn prepare_script_txs(
+    txs: Vec<(Transaction, Option<BlockId>)>,
+    script_hash: &[u8],
+    query: &Query,
+    config: &Config,
+) -> Vec<TransactionValue> {
+    let mut txs = prepare_txs(txs, query, config);
+    for tx in txs.iter_mut() {
+        tx.balance_delta = tx.balance_delta(script_hash);
+    }
+    txs
+}
+
+// An additional network served by the same process, under the /<prefix>/ routes
+pub struct NetworkMount {
+    pub prefix: String,
+    pub config: Arc<Config>,
+    pub query: Arc<Query>,
+    pub electrum_sessions: Option<Arc<Sessions>>,
+}
+
+// The additional network the request is for, along with the URI without the network prefix
+fn route_network<'a>(
+    networks: &'a [NetworkMount],
+    uri: &hyper::Uri,
+) -> Option<(&'a NetworkMount, hyper::Uri)> {
+    let path_and_query = uri.path_and_query()?.as_str().strip_prefix('/')?;
+    networks.iter().find_map(|network| {
+        let rest = path_and_query.strip_prefix(network.prefix.as_str())?;
+        let uri = match rest.chars().next() {
+            None => "/".to_string(),
+            Some('/') => rest.to_string(),
+            Some('?') => format!("/{}", rest),
+            Some(_) => return None,
+        };
+        Some((network, uri.parse().ok()?))
+    })
+}
+
 #[tokio::main]
-async fn run_server(config: Arc<Config>, query: Arc<Query>, rx: oneshot::Receiver<()>) {
+async fn run_server(
+    config: Arc<Config>,
+    query: Arc<Query>,
+    electrum_sessions: Option<Arc<Sessions>>,
+    networks: Vec<NetworkMount>,
+    rx: oneshot::Receiver<()>,
+) {
     let addr = &config.http_addr;
     let socket_file = &config.http_socket_file;
 
     let config = Arc::clone(&config);
     let query = Arc::clone(&query);
+    let networks = Arc::new(networks);
 
-    let make_service_fn_inn = || {
+    // the IP of the client, unknown on unix sockets
+    let make_service_fn_inn = |remote_ip: Option<IpAddr>| {
         let query = Arc::clone(&query);
         let config = Arc::clone(&config);
+        let electrum_sessions = electrum_sessions.clone();
+        let networks = Arc::clone(&networks);
 
         async move {
             Ok::<_, hyper::Error>(service_fn(move |req| {
-                let query = Arc::clone(&query);
-                let config = Arc::clone(&config);
+                let (query, config, electrum_sessions, uri) =
+                    match route_network(&networks, req.uri()) {
+                        Some((network, uri)) => (
+                            Arc::clone(&network.query),
+                            Arc::clone(&network.config),
+                            network.electrum_sessions.clone(),
+                            uri,
+                        ),
+                        None => (
+                            Arc::clone(&query),
+                            Arc::clone(&config),
+                            electrum_sessions.clone(),
+                            req.uri().clone(),
+                        ),
+                    };
 
                 async move {
                     let method = req.method().clone();
-                    let uri = req.uri().clone();
-                    let body = hyper::body::to_bytes(req.into_body()).await?;
-
-                    let mut resp = handle_request(method, uri, body, &query, &config)
-                        .unwrap_or_else(|err| {
+                    let request_id = request_id(req.headers());
+                    let binary_format = BinaryFormat::from_accept(req.headers());
+                    let origin = req
+                        .headers()
+                        .get("Origin")
+                        .and_then(|value| value.to_str().ok())
+                        .unwrap_or("")
+                        .to_string();
+                    let locale = response_locale(req.headers(), &config);
+                    if let Some(ref cors) = config.cors {
+                        if method == Method::OPTIONS
+                            && req.headers().contains_key("Access-Control-Request-Method")
+                        {
+                            return Ok::<_, hyper::Error>(cors_preflight(cors, &origin));
+                        }
+                    }
+                    let budget_client = query.request_budget().and_then(|budget| {
+                        let api_key = req.headers().get("X-Api-Key");
+                        budget.client(api_key.and_then(|key| key.to_str().ok()), remote_ip)
+                    });
+                    // the time until the budget of the client gets renewed, if spent already
+                    let retry_after = budget_client.as_ref().and_then(|client| {
+                        query.request_budget().unwrap().check(client).err()
+                    });
+                    let (parts, body) = req.into_parts();
+                    let body = hyper::body::to_bytes(body).await?;
+                    let tip = query.chain().best_header();
+
+                    let span = tracing::info_span!(
+                        "request",
+                        request_id = %request_id,
+                        method = %method,
+                        path = uri.path(),
+                    );
+                    let audit = query
+                        .audit_log()
+                        .and_then(|_| audit_event(&method, &uri, &body));
+                    let resp = span.in_scope(|| {
+                        let start_time = Instant::now();
+                        ReadStats::take();
+                        let deadline = config.request_timeout.map(Deadline::set);
+                        let mut result = match retry_after {
+                            Some(_) => Err(HttpError(
+                                StatusCode::TOO_MANY_REQUESTS,
+                                "Request budget exceeded".to_string(),
+                            )),
+                            None => handle_request(
+                                method.clone(),
+                                uri.clone(),
+                                body,
+                                &parts.headers,
+                                remote_ip,
+                                &query,
+                                &config,
+                                electrum_sessions.as_deref(),
+                            ),
+                        };
+                        // the response got computed from cancelled scans, and may be incomplete
+                        if deadline.map_or(false, |deadline| deadline.cancelled()) {
+                            result = Err(HttpError(
+                                StatusCode::SERVICE_UNAVAILABLE,
+                                "Request timed out".to_string(),
+                            ));
+                        }
+                        let db = ReadStats::take();
+                        if let (Some(budget), Some(client)) =
+                            (query.request_budget(), budget_client)
+                        {
+                            budget.charge(client, db.cost());
+                        }
+                        let error = result.as_ref().err().map(|err| err.1.clone());
+                        let resp = result.unwrap_or_else(|err| {
                             warn!("{:?}", err);
                             Response::builder()
                                 .status(err.0)
                                 .header("Content-Type", "text/plain")
-                                .body(Body::from(err.1))
+                                .body(Body::from(locale.translate(&err.1)))
                                 .unwrap()
                         });
-                    if let Some(ref origins) = config.cors {
+                        if let (Some(audit_log), Some(mut event)) = (query.audit_log(), audit) {
+                            event.request_id = Some(request_id.clone());
+                            event.details["status"] = resp.status().as_u16().into();
+                            event.error = error;
+                            audit_log.record(&event);
+                        }
+                        record_if_slow(&query, &config, &method, &uri, start_time.elapsed(), db);
+                        resp
+                    });
+                    let mut resp = encode_response(resp, binary_format).await?;
+                    if method == Method::HEAD {
+                        resp = strip_body(resp);
+                    }
+                    if config.cache_policy.no_store(uri.path()) {
                         resp.headers_mut()
-                            .insert("Access-Control-Allow-Origin", origins.parse().unwrap());
+                            .insert("Cache-Control", "no-store".parse().unwrap());
+                    }
+                    resp.headers_mut().insert("X-Request-Id", request_id.parse().unwrap());
+                    if let Some(retry_after) = retry_after {
+                        // rounded up, so that retrying right away is never too early
+                        let secs = retry_after.as_secs() + 1;
+                        resp.headers_mut().insert("Retry-After", secs.into());
+                    }
+                    // lets clients detect reorgs happening in between requests
+                    resp.headers_mut()
+                        .insert("X-Tip-Hash", tip.hash().to_string().parse().unwrap());
+                    resp.headers_mut().insert("X-Tip-Height", tip.height().into());
+                    if config.locales.len() > 1 {
+                        let headers = resp.headers_mut();
+                        headers.insert("Content-Language", locale.tag().parse().unwrap());
+                        headers.append("Vary", "Accept-Language".parse().unwrap());
+                    }
+                    if let Some(ref cors) = config.cors {
+                        add_cors_headers(&mut resp, cors, &origin);
                     }
                     Ok::<_, hyper::Error>(resp)
                 }
@@ -558,7 +1968,9 @@ async fn run_server(config: Arc<Config>, query: Arc<Query>, rx: oneshot::Receive
 
             Server::from_tcp(socket.into())
                 .expect("Server::from_tcp failed")
-                .serve(make_service_fn(move |_| make_service_fn_inn()))
+                .serve(make_service_fn(move |conn: &AddrStream| {
+                    make_service_fn_inn(Some(conn.remote_addr().ip()))
+                }))
                 .with_graceful_shutdown(async {
                     rx.await.ok();
                 })
@@ -576,7 +1988,7 @@ async fn run_server(config: Arc<Config>, query: Arc<Query>, rx: oneshot::Receive
 
             Server::bind_unix(path)
                 .expect("Server::bind_unix failed")
-                .serve(make_service_fn(move |_| make_service_fn_inn()))
+                .serve(make_service_fn(move |_| make_service_fn_inn(None)))
                 .with_graceful_shutdown(async {
                     rx.await.ok();
                 })
@@ -589,26 +2001,49 @@ async fn run_server(config: Arc<Config>, query: Arc<Query>, rx: oneshot::Receive
     }
 }
 
-pub fn start(config: Arc<Config>, query: Arc<Query>) -> Handle {
+pub fn start(
+    config: Arc<Config>,
+    query: Arc<Query>,
+    electrum_sessions: Option<Arc<Sessions>>,
+) -> Handle {
+    start_with_networks(config, query, electrum_sessions, vec![])
+}
+
+// Serve the additional networks along with the primary one, on the same address
+pub fn start_with_networks(
+    config: Arc<Config>,
+    query: Arc<Query>,
+    electrum_sessions: Option<Arc<Sessions>>,
+    networks: Vec<NetworkMount>,
+) -> Handle {
     let (tx, rx) = oneshot::channel::<()>();
 
     Handle {
         tx,
+        shutdown_timeout: config.shutdown_timeout,
         thread: thread::spawn(move || {
-            run_server(config, query, rx);
+            run_server(config, query, electrum_sessions, networks, rx);
         }),
     }
 }
 
 pub struct Handle {
     tx: oneshot::Sender<()>,
+    shutdown_timeout: Duration,
     thread: thread::JoinHandle<()>,
 }
 
 impl Handle {
+    // Stop accepting connections, and wait for the in-flight requests to complete
     pub fn stop(self) {
         self.tx.send(()).expect("failed to send shutdown signal");
-        self.thread.join().expect("REST server failed");
+        match join_timeout(self.thread, self.shutdown_timeout) {
+            Some(result) => result.expect("REST server failed"),
+            None => warn!(
+                "REST requests still in-flight after {:?}, stopping anyway",
+                self.shutdown_timeout
+            ),
+        }
     }
 }
 
@@ -616,8 +2051,11 @@ fn handle_request(
     method: Method,
     uri: hyper::Uri,
     body: hyper::body::Bytes,
-    query: &Query,
+    headers: &hyper::HeaderMap,
+    remote_ip: Option<IpAddr>,
+    query: &Arc<Query>,
     config: &Config,
+    electrum_sessions: Option<&Sessions>,
 ) -> Result<Response<Body>, HttpError> {
     // TODO it looks hyper does not have routing and query parsing :(
     let path: Vec<&str> = uri.path().split('/').skip(1).collect();
@@ -627,8 +2065,32 @@ fn handle_request(
             .collect::<HashMap<String, String>>(),
         None => HashMap::new(),
     };
+    let cache_ttl = config.cache_policy.ttl(uri.path());
 
     info!("handle {:?} {:?}", method, uri);
+
+    // let clients (and CORS preflights when --cors isn't set) discover the supported methods
+    if method == Method::OPTIONS {
+        return Ok(Response::builder()
+            .status(StatusCode::NO_CONTENT)
+            .header("Allow", ALLOWED_METHODS)
+            .body(Body::empty())
+            .unwrap());
+    }
+    // HEAD requests are served like GET ones, their body gets dropped once encoded
+    let method = if method == Method::HEAD {
+        Method::GET
+    } else {
+        method
+    };
+
+    // refuse to serve when the client expects a chain tip that is no longer current
+    if let Some(at_tip) = query_params.get("at_tip") {
+        if BlockHash::from_str(at_tip)? != query.chain().best_hash() {
+            bail!(HttpError(StatusCode::CONFLICT, "Chain tip changed".to_string()));
+        }
+    }
+
     match (
         &method,
         path.get(0),
@@ -640,18 +2102,503 @@ fn handle_request(
         (&Method::GET, Some(&"blocks"), Some(&"tip"), Some(&"hash"), None, None) => http_message(
             StatusCode::OK,
             query.chain().best_hash().to_string(),
-            TTL_SHORT,
+            cache_ttl.short,
         ),
 
         (&Method::GET, Some(&"blocks"), Some(&"tip"), Some(&"height"), None, None) => http_message(
             StatusCode::OK,
             query.chain().best_height().to_string(),
-            TTL_SHORT,
+            cache_ttl.short,
         ),
 
+        #[cfg(not(feature = "liquid"))]
+        (&Method::GET, Some(&"blocks"), Some(&"tip"), Some(&"chainwork"), None, None) => {
+            http_message(
+                StatusCode::OK,
+                format_chainwork(query.chain().best_header().chainwork()),
+                cache_ttl.short,
+            )
+        }
+
+        (&Method::GET, Some(&"blocks"), Some(&"tip"), None, None, None) => {
+            let tip = query.chain().best_header();
+            let header = tip.header();
+            json_response(
+                TipValue {
+                    height: tip.height(),
+                    hash: *tip.hash(),
+                    timestamp: header.time,
+                    mediantime: query.chain().get_mtp(tip.height()),
+                    #[cfg(not(feature = "liquid"))]
+                    difficulty: header.difficulty_float(),
+                    #[cfg(not(feature = "liquid"))]
+                    retarget: difficulty::next_retarget(
+                        query.chain(),
+                        config.retarget_interval,
+                        config.target_block_spacing,
+                    )?,
+                },
+                cache_ttl.short,
+            )
+        }
+
+        (&Method::GET, Some(&"stats"), Some(&"blocks"), None, None, None) => {
+            let query = Arc::clone(query);
+            swr_response(config, &uri, cache_ttl.short, move || {
+                let parse_param = |name: &str| {
+                    query_params
+                        .get(name)
+                        .map(|value| {
+                            value
+                                .parse::<usize>()
+                                .map_err(|_| HttpError::from(format!("Invalid {}", name)))
+                        })
+                        .transpose()
+                };
+                let tip_height = query.chain().best_height();
+                let end_height = parse_param("to")?.unwrap_or(tip_height).min(tip_height);
+                let start_height = parse_param("from")?
+                    .unwrap_or_else(|| end_height.saturating_sub(BLOCK_STATS_DEFAULT_RANGE - 1));
+                let interval = parse_param("interval")?.unwrap_or(1).max(1);
+
+                if start_height > end_height {
+                    return Err(HttpError::from("Invalid block range".to_string()));
+                }
+                let block_count = end_height - start_height + 1;
+                if block_count > MAX_BLOCK_STATS_RANGE {
+                    return Err(HttpError::from(format!(
+                        "Exceeded maximum of {} blocks",
+                        MAX_BLOCK_STATS_RANGE
+                    )));
+                }
+                if (block_count + interval - 1) / interval > MAX_BLOCK_STATS_POINTS {
+                    return Err(HttpError::from(format!(
+                        "Exceeded maximum of {} data points, use a larger interval",
+                        MAX_BLOCK_STATS_POINTS
+                    )));
+                }
+
+                let stats = query.chain().block_stats(start_height, end_height);
+                Ok(serde_json::to_string(&aggregate_block_stats(
+                    stats,
+                    start_height,
+                    interval,
+                ))?)
+            })
+        }
+
+        (&Method::GET, Some(&"stats"), Some(&"cdd"), None, None, None) => {
+            let window = query_params
+                .get("window")
+                .map_or(Ok(BLOCK_STATS_DEFAULT_RANGE), |window| window.parse::<usize>())?
+                .max(1);
+            if window > MAX_BLOCK_STATS_RANGE {
+                return Err(HttpError::from(format!(
+                    "Exceeded maximum of {} blocks",
+                    MAX_BLOCK_STATS_RANGE
+                )));
+            }
+            let query = Arc::clone(query);
+            swr_response(config, &uri, cache_ttl.short, move || {
+                let end_height = query.chain().best_height();
+                let start_height = (end_height + 1).saturating_sub(window);
+                let stats = query.chain().block_stats(start_height, end_height);
+                let coin_days_destroyed: f64 = stats.iter().map(|b| b.coin_days_destroyed).sum();
+
+                Ok(serde_json::to_string(&json!({
+                    "start_height": start_height,
+                    "end_height": end_height,
+                    "block_count": stats.len(),
+                    "coin_days_destroyed": coin_days_destroyed,
+                    "avg_coin_days_destroyed": coin_days_destroyed / stats.len().max(1) as f64,
+                }))?)
+            })
+        }
+        (&Method::GET, Some(&"stats"), Some(&"block-intervals"), None, None, None) => {
+            let window = query_params
+                .get("window")
+                .map_or(Ok(BLOCK_INTERVALS_DEFAULT_WINDOW), |window| window.parse::<usize>())?
+                .max(1);
+            if window > MAX_BLOCK_STATS_RANGE {
+                return Err(HttpError::from(format!(
+                    "Exceeded maximum of {} blocks",
+                    MAX_BLOCK_STATS_RANGE
+                )));
+            }
+            let end_height = query.chain().best_height();
+            let start_height = (end_height + 1).saturating_sub(window);
+            json_response(block_intervals(query, start_height, end_height)?, cache_ttl.short)
+        }
+        #[cfg(not(feature = "liquid"))]
+        (&Method::GET, Some(&"stats"), Some(&"version-bits"), None, None, None) => {
+            let window = query_params
+                .get("window")
+                .map_or(Ok(config.deployment_period), |window| window.parse::<usize>())?
+                .max(1);
+            if window > MAX_BLOCK_STATS_RANGE {
+                return Err(HttpError::from(format!(
+                    "Exceeded maximum of {} blocks",
+                    MAX_BLOCK_STATS_RANGE
+                )));
+            }
+            let end_height = query.chain().best_height();
+            let start_height = (end_height + 1).saturating_sub(window);
+            let deployments = config
+                .deployments
+                .iter()
+                .map(|deployment| {
+                    version_bits::deployment_status(
+                        query.chain(),
+                        deployment,
+                        config.deployment_period,
+                    )
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            let bits = version_bits::bit_signaling(query.chain(), start_height, end_height)?;
+
+            json_response(
+                json!({
+                    "start_height": start_height,
+                    "end_height": end_height,
+                    "block_count": end_height + 1 - start_height,
+                    "bits": bits,
+                    "deployments": deployments,
+                }),
+                cache_ttl.short,
+            )
+        }
+        #[cfg(not(feature = "liquid"))]
+        (&Method::GET, Some(&"stats"), Some(&"multisig"), None, None, None) => {
+            let query = Arc::clone(query);
+            swr_response(config, &uri, cache_ttl.short, move || {
+                let parse_param = |name: &str| {
+                    query_params
+                        .get(name)
+                        .map(|value| {
+                            value
+                                .parse::<usize>()
+                                .map_err(|_| HttpError::from(format!("Invalid {}", name)))
+                        })
+                        .transpose()
+                };
+                let tip_height = query.chain().best_height();
+                let end_height = parse_param("to")?.unwrap_or(tip_height).min(tip_height);
+                let start_height = parse_param("from")?
+                    .unwrap_or_else(|| end_height.saturating_sub(BLOCK_STATS_DEFAULT_RANGE - 1));
+                if start_height > end_height {
+                    return Err(HttpError::from("Invalid block range".to_string()));
+                }
+                if end_height - start_height + 1 > MAX_BLOCK_STATS_RANGE {
+                    return Err(HttpError::from(format!(
+                        "Exceeded maximum of {} blocks",
+                        MAX_BLOCK_STATS_RANGE
+                    )));
+                }
+
+                let mut counts = query.chain().multisig_stats(start_height, end_height);
+                counts.sort_by(|a, b| b.count.cmp(&a.count));
+                Ok(serde_json::to_string(&json!({
+                    "start_height": start_height,
+                    "end_height": end_height,
+                    "count": counts.iter().map(|c| c.count as u64).sum::<u64>(),
+                    "multisig": counts,
+                }))?)
+            })
+        }
+        #[cfg(not(feature = "liquid"))]
+        (&Method::GET, Some(&"stats"), Some(&"lightning"), None, None, None) => {
+            let query = Arc::clone(query);
+            swr_response(config, &uri, cache_ttl.short, move || {
+                let parse_param = |name: &str| {
+                    query_params
+                        .get(name)
+                        .map(|value| {
+                            value
+                                .parse::<usize>()
+                                .map_err(|_| HttpError::from(format!("Invalid {}", name)))
+                        })
+                        .transpose()
+                };
+                let tip_height = query.chain().best_height();
+                let end_height = parse_param("to")?.unwrap_or(tip_height).min(tip_height);
+                let start_height = parse_param("from")?
+                    .unwrap_or_else(|| end_height.saturating_sub(BLOCK_STATS_DEFAULT_RANGE - 1));
+                let interval = parse_param("interval")?.unwrap_or(1).max(1);
+
+                if start_height > end_height {
+                    return Err(HttpError::from("Invalid block range".to_string()));
+                }
+                let block_count = end_height - start_height + 1;
+                if block_count > MAX_BLOCK_STATS_RANGE {
+                    return Err(HttpError::from(format!(
+                        "Exceeded maximum of {} blocks",
+                        MAX_BLOCK_STATS_RANGE
+                    )));
+                }
+                if (block_count + interval - 1) / interval > MAX_BLOCK_STATS_POINTS {
+                    return Err(HttpError::from(format!(
+                        "Exceeded maximum of {} data points, use a larger interval",
+                        MAX_BLOCK_STATS_POINTS
+                    )));
+                }
+
+                let windows = lightning::windows(query.chain(), start_height, end_height, interval);
+                Ok(serde_json::to_string(&json!({
+                    "start_height": start_height,
+                    "end_height": end_height,
+                    "interval": interval,
+                    "windows": windows,
+                }))?)
+            })
+        }
+        #[cfg(not(feature = "liquid"))]
+        (&Method::GET, Some(&"stats"), Some(&"dust"), None, None, None) => {
+            let feerate = query_params.get("feerate").map_or(
+                Ok(config.network_type.params().dust_relay_feerate),
+                |feerate| feerate.parse::<u64>(),
+            )?;
+
+            let query = Arc::clone(query);
+            swr_response(config, &uri, cache_ttl.short, move || {
+                let histogram = utxo_histogram::current(query.chain())?;
+                let (utxo_count, utxo_value) = histogram.totals();
+                let (dust_count, dust_value) = histogram.dust(feerate);
+                Ok(serde_json::to_string(&json!({
+                    "feerate": feerate,
+                    "height": query.chain().best_height(),
+                    "utxo_count": utxo_count,
+                    "utxo_value": utxo_value,
+                    "dust_count": dust_count,
+                    "dust_value": dust_value,
+                }))?)
+            })
+        }
+        #[cfg(not(feature = "liquid"))]
+        (&Method::GET, Some(&"stats"), Some(&"utxo-types"), None, None, None) => {
+            let query = Arc::clone(query);
+            swr_response(config, &uri, cache_ttl.short, move || {
+                let types = utxo_types::current(query.chain())?;
+                Ok(serde_json::to_string(&json!({
+                    "height": query.chain().best_height(),
+                    "types": types.breakdown(),
+                }))?)
+            })
+        }
+        #[cfg(not(feature = "liquid"))]
+        (&Method::GET, Some(&"stats"), Some(&"utxo-types"), Some(&"history"), None, None) => {
+            let parse_param = |name: &str| {
+                query_params
+                    .get(name)
+                    .map(|value| {
+                        value
+                            .parse::<usize>()
+                            .map_err(|_| HttpError::from(format!("Invalid {}", name)))
+                    })
+                    .transpose()
+            };
+            let interval = parse_param("interval")?.unwrap_or(utxo_types::SNAPSHOT_INTERVAL);
+            if interval == 0 || interval % utxo_types::SNAPSHOT_INTERVAL != 0 {
+                return Err(HttpError::from(format!(
+                    "interval must be a multiple of {}",
+                    utxo_types::SNAPSHOT_INTERVAL
+                )));
+            }
+            let end_height = parse_param("to")?.unwrap_or_else(|| query.chain().best_height());
+            let start_height = parse_param("from")?.unwrap_or(0);
+            if start_height > end_height {
+                return Err(HttpError::from("Invalid block range".to_string()));
+            }
+
+            // bring the snapshots up to date
+            utxo_types::catch_up(query.chain())?;
+            let snapshots: Vec<serde_json::Value> =
+                utxo_types::snapshots(query.chain(), start_height, end_height, interval)
+                    .into_iter()
+                    .map(|(height, types)| json!({ "height": height, "types": types.breakdown() }))
+                    .collect();
+            json_response(snapshots, cache_ttl.short)
+        }
+        (&Method::GET, Some(&"prices"), None, None, None, None) => {
+            if config.price_feed.is_none() {
+                return Err(HttpError::from("price feed disabled".to_string()));
+            }
+            let point = match query_params.get("time") {
+                Some(time) => {
+                    let time = time
+                        .parse::<u32>()
+                        .map_err(|_| HttpError::from("Invalid time".to_string()))?;
+                    price::price_at(query.chain(), time)
+                }
+                None => price::latest_price(query.chain()),
+            };
+            let point =
+                point.ok_or_else(|| HttpError::not_found("No exchange rates".to_string()))?;
+            json_response(point, cache_ttl.short)
+        }
+        (&Method::GET, Some(&"network"), Some(&"forks"), None, None, None) => {
+            if config.secondary_daemons.is_empty() {
+                return Err(HttpError::from("fork monitoring disabled".to_string()));
+            }
+            let report = forks::report(query.chain()).unwrap_or_default();
+            let tip = query.chain().best_header();
+            json_response(
+                json!({
+                    "tip": { "height": tip.height(), "hash": tip.hash() },
+                    "alert_depth": config.fork_alert_depth,
+                    "alert": report.max_divergence() as usize > config.fork_alert_depth,
+                    "daemons": report.daemons,
+                    "competing_tips": report.competing_tips,
+                }),
+                cache_ttl.short,
+            )
+        }
+        (&Method::GET, Some(&"network"), Some(&"checkpoints"), None, None, None) => {
+            let checkpoints: Vec<serde_json::Value> = config
+                .checkpoints
+                .iter()
+                .map(|(height, hash)| {
+                    // null until the indexed chain reaches the checkpoint
+                    let matches = query
+                        .chain()
+                        .header_by_height(*height)
+                        .map(|header| header.hash() == hash);
+                    json!({ "height": height, "hash": hash, "matches": matches })
+                })
+                .collect();
+            json_response(
+                json!({
+                    "checkpoints": checkpoints,
+                    "max_reorg_depth": config.max_reorg_depth,
+                    "refused_reorg": query.chain().refused_reorg(),
+                }),
+                cache_ttl.short,
+            )
+        }
+        (&Method::GET, Some(&"stats"), Some(&"daily"), None, None, None) => {
+            if !config.daily_stats {
+                return Err(HttpError::from("daily stats disabled".to_string()));
+            }
+            let days = query_params
+                .get("days")
+                .map_or(Ok(DAILY_STATS_DEFAULT_DAYS), |days| days.parse::<u32>())?
+                .min(daily_stats::MAX_DAYS);
+
+            let stats: Vec<DailyStatsValue> = daily_stats::daily_stats(query.chain(), days)
+                .iter()
+                .map(DailyStatsValue::from)
+                .collect();
+            json_response(stats, cache_ttl.short)
+        }
+
+        (&Method::GET, Some(&"blocks"), Some(&"tip"), Some(&"wait"), None, None) => {
+            let known = query_params
+                .get("known")
+                .map(|hash| BlockHash::from_str(hash))
+                .transpose()?;
+            let timeout = longpoll_timeout(&query_params)?;
+
+            let tip = match known {
+                Some(known) if known == query.chain().best_hash() => {
+                    tokio::task::block_in_place(|| query.chain().wait_tip_change(&known, timeout))
+                }
+                _ => query.chain().best_hash(),
+            };
+            let height = query.chain().height_by_hash(&tip);
+
+            json_response(
+                json!({
+                    "hash": tip,
+                    "height": height,
+                    "changed": known.map_or(true, |known| known != tip),
+                }),
+                0,
+            )
+        }
+
+        (&Method::POST, Some(&"blocks"), Some(&"timestamps"), None, None, None) => {
+            let heights: Vec<usize> =
+                serde_json::from_slice(&body).map_err(|err| HttpError::from(err.to_string()))?;
+            check_batch_size(heights.len(), "heights", config)?;
+            let timestamps: Vec<Option<u32>> = heights
+                .iter()
+                .map(|height| {
+                    let header = query.chain().header_by_height(*height)?;
+                    Some(header.header().time)
+                })
+                .collect();
+            json_response(timestamps, 0)
+        }
+        (&Method::GET, Some(&"blocks"), Some(&"raw.gz"), None, None, None) => {
+            let parse_height = |name: &str| -> Result<usize, HttpError> {
+                query_params
+                    .get(name)
+                    .ok_or_else(|| HttpError::from(format!("Missing {}", name)))?
+                    .parse::<usize>()
+                    .map_err(|_| HttpError::from(format!("Invalid {}", name)))
+            };
+            let (start_height, end_height) = (parse_height("from")?, parse_height("to")?);
+            if start_height > end_height {
+                return Err(HttpError::from("Invalid block range".to_string()));
+            }
+            check_batch_size(end_height - start_height + 1, "blocks", config)?;
+
+            // consensus-encoded blocks back to back, in a single gzip stream. the blocks get
+            // compressed on the blocking pool of the runtime and sent one at a time, rather than
+            // buffering the whole range
+            let hashes = (start_height..=end_height)
+                .map(|height| {
+                    query
+                        .chain()
+                        .header_by_height(height)
+                        .map(|header| *header.hash())
+                        .ok_or_else(|| HttpError::not_found(format!("Block {} not found", height)))
+                })
+                .collect::<Result<Vec<BlockHash>, HttpError>>()?;
+            let ttl = ttl_by_depth(Some(end_height), query, cache_ttl);
+
+            let (mut sender, body) = Body::channel();
+            let query = Arc::clone(query);
+            let runtime = tokio::runtime::Handle::current();
+            runtime.clone().spawn_blocking(move || {
+                let mut encoder = GzEncoder::new(vec![], Compression::default());
+                for hash in hashes {
+                    let block = match query.chain().get_block_raw(&hash) {
+                        Some(block) => block,
+                        None => {
+                            warn!("block {} not found while streaming raw.gz", hash);
+                            sender.abort();
+                            return;
+                        }
+                    };
+                    // writing to a Vec never fails
+                    encoder.write_all(&block).unwrap();
+                    let chunk = std::mem::take(encoder.get_mut());
+                    if runtime.block_on(sender.send_data(chunk.into())).is_err() {
+                        return; // the client went away
+                    }
+                }
+                let chunk = encoder.finish().unwrap();
+                runtime.block_on(sender.send_data(chunk.into())).ok();
+            });
+
+            Ok(Response::builder()
+                .status(StatusCode::OK)
+                .header("Content-Type", "application/gzip")
+                .header("Cache-Control", format!("public, max-age={:}", ttl))
+                .body(body)
+                .unwrap())
+        }
         (&Method::GET, Some(&"blocks"), start_height, None, None, None) => {
+            if let Some(paginator) = Paginator::from_params(&query_params, BLOCK_LIMIT, BLOCK_LIMIT)? {
+                let values = block_values(query, paginator.cursor::<usize>()?, paginator.limit)?;
+                let next_cursor = values
+                    .last()
+                    .filter(|block| paginator.is_full(values.len()) && block.height > 0)
+                    .map(|block| block.height - 1);
+                return paginator.respond(values, next_cursor, None, cache_ttl.short);
+            }
             let start_height = start_height.and_then(|height| height.parse::<usize>().ok());
-            blocks(&query, start_height)
+            blocks(&query, start_height, cache_ttl)
         }
         (&Method::GET, Some(&"block-height"), Some(height), None, None, None) => {
             let height = height.parse::<usize>()?;
@@ -659,9 +2606,25 @@ fn handle_request(
                 .chain()
                 .header_by_height(height)
                 .ok_or_else(|| HttpError::not_found("Block not found".to_string()))?;
-            let ttl = ttl_by_depth(Some(height), query);
+            let ttl = ttl_by_depth(Some(height), query, cache_ttl);
             http_message(StatusCode::OK, header.hash().to_string(), ttl)
         }
+        (&Method::GET, Some(&"block-time"), Some(timestamp), None, None, None) => {
+            let timestamp = timestamp.parse::<u32>()?;
+            let header = query
+                .chain()
+                .header_by_time(timestamp)
+                .ok_or_else(|| HttpError::not_found("Block not found".to_string()))?;
+            let ttl = ttl_by_depth(Some(header.height()), query, cache_ttl);
+            json_response(
+                json!({
+                    "height": header.height(),
+                    "hash": header.hash(),
+                    "timestamp": header.header().time,
+                }),
+                ttl,
+            )
+        }
         (&Method::GET, Some(&"block"), Some(hash), None, None, None) => {
             let hash = BlockHash::from_str(hash)?;
             let blockhm = query
@@ -669,21 +2632,51 @@ fn handle_request(
                 .get_block_with_meta(&hash)
                 .ok_or_else(|| HttpError::not_found("Block not found".to_string()))?;
             let block_value = BlockValue::new(blockhm);
-            json_response(block_value, TTL_LONG)
+            // the stats are only kept for the blocks of the best chain
+            #[cfg(not(feature = "liquid"))]
+            let block_value = {
+                let stats = query
+                    .chain()
+                    .height_by_hash(&hash)
+                    .and_then(|height| query.chain().block_stats(height, height).pop());
+                block_value.with_adoption(stats.as_ref())
+            };
+            json_response(block_value, cache_ttl.long)
         }
         (&Method::GET, Some(&"block"), Some(hash), Some(&"status"), None, None) => {
             let hash = BlockHash::from_str(hash)?;
             let status = query.chain().get_block_status(&hash);
-            let ttl = ttl_by_depth(status.height, query);
+            let ttl = ttl_by_depth(status.height, query, cache_ttl);
             json_response(status, ttl)
         }
+        (&Method::GET, Some(&"block"), Some(hash), Some(&"cdd"), None, None) => {
+            let hash = BlockHash::from_str(hash)?;
+            let height = query
+                .chain()
+                .height_by_hash(&hash)
+                .ok_or_else(|| HttpError::not_found("Block not found".to_string()))?;
+            let stats = query
+                .chain()
+                .block_stats(height, height)
+                .pop()
+                .ok_or_else(|| HttpError::not_found("Block stats not indexed".to_string()))?;
+            let ttl = ttl_by_depth(Some(height), query, cache_ttl);
+            json_response(
+                json!({
+                    "height": stats.height,
+                    "time": stats.time,
+                    "coin_days_destroyed": stats.coin_days_destroyed,
+                }),
+                ttl,
+            )
+        }
         (&Method::GET, Some(&"block"), Some(hash), Some(&"txids"), None, None) => {
             let hash = BlockHash::from_str(hash)?;
             let txids = query
                 .chain()
                 .get_block_txids(&hash)
                 .ok_or_else(|| HttpError::not_found("Block not found".to_string()))?;
-            json_response(txids, TTL_LONG)
+            json_response(txids, cache_ttl.long)
         }
         (&Method::GET, Some(&"block"), Some(hash), Some(&"header"), None, None) => {
             let hash = BlockHash::from_str(hash)?;
@@ -693,7 +2686,7 @@ fn handle_request(
                 .ok_or_else(|| HttpError::not_found("Block not found".to_string()))?;
 
             let header_hex = encode::serialize_hex(&header);
-            http_message(StatusCode::OK, header_hex, TTL_LONG)
+            http_message(StatusCode::OK, header_hex, cache_ttl.long)
         }
         (&Method::GET, Some(&"block"), Some(hash), Some(&"raw"), None, None) => {
             let hash = BlockHash::from_str(hash)?;
@@ -702,11 +2695,20 @@ fn handle_request(
                 .get_block_raw(&hash)
                 .ok_or_else(|| HttpError::not_found("Block not found".to_string()))?;
 
+            Ok(raw_response(raw, cache_ttl.long, headers.get("Range")))
+        }
+        (&Method::GET, Some(&"block"), Some(hash), Some(&"raw.gz"), None, None) => {
+            let hash = BlockHash::from_str(hash)?;
+            let raw = query
+                .chain()
+                .get_block_raw(&hash)
+                .ok_or_else(|| HttpError::not_found("Block not found".to_string()))?;
+
             Ok(Response::builder()
                 .status(StatusCode::OK)
-                .header("Content-Type", "application/octet-stream")
-                .header("Cache-Control", format!("public, max-age={:}", TTL_LONG))
-                .body(Body::from(raw))
+                .header("Content-Type", "application/gzip")
+                .header("Cache-Control", format!("public, max-age={:}", cache_ttl.long))
+                .body(Body::from(gzip(&raw)?))
                 .unwrap())
         }
         (&Method::GET, Some(&"block"), Some(hash), Some(&"txid"), Some(index), None) => {
@@ -719,7 +2721,38 @@ fn handle_request(
             if index >= txids.len() {
                 bail!(HttpError::not_found("tx index out of range".to_string()));
             }
-            http_message(StatusCode::OK, txids[index].to_string(), TTL_LONG)
+            http_message(StatusCode::OK, txids[index].to_string(), cache_ttl.long)
+        }
+        (&Method::GET, Some(&"block"), Some(hash), Some(&"spends"), None, None) => {
+            let hash = BlockHash::from_str(hash)?;
+            let txids = query
+                .chain()
+                .get_block_txids(&hash)
+                .ok_or_else(|| HttpError::not_found("Block not found".to_string()))?;
+            let txs = txids
+                .iter()
+                .map(|txid| {
+                    query
+                        .chain()
+                        .lookup_txn(txid, Some(&hash))
+                        .ok_or_else(|| HttpError::from("missing tx".to_string()))
+                })
+                .collect::<Result<Vec<Transaction>, _>>()?;
+
+            let outpoints = txs
+                .iter()
+                .flat_map(|tx| tx.input.iter())
+                .filter(|txin| has_prevout(txin))
+                .map(|txin| txin.previous_output)
+                .collect();
+            let prevouts = query.lookup_txos(&outpoints);
+
+            let spends: Vec<BlockSpendsValue> = txs
+                .iter()
+                .map(|tx| BlockSpendsValue::new(tx, &prevouts, config))
+                .collect();
+            let ttl = ttl_by_depth(query.chain().height_by_hash(&hash), query, cache_ttl);
+            json_response(spends, ttl)
         }
         (&Method::GET, Some(&"block"), Some(hash), Some(&"txs"), start_index, None) => {
             let hash = BlockHash::from_str(hash)?;
@@ -728,17 +2761,25 @@ fn handle_request(
                 .get_block_txids(&hash)
                 .ok_or_else(|| HttpError::not_found("Block not found".to_string()))?;
 
-            let start_index = start_index
-                .map_or(0u32, |el| el.parse().unwrap_or(0))
-                .max(0u32) as usize;
-            if start_index >= txids.len() {
-                bail!(HttpError::not_found("start index out of range".to_string()));
-            } else if start_index % CHAIN_TXS_PER_PAGE != 0 {
-                bail!(HttpError::from(format!(
-                    "start index must be a multipication of {}",
-                    CHAIN_TXS_PER_PAGE
-                )));
-            }
+            let paginator =
+                Paginator::from_params(&query_params, CHAIN_TXS_PER_PAGE, CHAIN_TXS_PER_PAGE)?;
+            let (start_index, limit) = match paginator {
+                Some(ref paginator) => (paginator.cursor::<usize>()?.unwrap_or(0), paginator.limit),
+                None => {
+                    let start_index = start_index
+                        .map_or(0u32, |el| el.parse().unwrap_or(0))
+                        .max(0u32) as usize;
+                    if start_index >= txids.len() {
+                        bail!(HttpError::not_found("start index out of range".to_string()));
+                    } else if start_index % CHAIN_TXS_PER_PAGE != 0 {
+                        bail!(HttpError::from(format!(
+                            "start index must be a multipication of {}",
+                            CHAIN_TXS_PER_PAGE
+                        )));
+                    }
+                    (start_index, CHAIN_TXS_PER_PAGE)
+                }
+            };
 
             // blockid_by_hash() only returns the BlockId for non-orphaned blocks,
             // or None for orphaned
@@ -747,7 +2788,7 @@ fn handle_request(
             let txs = txids
                 .iter()
                 .skip(start_index)
-                .take(CHAIN_TXS_PER_PAGE)
+                .take(limit)
                 .map(|txid| {
                     query
                         .lookup_txn(&txid)
@@ -757,9 +2798,33 @@ fn handle_request(
                 .collect::<Result<Vec<(Transaction, Option<BlockId>)>, _>>()?;
 
             // XXX orphraned blocks alway get TTL_SHORT
-            let ttl = ttl_by_depth(confirmed_blockid.map(|b| b.height), query);
+            let ttl = ttl_by_depth(confirmed_blockid.map(|b| b.height), query, cache_ttl);
+
+            // the fees recorded at index time spare resolving the prevouts, unless asked for
+            let fees = query
+                .chain()
+                .get_block_fees(&hash)
+                .map(|fees| fees.into_iter().skip(start_index).collect::<Vec<u64>>());
+            let with_prevouts = query_params
+                .get("prevouts")
+                .map_or(false, |prevouts| prevouts == "true");
+            let fields = FieldSelector::from_params(&query_params)?;
+            let end_index = start_index + txs.len();
+            let txs_json = select_fields(
+                prepare_block_txs(txs, fees, with_prevouts, query, config),
+                fields.as_ref(),
+            );
+
+            if let Some(paginator) = paginator {
+                let next_cursor = Some(end_index).filter(|&end_index| end_index < txids.len());
+                return paginator.respond(txs_json, next_cursor, Some(txids.len()), ttl);
+            }
 
-            json_response(prepare_txs(txs, query, config), ttl)
+            json_response(txs_json, ttl)
+        }
+        #[cfg(not(feature = "liquid"))]
+        (&Method::GET, Some(&"v1"), Some(&"validate-address"), Some(addr), None, None) => {
+            json_response(AddressValidationValue::new(addr, config.network_type), cache_ttl.long)
         }
         (&Method::GET, Some(script_type @ &"address"), Some(script_str), Some(&"balance"), None, None)
         | (&Method::GET, Some(script_type @ &"scripthash"), Some(script_str), Some(&"balance"), None, None) => {
@@ -770,6 +2835,7 @@ fn handle_request(
                 .get("optimized")
                 .and_then(|s| s.parse::<bool>().ok())
                 .unwrap_or(false);
+            let unit = Unit::from_params(&query_params)?;
 
             // Get the balance
             let (confirmed_balance, pending_balance) = if use_optimized {
@@ -792,7 +2858,7 @@ fn handle_request(
                 (confirmed_sum, pending_sum)
             } else {
                 // Use the standard method for normal addresses
-                let stats = query.stats(&script_hash[..]);
+                let stats = query.stats(&script_hash[..])?;
                 // Use saturating_sub to prevent underflow for confirmed balance
                 let confirmed = stats.0.funded_txo_sum.saturating_sub(stats.0.spent_txo_sum);
                 // For pending balance, we need to handle potential negative values
@@ -801,31 +2867,42 @@ fn handle_request(
                 (confirmed, pending)
             };
 
-            let total_balance = confirmed_balance + pending_balance;
-
-            // Convert to BTC format (8 decimal places)
-            let to_btc_string = |satoshis: u64| -> String {
-                format!("{:.8}", satoshis as f64 / 100_000_000.0)
-            };
-
-            let balance = AddressBalanceValue {
-                confirm_amount: to_btc_string(confirmed_balance),
-                pending_amount: to_btc_string(pending_balance),
-                amount: to_btc_string(total_balance),
-                confirm_coin_amount: to_btc_string(confirmed_balance),
-                pending_coin_amount: to_btc_string(pending_balance),
-                coin_amount: to_btc_string(total_balance),
-            };
-
-            json_response(balance, TTL_SHORT)
+            let locale = response_locale(headers, config);
+            let mut balance =
+                AddressBalanceValue::new(confirmed_balance, pending_balance, unit, locale);
+            balance.fiat = fiat_value(
+                &query_params,
+                query,
+                config,
+                None,
+                &[
+                    ("confirm_amount", balance.confirm_sat_amount),
+                    ("pending_amount", balance.pending_sat_amount),
+                    ("amount", balance.sat_amount),
+                ],
+            )?;
+            json_response(balance, cache_ttl.short)
         }
 
         (&Method::GET, Some(script_type @ &"address"), Some(script_str), Some(&"stats"), None, None)
         | (&Method::GET, Some(script_type @ &"scripthash"), Some(script_str), Some(&"stats"), None, None) => {
             let script_hash = to_scripthash(script_type, script_str, config.network_type)?;
 
+            // the verbose mode adds how the confirmed spends satisfied the script
+            #[cfg(not(feature = "liquid"))]
+            let spend_paths = match query_params.get("verbose").map(String::as_str) {
+                Some("true") => {
+                    if !config.index_spend_paths {
+                        return Err(HttpError::from("spend path index disabled".to_string()));
+                    }
+                    let counts = query.chain().spend_path_counts(&script_hash[..])?;
+                    Some(counts.into_iter().map(SpendPathValue::from).collect())
+                }
+                _ => None,
+            };
+
             // Get confirmed and unconfirmed stats
-            let stats = query.stats(&script_hash[..]);
+            let stats = query.stats(&script_hash[..])?;
 
             // Calculate total stats
             let funded_txo_count = stats.0.funded_txo_count + stats.1.funded_txo_count;
@@ -871,23 +2948,37 @@ fn handle_request(
                 balance,
                 first_seen_tx_time,
                 last_seen_tx_time,
+                #[cfg(not(feature = "liquid"))]
+                spend_paths,
             };
 
-            json_response(response, TTL_SHORT)
+            json_response(response, cache_ttl.short)
         }
 
         (&Method::GET, Some(script_type @ &"address"), Some(script_str), None, None, None)
         | (&Method::GET, Some(script_type @ &"scripthash"), Some(script_str), None, None, None) => {
             let script_hash = to_scripthash(script_type, script_str, config.network_type)?;
-            let stats = query.stats(&script_hash[..]);
-            json_response(
-                json!({
-                    *script_type: script_str,
-                    "chain_stats": stats.0,
-                    "mempool_stats": stats.1,
-                }),
-                TTL_SHORT,
-            )
+            let stats = query.stats(&script_hash[..])?;
+            #[allow(unused_mut)]
+            let mut info = json!({
+                *script_type: script_str,
+                "chain_stats": stats.0,
+                "mempool_stats": stats.1,
+            });
+
+            // P2SH/P2WSH multisigs are only known once a spend revealed their script
+            #[cfg(not(feature = "liquid"))]
+            if let Some(revealed) = query.chain().revealed_script(&script_hash[..]) {
+                let innerscripts = InnerScripts {
+                    redeem_script: revealed.redeem_script,
+                    witness_script: revealed.witness_script,
+                };
+                if let Some((script_type, m, n)) = inner_multisig(&innerscripts) {
+                    info["multisig"] = json!({ "type": script_type, "m": m, "n": n });
+                }
+            }
+
+            json_response(info, cache_ttl.short)
         }
         (
             &Method::GET,
@@ -907,6 +2998,12 @@ fn handle_request(
         ) => {
             let script_hash = to_scripthash(script_type, script_str, config.network_type)?;
 
+            let paginator = Paginator::from_params(
+                &query_params,
+                CHAIN_TXS_PER_PAGE,
+                config.max_txs_per_page,
+            )?;
+
             // Check if pagination parameters are provided
             let has_pagination_params = query_params.contains_key("start_index") ||
                                        query_params.contains_key("limit") ||
@@ -918,15 +3015,18 @@ fn handle_request(
                 .and_then(|s| s.parse().ok())
                 .unwrap_or(0);
 
-            let limit: usize = query_params
-                .get("limit")
-                .and_then(|s| s.parse().ok())
-                .unwrap_or(CHAIN_TXS_PER_PAGE);
+            let limit: usize = match paginator {
+                Some(ref paginator) => paginator.limit,
+                None => parse_limit(&query_params, CHAIN_TXS_PER_PAGE, config.max_txs_per_page)?,
+            };
 
             // Get the last seen txid for cursor-based pagination
-            let after_txid = query_params
-                .get("after_txid")
-                .and_then(|s| s.parse::<Txid>().ok());
+            let after_txid = match paginator {
+                Some(ref paginator) => paginator.cursor::<Txid>()?,
+                None => query_params
+                    .get("after_txid")
+                    .and_then(|s| s.parse::<Txid>().ok()),
+            };
 
             // Determine if we should include mempool transactions
             let include_mempool = query_params
@@ -951,8 +3051,14 @@ fn handle_request(
             if txs.len() < limit {
                 let remaining = limit - txs.len();
 
-                // If we have mempool transactions, we don't need to use after_txid for chain transactions
-                let chain_after_txid = if txs.is_empty() { after_txid.as_ref() } else { None };
+                // If we have mempool transactions, or the cursor points at the last mempool
+                // transaction, the chain transactions start from the beginning
+                let chain_after_txid = after_txid.as_ref().filter(|txid| {
+                    let mempool = query.mempool();
+                    txs.is_empty()
+                        && mempool.lookup_txn(txid).is_none()
+                        && mempool.replaced_by(txid).is_none()
+                });
 
                 let chain_txs = query
                     .chain()
@@ -964,18 +3070,32 @@ fn handle_request(
             }
 
             // Get the total count of transactions for this address
-            let stats = query.stats(&script_hash[..]);
+            let stats = query.stats(&script_hash[..])?;
             let total_count = stats.0.tx_count + stats.1.tx_count;
 
             // Get the last txid in the current page for cursor-based pagination
             let last_txid = txs.last().map(|(tx, _)| tx.txid());
 
             // Prepare the transactions
-            let txs_json = prepare_txs(txs, query, config);
+            let fields = FieldSelector::from_params(&query_params)?;
+            let txs_json = select_fields(
+                prepare_script_txs(txs, &script_hash[..], query, config),
+                fields.as_ref(),
+            );
+
+            if let Some(paginator) = paginator {
+                let next_cursor = last_txid.filter(|_| paginator.is_full(txs_json.len()));
+                return paginator.respond(
+                    txs_json,
+                    next_cursor,
+                    Some(total_count),
+                    cache_ttl.short,
+                );
+            }
 
             // If no pagination parameters were provided, return just the transactions array (original behavior)
             if !has_pagination_params {
-                return json_response(txs_json, TTL_SHORT);
+                return json_response(txs_json, cache_ttl.short);
             }
 
             // Return with pagination metadata
@@ -987,7 +3107,7 @@ fn handle_request(
                 "next_page_after_txid": last_txid
             });
 
-            json_response(response, TTL_SHORT)
+            json_response(response, cache_ttl.short)
         }
 
         (
@@ -1020,7 +3140,14 @@ fn handle_request(
                 .map(|(tx, blockid)| (tx, Some(blockid)))
                 .collect();
 
-            json_response(prepare_txs(txs, query, config), TTL_SHORT)
+            let fields = FieldSelector::from_params(&query_params)?;
+            json_response(
+                select_fields(
+                    prepare_script_txs(txs, &script_hash[..], query, config),
+                    fields.as_ref(),
+                ),
+                cache_ttl.short,
+            )
         }
         (
             &Method::GET,
@@ -1042,12 +3169,83 @@ fn handle_request(
 
             let txs = query
                 .mempool()
-                .history(&script_hash[..], None, MAX_MEMPOOL_TXS)
+                .history(&script_hash[..], None, config.max_mempool_txs)
                 .into_iter()
                 .map(|tx| (tx, None))
                 .collect();
 
-            json_response(prepare_txs(txs, query, config), TTL_SHORT)
+            let fields = FieldSelector::from_params(&query_params)?;
+            json_response(
+                select_fields(
+                    prepare_script_txs(txs, &script_hash[..], query, config),
+                    fields.as_ref(),
+                ),
+                cache_ttl.short,
+            )
+        }
+        (
+            &Method::GET,
+            Some(script_type @ &"address"),
+            Some(script_str),
+            Some(&"txs"),
+            Some(&"summary"),
+            None,
+        )
+        | (
+            &Method::GET,
+            Some(script_type @ &"scripthash"),
+            Some(script_str),
+            Some(&"txs"),
+            Some(&"summary"),
+            None,
+        ) => {
+            let script_hash = to_scripthash(script_type, script_str, config.network_type)?;
+
+            let paginator = Paginator::from_params(
+                &query_params,
+                CHAIN_TXS_PER_PAGE,
+                config.max_txs_per_page,
+            )?;
+            let limit = match paginator {
+                Some(ref paginator) => paginator.limit,
+                None => parse_limit(&query_params, CHAIN_TXS_PER_PAGE, config.max_txs_per_page)?,
+            };
+            let after_txid = match paginator {
+                Some(ref paginator) => paginator.cursor::<Txid>()?,
+                None => query_params
+                    .get("after_txid")
+                    .map(|s| s.parse::<Txid>())
+                    .transpose()
+                    .map_err(|_| HttpError::from("Invalid after_txid".to_string()))?,
+            };
+
+            // Mempool transactions come first, followed by the confirmed ones
+            let mut summary = query
+                .mempool()
+                .summary(&script_hash[..], after_txid.as_ref(), limit);
+
+            if summary.len() < limit {
+                let chain_after_txid = after_txid
+                    .as_ref()
+                    .filter(|txid| {
+                        summary.is_empty() && query.mempool().lookup_txn(txid).is_none()
+                    });
+                summary.extend(query.chain().summary(
+                    &script_hash[..],
+                    chain_after_txid,
+                    limit - summary.len(),
+                ));
+            }
+
+            if let Some(paginator) = paginator {
+                let next_cursor = summary
+                    .last()
+                    .map(|entry| entry.txid)
+                    .filter(|_| paginator.is_full(summary.len()));
+                return paginator.respond(summary, next_cursor, None, cache_ttl.short);
+            }
+
+            json_response(summary, cache_ttl.short)
         }
 
         (
@@ -1068,13 +3266,211 @@ fn handle_request(
         ) => {
             // Legacy endpoint without pagination for backward compatibility
             let script_hash = to_scripthash(script_type, script_str, config.network_type)?;
-            let utxos: Vec<UtxoValue> = query
-                .utxo(&script_hash[..])?
-                .into_iter()
-                .map(UtxoValue::from)
-                .collect();
-                
-            json_response(utxos, TTL_SHORT)
+            let with_orphaned = query_params.get("orphaned").map_or(false, |v| v == "true");
+            let utxos = prepare_utxos(
+                query.utxo(&script_hash[..])?,
+                &script_hash[..],
+                with_orphaned,
+                query,
+            );
+
+            json_response(utxos, cache_ttl.short)
+        }
+        #[cfg(not(feature = "liquid"))]
+        (
+            &Method::GET,
+            Some(script_type @ &"address"),
+            Some(script_str),
+            Some(&"delta"),
+            None,
+            None,
+        )
+        | (
+            &Method::GET,
+            Some(script_type @ &"scripthash"),
+            Some(script_str),
+            Some(&"delta"),
+            None,
+            None,
+        ) => {
+            let script_hash = to_scripthash(script_type, script_str, config.network_type)?;
+            let since_height = query_params
+                .get("since_height")
+                .ok_or_else(|| HttpError::from("Missing since_height".to_string()))?
+                .parse::<usize>()
+                .map_err(|_| HttpError::from("Invalid since_height".to_string()))?;
+            // the tip is read first, so that resuming past it may repeat events but never miss any
+            let tip_height = query.chain().best_height();
+            let tip_hash = query.chain().best_hash();
+            let (events, next_since_height) = query
+                .history_delta(&script_hash[..], since_height, ADDRESS_DELTA_MAX_EVENTS)
+                .map_err(|err| match err.kind() {
+                    errors::ErrorKind::TooPopular => HttpError::from(format!(
+                        "Exceeded maximum of {} events in a single block or in the mempool",
+                        ADDRESS_DELTA_MAX_EVENTS
+                    )),
+                    _ => HttpError::from(err),
+                })?;
+            json_response(
+                json!({
+                    "since_height": since_height,
+                    "tip_height": tip_height,
+                    "tip_hash": tip_hash,
+                    "events": events,
+                    "truncated": next_since_height.is_some(),
+                    "next_since_height": next_since_height,
+                }),
+                cache_ttl.short,
+            )
+        }
+        #[cfg(not(feature = "liquid"))]
+        (
+            &Method::GET,
+            Some(script_type @ &"address"),
+            Some(script_str),
+            Some(&"activity"),
+            None,
+            None,
+        )
+        | (
+            &Method::GET,
+            Some(script_type @ &"scripthash"),
+            Some(script_str),
+            Some(&"activity"),
+            None,
+            None,
+        ) => {
+            let script_hash = to_scripthash(script_type, script_str, config.network_type)?;
+            let window = parse_window(
+                query_params
+                    .get("window")
+                    .map_or(ACTIVITY_DEFAULT_WINDOW, String::as_str),
+            )?;
+            if window > ACTIVITY_MAX_WINDOW {
+                return Err(HttpError::from(format!(
+                    "Exceeded maximum window of {} days",
+                    ACTIVITY_MAX_WINDOW.as_secs() / 86_400
+                )));
+            }
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+            let since = now.saturating_sub(window).as_secs();
+
+            let activity = query.activity(&script_hash[..], since as u32, ACTIVITY_MAX_TXS);
+            json_response(
+                json!({
+                    "window": window.as_secs(),
+                    "since": since,
+                    "tx_count": activity.tx_count,
+                    "received_count": activity.received_count,
+                    "sent_count": activity.sent_count,
+                    "received_value": activity.received_value,
+                    "sent_value": activity.sent_value,
+                    "counterparty_count": activity.counterparty_count,
+                    "truncated": activity.truncated,
+                }),
+                cache_ttl.short,
+            )
+        }
+        #[cfg(not(feature = "liquid"))]
+        (
+            &Method::GET,
+            Some(script_type @ &"address"),
+            Some(script_str),
+            Some(&"utxo"),
+            Some(&"summary"),
+            None,
+        )
+        | (
+            &Method::GET,
+            Some(script_type @ &"scripthash"),
+            Some(script_str),
+            Some(&"utxo"),
+            Some(&"summary"),
+            None,
+        ) => {
+            let script_hash = to_scripthash(script_type, script_str, config.network_type)?;
+            let dust_feerate = query_params.get("feerate").map_or(
+                Ok(config.network_type.params().dust_relay_feerate),
+                |feerate| feerate.parse::<u64>(),
+            )?;
+
+            let utxos = query.utxo_values(&script_hash[..])?;
+
+            // all the outputs share the same script, any of them tells how they are spent
+            let spending_vsize = utxos.first().map_or(0, |(outpoint, _)| {
+                query
+                    .lookup_txos(&std::iter::once(*outpoint).collect())
+                    .get(outpoint)
+                    .map_or(0, |txout| spending_vsize(&txout.script_pubkey))
+            });
+
+            let values: Vec<u64> = utxos.into_iter().map(|(_, value)| value).collect();
+            let summary = UtxoSummaryValue::new(
+                &values,
+                dust_feerate,
+                dust_feerate.saturating_mul(spending_vsize),
+            );
+            json_response(summary, cache_ttl.short)
+        }
+        #[cfg(not(feature = "liquid"))]
+        (
+            &Method::GET,
+            Some(script_type @ &"address"),
+            Some(script_str),
+            Some(&"consolidation-plan"),
+            None,
+            None,
+        )
+        | (
+            &Method::GET,
+            Some(script_type @ &"scripthash"),
+            Some(script_str),
+            Some(&"consolidation-plan"),
+            None,
+            None,
+        ) => {
+            let script_hash = to_scripthash(script_type, script_str, config.network_type)?;
+            let feerate = query_params
+                .get("feerate")
+                .ok_or_else(|| HttpError::from("Missing feerate".to_string()))?
+                .parse::<u64>()
+                .map_err(|_| HttpError::from("Invalid feerate".to_string()))?;
+            let target_utxos = query_params
+                .get("target_utxos")
+                .map_or(Ok(1), |target| target.parse::<usize>())
+                .ok()
+                .filter(|target| *target > 0)
+                .ok_or_else(|| HttpError::from("Invalid target_utxos".to_string()))?;
+            let order = query_params
+                .get("order")
+                .map_or(Ok(ConsolidationOrder::Smallest), |order| order.parse())?;
+
+            let utxos = query.utxo_values_with_height(&script_hash[..])?;
+
+            // all the outputs share the same script, any of them tells how they are spent
+            let script = utxos.first().map_or_else(Script::new, |(outpoint, _, _)| {
+                query
+                    .lookup_txos(&std::iter::once(*outpoint).collect())
+                    .remove(outpoint)
+                    .map_or_else(Script::new, |txout| txout.script_pubkey)
+            });
+            let shape = SpendShape {
+                input_vsize: spending_vsize(&script),
+                output_vsize: output_vsize(&script),
+                segwit: spends_with_witness(&script),
+            };
+
+            let plan = plan_consolidation(
+                utxos,
+                &shape,
+                feerate,
+                target_utxos,
+                order,
+                CONSOLIDATION_MAX_INPUTS,
+            );
+            json_response(plan, cache_ttl.short)
         }
         (
             &Method::GET,
@@ -1093,43 +3489,26 @@ fn handle_request(
             None,
         ) => {
             let script_hash = to_scripthash(script_type, script_str, config.network_type)?;
+            let with_orphaned = query_params.get("orphaned").map_or(false, |v| v == "true");
 
-            // Check if cursor parameter is provided (for cursor-based pagination)
-            let has_cursor = query_params.contains_key("cursor");
-            
             // Check if index-based pagination parameters are provided
             let has_pagination_params = query_params.contains_key("start_index") || query_params.contains_key("limit");
 
             // Get pagination parameters from query
-            let limit: usize = query_params
-                .get("limit")
-                .and_then(|s| s.parse().ok())
-                .unwrap_or(config.utxos_limit);
+            let limit = parse_limit(&query_params, config.utxos_limit, config.utxos_limit)?;
 
-            if has_cursor {
+            if let Some(paginator) =
+                Paginator::from_params(&query_params, config.utxos_limit, config.utxos_limit)?
+            {
                 // Use cursor-based pagination
-                let cursor = parse_cursor(query_params.get("cursor").unwrap())?;
-                let (utxos, total_count, next_cursor) = query.utxo_with_cursor(&script_hash[..], cursor, limit)?;
-                
-                // Format UTXOs for response
-                let utxos_json: Vec<UtxoValue> = utxos
-                    .into_iter()
-                    .map(UtxoValue::from)
-                    .collect();
+                let cursor = paginator.cursor.as_deref().map_or(Ok(None), parse_cursor)?;
+                let (utxos, total_count, next_cursor) =
+                    query.utxo_with_cursor(&script_hash[..], cursor, paginator.limit)?;
 
-                // Build response with pagination metadata
-                let mut response = json!({
-                    "utxos": utxos_json,
-                    "total": total_count,
-                    "limit": limit
-                });
-                
-                // Add next_cursor if there are more results
-                if let Some((txid, vout)) = next_cursor {
-                    response["next_cursor"] = json!(format!("{:x}:{}", txid, vout));
-                }
-                
-                json_response(response, TTL_SHORT)
+                let utxos_json = prepare_utxos(utxos, &script_hash[..], with_orphaned, query);
+                let next_cursor = next_cursor.map(|(txid, vout)| format!("{:x}:{}", txid, vout));
+
+                paginator.respond(utxos_json, next_cursor, Some(total_count), cache_ttl.short)
             } else if has_pagination_params {
                 // Use index-based pagination for backward compatibility
                 let start_index: usize = query_params
@@ -1140,10 +3519,7 @@ fn handle_request(
                 let (utxos, total_count) = query.utxo_paginated(&script_hash[..], start_index, limit)?;
                 
                 // Format UTXOs for response
-                let utxos_json: Vec<UtxoValue> = utxos
-                    .into_iter()
-                    .map(UtxoValue::from)
-                    .collect();
+                let utxos_json = prepare_utxos(utxos, &script_hash[..], with_orphaned, query);
 
                 // Return with pagination metadata
                 let response = json!({
@@ -1153,24 +3529,87 @@ fn handle_request(
                     "limit": limit
                 });
                 
-                json_response(response, TTL_SHORT)
+                json_response(response, cache_ttl.short)
+            } else if query_params.get("partial").map_or(false, |v| v == "true") {
+                // Past the limit, the first page is served in the cursor envelope along with the
+                // cursor to resume from, rather than failing
+                let (utxos, next_cursor) = query.utxo_truncated(&script_hash[..])?;
+                let utxos_json = prepare_utxos(utxos, &script_hash[..], with_orphaned, query);
+                let next_cursor = next_cursor.map(|(txid, vout)| format!("{:x}:{}", txid, vout));
+                let paginator = Paginator {
+                    cursor: None,
+                    limit: config.utxos_limit,
+                };
+                let truncated = next_cursor.is_some();
+                let mut envelope = paginator.envelope(utxos_json, next_cursor, None);
+                envelope["truncated"] = truncated.into();
+                json_response(envelope, cache_ttl.short)
             } else {
                 // For backward compatibility, return all UTXOs without pagination metadata
-                let utxos: Vec<UtxoValue> = query
-                    .utxo(&script_hash[..])?
-                    .into_iter()
-                    .map(UtxoValue::from)
-                    .collect();
-                    
-                json_response(utxos, TTL_SHORT)
+                let utxos = query.utxo(&script_hash[..])?;
+                let utxos = prepare_utxos(utxos, &script_hash[..], with_orphaned, query);
+                json_response(utxos, cache_ttl.short)
+            }
+        }
+        (&Method::GET, Some(&"script"), Some(script_hash), Some(&"reveal"), None, None) => {
+            if !config.index_revealed_scripts {
+                return Err(HttpError::from("revealed scripts index disabled".to_string()));
             }
+            let script_hash = parse_scripthash(script_hash)?;
+            let revealed = query
+                .chain()
+                .revealed_script(&script_hash[..])
+                .ok_or_else(|| HttpError::not_found("No revealed script found".to_string()))?;
+            let ttl = ttl_by_depth(Some(revealed.confirmed.height), query, cache_ttl);
+            json_response(RevealedScriptValue::from(revealed), ttl)
         }
         (&Method::GET, Some(&"address-prefix"), Some(prefix), None, None, None) => {
             if !config.address_search {
                 return Err(HttpError::from("address search disabled".to_string()));
             }
             let results = query.chain().address_search(prefix, ADDRESS_SEARCH_LIMIT);
-            json_response(results, TTL_SHORT)
+            json_response(results, cache_ttl.short)
+        }
+        #[cfg(not(feature = "liquid"))]
+        (&Method::GET, Some(&"flow"), None, None, None, None) => {
+            if !config.flow_analysis {
+                return Err(HttpError::from("flow analysis disabled".to_string()));
+            }
+            let address_param = |name: &str| {
+                let address = query_params
+                    .get(name)
+                    .ok_or_else(|| HttpError::from(format!("Missing {}", name)))?;
+                address_to_scripthash(address, config.network_type)
+            };
+            let from = address_param("from")?;
+            let to = address_param("to")?;
+            let max_hops = match query_params.get("max_hops") {
+                Some(max_hops) => max_hops
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|max_hops| *max_hops > 0)
+                    .ok_or_else(|| HttpError::from("Invalid max_hops".to_string()))?,
+                None => FLOW_DEFAULT_HOPS,
+            };
+            if max_hops > FLOW_MAX_HOPS {
+                bail!(HttpError::from(format!(
+                    "max_hops exceeds the maximum of {}",
+                    FLOW_MAX_HOPS
+                )));
+            }
+
+            let flow = query.flow(&from, &to, max_hops, FLOW_MAX_NODES, FLOW_MAX_PATHS)?;
+            json_response(
+                json!({
+                    "from": query_params["from"],
+                    "to": query_params["to"],
+                    "max_hops": max_hops,
+                    "found": !flow.paths.is_empty(),
+                    "paths": flow.paths,
+                    "truncated": flow.truncated,
+                }),
+                cache_ttl.short,
+            )
         }
         (&Method::GET, Some(&"tx"), Some(hash), None, None, None) => {
             let hash = Txid::from_str(hash)?;
@@ -1178,9 +3617,41 @@ fn handle_request(
                 .lookup_txn(&hash)
                 .ok_or_else(|| HttpError::not_found("Transaction not found".to_string()))?;
             let blockid = query.chain().tx_confirming_block(&hash);
-            let ttl = ttl_by_depth(blockid.as_ref().map(|b| b.height), query);
+            let block_time = blockid.as_ref().map(|b| b.time);
+            let mut ttl = ttl_by_depth(blockid.as_ref().map(|b| b.height), query, cache_ttl);
+
+            #[cfg(not(feature = "liquid"))]
+            let analyzed = match query_params.get("verbose").map(String::as_str) {
+                Some("true") => {
+                    let outpoints = tx
+                        .input
+                        .iter()
+                        .filter(|txin| has_prevout(txin))
+                        .map(|txin| txin.previous_output)
+                        .collect();
+                    Some((tx.clone(), query.lookup_txos(&outpoints)))
+                }
+                _ => None,
+            };
+
+            let mut tx = prepare_txs(vec![(tx, blockid)], query, config).remove(0);
+            #[cfg(not(feature = "liquid"))]
+            if let Some((rawtx, txos)) = analyzed {
+                tx = tx.with_analysis(&rawtx, &txos, config.network_type);
+            }
 
-            let tx = prepare_txs(vec![(tx, blockid)], query, config).remove(0);
+            // confirmed transactions are valued at the rate as of their block
+            let value = tx.vout.iter().filter_map(TxOutValue::explicit_value).sum();
+            tx.fiat = fiat_value(
+                &query_params,
+                query,
+                config,
+                block_time,
+                &[("value", value), ("fee", tx.fee)],
+            )?;
+            if tx.fiat.is_some() {
+                ttl = cache_ttl.short; // more rates may get known for the block time
+            }
 
             json_response(tx, ttl)
         }
@@ -1191,26 +3662,65 @@ fn handle_request(
                 .lookup_raw_txn(&hash)
                 .ok_or_else(|| HttpError::not_found("Transaction not found".to_string()))?;
 
-            let (content_type, body) = match *out_type {
-                "raw" => ("application/octet-stream", Body::from(rawtx)),
-                "hex" => ("text/plain", Body::from(rawtx.to_lower_hex_string())),
-                _ => unreachable!(),
-            };
-            let ttl = ttl_by_depth(query.get_tx_status(&hash).block_height, query);
+            let ttl = ttl_by_depth(query.get_tx_status(&hash).block_height, query, cache_ttl);
 
-            Ok(Response::builder()
-                .status(StatusCode::OK)
-                .header("Content-Type", content_type)
-                .header("Cache-Control", format!("public, max-age={:}", ttl))
-                .body(body)
-                .unwrap())
+            match *out_type {
+                "raw" => Ok(raw_response(rawtx, ttl, headers.get("Range"))),
+                "hex" => http_message(StatusCode::OK, rawtx.to_lower_hex_string(), ttl),
+                _ => unreachable!(),
+            }
         }
         (&Method::GET, Some(&"tx"), Some(hash), Some(&"status"), None, None) => {
             let hash = Txid::from_str(hash)?;
             let status = query.get_tx_status(&hash);
-            let ttl = ttl_by_depth(status.block_height, query);
+            let ttl = ttl_by_depth(status.block_height, query, cache_ttl);
             json_response(status, ttl)
         }
+        (&Method::GET, Some(&"tx"), Some(hash), Some(&"broadcast-status"), None, None) => {
+            let hash = Txid::from_str(hash)?;
+            let status = query.broadcast_status(&hash).ok_or_else(|| {
+                HttpError::not_found("Transaction was not queued for broadcast".to_string())
+            })?;
+            json_response(status, 0)
+        }
+        (&Method::GET, Some(&"tx"), Some(hash), Some(&"wait"), None, None) => {
+            let hash = Txid::from_str(hash)?;
+            let confirmations = query_params
+                .get("confirmations")
+                .map_or(Ok(1), |c| c.parse::<usize>())?;
+            let deadline = Instant::now() + longpoll_timeout(&query_params)?;
+            if query.lookup_raw_txn(&hash).is_none() {
+                bail!(HttpError::not_found("Transaction not found".to_string()));
+            }
+
+            // hold the request until the tx is buried deep enough or the timeout expires,
+            // re-checking whenever the indexer advances the tip
+            let (status, depth) = tokio::task::block_in_place(|| loop {
+                let tip = query.chain().best_hash();
+                let status = query.get_tx_status(&hash);
+                // the tip may have been reorged below the height of the status meanwhile
+                let depth = status.block_height.map_or(0, |height| {
+                    (query.chain().best_height() + 1).saturating_sub(height)
+                });
+                let now = Instant::now();
+                if depth >= confirmations || now >= deadline {
+                    break (status, depth);
+                }
+                query.chain().wait_tip_change(&tip, deadline - now);
+            });
+
+            json_response(
+                json!({
+                    "confirmed": status.confirmed,
+                    "block_height": status.block_height,
+                    "block_hash": status.block_hash,
+                    "block_time": status.block_time,
+                    "confirmations": depth,
+                    "timed_out": depth < confirmations,
+                }),
+                0,
+            )
+        }
 
         (&Method::GET, Some(&"tx"), Some(hash), Some(&"merkle-proof"), None, None) => {
             let hash = Txid::from_str(hash)?;
@@ -1220,7 +3730,7 @@ fn handle_request(
             let (merkle, pos) =
                 electrum_merkle::get_tx_merkle_proof(query.chain(), &hash, &blockid.hash)?;
             let merkle: Vec<String> = merkle.into_iter().map(|txid| txid.to_string()).collect();
-            let ttl = ttl_by_depth(Some(blockid.height), query);
+            let ttl = ttl_by_depth(Some(blockid.height), query, cache_ttl);
             json_response(
                 json!({ "block_height": blockid.height, "merkle": merkle, "pos": pos }),
                 ttl,
@@ -1241,7 +3751,7 @@ fn handle_request(
             http_message(
                 StatusCode::OK,
                 encode::serialize_hex(&merkleblock),
-                ttl_by_depth(height, query),
+                ttl_by_depth(height, query, cache_ttl),
             )
         }
         (&Method::GET, Some(&"tx"), Some(hash), Some(&"outspend"), Some(index), None) => {
@@ -1259,21 +3769,96 @@ fn handle_request(
                     .as_ref()
                     .and_then(|ref status| status.block_height),
                 query,
+                cache_ttl,
             );
             json_response(spend, ttl)
         }
         (&Method::GET, Some(&"tx"), Some(hash), Some(&"outspends"), None, None) => {
             let hash = Txid::from_str(hash)?;
-            let tx = query
-                .lookup_txn(&hash)
+            let outputs = query
+                .lookup_tx_outputs(&hash)
                 .ok_or_else(|| HttpError::not_found("Transaction not found".to_string()))?;
             let spends: Vec<SpendingValue> = query
-                .lookup_tx_spends(tx)
+                .lookup_tx_spends(&hash, &outputs)?
                 .into_iter()
                 .map(|spend| spend.map_or_else(SpendingValue::default, SpendingValue::from))
                 .collect();
             // @TODO long ttl if all outputs are either spent long ago or unspendable
-            json_response(spends, TTL_SHORT)
+            json_response(spends, cache_ttl.short)
+        }
+        (&Method::GET, Some(&"tx"), Some(hash), Some(&"timelocks"), None, None) => {
+            let hash = Txid::from_str(hash)?;
+            let tx = query
+                .lookup_txn(&hash)
+                .ok_or_else(|| HttpError::not_found("Transaction not found".to_string()))?;
+            json_response(TimelocksValue::new(&tx, query), cache_ttl.short)
+        }
+        (&Method::GET, Some(&"tx"), Some(hash), Some(relation @ &"ancestors"), None, None)
+        | (&Method::GET, Some(&"tx"), Some(hash), Some(relation @ &"descendants"), None, None) => {
+            let hash = Txid::from_str(hash)?;
+            let max_depth = match query_params.get("depth") {
+                Some(depth) => depth
+                    .parse::<usize>()
+                    .map_err(|_| HttpError::from("Invalid depth".to_string()))?,
+                None => TX_GRAPH_DEFAULT_DEPTH,
+            };
+            if max_depth > TX_GRAPH_MAX_DEPTH {
+                bail!(HttpError::from(format!(
+                    "depth exceeds the maximum of {}",
+                    TX_GRAPH_MAX_DEPTH
+                )));
+            }
+            query
+                .lookup_tx_outputs(&hash)
+                .ok_or_else(|| HttpError::not_found("Transaction not found".to_string()))?;
+
+            let relatives = match *relation {
+                "ancestors" => query.tx_ancestors(&hash, max_depth),
+                _ => query.tx_descendants(&hash, max_depth),
+            };
+            json_response(relatives, cache_ttl.short)
+        }
+        #[cfg(not(feature = "liquid"))]
+        (&Method::GET, Some(&"tx"), Some(hash), Some(&"graph"), None, None) => {
+            let hash = Txid::from_str(hash)?;
+            let max_depth = match query_params.get("depth") {
+                Some(depth) => depth
+                    .parse::<usize>()
+                    .map_err(|_| HttpError::from("Invalid depth".to_string()))?,
+                None => TX_SPEND_GRAPH_DEFAULT_DEPTH,
+            };
+            if max_depth > TX_SPEND_GRAPH_MAX_DEPTH {
+                bail!(HttpError::from(format!(
+                    "depth exceeds the maximum of {}",
+                    TX_SPEND_GRAPH_MAX_DEPTH
+                )));
+            }
+            let direction = match query_params.get("direction").map(String::as_str) {
+                None | Some("downstream") => GraphDirection::Downstream,
+                Some("upstream") => GraphDirection::Upstream,
+                Some(_) => bail!(HttpError::from(
+                    "direction must be upstream or downstream".to_string()
+                )),
+            };
+            let min_value = match query_params.get("min_value") {
+                Some(min_value) => min_value
+                    .parse::<u64>()
+                    .map_err(|_| HttpError::from("Invalid min_value".to_string()))?,
+                None => 0,
+            };
+            query
+                .lookup_tx_outputs(&hash)
+                .ok_or_else(|| HttpError::not_found("Transaction not found".to_string()))?;
+
+            let graph = query.tx_graph(
+                &hash,
+                direction,
+                max_depth,
+                min_value,
+                TX_SPEND_GRAPH_MAX_NODES,
+                TX_SPEND_GRAPH_MAX_EDGES,
+            )?;
+            json_response(graph, cache_ttl.short)
         }
         (&Method::GET, Some(&"broadcast"), None, None, None, None)
         | (&Method::POST, Some(&"tx"), None, None, None, None) => {
@@ -1287,26 +3872,116 @@ fn handle_request(
                     .ok_or_else(|| HttpError::from("Missing tx".to_string()))?,
                 _ => return http_message(StatusCode::METHOD_NOT_ALLOWED, "Invalid method", 0),
             };
-            let txid = query
-                .broadcast_raw(&txhex)
-                .map_err(|err| HttpError::from(err.description().to_string()))?;
-            http_message(StatusCode::OK, txid.to_string(), 0)
+            let idempotency_key = match headers.get("X-Idempotency-Key") {
+                Some(value) => Some(
+                    value
+                        .to_str()
+                        .ok()
+                        .filter(|key| !key.is_empty() && key.len() <= idempotency::MAX_KEY_LEN)
+                        .ok_or_else(|| HttpError::from("Invalid X-Idempotency-Key".to_string()))?,
+                ),
+                None => None,
+            };
+            // retries of a broadcast that went through return its original result. The keys
+            // are scoped to the API key of the client, or else to its address.
+            let client = match (headers.get("X-Api-Key"), remote_ip) {
+                (Some(api_key), _) => format!("key:{}", api_key.to_str().unwrap_or("")),
+                (None, Some(ip)) => format!("ip:{}", ip),
+                (None, None) => String::new(),
+            };
+            let reservation = match idempotency_key.map(|key| query.reserve_broadcast(&client, key))
+            {
+                Some(Reserved::Broadcasted(txid)) => {
+                    let requested_txid = Vec::<u8>::from_hex(txhex.trim())
+                        .ok()
+                        .and_then(|bytes| encode::deserialize::<Transaction>(&bytes).ok())
+                        .map(|tx| tx.txid());
+                    if requested_txid != Some(txid) {
+                        bail!(HttpError(
+                            StatusCode::UNPROCESSABLE_ENTITY,
+                            "X-Idempotency-Key was already used for another transaction"
+                                .to_string()
+                        ));
+                    }
+                    return http_message(StatusCode::OK, txid.to_string(), 0);
+                }
+                Some(Reserved::Reservation(reservation)) => Some(reservation),
+                None => None,
+            };
+            let track = query_params.get("rebroadcast").map_or(true, |v| v != "false");
+            let broadcast = query
+                .broadcast_raw(&txhex, track)
+                .map_err(|err| HttpError::from(err.to_string()))?;
+            if broadcast.queued {
+                // not submitted yet, retries get deduplicated by the queue
+                return http_message(StatusCode::ACCEPTED, broadcast.txid.to_string(), 0);
+            }
+            if let Some(reservation) = reservation {
+                reservation.record(query.chain(), &broadcast.txid);
+            }
+            http_message(StatusCode::OK, broadcast.txid.to_string(), 0)
+        }
+
+        #[cfg(feature = "liquid")]
+        (&Method::POST, Some(&"tx"), Some(hash), Some(&"unblind"), None, None) => {
+            let hash = Txid::from_str(hash)?;
+            let request: unblind::UnblindRequest =
+                serde_json::from_slice(&body).map_err(|err| HttpError::from(err.to_string()))?;
+            let tx = query
+                .lookup_txn(&hash)
+                .ok_or_else(|| HttpError::not_found("Transaction not found".to_string()))?;
+            // the response depends on the secrets in the request, and must not be cached
+            json_response(unblind::unblind_outputs(&tx, request)?, 0)
+        }
+
+        #[cfg(not(feature = "liquid"))]
+        (&Method::POST, Some(&"tx"), Some(&"analyze"), None, None, None) => {
+            let txhex = String::from_utf8(body.to_vec())?;
+            let tx: Transaction = encode::deserialize(&Vec::<u8>::from_hex(txhex.trim())?)?;
+            let outpoints = tx
+                .input
+                .iter()
+                .filter(|txin| has_prevout(txin))
+                .map(|txin| txin.previous_output)
+                .collect();
+            let txos = query
+                .mempool()
+                .lookup_txos(&outpoints)
+                .map_err(|_| HttpError::from("Transaction spends unknown outputs".to_string()))?;
+            let txvalue = TransactionValue::new(tx.clone(), None, &txos, config)
+                .with_feerate()
+                .with_analysis(&tx, &txos, config.network_type);
+            json_response(txvalue, 0)
         }
 
         (&Method::GET, Some(&"mempool"), None, None, None, None) => {
-            json_response(query.mempool().backlog_stats(), TTL_SHORT)
+            json_response(query.mempool().backlog_stats(), cache_ttl.short)
         }
         (&Method::GET, Some(&"mempool"), Some(&"txids"), None, None, None) => {
+            if let Some(paginator) = Paginator::from_params(
+                &query_params,
+                MEMPOOL_TXIDS_PER_PAGE,
+                MAX_MEMPOOL_TXIDS_PER_PAGE,
+            )? {
+                let mempool = query.mempool();
+                let after_txid = paginator.cursor::<Txid>()?;
+                let txids = mempool.txids_page(after_txid.as_ref(), paginator.limit);
+                let next_cursor = txids
+                    .last()
+                    .filter(|_| paginator.is_full(txids.len()))
+                    .cloned();
+                let total = mempool.count();
+                return paginator.respond(txids, next_cursor, Some(total), cache_ttl.short);
+            }
+
             // Get pagination parameters from query
             let start_index: usize = query_params
                 .get("start_index")
                 .and_then(|s| s.parse().ok())
                 .unwrap_or(0);
 
-            let limit: usize = query_params
-                .get("limit")
-                .and_then(|s| s.parse().ok())
-                .unwrap_or(100);
+            let limit =
+                parse_limit(&query_params, MEMPOOL_TXIDS_PER_PAGE, MAX_MEMPOOL_TXIDS_PER_PAGE)?;
 
             // Get all txids and apply pagination
             let all_txids = query.mempool().txids();
@@ -1327,17 +4002,157 @@ fn handle_request(
                 "limit": limit
             });
 
-            json_response(response, TTL_SHORT)
+            json_response(response, cache_ttl.short)
+        }
+        #[cfg(not(feature = "liquid"))]
+        (&Method::GET, Some(&"txs"), Some(&"large"), None, None, None) => {
+            let threshold = config
+                .large_tx_threshold
+                .ok_or_else(|| HttpError::from("large transaction index disabled".to_string()))?;
+            let min_value = query_params
+                .get("min_value")
+                .map_or(Ok(threshold), |value| value.parse::<u64>())?;
+            let since = query_params
+                .get("since")
+                .map_or(Ok(0), |height| height.parse::<usize>())?;
+            let limit = parse_limit(&query_params, CHAIN_TXS_PER_PAGE, config.max_txs_per_page)?;
+
+            // Mempool transactions come first, followed by the confirmed ones
+            let mut txs: Vec<LargeTxValue> = query
+                .mempool()
+                .large_txs(min_value)
+                .into_iter()
+                .take(limit)
+                .map(|(txid, value)| LargeTxValue::new(txid, value, None))
+                .collect();
+            if txs.len() < limit {
+                txs.extend(
+                    query
+                        .chain()
+                        .large_txs(min_value, since, limit - txs.len())
+                        .into_iter()
+                        .map(|(txid, value, blockid)| {
+                            LargeTxValue::new(txid, value, Some(blockid))
+                        }),
+                );
+            }
+            json_response(txs, cache_ttl.short)
+        }
+
+        #[cfg(not(feature = "liquid"))]
+        (&Method::GET, Some(&"templates"), None, None, None, None) => {
+            let templates: Vec<ScriptTemplateValue> = query
+                .chain()
+                .store()
+                .script_templates()
+                .into_iter()
+                .map(ScriptTemplateValue::from)
+                .collect();
+            json_response(templates, cache_ttl.short)
+        }
+
+        #[cfg(not(feature = "liquid"))]
+        (&Method::GET, Some(&"templates"), Some(name), Some(&"txs"), None, None) => {
+            let templates = query.chain().store().script_templates();
+            if !templates.iter().any(|template| template.name == *name) {
+                return Err(HttpError::not_found("Script template not found".to_string()));
+            }
+            // always paginated, starting from the most recent transactions when no cursor is given
+            let limit = parse_limit(&query_params, CHAIN_TXS_PER_PAGE, config.max_txs_per_page)?;
+            let paginator = Paginator::from_params(
+                &query_params,
+                CHAIN_TXS_PER_PAGE,
+                config.max_txs_per_page,
+            )?
+            .unwrap_or(Paginator {
+                cursor: None,
+                limit,
+            });
+            let last_seen_txid = paginator.cursor::<Txid>()?;
+
+            let txs: Vec<(Transaction, Option<BlockId>)> = query
+                .chain()
+                .template_history(name, last_seen_txid.as_ref(), paginator.limit)
+                .into_iter()
+                .map(|(tx, blockid)| (tx, Some(blockid)))
+                .collect();
+            let next_cursor = txs
+                .last()
+                .filter(|_| paginator.is_full(txs.len()))
+                .map(|(tx, _)| tx.txid());
+            paginator.respond(prepare_txs(txs, query, config), next_cursor, None, cache_ttl.short)
+        }
+
+        #[cfg(not(feature = "liquid"))]
+        (&Method::GET, Some(&"search"), Some(&"outputs"), None, None, None) => {
+            if !config.index_output_values {
+                return Err(HttpError::from("output value index disabled".to_string()));
+            }
+            let parse_param = |name: &str| {
+                query_params
+                    .get(name)
+                    .map(|value| {
+                        value
+                            .parse::<usize>()
+                            .map_err(|_| HttpError::from(format!("Invalid {}", name)))
+                    })
+                    .transpose()
+            };
+            let value = query_params
+                .get("value")
+                .ok_or_else(|| HttpError::from("Missing value".to_string()))?
+                .parse::<u64>()
+                .map_err(|_| HttpError::from("Invalid value".to_string()))?;
+            let min_height = parse_param("since")?.unwrap_or(0);
+            let max_height = parse_param("until")?.unwrap_or(usize::MAX);
+            let limit = parse_limit(&query_params, CHAIN_TXS_PER_PAGE, config.max_txs_per_page)?;
+
+            // Mempool outputs come first (unless searching a bounded height range), followed by
+            // the confirmed ones
+            let mut outputs: Vec<(OutPoint, Option<BlockId>)> = if max_height == usize::MAX {
+                query
+                    .mempool()
+                    .outputs_by_value(value)
+                    .into_iter()
+                    .take(limit)
+                    .map(|outpoint| (outpoint, None))
+                    .collect()
+            } else {
+                vec![]
+            };
+            if outputs.len() < limit {
+                outputs.extend(
+                    query
+                        .chain()
+                        .outputs_by_value(value, min_height, max_height, limit - outputs.len())
+                        .into_iter()
+                        .map(|(outpoint, blockid)| (outpoint, Some(blockid))),
+                );
+            }
+            let outputs: Vec<serde_json::Value> = outputs
+                .into_iter()
+                .map(|(outpoint, blockid)| {
+                    json!({
+                        "txid": outpoint.txid,
+                        "vout": outpoint.vout,
+                        "value": value,
+                        "status": TransactionStatus::from(blockid),
+                    })
+                })
+                .collect();
+            json_response(outputs, cache_ttl.short)
         }
+
         (&Method::GET, Some(&"mempool"), Some(&"recent"), None, None, None) => {
             let mempool = query.mempool();
             let _recent = mempool.recent_txs_overview();
-            json_response(_recent, TTL_MEMPOOL_RECENT)
+            json_response(_recent, cache_ttl.mempool_recent)
         }
 
         (&Method::POST, Some(&_internal_prefix), Some(&"mempool"), Some(&"txs"), None, None) => {
             let _txid_strings: Vec<String> =
                 serde_json::from_slice(&body).map_err(|err| HttpError::from(err.to_string()))?;
+            check_batch_size(_txid_strings.len(), "txids", config)?;
 
             match _txid_strings
                 .into_iter()
@@ -1371,7 +4186,13 @@ fn handle_request(
             let max_txs = query_params
                 .get("max_txs")
                 .and_then(|s| s.parse::<usize>().ok())
-                .unwrap_or(MAX_MEMPOOL_TXS);
+                .unwrap_or(config.max_mempool_txs);
+            if max_txs > config.max_mempool_txs {
+                return Err(HttpError::from(format!(
+                    "max_txs exceeds the maximum of {} transactions",
+                    config.max_mempool_txs
+                )));
+            }
 
             // Since txs_page is not available, use the standard txids method and filter
             let all_txs: Vec<(Transaction, Option<BlockId>)> = {
@@ -1392,11 +4213,343 @@ fn handle_request(
                     .collect()
             };
 
-            json_response(prepare_txs(all_txs, query, config), TTL_SHORT)
+            json_response(prepare_txs(all_txs, query, config), cache_ttl.short)
         }
 
         (&Method::GET, Some(&"fee-estimates"), None, None, None, None) => {
-            json_response(query.estimate_fee_map(), TTL_SHORT)
+            json_response(query.estimate_fee_map(), cache_ttl.short)
+        }
+
+        #[cfg(not(feature = "liquid"))]
+        (&Method::POST, Some(&"tools"), Some(&"batching-report"), None, None, None) => {
+            let payments: Vec<PaymentRequest> =
+                serde_json::from_slice(&body).map_err(|err| HttpError::from(err.to_string()))?;
+            if payments.is_empty() {
+                return Err(HttpError::from("No payments".to_string()));
+            }
+            check_batch_size(payments.len(), "payments", config)?;
+            let payment_value = payments
+                .iter()
+                .try_fold(0u64, |total, payment| total.checked_add(payment.amount))
+                .ok_or_else(|| HttpError::from("Payment amounts overflow".to_string()))?;
+            let input_type = query_params
+                .get("input_type")
+                .map_or(BATCHING_INPUT_TYPE, String::as_str);
+
+            let (conf_target, feerate) = match query_params.get("feerate") {
+                Some(feerate) => {
+                    let feerate = feerate
+                        .parse::<f64>()
+                        .ok()
+                        .filter(|feerate| feerate.is_finite() && *feerate >= 0.0)
+                        .ok_or_else(|| HttpError::from("Invalid feerate".to_string()))?;
+                    (None, feerate)
+                }
+                None => {
+                    let conf_target = query_params
+                        .get("conf_target")
+                        .map_or(Ok(BATCHING_CONF_TARGET), |target| target.parse::<u16>())
+                        .map_err(|_| HttpError::from("Invalid conf_target".to_string()))?;
+                    let feerate = query.estimate_fee(conf_target).ok_or_else(|| {
+                        HttpError(
+                            StatusCode::SERVICE_UNAVAILABLE,
+                            format!("No fee estimate for a target of {} blocks", conf_target),
+                        )
+                    })?;
+                    (Some(conf_target), feerate)
+                }
+            };
+
+            let payments = payments
+                .into_iter()
+                .map(|payment| {
+                    let script = address_to_script(&payment.address, config.network_type)?;
+                    Ok((payment, output_vsize(&script)))
+                })
+                .collect::<Result<Vec<_>, HttpError>>()?;
+            let report = BatchingReportValue::new(
+                payments,
+                payment_value,
+                input_type,
+                conf_target,
+                feerate,
+            )?;
+            json_response(report, 0)
+        }
+
+        (&Method::POST, Some(&"admin"), Some(&"cache"), Some(&"flush"), None, None) => {
+            require_admin(config, headers)?;
+            let flushed = query.chain().flush_caches();
+            info!("flushed {} cache entries", flushed);
+            json_response(json!({ "flushed": flushed }), 0)
+        }
+
+        (&Method::POST, Some(&"admin"), Some(&"reindex-scripthash"), None, None, None) => {
+            require_admin(config, headers)?;
+            if config.replica_path.is_some() {
+                return Err(HttpError::from(
+                    "Reindexing is not supported by read-only replicas".to_string(),
+                ));
+            }
+            let request: ReindexScripthashRequest =
+                serde_json::from_slice(&body).map_err(|err| HttpError::from(err.to_string()))?;
+            let scripthash = parse_scripthash(&request.scripthash)?;
+            let status = query
+                .start_reindex(&scripthash[..], &request.scripthash, request.dry_run)
+                .ok_or_else(|| {
+                    HttpError(
+                        StatusCode::CONFLICT,
+                        "Another reindexing is running".to_string(),
+                    )
+                })?;
+            info!(
+                "started reindexing scripthash {} (dry run: {}) as job {}",
+                request.scripthash, request.dry_run, status.id
+            );
+            let mut resp = json_response(status, 0)?;
+            *resp.status_mut() = StatusCode::ACCEPTED;
+            Ok(resp)
+        }
+
+        (&Method::GET, Some(&"admin"), Some(&"reindex-scripthash"), None, None, None) => {
+            require_admin(config, headers)?;
+            json_response(query.reindex_jobs(), 0)
+        }
+
+        (&Method::GET, Some(&"admin"), Some(&"reindex-scripthash"), Some(id), None, None) => {
+            require_admin(config, headers)?;
+            let id = id
+                .parse::<u64>()
+                .map_err(|_| HttpError::from("Invalid job id".to_string()))?;
+            let status = query
+                .reindex_status(id)
+                .ok_or_else(|| HttpError::not_found("Reindex job not found".to_string()))?;
+            json_response(status, 0)
+        }
+
+        #[cfg(not(feature = "liquid"))]
+        (&Method::POST, Some(&"admin"), Some(&"templates"), None, None, None) => {
+            require_admin(config, headers)?;
+            let request: ScriptTemplateValue =
+                serde_json::from_slice(&body).map_err(|err| HttpError::from(err.to_string()))?;
+            let template = ScriptTemplate::parse(&request.name, &request.pattern)
+                .map_err(|err| HttpError::from(err.to_string()))?;
+            let registered = ScriptTemplateValue::from(template.clone());
+            query
+                .chain()
+                .store()
+                .register_script_template(template)
+                .map_err(|err| HttpError::from(err.to_string()))?;
+            info!("registered script template {}: {}", registered.name, registered.pattern);
+            json_response(registered, 0)
+        }
+
+        (&Method::GET, Some(&"admin"), Some(&"rebroadcast"), Some(&"pending"), None, None) => {
+            require_admin(config, headers)?;
+            let after = config.rebroadcast_after.ok_or_else(|| {
+                HttpError::not_found("Rebroadcasting is disabled".to_string())
+            })?;
+            let tip_height = query.chain().best_height();
+            let pending: Vec<serde_json::Value> = rebroadcast::pending(query.chain())
+                .into_iter()
+                .map(|(txid, entry)| {
+                    let since_height = entry
+                        .last_rebroadcast_height
+                        .unwrap_or(entry.broadcast_height);
+                    json!({
+                        "txid": txid,
+                        "broadcast_height": entry.broadcast_height,
+                        "broadcast_time": entry.broadcast_time,
+                        "rebroadcast_count": entry.rebroadcast_count,
+                        "last_rebroadcast_height": entry.last_rebroadcast_height,
+                        "next_rebroadcast_height": (since_height + after).max(tip_height + 1),
+                    })
+                })
+                .collect();
+            json_response(
+                json!({
+                    "rebroadcast_after": after,
+                    "tip_height": tip_height,
+                    "pending": pending,
+                }),
+                0,
+            )
+        }
+
+        (&Method::GET, Some(&"admin"), Some(&"integrity"), None, None, None) => {
+            require_admin(config, headers)?;
+            let interval = config.integrity_check_interval.ok_or_else(|| {
+                HttpError::not_found("Integrity checking is disabled".to_string())
+            })?;
+            let report = integrity::report(query.chain()).unwrap_or_default();
+            json_response(
+                json!({
+                    "check_interval": interval,
+                    "sample_size": integrity::SAMPLE_SIZE,
+                    "runs": report.runs,
+                    "last_run": report.last_run,
+                    "checked_scripthashes": report.checked_scripthashes,
+                    "checked_entries": report.checked_entries,
+                    "findings_count": report.findings_count,
+                    "findings": report.findings,
+                }),
+                0,
+            )
+        }
+
+        #[cfg(feature = "liquid")]
+        (
+            &Method::POST,
+            Some(&"admin"),
+            Some(&"asset"),
+            Some(asset_str),
+            Some(&"refresh"),
+            None,
+        ) => {
+            require_admin(config, headers)?;
+            let asset_id = AssetId::from_str(asset_str)?;
+            let meta = query.refresh_asset_meta(&asset_id)?.ok_or_else(|| {
+                HttpError::not_found("Asset not found in the registry".to_string())
+            })?;
+            info!("refreshed the registry metadata of asset {}", asset_id);
+            json_response(meta, 0)
+        }
+
+        #[cfg(not(feature = "liquid"))]
+        (&Method::GET, Some(&"mining"), Some(&"difficulty"), Some(&"epochs"), None, None) => {
+            let limit =
+                parse_limit(&query_params, DIFFICULTY_EPOCHS_DEFAULT, DIFFICULTY_EPOCHS_MAX)?;
+            let epochs = difficulty::epochs(query.chain(), config.retarget_interval, limit)?;
+            json_response(epochs, cache_ttl.short)
+        }
+
+        #[cfg(not(feature = "liquid"))]
+        (&Method::GET, Some(&"mining"), Some(&"template"), None, None, None) => {
+            require_admin(config, headers)?;
+            json_response(query.block_template()?, 0)
+        }
+
+        #[cfg(not(feature = "liquid"))]
+        (&Method::GET, Some(&"mining"), Some(&"template"), Some(&"wait"), None, None) => {
+            require_admin(config, headers)?;
+            let known = query_params.get("known").map(String::as_str);
+            let timeout = longpoll_timeout(&query_params)?;
+
+            let template =
+                tokio::task::block_in_place(|| query.wait_template_change(known, timeout))?;
+            let template_hash = template["template_hash"].as_str();
+
+            json_response(
+                json!({
+                    "template_hash": template_hash,
+                    "height": template["height"],
+                    "previousblockhash": template["previousblockhash"],
+                    "changed": known != template_hash,
+                }),
+                0,
+            )
+        }
+
+        (&Method::GET, Some(&"admin"), Some(&"audit"), None, None, None) => {
+            require_admin(config, headers)?;
+            let audit_log = query
+                .audit_log()
+                .ok_or_else(|| HttpError::not_found("Audit log is disabled".to_string()))?;
+            let since = match query_params.get("since") {
+                Some(since) => since
+                    .parse::<u64>()
+                    .map_err(|_| HttpError::from("Invalid since".to_string()))?,
+                None => 0,
+            };
+            let limit =
+                parse_limit(&query_params, AUDIT_EVENTS_PER_PAGE, MAX_AUDIT_EVENTS_PER_PAGE)?;
+            json_response(audit_log.events_since(since, limit), 0)
+        }
+
+        (&Method::GET, Some(&"admin"), Some(&"mempool-info"), None, None, None) => {
+            require_admin(config, headers)?;
+            json_response(query.mempool().info(), 0)
+        }
+
+        (&Method::GET, Some(&"admin"), Some(&"electrum"), Some(&"connections"), None, None) => {
+            require_admin(config, headers)?;
+            let sessions = electrum_sessions
+                .ok_or_else(|| HttpError::not_found("Electrum server not running".to_string()))?
+                .list();
+
+            json_response(
+                json!({
+                    "count": sessions.len(),
+                    "max_connections": config.electrum_max_connections,
+                    "max_connections_per_ip": config.electrum_max_connections_per_ip,
+                    "sessions": sessions,
+                }),
+                0,
+            )
+        }
+
+        (&Method::POST, Some(&"admin"), Some(&"watch"), Some(&"import"), None, None) => {
+            require_admin(config, headers)?;
+            let name = watch_list_name(&query_params)?;
+            let webhook = query_params
+                .get("webhook")
+                .map(|webhook| match Url::parse(webhook) {
+                    Ok(url) if url.scheme() == "http" || url.scheme() == "https" => Ok(url),
+                    _ => Err(HttpError::from("Invalid webhook url".to_string())),
+                })
+                .transpose()?;
+            let replace = query_params.get("replace").map_or(false, |r| r == "true");
+            let is_csv = headers
+                .get("Content-Type")
+                .and_then(|content_type| content_type.to_str().ok())
+                .map_or(false, |content_type| {
+                    content_type.starts_with("text/csv") || content_type.starts_with("text/plain")
+                });
+            let scripthashes = parse_watch_entries(&body, is_csv, config.network_type)?;
+            let report = query
+                .watch_lists()
+                .import(query.chain(), name, webhook, scripthashes, replace)
+                .map_err(|err| HttpError::from(err.to_string()))?;
+            info!(
+                "imported {} scripthashes into watch list {} ({} in total)",
+                report.added, report.list, report.total
+            );
+            json_response(report, 0)
+        }
+
+        (&Method::GET, Some(&"admin"), Some(&"watch"), Some(&"export"), None, None) => {
+            require_admin(config, headers)?;
+            let name = watch_list_name(&query_params)?;
+            let (webhook, scripthashes) = query
+                .watch_lists()
+                .export(name)
+                .ok_or_else(|| HttpError::not_found("Watch list not found".to_string()))?;
+            let scripthashes = scripthashes.iter().map(watch_lists::electrum_hex);
+            if query_params.get("format").map(String::as_str) == Some("csv") {
+                let csv: String = scripthashes.map(|scripthash| scripthash + "\n").collect();
+                let mut resp = http_message(StatusCode::OK, csv, 0)?;
+                resp.headers_mut()
+                    .insert("Content-Type", "text/csv".parse().unwrap());
+                return Ok(resp);
+            }
+            json_response(
+                json!({
+                    "list": name,
+                    "webhook": webhook,
+                    "scripthashes": scripthashes.collect::<Vec<_>>(),
+                }),
+                0,
+            )
+        }
+
+        (&Method::GET, Some(&"admin"), Some(&"watch"), Some(&"changes"), None, None) => {
+            require_admin(config, headers)?;
+            let name = watch_list_name(&query_params)?;
+            let timeout = longpoll_timeout(&query_params)?;
+            let changes =
+                tokio::task::block_in_place(|| query.watch_lists().wait_changes(name, timeout))
+                    .ok_or_else(|| HttpError::not_found("Watch list not found".to_string()))?;
+            json_response(changes, 0)
         }
 
         (&Method::POST, Some(&"txs"), Some(&"test"), None, None, None) => {
@@ -1461,7 +4614,136 @@ fn handle_request(
                 }
             }).collect();
 
-            json_response(results, TTL_SHORT)
+            json_response(results, cache_ttl.short)
+        }
+        #[cfg(not(feature = "liquid"))]
+        (&Method::POST, Some(&"headers"), Some(&"verify"), None, None, None) => {
+            let raw_headers: Vec<String> =
+                serde_json::from_slice(&body).map_err(|err| HttpError::from(err.to_string()))?;
+
+            check_batch_size(raw_headers.len(), "headers", config)?;
+
+            // the headers of the request that connect, so that later ones can build on them
+            let mut connected: HashMap<BlockHash, (usize, Work, BlockHeader)> = HashMap::new();
+            let mut results = vec![];
+            for (index, raw_header) in raw_headers.iter().enumerate() {
+                let header: BlockHeader = Vec::<u8>::from_hex(raw_header)
+                    .ok()
+                    .and_then(|bytes| encode::deserialize(&bytes).ok())
+                    .ok_or_else(|| HttpError::from(format!("Invalid header at index {}", index)))?;
+                let hash = header.block_hash();
+                let entry = query.chain().header_by_hash(&hash);
+                // the headers that aren't indexed connect only with a valid proof of work
+                let (position, valid_pow) = match entry {
+                    Some(ref entry) => (Some((entry.height(), entry.chainwork())), Some(true)),
+                    None => {
+                        let prev = query
+                            .chain()
+                            .header_by_hash(&header.prev_blockhash)
+                            .map(|prev| (prev.height(), prev.chainwork(), *prev.header()))
+                            .or_else(|| connected.get(&header.prev_blockhash).cloned());
+                        let valid_pow = prev.map(|(height, _, prev)| {
+                            expected_target(query, config, &header, height + 1, &prev)
+                                .map_or(false, |target| header.validate_pow(target).is_ok())
+                        });
+                        let position = prev
+                            .filter(|_| valid_pow == Some(true))
+                            .map(|(height, chainwork, _)| (height + 1, chainwork + header.work()));
+                        (position, valid_pow)
+                    }
+                };
+                if let Some((height, chainwork)) = position {
+                    connected.insert(hash, (height, chainwork, header));
+                }
+                results.push(json!({
+                    "hash": hash,
+                    "in_best_chain": entry.is_some(),
+                    "connects": position.is_some(),
+                    "valid_pow": valid_pow,
+                    "height": position.map(|(height, _)| height),
+                    "chainwork": position.map(|(_, chainwork)| format_chainwork(chainwork)),
+                }));
+            }
+
+            json_response(results, 0)
+        }
+        (&Method::POST, Some(&"scripthashes"), Some(&"status"), None, None, None) => {
+            let scripthashes: Vec<String> =
+                serde_json::from_slice(&body).map_err(|err| HttpError::from(err.to_string()))?;
+
+            check_batch_size(scripthashes.len(), "scripthashes", config)?;
+
+            let statuses = scripthashes
+                .into_iter()
+                .map(|scripthash| {
+                    let hash = parse_electrum_scripthash(&scripthash)?;
+                    let status = get_scripthash_status(query, &hash[..], config.electrum_txs_limit);
+                    Ok(match status {
+                        Ok(status) => json!({
+                            "scripthash": scripthash,
+                            "status": status.map(|status| status.to_lower_hex_string()),
+                        }),
+                        Err(err) => json!({
+                            "scripthash": scripthash,
+                            "status": null,
+                            "error": err.to_string(),
+                        }),
+                    })
+                })
+                .collect::<Result<Vec<_>, HttpError>>()?;
+
+            json_response(statuses, 0)
+        }
+        #[cfg(not(feature = "liquid"))]
+        (&Method::POST, Some(&"scripthashes"), Some(&"delta"), None, None, None) => {
+            let requests: Vec<ScripthashDeltaRequest> =
+                serde_json::from_slice(&body).map_err(|err| HttpError::from(err.to_string()))?;
+
+            check_batch_size(requests.len(), "scripthashes", config)?;
+
+            let too_popular = format!(
+                "Exceeded maximum of {} events in a single block or in the mempool",
+                ADDRESS_DELTA_MAX_EVENTS
+            );
+            let tip_height = query.chain().best_height();
+            let tip_hash = query.chain().best_hash();
+            let deltas = requests
+                .into_iter()
+                .map(|request| {
+                    let hash = parse_electrum_scripthash(&request.scripthash)?;
+                    let delta = query.history_delta(
+                        &hash[..],
+                        request.since_height,
+                        ADDRESS_DELTA_MAX_EVENTS,
+                    );
+                    Ok(match delta {
+                        Ok((events, next_since_height)) => json!({
+                            "scripthash": request.scripthash,
+                            "since_height": request.since_height,
+                            "events": events,
+                            "truncated": next_since_height.is_some(),
+                            "next_since_height": next_since_height,
+                        }),
+                        Err(err) => match err.kind() {
+                            errors::ErrorKind::TooPopular => json!({
+                                "scripthash": request.scripthash,
+                                "since_height": request.since_height,
+                                "error": too_popular,
+                            }),
+                            _ => return Err(HttpError::from(err)),
+                        },
+                    })
+                })
+                .collect::<Result<Vec<_>, HttpError>>()?;
+
+            json_response(
+                json!({
+                    "tip_height": tip_height,
+                    "tip_hash": tip_hash,
+                    "deltas": deltas,
+                }),
+                0,
+            )
         }
         (&Method::POST, Some(&"txs"), Some(&"package"), None, None, None) => {
             let txhexes: Vec<String> =
@@ -1513,12 +4795,13 @@ fn handle_request(
             let mut error_txids = Vec::new();
 
             for (i, txhex) in txhexes.iter().enumerate() {
-                match query.broadcast_raw(txhex) {
-                    Ok(txid) => {
+                match query.broadcast_raw(txhex, true) {
+                    Ok(broadcast) => {
                         success_count += 1;
                         results.push(json!({
-                            "txid": txid.to_string(),
-                            "success": true
+                            "txid": broadcast.txid.to_string(),
+                            "success": true,
+                            "queued": broadcast.queued
                         }));
                     },
                     Err(e) => {
@@ -1538,44 +4821,78 @@ fn handle_request(
                 "transactions": results
             });
 
-            json_response(response, TTL_SHORT)
+            json_response(response, cache_ttl.short)
         }
         (&Method::GET, Some(&"txs"), Some(&"outspends"), None, None, None) => {
-            let txid_strings: Vec<&str> = query_params
+            let txids: Vec<String> = query_params
                 .get("txids")
                 .ok_or(HttpError::from("No txids specified".to_string()))?
-                .as_str()
                 .split(',')
+                .map(str::to_string)
                 .collect();
 
-            if txid_strings.len() > 50 {
+            if txids.len() > config.max_outspends_txids {
                 return http_message(StatusCode::BAD_REQUEST, "Too many txids requested", 0);
             }
 
-            let spends: Vec<Vec<SpendingValue>> = txid_strings
-                .into_iter()
-                .map(|txid_str| {
-                    Txid::from_str(txid_str)
-                        .ok()
-                        .and_then(|txid| query.lookup_txn(&txid))
-                        .map_or_else(Vec::new, |tx| {
-                            query
-                                .lookup_tx_spends(tx)
-                                .into_iter()
-                                .map(|spend| {
-                                    spend.map_or_else(SpendingValue::default, SpendingValue::from)
-                                })
-                                .collect()
+            // stream the spends as they get looked up, looking up the spends of up to
+            // --outspends-concurrency transactions in parallel. repeated txids are looked up once.
+            // the lookups run on the bounded blocking pool of the runtime, which reuses its threads
+            let (mut sender, body) = Body::channel();
+            let query = Arc::clone(query);
+            let runtime = tokio::runtime::Handle::current();
+            let chunk_size = config.outspends_concurrency;
+            runtime.clone().spawn_blocking(move || {
+                let mut looked_up: HashMap<&str, String> = HashMap::new();
+                for (i, chunk) in txids.chunks(chunk_size).enumerate() {
+                    let mut missing: Vec<&str> = chunk
+                        .iter()
+                        .map(String::as_str)
+                        .filter(|txid| !looked_up.contains_key(txid))
+                        .collect();
+                    missing.sort_unstable();
+                    missing.dedup();
+                    let spends = missing
+                        .into_par_iter()
+                        .map(|txid| {
+                            let spends = tx_outspends(&query, txid)?;
+                            Ok((txid, serde_json::to_string(&spends).unwrap()))
                         })
-                })
-                .collect();
+                        .collect::<Result<Vec<(&str, String)>, errors::Error>>();
+                    match spends {
+                        Ok(spends) => looked_up.extend(spends),
+                        Err(e) => {
+                            warn!("failed looking up outspends: {}", e);
+                            sender.abort();
+                            return;
+                        }
+                    }
+
+                    let mut data = String::from(if i == 0 { "[" } else { "," });
+                    data.push_str(
+                        &chunk
+                            .iter()
+                            .map(|txid| looked_up[txid.as_str()].as_str())
+                            .collect::<Vec<_>>()
+                            .join(","),
+                    );
+                    if runtime.block_on(sender.send_data(data.into())).is_err() {
+                        return; // the client went away
+                    }
+                }
+                runtime.block_on(sender.send_data("]".into())).ok();
+            });
 
-            json_response(spends, TTL_SHORT)
+            Ok(Response::builder()
+                .header("Content-Type", "application/json")
+                .header("Cache-Control", format!("public, max-age={:}", cache_ttl.short))
+                .body(body)
+                .unwrap())
         }
 
         (&Method::GET, Some(&"blockchain"), Some(&"getsupply"), None, None, None) => {
             // Use the get_total_coin_supply method instead of directly accessing daemon
-            let total_amount_float = query.get_total_coin_supply()?;
+            let total_sats = query.get_total_coin_supply()?;
 
             // Get the current chain tip information
             let chain = query.chain();
@@ -1583,16 +4900,18 @@ fn handle_request(
             let block_hash = chain.best_hash();
 
             // Format total amount with 8 decimal places
-            let total_amount = format!("{:.8}", total_amount_float);
+            let unit = Unit::from_params(&query_params)?;
+            let total_amount = unit.format(total_sats);
 
             let response = TotalCoinSupplyValue {
                 total_amount,
-                total_amount_float,
+                total_sat_amount: total_sats,
+                total_amount_float: total_sats as f64 / SATS_PER_COIN as f64,
                 height: height as u32,
                 block_hash: block_hash.to_string(),
             };
 
-            json_response(response, TTL_SHORT)
+            json_response(response, cache_ttl.short)
         }
 
 
@@ -1604,11 +4923,7 @@ fn handle_request(
                 .and_then(|n| n.parse().ok())
                 .unwrap_or(0);
 
-            let limit: usize = query_params
-                .get("limit")
-                .and_then(|n| n.parse().ok())
-                .map(|n: usize| n.min(ASSETS_MAX_PER_PAGE))
-                .unwrap_or(ASSETS_PER_PAGE);
+            let limit = parse_limit(&query_params, ASSETS_PER_PAGE, ASSETS_MAX_PER_PAGE)?;
 
             let sorting = AssetSorting::from_query_params(&query_params)?;
 
@@ -1630,19 +4945,40 @@ fn handle_request(
                 .lookup_asset(&asset_id)?
                 .ok_or_else(|| HttpError::not_found("Asset id not found".to_string()))?;
 
-            json_response(asset_entry, TTL_SHORT)
+            json_response(asset_entry, cache_ttl.short)
         }
 
         #[cfg(feature = "liquid")]
         (&Method::GET, Some(&"asset"), Some(asset_str), Some(&"txs"), None, None) => {
             let asset_id = AssetId::from_str(asset_str)?;
 
+            let paginator = Paginator::from_params(
+                &query_params,
+                CHAIN_TXS_PER_PAGE,
+                config.max_txs_per_page,
+            )?;
+            if let Some(paginator) = paginator {
+                let after_txid = paginator.cursor::<Txid>()?;
+                let txs = merged_asset_history(query, &asset_id, after_txid, paginator.limit);
+                let next_cursor = txs
+                    .last()
+                    .map(|(tx, _)| tx.txid())
+                    .filter(|_| paginator.is_full(txs.len()));
+                let (chain_count, mempool_count) = query.asset_tx_count(&asset_id);
+                return paginator.respond(
+                    prepare_txs(txs, query, config),
+                    next_cursor,
+                    Some(chain_count + mempool_count),
+                    cache_ttl.short,
+                );
+            }
+
             let mut txs = vec![];
 
             txs.extend(
                 query
                     .mempool()
-                    .asset_history(&asset_id, MAX_MEMPOOL_TXS)
+                    .asset_history(&asset_id, None, config.max_mempool_txs)
                     .into_iter()
                     .map(|tx| (tx, None)),
             );
@@ -1655,7 +4991,7 @@ fn handle_request(
                     .map(|(tx, blockid)| (tx, Some(blockid))),
             );
 
-            json_response(prepare_txs(txs, query, config), TTL_SHORT)
+            json_response(prepare_txs(txs, query, config), cache_ttl.short)
         }
 
         #[cfg(feature = "liquid")]
@@ -1668,30 +5004,124 @@ fn handle_request(
             last_seen_txid,
         ) => {
             let asset_id = AssetId::from_str(asset_str)?;
-            let last_seen_txid = last_seen_txid.and_then(|txid| Txid::from_str(txid).ok());
+            let paginator = Paginator::from_params(
+                &query_params,
+                CHAIN_TXS_PER_PAGE,
+                config.max_txs_per_page,
+            )?;
+            let (last_seen_txid, limit) = match paginator {
+                Some(ref paginator) => (paginator.cursor::<Txid>()?, paginator.limit),
+                None => (
+                    last_seen_txid.and_then(|txid| Txid::from_str(txid).ok()),
+                    CHAIN_TXS_PER_PAGE,
+                ),
+            };
 
-            let txs = query
+            let txs: Vec<_> = query
                 .chain()
-                .asset_history(&asset_id, last_seen_txid.as_ref(), CHAIN_TXS_PER_PAGE)
+                .asset_history(&asset_id, last_seen_txid.as_ref(), limit)
                 .into_iter()
                 .map(|(tx, blockid)| (tx, Some(blockid)))
                 .collect();
 
-            json_response(prepare_txs(txs, query, config), TTL_SHORT)
+            if let Some(paginator) = paginator {
+                let next_cursor = txs
+                    .last()
+                    .map(|(tx, _)| tx.txid())
+                    .filter(|_| paginator.is_full(txs.len()));
+                let (chain_count, _) = query.asset_tx_count(&asset_id);
+                return paginator.respond(
+                    prepare_txs(txs, query, config),
+                    next_cursor,
+                    Some(chain_count),
+                    cache_ttl.short,
+                );
+            }
+
+            json_response(prepare_txs(txs, query, config), cache_ttl.short)
         }
 
         #[cfg(feature = "liquid")]
         (&Method::GET, Some(&"asset"), Some(asset_str), Some(&"txs"), Some(&"mempool"), None) => {
             let asset_id = AssetId::from_str(asset_str)?;
+            let paginator = Paginator::from_params(
+                &query_params,
+                config.max_mempool_txs,
+                config.max_mempool_txs,
+            )?;
+            let (after_txid, limit) = match paginator {
+                Some(ref paginator) => (paginator.cursor::<Txid>()?, paginator.limit),
+                None => (None, config.max_mempool_txs),
+            };
 
-            let txs = query
+            let txs: Vec<_> = query
                 .mempool()
-                .asset_history(&asset_id, MAX_MEMPOOL_TXS)
+                .asset_history(&asset_id, after_txid.as_ref(), limit)
                 .into_iter()
                 .map(|tx| (tx, None))
                 .collect();
 
-            json_response(prepare_txs(txs, query, config), TTL_SHORT)
+            if let Some(paginator) = paginator {
+                let next_cursor = txs
+                    .last()
+                    .map(|(tx, _)| tx.txid())
+                    .filter(|_| paginator.is_full(txs.len()));
+                let (_, mempool_count) = query.asset_tx_count(&asset_id);
+                return paginator.respond(
+                    prepare_txs(txs, query, config),
+                    next_cursor,
+                    Some(mempool_count),
+                    cache_ttl.short,
+                );
+            }
+
+            json_response(prepare_txs(txs, query, config), cache_ttl.short)
+        }
+
+        #[cfg(feature = "liquid")]
+        (&Method::GET, Some(&"pegs"), Some(direction @ &"ins"), None, None, None)
+        | (&Method::GET, Some(&"pegs"), Some(direction @ &"outs"), None, None, None) => {
+            let direction = match *direction {
+                "ins" => peg::PEG_IN,
+                _ => peg::PEG_OUT,
+            };
+            // always paginated, starting from the most recent pegs when no cursor is given
+            let limit = parse_limit(&query_params, CHAIN_TXS_PER_PAGE, config.max_txs_per_page)?;
+            let paginator = Paginator::from_params(
+                &query_params,
+                CHAIN_TXS_PER_PAGE,
+                config.max_txs_per_page,
+            )?
+            .unwrap_or(Paginator {
+                cursor: None,
+                limit,
+            });
+            let last_seen = match paginator.cursor.as_deref().map_or(Ok(None), parse_cursor)? {
+                Some((txid, index)) => {
+                    let index = index
+                        .try_into()
+                        .map_err(|_| HttpError::from("Invalid cursor".to_string()))?;
+                    Some((txid, index))
+                }
+                None => None,
+            };
+
+            let pegs = query.pegs(direction, last_seen, paginator.limit);
+            let next_cursor = pegs
+                .last()
+                .filter(|_| paginator.is_full(pegs.len()))
+                .map(|peg| format!("{}:{}", peg.txid, peg.vin.or(peg.vout).unwrap_or(0)));
+            let mut envelope = paginator.envelope(pegs, next_cursor, query.peg_count(direction));
+            envelope["indexed_since_height"] = json!(query.peg_index_start());
+            json_response(envelope, cache_ttl.short)
+        }
+
+        #[cfg(feature = "liquid")]
+        (&Method::GET, Some(&"asset"), Some(asset_str), Some(&"holders"), None, None) => {
+            let asset_id = AssetId::from_str(asset_str)?;
+            let limit = parse_limit(&query_params, ASSET_HOLDERS_PER_PAGE, ASSET_HOLDERS_MAX)?;
+            let holders = query.asset_holders(&asset_id, limit);
+            json_response(holders, cache_ttl.short)
         }
 
         #[cfg(feature = "liquid")]
@@ -1708,9 +5138,9 @@ fn handle_request(
 
             if param == Some(&"decimal") && precision > 0 {
                 let supply_dec = supply as f64 / 10u32.pow(precision.into()) as f64;
-                http_message(StatusCode::OK, supply_dec.to_string(), TTL_SHORT)
+                http_message(StatusCode::OK, supply_dec.to_string(), cache_ttl.short)
             } else {
-                http_message(StatusCode::OK, supply.to_string(), TTL_SHORT)
+                http_message(StatusCode::OK, supply.to_string(), cache_ttl.short)
             }
         }
 
@@ -1721,6 +5151,66 @@ fn handle_request(
     }
 }
 
+enum ByteRange {
+    Full,
+    Partial(usize, usize), // [start, end)
+    Unsatisfiable,
+}
+
+// The slice of a resource of `len` bytes asked for by a Range header. Multiple ranges and
+// malformed headers are ignored, serving the whole resource.
+fn byte_range(header: Option<&hyper::header::HeaderValue>, len: usize) -> ByteRange {
+    let spec = match header
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().strip_prefix("bytes="))
+    {
+        Some(spec) if !spec.contains(',') => spec,
+        _ => return ByteRange::Full,
+    };
+    let range = spec.split_once('-').and_then(|(start, end)| match (start.trim(), end.trim()) {
+        // the last `suffix` bytes
+        ("", suffix) => suffix
+            .parse::<usize>()
+            .ok()
+            .map(|suffix| (len.saturating_sub(suffix), len)),
+        (start, "") => start.parse::<usize>().ok().map(|start| (start, len)),
+        (start, end) => match (start.parse::<usize>(), end.parse::<usize>()) {
+            (Ok(start), Ok(end)) if start <= end => Some((start, end.saturating_add(1).min(len))),
+            _ => None,
+        },
+    });
+    match range {
+        None => ByteRange::Full,
+        Some((start, end)) if start < end => ByteRange::Partial(start, end),
+        Some(_) => ByteRange::Unsatisfiable,
+    }
+}
+
+// Serve raw bytes, or the slice of them asked for by a Range header
+fn raw_response(
+    data: Vec<u8>,
+    ttl: u32,
+    range: Option<&hyper::header::HeaderValue>,
+) -> Response<Body> {
+    let len = data.len();
+    let resp = Response::builder()
+        .header("Content-Type", "application/octet-stream")
+        .header("Cache-Control", format!("public, max-age={:}", ttl))
+        .header("Accept-Ranges", "bytes");
+    match byte_range(range, len) {
+        ByteRange::Full => resp.status(StatusCode::OK).body(Body::from(data)),
+        ByteRange::Partial(start, end) => resp
+            .status(StatusCode::PARTIAL_CONTENT)
+            .header("Content-Range", format!("bytes {}-{}/{}", start, end - 1, len))
+            .body(Body::from(data[start..end].to_vec())),
+        ByteRange::Unsatisfiable => resp
+            .status(StatusCode::RANGE_NOT_SATISFIABLE)
+            .header("Content-Range", format!("bytes */{}", len))
+            .body(Body::empty()),
+    }
+    .unwrap()
+}
+
 fn http_message<T>(status: StatusCode, message: T, ttl: u32) -> Result<Response<Body>, HttpError>
 where
     T: Into<Body>,
@@ -1735,14 +5225,400 @@ where
 
 fn json_response<T: Serialize>(value: T, ttl: u32) -> Result<Response<Body>, HttpError> {
     let value = serde_json::to_string(&value)?;
-    Ok(Response::builder()
+    Ok(json_string_response(value, ttl))
+}
+
+// Like json_response(), for an already serialized value
+fn json_string_response(value: String, ttl: u32) -> Response<Body> {
+    Response::builder()
         .header("Content-Type", "application/json")
         .header("Cache-Control", format!("public, max-age={:}", ttl))
         .body(Body::from(value))
-        .unwrap())
+        .unwrap()
+}
+
+// Serve the JSON computed by `compute`, from the stale-while-revalidate cache when it's enabled.
+// Cached responses are fresh for `ttl` seconds, like for HTTP caches.
+fn swr_response<F>(
+    config: &Config,
+    uri: &hyper::Uri,
+    ttl: u32,
+    compute: F,
+) -> Result<Response<Body>, HttpError>
+where
+    F: FnOnce() -> Result<String, HttpError> + Send + 'static,
+{
+    let value = if config.stale_while_revalidate > 0 {
+        SWR_CACHE.get_or_compute(
+            // the networks served by the process share the cache
+            format!("{:?} {}", config.network_type, uri),
+            Duration::from_secs(ttl as u64),
+            Duration::from_secs(config.stale_while_revalidate),
+            compute,
+        )?
+    } else {
+        compute()?
+    };
+    Ok(json_string_response(value, ttl))
+}
+
+#[derive(Clone, Copy)]
+enum BinaryFormat {
+    Cbor,
+    MsgPack,
+}
+
+impl BinaryFormat {
+    // The binary format the client prefers according to the q-values of the Accept header, or
+    // None when it prefers JSON
+    fn from_accept(headers: &hyper::HeaderMap) -> Option<Self> {
+        let accept = headers.get(hyper::header::ACCEPT)?.to_str().ok()?;
+        let mut media_types: Vec<(&str, f32)> = accept
+            .split(',')
+            .filter_map(|media_type| {
+                let mut parts = media_type.split(';').map(str::trim);
+                let media_type = parts.next().filter(|media_type| !media_type.is_empty())?;
+                let quality = parts
+                    .find_map(|param| param.strip_prefix("q="))
+                    .map_or(Some(1.0), |q| q.parse::<f32>().ok())?;
+                Some((media_type, quality))
+            })
+            .filter(|(_, quality)| *quality > 0.0)
+            .collect();
+        // the sort is stable, so that equally preferred media types keep their order
+        media_types.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+        media_types
+            .iter()
+            .find_map(|(media_type, _)| match *media_type {
+                "application/cbor" => Some(Some(BinaryFormat::Cbor)),
+                "application/msgpack" | "application/x-msgpack" | "application/vnd.msgpack" => {
+                    Some(Some(BinaryFormat::MsgPack))
+                }
+                "application/json" | "application/*" | "*/*" => Some(None),
+                _ => None,
+            })
+            .flatten()
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            BinaryFormat::Cbor => "application/cbor",
+            BinaryFormat::MsgPack => "application/msgpack",
+        }
+    }
+
+    fn encode(self, value: &serde_json::Value) -> Vec<u8> {
+        match self {
+            BinaryFormat::Cbor => {
+                let mut encoded = vec![];
+                ciborium::ser::into_writer(value, &mut encoded).expect("cbor encoding failed");
+                encoded
+            }
+            BinaryFormat::MsgPack => rmp_serde::to_vec_named(value).expect("msgpack encoding failed"),
+        }
+    }
+}
+
+// JSON responses are re-encoded in the binary format requested via the Accept header, if any
+async fn encode_response(
+    mut resp: Response<Body>,
+    format: Option<BinaryFormat>,
+) -> Result<Response<Body>, hyper::Error> {
+    let is_json = resp
+        .headers()
+        .get("Content-Type")
+        .map_or(false, |content_type| content_type == "application/json");
+    if !is_json {
+        return Ok(resp);
+    }
+    resp.headers_mut().insert("Vary", "Accept".parse().unwrap());
+    let format = match format {
+        Some(format) => format,
+        None => return Ok(resp),
+    };
+
+    let (mut parts, body) = resp.into_parts();
+    let body = hyper::body::to_bytes(body).await?;
+    let value: serde_json::Value = serde_json::from_slice(&body).expect("invalid json response");
+    parts.headers.insert("Content-Type", format.content_type().parse().unwrap());
+    Ok(Response::from_parts(parts, Body::from(format.encode(&value))))
+}
+
+fn record_if_slow(
+    query: &Query,
+    config: &Config,
+    method: &Method,
+    uri: &hyper::Uri,
+    duration: Duration,
+    db: ReadStats,
+) {
+    let slow_query_log = match query.slow_query_log() {
+        Some(slow_query_log) if slow_query_log.is_slow(duration) => slow_query_log,
+        _ => return,
+    };
+    let path: Vec<&str> = uri.path().split('/').skip(1).collect();
+    let scripthash = match (path.get(0), path.get(1)) {
+        (Some(script_type), Some(script_str)) => {
+            to_scripthash(script_type, script_str, config.network_type).ok()
+        }
+        _ => None,
+    };
+    let route = format!("{} {}", method, uri.path());
+
+    slow_query_log.record(&SlowQuery {
+        source: "rest",
+        route: &route,
+        params: json!(uri.query()),
+        duration_ms: duration.as_millis(),
+        scripthash: scripthash.map(|scripthash| scripthash.to_lower_hex_string()),
+        script_tx_count: scripthash
+            .and_then(|scripthash| query.chain().cached_tx_count(&scripthash[..])),
+        db,
+    });
+}
+
+lazy_static! {
+    // distinguishes the generated request ids of different server runs
+    static ref REQUEST_ID_PREFIX: u64 = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs());
+    // the last responses of the heavy endpoints, with --stale-while-revalidate
+    static ref SWR_CACHE: SwrCache<String> = SwrCache::new(SWR_CACHE_ENTRIES);
+}
+static NEXT_REQUEST_ID: AtomicU64 = AtomicU64::new(0);
+
+// Reuse the request id set by a proxy in front of us if it looks sane, or generate a new one
+fn request_id(headers: &hyper::HeaderMap) -> String {
+    headers
+        .get("X-Request-Id")
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| {
+            !id.is_empty()
+                && id.len() <= 64
+                && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        })
+        .map_or_else(
+            || {
+                let id = NEXT_REQUEST_ID.fetch_add(1, Ordering::Relaxed);
+                format!("{:x}-{:x}", *REQUEST_ID_PREFIX, id)
+            },
+            |id| id.to_string(),
+        )
+}
+
+// Drop the body of the response to a HEAD request, keeping the Content-Length it would have
+fn strip_body(resp: Response<Body>) -> Response<Body> {
+    let (mut parts, body) = resp.into_parts();
+    if let Some(length) = body.size_hint().exact() {
+        parts.headers.insert("Content-Length", length.into());
+    }
+    Response::from_parts(parts, Body::empty())
+}
+
+// Answer CORS preflight requests, leaving the CORS headers out when the origin isn't allowed
+fn cors_preflight(cors: &CorsPolicy, origin: &str) -> Response<Body> {
+    let mut resp = Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .header("Vary", "Origin");
+    if let Some((allow_origin, methods)) = cors.lookup(origin) {
+        resp = resp
+            .header("Access-Control-Allow-Origin", allow_origin)
+            .header("Access-Control-Allow-Methods", methods.join(", "))
+            .header("Access-Control-Allow-Headers", &cors.allowed_headers)
+            .header("Access-Control-Max-Age", cors.max_age);
+    }
+    resp.body(Body::empty()).unwrap()
+}
+
+// Set on every response to the allowed origins, errors included, for browsers to let the page
+// read them. The allowed methods only restrict the preflighted requests.
+fn add_cors_headers(resp: &mut Response<Body>, cors: &CorsPolicy, origin: &str) {
+    let headers = resp.headers_mut();
+    headers.append("Vary", "Origin".parse().unwrap());
+    if let Some((allow_origin, _)) = cors.lookup(origin) {
+        headers.insert("Access-Control-Allow-Origin", allow_origin.parse().unwrap());
+        headers.insert(
+            "Access-Control-Expose-Headers",
+            "X-Tip-Hash, X-Tip-Height, X-Request-Id".parse().unwrap(),
+        );
+    }
+}
+
+// The audit log entry of the request if it is a mutating action: a broadcast, or a call to an
+// admin endpoint that isn't a GET. Only the txids of the broadcasted transactions get recorded,
+// never the request bodies.
+fn audit_event(method: &Method, uri: &hyper::Uri, body: &[u8]) -> Option<AuditEvent> {
+    let path = uri.path().trim_end_matches('/');
+    let txhexes: Vec<String> = match (method, path) {
+        (&Method::POST, "/tx") => vec![String::from_utf8_lossy(body).into_owned()],
+        (&Method::GET, "/broadcast") => form_urlencoded::parse(uri.query()?.as_bytes())
+            .filter(|(name, _)| name == "tx")
+            .map(|(_, txhex)| txhex.into_owned())
+            .collect(),
+        (&Method::POST, "/txs/package") => serde_json::from_slice(body).unwrap_or_default(),
+        (&Method::GET, _) | (&Method::HEAD, _) | (&Method::OPTIONS, _) => return None,
+        (_, path) if path.starts_with("/admin/") => {
+            let details = json!({ "method": method.as_str(), "path": path });
+            return Some(AuditEvent::new("admin", "rest", details));
+        }
+        _ => return None,
+    };
+    let txids: Vec<Option<Txid>> = txhexes
+        .iter()
+        .map(|txhex| {
+            let bytes = Vec::<u8>::from_hex(txhex.trim()).ok()?;
+            encode::deserialize::<Transaction>(&bytes).ok().map(|tx| tx.txid())
+        })
+        .collect();
+    let details = json!({ "method": method.as_str(), "path": path, "txids": txids });
+    Some(AuditEvent::new("broadcast", "rest", details))
+}
+
+// The admin endpoints are only available when explicitly enabled, to the requests bearing the
+// admin token
+fn require_admin(config: &Config, headers: &hyper::HeaderMap) -> Result<(), HttpError> {
+    let token = match (config.admin_api, config.admin_token.as_ref()) {
+        (true, Some(token)) => token,
+        _ => bail!(HttpError(StatusCode::FORBIDDEN, "Admin API is disabled".to_string())),
+    };
+    if !has_bearer_token(headers, token) {
+        bail!(HttpError(
+            StatusCode::UNAUTHORIZED,
+            "Missing or invalid admin token".to_string()
+        ));
+    }
+    Ok(())
+}
+
+// Whether the Authorization header bears the token, compared in constant time not to leak it
+// through timing
+fn has_bearer_token(headers: &hyper::HeaderMap, token: &str) -> bool {
+    let bearer = headers
+        .get("Authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    bearer.map_or(false, |bearer| {
+        bearer.len() == token.len()
+            && bearer
+                .bytes()
+                .zip(token.bytes())
+                .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+                == 0
+    })
+}
+
+// Parse the `limit` query parameter, refusing values above `max_limit`
+fn parse_limit(
+    query_params: &HashMap<String, String>,
+    default_limit: usize,
+    max_limit: usize,
+) -> Result<usize, HttpError> {
+    let limit = match query_params.get("limit") {
+        Some(limit) => limit
+            .parse::<usize>()
+            .map_err(|_| HttpError::from("Invalid limit".to_string()))?,
+        None => return Ok(default_limit.min(max_limit)),
+    };
+    if limit > max_limit {
+        return Err(HttpError::from(format!(
+            "limit exceeds the maximum of {} items",
+            max_limit
+        )));
+    }
+    Ok(limit.max(1))
+}
+
+// The target a header at `height` must meet, following its previous header. Within a retarget
+// period that's the target of the previous header. At the start of a period, that's the target
+// of the best chain header at the height if it builds on the same block, or else the target the
+// header claims if it's within the factor of 4 either way retargeting allows.
+#[cfg(not(feature = "liquid"))]
+fn expected_target(
+    query: &Query,
+    config: &Config,
+    header: &BlockHeader,
+    height: usize,
+    prev: &BlockHeader,
+) -> Option<Target> {
+    if config.network_type.is_regtest() || height % config.retarget_interval != 0 {
+        return Some(prev.target());
+    }
+    let sibling = query.chain().header_by_height(height);
+    if let Some(sibling) = sibling.filter(|s| s.header().prev_blockhash == header.prev_blockhash) {
+        return Some(sibling.header().target());
+    }
+    // with some leeway for the rounding of the compact targets
+    let adjustment = header.difficulty_float() / prev.difficulty_float();
+    (0.99 / 4.0..=4.0 * 1.01)
+        .contains(&adjustment)
+        .then(|| header.target())
+}
+
+// Formatted like bitcoind does, as 64 hex digits
+#[cfg(not(feature = "liquid"))]
+fn format_chainwork(work: Work) -> String {
+    work.to_be_bytes().to_lower_hex_string()
+}
+
+fn check_batch_size(len: usize, items: &str, config: &Config) -> Result<(), HttpError> {
+    if len > config.max_batch_size {
+        return Err(HttpError::from(format!(
+            "Exceeded maximum of {} {} per request",
+            config.max_batch_size, items
+        )));
+    }
+    Ok(())
+}
+
+fn gzip(data: &[u8]) -> Result<Vec<u8>, HttpError> {
+    let mut encoder = GzEncoder::new(Vec::with_capacity(data.len() / 2), Compression::default());
+    encoder
+        .write_all(data)
+        .and_then(|_| encoder.finish())
+        .map_err(|err| HttpError::from(format!("compression failed: {}", err)))
+}
+
+fn longpoll_timeout(query_params: &HashMap<String, String>) -> Result<Duration, HttpError> {
+    let timeout = query_params
+        .get("timeout")
+        .map_or(Ok(LONGPOLL_TIMEOUT), |t| t.parse::<u64>())?;
+    Ok(Duration::from_secs(timeout.min(LONGPOLL_MAX_TIMEOUT)))
+}
+
+// Parse a duration such as "90m", "24h" or "7d" (plain numbers are seconds)
+#[cfg(not(feature = "liquid"))]
+fn parse_window(window: &str) -> Result<Duration, HttpError> {
+    let (value, unit) = match window.find(|c: char| !c.is_ascii_digit()) {
+        Some(index) => window.split_at(index),
+        None => (window, "s"),
+    };
+    let unit_secs = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86_400,
+        _ => return Err(HttpError::from(format!("Invalid window {}", window))),
+    };
+    value
+        .parse::<u64>()?
+        .checked_mul(unit_secs)
+        .map(Duration::from_secs)
+        .ok_or_else(|| HttpError::from(format!("Invalid window {}", window)))
+}
+
+fn blocks(
+    query: &Query,
+    start_height: Option<usize>,
+    cache_ttl: CacheTtl,
+) -> Result<Response<Body>, HttpError> {
+    json_response(block_values(query, start_height, BLOCK_LIMIT)?, cache_ttl.short)
 }
 
-fn blocks(query: &Query, start_height: Option<usize>) -> Result<Response<Body>, HttpError> {
+fn block_values(
+    query: &Query,
+    start_height: Option<usize>,
+    limit: usize,
+) -> Result<Vec<BlockValue>, HttpError> {
     let mut values = Vec::new();
     let mut current_hash = match start_height {
         Some(height) => *query
@@ -1753,8 +5629,21 @@ fn blocks(query: &Query, start_height: Option<usize>) -> Result<Response<Body>,
         None => query.chain().best_hash(),
     };
 
+    // the stats of the listed blocks, looked up at once
+    #[cfg(not(feature = "liquid"))]
+    let stats: HashMap<u32, BlockStats> = {
+        let end_height = start_height.unwrap_or_else(|| query.chain().best_height());
+        let start_height = (end_height + 1).saturating_sub(limit);
+        query
+            .chain()
+            .block_stats(start_height, end_height)
+            .into_iter()
+            .map(|stats| (stats.height, stats))
+            .collect()
+    };
+
     let zero = [0u8; 32];
-    for _ in 0..BLOCK_LIMIT {
+    for _ in 0..limit {
         let blockhm = query
             .chain()
             .get_block_with_meta(&current_hash)
@@ -1763,6 +5652,10 @@ fn blocks(query: &Query, start_height: Option<usize>) -> Result<Response<Body>,
 
         #[allow(unused_mut)]
         let mut value = BlockValue::new(blockhm);
+        #[cfg(not(feature = "liquid"))]
+        {
+            value = value.with_adoption(stats.get(&value.height));
+        }
 
         #[cfg(feature = "liquid")]
         {
@@ -1775,7 +5668,192 @@ fn blocks(query: &Query, start_height: Option<usize>) -> Result<Response<Body>,
             break;
         }
     }
-    json_response(values, TTL_SHORT)
+    Ok(values)
+}
+
+// A page of the asset history, with the unconfirmed txs first followed by the confirmed ones. The
+// cursor may point at either, with the chain txs starting from the top past the mempool ones.
+#[cfg(feature = "liquid")]
+fn merged_asset_history(
+    query: &Query,
+    asset_id: &AssetId,
+    after_txid: Option<Txid>,
+    limit: usize,
+) -> Vec<(Transaction, Option<BlockId>)> {
+    let (mut txs, chain_after_txid) = {
+        let mempool = query.mempool();
+        let txs: Vec<_> = mempool
+            .asset_history(asset_id, after_txid.as_ref(), limit)
+            .into_iter()
+            .map(|tx| (tx, None))
+            .collect();
+        let chain_after_txid = after_txid.filter(|txid| {
+            txs.is_empty()
+                && mempool.lookup_txn(txid).is_none()
+                && mempool.replaced_by(txid).is_none()
+        });
+        (txs, chain_after_txid)
+    };
+
+    if txs.len() < limit {
+        txs.extend(
+            query
+                .chain()
+                .asset_history(asset_id, chain_after_txid.as_ref(), limit - txs.len())
+                .into_iter()
+                .map(|(tx, blockid)| (tx, Some(blockid))),
+        );
+    }
+    txs
+}
+
+// Keyset pagination shared by the list endpoints. Clients opt in by passing a `cursor`
+// parameter (empty for the first page) and get back an `{ items, paging }` envelope,
+// where `paging.next_cursor` is set for as long as there may be more items to fetch.
+struct Paginator {
+    cursor: Option<String>,
+    limit: usize,
+}
+
+impl Paginator {
+    fn from_params(
+        query_params: &HashMap<String, String>,
+        default_limit: usize,
+        max_limit: usize,
+    ) -> Result<Option<Self>, HttpError> {
+        let cursor = match query_params.get("cursor") {
+            Some(cursor) => cursor,
+            None => return Ok(None),
+        };
+        Ok(Some(Paginator {
+            cursor: Some(cursor.clone()).filter(|cursor| !cursor.is_empty()),
+            limit: parse_limit(query_params, default_limit, max_limit)?,
+        }))
+    }
+
+    fn cursor<T: FromStr>(&self) -> Result<Option<T>, HttpError> {
+        self.cursor
+            .as_ref()
+            .map(|cursor| {
+                cursor
+                    .parse::<T>()
+                    .map_err(|_| HttpError::from("Invalid cursor".to_string()))
+            })
+            .transpose()
+    }
+
+    fn is_full(&self, page_len: usize) -> bool {
+        page_len >= self.limit
+    }
+
+    fn respond<T: Serialize, C: ToString>(
+        &self,
+        items: Vec<T>,
+        next_cursor: Option<C>,
+        total: Option<usize>,
+        ttl: u32,
+    ) -> Result<Response<Body>, HttpError> {
+        json_response(self.envelope(items, next_cursor, total), ttl)
+    }
+
+    fn envelope<T: Serialize, C: ToString>(
+        &self,
+        items: Vec<T>,
+        next_cursor: Option<C>,
+        total: Option<usize>,
+    ) -> serde_json::Value {
+        json!({
+            "items": items,
+            "paging": {
+                "cursor": self.cursor,
+                "limit": self.limit,
+                "next_cursor": next_cursor.map(|cursor| cursor.to_string()),
+                "total": total,
+            },
+        })
+    }
+}
+
+// The fields picked with ?fields=, as comma-separated paths whose segments are separated by dots
+// (e.g. `txid,fee,status.block_height`). Paths into arrays apply to each of their elements.
+#[derive(Default)]
+struct FieldSelector(HashMap<String, Option<FieldSelector>>); // None selects the whole field
+
+impl FieldSelector {
+    fn from_params(query_params: &HashMap<String, String>) -> Result<Option<Self>, HttpError> {
+        let fields = match query_params.get("fields") {
+            Some(fields) => fields,
+            None => return Ok(None),
+        };
+        let mut selector = FieldSelector::default();
+        for path in fields.split(',') {
+            let segments: Vec<&str> = path.trim().split('.').collect();
+            if segments.iter().any(|segment| segment.is_empty()) {
+                bail!(HttpError::from(format!("Invalid field {:?}", path)));
+            }
+            selector.insert(&segments);
+        }
+        Ok(Some(selector))
+    }
+
+    fn insert(&mut self, segments: &[&str]) {
+        let (first, rest) = match segments.split_first() {
+            Some(split) => split,
+            None => return,
+        };
+        let entry = self
+            .0
+            .entry(first.to_string())
+            .or_insert_with(|| Some(FieldSelector::default()));
+        if rest.is_empty() {
+            *entry = None;
+        } else if let Some(child) = entry {
+            child.insert(rest);
+        }
+    }
+
+    fn prune(&self, value: serde_json::Value) -> serde_json::Value {
+        match value {
+            serde_json::Value::Array(items) => {
+                serde_json::Value::Array(items.into_iter().map(|item| self.prune(item)).collect())
+            }
+            serde_json::Value::Object(object) => serde_json::Value::Object(
+                object
+                    .into_iter()
+                    .filter_map(|(key, value)| match self.0.get(&key)? {
+                        None => Some((key, value)),
+                        Some(child) => Some((key, child.prune(value))),
+                    })
+                    .collect(),
+            ),
+            value => value,
+        }
+    }
+}
+
+// An item serialized with only the selected fields, if any
+struct Selected<'a, T> {
+    value: T,
+    fields: Option<&'a FieldSelector>,
+}
+
+impl<T: Serialize> Serialize for Selected<'_, T> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.fields {
+            None => self.value.serialize(serializer),
+            Some(fields) => {
+                let value = serde_json::to_value(&self.value).map_err(serde::ser::Error::custom)?;
+                fields.prune(value).serialize(serializer)
+            }
+        }
+    }
+}
+
+fn select_fields<T>(items: Vec<T>, fields: Option<&FieldSelector>) -> Vec<Selected<'_, T>> {
+    items
+        .into_iter()
+        .map(|value| Selected { value, fields })
+        .collect()
 }
 
 fn to_scripthash(
@@ -1791,6 +5869,10 @@ fn to_scripthash(
 }
 
 fn address_to_scripthash(addr: &str, network: Network) -> Result<FullHash, HttpError> {
+    Ok(compute_script_hash(&address_to_script(addr, network)?))
+}
+
+fn address_to_script(addr: &str, network: Network) -> Result<Script, HttpError> {
     #[cfg(not(feature = "liquid"))]
     let addr = address::Address::from_str(addr)?;
     #[cfg(feature = "liquid")]
@@ -1809,13 +5891,71 @@ fn address_to_scripthash(addr: &str, network: Network) -> Result<FullHash, HttpE
     #[cfg(not(feature = "liquid"))]
     let addr = addr.assume_checked();
 
-    Ok(compute_script_hash(&addr.script_pubkey()))
+    Ok(addr.script_pubkey())
+}
+
+fn watch_list_name(query_params: &HashMap<String, String>) -> Result<&str, HttpError> {
+    match query_params.get("list") {
+        Some(name)
+            if !name.is_empty()
+                && name.len() <= watch_lists::MAX_NAME_LEN
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') =>
+        {
+            Ok(name)
+        }
+        Some(_) => Err(HttpError::from(format!(
+            "Invalid list, expected up to {} letters, digits, '-' or '_'",
+            watch_lists::MAX_NAME_LEN
+        ))),
+        None => Err(HttpError::from("Missing list".to_string())),
+    }
+}
+
+// The scripthashes of a watch list import, given as a JSON array of addresses and scripthashes or
+// as CSV with one per line (only the first column is read)
+fn parse_watch_entries(
+    body: &[u8],
+    is_csv: bool,
+    network: Network,
+) -> Result<Vec<FullHash>, HttpError> {
+    let entries: Vec<String> = if is_csv {
+        std::str::from_utf8(body)
+            .map_err(|_| HttpError::from("Invalid UTF-8".to_string()))?
+            .lines()
+            .filter_map(|line| line.split(',').next())
+            .map(|entry| entry.trim().trim_matches('"'))
+            .filter(|entry| !entry.is_empty())
+            .map(String::from)
+            .collect()
+    } else {
+        serde_json::from_slice(body).map_err(|err| HttpError::from(err.to_string()))?
+    };
+    entries
+        .iter()
+        .map(|entry| {
+            let scripthash = if entry.len() == 64 && entry.chars().all(|c| c.is_ascii_hexdigit()) {
+                parse_electrum_scripthash(entry)
+            } else {
+                address_to_scripthash(entry, network)
+            };
+            scripthash.map_err(|err| HttpError::from(format!("Invalid entry {}: {}", entry, err.1)))
+        })
+        .collect()
 }
 
 fn parse_scripthash(scripthash: &str) -> Result<FullHash, HttpError> {
     FullHash::from_hex(scripthash).map_err(|_| HttpError::from("Invalid scripthash".to_string()))
 }
 
+// Electrum encodes scripthashes as reversed hex, like txids
+fn parse_electrum_scripthash(scripthash: &str) -> Result<FullHash, HttpError> {
+    let mut hash = parse_scripthash(scripthash)?;
+    hash.reverse();
+    Ok(hash)
+}
+
 // Parse a cursor string in the format "txid:vout" into a tuple (Txid, u32)
 fn parse_cursor(cursor_str: &str) -> Result<Option<(Txid, u32)>, HttpError> {
     if cursor_str.is_empty() {
@@ -1885,10 +6025,15 @@ impl From<bitcoin::address::Error> for HttpError {
 impl From<errors::Error> for HttpError {
     fn from(e: errors::Error) -> Self {
         warn!("errors::Error: {:?}", e);
-        match e.description().to_string().as_ref() {
-            "getblock RPC error: {\"code\":-5,\"message\":\"Block not found\"}" => {
+        match e.kind() {
+            // RPC_INVALID_ADDRESS_OR_KEY
+            errors::ErrorKind::RpcError(method, -5, _) if method == "getblock" => {
                 HttpError::not_found("Block not found".to_string())
             }
+            errors::ErrorKind::Timeout => HttpError(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Request timed out".to_string(),
+            ),
             _ => HttpError::from(e.to_string()),
         }
     }
@@ -1925,9 +6070,13 @@ impl From<address::AddressError> for HttpError {
 
 #[cfg(test)]
 mod tests {
-    use crate::rest::HttpError;
+    use crate::rest::{
+        byte_range, format_coin_amount, has_bearer_token, parse_window, BinaryFormat, ByteRange,
+        HttpError, Paginator,
+    };
     use serde_json::Value;
     use std::collections::HashMap;
+    use std::time::Duration;
 
     #[test]
     fn test_parse_query_param() {
@@ -1990,4 +6139,131 @@ mod tests {
 
         assert!(err.is_err());
     }
+
+    #[test]
+    fn test_paginator() {
+        let mut query_params = HashMap::new();
+        assert!(Paginator::from_params(&query_params, 25, 100).unwrap().is_none());
+
+        query_params.insert("cursor".to_string(), "".to_string());
+        let paginator = Paginator::from_params(&query_params, 25, 100).unwrap().unwrap();
+        assert_eq!(paginator.limit, 25);
+        assert_eq!(paginator.cursor::<usize>().unwrap(), None);
+        assert!(!paginator.is_full(24));
+        assert!(paginator.is_full(25));
+
+        query_params.insert("cursor".to_string(), "50".to_string());
+        query_params.insert("limit".to_string(), "500".to_string());
+        assert!(Paginator::from_params(&query_params, 25, 100).is_err());
+
+        query_params.insert("limit".to_string(), "100".to_string());
+        let paginator = Paginator::from_params(&query_params, 25, 100).unwrap().unwrap();
+        assert_eq!(paginator.limit, 100);
+        assert_eq!(paginator.cursor::<usize>().unwrap(), Some(50));
+
+        query_params.insert("cursor".to_string(), "abc".to_string());
+        let paginator = Paginator::from_params(&query_params, 25, 100).unwrap().unwrap();
+        assert!(paginator.cursor::<usize>().is_err());
+    }
+
+    #[test]
+    fn test_bearer_token() {
+        let headers = |value: &str| {
+            let mut headers = hyper::HeaderMap::new();
+            headers.insert("Authorization", value.parse().unwrap());
+            headers
+        };
+        assert!(has_bearer_token(&headers("Bearer s3cret"), "s3cret"));
+        assert!(!has_bearer_token(&headers("Bearer s3cre"), "s3cret"));
+        assert!(!has_bearer_token(&headers("Bearer s3cret!"), "s3cret"));
+        assert!(!has_bearer_token(&headers("Bearer S3cret"), "s3cret"));
+        assert!(!has_bearer_token(&headers("s3cret"), "s3cret"));
+        assert!(!has_bearer_token(&hyper::HeaderMap::new(), "s3cret"));
+    }
+
+    #[test]
+    fn test_binary_format() {
+        let format = |accept: &str| {
+            let mut headers = hyper::HeaderMap::new();
+            headers.insert("Accept", accept.parse().unwrap());
+            BinaryFormat::from_accept(&headers).map(BinaryFormat::content_type)
+        };
+        assert_eq!(format("application/cbor"), Some("application/cbor"));
+        assert_eq!(
+            format("text/html, application/x-msgpack"),
+            Some("application/msgpack")
+        );
+        assert_eq!(format("application/json, application/cbor"), None);
+        assert_eq!(
+            format("application/json;q=0.5, application/cbor"),
+            Some("application/cbor")
+        );
+        assert_eq!(format("application/cbor;q=0.5, */*"), None);
+        assert_eq!(format("application/cbor;q=0"), None);
+        assert_eq!(
+            format("application/cbor;q=0, application/msgpack;q=0.1"),
+            Some("application/msgpack")
+        );
+        assert_eq!(format("application/cbor;q=x"), None);
+    }
+
+    #[test]
+    fn test_byte_range() {
+        let range = |value: &str| byte_range(Some(&value.parse().unwrap()), 100);
+        assert!(matches!(byte_range(None, 100), ByteRange::Full));
+        assert!(matches!(range("bytes=0-79"), ByteRange::Partial(0, 80)));
+        assert!(matches!(range("bytes=80-"), ByteRange::Partial(80, 100)));
+        assert!(matches!(range("bytes=-10"), ByteRange::Partial(90, 100)));
+        assert!(matches!(range("bytes=-200"), ByteRange::Partial(0, 100)));
+        assert!(matches!(range("bytes=50-500"), ByteRange::Partial(50, 100)));
+        assert!(matches!(range("bytes=100-"), ByteRange::Unsatisfiable));
+        assert!(matches!(range("bytes=-0"), ByteRange::Unsatisfiable));
+        assert!(matches!(range("bytes=0-1,5-9"), ByteRange::Full));
+        assert!(matches!(range("bytes=9-5"), ByteRange::Full));
+        assert!(matches!(range("items=0-9"), ByteRange::Full));
+    }
+
+    #[test]
+    fn test_parse_window() {
+        assert_eq!(parse_window("90").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_window("15m").unwrap(), Duration::from_secs(900));
+        assert_eq!(parse_window("2h").unwrap(), Duration::from_secs(7200));
+        assert_eq!(parse_window("30d").unwrap(), Duration::from_secs(2_592_000));
+        assert!(parse_window("1w").is_err());
+        assert!(parse_window("d").is_err());
+        assert!(parse_window("-1d").is_err());
+        // overflowing rather than wrapping around below the maximum
+        assert!(parse_window("213503982334602d").is_err());
+        assert!(parse_window("18446744073709551616").is_err());
+    }
+    #[test]
+    fn test_format_coin_amount() {
+        assert_eq!(format_coin_amount(0), "0.00000000");
+        assert_eq!(format_coin_amount(1), "0.00000001");
+        assert_eq!(format_coin_amount(12_345_678), "0.12345678");
+        assert_eq!(format_coin_amount(2_100_000_000_000_000), "21000000.00000000");
+        // beyond the 53 bits of precision of f64
+        assert_eq!(format_coin_amount(u64::MAX), "184467440737.09551615");
+    }
+
+    #[test]
+    fn test_locale() {
+        use crate::util::Locale;
+
+        let enabled = [Locale::En, Locale::De, Locale::Fr];
+        assert_eq!(Locale::negotiate("de-CH, en;q=0.8", &enabled), Some(Locale::De));
+        assert_eq!(Locale::negotiate("es, fr;q=0.5, en;q=0.9", &enabled), Some(Locale::En));
+        assert_eq!(Locale::negotiate("fr;q=0, *;q=0.1", &enabled), Some(Locale::En));
+        assert_eq!(Locale::negotiate("pt-BR, es", &enabled), None);
+        assert_eq!(Locale::negotiate("", &enabled), None);
+
+        assert_eq!(Locale::De.translate("Block not found"), "Block nicht gefunden");
+        assert_eq!(Locale::De.translate("Invalid depth"), "Invalid depth");
+        assert_eq!(Locale::En.translate("Block not found"), "Block not found");
+
+        assert_eq!(Locale::En.format_amount("1234567.00000001"), "1234567.00000001");
+        assert_eq!(Locale::De.format_amount("1234567.00000001"), "1.234.567,00000001");
+        assert_eq!(Locale::Fr.format_amount("123456.5"), "123\u{202f}456,5");
+        assert_eq!(Locale::Pt.format_amount("100"), "100");
+    }
 }
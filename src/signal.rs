@@ -10,10 +10,12 @@ use crate::errors::*;
 #[derive(Clone)] // so multiple threads could wait on signals
 pub struct Waiter {
     receiver: channel::Receiver<i32>,
+    sender: channel::Sender<i32>,
 }
 
-fn notify(signals: &[i32]) -> channel::Receiver<i32> {
+fn notify(signals: &[i32]) -> (channel::Sender<i32>, channel::Receiver<i32>) {
     let (s, r) = channel::bounded(1);
+    let sender = s.clone();
     let mut signals =
         signal_hook::iterator::Signals::new(signals).expect("failed to register signal hook");
     thread::spawn(move || {
@@ -22,17 +24,22 @@ fn notify(signals: &[i32]) -> channel::Receiver<i32> {
                 .unwrap_or_else(|_| panic!("failed to send signal {}", signal));
         }
     });
-    r
+    (sender, r)
 }
 
 impl Waiter {
     pub fn start() -> Waiter {
-        Waiter {
-            receiver: notify(&[
-                SIGINT, SIGTERM,
-                SIGUSR1, // allow external triggering (e.g. via bitcoind `blocknotify`)
-            ]),
-        }
+        let (sender, receiver) = notify(&[
+            SIGINT, SIGTERM,
+            SIGUSR1, // allow external triggering (e.g. via bitcoind `blocknotify`)
+        ]);
+        Waiter { receiver, sender }
+    }
+
+    // Wake up the waits accepting SIGUSR1, as if it was received
+    pub fn trigger(&self) {
+        // a full channel already has a signal pending
+        let _ = self.sender.try_send(SIGUSR1);
     }
 
     pub fn wait(&self, duration: Duration, accept_sigusr: bool) -> Result<()> {
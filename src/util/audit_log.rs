@@ -0,0 +1,156 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde_json::Value;
+
+use crate::config::Config;
+
+/// A mutating action (a broadcast or an admin endpoint call), logged as a JSON line
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AuditEvent {
+    pub time: u64, // unix timestamp, in seconds
+    pub action: String,
+    pub source: String, // "rest" or "electrum"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client: Option<String>,
+    pub details: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl AuditEvent {
+    pub fn new(action: &str, source: &str, details: Value) -> Self {
+        AuditEvent {
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |since_epoch| since_epoch.as_secs()),
+            action: action.to_string(),
+            source: source.to_string(),
+            request_id: None,
+            client: None,
+            details,
+            error: None,
+        }
+    }
+}
+
+/// An append-only log file, rotated to `<path>.1` ... `<path>.<max_files>` once it grows past
+/// its maximum size
+pub struct AuditLog {
+    path: PathBuf,
+    max_size: u64,
+    max_files: usize,
+    file: Mutex<(Option<File>, u64)>, // the current file (unless it failed to open) and its size
+}
+
+impl AuditLog {
+    /// Returns None when no audit log file is configured
+    pub fn new(config: &Config) -> Option<Self> {
+        let path = config.audit_log_file.clone()?;
+        let file = open(&path);
+        let size = file
+            .as_ref()
+            .and_then(|file| file.metadata().ok())
+            .map_or(0, |meta| meta.len());
+        Some(AuditLog {
+            path,
+            max_size: config.audit_log_max_size,
+            max_files: config.audit_log_max_files,
+            file: Mutex::new((file, size)),
+        })
+    }
+
+    pub fn record(&self, event: &AuditEvent) {
+        let line = format!("{}\n", serde_json::to_string(event).unwrap());
+        let mut file = self.file.lock().unwrap();
+        if file.1 > 0 && file.1 + line.len() as u64 > self.max_size {
+            if let Err(e) = self.rotate() {
+                warn!("failed rotating the audit log: {}", e);
+            }
+            *file = (open(&self.path), 0);
+        } else if file.0.is_none() {
+            // retry opening the file, in case the failure was transient
+            file.0 = open(&self.path);
+        }
+        let result = match file.0 {
+            Some(ref mut current) => current.write_all(line.as_bytes()),
+            None => return, // the failure got logged already
+        };
+        match result {
+            Ok(()) => file.1 += line.len() as u64,
+            Err(e) => warn!("failed writing to the audit log: {}", e),
+        }
+    }
+
+    /// The events recorded since the given time (inclusive), oldest first
+    pub fn events_since(&self, since: u64, limit: usize) -> Vec<AuditEvent> {
+        // open the files at once, as they can't get rotated meanwhile, then read them without
+        // holding up the writers. once opened, the files can get renamed or removed by a rotation
+        // without affecting the reading.
+        let files: Vec<(PathBuf, File)> = {
+            let _file = self.file.lock().unwrap();
+            (1..=self.max_files)
+                .rev()
+                .map(|index| self.rotated_path(index))
+                .chain(std::iter::once(self.path.clone()))
+                // the missing ones didn't get rotated that many times yet
+                .filter_map(|path| File::open(&path).ok().map(|file| (path, file)))
+                .collect()
+        };
+        let mut events = vec![];
+        for (path, file) in files {
+            let lines = BufReader::new(file).lines().map_while(|line| line.ok());
+            for line in lines {
+                let event: AuditEvent = match serde_json::from_str(&line) {
+                    Ok(event) => event,
+                    Err(e) => {
+                        warn!("invalid audit log entry in {}: {}", path.display(), e);
+                        continue;
+                    }
+                };
+                if event.time >= since {
+                    events.push(event);
+                    if events.len() >= limit {
+                        return events;
+                    }
+                }
+            }
+        }
+        events
+    }
+
+    // Shift the rotated files by one, dropping the oldest one
+    fn rotate(&self) -> std::io::Result<()> {
+        if self.max_files == 0 {
+            return fs::remove_file(&self.path);
+        }
+        for index in (1..self.max_files).rev() {
+            let from = self.rotated_path(index);
+            if from.exists() {
+                fs::rename(from, self.rotated_path(index + 1))?;
+            }
+        }
+        fs::rename(&self.path, self.rotated_path(1))
+    }
+
+    fn rotated_path(&self, index: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", index));
+        path.into()
+    }
+}
+
+fn open(path: &Path) -> Option<File> {
+    match OpenOptions::new().create(true).append(true).open(path) {
+        Ok(file) => Some(file),
+        Err(e) => {
+            warn!("failed opening the audit log {}: {}", path.display(), e);
+            None
+        }
+    }
+}
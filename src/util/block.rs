@@ -1,11 +1,21 @@
-use crate::chain::{BlockHash, BlockHeader};
+use crate::chain::{BlockHash, BlockHeader, Network, OutPoint, Transaction, TxOut, Txid};
 use crate::errors::*;
 use crate::new_index::BlockEntry;
-
+use crate::util::extract_tx_prevouts;
+use crate::util::fees::TxFeeInfo;
+#[cfg(not(feature = "liquid"))]
+use bitcoin::Work;
+#[cfg(not(feature = "liquid"))]
+use crate::util::{get_innerscripts, has_prevout, inner_multisig, parse_multisig, MultisigType};
+
+#[cfg(not(feature = "liquid"))]
+use std::collections::BTreeMap;
 use std::collections::HashMap;
 use std::fmt;
 use std::iter::FromIterator;
 use std::slice;
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
 use time::format_description::well_known::Rfc3339;
 use time::OffsetDateTime as DateTime;
 
@@ -40,6 +50,8 @@ pub struct HeaderEntry {
     height: usize,
     hash: BlockHash,
     header: BlockHeader,
+    #[cfg(not(feature = "liquid"))]
+    chainwork: Work, // the cumulative work of the chain up to and including this block
 }
 
 impl HeaderEntry {
@@ -54,6 +66,11 @@ impl HeaderEntry {
     pub fn height(&self) -> usize {
         self.height
     }
+
+    #[cfg(not(feature = "liquid"))]
+    pub fn chainwork(&self) -> Work {
+        self.chainwork
+    }
 }
 
 impl fmt::Debug for HeaderEntry {
@@ -72,6 +89,8 @@ impl fmt::Debug for HeaderEntry {
 pub struct HeaderList {
     headers: Vec<HeaderEntry>,
     heights: HashMap<BlockHash, usize>,
+    // the latest timestamp up to each height, which unlike the block timestamps never decreases
+    max_times: Vec<u32>,
     tip: BlockHash,
 }
 
@@ -80,6 +99,7 @@ impl HeaderList {
         HeaderList {
             headers: vec![],
             heights: HashMap::new(),
+            max_times: vec![],
             tip: *DEFAULT_BLOCKHASH,
         }
     }
@@ -142,20 +162,32 @@ impl HeaderList {
             Some(h) => h.header.prev_blockhash,
             None => return vec![], // hashed_headers is empty
         };
-        let new_height: usize = if prev_blockhash == *DEFAULT_BLOCKHASH {
-            0
+        let prev_header = if prev_blockhash == *DEFAULT_BLOCKHASH {
+            None
         } else {
-            self.header_by_blockhash(&prev_blockhash)
-                .unwrap_or_else(|| panic!("{} is not part of the blockchain", prev_blockhash))
-                .height()
-                + 1
+            Some(
+                self.header_by_blockhash(&prev_blockhash)
+                    .unwrap_or_else(|| panic!("{} is not part of the blockchain", prev_blockhash)),
+            )
         };
+        let new_height = prev_header.map_or(0, |header| header.height() + 1);
+        #[cfg(not(feature = "liquid"))]
+        let mut chainwork =
+            prev_header.map_or(Work::from_be_bytes([0; 32]), |header| header.chainwork);
         (new_height..)
             .zip(hashed_headers.into_iter())
-            .map(|(height, hashed_header)| HeaderEntry {
-                height,
-                hash: hashed_header.blockhash,
-                header: hashed_header.header,
+            .map(|(height, hashed_header)| {
+                #[cfg(not(feature = "liquid"))]
+                {
+                    chainwork = chainwork + hashed_header.header.work();
+                }
+                HeaderEntry {
+                    height,
+                    hash: hashed_header.blockhash,
+                    header: hashed_header.header,
+                    #[cfg(not(feature = "liquid"))]
+                    chainwork,
+                }
             })
             .collect()
     }
@@ -188,9 +220,16 @@ impl HeaderList {
             new_height
         );
         let _removed = self.headers.split_off(new_height); // keep [0..new_height) entries
+        self.max_times.truncate(new_height);
         for new_header in new_headers {
             let height = new_header.height();
             assert_eq!(height, self.headers.len());
+            let time = new_header.header().time;
+            let max_time = self
+                .max_times
+                .last()
+                .map_or(time, |max_time| time.max(*max_time));
+            self.max_times.push(max_time);
             self.tip = *new_header.hash();
             self.headers.push(new_header);
             self.heights.insert(self.tip, height);
@@ -214,6 +253,14 @@ impl HeaderList {
         })
     }
 
+    // The first block with a timestamp at or after `time`. Block timestamps are only roughly
+    // ordered, so the search runs over their running max, and a later block may have an earlier
+    // timestamp than the one found.
+    pub fn header_by_time(&self, time: u32) -> Option<&HeaderEntry> {
+        let height = self.max_times.partition_point(|max_time| *max_time < time);
+        self.headers.get(height)
+    }
+
     pub fn equals(&self, other: &HeaderList) -> bool {
         self.headers.last() == other.headers.last()
     }
@@ -259,6 +306,39 @@ impl HeaderList {
     }
 }
 
+/// Wakes up threads waiting for the indexed chain tip to change
+pub struct TipNotifier {
+    tip: Mutex<BlockHash>,
+    cond: Condvar,
+}
+
+impl TipNotifier {
+    pub fn new(tip: BlockHash) -> Self {
+        TipNotifier {
+            tip: Mutex::new(tip),
+            cond: Condvar::new(),
+        }
+    }
+
+    pub fn notify(&self, tip: BlockHash) {
+        let mut current = self.tip.lock().unwrap();
+        if *current != tip {
+            *current = tip;
+            self.cond.notify_all();
+        }
+    }
+
+    /// Block until the tip differs from `known` or `timeout` elapses, returning the current tip
+    pub fn wait_change(&self, known: &BlockHash, timeout: Duration) -> BlockHash {
+        let current = self.tip.lock().unwrap();
+        let (current, _) = self
+            .cond
+            .wait_timeout_while(current, timeout, |tip| tip == known)
+            .unwrap();
+        *current
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct BlockStatus {
     pub in_best_chain: bool,
@@ -284,7 +364,7 @@ impl BlockStatus {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct BlockMeta {
     #[serde(alias = "nTx")]
     pub tx_count: u32,
@@ -336,3 +416,191 @@ impl BlockMeta {
         })
     }
 }
+
+// Per-block figures for charting, computed while indexing the block history (which is
+// when the prevouts needed for the fees are at hand)
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BlockStats {
+    pub height: u32,
+    pub time: u32,
+    pub size: u32,
+    pub weight: u32,
+    pub tx_count: u32,
+    pub input_count: u32,
+    pub output_count: u32,
+    pub total_fee: u64,
+    pub median_feerate: f64, // in sat/vB, excluding the coinbase
+    pub coin_days_destroyed: f64,
+    pub segwit_input_count: u32,  // inputs spending with a witness
+    pub taproot_input_count: u32, // inputs spending P2TR outputs
+}
+
+impl BlockStats {
+    // `txo_times` maps the txids of the spent outputs to the time of their confirming block
+    pub fn new(
+        b: &BlockEntry,
+        txos: &HashMap<OutPoint, TxOut>,
+        txo_times: &HashMap<Txid, u32>,
+        network: Network,
+    ) -> Self {
+        let meta = BlockMeta::from(b);
+        let time = b.entry.header().time;
+        let mut feerates = vec![];
+        let mut total_fee = 0;
+        let mut input_count = 0;
+        let mut output_count = 0;
+        let mut coin_days_destroyed = 0.0;
+        let mut segwit_input_count = 0;
+        let mut taproot_input_count = 0;
+
+        for tx in &b.block.txdata {
+            input_count += tx.input.len() as u32;
+            output_count += tx.output.len() as u32;
+            if tx.is_coinbase() {
+                continue;
+            }
+            let prevouts = extract_tx_prevouts(tx, txos, false);
+            let fee_info = TxFeeInfo::new(tx, &prevouts, network);
+            total_fee += fee_info.fee;
+            feerates.push(fee_info.fee_per_vbyte);
+            coin_days_destroyed += tx_coin_days_destroyed(tx, &prevouts, txo_times, time);
+            let (segwit, taproot) = tx_witness_spends(tx, &prevouts);
+            segwit_input_count += segwit;
+            taproot_input_count += taproot;
+        }
+        feerates.sort_unstable_by(|a, b| a.partial_cmp(b).unwrap());
+
+        BlockStats {
+            height: b.entry.height() as u32,
+            time: b.entry.header().time,
+            size: meta.size,
+            weight: meta.weight,
+            tx_count: meta.tx_count,
+            input_count,
+            output_count,
+            total_fee,
+            median_feerate: median(&feerates),
+            coin_days_destroyed,
+            segwit_input_count,
+            taproot_input_count,
+        }
+    }
+
+    // The share of the inputs (excluding the coinbase) spending with a witness, in percent
+    pub fn segwit_percent(&self) -> Option<f64> {
+        self.spending_share(self.segwit_input_count)
+    }
+
+    // The share of the inputs (excluding the coinbase) spending P2TR outputs, in percent
+    pub fn taproot_percent(&self) -> Option<f64> {
+        self.spending_share(self.taproot_input_count)
+    }
+
+    fn spending_share(&self, count: u32) -> Option<f64> {
+        // the coinbase transaction has a single input
+        let spending_count = self.input_count.checked_sub(1).filter(|n| *n > 0)?;
+        Some(count as f64 * 100.0 / spending_count as f64)
+    }
+}
+
+// The number of m-of-n multisig scripts of each type seen in a block
+#[cfg(not(feature = "liquid"))]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MultisigCount {
+    #[serde(rename = "type")]
+    pub script_type: MultisigType,
+    pub m: u8,
+    pub n: u8,
+    pub count: u32,
+}
+
+// Bare multisig scripts are counted as they get created by the outputs of the block, P2SH and
+// P2WSH ones as they get revealed by its inputs
+#[cfg(not(feature = "liquid"))]
+pub fn block_multisig_counts(
+    b: &BlockEntry,
+    txos: &HashMap<OutPoint, TxOut>,
+) -> Vec<MultisigCount> {
+    let mut counts: BTreeMap<(MultisigType, u8, u8), u32> = BTreeMap::new();
+    for tx in &b.block.txdata {
+        let bare = tx.output.iter().filter_map(|txout| {
+            let (m, n) = parse_multisig(&txout.script_pubkey)?;
+            Some((MultisigType::Bare, m, n))
+        });
+        let revealed = tx
+            .input
+            .iter()
+            .filter(|txin| has_prevout(txin))
+            .filter_map(|txin| {
+                let prevout = txos.get(&txin.previous_output)?;
+                inner_multisig(&get_innerscripts(txin, prevout))
+            });
+        for multisig in bare.chain(revealed) {
+            *counts.entry(multisig).or_insert(0) += 1;
+        }
+    }
+    counts
+        .into_iter()
+        .map(|((script_type, m, n), count)| MultisigCount {
+            script_type,
+            m,
+            n,
+            count,
+        })
+        .collect()
+}
+
+// The value of the spent outputs in coins, weighted by the days since they were confirmed
+#[cfg(not(feature = "liquid"))]
+fn tx_coin_days_destroyed(
+    tx: &Transaction,
+    prevouts: &HashMap<u32, &TxOut>,
+    txo_times: &HashMap<Txid, u32>,
+    time: u32,
+) -> f64 {
+    prevouts
+        .iter()
+        .filter_map(|(vin, prevout)| {
+            let prev_time = txo_times.get(&tx.input[*vin as usize].previous_output.txid)?;
+            let age_days = time.saturating_sub(*prev_time) as f64 / 86_400.0;
+            Some(prevout.value.to_btc() * age_days)
+        })
+        .sum()
+}
+
+// The number of inputs spending with a witness, and of those spending P2TR outputs
+#[cfg(not(feature = "liquid"))]
+fn tx_witness_spends(tx: &Transaction, prevouts: &HashMap<u32, &TxOut>) -> (u32, u32) {
+    let segwit = tx.input.iter().filter(|txin| !txin.witness.is_empty()).count();
+    let taproot = prevouts
+        .values()
+        .filter(|prevout| prevout.script_pubkey.is_p2tr())
+        .count();
+    (segwit as u32, taproot as u32)
+}
+
+// Segwit and taproot adoption is not tracked for Elements
+#[cfg(feature = "liquid")]
+fn tx_witness_spends(_tx: &Transaction, _prevouts: &HashMap<u32, &TxOut>) -> (u32, u32) {
+    (0, 0)
+}
+
+// Output values are confidential in Elements
+#[cfg(feature = "liquid")]
+fn tx_coin_days_destroyed(
+    _tx: &Transaction,
+    _prevouts: &HashMap<u32, &TxOut>,
+    _txo_times: &HashMap<Txid, u32>,
+    _time: u32,
+) -> f64 {
+    0.0
+}
+
+// The median of sorted values, or 0 if there are none
+pub fn median(sorted: &[f64]) -> f64 {
+    match sorted.len() {
+        0 => 0.0,
+        n if n % 2 == 0 => (sorted[n / 2 - 1] + sorted[n / 2]) / 2.0,
+        n => sorted[n / 2],
+    }
+}
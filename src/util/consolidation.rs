@@ -0,0 +1,184 @@
+use std::collections::BinaryHeap;
+use std::str::FromStr;
+
+use crate::chain::OutPoint;
+use crate::util::estimate_tx_vsize;
+
+// Standard transactions weigh at most 400k weight units
+const MAX_STANDARD_TX_VSIZE: u64 = 100_000;
+
+/// Which outputs get consolidated first
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConsolidationOrder {
+    Smallest,
+    Oldest,
+}
+
+impl FromStr for ConsolidationOrder {
+    type Err = String;
+
+    fn from_str(order: &str) -> Result<Self, Self::Err> {
+        match order {
+            "smallest" => Ok(ConsolidationOrder::Smallest),
+            "oldest" => Ok(ConsolidationOrder::Oldest),
+            _ => Err(format!(
+                "Invalid order {}, expected smallest or oldest",
+                order
+            )),
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct ConsolidationInput {
+    pub txid: String,
+    pub vout: u32,
+    pub value: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub block_height: Option<usize>, // None for unconfirmed outputs
+}
+
+impl ConsolidationInput {
+    pub fn new(outpoint: OutPoint, block_height: Option<usize>, value: u64) -> Self {
+        ConsolidationInput {
+            txid: outpoint.txid.to_string(),
+            vout: outpoint.vout,
+            value,
+            block_height,
+        }
+    }
+}
+
+/// A transaction spending some of the outputs to a single one, back to the same script
+#[derive(Serialize, Debug)]
+pub struct ConsolidationTx {
+    pub inputs: Vec<ConsolidationInput>,
+    pub input_value: u64,
+    pub vsize: u64,
+    pub fee: u64,
+    pub output_value: u64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ConsolidationPlan {
+    pub utxo_count: usize,
+    pub target_utxos: usize,
+    pub feerate: u64,
+    pub transactions: Vec<ConsolidationTx>,
+    pub input_count: usize,
+    pub vsize: u64,
+    pub fee: u64,
+    pub remaining_utxos: usize,
+    // the outputs worth less than the fee to spend them are never consolidated
+    pub uneconomical_count: usize,
+    // whether the plan stopped at the maximum number of inputs before reaching the target
+    pub truncated: bool,
+}
+
+/// The shape of the transactions spending the outputs of a script
+pub struct SpendShape {
+    pub input_vsize: u64,  // as estimated by `spending_vsize`
+    pub output_vsize: u64, // of the consolidated output
+    pub segwit: bool,
+}
+
+/// Plan the transactions bringing the outputs of a script down to `target_utxos`, picking them
+/// in the given order and filling each transaction up to the standard size limit. Plans with
+/// more than `max_inputs` inputs are truncated, so only the first `max_inputs` outputs in order
+/// are kept while going through them.
+pub fn plan_consolidation(
+    utxos: impl IntoIterator<Item = (OutPoint, Option<usize>, u64)>,
+    shape: &SpendShape,
+    feerate: u64,
+    target_utxos: usize,
+    order: ConsolidationOrder,
+    max_inputs: usize,
+) -> ConsolidationPlan {
+    let input_fee = feerate.saturating_mul(shape.input_vsize);
+    // unconfirmed outputs count as the most recent ones, and ties are broken by outpoint for the
+    // plans to be stable
+    let order_key = |outpoint: OutPoint, height: Option<usize>, value: u64| {
+        let age = height.map_or(u64::MAX, |height| height as u64);
+        match order {
+            ConsolidationOrder::Smallest => (value, age, outpoint),
+            ConsolidationOrder::Oldest => (age, value, outpoint),
+        }
+    };
+    let mut utxo_count = 0;
+    let mut uneconomical_count = 0;
+    let mut candidate_count = 0;
+    // the first candidates in order, with the last one on top to be evicted by earlier ones
+    let mut candidates = BinaryHeap::with_capacity(max_inputs + 1);
+    for (outpoint, height, value) in utxos {
+        utxo_count += 1;
+        if value <= input_fee {
+            uneconomical_count += 1;
+            continue;
+        }
+        candidate_count += 1;
+        candidates.push((order_key(outpoint, height, value), height, value));
+        if candidates.len() > max_inputs {
+            candidates.pop();
+        }
+    }
+    let mut candidates = candidates
+        .into_sorted_vec()
+        .into_iter()
+        .map(|((_, _, outpoint), height, value)| ConsolidationInput::new(outpoint, height, value));
+
+    let tx_vsize = |input_count: usize| {
+        let inputs_vsize = input_count as u64 * shape.input_vsize;
+        estimate_tx_vsize(
+            input_count,
+            inputs_vsize,
+            1,
+            shape.output_vsize,
+            shape.segwit,
+        )
+    };
+    let inputs_per_tx = ((MAX_STANDARD_TX_VSIZE - tx_vsize(0)) / shape.input_vsize) as usize;
+
+    // every transaction turns its inputs into a single output
+    let mut excess = utxo_count.saturating_sub(target_utxos);
+    let mut transactions = vec![];
+    let mut input_count = 0;
+    let mut truncated = false;
+    while excess > 0 {
+        let wanted = inputs_per_tx
+            .min(excess + 1)
+            .min(candidate_count - input_count);
+        let count = wanted.min(max_inputs - input_count);
+        if count < wanted {
+            truncated = true;
+        }
+        if count < 2 {
+            break;
+        }
+        let inputs: Vec<ConsolidationInput> = candidates.by_ref().take(count).collect();
+        let input_value = inputs.iter().map(|input| input.value).sum::<u64>();
+        let vsize = tx_vsize(count);
+        let fee = feerate.saturating_mul(vsize);
+        transactions.push(ConsolidationTx {
+            inputs,
+            input_value,
+            vsize,
+            fee,
+            output_value: input_value.saturating_sub(fee),
+        });
+        excess -= count - 1;
+        input_count += count;
+    }
+
+    ConsolidationPlan {
+        utxo_count,
+        target_utxos,
+        feerate,
+        input_count,
+        vsize: transactions.iter().map(|tx| tx.vsize).sum(),
+        fee: transactions.iter().map(|tx| tx.fee).sum(),
+        remaining_utxos: utxo_count - input_count + transactions.len(),
+        uneconomical_count,
+        truncated,
+        transactions,
+    }
+}
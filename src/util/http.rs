@@ -0,0 +1,41 @@
+use std::io::Read;
+use std::time::Duration;
+
+use url::Url;
+
+use crate::errors::*;
+
+const HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+// responses past this size are rejected rather than buffered
+const MAX_BODY_SIZE: u64 = 64 * 1024 * 1024;
+
+lazy_static! {
+    static ref AGENT: ureq::Agent = ureq::AgentBuilder::new()
+        .timeout_connect(HTTP_TIMEOUT)
+        .timeout_read(HTTP_TIMEOUT)
+        .build();
+}
+
+// A GET over http or https, failing with `HttpStatus` unless the server replies with a 200
+pub fn http_get(url: &Url) -> Result<String> {
+    let response = match AGENT
+        .request_url("GET", url)
+        .set("Accept", "application/json")
+        .call()
+    {
+        Ok(response) => response,
+        Err(ureq::Error::Status(code, _)) => bail!(ErrorKind::HttpStatus(code)),
+        Err(e) => bail!("failed requesting {}: {}", url, e),
+    };
+
+    let mut body = String::new();
+    response
+        .into_reader()
+        .take(MAX_BODY_SIZE + 1)
+        .read_to_string(&mut body)
+        .chain_err(|| "failed to read response")?;
+    if body.len() as u64 > MAX_BODY_SIZE {
+        bail!("response exceeds the maximum of {} bytes", MAX_BODY_SIZE);
+    }
+    Ok(body)
+}
@@ -0,0 +1,252 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// A language the human-readable parts of the REST responses can be served in
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Locale {
+    En,
+    De,
+    Es,
+    Fr,
+    Pt,
+}
+
+// The translations of the most common error messages, in the De, Es, Fr and Pt order. The other
+// messages are served in English.
+const TRANSLATIONS: &[(&str, [&str; 4])] = &[
+    (
+        "Block not found",
+        [
+            "Block nicht gefunden",
+            "Bloque no encontrado",
+            "Bloc introuvable",
+            "Bloco não encontrado",
+        ],
+    ),
+    (
+        "Transaction not found",
+        [
+            "Transaktion nicht gefunden",
+            "Transacción no encontrada",
+            "Transaction introuvable",
+            "Transação não encontrada",
+        ],
+    ),
+    (
+        "Transaction not found or is unconfirmed",
+        [
+            "Transaktion nicht gefunden oder unbestätigt",
+            "Transacción no encontrada o sin confirmar",
+            "Transaction introuvable ou non confirmée",
+            "Transação não encontrada ou não confirmada",
+        ],
+    ),
+    (
+        "Invalid Bitcoin address",
+        [
+            "Ungültige Adresse",
+            "Dirección no válida",
+            "Adresse invalide",
+            "Endereço inválido",
+        ],
+    ),
+    (
+        "Address on invalid network",
+        [
+            "Adresse eines anderen Netzwerks",
+            "Dirección de otra red",
+            "Adresse d'un autre réseau",
+            "Endereço de outra rede",
+        ],
+    ),
+    (
+        "Invalid scripthash",
+        [
+            "Ungültiger Scripthash",
+            "Scripthash no válido",
+            "Scripthash invalide",
+            "Scripthash inválido",
+        ],
+    ),
+    (
+        "Invalid hash string",
+        [
+            "Ungültiger Hash",
+            "Hash no válido",
+            "Hash invalide",
+            "Hash inválido",
+        ],
+    ),
+    (
+        "Invalid hex string",
+        [
+            "Ungültige Hex-Zeichenkette",
+            "Cadena hexadecimal no válida",
+            "Chaîne hexadécimale invalide",
+            "Cadeia hexadecimal inválida",
+        ],
+    ),
+    (
+        "Invalid number",
+        [
+            "Ungültige Zahl",
+            "Número no válido",
+            "Nombre invalide",
+            "Número inválido",
+        ],
+    ),
+    (
+        "Invalid block range",
+        [
+            "Ungültiger Blockbereich",
+            "Rango de bloques no válido",
+            "Plage de blocs invalide",
+            "Intervalo de blocos inválido",
+        ],
+    ),
+    (
+        "Invalid cursor",
+        [
+            "Ungültiger Cursor",
+            "Cursor no válido",
+            "Curseur invalide",
+            "Cursor inválido",
+        ],
+    ),
+    (
+        "Invalid unit, expected sat or coin",
+        [
+            "Ungültige Einheit, erwartet sat oder coin",
+            "Unidad no válida, se esperaba sat o coin",
+            "Unité invalide, sat ou coin attendu",
+            "Unidade inválida, esperado sat ou coin",
+        ],
+    ),
+    (
+        "Request budget exceeded",
+        [
+            "Anfragebudget überschritten",
+            "Presupuesto de solicitudes agotado",
+            "Budget de requêtes dépassé",
+            "Orçamento de requisições esgotado",
+        ],
+    ),
+    (
+        "Request timed out",
+        [
+            "Zeitüberschreitung der Anfrage",
+            "La solicitud ha expirado",
+            "Délai de la requête dépassé",
+            "A requisição expirou",
+        ],
+    ),
+    (
+        "Transaction spends unknown outputs",
+        [
+            "Die Transaktion gibt unbekannte Outputs aus",
+            "La transacción gasta salidas desconocidas",
+            "La transaction dépense des sorties inconnues",
+            "A transação gasta saídas desconhecidas",
+        ],
+    ),
+];
+
+lazy_static! {
+    static ref TRANSLATION_INDEX: HashMap<&'static str, &'static [&'static str; 4]> = TRANSLATIONS
+        .iter()
+        .map(|(message, translations)| (*message, translations))
+        .collect();
+}
+
+impl Locale {
+    pub fn tag(self) -> &'static str {
+        match self {
+            Locale::En => "en",
+            Locale::De => "de",
+            Locale::Es => "es",
+            Locale::Fr => "fr",
+            Locale::Pt => "pt",
+        }
+    }
+
+    /// Pick the locale the client prefers among the enabled ones, according to the given
+    /// Accept-Language header. None if it accepts none of them.
+    pub fn negotiate(accept_language: &str, enabled: &[Locale]) -> Option<Locale> {
+        let mut ranges: Vec<(&str, f32)> = accept_language
+            .split(',')
+            .filter_map(|range| {
+                let mut parts = range.split(';').map(str::trim);
+                let tag = parts.next().filter(|tag| !tag.is_empty())?;
+                let quality = parts
+                    .find_map(|param| param.strip_prefix("q="))
+                    .map_or(Some(1.0), |q| q.parse::<f32>().ok())?;
+                Some((tag, quality))
+            })
+            .filter(|(_, quality)| *quality > 0.0)
+            .collect();
+        // the sort is stable, so that equally preferred ranges keep their order
+        ranges.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+        ranges.iter().find_map(|(tag, _)| match *tag {
+            "*" => enabled.first().copied(),
+            tag => tag.parse().ok().filter(|locale| enabled.contains(locale)),
+        })
+    }
+
+    /// The message in this locale, or as is if there is no translation for it
+    pub fn translate(self, message: &str) -> String {
+        let index = match self {
+            Locale::En => return message.to_string(),
+            Locale::De => 0,
+            Locale::Es => 1,
+            Locale::Fr => 2,
+            Locale::Pt => 3,
+        };
+        TRANSLATION_INDEX
+            .get(message)
+            .map_or(message, |translations| translations[index])
+            .to_string()
+    }
+
+    /// Localize the separators of a decimal amount formatted as `1234.5678`. English amounts are
+    /// left as is, so that they remain machine-readable.
+    pub fn format_amount(self, amount: &str) -> String {
+        let (decimal_sep, group_sep) = match self {
+            Locale::En => return amount.to_string(),
+            Locale::De | Locale::Es | Locale::Pt => (",", "."),
+            Locale::Fr => (",", "\u{202f}"),
+        };
+        let (integer, fraction) = match amount.split_once('.') {
+            Some((integer, fraction)) => (integer, Some(fraction)),
+            None => (amount, None),
+        };
+        let mut formatted = String::with_capacity(amount.len() + amount.len() / 3);
+        for (i, digit) in integer.chars().enumerate() {
+            if i > 0 && (integer.len() - i) % 3 == 0 {
+                formatted.push_str(group_sep);
+            }
+            formatted.push(digit);
+        }
+        if let Some(fraction) = fraction {
+            formatted.push_str(decimal_sep);
+            formatted.push_str(fraction);
+        }
+        formatted
+    }
+}
+
+impl FromStr for Locale {
+    type Err = String;
+
+    /// Parse a language tag, ignoring its region (e.g. `pt-BR`)
+    fn from_str(tag: &str) -> Result<Self, Self::Err> {
+        let language = tag.split(['-', '_']).next().unwrap_or("");
+        match language.to_ascii_lowercase().as_str() {
+            "en" => Ok(Locale::En),
+            "de" => Ok(Locale::De),
+            "es" => Ok(Locale::Es),
+            "fr" => Ok(Locale::Fr),
+            "pt" => Ok(Locale::Pt),
+            _ => Err(format!("unsupported locale: {}", tag)),
+        }
+    }
+}
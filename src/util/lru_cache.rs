@@ -0,0 +1,510 @@
+use std::fmt;
+use std::fs;
+use std::hash::Hash;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use lru::LruCache;
+
+use crate::metrics::{CounterVec, GaugeVec, MetricOpts, Metrics};
+use crate::util::spawn_thread;
+
+/// Hit/miss and size metrics, shared by all the caches and labeled by cache name
+#[derive(Clone)]
+pub struct CacheMetrics {
+    lookups: CounterVec,
+    size: GaugeVec,
+}
+
+impl CacheMetrics {
+    pub fn new(metrics: &Metrics) -> Self {
+        CacheMetrics {
+            lookups: metrics.counter_vec(
+                MetricOpts::new("cache_lookups", "# of cache lookups, by hit/miss"),
+                &["cache", "result"],
+            ),
+            size: metrics.gauge_vec(
+                MetricOpts::new("cache_size", "Approximate size of cached entries (in bytes)"),
+                &["cache"],
+            ),
+        }
+    }
+}
+
+struct Entries<K: Hash + Eq, V> {
+    lru: LruCache<K, (V, usize)>,
+    bytes: usize,
+}
+
+/// A thread-safe LRU cache, bounded by the approximate total size of its entries in bytes
+pub struct SizedLruCache<K: Hash + Eq, V: Clone> {
+    name: &'static str,
+    max_bytes: usize,
+    entries: Mutex<Entries<K, V>>,
+    metrics: CacheMetrics,
+}
+
+impl<K: Hash + Eq, V: Clone> SizedLruCache<K, V> {
+    pub fn new(name: &'static str, max_bytes: usize, metrics: &CacheMetrics) -> Self {
+        SizedLruCache {
+            name,
+            max_bytes,
+            entries: Mutex::new(Entries {
+                lru: LruCache::unbounded(),
+                bytes: 0,
+            }),
+            metrics: metrics.clone(),
+        }
+    }
+
+    pub fn get(&self, key: &K) -> Option<V> {
+        if self.max_bytes == 0 {
+            return None;
+        }
+        let value = self
+            .entries
+            .lock()
+            .unwrap()
+            .lru
+            .get(key)
+            .map(|(value, _)| value.clone());
+        let result = if value.is_some() { "hit" } else { "miss" };
+        self.metrics.lookups.with_label_values(&[self.name, result]).inc();
+        value
+    }
+
+    /// `size` is the approximate memory used by the entry, in bytes
+    pub fn insert(&self, key: K, value: V, size: usize) {
+        if size > self.max_bytes {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        if let Some((_, old_size)) = entries.lru.put(key, (value, size)) {
+            entries.bytes -= old_size;
+        }
+        entries.bytes += size;
+        while entries.bytes > self.max_bytes {
+            match entries.lru.pop_lru() {
+                Some((_, (_, evicted_size))) => entries.bytes -= evicted_size,
+                None => break,
+            }
+        }
+        self.update_size(&entries);
+    }
+
+    /// Remove all entries, returning how many were removed
+    pub fn clear(&self) -> usize {
+        let mut entries = self.entries.lock().unwrap();
+        let count = entries.lru.len();
+        entries.lru.clear();
+        entries.bytes = 0;
+        self.update_size(&entries);
+        count
+    }
+
+    fn update_size(&self, entries: &Entries<K, V>) {
+        self.metrics.size.with_label_values(&[self.name]).set(entries.bytes as f64);
+    }
+}
+
+/// An LRU cache of blobs stored as files named after their keys, bounded by their total size in
+/// bytes. Entries left in the directory by previous runs are picked up again, oldest first.
+pub struct DiskLruCache<K: Hash + Eq> {
+    name: &'static str,
+    dir: PathBuf,
+    max_bytes: usize,
+    entries: Mutex<Entries<K, ()>>,
+    metrics: CacheMetrics,
+    tmp_counter: AtomicUsize,
+}
+
+impl<K: Hash + Eq + fmt::Display + FromStr> DiskLruCache<K> {
+    pub fn new(
+        name: &'static str,
+        dir: PathBuf,
+        max_bytes: usize,
+        metrics: &CacheMetrics,
+    ) -> Self {
+        fs::create_dir_all(&dir)
+            .unwrap_or_else(|e| panic!("failed to create {}: {}", dir.display(), e));
+        let mut files: Vec<_> = fs::read_dir(&dir)
+            .unwrap_or_else(|e| panic!("failed to read {}: {}", dir.display(), e))
+            .filter_map(|entry| {
+                let entry = entry.ok()?;
+                let key = match entry.file_name().to_str().and_then(|name| name.parse().ok()) {
+                    Some(key) => key,
+                    None => {
+                        // leftovers of interrupted writes
+                        let _ = fs::remove_file(entry.path());
+                        return None;
+                    }
+                };
+                let metadata = entry.metadata().ok()?;
+                Some((metadata.modified().ok()?, key, metadata.len() as usize))
+            })
+            .collect();
+        files.sort_by_key(|(modified, _, _)| *modified);
+
+        let cache = DiskLruCache {
+            name,
+            dir,
+            max_bytes,
+            entries: Mutex::new(Entries {
+                lru: LruCache::unbounded(),
+                bytes: 0,
+            }),
+            metrics: metrics.clone(),
+            tmp_counter: AtomicUsize::new(0),
+        };
+        {
+            let mut entries = cache.entries.lock().unwrap();
+            for (_, key, size) in files {
+                entries.lru.put(key, ((), size));
+                entries.bytes += size;
+            }
+            cache.evict(&mut entries);
+        }
+        cache
+    }
+
+    fn path(&self, key: &K) -> PathBuf {
+        self.dir.join(key.to_string())
+    }
+
+    pub fn get(&self, key: &K) -> Option<Vec<u8>> {
+        let cached = self.entries.lock().unwrap().lru.get(key).is_some();
+        // the entry may get evicted in the meantime, which makes it a miss
+        let value = if cached {
+            fs::read(self.path(key)).ok()
+        } else {
+            None
+        };
+        let result = if value.is_some() { "hit" } else { "miss" };
+        self.metrics.lookups.with_label_values(&[self.name, result]).inc();
+        value
+    }
+
+    pub fn insert(&self, key: K, value: &[u8]) {
+        if value.len() > self.max_bytes {
+            return;
+        }
+        // write to a temporary file first, so that readers never see partial entries. It is named
+        // uniquely, as several threads may be inserting the same key.
+        let tmp_path = self.dir.join(format!(
+            "{}.{}.tmp",
+            key,
+            self.tmp_counter.fetch_add(1, Ordering::Relaxed)
+        ));
+        let written =
+            fs::write(&tmp_path, value).and_then(|_| fs::rename(&tmp_path, self.path(&key)));
+        if let Err(e) = written {
+            warn!("failed to cache {} entry {}: {}", self.name, key, e);
+            let _ = fs::remove_file(&tmp_path);
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        if let Some((_, old_size)) = entries.lru.put(key, ((), value.len())) {
+            entries.bytes -= old_size;
+        }
+        entries.bytes += value.len();
+        self.evict(&mut entries);
+    }
+
+    fn evict(&self, entries: &mut Entries<K, ()>) {
+        while entries.bytes > self.max_bytes {
+            match entries.lru.pop_lru() {
+                Some((key, (_, evicted_size))) => {
+                    entries.bytes -= evicted_size;
+                    let _ = fs::remove_file(self.path(&key));
+                }
+                None => break,
+            }
+        }
+        self.update_size(entries);
+    }
+
+    /// Remove all entries, returning how many were removed
+    pub fn clear(&self) -> usize {
+        let mut entries = self.entries.lock().unwrap();
+        let count = entries.lru.len();
+        for (key, _) in entries.lru.iter() {
+            let _ = fs::remove_file(self.path(key));
+        }
+        entries.lru.clear();
+        entries.bytes = 0;
+        self.update_size(&entries);
+        count
+    }
+
+    fn update_size(&self, entries: &Entries<K, ()>) {
+        self.metrics.size.with_label_values(&[self.name]).set(entries.bytes as f64);
+    }
+}
+
+struct SwrEntry<V> {
+    value: V,
+    computed_at: Instant,
+    refreshing: bool,
+}
+
+/// A cache of computed values, served as is for `ttl` and then for up to `max_stale` past it
+/// while they get recomputed in the background (stale-while-revalidate). Bounded by the number of
+/// entries.
+pub struct SwrCache<V: Clone> {
+    entries: Arc<Mutex<LruCache<String, SwrEntry<V>>>>,
+    capacity: usize,
+}
+
+impl<V: Clone + Send + 'static> SwrCache<V> {
+    pub fn new(capacity: usize) -> Self {
+        SwrCache {
+            entries: Arc::new(Mutex::new(LruCache::unbounded())),
+            capacity,
+        }
+    }
+
+    /// The value of the key, computed by `compute` unless a fresh enough one is cached. Failures
+    /// of the background refreshes keep the stale value around until it's too old to be served.
+    pub fn get_or_compute<E, F>(
+        &self,
+        key: String,
+        ttl: Duration,
+        max_stale: Duration,
+        compute: F,
+    ) -> Result<V, E>
+    where
+        E: fmt::Debug,
+        F: FnOnce() -> Result<V, E> + Send + 'static,
+    {
+        {
+            let mut entries = self.entries.lock().unwrap();
+            if let Some(entry) = entries.get_mut(&key) {
+                let age = entry.computed_at.elapsed();
+                if age < ttl {
+                    return Ok(entry.value.clone());
+                }
+                if age < ttl + max_stale {
+                    if !entry.refreshing {
+                        entry.refreshing = true;
+                        let entries = Arc::clone(&self.entries);
+                        let capacity = self.capacity;
+                        spawn_thread("swr-refresh", move || {
+                            let result = compute();
+                            if let Err(ref e) = result {
+                                warn!("failed refreshing {}: {:?}", key, e);
+                            }
+                            Self::store(&entries, capacity, key, result.ok());
+                        });
+                    }
+                    return Ok(entry.value.clone());
+                }
+            }
+        }
+        let value = compute()?;
+        Self::store(&self.entries, self.capacity, key, Some(value.clone()));
+        Ok(value)
+    }
+
+    fn store(
+        entries: &Mutex<LruCache<String, SwrEntry<V>>>,
+        capacity: usize,
+        key: String,
+        value: Option<V>,
+    ) {
+        let mut entries = entries.lock().unwrap();
+        match value {
+            Some(value) => {
+                entries.put(
+                    key,
+                    SwrEntry {
+                        value,
+                        computed_at: Instant::now(),
+                        refreshing: false,
+                    },
+                );
+                while entries.len() > capacity {
+                    entries.pop_lru();
+                }
+            }
+            // let the next request after a failed refresh try again
+            None => {
+                if let Some(entry) = entries.peek_mut(&key) {
+                    entry.refreshing = false;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CacheMetrics, DiskLruCache, SizedLruCache};
+    use crate::metrics::Metrics;
+    use std::fs;
+    use std::sync::Arc;
+    use std::thread;
+
+    fn cache_metrics() -> CacheMetrics {
+        CacheMetrics::new(&Metrics::new("127.0.0.1:0".parse().unwrap()))
+    }
+
+    fn cached_bytes<V: Clone>(cache: &SizedLruCache<u32, V>) -> usize {
+        cache.entries.lock().unwrap().bytes
+    }
+
+    #[test]
+    fn test_sized_cache_eviction() {
+        let metrics = cache_metrics();
+        let cache = SizedLruCache::new("test", 10, &metrics);
+
+        cache.insert(1u32, "a", 4);
+        cache.insert(2, "b", 4);
+        assert_eq!(cached_bytes(&cache), 8);
+        assert_eq!(cache.get(&1), Some("a")); // 2 is now the least recently used
+        cache.insert(3, "c", 4);
+        assert_eq!(cache.get(&2), None);
+        assert_eq!(cache.get(&1), Some("a"));
+        assert_eq!(cache.get(&3), Some("c"));
+        assert_eq!(cached_bytes(&cache), 8);
+
+        // evicts as many entries as needed to make room
+        cache.insert(4, "d", 9);
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.get(&3), None);
+        assert_eq!(cache.get(&4), Some("d"));
+        assert_eq!(cached_bytes(&cache), 9);
+
+        // too large to ever fit, leaving the cache untouched
+        cache.insert(5, "e", 11);
+        assert_eq!(cache.get(&5), None);
+        assert_eq!(cache.get(&4), Some("d"));
+    }
+
+    #[test]
+    fn test_sized_cache_size_accounting() {
+        let metrics = cache_metrics();
+        let cache = SizedLruCache::new("test", 10, &metrics);
+
+        cache.insert(1u32, "a", 3);
+        cache.insert(2, "b", 3);
+        // overwriting an entry replaces its size rather than adding to it
+        cache.insert(1, "A", 5);
+        assert_eq!(cached_bytes(&cache), 8);
+        assert_eq!(cache.get(&1), Some("A"));
+        assert_eq!(cache.get(&2), Some("b"));
+        cache.insert(2, "B", 1);
+        assert_eq!(cached_bytes(&cache), 6);
+
+        // exactly at the limit
+        cache.insert(3, "c", 4);
+        assert_eq!(cached_bytes(&cache), 10);
+        assert_eq!(cache.get(&1), Some("A"));
+
+        assert_eq!(cache.clear(), 3);
+        assert_eq!(cached_bytes(&cache), 0);
+        assert_eq!(cache.get(&1), None);
+    }
+
+    #[test]
+    fn test_sized_cache_disabled() {
+        let metrics = cache_metrics();
+        let cache = SizedLruCache::new("test", 0, &metrics);
+        cache.insert(1u32, "a", 0);
+        assert_eq!(cache.get(&1), None);
+    }
+
+    fn file_names(cache: &DiskLruCache<u32>) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(&cache.dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn test_disk_cache_eviction() {
+        let dir = tempfile::tempdir().unwrap();
+        let metrics = cache_metrics();
+        let cache = DiskLruCache::new("test", dir.path().to_path_buf(), 10, &metrics);
+
+        cache.insert(1u32, &[1; 4]);
+        cache.insert(2, &[2; 4]);
+        assert_eq!(cache.get(&1), Some(vec![1; 4])); // 2 is now the least recently used
+        cache.insert(3, &[3; 4]);
+        assert_eq!(cache.get(&2), None);
+        assert_eq!(cache.get(&3), Some(vec![3; 4]));
+        assert_eq!(file_names(&cache), vec!["1", "3"]);
+
+        // too large to ever fit
+        cache.insert(4, &[4; 11]);
+        assert_eq!(cache.get(&4), None);
+
+        // overwriting an entry accounts for its new size only
+        cache.insert(1, &[1; 6]);
+        assert_eq!(cache.get(&1), Some(vec![1; 6]));
+        assert_eq!(cache.get(&3), Some(vec![3; 4]));
+
+        assert_eq!(cache.clear(), 2);
+        assert_eq!(cache.get(&1), None);
+        assert!(file_names(&cache).is_empty());
+    }
+
+    #[test]
+    fn test_disk_cache_reload() {
+        let dir = tempfile::tempdir().unwrap();
+        let metrics = cache_metrics();
+        {
+            let cache = DiskLruCache::new("test", dir.path().to_path_buf(), 10, &metrics);
+            cache.insert(1u32, &[1; 4]);
+            cache.insert(2, &[2; 4]);
+        }
+        // leftovers of an interrupted write
+        fs::write(dir.path().join("3.0.tmp"), [3; 4]).unwrap();
+
+        let cache = DiskLruCache::new("test", dir.path().to_path_buf(), 10, &metrics);
+        assert_eq!(file_names(&cache), vec!["1", "2"]);
+        assert_eq!(cache.get(&1), Some(vec![1; 4]));
+        assert_eq!(cache.get(&2), Some(vec![2; 4]));
+
+        // shrinking the cache evicts the entries over the new limit
+        drop(cache);
+        let cache = DiskLruCache::new("test", dir.path().to_path_buf(), 4, &metrics);
+        assert_eq!(file_names(&cache).len(), 1);
+    }
+
+    #[test]
+    fn test_disk_cache_concurrent_inserts() {
+        let dir = tempfile::tempdir().unwrap();
+        let metrics = cache_metrics();
+        let cache = Arc::new(DiskLruCache::new(
+            "test",
+            dir.path().to_path_buf(),
+            1 << 20,
+            &metrics,
+        ));
+
+        let threads: Vec<_> = (0..8u8)
+            .map(|i| {
+                let cache = Arc::clone(&cache);
+                thread::spawn(move || {
+                    for _ in 0..50 {
+                        cache.insert(1u32, &[i; 1000]);
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        // the entry is one of the inserted values in full, with no temporary file left behind
+        let value = cache.get(&1).unwrap();
+        assert_eq!(value.len(), 1000);
+        assert!(value.iter().all(|byte| *byte == value[0]));
+        assert_eq!(file_names(&cache), vec!["1"]);
+    }
+}
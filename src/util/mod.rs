@@ -1,24 +1,56 @@
+mod audit_log;
 mod block;
+#[cfg(not(feature = "liquid"))]
+mod consolidation;
+mod http;
+mod lru_cache;
+mod request_budget;
 mod script;
+mod slow_query;
 mod transaction;
 
 pub mod bincode;
 pub mod electrum_merkle;
 pub mod fees;
+pub mod i18n;
+pub mod sd_notify;
 
+pub use self::audit_log::{AuditEvent, AuditLog};
 pub use self::block::{
-    BlockHeaderMeta, BlockId, BlockMeta, BlockStatus, HeaderEntry, HeaderList, DEFAULT_BLOCKHASH,
+    median, BlockHeaderMeta, BlockId, BlockMeta, BlockStats, BlockStatus, HeaderEntry, HeaderList,
+    TipNotifier, DEFAULT_BLOCKHASH,
+};
+#[cfg(not(feature = "liquid"))]
+pub use self::block::{block_multisig_counts, MultisigCount};
+#[cfg(not(feature = "liquid"))]
+pub use self::consolidation::{
+    plan_consolidation, ConsolidationInput, ConsolidationOrder, ConsolidationPlan, SpendShape,
 };
 pub use self::fees::get_tx_fee;
-pub use self::script::{get_innerscripts, ScriptToAddr, ScriptToAsm};
+pub use self::http::http_get;
+pub use self::i18n::Locale;
+pub use self::lru_cache::{CacheMetrics, DiskLruCache, SizedLruCache, SwrCache};
+pub use self::request_budget::{BudgetClient, RequestBudget};
+pub use self::script::{get_innerscripts, InnerScripts, ScriptToAddr, ScriptToAsm};
+#[cfg(not(feature = "liquid"))]
+pub use self::script::{
+    count_sigops, estimate_tx_vsize, inner_multisig, output_vsize, parse_multisig,
+    script_timelocks, spend_path, spending_vsize, spends_with_witness, MultisigType,
+    ScriptTemplate, ScriptTimelock, SpendPath,
+};
+pub use self::slow_query::{SlowQuery, SlowQueryLog};
 pub use self::transaction::{
-    extract_tx_prevouts, has_prevout, is_coinbase, is_spendable, serialize_outpoint,
+    absolute_timelock, decode_locktime, decode_sequence, decode_tx_outputs, extract_tx_prevouts,
+    has_prevout, is_coinbase, is_spendable, relative_timelock, serialize_outpoint, Timelock,
     TransactionStatus, TxInput,
 };
+#[cfg(not(feature = "liquid"))]
+pub use self::transaction::{policy_violations, tx_sigops, SigopCounts};
 
 use std::collections::HashMap;
 use std::sync::mpsc::{channel, sync_channel, Receiver, Sender, SyncSender};
 use std::thread;
+use std::time::{Duration, Instant};
 
 use crate::chain::BlockHeader;
 use bitcoin::hashes::sha256d::Hash as Sha256dHash;
@@ -97,6 +129,21 @@ where
         .unwrap()
 }
 
+// Join the thread, unless it doesn't finish within the timeout
+pub fn join_timeout<T>(
+    handle: thread::JoinHandle<T>,
+    timeout: Duration,
+) -> Option<thread::Result<T>> {
+    let deadline = Instant::now() + timeout;
+    while !handle.is_finished() {
+        if Instant::now() >= deadline {
+            return None;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    Some(handle.join())
+}
+
 // Similar to https://doc.rust-lang.org/std/primitive.bool.html#method.then (nightly only),
 // but with a function that returns an `Option<T>` instead of `T`. Adding something like
 // this to std is being discussed: https://github.com/rust-lang/rust/issues/64260
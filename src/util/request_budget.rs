@@ -0,0 +1,178 @@
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::config::Config;
+
+const BUDGET_WINDOW: Duration = Duration::from_secs(60);
+const MAX_TRACKED_CLIENTS: usize = 100_000; // past which the expired windows get pruned
+
+/// Who a request gets charged to: the API key it was made with, or the IP it came from
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum BudgetClient {
+    Key(String),
+    Ip(IpAddr),
+}
+
+struct Window {
+    start: Instant,
+    spent: u64,
+}
+
+/// Per-minute budgets of DB work (as scored by `ReadStats::cost`), per IP and per API key.
+/// Requests are served as long as some budget is left, and charged with their cost once served.
+pub struct RequestBudget {
+    ip_budget: Option<u64>,
+    key_budgets: BTreeMap<String, u64>,
+    windows: Mutex<HashMap<BudgetClient, Window>>,
+}
+
+impl RequestBudget {
+    /// Returns None when no budget is configured
+    pub fn new(config: &Config) -> Option<Self> {
+        if config.request_budget.is_none() && config.request_budget_keys.is_empty() {
+            return None;
+        }
+        Some(RequestBudget {
+            ip_budget: config.request_budget,
+            key_budgets: config.request_budget_keys.clone(),
+            windows: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// The client to charge, by its API key if it's a configured one, or by its IP. None for
+    /// the requests that aren't subject to any budget.
+    pub fn client(&self, api_key: Option<&str>, ip: Option<IpAddr>) -> Option<BudgetClient> {
+        match api_key {
+            Some(key) if self.key_budgets.contains_key(key) => {
+                Some(BudgetClient::Key(key.to_string()))
+            }
+            _ => ip
+                .filter(|_| self.ip_budget.is_some())
+                .map(BudgetClient::Ip),
+        }
+    }
+
+    fn budget(&self, client: &BudgetClient) -> u64 {
+        match client {
+            BudgetClient::Key(key) => self.key_budgets[key],
+            BudgetClient::Ip(_) => self.ip_budget.unwrap(),
+        }
+    }
+
+    /// Err with the time until the budget gets renewed if the client has spent all of it
+    pub fn check(&self, client: &BudgetClient) -> Result<(), Duration> {
+        self.check_at(client, Instant::now())
+    }
+
+    fn check_at(&self, client: &BudgetClient, now: Instant) -> Result<(), Duration> {
+        let windows = self.windows.lock().unwrap();
+        match windows.get(client) {
+            Some(window) if window.spent >= self.budget(client) => {
+                // the window may have expired while checking it
+                match BUDGET_WINDOW.checked_sub(now.saturating_duration_since(window.start)) {
+                    Some(left) if left > Duration::ZERO => Err(left),
+                    _ => Ok(()),
+                }
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn charge(&self, client: BudgetClient, cost: u64) {
+        self.charge_at(client, cost, Instant::now())
+    }
+
+    fn charge_at(&self, client: BudgetClient, cost: u64, now: Instant) {
+        let expired =
+            |window: &Window| now.saturating_duration_since(window.start) >= BUDGET_WINDOW;
+        let mut windows = self.windows.lock().unwrap();
+        if windows.len() >= MAX_TRACKED_CLIENTS {
+            windows.retain(|_, window| !expired(window));
+        }
+        let window = windows.entry(client).or_insert(Window {
+            start: now,
+            spent: 0,
+        });
+        if expired(window) {
+            *window = Window {
+                start: now,
+                spent: 0,
+            };
+        }
+        window.spent += cost;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BudgetClient, RequestBudget, BUDGET_WINDOW, MAX_TRACKED_CLIENTS};
+    use std::collections::{BTreeMap, HashMap};
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::sync::Mutex;
+    use std::time::{Duration, Instant};
+
+    fn budget(ip_budget: u64) -> RequestBudget {
+        RequestBudget {
+            ip_budget: Some(ip_budget),
+            key_budgets: BTreeMap::new(),
+            windows: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn ip(n: u32) -> BudgetClient {
+        BudgetClient::Ip(IpAddr::V4(Ipv4Addr::from(n)))
+    }
+
+    #[test]
+    fn test_window_rollover() {
+        let budget = budget(10);
+        let start = Instant::now();
+        assert_eq!(budget.check_at(&ip(1), start), Ok(()));
+
+        budget.charge_at(ip(1), 10, start);
+        let second = Duration::from_secs(1);
+        assert_eq!(
+            budget.check_at(&ip(1), start + second),
+            Err(BUDGET_WINDOW - second)
+        );
+        // other clients have their own budget
+        assert_eq!(budget.check_at(&ip(2), start + second), Ok(()));
+
+        // renewed once the window expires, even when checked past it
+        assert_eq!(budget.check_at(&ip(1), start + BUDGET_WINDOW), Ok(()));
+        assert_eq!(budget.check_at(&ip(1), start + BUDGET_WINDOW * 2), Ok(()));
+
+        // a charge past the window starts a new one
+        budget.charge_at(ip(1), 4, start + BUDGET_WINDOW);
+        assert_eq!(budget.check_at(&ip(1), start + BUDGET_WINDOW), Ok(()));
+        budget.charge_at(ip(1), 6, start + BUDGET_WINDOW + second);
+        assert_eq!(
+            budget.check_at(&ip(1), start + BUDGET_WINDOW + second),
+            Err(BUDGET_WINDOW - second)
+        );
+
+        // a window started after the time it's checked at (by a concurrent request)
+        budget.charge_at(ip(3), 10, start + second);
+        assert_eq!(budget.check_at(&ip(3), start), Err(BUDGET_WINDOW));
+    }
+
+    #[test]
+    fn test_pruning() {
+        let budget = budget(10);
+        let start = Instant::now();
+        for n in 0..MAX_TRACKED_CLIENTS as u32 - 1 {
+            budget.charge_at(ip(n), 10, start);
+        }
+        budget.charge_at(ip(u32::MAX), 10, start + BUDGET_WINDOW / 2);
+        assert_eq!(budget.windows.lock().unwrap().len(), MAX_TRACKED_CLIENTS);
+
+        // the expired windows get pruned once too many clients are tracked, the others are kept
+        budget.charge_at(ip(u32::MAX - 1), 1, start + BUDGET_WINDOW);
+        let windows = budget.windows.lock().unwrap();
+        assert_eq!(windows.len(), 2);
+        assert_eq!(windows[&ip(u32::MAX)].spent, 10);
+        assert_eq!(windows[&ip(u32::MAX - 1)].spent, 1);
+    }
+}
@@ -4,7 +4,15 @@ use crate::elements::ebcompact::*;
 use elements::address as elements_address;
 
 use crate::chain::{script, Network, Script, TxIn, TxOut};
+#[cfg(not(feature = "liquid"))]
+use crate::errors::{Error, ResultExt};
+#[cfg(not(feature = "liquid"))]
+use crate::util::{decode_locktime, decode_sequence, Timelock};
 use script::Instruction::PushBytes;
+#[cfg(not(feature = "liquid"))]
+use bitcoin::opcodes::{all as opcodes, Opcode};
+#[cfg(not(feature = "liquid"))]
+use script::Instruction::{self, Op};
 
 pub struct InnerScripts {
     pub redeem_script: Option<Script>,
@@ -79,3 +87,543 @@ pub fn get_innerscripts(txin: &TxIn, prevout: &TxOut) -> InnerScripts {
         witness_script,
     }
 }
+
+// Estimated virtual size of an input spending an output locked by this script, assuming
+// the usual single-key spending path (p2sh is assumed to wrap a p2wpkh)
+#[cfg(not(feature = "liquid"))]
+pub fn spending_vsize(script: &bitcoin::Script) -> u64 {
+    if script.is_p2wpkh() {
+        68
+    } else if script.is_p2tr() {
+        58
+    } else if script.is_p2sh() {
+        91
+    } else if script.is_p2wsh() {
+        104
+    } else if script.is_p2pk() {
+        114
+    } else {
+        148
+    }
+}
+
+// Whether spending an output locked by this script needs a witness, with the same assumptions
+// as `spending_vsize`
+#[cfg(not(feature = "liquid"))]
+pub fn spends_with_witness(script: &bitcoin::Script) -> bool {
+    script.is_p2wpkh() || script.is_p2tr() || script.is_p2sh() || script.is_p2wsh()
+}
+
+// Virtual size of an output locked by this script
+#[cfg(not(feature = "liquid"))]
+pub fn output_vsize(script: &bitcoin::Script) -> u64 {
+    8 + varint_len(script.len() as u64) + script.len() as u64
+}
+
+// Estimated virtual size of a transaction, given the total virtual sizes of its inputs (as
+// estimated by `spending_vsize`) and of its outputs
+#[cfg(not(feature = "liquid"))]
+pub fn estimate_tx_vsize(
+    input_count: usize,
+    inputs_vsize: u64,
+    output_count: usize,
+    outputs_vsize: u64,
+    segwit: bool,
+) -> u64 {
+    // the version and locktime, the counts of inputs and outputs, and the segwit marker and
+    // flag (half a vbyte, rounded up)
+    let overhead =
+        8 + varint_len(input_count as u64) + varint_len(output_count as u64) + segwit as u64;
+    overhead + inputs_vsize + outputs_vsize
+}
+
+#[cfg(not(feature = "liquid"))]
+fn varint_len(n: u64) -> u64 {
+    match n {
+        0..=0xfc => 1,
+        0xfd..=0xffff => 3,
+        0x10000..=0xffff_ffff => 5,
+        _ => 9,
+    }
+}
+
+// A timelock enforced by a script with OP_CHECKLOCKTIMEVERIFY or OP_CHECKSEQUENCEVERIFY
+#[cfg(not(feature = "liquid"))]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScriptTimelock {
+    Absolute(Timelock),
+    Relative(Timelock),
+}
+
+// The timelocks the script checks for, in the usual `<n> OP_CLTV`/`<n> OP_CSV` form
+#[cfg(not(feature = "liquid"))]
+pub fn script_timelocks(script: &bitcoin::Script) -> Vec<ScriptTimelock> {
+    let as_u32 = |n: i64| Some(n as u32).filter(|_| (0..=u32::MAX as i64).contains(&n));
+    let mut timelocks = vec![];
+    let mut last_num: Option<i64> = None;
+    for instruction in script.instructions() {
+        let num = match instruction {
+            Ok(PushBytes(bytes)) => read_scriptnum(bytes.as_bytes()),
+            Ok(Op(op)) if op == opcodes::OP_CLTV => {
+                if let Some(locktime) = last_num.and_then(as_u32) {
+                    timelocks.push(ScriptTimelock::Absolute(decode_locktime(locktime)));
+                }
+                None
+            }
+            Ok(Op(op)) if op == opcodes::OP_CSV => {
+                if let Some(sequence) = last_num.and_then(as_u32) {
+                    if let Some(timelock) = decode_sequence(sequence) {
+                        timelocks.push(ScriptTimelock::Relative(timelock));
+                    }
+                }
+                None
+            }
+            Ok(instruction) => read_pushnum(&instruction).map(i64::from),
+            Err(_) => break,
+        };
+        last_num = num;
+    }
+    timelocks
+}
+
+// The signature operations of the script, as bitcoind counts them. Inaccurate counting (used for
+// scriptSigs and scriptPubKeys) assumes the maximum of 20 keys for every OP_CHECKMULTISIG, while
+// accurate counting (used for redeem and witness scripts) reads the key count pushed before it.
+#[cfg(not(feature = "liquid"))]
+pub fn count_sigops(script: &bitcoin::Script, accurate: bool) -> usize {
+    let mut count = 0;
+    let mut last_instruction = None;
+    for instruction in script.instructions() {
+        let instruction = match instruction {
+            Ok(instruction) => instruction,
+            Err(_) => break,
+        };
+        match instruction {
+            Op(op) if op == opcodes::OP_CHECKSIG || op == opcodes::OP_CHECKSIGVERIFY => count += 1,
+            Op(op) if op == opcodes::OP_CHECKMULTISIG || op == opcodes::OP_CHECKMULTISIGVERIFY => {
+                count += match last_instruction.as_ref().and_then(read_pushnum) {
+                    Some(n) if accurate => n as usize,
+                    _ => 20,
+                }
+            }
+            _ => (),
+        }
+        last_instruction = Some(instruction);
+    }
+    count
+}
+
+// The number pushed by OP_1 to OP_16
+#[cfg(not(feature = "liquid"))]
+fn read_pushnum(instruction: &Instruction) -> Option<u8> {
+    let (pushnum_1, pushnum_16) = (opcodes::OP_PUSHNUM_1.to_u8(), opcodes::OP_PUSHNUM_16.to_u8());
+    match instruction {
+        Op(op) if (pushnum_1..=pushnum_16).contains(&op.to_u8()) => {
+            Some(op.to_u8() - pushnum_1 + 1)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(not(feature = "liquid"))]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "kebab-case")]
+pub enum MultisigType {
+    Bare,
+    P2sh,
+    P2wsh,
+    P2shP2wsh,
+}
+
+// The m and n of an `m <pubkey>... n OP_CHECKMULTISIG` script
+#[cfg(not(feature = "liquid"))]
+pub fn parse_multisig(script: &bitcoin::Script) -> Option<(u8, u8)> {
+    let instructions = script.instructions().collect::<Result<Vec<_>, _>>().ok()?;
+    let (m, rest) = instructions.split_first()?;
+    let (checkmultisig, rest) = rest.split_last()?;
+    let (n, pubkeys) = rest.split_last()?;
+    match checkmultisig {
+        Op(op) if *op == opcodes::OP_CHECKMULTISIG => (),
+        _ => return None,
+    }
+    let (m, n) = (read_pushnum(m)?, read_pushnum(n)?);
+    let is_pubkey = |instruction: &Instruction| match instruction {
+        PushBytes(bytes) => [33, 65].contains(&bytes.as_bytes().len()),
+        _ => false,
+    };
+    if m > n || pubkeys.len() != n as usize || !pubkeys.iter().all(is_pubkey) {
+        return None;
+    }
+    Some((m, n))
+}
+
+// The multisig behind the inner scripts revealed by a P2SH/P2WSH spend, if any
+#[cfg(not(feature = "liquid"))]
+pub fn inner_multisig(innerscripts: &InnerScripts) -> Option<(MultisigType, u8, u8)> {
+    match (&innerscripts.redeem_script, &innerscripts.witness_script) {
+        (redeem_script, Some(witness_script)) => {
+            let (m, n) = parse_multisig(witness_script)?;
+            match redeem_script {
+                Some(_) => Some((MultisigType::P2shP2wsh, m, n)),
+                None => Some((MultisigType::P2wsh, m, n)),
+            }
+        }
+        (Some(redeem_script), None) => {
+            let (m, n) = parse_multisig(redeem_script)?;
+            Some((MultisigType::P2sh, m, n))
+        }
+        (None, None) => None,
+    }
+}
+
+// The path a spend took through the script of its prevout, for the scripts offering a choice
+#[cfg(not(feature = "liquid"))]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum SpendPath {
+    // Taproot spends, by the key or by the script of the leaf with this hash
+    KeyPath,
+    ScriptPath([u8; 32]),
+    // P2WSH spends of scripts starting with OP_IF/OP_NOTIF, by the branch that got executed
+    IfBranch,
+    ElseBranch,
+    // Other P2WSH spends, whose branch couldn't be told from the witness
+    WitnessScript,
+}
+
+// The spend path of the input, for Taproot and (possibly P2SH-wrapped) P2WSH prevouts
+#[cfg(not(feature = "liquid"))]
+pub fn spend_path(txin: &TxIn, prevout: &TxOut) -> Option<SpendPath> {
+    use bitcoin::hashes::Hash;
+    use bitcoin::taproot::{LeafVersion, TapLeafHash, TAPROOT_ANNEX_PREFIX};
+
+    let mut witness: Vec<&[u8]> = txin.witness.iter().collect();
+    if prevout.script_pubkey.is_p2tr() {
+        if witness.len() >= 2 && witness.last()?.first() == Some(&TAPROOT_ANNEX_PREFIX) {
+            witness.pop();
+        }
+        let control_block = witness.pop()?;
+        let script = match witness.pop() {
+            Some(script) => bitcoin::Script::from_bytes(script),
+            None => return Some(SpendPath::KeyPath),
+        };
+        let version = LeafVersion::from_consensus(control_block.first()? & 0xfe).ok()?;
+        let leaf_hash = TapLeafHash::from_script(script, version);
+        return Some(SpendPath::ScriptPath(leaf_hash.to_byte_array()));
+    }
+
+    let witness_script = get_innerscripts(txin, prevout).witness_script?;
+    let negate = match witness_script.instructions().next() {
+        Some(Ok(Op(op))) if op == opcodes::OP_IF => false,
+        Some(Ok(Op(op))) if op == opcodes::OP_NOTIF => true,
+        _ => return Some(SpendPath::WitnessScript),
+    };
+    // the branch selector is the witness item right below the witness script, which the
+    // MINIMALIF policy restricts to either 0x01 or empty
+    witness.pop();
+    let branch = match witness.pop() {
+        Some([1]) => true,
+        Some([]) => false,
+        _ => return Some(SpendPath::WitnessScript),
+    };
+    Some(if branch != negate {
+        SpendPath::IfBranch
+    } else {
+        SpendPath::ElseBranch
+    })
+}
+
+#[cfg(not(feature = "liquid"))]
+const MAX_TEMPLATE_NAME_LEN: usize = 64;
+
+#[cfg(not(feature = "liquid"))]
+#[derive(Debug, Clone, PartialEq)]
+enum TemplateToken {
+    Op(Opcode),
+    Push(Option<usize>), // of that many bytes, or any push
+    Any,                 // any single instruction
+    Span,                // any number of instructions
+}
+
+#[cfg(not(feature = "liquid"))]
+impl TemplateToken {
+    fn parse(token: &str) -> Result<Self, Error> {
+        // the small numbers are also known by the OP_1 to OP_16 names bitcoind gives them
+        let small_number = token
+            .strip_prefix("OP_")
+            .and_then(|n| n.parse::<u8>().ok())
+            .filter(|n| (1..=16).contains(n));
+        if let Some(n) = small_number {
+            let op = Opcode::from(opcodes::OP_PUSHNUM_1.to_u8() + n - 1);
+            return Ok(TemplateToken::Op(op));
+        }
+        Ok(match token {
+            "*" => TemplateToken::Any,
+            "..." => TemplateToken::Span,
+            "<*>" => TemplateToken::Push(None),
+            "OP_0" | "OP_FALSE" => TemplateToken::Push(Some(0)),
+            "OP_TRUE" => TemplateToken::Op(opcodes::OP_PUSHNUM_1),
+            _ if token.starts_with('<') && token.ends_with('>') => {
+                let len = token[1..token.len() - 1]
+                    .parse()
+                    .chain_err(|| format!("invalid push size in {}", token))?;
+                TemplateToken::Push(Some(len))
+            }
+            _ => {
+                let op = (0..=u8::MAX)
+                    .map(Opcode::from)
+                    .find(|op| op.to_string() == token)
+                    .chain_err(|| format!("unknown opcode {}", token))?;
+                // pushes are read as data, whatever opcode encodes them
+                if op.to_u8() <= opcodes::OP_PUSHDATA4.to_u8() {
+                    bail!("pushes are matched with <size> or <*>, not {}", token);
+                }
+                TemplateToken::Op(op)
+            }
+        })
+    }
+
+    fn matches(&self, instruction: &Instruction) -> bool {
+        match (self, instruction) {
+            (TemplateToken::Op(op), Op(other)) => op == other,
+            (TemplateToken::Push(None), PushBytes(_)) => true,
+            (TemplateToken::Push(Some(len)), PushBytes(bytes)) => bytes.len() == *len,
+            (TemplateToken::Any, _) => true,
+            _ => false,
+        }
+    }
+}
+
+// A pattern over the instructions of scripts, in the asm form with wildcards: `<20>` matches a push
+// of 20 bytes, `<*>` any push, `*` any single instruction and `...` any number of instructions.
+// Patterns get compiled into the runs of single-instruction tokens between their `...`.
+#[cfg(not(feature = "liquid"))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScriptTemplate {
+    pub name: String,
+    pub pattern: String,
+    runs: Vec<Vec<TemplateToken>>,
+}
+
+#[cfg(not(feature = "liquid"))]
+impl ScriptTemplate {
+    pub fn parse(name: &str, pattern: &str) -> Result<Self, Error> {
+        let valid_char = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+        if name.is_empty() || name.len() > MAX_TEMPLATE_NAME_LEN || !name.chars().all(valid_char) {
+            bail!("invalid template name {:?}", name);
+        }
+        let tokens = pattern
+            .split_whitespace()
+            .map(TemplateToken::parse)
+            .collect::<Result<Vec<_>, _>>()?;
+        if tokens.is_empty() {
+            bail!("empty template pattern");
+        }
+        Ok(ScriptTemplate {
+            name: name.to_string(),
+            pattern: pattern.split_whitespace().collect::<Vec<_>>().join(" "),
+            runs: tokens
+                .split(|token| *token == TemplateToken::Span)
+                .map(<[_]>::to_vec)
+                .collect(),
+        })
+    }
+
+    pub fn matches(&self, script: &bitcoin::Script) -> bool {
+        match script.instructions().collect::<Result<Vec<_>, _>>() {
+            Ok(instructions) => match_runs(&self.runs, &instructions),
+            Err(_) => false,
+        }
+    }
+}
+
+// The first run is anchored at the start of the script and the last one at its end. The runs in
+// between are matched at their leftmost position: as their tokens match single instructions,
+// matching them any later can't let the following runs match where they wouldn't. The script
+// is matched in a single pass this way, without backtracking.
+#[cfg(not(feature = "liquid"))]
+fn match_runs(runs: &[Vec<TemplateToken>], instructions: &[Instruction]) -> bool {
+    let run_matches = |run: &[TemplateToken], instructions: &[Instruction]| {
+        run.iter()
+            .zip(instructions)
+            .all(|(token, instruction)| token.matches(instruction))
+    };
+    let (first, rest) = match runs.split_first() {
+        Some(split) => split,
+        None => return instructions.is_empty(),
+    };
+    let (last, middle) = match rest.split_last() {
+        Some(split) => split,
+        None => return first.len() == instructions.len() && run_matches(first, instructions),
+    };
+    if first.len() + last.len() > instructions.len() {
+        return false;
+    }
+    let (head, tail) = instructions.split_at(first.len());
+    let (mut body, end) = tail.split_at(tail.len() - last.len());
+    if !run_matches(first, head) || !run_matches(last, end) {
+        return false;
+    }
+    for run in middle {
+        let last_start = match body.len().checked_sub(run.len()) {
+            Some(last_start) => last_start,
+            None => return false,
+        };
+        match (0..=last_start).find(|start| run_matches(run, &body[*start..])) {
+            Some(start) => body = &body[start + run.len()..],
+            None => return false,
+        }
+    }
+    true
+}
+
+// Decode a minimally encoded script number, allowing up to 5 bytes like OP_CLTV/OP_CSV do
+#[cfg(not(feature = "liquid"))]
+fn read_scriptnum(bytes: &[u8]) -> Option<i64> {
+    if bytes.len() > 5 {
+        return None;
+    }
+    let mut num = bytes
+        .iter()
+        .enumerate()
+        .fold(0i64, |num, (i, byte)| num | ((*byte as i64) << (8 * i)));
+    if let Some(last) = bytes.last() {
+        if last & 0x80 != 0 {
+            num = -(num & !(0x80i64 << (8 * (bytes.len() - 1))));
+        }
+    }
+    Some(num)
+}
+
+#[cfg(all(test, not(feature = "liquid")))]
+mod tests {
+    use super::{
+        count_sigops, estimate_tx_vsize, output_vsize, parse_multisig, script_timelocks,
+        spending_vsize, ScriptTemplate, ScriptTimelock,
+    };
+    use crate::util::Timelock;
+    use bitcoin::opcodes::all::{
+        OP_CHECKMULTISIG, OP_CHECKSIG, OP_CHECKSIGVERIFY, OP_CLTV, OP_CSV, OP_DROP,
+    };
+    use bitcoin::script::Builder;
+
+    #[test]
+    fn test_script_timelocks() {
+        let script = Builder::new()
+            .push_int(700_000)
+            .push_opcode(OP_CLTV)
+            .push_opcode(OP_DROP)
+            .push_int(16)
+            .push_opcode(OP_CSV)
+            .push_opcode(OP_DROP)
+            .push_int((1 << 22) | 10)
+            .push_opcode(OP_CSV)
+            .push_opcode(OP_DROP)
+            .push_int(1_700_000_000)
+            .push_opcode(OP_CLTV)
+            .push_opcode(OP_DROP)
+            .push_opcode(OP_CHECKSIG)
+            .into_script();
+        assert_eq!(
+            script_timelocks(&script),
+            vec![
+                ScriptTimelock::Absolute(Timelock::Height(700_000)),
+                ScriptTimelock::Relative(Timelock::Height(16)),
+                ScriptTimelock::Relative(Timelock::Time(5120)),
+                ScriptTimelock::Absolute(Timelock::Time(1_700_000_000)),
+            ]
+        );
+
+        // relative timelocks with the disable flag are not enforced
+        let script = Builder::new()
+            .push_int(1 << 31)
+            .push_opcode(OP_CSV)
+            .into_script();
+        assert!(script_timelocks(&script).is_empty());
+    }
+
+    #[test]
+    fn test_parse_multisig() {
+        let pubkey = [0x02; 33];
+        let script = Builder::new()
+            .push_int(2)
+            .push_slice(pubkey)
+            .push_slice(pubkey)
+            .push_slice(pubkey)
+            .push_int(3)
+            .push_opcode(OP_CHECKMULTISIG)
+            .into_script();
+        assert_eq!(parse_multisig(&script), Some((2, 3)));
+
+        // n must match the number of pubkeys
+        let script = Builder::new()
+            .push_int(1)
+            .push_slice(pubkey)
+            .push_int(2)
+            .push_opcode(OP_CHECKMULTISIG)
+            .into_script();
+        assert_eq!(parse_multisig(&script), None);
+    }
+
+    #[test]
+    fn test_script_template() {
+        let pubkey = [0x02; 33];
+        let script = Builder::new()
+            .push_int(1)
+            .push_slice(pubkey)
+            .push_slice(pubkey)
+            .push_int(2)
+            .push_opcode(OP_CHECKMULTISIG)
+            .into_script();
+        let template = |pattern| ScriptTemplate::parse("test", pattern).unwrap();
+        assert!(template("OP_1 <33> <33> OP_PUSHNUM_2 OP_CHECKMULTISIG").matches(&script));
+        assert!(template("OP_TRUE ... OP_2 OP_CHECKMULTISIG").matches(&script));
+        assert!(template("... <33> * ... OP_CHECKMULTISIG").matches(&script));
+        assert!(template("... <33> ... <33> ...").matches(&script));
+        assert!(!template("OP_1 <33> OP_2 OP_CHECKMULTISIG").matches(&script));
+        assert!(!template("... <20> ...").matches(&script));
+        assert!(!template("... <33> ... <33> ... <33> ...").matches(&script));
+        // the instructions matched before and after a `...` can't overlap
+        assert!(!template("OP_1 <33> ... <33> <33> OP_2 OP_CHECKMULTISIG").matches(&script));
+
+        assert!(ScriptTemplate::parse("test", "OP_17").is_err());
+    }
+
+    #[test]
+    fn test_count_sigops() {
+        let pubkey = [0x02; 33];
+        let script = Builder::new()
+            .push_slice(pubkey)
+            .push_opcode(OP_CHECKSIGVERIFY)
+            .push_int(1)
+            .push_slice(pubkey)
+            .push_slice(pubkey)
+            .push_int(2)
+            .push_opcode(OP_CHECKMULTISIG)
+            .into_script();
+        assert_eq!(count_sigops(&script, true), 3);
+        assert_eq!(count_sigops(&script, false), 21);
+
+        // without a preceding key count, multisig counts for 20 keys either way
+        let script = Builder::new()
+            .push_opcode(OP_CHECKMULTISIG)
+            .push_opcode(OP_CHECKSIG)
+            .into_script();
+        assert_eq!(count_sigops(&script, true), 21);
+    }
+
+    #[test]
+    fn test_estimate_tx_vsize() {
+        let script = Builder::new()
+            .push_int(0)
+            .push_slice([0x01; 20])
+            .into_script();
+        assert!(script.is_p2wpkh());
+        assert_eq!(output_vsize(&script), 31);
+        // a p2wpkh spend to a single p2wpkh output weighs 438 WU, or 109.5 vbytes
+        let vsize = estimate_tx_vsize(1, spending_vsize(&script), 1, output_vsize(&script), true);
+        assert_eq!(vsize, 110);
+        // the counts take three bytes past 252 inputs
+        let vsize = estimate_tx_vsize(253, 253 * 68, 1, 31, true);
+        assert_eq!(vsize, 13 + 253 * 68 + 31);
+    }
+}
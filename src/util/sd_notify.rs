@@ -0,0 +1,88 @@
+// Notifications to systemd about the service state (see sd_notify(3)), for services running with
+// `Type=notify` and optionally `WatchdogSec=`. These are no-ops when not supervised by systemd.
+use std::env;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use crate::util::spawn_thread;
+
+// how often the startup timeout gets extended while starting up
+const STARTUP_EXTEND_INTERVAL: Duration = Duration::from_secs(30);
+
+static READY: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+fn notify(state: &str) {
+    use std::os::unix::net::UnixDatagram;
+
+    let path = match env::var_os("NOTIFY_SOCKET") {
+        Some(path) => path,
+        None => return,
+    };
+    let sent = UnixDatagram::unbound().and_then(|socket| {
+        match path.to_str().and_then(|path| path.strip_prefix('@')) {
+            // an abstract socket
+            #[cfg(target_os = "linux")]
+            Some(name) => {
+                use std::os::linux::net::SocketAddrExt;
+                std::os::unix::net::SocketAddr::from_abstract_name(name)
+                    .and_then(|addr| socket.send_to_addr(state.as_bytes(), &addr))
+            }
+            _ => socket.send_to(state.as_bytes(), &path),
+        }
+    });
+    if let Err(e) = sent {
+        warn!("failed notifying systemd of {:?}: {}", state, e);
+    }
+}
+
+#[cfg(not(unix))]
+fn notify(_state: &str) {}
+
+// Tell that the service finished starting up, once the index caught up with the daemon
+pub fn ready() {
+    READY.store(true, Ordering::Relaxed);
+    notify("READY=1");
+}
+
+pub fn stopping() {
+    notify("STOPPING=1");
+}
+
+// A free-form status shown by `systemctl status`
+pub fn status(status: &str) {
+    notify(&format!("STATUS={}", status));
+}
+
+// Tell the watchdog that the service is still alive
+pub fn watchdog() {
+    notify("WATCHDOG=1");
+}
+
+// The interval the watchdog has to be notified within, when enabled for this process
+pub fn watchdog_interval() -> Option<Duration> {
+    // the watchdog may be meant for another process of the service
+    if let Ok(pid) = env::var("WATCHDOG_PID") {
+        if pid.parse::<u32>().ok() != Some(std::process::id()) {
+            return None;
+        }
+    }
+    let usec = env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    Some(Duration::from_micros(usec))
+}
+
+// Keep extending the startup timeout until ready() gets called, so that systemd doesn't kill the
+// service during long initial syncs
+pub fn spawn_startup_extender() {
+    if env::var_os("NOTIFY_SOCKET").is_none() {
+        return;
+    }
+    spawn_thread("sd-notify", || {
+        while !READY.load(Ordering::Relaxed) {
+            let extend_by = STARTUP_EXTEND_INTERVAL * 2;
+            notify(&format!("EXTEND_TIMEOUT_USEC={}", extend_by.as_micros()));
+            thread::sleep(STARTUP_EXTEND_INTERVAL);
+        }
+    });
+}
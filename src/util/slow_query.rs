@@ -0,0 +1,60 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use serde_json::Value;
+
+use crate::config::Config;
+use crate::new_index::ReadStats;
+
+/// A request that took longer than the configured threshold, logged as a JSON line
+#[derive(Serialize)]
+pub struct SlowQuery<'a> {
+    pub source: &'a str,
+    pub route: &'a str,
+    pub params: Value,
+    pub duration_ms: u128,
+    pub scripthash: Option<String>,
+    /// The script's tx count according to the stats cache, as a hint of its history size
+    pub script_tx_count: Option<usize>,
+    /// DB reads made by the thread serving the request
+    pub db: ReadStats,
+}
+
+pub struct SlowQueryLog {
+    threshold: Duration,
+    file: Option<Mutex<File>>,
+}
+
+impl SlowQueryLog {
+    /// Returns None when no slow query threshold is configured
+    pub fn new(config: &Config) -> Option<Self> {
+        let threshold = config.slow_query_threshold?;
+        let file = config.slow_query_log_file.as_ref().map(|path| {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .unwrap_or_else(|e| panic!("failed opening {}: {}", path.display(), e));
+            Mutex::new(file)
+        });
+        Some(SlowQueryLog { threshold, file })
+    }
+
+    pub fn is_slow(&self, duration: Duration) -> bool {
+        duration >= self.threshold
+    }
+
+    pub fn record(&self, query: &SlowQuery) {
+        let line = serde_json::to_string(query).unwrap();
+        match self.file {
+            Some(ref file) => {
+                if let Err(e) = writeln!(file.lock().unwrap(), "{}", line) {
+                    warn!("failed writing to the slow query log: {}", e);
+                }
+            }
+            None => warn!(target: "slow_query", "{}", line),
+        }
+    }
+}
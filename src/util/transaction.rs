@@ -1,5 +1,9 @@
+#[cfg(not(feature = "liquid"))]
+use crate::chain::ChainParams;
 use crate::chain::{BlockHash, OutPoint, Transaction, TxIn, TxOut, Txid};
 use crate::util::BlockId;
+#[cfg(not(feature = "liquid"))]
+use crate::util::{count_sigops, get_innerscripts, parse_multisig};
 
 use std::collections::HashMap;
 
@@ -75,6 +79,44 @@ pub fn is_spendable(txout: &TxOut) -> bool {
     return !txout.is_fee() && !txout.script_pubkey.is_provably_unspendable();
 }
 
+/// Decode only the outputs of a raw transaction, skipping over the inputs and witnesses
+#[cfg(not(feature = "liquid"))]
+pub fn decode_tx_outputs(rawtx: &[u8]) -> Option<Vec<TxOut>> {
+    use bitcoin::consensus::encode::Decodable;
+    use bitcoin::VarInt;
+    use std::io::Cursor;
+
+    fn skip(cursor: &mut Cursor<&[u8]>, len: u64) -> Option<()> {
+        let pos = cursor.position().checked_add(len)?;
+        if pos > cursor.get_ref().len() as u64 {
+            return None;
+        }
+        cursor.set_position(pos);
+        Some(())
+    }
+
+    let mut cursor = Cursor::new(rawtx);
+    skip(&mut cursor, 4)?; // version
+    if rawtx.get(4) == Some(&0) {
+        skip(&mut cursor, 2)?; // segwit marker and flag
+    }
+    let input_count = VarInt::consensus_decode(&mut cursor).ok()?.0;
+    for _ in 0..input_count {
+        skip(&mut cursor, 36)?; // previous outpoint
+        let script_len = VarInt::consensus_decode(&mut cursor).ok()?.0;
+        skip(&mut cursor, script_len.checked_add(4)?)?; // script_sig and sequence
+    }
+    Vec::<TxOut>::consensus_decode(&mut cursor).ok()
+}
+
+#[cfg(feature = "liquid")]
+pub fn decode_tx_outputs(rawtx: &[u8]) -> Option<Vec<TxOut>> {
+    // elements transactions are decoded in full
+    elements::encode::deserialize::<Transaction>(rawtx)
+        .ok()
+        .map(|tx| tx.output)
+}
+
 pub fn extract_tx_prevouts<'a>(
     tx: &Transaction,
     txos: &'a HashMap<OutPoint, TxOut>,
@@ -96,6 +138,199 @@ pub fn extract_tx_prevouts<'a>(
         .collect()
 }
 
+// The signature operations of a transaction, by where they're counted from
+#[cfg(not(feature = "liquid"))]
+#[derive(Serialize, Debug, Default, Clone, Copy, PartialEq)]
+pub struct SigopCounts {
+    pub legacy: usize,  // scriptSigs and scriptPubKeys, counted inaccurately
+    pub p2sh: usize,    // redeem scripts
+    pub witness: usize, // witness programs and scripts
+    pub cost: usize,    // the BIP141 sigop cost, with the non-witness sigops weighted by 4
+}
+
+// Count the sigops like bitcoind does for block and mempool limits. The P2SH and witness sigops
+// of inputs with missing prevouts are not counted.
+#[cfg(not(feature = "liquid"))]
+pub fn tx_sigops(tx: &Transaction, prevouts: &HashMap<u32, &TxOut>) -> SigopCounts {
+    let mut counts = SigopCounts::default();
+    counts.legacy += tx
+        .output
+        .iter()
+        .map(|txout| count_sigops(&txout.script_pubkey, false))
+        .sum::<usize>();
+    for (index, txin) in tx.input.iter().enumerate() {
+        counts.legacy += count_sigops(&txin.script_sig, false);
+        let prevout = match prevouts.get(&(index as u32)) {
+            Some(prevout) => prevout,
+            None => continue,
+        };
+        let innerscripts = get_innerscripts(txin, prevout);
+        if let Some(ref redeem_script) = innerscripts.redeem_script {
+            if txin.script_sig.is_push_only() {
+                counts.p2sh += count_sigops(redeem_script, true);
+            }
+        }
+        let program = innerscripts
+            .redeem_script
+            .as_ref()
+            .unwrap_or(&prevout.script_pubkey);
+        if program.is_p2wpkh() {
+            counts.witness += 1;
+        } else if program.is_p2wsh() {
+            if let Some(ref witness_script) = innerscripts.witness_script {
+                counts.witness += count_sigops(witness_script, true);
+            }
+        }
+    }
+    counts.cost = (counts.legacy + counts.p2sh) * 4 + counts.witness;
+    counts
+}
+
+// The relay policy rules the transaction breaks, by the reject reasons bitcoind reports for them
+#[cfg(not(feature = "liquid"))]
+pub fn policy_violations(
+    tx: &Transaction,
+    prevouts: &HashMap<u32, &TxOut>,
+    sigops: &SigopCounts,
+    params: &ChainParams,
+) -> Vec<&'static str> {
+    let mut violations = vec![];
+    if !(1..=2).contains(&tx.version.0) {
+        violations.push("version");
+    }
+    if tx.weight().to_wu() > params.max_standard_tx_weight {
+        violations.push("tx-size");
+    }
+    if tx
+        .input
+        .iter()
+        .any(|txin| txin.script_sig.len() > params.max_standard_scriptsig_size)
+    {
+        violations.push("scriptsig-size");
+    }
+    if !tx.input.iter().all(|txin| txin.script_sig.is_push_only()) {
+        violations.push("scriptsig-not-pushonly");
+    }
+    if !tx
+        .output
+        .iter()
+        .all(|txout| is_standard_script(&txout.script_pubkey, params))
+    {
+        violations.push("scriptpubkey");
+    }
+    if tx.output.iter().any(|txout| is_dust(txout, params)) {
+        violations.push("dust");
+    }
+    if tx.output.iter().filter(|txout| txout.script_pubkey.is_op_return()).count() > 1 {
+        violations.push("multi-op-return");
+    }
+    let p2sh_sigops_exceeded = tx.input.iter().enumerate().any(|(index, txin)| {
+        prevouts.get(&(index as u32)).map_or(false, |prevout| {
+            get_innerscripts(txin, prevout)
+                .redeem_script
+                .map_or(false, |redeem_script| {
+                    count_sigops(&redeem_script, true) > params.max_p2sh_sigops
+                })
+        })
+    });
+    if p2sh_sigops_exceeded {
+        violations.push("bad-txns-nonstandard-inputs");
+    }
+    if sigops.cost > params.max_standard_tx_sigops_cost {
+        violations.push("bad-txns-too-many-sigops");
+    }
+    violations
+}
+
+#[cfg(not(feature = "liquid"))]
+fn is_standard_script(script: &bitcoin::Script, params: &ChainParams) -> bool {
+    script.is_p2pkh()
+        || script.is_p2sh()
+        || script.is_p2pk()
+        || script.is_witness_program()
+        || (script.is_op_return() && script.len() <= params.max_op_return_relay)
+        || parse_multisig(script).map_or(false, |(_, n)| n <= 3)
+}
+
+// Outputs worth less than the fee to spend them at the dust relay feerate
+#[cfg(not(feature = "liquid"))]
+fn is_dust(txout: &TxOut, params: &ChainParams) -> bool {
+    if txout.script_pubkey.is_op_return() {
+        return false;
+    }
+    let output_size = bitcoin::consensus::encode::serialize(txout).len() as u64;
+    let input_size = if txout.script_pubkey.is_witness_program() {
+        32 + 4 + 1 + 107 / 4 + 4
+    } else {
+        32 + 4 + 1 + 107 + 4
+    };
+    txout.value.to_sat() < (output_size + input_size) * params.dust_relay_feerate
+}
+
+// Locktimes below this are block heights, the others unix timestamps
+const LOCKTIME_THRESHOLD: u32 = 500_000_000;
+// BIP68 relative timelock encoding of the sequence number
+const SEQUENCE_LOCKTIME_DISABLE_FLAG: u32 = 1 << 31;
+const SEQUENCE_LOCKTIME_TYPE_FLAG: u32 = 1 << 22;
+const SEQUENCE_LOCKTIME_MASK: u32 = 0x0000_ffff;
+const SEQUENCE_LOCKTIME_GRANULARITY: u32 = 9; // time-based relative locks are in units of 512s
+const SEQUENCE_FINAL: u32 = 0xffff_ffff;
+
+// A timelock, either in blocks or in seconds. Absolute timelocks are a block height or a
+// unix timestamp, relative ones a number of blocks or seconds since the prevout confirmed.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(tag = "type", content = "value", rename_all = "lowercase")]
+pub enum Timelock {
+    Height(u32),
+    Time(u32),
+}
+
+// Interpret a locktime (or an OP_CHECKLOCKTIMEVERIFY argument)
+pub fn decode_locktime(locktime: u32) -> Timelock {
+    if locktime < LOCKTIME_THRESHOLD {
+        Timelock::Height(locktime)
+    } else {
+        Timelock::Time(locktime)
+    }
+}
+
+// Interpret a BIP68 sequence number (or an OP_CHECKSEQUENCEVERIFY argument), if it
+// doesn't have the relative timelock disabled
+pub fn decode_sequence(sequence: u32) -> Option<Timelock> {
+    if sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG != 0 {
+        None
+    } else if sequence & SEQUENCE_LOCKTIME_TYPE_FLAG != 0 {
+        let units = sequence & SEQUENCE_LOCKTIME_MASK;
+        Some(Timelock::Time(units << SEQUENCE_LOCKTIME_GRANULARITY))
+    } else {
+        Some(Timelock::Height(sequence & SEQUENCE_LOCKTIME_MASK))
+    }
+}
+
+// The absolute timelock of the transaction, if enforced (i.e. non-zero, with a non-final input)
+pub fn absolute_timelock(tx: &Transaction) -> Option<Timelock> {
+    let locktime = tx.lock_time.to_consensus_u32();
+    if locktime == 0 || tx.input.iter().all(|txin| txin.sequence.0 == SEQUENCE_FINAL) {
+        None
+    } else {
+        Some(decode_locktime(locktime))
+    }
+}
+
+// The BIP68 relative timelock of the input, if enforced
+pub fn relative_timelock(tx: &Transaction, txin: &TxIn) -> Option<Timelock> {
+    #[cfg(not(feature = "liquid"))]
+    let version = tx.version.0 as u32;
+    #[cfg(feature = "liquid")]
+    let version = tx.version;
+
+    if version < 2 || !has_prevout(txin) {
+        None
+    } else {
+        decode_sequence(txin.sequence.0)
+    }
+}
+
 pub fn serialize_outpoint<S>(outpoint: &OutPoint, serializer: S) -> Result<S::Ok, S::Error>
 where
     S: serde::ser::Serializer,
@@ -0,0 +1,259 @@
+// A minimal ZMQ subscriber for the daemon's notifications, speaking ZMTP 3.0 over TCP with the
+// NULL security mechanism (see https://rfc.zeromq.org/spec/23/), which is all bitcoind offers.
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::Duration;
+
+use error_chain::ChainedError;
+use socket2::{SockRef, TcpKeepalive};
+
+use crate::chain::{deserialize, BlockHash, Transaction};
+use crate::errors::*;
+use crate::new_index::Mempool;
+use crate::signal::Waiter;
+use crate::util::spawn_thread;
+
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(10);
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+// dead connections are detected with TCP keepalives, as the daemon doesn't send ZMTP heartbeats
+const KEEPALIVE_TIME: Duration = Duration::from_secs(60);
+
+const TOPIC_RAWTX: &str = "rawtx";
+const TOPIC_HASHBLOCK: &str = "hashblock";
+
+// larger frames are refused rather than allocated, as the raw transactions are well below it
+const MAX_FRAME_SIZE: usize = 32 * 1024 * 1024;
+
+const FLAG_MORE: u8 = 0x01;
+const FLAG_LONG: u8 = 0x02;
+const FLAG_COMMAND: u8 = 0x04;
+
+pub enum Notification {
+    RawTx(Transaction),
+    HashBlock(BlockHash),
+}
+
+pub struct Subscriber {
+    stream: TcpStream,
+}
+
+impl Subscriber {
+    pub fn connect(addr: SocketAddr, topics: &[&str]) -> Result<Subscriber> {
+        let stream = TcpStream::connect_timeout(&addr, CONNECTION_TIMEOUT)
+            .chain_err(|| format!("failed to connect to {}", addr))?;
+        SockRef::from(&stream)
+            .set_tcp_keepalive(&TcpKeepalive::new().with_time(KEEPALIVE_TIME))
+            .chain_err(|| "failed to enable keepalives")?;
+        let mut subscriber = Subscriber { stream };
+        subscriber.handshake()?;
+        for topic in topics {
+            // ZMTP 3.0 subscriptions are messages starting with 0x01
+            let subscription = [&[1u8][..], topic.as_bytes()].concat();
+            subscriber.write(&encode_frame(0, &subscription))?;
+        }
+        Ok(subscriber)
+    }
+
+    fn handshake(&mut self) -> Result<()> {
+        let mut greeting = [0u8; 64];
+        greeting[0] = 0xff; // signature
+        greeting[8] = 0x01;
+        greeting[9] = 0x7f;
+        greeting[10] = 3; // version 3.0
+        greeting[12..16].copy_from_slice(b"NULL"); // mechanism, followed by as-server=0
+        self.write(&greeting)?;
+
+        let mut peer_greeting = [0u8; 64];
+        self.read(&mut peer_greeting)?;
+        if peer_greeting[0] != 0xff || peer_greeting[9] != 0x7f || peer_greeting[10] < 3 {
+            bail!("unsupported ZMTP peer");
+        }
+
+        let mut ready = b"\x05READY".to_vec();
+        ready.push(11);
+        ready.extend_from_slice(b"Socket-Type");
+        ready.extend_from_slice(&3u32.to_be_bytes());
+        ready.extend_from_slice(b"SUB");
+        self.write(&encode_frame(FLAG_COMMAND, &ready))
+    }
+
+    // The next notification, skipping the unknown ones
+    pub fn recv(&mut self) -> Result<Notification> {
+        loop {
+            // bitcoind sends the topic, the body and a sequence number
+            let message = read_message(&mut self.stream)?;
+            let (topic, body) = match message.as_slice() {
+                [topic, body, ..] => (topic, body),
+                _ => continue,
+            };
+            if topic == TOPIC_RAWTX.as_bytes() {
+                let tx = deserialize(body).chain_err(|| "failed to parse notified tx")?;
+                return Ok(Notification::RawTx(tx));
+            }
+            if topic == TOPIC_HASHBLOCK.as_bytes() {
+                // sent in the display (reversed) byte order
+                let hash: Vec<u8> = body.iter().rev().cloned().collect();
+                let hash = deserialize(&hash).chain_err(|| "failed to parse notified hash")?;
+                return Ok(Notification::HashBlock(hash));
+            }
+        }
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<()> {
+        self.stream
+            .read_exact(buf)
+            .chain_err(|| ErrorKind::Connection("ZMQ connection closed".to_string()))
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Result<()> {
+        self.stream
+            .write_all(buf)
+            .chain_err(|| ErrorKind::Connection("ZMQ connection closed".to_string()))
+    }
+}
+
+// Read a message, made of the frames up to the one without the MORE flag. Commands (like the
+// peer's READY) aren't part of the messages and get skipped.
+fn read_message(stream: &mut impl Read) -> Result<Vec<Vec<u8>>> {
+    let mut read = |buf: &mut [u8]| {
+        stream
+            .read_exact(buf)
+            .chain_err(|| ErrorKind::Connection("ZMQ connection closed".to_string()))
+    };
+    let mut frames = vec![];
+    loop {
+        let mut flags = [0u8; 1];
+        read(&mut flags)?;
+        let size = if flags[0] & FLAG_LONG != 0 {
+            let mut size = [0u8; 8];
+            read(&mut size)?;
+            u64::from_be_bytes(size)
+        } else {
+            let mut size = [0u8; 1];
+            read(&mut size)?;
+            size[0] as u64
+        };
+        if size > MAX_FRAME_SIZE as u64 {
+            bail!(ErrorKind::Connection(format!(
+                "ZMQ frame too large ({} bytes)",
+                size
+            )));
+        }
+        let mut body = vec![0u8; size as usize];
+        read(&mut body)?;
+        if flags[0] & FLAG_COMMAND != 0 {
+            continue;
+        }
+        frames.push(body);
+        if flags[0] & FLAG_MORE == 0 {
+            return Ok(frames);
+        }
+    }
+}
+
+fn encode_frame(flags: u8, body: &[u8]) -> Vec<u8> {
+    let mut frame = if body.len() > u8::MAX as usize {
+        let mut frame = vec![flags | FLAG_LONG];
+        frame.extend_from_slice(&(body.len() as u64).to_be_bytes());
+        frame
+    } else {
+        vec![flags, body.len() as u8]
+    };
+    frame.extend_from_slice(body);
+    frame
+}
+
+fn listen(subscriber: &mut Subscriber, mempool: &RwLock<Mempool>, signal: &Waiter) -> Result<()> {
+    loop {
+        match subscriber.recv()? {
+            Notification::RawTx(tx) => mempool.write().unwrap().add_notified(tx),
+            Notification::HashBlock(hash) => {
+                debug!("notified of block {}", hash);
+                // wake up the main loop for indexing it
+                signal.trigger();
+            }
+        }
+    }
+}
+
+// Keep adding the transactions entering the daemon's mempool in the background, and wake up the
+// main loop on new blocks. The returned flag gets set whenever the mempool should be fully
+// resynced with the daemon's, which is the case after (re)connecting as notifications may have
+// been missed.
+pub fn spawn(addr: SocketAddr, mempool: Arc<RwLock<Mempool>>, signal: Waiter) -> Arc<AtomicBool> {
+    let resync = Arc::new(AtomicBool::new(false));
+    let resync_needed = Arc::clone(&resync);
+    spawn_thread("zmq", move || loop {
+        match Subscriber::connect(addr, &[TOPIC_RAWTX, TOPIC_HASHBLOCK]) {
+            Ok(mut subscriber) => {
+                info!("subscribed to the ZMQ notifications of {}", addr);
+                resync_needed.store(true, Ordering::Relaxed);
+                signal.trigger();
+                if let Err(e) = listen(&mut subscriber, &mempool, &signal) {
+                    warn!("ZMQ subscription failed: {}", e.display_chain());
+                }
+            }
+            Err(e) => warn!("failed subscribing to ZMQ: {}", e.display_chain()),
+        }
+        thread::sleep(RECONNECT_DELAY);
+    });
+    resync
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{encode_frame, read_message, FLAG_COMMAND, FLAG_MORE, MAX_FRAME_SIZE};
+    use std::io::Cursor;
+
+    #[test]
+    fn test_frame_encoding() {
+        assert_eq!(encode_frame(FLAG_MORE, b"rawtx"), b"\x01\x05rawtx");
+        let body = vec![7u8; 256];
+        let frame = encode_frame(0, &body);
+        assert_eq!(frame[0], 0x02); // LONG
+        assert_eq!(frame[1..9], 256u64.to_be_bytes());
+        assert_eq!(frame[9..], body[..]);
+    }
+
+    #[test]
+    fn test_read_message() {
+        let long_body = vec![1u8; 300];
+        let stream = [
+            encode_frame(FLAG_COMMAND, b"\x05READY"),
+            encode_frame(FLAG_MORE, b"rawtx"),
+            encode_frame(FLAG_MORE, &long_body),
+            encode_frame(0, &[1, 0, 0, 0]),
+            encode_frame(0, b"next"),
+        ]
+        .concat();
+        let mut stream = Cursor::new(stream);
+        let message = read_message(&mut stream).unwrap();
+        assert_eq!(
+            message,
+            vec![b"rawtx".to_vec(), long_body, vec![1, 0, 0, 0]]
+        );
+        assert_eq!(read_message(&mut stream).unwrap(), vec![b"next".to_vec()]);
+        // closed connection
+        assert!(read_message(&mut stream).is_err());
+    }
+
+    #[test]
+    fn test_read_message_truncated() {
+        let frame = encode_frame(0, &[0u8; 300]);
+        let mut stream = Cursor::new(&frame[..frame.len() - 1]);
+        assert!(read_message(&mut stream).is_err());
+    }
+
+    #[test]
+    fn test_read_message_too_large() {
+        let mut frame = vec![0x02];
+        frame.extend_from_slice(&(MAX_FRAME_SIZE as u64 + 1).to_be_bytes());
+        assert!(read_message(&mut Cursor::new(frame)).is_err());
+        let mut frame = vec![0x02];
+        frame.extend_from_slice(&u64::MAX.to_be_bytes());
+        assert!(read_message(&mut Cursor::new(frame)).is_err());
+    }
+}
@@ -1,5 +1,6 @@
 use std::sync::{Arc, Once, RwLock};
-use std::{env, net};
+use std::time::Duration;
+use std::{env, fs, net};
 
 use stderrlog::StdErrLog;
 use tempfile::TempDir;
@@ -17,13 +18,19 @@ use noded::bitcoincore_rpc::{self, RpcApi};
 
 use electrs::{
     chain::{Address, BlockHash, Network, Txid},
-    config::Config,
+    config::{
+        CachePolicy, CacheTtl, Config, CorsPolicy, Deployment, MempoolEviction, SecondaryDaemon,
+    },
     daemon::Daemon,
     electrum::RPC as ElectrumRPC,
     metrics::Metrics,
-    new_index::{ChainQuery, FetchFrom, Indexer, Mempool, Query, Store},
+    new_index::{
+        broadcast_queue, daily_stats, forks, price, rebroadcast, ChainQuery, FetchFrom, Indexer,
+        Mempool, Query, Store,
+    },
     rest,
     signal::Waiter,
+    util::Locale,
 };
 
 pub struct TestRunner {
@@ -34,15 +41,39 @@ pub struct TestRunner {
     indexer: Indexer,
     query: Arc<Query>,
     daemon: Arc<Daemon>,
+    secondary_daemons: Vec<(String, Daemon)>,
     mempool: Arc<RwLock<Mempool>>,
     metrics: Metrics,
 }
 
 impl TestRunner {
     pub fn new() -> Result<TestRunner> {
+        TestRunner::init(false)
+    }
+
+    /// Like `new`, with the daemon publishing its ZMQ notifications to `config().zmq_addr`
+    pub fn with_zmq() -> Result<TestRunner> {
+        TestRunner::init(true)
+    }
+
+    fn init(zmq: bool) -> Result<TestRunner> {
         let log = init_log();
 
         // Setup the bitcoind/elementsd config
+        let zmq_addr = if zmq {
+            Some(rand_available_addr())
+        } else {
+            None
+        };
+        let zmq_args: Vec<String> = zmq_addr
+            .iter()
+            .flat_map(|addr| {
+                vec![
+                    format!("-zmqpubrawtx=tcp://{}", addr),
+                    format!("-zmqpubhashblock=tcp://{}", addr),
+                ]
+            })
+            .collect();
         let mut node_conf = noded::Conf::default();
         {
             #[cfg(not(feature = "liquid"))]
@@ -53,6 +84,7 @@ impl TestRunner {
             #[cfg(feature = "liquid")]
             node_conf.args.push("-anyonecanspendaremine=1");
 
+            node_conf.args.extend(zmq_args.iter().map(String::as_str));
             node_conf.view_stdout = true;
         }
 
@@ -78,12 +110,22 @@ impl TestRunner {
         #[cfg(feature = "liquid")]
         let network_type = Network::LiquidRegtest;
 
+        // pin the first block after the genesis
+        let checkpoint: BlockHash = node_client.get_block_hash(1)?.to_string().parse().unwrap();
+
         let mut daemon_subdir = params.cookie_file.clone();
         // drop `.cookie` filename, leaving just the network subdirectory
         daemon_subdir.pop();
 
         let electrsdb = tempfile::tempdir().unwrap();
 
+        // a static price feed, with a past rate and a current one
+        let price_feed = electrsdb.path().join("prices.json");
+        fs::write(
+            &price_feed,
+            r#"[{"time": 1, "rates": {"usd": 2.0}}, {"time": 2000000000, "rates": {"usd": 3.0}}]"#,
+        )?;
+
         let config = Arc::new(Config {
             log,
             network_type,
@@ -92,28 +134,124 @@ impl TestRunner {
             blocks_dir: daemon_subdir.join("blocks"),
             daemon_rpc_addr: params.rpc_socket.into(),
             cookie: None,
+            zmq_addr,
+            zmq_resync_interval: 60,
+            // fetch the new mempool txs in parallel even when there are only a few of them
+            mempool_fetch_batch_size: 1,
+            mempool_fetch_concurrency: 2,
+            mempool_limit_txs: None,
+            mempool_limit_memory: None,
+            mempool_eviction: MempoolEviction::LowestFeerate,
+            // monitor the primary node as its own secondary, which is always in sync with it
+            secondary_daemons: vec![SecondaryDaemon {
+                rpc_addr: params.rpc_socket.into(),
+                cookie_file: Some(params.cookie_file.clone()),
+            }],
+            fork_alert_depth: 6,
+            checkpoints: vec![(1, checkpoint)].into_iter().collect(),
+            max_reorg_depth: Some(2),
+            deployments: vec![Deployment {
+                name: "testdummy".to_string(),
+                bit: 28,
+                start_time: 0,
+                timeout: u32::MAX,
+                threshold: None,
+            }],
+            deployment_period: 144,
+            #[cfg(not(feature = "liquid"))]
+            retarget_interval: 10,
+            #[cfg(not(feature = "liquid"))]
+            target_block_spacing: 600,
             electrum_rpc_addr: rand_available_addr(),
             http_addr: rand_available_addr(),
             http_socket_file: None, // XXX test with socket file or tcp?
             monitoring_addr: rand_available_addr(),
             jsonrpc_import: false,
             light_mode: false,
+            replica_path: None,
             address_search: true,
+            flow_analysis: true,
             index_unspendables: false,
-            cors: None,
+            large_tx_threshold: Some(100_000_000),
+            index_output_values: true,
+            replaced_tx_retention: Duration::from_secs(3600),
+            orphaned_spend_window: 6,
+            index_revealed_scripts: true,
+            index_spend_paths: true,
+            #[cfg(not(feature = "liquid"))]
+            script_templates: vec![electrs::util::ScriptTemplate::parse("p2wpkh", "OP_0 <20>")?],
+            daily_stats: true,
+            broadcast_queue: true,
+            rebroadcast_after: Some(1),
+            integrity_check_interval: None,
+            cache_policy: CachePolicy::new(
+                CacheTtl {
+                    long: 157_784_630,
+                    short: 10,
+                    mempool_recent: 5,
+                },
+                "/blocks/tip/*=short:2,/fee-estimates=no-store",
+            ),
+            stale_while_revalidate: 0,
+            cors: Some(CorsPolicy::new(
+                "https://*.example.com,https://admin.test=GET|DELETE",
+                "GET,POST",
+                "Content-Type",
+                600,
+            )),
+            locales: vec![Locale::En, Locale::De],
             precache_scripts: None,
+            price_feed: Some(price_feed.to_str().unwrap().to_string()),
+            price_feed_interval: Duration::from_secs(300),
             utxos_limit: 100,
+            max_txs_per_page: 100,
+            max_mempool_txs: 50,
+            max_batch_size: 100,
+            max_outspends_txids: 1000,
+            outspends_concurrency: 4,
             electrum_txs_limit: 100,
             electrum_banner: "".into(),
+            electrum_donation_address: None,
+            electrum_peers: vec![],
             electrum_rpc_logging: None,
+            electrum_batch_limit: 100,
+            electrum_max_response_size: 10_000_000,
+            electrum_max_pending_requests: 10,
+            electrum_max_connections: None,
+            electrum_max_connections_per_ip: None,
+            electrum_idle_timeout: None,
+            admin_api: false,
+            admin_token: None,
+            shutdown_timeout: Duration::from_secs(5),
+            additional_networks: vec![],
+            tx_cache_size: 1024 * 1024,
+            block_cache_size: 1024 * 1024,
+            prevout_cache_size: 1024 * 1024,
+            raw_block_cache_size: 0,
+            slow_query_threshold: None,
+            slow_query_log_file: None,
+            audit_log_file: Some(electrsdb.path().join("audit.log")),
+            audit_log_max_size: 64 * 1024 * 1024,
+            audit_log_max_files: 4,
+            request_budget: None,
+            request_budget_keys: vec![("tiny-budget".to_string(), 1)].into_iter().collect(),
+            request_timeout: None,
 
             #[cfg(feature = "liquid")]
             asset_db_path: None, // XXX
             #[cfg(feature = "liquid")]
+            asset_registry_sources: vec![],
+            #[cfg(feature = "liquid")]
+            asset_registry_refresh_interval: Duration::from_secs(15),
+            #[cfg(feature = "liquid")]
+            asset_registry_merge: electrs::elements::RegistryMerge::First,
+            #[cfg(feature = "liquid")]
             parent_network: bitcoin::Network::Regtest,
             //#[cfg(feature = "electrum-discovery")]
             //electrum_public_hosts: Option<crate::electrum::ServerHosts>,
             //#[cfg(feature = "electrum-discovery")]
+            //electrum_onion_host: Option<crate::electrum::Hostname>,
+            //#[cfg(feature = "electrum-discovery")]
             //electrum_announce: bool,
             //#[cfg(feature = "electrum-discovery")]
             //tor_proxy: Option<std::net::SocketAddr>,
@@ -132,6 +270,7 @@ impl TestRunner {
             signal.clone(),
             &metrics,
         )?);
+        let secondary_daemons = forks::connect(&daemon, &config)?;
 
         let store = Arc::new(Store::open(&config.db_path.join("newindex"), &config));
 
@@ -180,11 +319,27 @@ impl TestRunner {
             indexer,
             query,
             daemon,
+            secondary_daemons,
             mempool,
             metrics,
         })
     }
 
+    pub fn query(&self) -> &Query {
+        &self.query
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    // Open the index the way a read-only replica does
+    pub fn open_replica(&self) -> Store {
+        let mut config = (*self.config).clone();
+        config.replica_path = Some(self.config.db_path.join("replica"));
+        Store::open(&config.db_path.join("newindex"), &config)
+    }
+
     pub fn node_client(&self) -> &bitcoincore_rpc::Client {
         #[cfg(not(feature = "liquid"))]
         return &self.node.client;
@@ -194,7 +349,20 @@ impl TestRunner {
 
     pub fn sync(&mut self) -> Result<()> {
         self.indexer.update(&self.daemon)?;
+        if self.config.daily_stats {
+            daily_stats::update(self.query.chain())?;
+        }
+        if let Some(ref source) = self.config.price_feed {
+            price::update(self.query.chain(), source);
+        }
+        forks::update(self.query.chain(), &self.secondary_daemons);
         Mempool::update(&self.mempool, &self.daemon)?;
+        if self.config.broadcast_queue {
+            broadcast_queue::update(&self.query);
+        }
+        if let Some(after) = self.config.rebroadcast_after {
+            rebroadcast::update(&self.query, after);
+        }
         // force an update for the mempool stats, which are normally cached
         self.mempool.write().unwrap().update_backlog_stats();
         Ok(())
@@ -263,8 +431,9 @@ impl TestRunner {
 }
 
 pub fn init_rest_tester() -> Result<(rest::Handle, net::SocketAddr, TestRunner)> {
-    let tester = TestRunner::new()?;
-    let rest_server = rest::start(Arc::clone(&tester.config), Arc::clone(&tester.query));
+    // the REST tests cover the ZMQ notifications of the daemon
+    let tester = TestRunner::with_zmq()?;
+    let rest_server = rest::start(Arc::clone(&tester.config), Arc::clone(&tester.query), None);
     log::info!("REST server running on {}", tester.config.http_addr);
     Ok((rest_server, tester.config.http_addr, tester))
 }
@@ -6,6 +6,9 @@ use electrumd::jsonrpc::serde_json::json;
 use electrumd::ElectrumD;
 
 use electrs::chain::Address;
+use electrs::new_index::compute_script_hash;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
 
 #[cfg(not(feature = "liquid"))]
 use bitcoin::address;
@@ -132,5 +135,164 @@ fn test_electrum() -> Result<()> {
     notify_wallet();
     assert_balance(0.139, 0.0);
 
+    // Verify blockchain.transaction.get in verbose mode, calling the server directly
+    let electrum_call = |method: &str, params: serde_json::Value| -> Result<serde_json::Value> {
+        let mut stream = TcpStream::connect(electrum_addr)?;
+        let request = serde_json::json!({ "id": 0, "method": method, "params": params });
+        writeln!(stream, "{}", request)?;
+        let mut line = String::new();
+        BufReader::new(stream).read_line(&mut line)?;
+        Ok(serde_json::from_str(&line)?)
+    };
+    let tx = electrum_call(
+        "blockchain.transaction.get",
+        serde_json::json!([txid1.to_string(), true]),
+    )?["result"]
+        .take();
+    assert_eq!(tx["txid"].as_str(), Some(txid1.to_string().as_str()));
+    assert_eq!(tx["confirmations"].as_u64(), Some(3)); // confirmed at 102, the tip is at 104
+    let blockhash = tester.node_client().get_block_hash(102)?;
+    assert_eq!(tx["blockhash"].as_str(), Some(blockhash.to_string().as_str()));
+    assert!(tx["blocktime"].as_u64().unwrap() > 0);
+    let rawtx = electrum_call(
+        "blockchain.transaction.get",
+        serde_json::json!([txid1.to_string()]),
+    )?["result"]
+        .take();
+    assert_eq!(tx["hex"], rawtx);
+    assert!(tx["vout"].as_array().unwrap().iter().any(|vout| {
+        vout["scriptPubKey"]["address"].as_str() == Some(addr1.to_string().as_str())
+            && vout["value"].as_f64() == Some(0.1)
+    }));
+
+    // Verify that broadcast failures are classified
+    let response = electrum_call("blockchain.transaction.broadcast", serde_json::json!([rawtx]))?;
+    assert_eq!(response["error"]["code"].as_i64(), Some(1));
+    assert_eq!(
+        response["error"]["data"]["reason"].as_str(),
+        Some("already-in-chain")
+    );
+
+    Ok(())
+}
+
+/// Test JSON-RPC batches, calling the server directly
+#[test]
+fn test_electrum_batch() -> Result<()> {
+    let (_electrum_server, electrum_addr, _tester) = common::init_electrum_tester().unwrap();
+
+    let stream = TcpStream::connect(electrum_addr)?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+    let mut call = |request: serde_json::Value| -> Result<serde_json::Value> {
+        writeln!(writer, "{}", request)?;
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        Ok(serde_json::from_str(&line)?)
+    };
+
+    // the replies come in the order of the requests
+    let replies = call(serde_json::json!([
+        { "id": 1, "method": "server.ping", "params": [] },
+        { "id": 2, "method": "blockchain.headers.subscribe", "params": [] },
+    ]))?;
+    assert_eq!(replies[0]["id"], 1);
+    assert!(replies[0]["result"].is_null());
+    assert_eq!(replies[1]["id"], 2);
+    assert!(replies[1]["result"]["height"].is_u64());
+
+    // malformed entries get an error each, without affecting the others
+    let replies = call(serde_json::json!([
+        { "id": 1, "method": "server.ping" },
+        { "id": 2, "params": [] },
+        "not a request",
+        { "id": 4, "method": "server.ping", "params": {} },
+        { "id": 5, "method": "no.such.method", "params": [] },
+        { "id": 6, "method": "server.ping", "params": [] },
+    ]))?;
+    let replies = replies.as_array().unwrap();
+    assert_eq!(replies.len(), 6);
+    assert!(replies[0]["error"].is_null());
+    // no id to reply with for entries that aren't objects
+    for (reply, id) in replies[1..5].iter().zip(&[Some(2), None, Some(4), Some(5)]) {
+        assert_eq!(reply["id"], serde_json::json!(id));
+        assert!(reply["error"].is_string(), "{}", reply);
+    }
+    assert_eq!(replies[5]["id"], 6);
+    assert!(replies[5]["error"].is_null());
+
+    // batches have to be neither empty nor larger than the limit
+    let reply = call(serde_json::json!([]))?;
+    assert_eq!(reply["error"], "batch size must be between 1 and 100");
+    let requests: Vec<_> = (0..101)
+        .map(|id| serde_json::json!({ "id": id, "method": "server.ping", "params": [] }))
+        .collect();
+    let reply = call(serde_json::json!(requests))?;
+    assert_eq!(reply["error"], "batch size must be between 1 and 100");
+
+    // the connection is still usable
+    let reply = call(serde_json::json!({ "id": 7, "method": "server.ping", "params": [] }))?;
+    assert_eq!(reply["id"], 7);
+    assert!(reply["error"].is_null());
+
+    Ok(())
+}
+
+/// Test that the statuses notified to subscribers match the ones computed from scratch
+#[test]
+fn test_electrum_subscription() -> Result<()> {
+    let (electrum_server, electrum_addr, mut tester) = common::init_electrum_tester().unwrap();
+
+    let addr = tester.newaddress()?;
+    let mut scripthash = compute_script_hash(&addr.script_pubkey());
+    scripthash.reverse();
+    let scripthash = scripthash
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<String>();
+
+    let subscribe = || -> Result<(BufReader<TcpStream>, serde_json::Value)> {
+        let mut stream = TcpStream::connect(electrum_addr)?;
+        let request = serde_json::json!({
+            "id": 1,
+            "method": "blockchain.scripthash.subscribe",
+            "params": [scripthash],
+        });
+        writeln!(stream, "{}", request)?;
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let reply: serde_json::Value = serde_json::from_str(&line)?;
+        Ok((reader, reply["result"].clone()))
+    };
+    let next_status = |reader: &mut BufReader<TcpStream>| -> Result<serde_json::Value> {
+        electrum_server.notify();
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let notification: serde_json::Value = serde_json::from_str(&line)?;
+        assert_eq!(notification["method"], "blockchain.scripthash.subscribe");
+        assert_eq!(notification["params"][0], scripthash.as_str());
+        Ok(notification["params"][1].clone())
+    };
+
+    let (mut reader, status) = subscribe()?;
+    assert!(status.is_null());
+
+    // the confirmed history is folded into the status block by block, along with the mempool
+    let mut statuses = vec![status];
+    for _ in 0..2 {
+        tester.send(&addr, bitcoin::Amount::from_sat(100_000))?;
+        statuses.push(next_status(&mut reader)?);
+        tester.mine()?;
+        statuses.push(next_status(&mut reader)?);
+        assert_eq!(statuses.last(), Some(&subscribe()?.1));
+    }
+    tester.send(&addr, bitcoin::Amount::from_sat(100_000))?;
+    statuses.push(next_status(&mut reader)?);
+    assert_eq!(statuses.last(), Some(&subscribe()?.1));
+
+    statuses.dedup();
+    assert_eq!(statuses.len(), 6);
+
     Ok(())
 }
@@ -1,8 +1,15 @@
 use bitcoind::bitcoincore_rpc::RpcApi;
 use serde_json::Value;
 use std::collections::HashSet;
+use std::io::Read;
 
 use electrs::chain::Txid;
+use flate2::read::GzDecoder;
+use electrs::new_index::db::DBFlush;
+use electrs::new_index::{
+    broadcast_queue, compute_script_hash, integrity, rebroadcast, watch_lists, DBRow, Deadline,
+    ScriptChanges, TxHistoryInfo, TxHistoryRow,
+};
 
 pub mod common;
 
@@ -24,6 +31,15 @@ fn test_rest() -> Result<()> {
             .into_string()?)
     };
 
+    let get_bytes = |path: &str| -> Result<Vec<u8>> {
+        let mut bytes = vec![];
+        ureq::get(&format!("http://{}{}", rest_addr, path))
+            .call()?
+            .into_reader()
+            .read_to_end(&mut bytes)?;
+        Ok(bytes)
+    };
+
     // Send transaction and confirm it
     let addr1 = tester.newaddress()?;
     let txid1_confirmed = tester.send(&addr1, "1.19123 BTC".parse().unwrap())?;
@@ -41,6 +57,84 @@ fn test_rest() -> Result<()> {
             && vout["value"].as_u64() == Some(119123000)
     }));
 
+    // Test GET /tx/:txid?verbose=true
+    #[cfg(not(feature = "liquid"))]
+    {
+        let res = get_json(&format!("/tx/{}?verbose=true", txid1_confirmed))?;
+        let analysis = &res["analysis"];
+        assert_eq!(analysis["standard"].as_bool(), Some(true));
+        assert!(analysis["violations"].as_array().unwrap().is_empty());
+        let sigops = &analysis["sigops"];
+        let (legacy, p2sh, witness) = (
+            sigops["legacy"].as_u64().unwrap(),
+            sigops["p2sh"].as_u64().unwrap(),
+            sigops["witness"].as_u64().unwrap(),
+        );
+        assert!(legacy + p2sh + witness > 0);
+        assert_eq!(sigops["cost"].as_u64(), Some(4 * (legacy + p2sh) + witness));
+        assert!(get_json(&format!("/tx/{}", txid1_confirmed))?["analysis"].is_null());
+
+        // Test POST /tx/analyze
+        let txhex = get_plain(&format!("/tx/{}/hex", txid2_mempool))?;
+        let res = ureq::post(&format!("http://{}/tx/analyze", rest_addr))
+            .send_string(&txhex)?
+            .into_json::<Value>()?;
+        let verbose = get_json(&format!("/tx/{}?verbose=true", txid2_mempool))?;
+        assert_eq!(res["txid"].as_str(), Some(txid2_mempool.to_string().as_str()));
+        assert_eq!(res["analysis"], verbose["analysis"]);
+        assert_eq!(res["fee"], verbose["fee"]);
+        assert!(res["feerate"].as_f64().unwrap() > 0.0);
+    }
+
+    // Test GET /address/:addr/balance
+    let res = get_json(&format!("/address/{}/balance", addr1))?;
+    assert_eq!(res["confirm_amount"].as_str(), Some("1.19123000"));
+    assert_eq!(res["pending_amount"].as_str(), Some("0.71130000"));
+    assert_eq!(res["coin_amount"].as_str(), Some("1.90253000"));
+    assert_eq!(res["sat_amount"].as_u64(), Some(190253000));
+    let res = get_json(&format!("/address/{}/balance?unit=sat", addr1))?;
+    assert_eq!(res["amount"].as_str(), Some("190253000"));
+    assert_eq!(res["coin_amount"].as_str(), Some("1.90253000"));
+
+    // Test the locale negotiated with Accept-Language (the test config enables en and de)
+    let resp = ureq::get(&format!("http://{}/address/{}/balance?unit=sat", rest_addr, addr1))
+        .set("Accept-Language", "de-DE, en;q=0.5")
+        .call()?;
+    assert_eq!(resp.header("Content-Language"), Some("de"));
+    let res = resp.into_json::<Value>()?;
+    assert_eq!(res["amount"].as_str(), Some("190.253.000"));
+    assert_eq!(res["coin_amount"].as_str(), Some("1,90253000"));
+    assert_eq!(res["sat_amount"].as_u64(), Some(190253000));
+    let res = ureq::get(&format!("http://{}/tx/{}", rest_addr, "00".repeat(32)))
+        .set("Accept-Language", "de")
+        .call();
+    match res {
+        Err(ureq::Error::Status(404, resp)) => {
+            assert_eq!(resp.into_string()?, "Transaktion nicht gefunden")
+        }
+        _ => panic!("expected a 404"),
+    }
+    let resp = ureq::get(&format!("http://{}/address/{}/balance", rest_addr, addr1))
+        .set("Accept-Language", "fr")
+        .call()?;
+    assert_eq!(resp.header("Content-Language"), Some("en"));
+
+    // Test ?fiat=<currency> and GET /prices
+    let res = get_json(&format!("/address/{}/balance?fiat=usd", addr1))?;
+    assert_eq!(res["fiat"]["currency"].as_str(), Some("USD"));
+    assert_eq!(res["fiat"]["rate"].as_f64(), Some(3.0));
+    assert!((res["fiat"]["amount"].as_f64().unwrap() - 1.90253 * 3.0).abs() < 1e-9);
+    let res = get_json(&format!("/tx/{}?fiat=usd", txid1_confirmed))?;
+    assert_eq!(res["fiat"]["rate"].as_f64(), Some(2.0)); // as of the block time
+    assert_eq!(res["fiat"]["rate_time"].as_u64(), Some(1));
+    let fee = res["fee"].as_u64().unwrap() as f64;
+    assert!((res["fiat"]["fee"].as_f64().unwrap() - fee / 1e8 * 2.0).abs() < 1e-9);
+    let res = get_json("/prices")?;
+    assert_eq!(res["time"].as_u64(), Some(2000000000));
+    assert_eq!(res["rates"]["USD"].as_f64(), Some(3.0));
+    let res = get_json("/prices?time=1000")?;
+    assert_eq!(res["rates"]["USD"].as_f64(), Some(2.0));
+
     // Test GET /tx/:txid/status
     let res = get_json(&format!("/tx/{}/status", txid1_confirmed))?;
     assert_eq!(res["confirmed"].as_bool(), Some(true));
@@ -50,6 +144,44 @@ fn test_rest() -> Result<()> {
     assert_eq!(res["confirmed"].as_bool(), Some(false));
     assert_eq!(res["block_height"].as_u64(), None);
 
+    // Test GET /tx/:txid/timelocks
+    // (the wallet sets the locktime to the tip height against fee sniping)
+    let res = get_json(&format!("/tx/{}/timelocks", txid1_confirmed))?;
+    assert_eq!(res["satisfied"].as_bool(), Some(true));
+    assert!(res["earliest_height"].as_u64().unwrap() <= 102);
+    let locktime = res["locktime"].as_u64().unwrap();
+    if !res["absolute"].is_null() {
+        assert_eq!(res["absolute"]["type"].as_str(), Some("height"));
+        assert_eq!(res["absolute"]["value"].as_u64(), Some(locktime));
+        assert_eq!(res["earliest_height"].as_u64(), Some(locktime + 1));
+    }
+    let inputs = res["inputs"].as_array().expect("array of inputs");
+    assert!(!inputs.is_empty());
+    assert!(inputs.iter().all(|input| input["relative"].is_null()));
+
+    let res = get_json(&format!("/tx/{}/timelocks", txid2_mempool))?;
+    assert_eq!(res["satisfied"].as_bool(), Some(true));
+
+    // Test GET /block/:hash/spends
+    let tx1 = get_json(&format!("/tx/{}", txid1_confirmed))?;
+    let tx1_blockhash = tx1["status"]["block_hash"].as_str().unwrap().to_string();
+    let res = get_json(&format!("/block/{}/spends", tx1_blockhash))?;
+    let spends = res.as_array().expect("array of block spends");
+    assert!(spends[0]["prevouts"].as_array().unwrap().is_empty()); // coinbase
+    let tx1_spends = spends
+        .iter()
+        .find(|spends| spends["txid"].as_str() == Some(txid1_confirmed.to_string().as_str()))
+        .expect("spends of tx1");
+    let tx1_vin = tx1["vin"].as_array().unwrap();
+    let tx1_prevouts = tx1_spends["prevouts"].as_array().unwrap();
+    assert_eq!(tx1_prevouts.len(), tx1_vin.len());
+    for (prevout, vin) in tx1_prevouts.iter().zip(tx1_vin) {
+        assert_eq!(prevout["txid"], vin["txid"]);
+        assert_eq!(prevout["vout"], vin["vout"]);
+        assert_eq!(prevout["scriptpubkey"], vin["prevout"]["scriptpubkey"]);
+        assert_eq!(prevout["value"], vin["prevout"]["value"]);
+    }
+
     // Test GET /address/:address
     let res = get_json(&format!("/address/{}", addr1))?;
     assert_eq!(res["chain_stats"]["funded_txo_count"].as_u64(), Some(1));
@@ -75,6 +207,335 @@ fn test_rest() -> Result<()> {
     assert!(txids.remove(&txid1_confirmed));
     assert!(txids.remove(&txid2_mempool));
     assert!(txids.is_empty());
+    #[cfg(not(feature = "liquid"))]
+    assert!(txs.iter().any(|tx| {
+        tx["txid"].as_str() == Some(txid1_confirmed.to_string().as_str())
+            && tx["balance_delta"].as_i64() == Some(119123000)
+    }));
+
+    // Test GET /address/:address/txs/summary
+    let res = get_json(&format!("/address/{}/txs/summary", addr1))?;
+    let summary = res.as_array().expect("array of tx summaries");
+    assert_eq!(summary.len(), 2);
+    assert_eq!(
+        summary[0]["txid"].as_str(),
+        Some(txid2_mempool.to_string().as_str())
+    );
+    assert_eq!(summary[0]["height"].as_u64(), Some(0));
+    assert_eq!(
+        summary[1]["txid"].as_str(),
+        Some(txid1_confirmed.to_string().as_str())
+    );
+    assert_eq!(summary[1]["height"].as_u64(), Some(102));
+    #[cfg(not(feature = "liquid"))]
+    assert_eq!(summary[1]["value"].as_i64(), Some(119123000));
+
+    // Test GET /address/:address/utxo/summary
+    #[cfg(not(feature = "liquid"))]
+    {
+        let utxos = get_json(&format!("/address/{}/utxo", addr1))?;
+        let utxos = utxos.as_array().expect("array of utxos");
+        let res = get_json(&format!("/address/{}/utxo/summary", addr1))?;
+        assert_eq!(res["count"].as_u64(), Some(utxos.len() as u64));
+        assert_eq!(
+            res["value"].as_u64(),
+            Some(utxos.iter().map(|utxo| utxo["value"].as_u64().unwrap()).sum())
+        );
+        assert_eq!(res["dust_count"].as_u64(), Some(0));
+        let buckets = res["buckets"].as_array().expect("array of buckets");
+        assert_eq!(buckets.len(), 7);
+    }
+
+    // Test GET /address/:address/consolidation-plan
+    #[cfg(not(feature = "liquid"))]
+    {
+        let utxos = get_json(&format!("/address/{}/utxo", addr1))?;
+        let utxo_count = utxos.as_array().expect("array of utxos").len();
+        let res = get_json(&format!(
+            "/address/{}/consolidation-plan?feerate=2&order=oldest",
+            addr1
+        ))?;
+        assert_eq!(res["utxo_count"].as_u64(), Some(utxo_count as u64));
+        assert_eq!(res["target_utxos"].as_u64(), Some(1));
+        assert_eq!(res["remaining_utxos"].as_u64(), Some(1));
+        assert_eq!(res["uneconomical_count"].as_u64(), Some(0));
+        assert_eq!(res["truncated"].as_bool(), Some(false));
+        let txs = res["transactions"].as_array().expect("array of transactions");
+        if utxo_count > 1 {
+            assert_eq!(txs.len(), 1);
+            assert_eq!(res["input_count"].as_u64(), Some(utxo_count as u64));
+            let tx = &txs[0];
+            assert_eq!(tx["fee"].as_u64(), Some(2 * tx["vsize"].as_u64().unwrap()));
+            assert_eq!(res["fee"], tx["fee"]);
+            assert_eq!(
+                tx["output_value"].as_u64(),
+                Some(tx["input_value"].as_u64().unwrap() - tx["fee"].as_u64().unwrap())
+            );
+            // the confirmed outputs come first, the unconfirmed ones have no height
+            let inputs = tx["inputs"].as_array().unwrap();
+            assert!(inputs[0]["block_height"].as_u64().is_some());
+        } else {
+            assert!(txs.is_empty());
+        }
+
+        // nothing to do when the outputs are already within the target
+        let res = get_json(&format!(
+            "/address/{}/consolidation-plan?feerate=2&target_utxos={}",
+            addr1, utxo_count
+        ))?;
+        assert!(res["transactions"].as_array().unwrap().is_empty());
+        assert_eq!(res["remaining_utxos"].as_u64(), Some(utxo_count as u64));
+
+        for params in &["", "feerate=x", "feerate=1&target_utxos=0", "feerate=1&order=x"] {
+            let res = ureq::get(&format!(
+                "http://{}/address/{}/consolidation-plan?{}",
+                rest_addr, addr1, params
+            ))
+            .call();
+            assert!(matches!(res, Err(ureq::Error::Status(400, _))));
+        }
+    }
+
+    // Test GET /address/:address/activity
+    #[cfg(not(feature = "liquid"))]
+    {
+        let res = get_json(&format!("/address/{}/activity?window=1h", addr1))?;
+        assert_eq!(res["window"].as_u64(), Some(3600));
+        assert_eq!(res["tx_count"].as_u64(), Some(2));
+        assert_eq!(res["truncated"].as_bool(), Some(false));
+
+        // the wallet may have funded the mempool tx with the confirmed one's output to addr1,
+        // the expected figures follow from the net effect of each tx
+        let summary = get_json(&format!("/address/{}/txs/summary", addr1))?;
+        let (mut received, mut sent) = ((0, 0), (0, 0));
+        let mut counterparties = HashSet::new();
+        for entry in summary.as_array().unwrap() {
+            let tx = get_json(&format!("/tx/{}", entry["txid"].as_str().unwrap()))?;
+            let value = entry["value"].as_i64().unwrap();
+            let addresses: Vec<&Value> = if value > 0 {
+                received = (received.0 + 1, received.1 + value as u64);
+                let vin = tx["vin"].as_array().unwrap();
+                vin.iter().map(|vin| &vin["prevout"]["scriptpubkey_address"]).collect()
+            } else {
+                sent = (sent.0 + 1, sent.1 + value.unsigned_abs());
+                let vout = tx["vout"].as_array().unwrap();
+                vout.iter().map(|vout| &vout["scriptpubkey_address"]).collect()
+            };
+            let addresses = addresses.into_iter().filter_map(|addr| addr.as_str());
+            counterparties.extend(addresses.map(String::from));
+        }
+        counterparties.remove(&addr1.to_string());
+        assert_eq!(res["received_count"].as_u64(), Some(received.0));
+        assert_eq!(res["received_value"].as_u64(), Some(received.1));
+        assert_eq!(res["sent_count"].as_u64(), Some(sent.0));
+        assert_eq!(res["sent_value"].as_u64(), Some(sent.1));
+        assert_eq!(
+            res["counterparty_count"].as_u64(),
+            Some(counterparties.len() as u64)
+        );
+    }
+
+    // Test GET /address/:address/delta
+    #[cfg(not(feature = "liquid"))]
+    {
+        let res = get_json(&format!("/address/{}/delta?since_height=102", addr1))?;
+        assert_eq!(res["tip_height"].as_u64(), Some(102));
+        let events = res["events"].as_array().expect("array of events");
+        let find = |txid: &Txid| {
+            events
+                .iter()
+                .find(|event| event["txid"].as_str() == Some(txid.to_string().as_str()))
+                .expect("history event")
+        };
+        let funding = find(&txid1_confirmed);
+        assert_eq!(funding["type"].as_str(), Some("funding"));
+        assert_eq!(funding["height"].as_u64(), Some(102));
+        assert_eq!(funding["value"].as_u64(), Some(119123000));
+        assert!(find(&txid2_mempool)["height"].is_null());
+        assert_eq!(res["truncated"].as_bool(), Some(false));
+        assert!(res["next_since_height"].is_null());
+        // past the maximum, the unconfirmed events are left for the next call
+        let scripthash = compute_script_hash(&addr1.script_pubkey());
+        let (events, next_since_height) = tester.query().history_delta(&scripthash, 0, 1)?;
+        assert_eq!(events.len(), 1);
+        assert_eq!(next_since_height, Some(103));
+        // and blocks aren't split across calls
+        assert!(tester.query().history_delta(&scripthash, 0, 0).is_err());
+        // addr1 has no history before
+        let res = get_json(&format!("/address/{}/delta?since_height=0", addr1))?;
+        assert_eq!(res["events"].as_array(), Some(events));
+        // only the unconfirmed events are left when resuming past the tip
+        let res = get_json(&format!("/address/{}/delta?since_height=103", addr1))?;
+        let events = res["events"].as_array().unwrap();
+        assert!(!events.is_empty());
+        assert!(events.iter().all(|event| event["height"].is_null()));
+    }
+
+    // Test the block template (through Query, as GET /mining/template is an admin endpoint)
+    #[cfg(not(feature = "liquid"))]
+    {
+        let template = tester.query().block_template()?;
+        assert_eq!(template["height"].as_u64(), Some(103));
+        let txs = template["transactions"].as_array().expect("array of txs");
+        let tx = txs
+            .iter()
+            .find(|tx| tx["txid"].as_str() == Some(txid2_mempool.to_string().as_str()))
+            .expect("template tx");
+        assert!(tx["feerate"].as_f64().unwrap() > 0.0);
+        let mempool_count = template["mempool_count"].as_u64().unwrap();
+        assert!(tx["mempool_rank"].as_u64().unwrap() < mempool_count);
+        // the template doesn't change without new txs or blocks
+        let template_hash = template["template_hash"].as_str().unwrap();
+        let timeout = std::time::Duration::from_secs(0);
+        let res = tester.query().wait_template_change(Some(template_hash), timeout)?;
+        assert_eq!(res["template_hash"].as_str(), Some(template_hash));
+        let res = tester.query().wait_template_change(Some("unknown"), timeout)?;
+        assert_eq!(res["template_hash"].as_str(), Some(template_hash));
+        let res = ureq::get(&format!("http://{}/mining/template", rest_addr)).call();
+        assert!(matches!(res, Err(ureq::Error::Status(403, _))));
+        let res = ureq::get(&format!("http://{}/mining/template/wait", rest_addr)).call();
+        assert!(matches!(res, Err(ureq::Error::Status(403, _))));
+    }
+
+    // Test GET /txs/large
+    #[cfg(not(feature = "liquid"))]
+    {
+        let res = get_json("/txs/large?since=102&limit=100")?;
+        let txs = res.as_array().expect("array of large txs");
+        let find = |txid: &Txid| {
+            txs.iter()
+                .find(|tx| tx["txid"].as_str() == Some(txid.to_string().as_str()))
+                .expect("large tx")
+        };
+        assert_eq!(find(&txid1_confirmed)["status"]["block_height"].as_u64(), Some(102));
+        assert_eq!(find(&txid2_mempool)["status"]["confirmed"].as_bool(), Some(false));
+        assert!(txs.iter().all(|tx| tx["value"].as_u64().unwrap() >= 100_000_000));
+    }
+
+    // Test GET /search/outputs
+    #[cfg(not(feature = "liquid"))]
+    {
+        let find = |res: &Value, txid: &Txid| {
+            res.as_array()
+                .expect("array of outputs")
+                .iter()
+                .find(|output| output["txid"].as_str() == Some(txid.to_string().as_str()))
+                .cloned()
+        };
+        let res = get_json("/search/outputs?value=119123000")?;
+        let output = find(&res, &txid1_confirmed).expect("confirmed output");
+        assert_eq!(output["value"].as_u64(), Some(119123000));
+        assert_eq!(output["status"]["block_height"].as_u64(), Some(102));
+        let res = get_json("/search/outputs?value=71130000")?;
+        let output = find(&res, &txid2_mempool).expect("mempool output");
+        assert_eq!(output["status"]["confirmed"].as_bool(), Some(false));
+        let res = get_json("/search/outputs?value=119123000&until=101")?;
+        assert!(find(&res, &txid1_confirmed).is_none());
+    }
+
+    // Test POST /scripthashes/status
+    let mut addr1_scripthash = compute_script_hash(&addr1.script_pubkey());
+    addr1_scripthash.reverse();
+    let addr1_scripthash = addr1_scripthash
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<String>();
+    let res = ureq::post(&format!("http://{}/scripthashes/status", rest_addr))
+        .send_json(serde_json::json!([addr1_scripthash, "00".repeat(32)]))?
+        .into_json::<Value>()?;
+    let statuses = res.as_array().expect("array of statuses");
+    assert_eq!(statuses.len(), 2);
+    assert_eq!(
+        statuses[0]["scripthash"].as_str(),
+        Some(addr1_scripthash.as_str())
+    );
+    assert_eq!(statuses[0]["status"].as_str().map(str::len), Some(64));
+    assert!(statuses[1]["status"].is_null());
+
+    // Test POST /scripthashes/delta
+    #[cfg(not(feature = "liquid"))]
+    {
+        let res = ureq::post(&format!("http://{}/scripthashes/delta", rest_addr))
+            .send_json(serde_json::json!([
+                { "scripthash": addr1_scripthash, "since_height": 0 },
+                { "scripthash": "00".repeat(32), "since_height": 0 },
+            ]))?
+            .into_json::<Value>()?;
+        let deltas = res["deltas"].as_array().expect("array of deltas");
+        assert_eq!(deltas.len(), 2);
+        let address_delta = get_json(&format!("/address/{}/delta?since_height=0", addr1))?;
+        assert_eq!(deltas[0]["events"], address_delta["events"]);
+        assert!(deltas[1]["events"].as_array().unwrap().is_empty());
+        assert_eq!(res["tip_height"], address_delta["tip_height"]);
+    }
+
+    // Test POST /tools/batching-report
+    #[cfg(not(feature = "liquid"))]
+    {
+        let payments = serde_json::json!([
+            { "address": addr1.to_string(), "amount": 100000 },
+            { "address": addr1.to_string(), "amount": 200000 },
+            { "address": addr1.to_string(), "amount": 300000 },
+        ]);
+        let res = ureq::post(&format!(
+            "http://{}/tools/batching-report?feerate=10",
+            rest_addr
+        ))
+        .send_json(payments.clone())?
+        .into_json::<Value>()?;
+        assert_eq!(res["payment_count"].as_u64(), Some(3));
+        assert_eq!(res["payment_value"].as_u64(), Some(600000));
+        assert_eq!(res["input_type"].as_str(), Some("p2pkh"));
+        assert!(res["conf_target"].is_null());
+        let vsize = res["payments"][0]["vsize"].as_u64().unwrap();
+        assert_eq!(res["payments"][0]["fee"].as_u64(), Some(10 * vsize));
+        assert_eq!(res["individual"]["tx_count"].as_u64(), Some(3));
+        assert_eq!(res["individual"]["vsize"].as_u64(), Some(3 * vsize));
+        assert_eq!(res["batched"]["tx_count"].as_u64(), Some(1));
+        let batched_vsize = res["batched"]["vsize"].as_u64().unwrap();
+        assert!(batched_vsize < 2 * vsize);
+        assert_eq!(res["batched"]["fee"].as_u64(), Some(10 * batched_vsize));
+        assert_eq!(
+            res["fee_savings"].as_u64(),
+            Some(10 * (3 * vsize - batched_vsize))
+        );
+
+        // a segwit input and change output make for smaller transactions
+        let res = ureq::post(&format!(
+            "http://{}/tools/batching-report?feerate=10&input_type=p2wpkh",
+            rest_addr
+        ))
+        .send_json(payments.clone())?
+        .into_json::<Value>()?;
+        assert_eq!(res["input_type"].as_str(), Some("p2wpkh"));
+        assert!(res["payments"][0]["vsize"].as_u64().unwrap() < vsize);
+
+        // the feerate defaults to the estimate for 6 blocks
+        let res = ureq::post(&format!("http://{}/tools/batching-report", rest_addr))
+            .send_json(payments.clone())?
+            .into_json::<Value>()?;
+        assert_eq!(res["conf_target"].as_u64(), Some(6));
+        assert!(res["feerate"].as_f64().unwrap() > 0.0);
+
+        for payments in &[
+            serde_json::json!([]),
+            serde_json::json!([{ "address": "invalid", "amount": 1 }]),
+            serde_json::json!([
+                { "address": addr1.to_string(), "amount": u64::MAX },
+                { "address": addr1.to_string(), "amount": 1 },
+            ]),
+        ] {
+            let res = ureq::post(&format!("http://{}/tools/batching-report", rest_addr))
+                .send_json(payments.clone());
+            assert!(matches!(res, Err(ureq::Error::Status(400, _))));
+        }
+        let res = ureq::post(&format!(
+            "http://{}/tools/batching-report?feerate=10&input_type=p2pk",
+            rest_addr
+        ))
+        .send_json(payments);
+        assert!(matches!(res, Err(ureq::Error::Status(400, _))));
+    }
 
     // Test GET /address-prefix/:prefix
     let addr1_prefix = &addr1.to_string()[0..8];
@@ -100,6 +561,444 @@ fn test_rest() -> Result<()> {
         bestblockheight
     );
 
+    // Test GET /blocks/tip
+    let res = get_json("/blocks/tip")?;
+    let info: Value = tester
+        .node_client()
+        .call("getblockheader", &[bestblockhash.to_string().into()])?;
+    assert_eq!(res["height"].as_u64(), Some(bestblockheight));
+    assert_eq!(res["hash"].as_str(), Some(bestblockhash.to_string().as_str()));
+    assert_eq!(res["timestamp"], info["time"]);
+    assert_eq!(res["mediantime"], info["mediantime"]);
+    #[cfg(not(feature = "liquid"))]
+    {
+        let next_retarget_height = res["next_retarget_height"].as_u64().unwrap();
+        assert_eq!(next_retarget_height % 10, 0);
+        assert!(next_retarget_height > bestblockheight);
+        assert_eq!(
+            res["blocks_until_retarget"].as_u64(),
+            Some(next_retarget_height - bestblockheight)
+        );
+        // regtest blocks are found way faster than targeted, the estimate is capped
+        let difficulty = res["difficulty"].as_f64().unwrap();
+        let estimated = res["estimated_next_difficulty"].as_f64().unwrap();
+        if bestblockheight % 10 == 0 {
+            assert_eq!(estimated, difficulty);
+        } else {
+            assert!((estimated - difficulty * 4.0).abs() < difficulty * 1e-9);
+        }
+    }
+
+    // Test GET /mining/difficulty/epochs
+    #[cfg(not(feature = "liquid"))]
+    {
+        let res = get_json("/mining/difficulty/epochs?limit=3")?;
+        let epochs = res.as_array().expect("array of epochs");
+        assert_eq!(epochs.len(), 3);
+        let current_start = bestblockheight - bestblockheight % 10;
+        assert_eq!(epochs[0]["start_height"].as_u64(), Some(current_start));
+        assert_eq!(epochs[0]["end_height"].as_u64(), Some(bestblockheight));
+        assert_eq!(epochs[0]["complete"].as_bool(), Some(false));
+        assert_eq!(epochs[1]["start_height"].as_u64(), Some(current_start - 10));
+        assert_eq!(epochs[1]["end_height"].as_u64(), Some(current_start - 1));
+        assert_eq!(epochs[1]["complete"].as_bool(), Some(true));
+        // regtest never retargets
+        assert_eq!(epochs[1]["adjustment"].as_f64(), Some(1.0));
+        assert!(epochs[1]["average_block_interval"].as_f64().is_some());
+        // the completed epochs are served from the cache the second time
+        let res = get_json("/mining/difficulty/epochs?limit=3")?;
+        assert_eq!(res.as_array(), Some(epochs));
+        let res = get_json("/mining/difficulty/epochs")?;
+        assert_eq!(res.as_array().unwrap().len() as u64, bestblockheight / 10 + 1);
+    }
+
+    // Test GET /block-time/:timestamp
+    {
+        let tip_time = info["time"].as_u64().unwrap();
+        let res = get_json(&format!("/block-time/{}", tip_time))?;
+        let height = res["height"].as_u64().unwrap();
+        assert!(height <= bestblockheight);
+        assert!(res["timestamp"].as_u64().unwrap() >= tip_time);
+        let hash = get_plain(&format!("/block-height/{}", height))?;
+        assert_eq!(res["hash"].as_str(), Some(hash.as_str()));
+
+        // Test POST /blocks/timestamps
+        let res = ureq::post(&format!("http://{}/blocks/timestamps", rest_addr))
+            .send_json(serde_json::json!([bestblockheight, 0, bestblockheight + 1]))?
+            .into_json::<Value>()?;
+        assert_eq!(res, serde_json::json!([tip_time, res[1], null]));
+        assert!(res[1].as_u64().unwrap() <= tip_time);
+        let res = ureq::post(&format!("http://{}/blocks/timestamps", rest_addr))
+            .send_json(serde_json::json!(vec![0; 101]));
+        assert!(matches!(res, Err(ureq::Error::Status(400, _))));
+
+        let res = get_json("/block-time/0")?;
+        assert_eq!(res["height"].as_u64(), Some(0));
+        let path = format!("/block-time/{}", tip_time + 3600);
+        let res = ureq::get(&format!("http://{}{}", rest_addr, path)).call();
+        assert!(matches!(res, Err(ureq::Error::Status(404, _))));
+    }
+
+    // Test GET /block/:hash/raw.gz and /blocks/raw.gz
+    let gunzip = |bytes: Vec<u8>| -> Result<Vec<u8>> {
+        let mut raw = vec![];
+        GzDecoder::new(&bytes[..]).read_to_end(&mut raw)?;
+        Ok(raw)
+    };
+    let raw_tip = get_bytes(&format!("/block/{}/raw", bestblockhash))?;
+    let res = get_bytes(&format!("/block/{}/raw.gz", bestblockhash))?;
+    assert_eq!(gunzip(res)?, raw_tip);
+    let res = get_bytes(&format!(
+        "/blocks/raw.gz?from={}&to={}",
+        bestblockheight - 1,
+        bestblockheight
+    ))?;
+    let raw_range = gunzip(res)?;
+    assert!(raw_range.len() > raw_tip.len());
+    assert!(raw_range.ends_with(&raw_tip));
+
+    // Test Range requests on GET /block/:hash/raw
+    let resp = ureq::get(&format!("http://{}/block/{}/raw", rest_addr, bestblockhash))
+        .set("Range", "bytes=0-79")
+        .call()?;
+    assert_eq!(resp.status(), 206);
+    assert_eq!(
+        resp.header("Content-Range"),
+        Some(format!("bytes 0-79/{}", raw_tip.len()).as_str())
+    );
+    let mut header = vec![];
+    resp.into_reader().read_to_end(&mut header)?;
+    assert_eq!(header, raw_tip[..80]);
+    let res = ureq::get(&format!("http://{}/block/{}/raw", rest_addr, bestblockhash))
+        .set("Range", &format!("bytes={}-", raw_tip.len()))
+        .call();
+    assert!(matches!(res, Err(ureq::Error::Status(416, _))));
+
+    // Test GET /stats/blocks
+    let res = get_json(&format!("/stats/blocks?from=1&to={}&interval=10", bestblockheight))?;
+    let points = res.as_array().expect("array of block stats");
+    assert_eq!(points.len() as u64, (bestblockheight + 9) / 10);
+    assert_eq!(points[0]["start_height"].as_u64(), Some(1));
+    assert_eq!(points[0]["block_count"].as_u64(), Some(10));
+    let last = points.last().unwrap();
+    assert_eq!(last["end_height"].as_u64(), Some(bestblockheight));
+    let tx_count: u64 = points.iter().map(|p| p["tx_count"].as_u64().unwrap()).sum();
+    assert!(tx_count > bestblockheight);
+    // the test wallet spends segwit outputs
+    #[cfg(not(feature = "liquid"))]
+    {
+        let segwit_count: u64 =
+            points.iter().map(|p| p["segwit_input_count"].as_u64().unwrap()).sum();
+        assert!(segwit_count > 0);
+        assert!(points.iter().all(|p| {
+            p["taproot_input_count"].as_u64() <= p["segwit_input_count"].as_u64()
+                && p["segwit_percent"].as_f64().map_or(true, |percent| percent <= 100.0)
+        }));
+        let res = get_json(&format!("/block/{}", tx1_blockhash))?;
+        assert!(res["segwit_spend_percent"].as_f64().unwrap() > 0.0);
+        assert!(res["taproot_spend_percent"].as_f64().is_some());
+    }
+
+    // Test GET /block/:hash/cdd and /stats/cdd
+    let res = get_json(&format!("/block/{}/cdd", bestblockhash))?;
+    assert_eq!(res["height"].as_u64(), Some(bestblockheight));
+    assert!(res["coin_days_destroyed"].as_f64().unwrap() >= 0.0);
+    let res = get_json("/stats/cdd?window=10")?;
+    assert_eq!(res["end_height"].as_u64(), Some(bestblockheight));
+    assert_eq!(res["block_count"].as_u64(), Some(10));
+    assert!(res["coin_days_destroyed"].as_f64().unwrap() >= 0.0);
+
+    // Test skipping the block stats of an unknown layout
+    {
+        let chain = tester.query().chain();
+        let height = chain.best_height();
+        let key = [&b"G"[..], &(height as u32).to_be_bytes(), &[u8::MAX]].concat();
+        let row = DBRow {
+            key,
+            value: vec![0; 8],
+        };
+        chain.store().history_db().write(vec![row], DBFlush::Enable);
+        assert_eq!(chain.block_stats(height, height).len(), 1);
+    }
+
+    // Test GET /stats/multisig
+    // (the test wallet doesn't use multisig, so there is nothing to count)
+    #[cfg(not(feature = "liquid"))]
+    {
+        let res = get_json(&format!("/stats/multisig?from=0&to={}", bestblockheight))?;
+        assert_eq!(res["start_height"].as_u64(), Some(0));
+        assert_eq!(res["end_height"].as_u64(), Some(bestblockheight));
+        assert_eq!(res["count"].as_u64(), Some(0));
+        assert_eq!(res["multisig"].as_array().map(Vec::len), Some(0));
+    }
+
+    // Test GET /stats/lightning
+    // (no channels either, but the windows still cover the whole range)
+    #[cfg(not(feature = "liquid"))]
+    {
+        let res = get_json("/stats/lightning?from=0&interval=10")?;
+        assert_eq!(res["end_height"].as_u64(), Some(bestblockheight));
+        let windows = res["windows"].as_array().expect("array of windows");
+        assert_eq!(windows.len() as u64, bestblockheight / 10 + 1);
+        assert_eq!(windows[0]["end_height"].as_u64(), Some(9));
+        for window in windows {
+            assert_eq!(window["channel_opens"].as_u64(), Some(0));
+            assert_eq!(window["cooperative_closes"].as_u64(), Some(0));
+            assert_eq!(window["force_closes"].as_u64(), Some(0));
+        }
+    }
+
+    // Test GET /v1/validate-address/:addr
+    #[cfg(not(feature = "liquid"))]
+    {
+        let res = get_json(&format!("/v1/validate-address/{}", addr1))?;
+        assert_eq!(res["isvalid"].as_bool(), Some(true));
+        assert_eq!(res["address"].as_str(), Some(addr1.to_string().as_str()));
+        assert_eq!(
+            res["scriptPubKey"].as_str(),
+            Some(addr1.script_pubkey().to_hex_string().as_str())
+        );
+        assert_eq!(res["scriptpubkey_type"].as_str(), Some("v0_p2wpkh"));
+        assert_eq!(res["iswitness"].as_bool(), Some(true));
+        assert_eq!(res["witness_version"].as_u64(), Some(0));
+        assert_eq!(res["witness_program"].as_str().map(str::len), Some(40));
+
+        let res = get_json("/v1/validate-address/notanaddress")?;
+        assert_eq!(res["isvalid"].as_bool(), Some(false));
+        assert!(res["error"].is_string());
+        assert!(res.get("scriptPubKey").is_none());
+    }
+
+    // Test GET /stats/dust
+    #[cfg(not(feature = "liquid"))]
+    {
+        let res = get_json("/stats/dust")?;
+        assert_eq!(res["feerate"].as_u64(), Some(3));
+        assert_eq!(res["height"].as_u64(), Some(bestblockheight));
+        let utxo_count = res["utxo_count"].as_u64().unwrap();
+        assert!(utxo_count > 0);
+        assert!(res["dust_count"].as_u64().unwrap() <= utxo_count);
+        // every output is dust at a high enough feerate
+        let res = get_json("/stats/dust?feerate=100000000000")?;
+        assert_eq!(res["dust_count"].as_u64(), Some(utxo_count));
+        assert_eq!(res["dust_value"], res["utxo_value"]);
+    }
+
+    // Test GET /stats/utxo-types
+    #[cfg(not(feature = "liquid"))]
+    {
+        let dust = get_json("/stats/dust")?;
+        let res = get_json("/stats/utxo-types")?;
+        assert_eq!(res["height"].as_u64(), Some(bestblockheight));
+        let types = res["types"].as_object().expect("object of utxo types");
+        assert_eq!(types.len(), 6);
+        assert!(types["v0_p2wpkh"]["count"].as_u64().unwrap() > 0);
+        let count: u64 = types.values().map(|t| t["count"].as_u64().unwrap()).sum();
+        let value: u64 = types.values().map(|t| t["value"].as_u64().unwrap()).sum();
+        assert_eq!(Some(count), dust["utxo_count"].as_u64());
+        assert_eq!(Some(value), dust["utxo_value"].as_u64());
+
+        let res = get_json("/stats/utxo-types/history")?;
+        let snapshots = res.as_array().expect("array of snapshots");
+        let heights: Vec<u64> = snapshots.iter().map(|s| s["height"].as_u64().unwrap()).collect();
+        assert!(heights.iter().all(|height| height % 100 == 0));
+        assert_eq!(heights.contains(&100), bestblockheight >= 110);
+        let res = ureq::get(&format!("http://{}/stats/utxo-types/history?interval=150", rest_addr))
+            .call();
+        assert!(matches!(res, Err(ureq::Error::Status(400, _))));
+    }
+
+    // Test GET /stats/daily
+    let res = get_json("/stats/daily?days=2")?;
+    let days = res.as_array().expect("array of daily stats");
+    assert!(!days.is_empty());
+    let last = days.last().unwrap();
+    let block_count = last["block_count"].as_u64().unwrap();
+    assert!(block_count > 0);
+    assert!(last["tx_count"].as_u64().unwrap() >= block_count);
+    assert_eq!(last["date"].as_str().unwrap().len(), 10);
+
+    // Test GET /stats/block-intervals
+    let res = get_json("/stats/block-intervals?window=5")?;
+    assert_eq!(res["block_count"].as_u64(), Some(5));
+    let min_interval = res["min_interval"].as_i64().unwrap();
+    assert!(res["median_interval"].as_f64().unwrap() >= min_interval as f64);
+    assert!(res["max_interval"].as_i64().unwrap() >= min_interval);
+    assert_eq!(
+        res["anomalies"].as_array().map(Vec::len),
+        res["anomaly_count"].as_u64().map(|count| count as usize)
+    );
+
+    // Test GET /stats/version-bits
+    #[cfg(not(feature = "liquid"))]
+    {
+        let res = get_json("/stats/version-bits?window=10")?;
+        assert_eq!(res["block_count"].as_u64(), Some(10));
+        for signaling in res["bits"].as_array().expect("array of bits") {
+            assert!(signaling["count"].as_u64().unwrap() <= 10);
+        }
+        // the chain is still in its first period
+        let deployment = &res["deployments"][0];
+        assert_eq!(deployment["name"].as_str(), Some("testdummy"));
+        assert_eq!(deployment["state"].as_str(), Some("defined"));
+        assert_eq!(deployment["threshold"].as_u64(), Some(137));
+        assert_eq!(
+            deployment["period_elapsed"].as_u64(),
+            Some(res["end_height"].as_u64().unwrap() + 1)
+        );
+    }
+
+    // Test GET /network/forks (the test node monitors itself as a secondary daemon)
+    let res = get_json("/network/forks")?;
+    let daemons = res["daemons"].as_array().expect("array of daemons");
+    assert_eq!(daemons.len(), 1);
+    assert_eq!(daemons[0]["status"].as_str(), Some("synced"));
+    assert_eq!(daemons[0]["hash"], res["tip"]["hash"]);
+    assert_eq!(daemons[0]["divergence"].as_u64(), Some(0));
+    assert_eq!(res["alert"].as_bool(), Some(false));
+    assert_eq!(res["competing_tips"].as_array().map(Vec::len), Some(0));
+
+    // Test HEAD and OPTIONS requests
+    let url = format!("http://{}/block/{}", rest_addr, bestblockhash);
+    let body = ureq::get(&url).call()?.into_string()?;
+    let resp = ureq::head(&url).call()?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.header("Content-Type"), Some("application/json"));
+    assert_eq!(
+        resp.header("Content-Length"),
+        Some(body.len().to_string().as_str())
+    );
+    assert_eq!(resp.into_string()?, "");
+    let resp = ureq::request("OPTIONS", &url).call()?;
+    assert_eq!(resp.status(), 204);
+    assert_eq!(resp.header("Allow"), Some("GET, HEAD, POST, OPTIONS"));
+
+    // Test CORS preflight and response headers
+    let preflight = |origin: &str, method: &str| {
+        ureq::request("OPTIONS", &format!("http://{}/tx", rest_addr))
+            .set("Origin", origin)
+            .set("Access-Control-Request-Method", method)
+            .call()
+    };
+    let resp = preflight("https://app.example.com", "POST")?;
+    assert_eq!(resp.status(), 204);
+    assert_eq!(
+        resp.header("Access-Control-Allow-Origin"),
+        Some("https://app.example.com")
+    );
+    assert_eq!(resp.header("Access-Control-Allow-Methods"), Some("GET, POST"));
+    assert_eq!(resp.header("Access-Control-Allow-Headers"), Some("Content-Type"));
+    assert_eq!(resp.header("Access-Control-Max-Age"), Some("600"));
+    let resp = preflight("https://admin.test", "DELETE")?;
+    assert_eq!(resp.header("Access-Control-Allow-Methods"), Some("GET, DELETE"));
+    let resp = preflight("https://example.org", "POST")?;
+    assert_eq!(resp.status(), 204);
+    assert_eq!(resp.header("Access-Control-Allow-Origin"), None);
+    let resp = ureq::get(&format!("http://{}/blocks/tip/height", rest_addr))
+        .set("Origin", "https://app.example.com")
+        .call()?;
+    assert_eq!(
+        resp.header("Access-Control-Allow-Origin"),
+        Some("https://app.example.com")
+    );
+    let resp = ureq::get(&format!("http://{}/blocks/tip/height", rest_addr))
+        .set("Origin", "https://example.org")
+        .call()?;
+    assert_eq!(resp.header("Access-Control-Allow-Origin"), None);
+    // error responses and methods outside of the allowed ones get the CORS headers too
+    let res = ureq::get(&format!("http://{}/tx/{}", rest_addr, "00".repeat(32)))
+        .set("Origin", "https://app.example.com")
+        .call();
+    let resp = match res {
+        Err(ureq::Error::Status(404, resp)) => resp,
+        _ => panic!("expected a 404"),
+    };
+    assert_eq!(
+        resp.header("Access-Control-Allow-Origin"),
+        Some("https://app.example.com")
+    );
+    let resp = ureq::request("OPTIONS", &format!("http://{}/tx", rest_addr))
+        .set("Origin", "https://app.example.com")
+        .call()?;
+    assert_eq!(
+        resp.header("Access-Control-Allow-Origin"),
+        Some("https://app.example.com")
+    );
+
+    // Test X-Tip-Hash header and ?at_tip guard
+    let resp = ureq::get(&format!("http://{}/blocks/tip/height", rest_addr)).call()?;
+    assert_eq!(
+        resp.header("X-Tip-Hash"),
+        Some(bestblockhash.to_string().as_str())
+    );
+    let res = ureq::get(&format!(
+        "http://{}/blocks/tip/height?at_tip={}",
+        rest_addr, txid1_confirmed
+    ))
+    .call();
+    assert!(matches!(res, Err(ureq::Error::Status(409, _))));
+
+    // Test X-Request-Id propagation
+    let resp = ureq::get(&format!("http://{}/blocks/tip/height", rest_addr))
+        .set("X-Request-Id", "test-request-1")
+        .call()?;
+    assert_eq!(resp.header("X-Request-Id"), Some("test-request-1"));
+    let resp = ureq::get(&format!("http://{}/blocks/tip/height", rest_addr)).call()?;
+    assert!(resp.header("X-Request-Id").is_some());
+
+    // Test binary response format negotiation
+    let resp = ureq::get(&format!("http://{}/blocks/tip/wait?timeout=0", rest_addr))
+        .set("Accept", "application/cbor")
+        .call()?;
+    assert_eq!(resp.header("Content-Type"), Some("application/cbor"));
+    let resp = ureq::get(&format!("http://{}/blocks/tip/wait?timeout=0", rest_addr))
+        .set("Accept", "application/msgpack;q=0.9, application/json;q=0.8")
+        .call()?;
+    assert_eq!(resp.header("Content-Type"), Some("application/msgpack"));
+
+    // Test the request budgets (the tiny-budget key gets 1 cost unit per minute)
+    let budgeted = || {
+        ureq::get(&format!("http://{}/address/{}/txs", rest_addr, addr1))
+            .set("X-Api-Key", "tiny-budget")
+            .call()
+    };
+    budgeted()?;
+    match budgeted() {
+        Err(ureq::Error::Status(429, resp)) => {
+            let retry_after: u64 = resp.header("Retry-After").unwrap().parse().unwrap();
+            assert!((1..=60).contains(&retry_after));
+        }
+        res => panic!("expected a 429, got {:?}", res.map(|resp| resp.status())),
+    }
+    // unknown keys are charged to the IP, which has no budget
+    ureq::get(&format!("http://{}/address/{}/txs", rest_addr, addr1))
+        .set("X-Api-Key", "unknown")
+        .call()?;
+
+    // Test that the admin endpoints are disabled by default
+    let res = ureq::get(&format!("http://{}/admin/electrum/connections", rest_addr)).call();
+    assert!(matches!(res, Err(ureq::Error::Status(403, _))));
+
+    // Test GET /blocks/tip/wait
+    let res = get_json(&format!("/blocks/tip/wait?known={}&timeout=0", bestblockhash))?;
+    assert_eq!(res["hash"].as_str(), Some(bestblockhash.to_string().as_str()));
+    assert_eq!(res["changed"].as_bool(), Some(false));
+    let res = get_json(&format!("/blocks/tip/wait?known={}", txid1_confirmed))?;
+    assert_eq!(res["changed"].as_bool(), Some(true));
+    assert_eq!(res["height"].as_u64(), Some(bestblockheight));
+
+    // Test GET /tx/:txid/wait
+    let res = get_json(&format!("/tx/{}/wait?confirmations=1", txid1_confirmed))?;
+    assert_eq!(res["timed_out"].as_bool(), Some(false));
+    assert_eq!(res["confirmations"].as_u64(), Some(2));
+    let res = get_json(&format!("/tx/{}/wait?confirmations=5&timeout=0", txid2_mempool))?;
+    assert_eq!(res["timed_out"].as_bool(), Some(true));
+    assert_eq!(res["confirmations"].as_u64(), Some(1));
+    // unknown transactions fail right away rather than waiting for the timeout
+    let unknown_txid = "00".repeat(32);
+    let res = ureq::get(&format!("http://{}/tx/{}/wait", rest_addr, unknown_txid)).call();
+    assert!(matches!(res, Err(ureq::Error::Status(404, _))));
+
     // Test GET /block-height/:height
     let res = get_plain(&format!("/block-height/{}", bestblockheight))?;
     assert_eq!(res, bestblockhash.to_string());
@@ -133,6 +1032,24 @@ fn test_rest() -> Result<()> {
     );
     assert_eq!(res["tx_count"].as_u64(), Some(2));
 
+    // Test the chainwork of GET /block/:hash and GET /blocks/tip/chainwork
+    #[cfg(not(feature = "liquid"))]
+    {
+        let info: Value = tester
+            .node_client()
+            .call("getblockheader", &[blockhash.to_string().into()])?;
+        assert_eq!(res["chainwork"], info["chainwork"]);
+        assert_eq!(get_plain("/blocks/tip/chainwork")?, info["chainwork"].as_str().unwrap());
+        let prev = get_json(&format!("/block/{}", res["previousblockhash"].as_str().unwrap()))?;
+        // regtest chainwork fits in the lower 128 bits
+        let parse_work =
+            |work: &Value| u128::from_str_radix(&work.as_str().unwrap()[32..], 16).unwrap();
+        assert_eq!(
+            parse_work(&prev["chainwork"]) + parse_work(&res["work"]),
+            parse_work(&res["chainwork"])
+        );
+    }
+
     // Test GET /block/:hash/txs
     let res = get_json(&format!("/block/{}/txs", blockhash))?;
     let block_txs = res.as_array().expect("list of txs");
@@ -142,11 +1059,300 @@ fn test_rest() -> Result<()> {
         block_txs[1]["txid"].as_str(),
         Some(txid.to_string().as_str())
     );
+    assert_eq!(block_txs[0]["fee"].as_u64(), Some(0));
+    let fee = block_txs[1]["fee"].as_u64().expect("fee");
+    assert!(fee > 0);
+    let weight = block_txs[1]["weight"].as_u64().unwrap();
+    let feerate = (fee * 4000 / weight) as f64 / 1000.0;
+    assert_eq!(block_txs[1]["feerate"].as_f64(), Some(feerate));
+    // the prevouts are only resolved when asked for
+    assert!(block_txs[1]["vin"][0]["prevout"].is_null());
+
+    // Test GET /block/:hash/txs?prevouts=true
+    let res = get_json(&format!("/block/{}/txs?prevouts=true", blockhash))?;
+    let block_txs = res.as_array().expect("list of txs");
+    assert_eq!(block_txs[1]["fee"].as_u64(), Some(fee));
+    assert!(block_txs[1]["vin"][0]["prevout"]["value"].is_u64());
+
+    // Test GET /block/:hash/txs?fields=
+    let res = get_json(&format!(
+        "/block/{}/txs?fields=txid,fee,status.block_height,vout.value",
+        blockhash
+    ))?;
+    let block_txs = res.as_array().expect("list of txs");
+    assert_eq!(block_txs.len(), 2);
+    let selected = block_txs[1].as_object().unwrap();
+    assert_eq!(selected.len(), 4);
+    assert_eq!(selected["txid"].as_str(), Some(txid.to_string().as_str()));
+    assert_eq!(selected["fee"].as_u64(), Some(fee));
+    assert_eq!(
+        selected["status"],
+        serde_json::json!({ "block_height": tester.node_client().get_block_count()? })
+    );
+    let vout = selected["vout"].as_array().unwrap();
+    assert!(vout
+        .iter()
+        .all(|txout| txout.as_object().unwrap().keys().all(|key| key == "value")));
+    let res = ureq::get(&format!("http://{}/block/{}/txs?fields=txid,,fee", rest_addr, blockhash))
+        .call();
+    assert!(matches!(res, Err(ureq::Error::Status(400, _))));
+
+    // Test POST /headers/verify
+    #[cfg(not(feature = "liquid"))]
+    {
+        let tip_hash = get_plain("/blocks/tip/hash")?;
+        let tip_height: u64 = get_plain("/blocks/tip/height")?.parse().unwrap();
+        let node = tester.node_client();
+        let tip_header: String =
+            node.call("getblockheader", &[tip_hash.clone().into(), false.into()])?;
+        let tip_info: Value = node.call("getblockheader", &[tip_hash.clone().into()])?;
+        // headers building on the tip and on an unknown block, with their nonce ground to meet
+        // the regtest target. the forged one claims a target other than the one of the tip.
+        let mine_header = |prev_hash: &str, bits: Option<u32>| -> String {
+            use hex::{DisplayHex, FromHex};
+            let raw = Vec::<u8>::from_hex(&tip_header).unwrap();
+            let mut header: bitcoin::block::Header = bitcoin::consensus::deserialize(&raw).unwrap();
+            header.prev_blockhash = prev_hash.parse().unwrap();
+            if let Some(bits) = bits {
+                header.bits = bitcoin::CompactTarget::from_consensus(bits);
+            }
+            while header.validate_pow(header.target()).is_err() {
+                header.nonce += 1;
+            }
+            bitcoin::consensus::serialize(&header).to_lower_hex_string()
+        };
+        let next_header = mine_header(&tip_hash, None);
+        let orphan_header = mine_header(&"ab".repeat(32), None);
+        let forged_header = mine_header(&tip_hash, Some(0x2100ffff));
+
+        let res = ureq::post(&format!("http://{}/headers/verify", rest_addr))
+            .send_json(serde_json::json!([tip_header, next_header, orphan_header, forged_header]))?
+            .into_json::<Value>()?;
+        let verified = res.as_array().expect("array of headers");
+        assert_eq!(verified.len(), 4);
+        assert_eq!(verified[0]["hash"].as_str(), Some(tip_hash.as_str()));
+        assert_eq!(verified[0]["in_best_chain"].as_bool(), Some(true));
+        assert_eq!(verified[0]["connects"].as_bool(), Some(true));
+        assert_eq!(verified[0]["height"].as_u64(), Some(tip_height));
+        assert_eq!(verified[0]["chainwork"], tip_info["chainwork"]);
+        assert_eq!(verified[1]["in_best_chain"].as_bool(), Some(false));
+        assert_eq!(verified[1]["connects"].as_bool(), Some(true));
+        assert_eq!(verified[1]["height"].as_u64(), Some(tip_height + 1));
+        assert!(verified[1]["chainwork"].as_str() > verified[0]["chainwork"].as_str());
+        assert_eq!(verified[1]["valid_pow"].as_bool(), Some(true));
+        assert_eq!(verified[2]["connects"].as_bool(), Some(false));
+        assert!(verified[2]["valid_pow"].is_null());
+        assert!(verified[2]["height"].is_null());
+        assert!(verified[2]["chainwork"].is_null());
+        assert_eq!(verified[3]["connects"].as_bool(), Some(false));
+        assert_eq!(verified[3]["valid_pow"].as_bool(), Some(false));
+
+        let res = ureq::post(&format!("http://{}/headers/verify", rest_addr))
+            .send_json(serde_json::json!(["zz"]));
+        assert!(matches!(res, Err(ureq::Error::Status(400, _))));
+    }
 
     // Test GET /block/:hash/txid/:index
     let res = get_plain(&format!("/block/{}/txid/1", blockhash))?;
     assert_eq!(res, txid.to_string());
 
+    // Test GET /script/:hash/reveal
+    // (spending a p2sh-wrapped segwit output reveals its redeem script)
+    #[cfg(not(feature = "liquid"))]
+    {
+        let node = tester.node_client();
+        let p2sh_addr: String = node.call("getnewaddress", &["".into(), "p2sh-segwit".into()])?;
+        let funding_txid: Txid =
+            node.call("sendtoaddress", &[p2sh_addr.clone().into(), 0.5.into()])?;
+        tester.mine()?;
+        let funding_tx = get_json(&format!("/tx/{}", funding_txid))?;
+        let (vout, funding_txo) = funding_tx["vout"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .enumerate()
+            .find(|(_, vout)| vout["scriptpubkey_address"].as_str() == Some(p2sh_addr.as_str()))
+            .expect("p2sh output");
+        let spk = bitcoin::ScriptBuf::from_hex(funding_txo["scriptpubkey"].as_str().unwrap());
+        let scripthash = compute_script_hash(&spk.unwrap())
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>();
+        let res = ureq::get(&format!("http://{}/script/{}/reveal", rest_addr, scripthash)).call();
+        assert!(matches!(res, Err(ureq::Error::Status(404, _))));
+
+        let node = tester.node_client();
+        let rawtx: String = node.call(
+            "createrawtransaction",
+            &[
+                serde_json::json!([{ "txid": funding_txid, "vout": vout }]),
+                serde_json::json!({ addr1.to_string(): 0.4999 }),
+            ],
+        )?;
+        let signed: Value = node.call("signrawtransactionwithwallet", &[rawtx.into()])?;
+        let spending_txid: Txid = node.call("sendrawtransaction", &[signed["hex"].clone()])?;
+        tester.mine()?;
+
+        let res = get_json(&format!("/script/{}/reveal", scripthash))?;
+        assert_eq!(res["txid"].as_str(), Some(spending_txid.to_string().as_str()));
+        assert_eq!(res["vin"].as_u64(), Some(0));
+        assert_eq!(res["status"]["confirmed"].as_bool(), Some(true));
+        // the redeem script is the wrapped p2wpkh witness program
+        assert!(res["redeemscript_asm"]
+            .as_str()
+            .unwrap()
+            .starts_with("OP_0 OP_PUSHBYTES_20"));
+        assert!(res["witnessscript"].is_null());
+
+        // the spent prevout is labeled as nested segwit
+        let res = get_json(&format!("/tx/{}", spending_txid))?;
+        let prevout = &res["vin"][0]["prevout"];
+        assert_eq!(prevout["scriptpubkey_type"].as_str(), Some("p2sh"));
+        assert_eq!(prevout["spent_as"].as_str(), Some("p2sh-p2wpkh"));
+        assert!(funding_txo["spent_as"].is_null());
+    }
+
+    // Test GET /address/:address/stats?verbose=true
+    // (a taproot output of the wallet gets spent by its key path)
+    #[cfg(not(feature = "liquid"))]
+    {
+        let node = tester.node_client();
+        let tr_addr: String = node.call("getnewaddress", &["".into(), "bech32m".into()])?;
+        let res = get_json(&format!("/address/{}/stats", tr_addr))?;
+        assert!(res["spend_paths"].is_null());
+        let res = get_json(&format!("/address/{}/stats?verbose=true", tr_addr))?;
+        assert_eq!(res["spend_paths"].as_array().map(Vec::len), Some(0));
+
+        let funding_txid: Txid =
+            node.call("sendtoaddress", &[tr_addr.clone().into(), 0.5.into()])?;
+        tester.mine()?;
+        let funding_tx = get_json(&format!("/tx/{}", funding_txid))?;
+        let vout = funding_tx["vout"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .position(|vout| vout["scriptpubkey_address"].as_str() == Some(tr_addr.as_str()))
+            .expect("p2tr output");
+        let rawtx: String = node.call(
+            "createrawtransaction",
+            &[
+                serde_json::json!([{ "txid": funding_txid, "vout": vout }]),
+                serde_json::json!({ addr1.to_string(): 0.4999 }),
+            ],
+        )?;
+        let signed: Value = node.call("signrawtransactionwithwallet", &[rawtx.into()])?;
+        node.call::<Txid>("sendrawtransaction", &[signed["hex"].clone()])?;
+        tester.mine()?;
+
+        let res = get_json(&format!("/address/{}/stats?verbose=true", tr_addr))?;
+        assert_eq!(res["spent_txo_count"].as_u64(), Some(1));
+        let spend_paths = res["spend_paths"].as_array().unwrap();
+        assert_eq!(spend_paths.len(), 1);
+        assert_eq!(spend_paths[0]["path"].as_str(), Some("key_path"));
+        assert_eq!(spend_paths[0]["count"].as_u64(), Some(1));
+        assert!(spend_paths[0]["leaf_hash"].is_null());
+
+        // Test GET /flow
+        // (the taproot output was spent to addr1 directly)
+        let res = get_json(&format!("/flow?from={}&to={}&max_hops=2", tr_addr, addr1))?;
+        assert_eq!(res["found"].as_bool(), Some(true));
+        let paths = res["paths"].as_array().unwrap();
+        assert_eq!(paths.len(), 1);
+        assert_eq!(paths[0]["hops"].as_u64(), Some(1));
+        let outputs = paths[0]["outputs"].as_array().unwrap();
+        assert_eq!(outputs.len(), 1);
+        assert_eq!(outputs[0]["value"].as_u64(), Some(49_990_000));
+        assert_eq!(res["truncated"].as_bool(), Some(false));
+
+        let res = ureq::get(&format!(
+            "http://{}/flow?from={}&to={}&max_hops=0",
+            rest_addr, tr_addr, addr1
+        ))
+        .call();
+        assert!(matches!(res, Err(ureq::Error::Status(400, _))));
+    }
+
+    // Test GET /templates and /templates/:name/txs
+    // (the test config watches p2wpkh outputs, more templates get registered through the store
+    // as the admin API is disabled)
+    #[cfg(not(feature = "liquid"))]
+    {
+        let res = get_json("/templates")?;
+        assert_eq!(res[0]["name"].as_str(), Some("p2wpkh"));
+        assert_eq!(res[0]["pattern"].as_str(), Some("OP_0 <20>"));
+
+        let res = get_json("/templates/p2wpkh/txs?limit=2")?;
+        let first_page = res["items"].as_array().unwrap().clone();
+        assert_eq!(first_page.len(), 2);
+        for tx in &first_page {
+            assert!(tx["vout"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .any(|vout| vout["scriptpubkey_type"].as_str() == Some("v0_p2wpkh")));
+        }
+        let next_cursor = res["paging"]["next_cursor"].as_str().unwrap().to_string();
+        assert_eq!(next_cursor, first_page[1]["txid"].as_str().unwrap());
+        let res = get_json(&format!("/templates/p2wpkh/txs?limit=2&cursor={}", next_cursor))?;
+        let second_page = res["items"].as_array().unwrap();
+        assert!(!second_page.is_empty());
+        assert!(second_page.iter().all(|tx| !first_page.contains(tx)));
+
+        let res = ureq::get(&format!("http://{}/templates/p2tr/txs", rest_addr)).call();
+        assert!(matches!(res, Err(ureq::Error::Status(404, _))));
+
+        let store = tester.query().chain().store();
+        let p2tr = electrs::util::ScriptTemplate::parse("p2tr", "OP_PUSHNUM_1 <32>")?;
+        store.register_script_template(p2tr.clone())?;
+        assert!(store.register_script_template(p2tr).is_err());
+        assert!(electrs::util::ScriptTemplate::parse("bad", "OP_PUSHBYTES_20").is_err());
+
+        let node = tester.node_client();
+        let tr_addr: String = node.call("getnewaddress", &["".into(), "bech32m".into()])?;
+        let txid: Txid = node.call("sendtoaddress", &[tr_addr.into(), 0.1.into()])?;
+        tester.mine()?;
+        let res = get_json("/templates/p2tr/txs")?;
+        let items = res["items"].as_array().unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0]["txid"].as_str(), Some(txid.to_string().as_str()));
+    }
+
+    // Test the Cache-Control headers, with the overrides of the test config
+    {
+        let cache_control = |path: &str| -> Result<Option<String>> {
+            let resp = ureq::get(&format!("http://{}{}", rest_addr, path)).call()?;
+            Ok(resp.header("Cache-Control").map(str::to_string))
+        };
+        assert_eq!(cache_control("/mempool")?.as_deref(), Some("public, max-age=10"));
+        assert_eq!(cache_control("/blocks/tip/height")?.as_deref(), Some("public, max-age=2"));
+        assert_eq!(cache_control("/fee-estimates")?.as_deref(), Some("no-store"));
+        let genesis = get_plain("/block-height/0")?;
+        assert_eq!(
+            cache_control(&format!("/block/{}", genesis))?.as_deref(),
+            Some("public, max-age=157784630")
+        );
+    }
+
+    // Test the stale-while-revalidate cache: stale values are served while being recomputed
+    {
+        use electrs::util::SwrCache;
+        use std::time::Duration;
+        let cache = SwrCache::new(10);
+        let (ttl, max_stale) = (Duration::from_millis(200), Duration::from_secs(60));
+        let compute = |value: u32| move || -> std::result::Result<u32, ()> { Ok(value) };
+        assert_eq!(cache.get_or_compute("k".into(), ttl, max_stale, compute(1)), Ok(1));
+        assert_eq!(cache.get_or_compute("k".into(), ttl, max_stale, compute(2)), Ok(1));
+        std::thread::sleep(ttl);
+        // stale, refreshed in the background
+        assert_eq!(cache.get_or_compute("k".into(), ttl, max_stale, compute(3)), Ok(1));
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(cache.get_or_compute("k".into(), ttl, max_stale, compute(4)), Ok(3));
+        // past max_stale, computed synchronously
+        let no_stale = Duration::from_secs(0);
+        std::thread::sleep(ttl);
+        assert_eq!(cache.get_or_compute("k".into(), ttl, no_stale, compute(5)), Ok(5));
+    }
+
     // Test GET /mempool/txids
     let txid = tester.send(&addr1, "3.21 BTC".parse().unwrap())?;
     let res = get_json("/mempool/txids")?;
@@ -165,9 +1371,166 @@ fn test_rest() -> Result<()> {
     tester.send(&addr1, "0.00022 BTC".parse().unwrap())?;
     assert_eq!(get_json("/mempool")?["count"].as_u64(), Some(3));
 
+    // Test the mempool memory accounting (served by GET /admin/mempool-info)
+    {
+        let info = tester.query().mempool().info();
+        assert_eq!(info.count, 3);
+        assert!(info.memory_usage > 3 * 200);
+        assert_eq!(info.limit_txs, None);
+        assert_eq!(info.evicted, 0);
+        assert!(info.min_feerate.unwrap() > 0.0);
+    }
+
+    // Test GET /tx/:txid/ancestors and /tx/:txid/descendants
+    // (whether the wallet chained its txs is up to it, so only check that the graph is consistent)
+    let mempool_txids = get_json("/mempool/txids")?;
+    let mempool_txids = mempool_txids.as_array().expect("list of txids");
+    for mempool_txid in mempool_txids {
+        let mempool_txid = mempool_txid.as_str().unwrap();
+        let ancestors = get_json(&format!("/tx/{}/ancestors", mempool_txid))?;
+        for ancestor in ancestors.as_array().expect("list of ancestors") {
+            assert!(mempool_txids.contains(&ancestor["txid"]));
+            assert!(ancestor["depth"].as_u64().unwrap() >= 1);
+            assert!(ancestor["vsize"].as_u64().unwrap() > 0);
+        }
+        let descendants = get_json(&format!("/tx/{}/descendants", mempool_txid))?;
+        for descendant in descendants.as_array().expect("list of descendants") {
+            let res = get_json(&format!("/tx/{}/ancestors", descendant["txid"].as_str().unwrap()))?;
+            assert!(res
+                .as_array()
+                .unwrap()
+                .iter()
+                .any(|ancestor| ancestor["txid"].as_str() == Some(mempool_txid)));
+        }
+        let res = get_json(&format!("/tx/{}/descendants?depth=0", mempool_txid))?;
+        assert_eq!(res.as_array().map(Vec::len), Some(0));
+    }
+
+    // Test GET /tx/:txid/graph
+    #[cfg(not(feature = "liquid"))]
+    {
+        let res = get_json(&format!("/tx/{}/graph?direction=upstream&depth=1", txid))?;
+        let nodes = res["nodes"].as_array().expect("list of nodes");
+        let edges = res["edges"].as_array().expect("list of edges");
+        assert_eq!(nodes[0]["txid"].as_str(), Some(txid.to_string().as_str()));
+        assert_eq!(nodes[0]["depth"].as_u64(), Some(0));
+        assert_eq!(nodes[0]["status"]["confirmed"].as_bool(), Some(false));
+        assert!(nodes[1..].iter().all(|node| node["depth"].as_u64() == Some(1)));
+        assert!(!edges.is_empty());
+        for edge in edges {
+            assert_eq!(edge["spent_by"].as_str(), Some(txid.to_string().as_str()));
+            assert!(nodes.iter().any(|node| node["txid"] == edge["txid"]));
+        }
+        assert_eq!(res["truncated"].as_bool(), Some(false));
+
+        // the funding outputs are worth less than the whole supply
+        let res = get_json(&format!(
+            "/tx/{}/graph?direction=upstream&min_value=2100000000000000",
+            txid
+        ))?;
+        assert_eq!(res["nodes"].as_array().map(Vec::len), Some(1));
+        assert_eq!(res["edges"].as_array().map(Vec::len), Some(0));
+
+        let res = ureq::get(&format!("http://{}/tx/{}/graph?direction=sideways", rest_addr, txid))
+            .call();
+        assert!(matches!(res, Err(ureq::Error::Status(400, _))));
+    }
+
+    // Test the ZMQ notifications of the daemon, as used for updating the mempool
+    {
+        use electrs::zmq::{Notification, Subscriber};
+        let zmq_addr = tester.config().zmq_addr.unwrap();
+        let mut subscriber = Subscriber::connect(zmq_addr, &["rawtx"])?;
+        // give the subscription time to propagate
+        std::thread::sleep(std::time::Duration::from_millis(500));
+        let txid = tester.send(&addr1, "0.5 BTC".parse().unwrap())?;
+        match subscriber.recv()? {
+            Notification::RawTx(tx) => assert_eq!(tx.txid(), txid),
+            Notification::HashBlock(_) => panic!("not subscribed to hashblock"),
+        }
+    }
+
     tester.mine()?;
     assert_eq!(get_json("/mempool")?["count"].as_u64(), Some(0));
 
+    // Test that replaced mempool transactions stay in the address history
+    #[cfg(not(feature = "liquid"))]
+    {
+        let addr = tester.newaddress()?;
+        let node = tester.node_client();
+        let txid: Txid = node.call(
+            "sendtoaddress",
+            &[
+                addr.to_string().into(),
+                0.1.into(),
+                Value::Null,
+                Value::Null,
+                false.into(),
+                true.into(), // replaceable
+            ],
+        )?;
+        let bumped = node.call::<Value>("bumpfee", &[txid.to_string().into()])?;
+        let bumped_txid = bumped["txid"].as_str().expect("bumped txid").to_string();
+        tester.sync()?;
+
+        let res = get_json(&format!("/address/{}/txs/mempool", addr))?;
+        let txs = res.as_array().expect("array of txs");
+        assert_eq!(txs.len(), 2);
+        assert_eq!(txs[0]["txid"].as_str(), Some(bumped_txid.as_str()));
+        assert!(txs[0]["replaced_by"].is_null());
+        assert_eq!(txs[1]["txid"].as_str(), Some(txid.to_string().as_str()));
+        assert_eq!(txs[1]["replaced_by"].as_str(), Some(bumped_txid.as_str()));
+
+        let res = get_json(&format!("/address/{}/txs", addr))?;
+        assert_eq!(res["transactions"][1]["replaced_by"].as_str(), Some(bumped_txid.as_str()));
+
+        // confirming the replacement doesn't drop the replaced tx
+        tester.mine()?;
+        let res = get_json(&format!("/address/{}/txs/mempool", addr))?;
+        assert_eq!(res.as_array().map(Vec::len), Some(1));
+
+        // a transaction double-spent by a confirmed one is replaced by it
+        let txid: Txid = tester
+            .node_client()
+            .call("sendtoaddress", &[addr.to_string().into(), 0.1.into()])?;
+        tester.sync()?;
+        let node = tester.node_client();
+        let tx: Value = node.call("getrawtransaction", &[txid.to_string().into(), true.into()])?;
+        let (prev_txid, prev_vout) = (&tx["vin"][0]["txid"], &tx["vin"][0]["vout"]);
+        // the input as of the chain, as it's spent in the mempool
+        let prevout: Value =
+            node.call("gettxout", &[prev_txid.clone(), prev_vout.clone(), false.into()])?;
+        let value = prevout["value"].as_f64().expect("prevout value");
+        let rawtx: String = node.call(
+            "createrawtransaction",
+            &[
+                serde_json::json!([{ "txid": prev_txid, "vout": prev_vout }]),
+                serde_json::json!({ addr.to_string(): ((value - 0.001) * 1e8).round() / 1e8 }),
+            ],
+        )?;
+        let signed: Value = node.call("signrawtransactionwithwallet", &[rawtx.into()])?;
+        let miner = tester.newaddress()?;
+        let block: Value = tester.node_client().call(
+            "generateblock",
+            &[miner.to_string().into(), serde_json::json!([signed["hex"]])],
+        )?;
+        let conflict: Value = tester.node_client().call(
+            "getblock",
+            &[block["hash"].clone(), 1.into()],
+        )?;
+        let conflict_txid = conflict["tx"][1].as_str().expect("conflicting txid").to_string();
+        tester.sync()?;
+
+        let res = get_json(&format!("/address/{}/txs/mempool", addr))?;
+        let replaced = res
+            .as_array()
+            .expect("array of txs")
+            .iter()
+            .find(|tx| tx["txid"].as_str() == Some(txid.to_string().as_str()))
+            .expect("replaced tx");
+        assert_eq!(replaced["replaced_by"].as_str(), Some(conflict_txid.as_str()));
+    }
+
     // Elements-only tests
     #[cfg(feature = "liquid")]
     {
@@ -315,6 +1678,447 @@ fn test_rest() -> Result<()> {
         }
     }
 
+    // Test the watch lists (through Query, as the admin API is disabled)
+    {
+        let query = tester.query();
+        let lists = query.watch_lists();
+        let scripthash1 = compute_script_hash(&addr1.script_pubkey());
+        let scripthash2 = compute_script_hash(&tester.newaddress()?.script_pubkey());
+        let timeout = std::time::Duration::from_secs(0);
+
+        let report = lists.import(query.chain(), "wallet", None, vec![scripthash1], false)?;
+        assert_eq!((report.added, report.total), (1, 1));
+        let report = lists.import(
+            query.chain(),
+            "wallet",
+            None,
+            vec![scripthash1, scripthash2],
+            false,
+        )?;
+        assert_eq!((report.added, report.total), (1, 2));
+        let (webhook, scripthashes) = lists.export("wallet").unwrap();
+        assert!(webhook.is_none());
+        assert_eq!(scripthashes.len(), 2);
+        assert!(lists.export("other").is_none());
+
+        // the lists without a webhook accumulate the changes of their scripts until polled
+        let mut changes = ScriptChanges::default();
+        changes.insert(scripthash2);
+        changes.insert([0; 32]);
+        lists.notify(&changes);
+        let polled = lists.wait_changes("wallet", timeout).unwrap();
+        assert!(!polled.all);
+        assert_eq!(
+            polled.scripthashes,
+            vec![watch_lists::electrum_hex(&scripthash2)]
+        );
+        let polled = lists.wait_changes("wallet", timeout).unwrap();
+        assert!(polled.scripthashes.is_empty());
+        assert!(lists.wait_changes("other", timeout).is_none());
+
+        // replacing the scripthashes with none removes the list
+        let report = lists.import(query.chain(), "wallet", None, vec![scripthash1], true)?;
+        assert_eq!((report.added, report.total), (1, 1));
+        let report = lists.import(query.chain(), "wallet", None, vec![], true)?;
+        assert_eq!(report.total, 0);
+        assert!(lists.export("wallet").is_none());
+    }
+
+    // Test GET /network/checkpoints
+    let res = get_json("/network/checkpoints")?;
+    assert_eq!(res["checkpoints"][0]["height"].as_u64(), Some(1));
+    assert_eq!(res["checkpoints"][0]["matches"].as_bool(), Some(true));
+    assert_eq!(res["max_reorg_depth"].as_u64(), Some(2));
+    assert!(res["refused_reorg"].is_null());
+
+    // Test the UTXOs spent in orphaned blocks
+    #[cfg(not(feature = "liquid"))]
+    {
+        let spent_addr = tester.newaddress()?;
+        tester.send(&spent_addr, "1 BTC".parse().unwrap())?;
+        let other_addr = tester.newaddress()?;
+        tester.send(&other_addr, "1 BTC".parse().unwrap())?;
+        tester.mine()?;
+        let utxo = |addr: String| -> Result<Value> {
+            Ok(get_json(&format!("/address/{}/utxo?orphaned=true", addr))?[0].take())
+        };
+        let spent_utxo = utxo(spent_addr.to_string())?;
+        let other_utxo = utxo(other_addr.to_string())?;
+        let input =
+            |utxo: &Value| serde_json::json!({ "txid": utxo["txid"], "vout": utxo["vout"] });
+        let sign_and_send =
+            |tester: &common::TestRunner, inputs: Value, value: f64| -> Result<Txid> {
+                let node = tester.node_client();
+                let rawtx: String = node.call(
+                    "createrawtransaction",
+                    &[
+                        inputs,
+                        serde_json::json!({ addr1.to_string(): value }),
+                        0.into(),
+                        true.into(), // replaceable
+                    ],
+                )?;
+                let signed: Value = node.call("signrawtransactionwithwallet", &[rawtx.into()])?;
+                Ok(node.call("sendrawtransaction", &[signed["hex"].clone()])?)
+            };
+
+        // spend both outputs, then reorg the spend out in favor of a conflicting transaction
+        // that only spends the other output
+        let orphaned_txid = sign_and_send(
+            &tester,
+            serde_json::json!([input(&spent_utxo), input(&other_utxo)]),
+            1.9999,
+        )?;
+        let orphaned_block = tester.mine()?;
+        assert!(get_json(&format!("/address/{}/utxo", spent_addr))?
+            .as_array()
+            .unwrap()
+            .is_empty());
+        tester
+            .node_client()
+            .call::<Value>("invalidateblock", &[orphaned_block.to_string().into()])?;
+        sign_and_send(&tester, serde_json::json!([input(&other_utxo)]), 0.999)?;
+        tester.mine()?;
+
+        let res = utxo(spent_addr.to_string())?;
+        assert_eq!(res["txid"], spent_utxo["txid"]);
+        assert_eq!(
+            res["last_spent_in_orphaned"].as_str(),
+            Some(orphaned_txid.to_string().as_str())
+        );
+        assert!(utxo(addr1.to_string())?["last_spent_in_orphaned"].is_null());
+        // and only looked up when asked for
+        let res = get_json(&format!("/address/{}/utxo", spent_addr))?;
+        assert!(res[0]["last_spent_in_orphaned"].is_null());
+    }
+
+    // Test GET /txs/outspends, with a repeated txid and more txids than looked up in parallel
+    let txids = [txid1_confirmed, txid2_mempool, txid1_confirmed, txid2_mempool, txid1_confirmed];
+    let txids = txids.iter().map(Txid::to_string).collect::<Vec<_>>().join(",");
+    let res = get_json(&format!("/txs/outspends?txids={},invalid", txids))?;
+    let outspends = res.as_array().unwrap();
+    assert_eq!(outspends.len(), 6);
+    assert_eq!(outspends[0], get_json(&format!("/tx/{}/outspends", txid1_confirmed))?);
+    assert_eq!(outspends[1], get_json(&format!("/tx/{}/outspends", txid2_mempool))?);
+    assert_eq!(outspends[0], outspends[2]);
+    assert_eq!(outspends[0], outspends[4]);
+    assert_eq!(outspends[1], outspends[3]);
+    assert_eq!(outspends[5], serde_json::json!([]));
+
+    // Test GET /tx/:txid/broadcast-status
+    #[cfg(not(feature = "liquid"))]
+    {
+        let path = format!("/tx/{}/broadcast-status", txid1_confirmed);
+        let res = ureq::get(&format!("http://{}{}", rest_addr, path)).call();
+        assert!(matches!(res, Err(ureq::Error::Status(404, _))));
+
+        // queue a transaction as if the daemon was unreachable, it gets submitted on the next sync
+        let node = tester.node_client();
+        let rawtx: String = node.call(
+            "createrawtransaction",
+            &[serde_json::json!([]), serde_json::json!({ addr1.to_string(): 0.1 })],
+        )?;
+        let funded: Value = node.call("fundrawtransaction", &[rawtx.into()])?;
+        let signed: Value = node.call("signrawtransactionwithwallet", &[funded["hex"].clone()])?;
+        let decoded: Value = node.call("decoderawtransaction", &[signed["hex"].clone()])?;
+        let txid: Txid = decoded["txid"].as_str().unwrap().parse().unwrap();
+        broadcast_queue::enqueue(
+            tester.query().chain(),
+            &txid,
+            signed["hex"].as_str().unwrap(),
+            "daemon is reconnecting".to_string(),
+        );
+
+        let res = get_json(&format!("/tx/{}/broadcast-status", txid))?;
+        assert_eq!(res["status"].as_str(), Some("queued"));
+        assert_eq!(res["attempts"].as_u64(), Some(1));
+        assert_eq!(res["error"].as_str(), Some("daemon is reconnecting"));
+
+        tester.sync()?;
+        let res = get_json(&format!("/tx/{}/broadcast-status", txid))?;
+        assert_eq!(res["status"].as_str(), Some("submitted"));
+        assert_eq!(res["attempts"].as_u64(), Some(2));
+        assert!(res["error"].is_null());
+        let res = get_json(&format!("/tx/{}/status", txid))?;
+        assert_eq!(res["confirmed"].as_bool(), Some(false));
+        tester.mine()?;
+    }
+
+    // Test the rebroadcasting of stuck transactions
+    #[cfg(not(feature = "liquid"))]
+    {
+        let broadcast = |tester: &common::TestRunner, params: &str| -> Result<Txid> {
+            let node = tester.node_client();
+            let rawtx: String = node.call(
+                "createrawtransaction",
+                &[serde_json::json!([]), serde_json::json!({ addr1.to_string(): 0.1 })],
+            )?;
+            let funded: Value = node.call("fundrawtransaction", &[rawtx.into()])?;
+            let signed: Value =
+                node.call("signrawtransactionwithwallet", &[funded["hex"].clone()])?;
+            let txid = ureq::post(&format!("http://{}/tx{}", rest_addr, params))
+                .send_string(signed["hex"].as_str().unwrap())?
+                .into_string()?;
+            Ok(txid.parse().unwrap())
+        };
+        let tracked_txid = broadcast(&tester, "")?;
+        let untracked_txid = broadcast(&tester, "?rebroadcast=false")?;
+        let pending = rebroadcast::pending(tester.query().chain());
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].0, tracked_txid);
+        assert_eq!(pending[0].1.rebroadcast_count, 0);
+        assert_ne!(pending[0].0, untracked_txid);
+
+        // keep the tracked transaction out of the next block, so that it gets rebroadcasted
+        let prioritise = |tester: &common::TestRunner, fee_delta: i64| -> Result<bool> {
+            Ok(tester.node_client().call(
+                "prioritisetransaction",
+                &[tracked_txid.to_string().into(), 0.into(), fee_delta.into()],
+            )?)
+        };
+        prioritise(&tester, -100_000_000)?;
+        tester.mine()?;
+        let pending = rebroadcast::pending(tester.query().chain());
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].1.rebroadcast_count, 1);
+
+        // it's no longer tracked once confirmed
+        prioritise(&tester, 100_000_000)?;
+        tester.mine()?;
+        assert!(rebroadcast::pending(tester.query().chain()).is_empty());
+
+        let res = ureq::get(&format!("http://{}/admin/rebroadcast/pending", rest_addr)).call();
+        assert!(matches!(res, Err(ureq::Error::Status(403, _))));
+    }
+
+    // Test POST /tx with X-Idempotency-Key
+    #[cfg(not(feature = "liquid"))]
+    {
+        let node = tester.node_client();
+        let mut signed_txs = vec![];
+        for _ in 0..3 {
+            let rawtx: String = node.call(
+                "createrawtransaction",
+                &[serde_json::json!([]), serde_json::json!({ addr1.to_string(): 0.1 })],
+            )?;
+            let funded: Value = node.call("fundrawtransaction", &[rawtx.into()])?;
+            let signed: Value =
+                node.call("signrawtransactionwithwallet", &[funded["hex"].clone()])?;
+            signed_txs.push(signed["hex"].as_str().unwrap().to_string());
+        }
+        let broadcast = |key: &str, txhex: &str| {
+            ureq::post(&format!("http://{}/tx", rest_addr))
+                .set("X-Idempotency-Key", key)
+                .send_string(txhex)
+        };
+
+        let txid = broadcast("payment-1", &signed_txs[0])?.into_string()?;
+        assert_eq!(broadcast("payment-1", &signed_txs[0])?.into_string()?, txid);
+
+        // retries of confirmed transactions still return the original result
+        tester.mine()?;
+        assert_eq!(broadcast("payment-1", &signed_txs[0])?.into_string()?, txid);
+
+        let res = broadcast("payment-1", &signed_txs[1]);
+        assert!(matches!(res, Err(ureq::Error::Status(422, _))));
+        let res = broadcast(&"x".repeat(129), &signed_txs[1]);
+        assert!(matches!(res, Err(ureq::Error::Status(400, _))));
+
+        // concurrent retries wait for the broadcast in flight, rather than resubmit it
+        let txids = std::thread::scope(|s| {
+            let retries: Vec<_> = (0..4)
+                .map(|_| s.spawn(|| broadcast("payment-2", &signed_txs[1])))
+                .collect();
+            retries
+                .into_iter()
+                .map(|retry| retry.join().unwrap().unwrap().into_string().unwrap())
+                .collect::<HashSet<_>>()
+        });
+        assert_eq!(txids.len(), 1);
+
+        // the keys are scoped to the client
+        let resp = ureq::post(&format!("http://{}/tx", rest_addr))
+            .set("X-Idempotency-Key", "payment-1")
+            .set("X-Api-Key", "another-client")
+            .send_string(&signed_txs[2])?;
+        assert_ne!(resp.into_string()?, txid);
+
+        // the broadcasts got recorded in the audit log, served by GET /admin/audit (disabled)
+        let events = tester.query().audit_log().unwrap().events_since(0, 1000);
+        assert!(events.iter().any(|event| event.action == "broadcast"
+            && event.source == "rest"
+            && event.request_id.is_some()
+            && event.details["txids"][0].as_str() == Some(txid.as_str())
+            && event.details["status"].as_u64() == Some(200)));
+        let res = ureq::get(&format!("http://{}/admin/audit", rest_addr)).call();
+        assert!(matches!(res, Err(ureq::Error::Status(403, _))));
+    }
+
+    // Test the reindexing of a scripthash (through ChainQuery, as the admin API is disabled)
+    {
+        let chain = tester.query().chain();
+        let scripthash = compute_script_hash(&addr1.script_pubkey());
+        let report = chain.reindex_scripthash(&scripthash, false, true, &Default::default())?;
+        assert!(report.history_entries > 0);
+        assert!(report.missing.is_empty());
+        assert!(report.extra.is_empty());
+
+        let row = chain.history_iter_scan(b'H', &scripthash, 0).next().unwrap();
+        chain.store().history_db().delete(&row.key);
+        let report = chain.reindex_scripthash(&scripthash, false, true, &Default::default())?;
+        assert_eq!(report.missing.len(), 1);
+        assert!(report.extra.is_empty());
+        let report = chain.reindex_scripthash(&scripthash, false, false, &Default::default())?;
+        assert_eq!(report.missing.len(), 1);
+        assert_eq!(chain.history_iter_scan(b'H', &scripthash, 0).next().unwrap(), row);
+        let report = chain.reindex_scripthash(&scripthash, false, true, &Default::default())?;
+        assert!(report.missing.is_empty());
+
+        // the reindexing jobs run in the background (through Query, as the admin API is disabled)
+        use hex::DisplayHex;
+        let query = tester.query();
+        let hex = scripthash.to_lower_hex_string();
+        let started = query.start_reindex(&scripthash, &hex, true).unwrap();
+        assert_eq!(started.state, "running");
+        let status = loop {
+            let status = query.reindex_status(started.id).unwrap();
+            if status.state != "running" {
+                break status;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(50));
+        };
+        assert_eq!(status.state, "done");
+        assert_eq!(status.scanned_blocks, status.total_blocks);
+        assert!(status.report.unwrap().missing.is_empty());
+        assert_eq!(query.reindex_jobs()[0].id, started.id);
+        assert!(query.reindex_status(started.id + 1).is_none());
+    }
+
+    // Test the cancellation of DB scans past the request deadline (disabled by default)
+    {
+        let chain = tester.query().chain();
+        let scripthash = compute_script_hash(&addr1.script_pubkey());
+        let deadline = Deadline::set(std::time::Duration::from_secs(0));
+        assert!(chain.history_iter_scan(b'H', &scripthash, 0).next().is_none());
+        assert!(deadline.cancelled());
+        drop(deadline);
+        assert!(chain.history_iter_scan(b'H', &scripthash, 0).next().is_some());
+        let deadline = Deadline::set(std::time::Duration::from_secs(60));
+        assert!(chain.history_iter_scan(b'H', &scripthash, 0).next().is_some());
+        assert!(!deadline.cancelled());
+    }
+
+    // Test the integrity checker (directly, as it's disabled by default)
+    {
+        let chain = tester.query().chain();
+        let scripthash = compute_script_hash(&addr1.script_pubkey());
+        let (findings, checked) = integrity::check_scripthash(chain, &scripthash, 1000);
+        assert!(checked > 0);
+        assert!(findings.is_empty());
+
+        // index a funding entry for an output that doesn't exist
+        let mut entry = chain
+            .history_iter_scan(b'H', &scripthash, 0)
+            .map(TxHistoryRow::from_row)
+            .find(|entry| matches!(entry.key.txinfo, TxHistoryInfo::Funding(_)))
+            .unwrap();
+        if let TxHistoryInfo::Funding(ref mut info) = entry.key.txinfo {
+            info.vout += 100;
+        }
+        let txid = entry.get_txid();
+        let row = entry.into_row();
+        let key = row.key.clone();
+        chain.store().history_db().write(vec![row], DBFlush::Enable);
+
+        // the cached stats and UTXOs, if any, might disagree as well
+        let (findings, _) = integrity::check_scripthash(chain, &scripthash, 1000);
+        let findings: Vec<_> = findings
+            .into_iter()
+            .filter(|finding| finding.kind == integrity::FindingKind::History)
+            .collect();
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].txid, Some(txid));
+        chain.store().history_db().delete(&key);
+
+        let report = integrity::update(chain, 1000);
+        assert_eq!(report.runs, 1);
+        assert!(report.checked_scripthashes > 0);
+        assert!(report.findings.is_empty());
+        assert_eq!(integrity::report(chain).unwrap().runs, 1);
+    }
+
+    // Test that cancelled scans fail without caching the partial stats and UTXOs they computed
+    {
+        let addr = tester.newaddress()?;
+        // enough history for the stats and UTXOs to get cached
+        for _ in 0..101 {
+            tester.node_client().call::<Value>(
+                "sendtoaddress",
+                &[addr.to_string().into(), serde_json::json!(0.001)],
+            )?;
+        }
+        tester.mine()?;
+        let chain = tester.query().chain();
+        let scripthash = compute_script_hash(&addr.script_pubkey());
+
+        let deadline = Deadline::set(std::time::Duration::from_secs(0));
+        let is_timeout = |err: &electrs::errors::Error| {
+            matches!(err.kind(), electrs::errors::ErrorKind::Timeout)
+        };
+        assert!(is_timeout(&chain.stats(&scripthash).unwrap_err()));
+        assert!(is_timeout(&chain.utxo(&scripthash, usize::MAX).unwrap_err()));
+        assert!(deadline.cancelled());
+        drop(deadline);
+        assert_eq!(chain.cached_tx_count(&scripthash), None);
+        assert_eq!(chain.verify_cached_utxos(&scripthash, usize::MAX)?, None);
+
+        assert_eq!(chain.stats(&scripthash)?.tx_count, 101);
+        assert_eq!(chain.cached_tx_count(&scripthash), Some(101));
+        assert_eq!(chain.utxo(&scripthash, usize::MAX)?.len(), 101);
+        assert_eq!(chain.verify_cached_stats(&scripthash), None);
+        assert_eq!(chain.verify_cached_utxos(&scripthash, usize::MAX)?, None);
+
+        // past --utxos-limit, the plain array fails and the first UTXOs are returned only when
+        // asked for, along with the cursor to resume from
+        let res = ureq::get(&format!("http://{}/address/{}/utxo", rest_addr, addr)).call();
+        assert!(matches!(res, Err(ureq::Error::Status(400, _))));
+        let res = get_json(&format!("/address/{}/utxo?partial=true", addr))?;
+        assert_eq!(res["truncated"].as_bool(), Some(true));
+        assert_eq!(res["items"].as_array().map(Vec::len), Some(100));
+        let next_cursor = res["paging"]["next_cursor"].as_str().expect("cursor");
+        let res = get_json(&format!("/address/{}/utxo?cursor={}", addr, next_cursor))?;
+        assert_eq!(res["items"].as_array().map(Vec::len), Some(1));
+        assert!(res["paging"]["next_cursor"].is_null());
+    }
+
+    // Test following the index as a read-only replica
+    {
+        let replica = tester.open_replica();
+        assert_eq!(replica.catch_up(), tester.query().chain().best_hash());
+        let blockhash = tester.mine()?;
+        assert_eq!(replica.catch_up(), blockhash);
+        assert!(replica.done_initial_sync());
+    }
+
+    // Test that reorgs deeper than --max-reorg-depth are refused (this leaves the index stuck on
+    // the old chain, so it has to come last)
+    {
+        let tip_hash = get_plain("/blocks/tip/hash")?;
+        let tip_height: u64 = get_plain("/blocks/tip/height")?.parse().unwrap();
+        let invalidated = get_plain(&format!("/block-height/{}", tip_height - 2))?;
+        tester
+            .node_client()
+            .call::<Value>("invalidateblock", &[invalidated.into()])?;
+        tester.mine()?;
+
+        assert_eq!(get_plain("/blocks/tip/hash")?, tip_hash);
+        let res = get_json("/network/checkpoints")?;
+        let refused = &res["refused_reorg"];
+        assert_eq!(refused["height"].as_u64(), Some(tip_height - 2));
+        assert_eq!(refused["fork_height"].as_u64(), Some(tip_height - 3));
+        assert_eq!(refused["depth"].as_u64(), Some(3));
+    }
+
     rest_handle.stop();
     Ok(())
 }